@@ -0,0 +1,321 @@
+use alloc::{boxed::Box, collections::BTreeMap, vec, vec::Vec};
+use core::{fmt, ops::Range};
+
+use tracing::{instrument, trace};
+
+use crate::{dram::Dram, exception::Exception};
+
+/// The address which dram starts, same as QEMU virt machine.
+pub const DRAM_BASE: u64 = 0x8000_0000;
+
+/// Something answering loads and stores to a memory mapped region. Offsets are
+/// relative to the start of the region and sizes are in bits.
+pub trait Device: Send {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception>;
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception>;
+
+    /// The name it traces accesses with.
+    fn name(&self) -> &str {
+        core::any::type_name::<Self>()
+    }
+
+    /// Where [`Bus::attach`] maps it, none for devices which don't have an
+    /// address of their own and need [`Bus::map`].
+    fn address_range(&self) -> Option<Range<u64>> {
+        None
+    }
+
+    /// Moves it forward, once each time the machine polls for interrupts.
+    fn tick(&mut self) {}
+
+    /// Moves its transfers of guest memory forward, right after
+    /// [`Device::tick`].
+    fn dma(&mut self, _dma: &mut Dma<'_>) {}
+
+    /// The interrupt source id it raises, while its line is high.
+    fn pending_irq(&self) -> Option<u32> {
+        None
+    }
+}
+
+pub struct MmioRegion {
+    pub base: u64,
+    pub size: u64,
+    pub device: Box<dyn Device>,
+}
+
+impl MmioRegion {
+    fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr - self.base < self.size
+    }
+}
+
+impl fmt::Debug for MmioRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmioRegion")
+            .field("device", &self.device.name())
+            .field("base", &self.base)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Bytes reserved by an LR, the aligned doubleword around its address.
+pub const RESERVATION_GRANULE: u64 = 8;
+
+/// Size of the pages an [`Iommu`] translates, DMA crossing them is split.
+pub const IOMMU_PAGE: u64 = 4096;
+
+/// Translates the addresses devices give for DMA to guest physical ones, as
+/// an IOMMU does, blocking those it doesn't map.
+pub trait Iommu: Send {
+    /// The guest physical address of the `len` bytes at `iova`, which don't
+    /// cross an [`IOMMU_PAGE`], or none to fault the access.
+    fn translate(&self, iova: u64, len: usize, write: bool) -> Option<u64>;
+}
+
+/// A device's access to guest dram, bounds checked and translated by the
+/// [`Iommu`] of the bus if it has one. Writes drop the reservations they
+/// overlap, as stores from the harts do.
+pub struct Dma<'a> {
+    dram: &'a mut Dram,
+    reservations: &'a mut BTreeMap<u64, u64>,
+    iommu: Option<&'a dyn Iommu>,
+}
+
+impl Dma<'_> {
+    /// Copies the dram at `addr` into `buf`.
+    pub fn read(&self, addr: u64, buf: &mut [u8]) -> Result<(), Exception> {
+        dma_read(self.dram, self.iommu, addr, buf)
+    }
+
+    /// Copies `data` into the dram at `addr`.
+    pub fn write(&mut self, addr: u64, data: &[u8]) -> Result<(), Exception> {
+        let ranges = dma_ranges(self.dram, self.iommu, addr, data.len(), true)
+            .ok_or(Exception::StoreAmoAccessFault(addr))?;
+        let mut done = 0;
+        for range in ranges {
+            let start = self.dram.base + range.start as u64;
+            let len = range.len();
+            self.dram.dram[range].copy_from_slice(&data[done..done + len]);
+            drop_granules(self.reservations, start, len as u64);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Dma<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dma")
+            .field("iommu", &self.iommu.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct Bus {
+    pub dram: Dram,
+    /// Granule reserved by the last LR of each hart, by hart id. A store to
+    /// it by any hart or device drops the reservations, so an SC after it
+    /// fails. Harts run one instruction at a time, so AMOs are atomic.
+    pub reservations: BTreeMap<u64, u64>,
+    pub mmio: Vec<MmioRegion>,
+    /// Translates the DMA of every device, none to let them reach all of
+    /// dram.
+    pub iommu: Option<Box<dyn Iommu>>,
+}
+
+impl fmt::Debug for Bus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bus")
+            .field("dram", &self.dram)
+            .field("reservations", &self.reservations)
+            .field("mmio", &self.mmio)
+            .field("iommu", &self.iommu.is_some())
+            .finish()
+    }
+}
+
+impl Bus {
+    pub fn new(dram: Dram) -> Self {
+        Self {
+            dram,
+            reservations: BTreeMap::new(),
+            mmio: Vec::new(),
+            iommu: None,
+        }
+    }
+
+    /// Maps `device` at `[base, base + size)`. Mapped regions take precedence
+    /// over dram, and over the regions mapped before them.
+    pub fn map(&mut self, base: u64, size: u64, device: impl Device + 'static) {
+        self.mmio.push(MmioRegion {
+            base,
+            size,
+            device: Box::new(device),
+        });
+    }
+
+    /// Maps `device` at its [`Device::address_range`], returning it back if
+    /// it doesn't have one.
+    pub fn attach<D: Device + 'static>(&mut self, device: D) -> Result<(), D> {
+        let Some(range) = device.address_range() else {
+            return Err(device);
+        };
+        self.map(range.start, range.end - range.start, device);
+        Ok(())
+    }
+
+    /// Ticks every mapped device, then has it move its DMA forward.
+    pub fn tick(&mut self) {
+        let mut dma = Dma {
+            dram: &mut self.dram,
+            reservations: &mut self.reservations,
+            iommu: self.iommu.as_deref(),
+        };
+        for region in &mut self.mmio {
+            region.device.tick();
+            region.device.dma(&mut dma);
+        }
+    }
+
+    /// The access to guest dram devices have, for the ones driven outside
+    /// of [`Bus::tick`].
+    pub fn dma(&mut self) -> Dma<'_> {
+        Dma {
+            dram: &mut self.dram,
+            reservations: &mut self.reservations,
+            iommu: self.iommu.as_deref(),
+        }
+    }
+
+    /// The interrupt source ids the mapped devices raise, in the order they
+    /// were mapped.
+    pub fn pending_irqs(&self) -> impl Iterator<Item = u32> + '_ {
+        self.mmio.iter().filter_map(|x| x.device.pending_irq())
+    }
+
+    /// Reserves the granule of `addr` for `hart`, as an LR does.
+    pub fn reserve(&mut self, hart: u64, addr: u64) {
+        self.reservations
+            .insert(hart, addr & !(RESERVATION_GRANULE - 1));
+    }
+
+    /// Whether `hart` still holds a reservation for `addr`, which is dropped
+    /// either way, as an SC does.
+    pub fn take_reservation(&mut self, hart: u64, addr: u64) -> bool {
+        self.reservations.remove(&hart) == Some(addr & !(RESERVATION_GRANULE - 1))
+    }
+
+    /// Whether `hart` holds a reservation, for any address.
+    pub fn holds_reservation(&self, hart: u64) -> bool {
+        self.reservations.contains_key(&hart)
+    }
+
+    /// Whether `addr` is routed to a mapped device.
+    pub fn is_mmio(&self, addr: u64) -> bool {
+        self.mmio.iter().any(|x| x.contains(addr))
+    }
+
+    #[instrument(skip(self))]
+    pub fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
+        trace!("load");
+        if let Some(region) = self.mmio.iter_mut().rev().find(|x| x.contains(addr)) {
+            let offset = addr - region.base;
+            trace!(device = region.device.name(), offset, "device load");
+            return region.device.load(offset, size);
+        }
+        if self.dram.contains(addr) {
+            return self.dram.load(addr, size);
+        }
+        Err(Exception::LoadAccessFault(addr))
+    }
+
+    #[instrument(skip(self))]
+    pub fn store(&mut self, addr: u64, size: u64, value: u64) -> Result<(), Exception> {
+        trace!("store");
+        if let Some(region) = self.mmio.iter_mut().rev().find(|x| x.contains(addr)) {
+            let offset = addr - region.base;
+            trace!(device = region.device.name(), offset, "device store");
+            return region.device.store(offset, size, value);
+        }
+        if self.dram.contains(addr) {
+            self.dram.store(addr, size, value)?;
+            self.drop_reservations(addr, size / 8);
+            return Ok(());
+        }
+        Err(Exception::StoreAmoAccessFault(addr))
+    }
+
+    /// Copies the dram at `addr` into `buf`, as a device reading guest memory
+    /// does, without going through the mapped devices.
+    pub fn dma_read(&self, addr: u64, buf: &mut [u8]) -> Result<(), Exception> {
+        dma_read(&self.dram, self.iommu.as_deref(), addr, buf)
+    }
+
+    /// Copies `data` into the dram at `addr`, as a device writing guest
+    /// memory does, dropping the reservations it overlaps.
+    pub fn dma_write(&mut self, addr: u64, data: &[u8]) -> Result<(), Exception> {
+        self.dma().write(addr, data)
+    }
+
+    /// Drops the reservations of the granules overlapping the `bytes` at
+    /// `addr`.
+    fn drop_reservations(&mut self, addr: u64, bytes: u64) {
+        drop_granules(&mut self.reservations, addr, bytes);
+    }
+}
+
+fn drop_granules(reservations: &mut BTreeMap<u64, u64>, addr: u64, bytes: u64) {
+    if !reservations.is_empty() {
+        let end = addr.wrapping_add(bytes);
+        reservations.retain(|_, granule| end <= *granule || *granule + RESERVATION_GRANULE <= addr);
+    }
+}
+
+fn dram_range(dram: &Dram, addr: u64, len: usize) -> Option<Range<usize>> {
+    let start = addr.checked_sub(dram.base)? as usize;
+    let end = start.checked_add(len)?;
+    (end <= dram.dram.len()).then_some(start..end)
+}
+
+/// The dram backing the `len` bytes a device accesses at `addr`, a range for
+/// each page the iommu translates.
+fn dma_ranges(
+    dram: &Dram,
+    iommu: Option<&dyn Iommu>,
+    addr: u64,
+    len: usize,
+    write: bool,
+) -> Option<Vec<Range<usize>>> {
+    let Some(iommu) = iommu else {
+        return Some(vec![dram_range(dram, addr, len)?]);
+    };
+    let end = addr.checked_add(len as u64)?;
+    let mut ranges = Vec::new();
+    let mut iova = addr;
+    while iova < end {
+        let chunk = (IOMMU_PAGE - iova % IOMMU_PAGE).min(end - iova) as usize;
+        let phys = iommu.translate(iova, chunk, write)?;
+        ranges.push(dram_range(dram, phys, chunk)?);
+        iova += chunk as u64;
+    }
+    Some(ranges)
+}
+
+fn dma_read(
+    dram: &Dram,
+    iommu: Option<&dyn Iommu>,
+    addr: u64,
+    buf: &mut [u8],
+) -> Result<(), Exception> {
+    let ranges =
+        dma_ranges(dram, iommu, addr, buf.len(), false).ok_or(Exception::LoadAccessFault(addr))?;
+    let mut done = 0;
+    for range in ranges {
+        let len = range.len();
+        buf[done..done + len].copy_from_slice(&dram.dram[range]);
+        done += len;
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Set-associative L1 cache models with LRU replacement. They only track
+//! which lines are present to count hits and misses, the data always comes
+//! from the bus. Device accesses bypass them.
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::{fmt, str::FromStr};
+
+use crate::error::EmulatorError;
+
+/// Geometry of a cache, sizes in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub size: u64,
+    pub ways: u64,
+    pub line: u64,
+    /// Cycles added to `mcycle` for every miss.
+    pub miss_penalty: u64,
+}
+
+impl CacheConfig {
+    fn sets(&self) -> u64 {
+        self.size / (self.ways * self.line)
+    }
+
+    fn validate(&self) -> Result<(), EmulatorError> {
+        let invalid = |reason| Err(EmulatorError::InvalidCache(String::from(reason)));
+        if self.ways == 0 || self.line == 0 || self.size == 0 {
+            return invalid("size, ways and line must not be 0");
+        }
+        if !self.line.is_power_of_two() {
+            return invalid("line size must be a power of two");
+        }
+        if !self.size.is_multiple_of(self.ways * self.line) || !self.sets().is_power_of_two() {
+            return invalid("size must be a power of two number of sets of ways lines");
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for CacheConfig {
+    type Err = EmulatorError;
+
+    /// Parses `SIZE,WAYS,LINE[,PENALTY]`, where `SIZE` may have a `K` or `M`
+    /// suffix, e.g. `32K,8,64,10`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            || EmulatorError::InvalidCache(format!("expected SIZE,WAYS,LINE[,PENALTY], got {s}"));
+        let fields: Vec<&str> = s.split(',').collect();
+        let (size, rest) = match fields.as_slice() {
+            [size, rest @ ..] if (2..=3).contains(&rest.len()) => (*size, rest),
+            _ => return Err(invalid()),
+        };
+        let (digits, multiplier) = match size.as_bytes().last() {
+            Some(b'k' | b'K') => (&size[..size.len() - 1], 1 << 10),
+            Some(b'm' | b'M') => (&size[..size.len() - 1], 1 << 20),
+            _ => (size, 1),
+        };
+        let number = |s: &str| s.parse::<u64>().map_err(|_| invalid());
+        let config = Self {
+            size: number(digits)?
+                .checked_mul(multiplier)
+                .ok_or_else(invalid)?,
+            ways: number(rest[0])?,
+            line: number(rest[1])?,
+            miss_penalty: rest.get(2).map(|x| number(x)).transpose()?.unwrap_or(0),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub config: CacheConfig,
+    /// Tags of the lines in each set, most recently used first.
+    sets: Vec<Vec<u64>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Result<Self, EmulatorError> {
+        config.validate()?;
+        Ok(Self {
+            config,
+            sets: vec![Vec::with_capacity(config.ways as usize); config.sets() as usize],
+            hits: 0,
+            misses: 0,
+        })
+    }
+
+    /// Records an access of `bytes` at `addr`, returning whether it hit. An
+    /// access straddling two lines hits only if both were present.
+    pub fn access(&mut self, addr: u64, bytes: u64) -> bool {
+        let first = addr / self.config.line;
+        let last = addr.saturating_add(bytes.max(1) - 1) / self.config.line;
+        let mut hit = self.touch(first);
+        if last != first {
+            hit &= self.touch(last);
+        }
+        if hit {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        hit
+    }
+
+    /// Moves `line` to the front of its set, evicting the least recently
+    /// used one on a miss.
+    fn touch(&mut self, line: u64) -> bool {
+        let set = &mut self.sets[(line % self.config.sets()) as usize];
+        let tag = line / self.config.sets();
+        match set.iter().position(|x| *x == tag) {
+            Some(i) => {
+                set[..=i].rotate_right(1);
+                true
+            }
+            None => {
+                if set.len() == self.config.ways as usize {
+                    set.pop();
+                }
+                set.insert(0, tag);
+                false
+            }
+        }
+    }
+
+    /// Drops every line, keeping the counters.
+    pub fn invalidate(&mut self) {
+        self.sets.iter_mut().for_each(Vec::clear);
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.hits + self.misses
+    }
+
+    /// Fraction of the accesses which hit, 0 before any.
+    pub fn hit_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            n => self.hits as f64 / n as f64,
+        }
+    }
+}
+
+impl fmt::Display for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} accesses, {} hits, {} misses ({:.2}% hit rate)",
+            self.accesses(),
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0
+        )
+    }
+}
@@ -0,0 +1,243 @@
+//! The C extension. Every compressed instruction is a short form of a 32-bit
+//! one, so the decoder expands it and the executor never sees the 16-bit
+//! encoding; only the pc advances by 2 instead of 4.
+//!
+//! The floating point loads and stores expand too, whether they execute
+//! depends on the F and D extensions.
+
+/// Whether the instruction starting with `parcel` is a 16-bit one, the low
+/// two bits of a 32-bit instruction are both set.
+pub fn is_compressed(parcel: u64) -> bool {
+    parcel & 0b11 != 0b11
+}
+
+/// Bits `hi..=lo` of `inst`, shifted down.
+fn bits(inst: u32, hi: u32, lo: u32) -> u32 {
+    (inst >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+/// Sign extends the low `width` bits of `x`.
+fn sext(x: u32, width: u32) -> u32 {
+    (((x << (32 - width)) as i32) >> (32 - width)) as u32
+}
+
+/// One of x8-x15, as named by the 3-bit register fields.
+fn prime(field: u32) -> u32 {
+    field + 8
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm & 0xfff) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    bits(imm, 11, 5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | bits(imm, 4, 0) << 7 | opcode
+}
+
+fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    bits(imm, 12, 12) << 31
+        | bits(imm, 10, 5) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | bits(imm, 4, 1) << 8
+        | bits(imm, 11, 11) << 7
+        | 0x63
+}
+
+fn j_type(imm: u32, rd: u32) -> u32 {
+    bits(imm, 20, 20) << 31
+        | bits(imm, 10, 1) << 21
+        | bits(imm, 11, 11) << 20
+        | bits(imm, 19, 12) << 12
+        | rd << 7
+        | 0x6f
+}
+
+/// The 32-bit instruction `parcel` stands for, `None` for illegal and
+/// reserved encodings.
+pub fn expand(parcel: u16) -> Option<u32> {
+    let inst = parcel as u32;
+    let funct3 = bits(inst, 15, 13);
+    // full register fields of the CR, CI and CSS formats
+    let rd = bits(inst, 11, 7);
+    let rs2 = bits(inst, 6, 2);
+    // x8-x15 register fields of the CIW, CL, CS, CA and CB formats: inst[4:2]
+    // is rd' or rs2', inst[9:7] is rs1' and for CA and CB also rd'
+    let low = prime(bits(inst, 4, 2));
+    let high = prime(bits(inst, 9, 7));
+    // imm[5|4:0] = inst[12|6:2]
+    let imm6 = sext(bits(inst, 12, 12) << 5 | bits(inst, 6, 2), 6);
+    // uimm[5:3|7:6] = inst[12:10|6:5], the doubleword loads and stores
+    let uimm_d = bits(inst, 12, 10) << 3 | bits(inst, 6, 5) << 6;
+    // uimm[5:3|2|6] = inst[12:10|6|5], the word loads and stores
+    let uimm_w = bits(inst, 12, 10) << 3 | bits(inst, 6, 6) << 2 | bits(inst, 5, 5) << 6;
+
+    let expanded = match (inst & 0b11, funct3) {
+        (0b00, 0b000) => {
+            // c.addi4spn, nzuimm[5:4|9:6|2|3] = inst[12:11|10:7|6|5]
+            let imm = bits(inst, 12, 11) << 4
+                | bits(inst, 10, 7) << 6
+                | bits(inst, 6, 6) << 2
+                | bits(inst, 5, 5) << 3;
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, 0b000, low, 0x13)
+        }
+        // c.fld
+        (0b00, 0b001) => i_type(uimm_d, high, 0b011, low, 0x07),
+        // c.lw
+        (0b00, 0b010) => i_type(uimm_w, high, 0b010, low, 0x03),
+        // c.ld
+        (0b00, 0b011) => i_type(uimm_d, high, 0b011, low, 0x03),
+        // c.fsd
+        (0b00, 0b101) => s_type(uimm_d, low, high, 0b011, 0x27),
+        // c.sw
+        (0b00, 0b110) => s_type(uimm_w, low, high, 0b010, 0x23),
+        // c.sd
+        (0b00, 0b111) => s_type(uimm_d, low, high, 0b011, 0x23),
+
+        // c.addi, c.nop
+        (0b01, 0b000) => i_type(imm6, rd, 0b000, rd, 0x13),
+        // c.addiw
+        (0b01, 0b001) if rd != 0 => i_type(imm6, rd, 0b000, rd, 0x1b),
+        // c.li
+        (0b01, 0b010) => i_type(imm6, 0, 0b000, rd, 0x13),
+        (0b01, 0b011) if rd == 2 => {
+            // c.addi16sp, nzimm[9|4|6|8:7|5] = inst[12|6|5|4:3|2]
+            let imm = sext(
+                bits(inst, 12, 12) << 9
+                    | bits(inst, 6, 6) << 4
+                    | bits(inst, 5, 5) << 6
+                    | bits(inst, 4, 3) << 7
+                    | bits(inst, 2, 2) << 5,
+                10,
+            );
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, 0b000, 2, 0x13)
+        }
+        (0b01, 0b011) => {
+            // c.lui, nzimm[17|16:12] = inst[12|6:2]
+            if imm6 == 0 {
+                return None;
+            }
+            (imm6 << 12) | rd << 7 | 0x37
+        }
+        (0b01, 0b100) => {
+            let shamt = bits(inst, 12, 12) << 5 | bits(inst, 6, 2);
+            match (bits(inst, 11, 10), bits(inst, 12, 12), bits(inst, 6, 5)) {
+                // c.srli
+                (0b00, _, _) => i_type(shamt, high, 0b101, high, 0x13),
+                // c.srai
+                (0b01, _, _) => i_type(0x400 | shamt, high, 0b101, high, 0x13),
+                // c.andi
+                (0b10, _, _) => i_type(imm6, high, 0b111, high, 0x13),
+                // c.sub
+                (0b11, 0, 0b00) => r_type(0x20, low, high, 0b000, high, 0x33),
+                // c.xor
+                (0b11, 0, 0b01) => r_type(0x00, low, high, 0b100, high, 0x33),
+                // c.or
+                (0b11, 0, 0b10) => r_type(0x00, low, high, 0b110, high, 0x33),
+                // c.and
+                (0b11, 0, 0b11) => r_type(0x00, low, high, 0b111, high, 0x33),
+                // c.subw
+                (0b11, 1, 0b00) => r_type(0x20, low, high, 0b000, high, 0x3b),
+                // c.addw
+                (0b11, 1, 0b01) => r_type(0x00, low, high, 0b000, high, 0x3b),
+                _ => return None,
+            }
+        }
+        (0b01, 0b101) => {
+            // c.j, offset[11|4|9:8|10|6|7|3:1|5] = inst[12|11|10:9|8|7|6|5:3|2]
+            let imm = sext(
+                bits(inst, 12, 12) << 11
+                    | bits(inst, 11, 11) << 4
+                    | bits(inst, 10, 9) << 8
+                    | bits(inst, 8, 8) << 10
+                    | bits(inst, 7, 7) << 6
+                    | bits(inst, 6, 6) << 7
+                    | bits(inst, 5, 3) << 1
+                    | bits(inst, 2, 2) << 5,
+                12,
+            );
+            j_type(imm, 0)
+        }
+        (0b01, 0b110 | 0b111) => {
+            // c.beqz, c.bnez, offset[8|4:3|7:6|2:1|5] = inst[12|11:10|6:5|4:3|2]
+            let imm = sext(
+                bits(inst, 12, 12) << 8
+                    | bits(inst, 11, 10) << 3
+                    | bits(inst, 6, 5) << 6
+                    | bits(inst, 4, 3) << 1
+                    | bits(inst, 2, 2) << 5,
+                9,
+            );
+            b_type(imm, 0, high, funct3 & 1)
+        }
+
+        // c.slli
+        (0b10, 0b000) => i_type(bits(inst, 12, 12) << 5 | rs2, rd, 0b001, rd, 0x13),
+        (0b10, 0b001) => {
+            // c.fldsp, uimm[5|4:3|8:6] = inst[12|6:5|4:2]
+            let imm = bits(inst, 12, 12) << 5 | bits(inst, 6, 5) << 3 | bits(inst, 4, 2) << 6;
+            i_type(imm, 2, 0b011, rd, 0x07)
+        }
+        (0b10, 0b010) if rd != 0 => {
+            // c.lwsp, uimm[5|4:2|7:6] = inst[12|6:4|3:2]
+            let imm = bits(inst, 12, 12) << 5 | bits(inst, 6, 4) << 2 | bits(inst, 3, 2) << 6;
+            i_type(imm, 2, 0b010, rd, 0x03)
+        }
+        (0b10, 0b011) if rd != 0 => {
+            // c.ldsp, uimm[5|4:3|8:6] = inst[12|6:5|4:2]
+            let imm = bits(inst, 12, 12) << 5 | bits(inst, 6, 5) << 3 | bits(inst, 4, 2) << 6;
+            i_type(imm, 2, 0b011, rd, 0x03)
+        }
+        (0b10, 0b100) => match (bits(inst, 12, 12), rd, rs2) {
+            // c.jr
+            (0, 1.., 0) => i_type(0, rd, 0b000, 0, 0x67),
+            // c.mv
+            (0, _, 1..) => r_type(0, rs2, 0, 0b000, rd, 0x33),
+            // c.ebreak
+            (1, 0, 0) => 0x00100073,
+            // c.jalr
+            (1, _, 0) => i_type(0, rd, 0b000, 1, 0x67),
+            // c.add
+            (1, _, _) => r_type(0, rs2, rd, 0b000, rd, 0x33),
+            _ => return None,
+        },
+        (0b10, 0b101) => {
+            // c.fsdsp, uimm[5:3|8:6] = inst[12:10|9:7]
+            let imm = bits(inst, 12, 10) << 3 | bits(inst, 9, 7) << 6;
+            s_type(imm, rs2, 2, 0b011, 0x27)
+        }
+        (0b10, 0b110) => {
+            // c.swsp, uimm[5:2|7:6] = inst[12:9|8:7]
+            let imm = bits(inst, 12, 9) << 2 | bits(inst, 8, 7) << 6;
+            s_type(imm, rs2, 2, 0b010, 0x23)
+        }
+        (0b10, 0b111) => {
+            // c.sdsp, uimm[5:3|8:6] = inst[12:10|9:7]
+            let imm = bits(inst, 12, 10) << 3 | bits(inst, 9, 7) << 6;
+            s_type(imm, rs2, 2, 0b011, 0x23)
+        }
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+/// Like [`expand`], for RV32, where c.jal takes the encoding of c.addiw.
+/// The other RV64 only expansions are left to the executor to reject.
+pub fn expand_rv32(parcel: u16) -> Option<u32> {
+    match parcel & 0xe003 {
+        // c.jal is c.j linking ra
+        0x2001 => expand((parcel & !0xe000) | 0xa000).map(|j| j | 1 << 7),
+        _ => expand(parcel),
+    }
+}
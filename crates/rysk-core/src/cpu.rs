@@ -0,0 +1,2226 @@
+use alloc::{boxed::Box, format, string::String, vec::Vec};
+use core::{
+    fmt,
+    ops::{BitAnd, BitOr, BitXor},
+    str::FromStr,
+};
+
+use tracing::{debug, instrument};
+
+use crate::{
+    bus::Bus,
+    cache::Cache,
+    compressed::{self, is_compressed},
+    crypto,
+    csr::CSRS,
+    dram::Dram,
+    exception::{Exception, Interrupt},
+    hooks::{HookContext, Hooks},
+    hpm::{Event, Hpm, MHPMCOUNTER3},
+    instruction::Instruction,
+    isa::{Extension, Isa, Xlen},
+    mmu::{Access, AdPolicy, Tlb, PAGE_SIZE},
+    observer::{AccessKind, MmioAccess, Observers},
+    time::Clock,
+    timing::Pipeline,
+    trigger::{Trigger, TCONTROL, TCONTROL_MPTE, TCONTROL_MTE, TRIGGERS},
+    vector::Vector,
+};
+
+#[derive(Debug)]
+pub struct Cpu {
+    pub regs: [u64; 32],
+    /// Floating point registers, see [`crate::fpu`].
+    pub fregs: [u64; 32],
+    /// Vector registers, see [`crate::vector`].
+    pub vector: Vector,
+    pub pc: u64,
+    pub bus: Bus,
+    /// Control and status registers. RISC-V ISA sets aside a 12-bit encoding
+    /// space (csr[11:0]) for up to 4096 CSRs. This is their storage, what
+    /// the csr instructions see of it is up to [`crate::csr`].
+    pub csrs: [u64; 4096],
+    /// Drives the `time` csr.
+    pub clock: Box<dyn Clock>,
+    pub isa: Isa,
+    pub hooks: Hooks,
+    pub observers: Observers,
+    /// Events counted by the `mhpmcounter` csrs, see [`crate::hpm`].
+    pub hpm: Hpm,
+    /// Cache models fed by fetches and data accesses, see [`crate::cache`].
+    pub icache: Option<Cache>,
+    pub dcache: Option<Cache>,
+    /// Translations of virtual addresses, see [`crate::mmu`].
+    pub tlb: Tlb,
+    /// Charges pipeline stalls to `mcycle`, see [`crate::timing`].
+    pub pipeline: Option<Pipeline>,
+    /// The last instruction was a `wfi` with no interrupt pending, or a
+    /// `wrs.nto` holding a reservation: whatever runs the cpu may idle until
+    /// [`Cpu::interrupt_pending`] or the reservation is dropped.
+    pub idle: bool,
+    /// The privilege level the hart runs at.
+    pub mode: Mode,
+    /// Cycles elapsed and instructions retired on the running hart, whether
+    /// or not [`MCOUNTINHIBIT`] stops `mcycle` and `minstret`. The clock and
+    /// the observers go by these.
+    pub cycles: u64,
+    pub retired: u64,
+    /// What loads and stores not aligned on their size do.
+    pub misaligned: MisalignedPolicy,
+    /// Whether translations set the accessed and dirty bits, see
+    /// [`crate::mmu`].
+    pub ad_policy: AdPolicy,
+    /// Debug triggers of the running hart, see [`crate::trigger`].
+    pub triggers: [Trigger; TRIGGERS],
+    /// The last breakpoint exception was a trigger asking for debug mode:
+    /// whatever runs the cpu stops rather than taking it as a trap.
+    pub trigger_halt: bool,
+}
+
+/// Floating point accrued exceptions and rounding mode, fields of [`FCSR`].
+pub const FFLAGS: usize = 0x001;
+pub const FRM: usize = 0x002;
+pub const FCSR: usize = 0x003;
+/// Vector state, see [`crate::vector`]. [`VXSAT`] and [`VXRM`] are fields of [`VCSR`].
+pub const VSTART: usize = 0x008;
+pub const VXSAT: usize = 0x009;
+pub const VXRM: usize = 0x00A;
+pub const VCSR: usize = 0x00F;
+pub const VL: usize = 0xC20;
+pub const VTYPE: usize = 0xC21;
+pub const VLENB: usize = 0xC22;
+pub const MIP: usize = 0x344;
+pub const MIE: usize = 0x304;
+pub const SIP: usize = 0x144;
+pub const SIE: usize = 0x104;
+/// The extensions enabled, see [`Isa::misa`].
+pub const MISA: usize = 0x301;
+/// The exceptions and interrupts trapping to the supervisor rather than to
+/// machine mode, a bit per cause.
+pub const MEDELEG: usize = 0x302;
+pub const MIDELEG: usize = 0x303;
+/// Machine mode trap setup and handling, see [`Cpu::trap`].
+pub const MSTATUS: usize = 0x300;
+pub const MTVEC: usize = 0x305;
+pub const MEPC: usize = 0x341;
+pub const MCAUSE: usize = 0x342;
+pub const MTVAL: usize = 0x343;
+/// Supervisor mode trap setup and handling. [`SSTATUS`] is a view of the
+/// supervisor fields of [`MSTATUS`].
+pub const SSTATUS: usize = 0x100;
+pub const STVEC: usize = 0x105;
+pub const SEPC: usize = 0x141;
+pub const SCAUSE: usize = 0x142;
+pub const STVAL: usize = 0x143;
+pub const SATP: usize = 0x180;
+pub const RDCYCLE: usize = 0xC00;
+pub const RDTIME: usize = 0xC01;
+pub const INSTRET: usize = 0xC02;
+/// Machine mode aliases of [`RDCYCLE`] and [`INSTRET`].
+pub const MCYCLE: usize = 0xB00;
+pub const MINSTRET: usize = 0xB02;
+/// The high halves of the counters, for RV32.
+pub const RDCYCLEH: usize = 0xC80;
+pub const RDTIMEH: usize = 0xC81;
+pub const INSTRETH: usize = 0xC82;
+pub const MCYCLEH: usize = 0xB80;
+pub const MINSTRETH: usize = 0xB82;
+pub const MHARTID: usize = 0xF14;
+/// The counters readable below machine mode, a bit per counter from
+/// [`RDCYCLE`] up. The user needs the bit in both.
+pub const MCOUNTEREN: usize = 0x306;
+pub const SCOUNTEREN: usize = 0x106;
+/// Stops the counters, with the bits of [`MCOUNTEREN`]. `time` can't be.
+pub const MCOUNTINHIBIT: usize = 0x320;
+/// Machine environment configuration, [`MENVCFGH`] holds the high half on
+/// RV32. Only [`MENVCFG_STCE`] is implemented.
+pub const MENVCFG: usize = 0x30A;
+pub const MENVCFGH: usize = 0x31A;
+/// Enables Sstc: the supervisor timer interrupt is pending while `time` is
+/// at least [`STIMECMP`], rather than as machine mode sets it.
+pub const MENVCFG_STCE: u64 = 1 << 63;
+/// Supervisor timer compare of Sstc, [`STIMECMPH`] holds the high half on
+/// RV32.
+pub const STIMECMP: usize = 0x14D;
+pub const STIMECMPH: usize = 0x15D;
+
+/// Size in bytes of the blocks the `cbo` instructions operate on.
+pub const CACHE_BLOCK: u64 = 64;
+
+/// Fields of [`MSTATUS`]: the interrupt enables, the enables before the
+/// last trap and the mode it came from, the state of the floating point
+/// registers, the address translation of loads and stores, see
+/// [`crate::mmu`], and the bits trapping the supervisor's `satp` accesses,
+/// waits and `sret`s.
+const STATUS_SIE: u64 = 1 << 1;
+const STATUS_MIE: u64 = 1 << 3;
+const STATUS_SPIE: u64 = 1 << 5;
+const STATUS_MPIE: u64 = 1 << 7;
+const STATUS_SPP: u64 = 1 << 8;
+const STATUS_MPP: u64 = 0b11 << 11;
+pub(crate) const STATUS_FS: u64 = 0b11 << 13;
+pub(crate) const STATUS_MPRV: u64 = 1 << 17;
+pub(crate) const STATUS_SUM: u64 = 1 << 18;
+pub(crate) const STATUS_MXR: u64 = 1 << 19;
+const STATUS_TVM: u64 = 1 << 20;
+const STATUS_TW: u64 = 1 << 21;
+const STATUS_TSR: u64 = 1 << 22;
+
+/// What a load or store whose address isn't a multiple of its size does.
+/// Atomics raise an address misaligned exception either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MisalignedPolicy {
+    /// Accesses the bytes one at a time, as a trap handler emulating it
+    /// would. It may fault half way through a store.
+    #[default]
+    Emulate,
+    /// Raises an address misaligned exception, with the address in `xtval`.
+    Trap,
+}
+
+impl FromStr for MisalignedPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "emulate" => Ok(Self::Emulate),
+            "trap" => Ok(Self::Trap),
+            _ => Err(format!(
+                "unknown misaligned policy {s}, expected emulate or trap"
+            )),
+        }
+    }
+}
+
+/// Privilege levels, as `mstatus.MPP` encodes them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Mode {
+    User = 0,
+    Supervisor = 1,
+    #[default]
+    Machine = 3,
+}
+
+impl Mode {
+    /// The level `bits` encode, none for the reserved 2.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Mode::User),
+            1 => Some(Mode::Supervisor),
+            3 => Some(Mode::Machine),
+            _ => None,
+        }
+    }
+}
+
+impl Cpu {
+    pub fn new(code: Vec<u8>) -> Self {
+        Self::with_bus(Bus::new(Dram::new(code)), Isa::default())
+    }
+
+    /// Creates a cpu executing `isa` on top of an already populated bus.
+    pub fn with_bus(bus: Bus, isa: Isa) -> Self {
+        let mut cpu = Cpu {
+            regs: Default::default(),
+            fregs: Default::default(),
+            vector: Vector::default(),
+            pc: bus.dram.base,
+            csrs: core::array::from_fn(|addr| CSRS[addr].reset),
+            clock: default_clock(),
+            isa,
+            hooks: Hooks::default(),
+            observers: Observers::default(),
+            hpm: Hpm::default(),
+            icache: None,
+            dcache: None,
+            tlb: Tlb::default(),
+            pipeline: None,
+            idle: false,
+            mode: Mode::Machine,
+            cycles: 0,
+            retired: 0,
+            misaligned: MisalignedPolicy::default(),
+            ad_policy: AdPolicy::default(),
+            triggers: Default::default(),
+            trigger_halt: false,
+            bus,
+        };
+
+        cpu.regs[0] = 0;
+        cpu.regs[2] = cpu.sext(cpu.bus.dram.base + cpu.bus.dram.size());
+        if cpu.isa.xlen == Xlen::Rv32 {
+            // RV32 has no UXL or SXL
+            cpu.csrs[MSTATUS] &= !(0xf << 32);
+        }
+
+        cpu
+    }
+
+    /// Sets or clears the `mip` bit of `interrupt`, as a device raising or
+    /// lowering its line would.
+    pub fn set_pending(&mut self, interrupt: Interrupt, pending: bool) {
+        let bit = 1 << interrupt.code();
+        if (self.csrs[MIP] & bit != 0) == pending {
+            return;
+        }
+        if pending {
+            self.csrs[MIP] |= bit;
+        } else {
+            self.csrs[MIP] &= !bit;
+        }
+        for observer in self.observers.iter_mut() {
+            observer.on_pending(interrupt, pending);
+        }
+    }
+
+    /// Whether an interrupt is both pending and enabled in `mie`, which is
+    /// what wakes a hart up from a `wfi` whether or not it is taken.
+    pub fn interrupt_pending(&self) -> bool {
+        self.csrs[MIP] & self.csrs[MIE] != 0
+    }
+
+    /// Makes stores to instruction memory visible to the fetches that
+    /// follow, as `fence.i` does. Fetches always read the bus, so only the
+    /// instruction cache model has anything to drop.
+    pub fn flush_icache(&mut self) {
+        if let Some(icache) = &mut self.icache {
+            icache.invalidate();
+        }
+    }
+
+    /// Steps until an exception the program doesn't handle, see [`Cpu::trap`],
+    /// which is returned. A program returning to 0 from its entry point stops
+    /// with an instruction access fault there.
+    pub fn run(&mut self) -> Exception {
+        loop {
+            if let Err(exception) = self.step().or_else(|e| self.trap(e)) {
+                return exception;
+            }
+        }
+    }
+
+    /// Takes `exception`, raised by the instruction at the pc, as a trap to the
+    /// handler at `mtvec`, or at `stvec` if `medeleg` delegates it and the hart
+    /// isn't in machine mode: `xepc`, `xcause` and `xtval` describe it, and
+    /// `mstatus` stacks the interrupt enable and the mode it came from.
+    ///
+    /// The exception is returned instead while that `xtvec` is 0, which is
+    /// before the program installed a handler, or when fetching the handler
+    /// faults, which would trap again forever.
+    pub fn trap(&mut self, exception: Exception) -> Result<(), Exception> {
+        let code = exception.code();
+        let delegated = self.mode < Mode::Machine && (self.csrs[MEDELEG] >> code) & 1 != 0;
+        let base = self.csrs[if delegated { STVEC } else { MTVEC }] & !3;
+        let refetch = matches!(
+            exception,
+            Exception::InstructionAccessFault(_) | Exception::InstructionPageFault(_)
+        ) && exception.value() == base;
+        if base == 0 || refetch {
+            return Err(exception);
+        }
+        self.enter_trap(code, exception.value(), delegated);
+        Ok(())
+    }
+
+    /// The interrupt the hart takes before its next instruction, if any: the
+    /// highest priority one pending and enabled in `mie`, which `mideleg`
+    /// sends to machine or supervisor mode. Interrupts for a more privileged
+    /// mode than the hart's are always taken, for its own mode only with the
+    /// global enable of `mstatus` set. A mode with no handler takes none.
+    pub fn pending_interrupt(&self) -> Option<Interrupt> {
+        let pending = self.csrs[MIP] & self.csrs[MIE];
+        if pending == 0 {
+            return None;
+        }
+        let mstatus = self.csrs[MSTATUS];
+        let enabled = |mode: Mode, enable: u64, tvec: usize| {
+            (self.mode < mode || (self.mode == mode && mstatus & enable != 0))
+                && self.csrs[tvec] & !3 != 0
+        };
+        let machine = match enabled(Mode::Machine, STATUS_MIE, MTVEC) {
+            true => pending & !self.csrs[MIDELEG],
+            false => 0,
+        };
+        let supervisor = match enabled(Mode::Supervisor, STATUS_SIE, STVEC) {
+            true => pending & self.csrs[MIDELEG],
+            false => 0,
+        };
+        Interrupt::BY_PRIORITY
+            .into_iter()
+            .find(|x| machine >> x.code() & 1 != 0)
+            .or_else(|| {
+                Interrupt::BY_PRIORITY
+                    .into_iter()
+                    .find(|x| supervisor >> x.code() & 1 != 0)
+            })
+    }
+
+    /// Takes [`Cpu::pending_interrupt`] as a trap, with the pc as `xepc` and
+    /// the interrupt bit set in `xcause`, returning it.
+    pub fn take_interrupt(&mut self) -> Option<Interrupt> {
+        let interrupt = self.pending_interrupt()?;
+        let delegated = (self.csrs[MIDELEG] >> interrupt.code()) & 1 != 0;
+        let cause = 1 << (self.isa.xlen.bits() - 1) | interrupt.code();
+        let pc = self.pc;
+        self.enter_trap(cause, 0, delegated);
+        self.count(Event::Interrupt);
+        for observer in self.observers.iter_mut() {
+            observer.on_interrupt(pc, interrupt);
+        }
+        Some(interrupt)
+    }
+
+    /// Enters the machine mode handler, or the supervisor one if `delegated`.
+    /// The low bits of `xtvec` select the mode: direct sends every trap to
+    /// the base, vectored sends interrupts to `base + 4 * code`.
+    fn enter_trap(&mut self, cause: u64, tval: u64, delegated: bool) {
+        let (tvec, epc, xcause, xtval) = match delegated {
+            true => (STVEC, SEPC, SCAUSE, STVAL),
+            false => (MTVEC, MEPC, MCAUSE, MTVAL),
+        };
+        self.csrs[epc] = self.pc;
+        self.csrs[xcause] = cause;
+        self.csrs[xtval] = tval;
+        let mstatus = self.csrs[MSTATUS];
+        self.csrs[MSTATUS] = match delegated {
+            // SPP = mode, SPIE = SIE, SIE = 0
+            true => {
+                let spp = if self.mode == Mode::Supervisor {
+                    STATUS_SPP
+                } else {
+                    0
+                };
+                (mstatus & !(STATUS_SIE | STATUS_SPIE | STATUS_SPP))
+                    | (mstatus & STATUS_SIE) << 4
+                    | spp
+            }
+            // MPP = mode, MPIE = MIE, MIE = 0
+            false => {
+                (mstatus & !(STATUS_MIE | STATUS_MPIE | STATUS_MPP))
+                    | (mstatus & STATUS_MIE) << 4
+                    | (self.mode as u64) << 11
+            }
+        };
+        // MPTE = MTE, MTE = 0
+        if !delegated {
+            self.csrs[TCONTROL] = (self.csrs[TCONTROL] & TCONTROL_MTE) << 4;
+        }
+        self.mode = match delegated {
+            true => Mode::Supervisor,
+            false => Mode::Machine,
+        };
+        let interrupt = 1 << (self.isa.xlen.bits() - 1);
+        let base = self.csrs[tvec] & !3;
+        self.pc = match self.csrs[tvec] & 3 {
+            1 if cause & interrupt != 0 => base + 4 * (cause & !interrupt),
+            _ => base,
+        };
+    }
+
+    /// Takes the pending interrupt if any, then fetches and executes a single
+    /// instruction.
+    pub fn step(&mut self) -> Result<(), Exception> {
+        self.take_interrupt();
+        let pc = self.pc;
+        let cycle = self.cycles;
+        let result = self.fetch_and_execute();
+
+        match &result {
+            Ok(inst) => {
+                let cycles = self.cycles.wrapping_sub(cycle);
+                for observer in self.observers.iter_mut() {
+                    observer.on_instruction(pc, inst);
+                    observer.on_cycles(cycles);
+                }
+            }
+            Err(exception) => {
+                self.count(Event::Exception);
+                for observer in self.observers.iter_mut() {
+                    observer.on_trap(pc, exception);
+                }
+            }
+        }
+
+        result.map(|_| ())
+    }
+
+    /// The low XLEN bits of `x`, zero extended: an address, or an operand of
+    /// the unsigned operations.
+    pub fn unsigned(&self, x: u64) -> u64 {
+        match self.isa.xlen {
+            Xlen::Rv32 => x as u32 as u64,
+            Xlen::Rv64 => x,
+        }
+    }
+
+    /// The low XLEN bits of `x`, sign extended as the registers hold them.
+    fn sext(&self, x: u64) -> u64 {
+        match self.isa.xlen {
+            Xlen::Rv32 => x as i32 as i64 as u64,
+            Xlen::Rv64 => x,
+        }
+    }
+
+    /// The low XLEN bits of `x` rotated right by `shamt`.
+    fn rotate_right(&self, x: u64, shamt: u32) -> u64 {
+        match self.isa.xlen {
+            Xlen::Rv32 => (x as u32).rotate_right(shamt) as u64,
+            Xlen::Rv64 => x.rotate_right(shamt),
+        }
+    }
+
+    /// Increments the performance counters selecting `event`, but those
+    /// `mcountinhibit` stops.
+    pub fn count(&mut self, event: Event) {
+        for i in self.hpm.counters(event) {
+            if self.csrs[MCOUNTINHIBIT] & (1 << (i + 3)) == 0 {
+                self.csrs[MHPMCOUNTER3 + i] = self.csrs[MHPMCOUNTER3 + i].wrapping_add(1);
+            }
+        }
+    }
+
+    /// Charges `cycles` to the running hart, and to `mcycle` unless it is
+    /// inhibited.
+    pub fn add_cycles(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        if self.csrs[MCOUNTINHIBIT] & 1 == 0 {
+            self.csrs[RDCYCLE] += cycles;
+        }
+    }
+
+    /// Counts an instruction retiring, in `minstret` if `counted` and it isn't
+    /// inhibited, and moves `time` along.
+    fn retire(&mut self, counted: bool) {
+        self.retired += 1;
+        if counted && self.csrs[MCOUNTINHIBIT] & (1 << 2) == 0 {
+            self.csrs[INSTRET] += 1;
+        }
+        self.csrs[RDTIME] = self.clock.now(self.retired);
+        self.poll_stimecmp();
+    }
+
+    /// Makes the supervisor timer interrupt pending as `stimecmp` says, if
+    /// Sstc is enabled.
+    pub fn poll_stimecmp(&mut self) {
+        if let Some(pending) = stimecmp_pending(&self.csrs, self.csrs[RDTIME]) {
+            self.set_pending(Interrupt::SupervisorTimer, pending);
+        }
+    }
+
+    /// Counts the events of an instruction which retired.
+    fn count_retired(&mut self, inst: &Instruction, pc: u64) {
+        match inst.opcode {
+            0x03 => self.count(Event::Load),
+            0x23 => self.count(Event::Store),
+            0x2f => match inst.funct7 >> 2 {
+                // lr
+                0x02 => self.count(Event::Load),
+                // sc
+                0x03 => self.count(Event::Store),
+                _ => {
+                    self.count(Event::Load);
+                    self.count(Event::Store);
+                }
+            },
+            0x63 => {
+                self.count(Event::Branch);
+                if self.pc != pc.wrapping_add(inst.len) {
+                    self.count(Event::TakenBranch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn fetch_and_execute(&mut self) -> Result<Instruction, Exception> {
+        self.idle = false;
+        let raw = self.fetch()?;
+        let inst = match is_compressed(raw) {
+            true => {
+                // Parcels which don't expand decode as the all-zero illegal
+                // instruction, reported as fetched below.
+                let expand = match self.isa.xlen {
+                    Xlen::Rv32 => compressed::expand_rv32,
+                    Xlen::Rv64 => compressed::expand,
+                };
+                let expanded = expand(raw as u16)
+                    .filter(|_| self.isa.has(Extension::C))
+                    .unwrap_or(0);
+                Instruction {
+                    len: 2,
+                    ..Instruction::decode(expanded as u64)
+                }
+            }
+            false => Instruction::decode(raw),
+        };
+        let pc = self.pc;
+        self.pc += inst.len;
+
+        // Update counters, a write to mcycle holds for the instruction itself
+        self.add_cycles(1);
+        let instret = self.csrs[INSTRET];
+
+        self.hooks.run_pre(&HookContext {
+            pc,
+            inst,
+            regs: &self.regs,
+        });
+
+        // 3. Decode.
+        // 4. Execute.
+        let result = match self.execute(inst) {
+            // xtval holds the instruction as fetched, not its expansion.
+            Err(Exception::IllegalInstruction(_)) if inst.len == 2 => {
+                Err(Exception::IllegalInstruction(raw))
+            }
+            result => result,
+        };
+        self.regs[0] = 0;
+        // RV32 keeps the registers sign extended from bit 31, which most
+        // instructions then get right without knowing the XLEN.
+        if self.isa.xlen == Xlen::Rv32 {
+            self.regs[inst.rd] = self.sext(self.regs[inst.rd]);
+            self.pc = self.unsigned(self.pc);
+        }
+
+        match result {
+            Ok(()) => {
+                // Only once it didn't trap, so that a write to mcountinhibit
+                // or to minstret holds for the instruction itself.
+                self.retire(self.csrs[INSTRET] == instret);
+                if self.hpm.is_active() {
+                    self.count_retired(&inst, pc);
+                }
+                if let Some(pipeline) = &mut self.pipeline {
+                    let cycles = pipeline.retire(&inst, pc, self.pc);
+                    self.add_cycles(cycles);
+                }
+                self.hooks.run_post(&HookContext {
+                    pc,
+                    inst,
+                    regs: &self.regs,
+                })
+            }
+            // Exceptions are precise, leave pc at the instruction which raised it.
+            Err(_) => {
+                self.pc = pc;
+                if let Some(pipeline) = &mut self.pipeline {
+                    pipeline.flush();
+                }
+            }
+        }
+
+        result.map(|_| inst)
+    }
+
+    #[instrument(skip(self))]
+    fn load_csr(&mut self, addr: usize) -> u64 {
+        debug!("loading csr");
+        let csr = &CSRS[addr];
+        let value = match csr.read {
+            Some(read) => read(self, addr),
+            None => self.csrs[addr] & csr.read_mask,
+        };
+
+        for observer in self.observers.iter_mut() {
+            observer.on_csr_access(addr, value, AccessKind::Read);
+        }
+
+        value
+    }
+
+    /// Whether the hart may access `addr`, writing it if `write`: the address
+    /// encodes the lowest privilege level and whether the csr is read only.
+    /// `mstatus.TVM` keeps `satp` from the supervisor, and the counter
+    /// enables the unprivileged counters. The supervisor needs both Sstc and
+    /// `time` enabled for `stimecmp`. There is neither a hypervisor nor a
+    /// debug mode, so their csrs are never accessible.
+    fn csr_accessible(&self, addr: usize, write: bool) -> bool {
+        let level = (addr >> 8) & 0b11;
+        let read_only = addr >> 10 == 0b11;
+        let trapped_vm =
+            addr == SATP && self.mode == Mode::Supervisor && self.csrs[MSTATUS] & STATUS_TVM != 0;
+        let trapped_timer = matches!(addr, STIMECMP | STIMECMPH)
+            && self.mode != Mode::Machine
+            && (self.csrs[MENVCFG] & MENVCFG_STCE == 0 || self.csrs[MCOUNTEREN] & 0b10 == 0);
+        let fp_off = matches!(addr, FFLAGS | FRM | FCSR) && self.csrs[MSTATUS] & STATUS_FS == 0;
+        let missing = level == 0b10 || (0x7b0..=0x7bf).contains(&addr);
+        if missing || trapped_vm || trapped_timer || fp_off || !self.counter_enabled(addr) {
+            return false;
+        }
+        self.mode as usize >= level && !(write && read_only)
+    }
+
+    /// Whether `addr` isn't an unprivileged counter, or `mcounteren`, and
+    /// `scounteren` for the user, enable it.
+    fn counter_enabled(&self, addr: usize) -> bool {
+        if !matches!(addr, RDCYCLE..=0xC1F | RDCYCLEH..=0xC9F) {
+            return true;
+        }
+        let bit = 1 << (addr & 0x1f);
+        let machine = self.csrs[MCOUNTEREN] & bit != 0;
+        let supervisor = self.csrs[SCOUNTEREN] & bit != 0;
+        match self.mode {
+            Mode::Machine => true,
+            Mode::Supervisor => machine,
+            Mode::User => machine && supervisor,
+        }
+    }
+
+    /// The bits of `xepc` an `xret` returns to: instructions are aligned on 2
+    /// bytes with C, 4 without.
+    fn epc_mask(&self) -> u64 {
+        match self.isa.has(Extension::C) {
+            true => !1,
+            false => !3,
+        }
+    }
+
+    /// `target` as the next pc of a jump, branch or return, which raises an
+    /// instruction address misaligned exception unless it's on a 4 byte
+    /// boundary or the hart has C.
+    fn jump_target(&self, target: u64) -> Result<u64, Exception> {
+        match self.isa.has(Extension::C) || target & 3 == 0 {
+            true => Ok(target),
+            false => Err(Exception::InstructionAddressMisaligned(target)),
+        }
+    }
+
+    /// Whether a `wfi` or `wrs.nto` is illegal in the current mode: always
+    /// below the supervisor, and with `mstatus.TW` below machine mode.
+    fn wait_trapped(&self) -> bool {
+        match self.mode {
+            Mode::User => true,
+            Mode::Supervisor => self.csrs[MSTATUS] & STATUS_TW != 0,
+            Mode::Machine => false,
+        }
+    }
+
+    #[instrument(skip(self))]
+    fn store_csr(&mut self, addr: usize, value: u64) {
+        debug!("storing csr");
+        for observer in self.observers.iter_mut() {
+            observer.on_csr_access(addr, value, AccessKind::Write);
+        }
+
+        let csr = &CSRS[addr];
+        match csr.write {
+            Some(write) => write(self, addr, value),
+            None => {
+                self.csrs[addr] = (self.csrs[addr] & !csr.write_mask) | (value & csr.write_mask);
+            }
+        }
+    }
+
+    #[inline]
+    /// Fetches the instruction at the pc, 16 bits of it if it is compressed.
+    fn fetch(&mut self) -> Result<u64, Exception> {
+        let pc = self.pc;
+        let fault = |_| Exception::InstructionAccessFault(pc);
+        self.check_triggers(pc, 2, Access::Fetch)?;
+        let addr = self.translate(pc, Access::Fetch)?;
+        self.pmp_check(pc, addr, 2, Access::Fetch)?;
+        let mut inst = self.bus.load(addr, 16).map_err(fault)?;
+        let len = match is_compressed(inst) {
+            true => 2,
+            false => {
+                // the upper half may be on the next page, or in another
+                // region, only the next page is translated again
+                let upper = pc.wrapping_add(2);
+                let addr = match upper.is_multiple_of(PAGE_SIZE) {
+                    true => self.translate(upper, Access::Fetch)?,
+                    false => addr + 2,
+                };
+                self.pmp_check(upper, addr, 2, Access::Fetch)?;
+                let fault = |_| Exception::InstructionAccessFault(upper);
+                inst |= self.bus.load(addr, 16).map_err(fault)? << 16;
+                4
+            }
+        };
+        if let Some(icache) = &mut self.icache {
+            if !icache.access(pc, len) {
+                let penalty = icache.config.miss_penalty;
+                self.add_cycles(penalty);
+                self.count(Event::ICacheMiss);
+            }
+        }
+        Ok(inst)
+    }
+
+    /// Feeds a data access which went to dram to the data cache.
+    fn access_dcache(&mut self, addr: u64, size: u64) {
+        let Some(dcache) = &mut self.dcache else {
+            return;
+        };
+        if self.bus.is_mmio(addr) {
+            return;
+        }
+        if !dcache.access(addr, size / 8) {
+            let penalty = dcache.config.miss_penalty;
+            self.add_cycles(penalty);
+            self.count(Event::DCacheMiss);
+        }
+    }
+
+    /// Data load through the bus, reporting it to the observers.
+    pub(crate) fn load(&mut self, addr: u64, size: u64) -> Result<u64, Exception> {
+        self.check_triggers(addr, size / 8, Access::Load)?;
+        let aligned = addr.is_multiple_of(size / 8);
+        if !aligned && self.misaligned == MisalignedPolicy::Trap {
+            Err(Exception::LoadAddressMisaligned(addr))?
+        }
+        let paddr = self.translate(addr, Access::Load)?;
+        let value = match aligned {
+            true => {
+                self.pmp_check(addr, paddr, size / 8, Access::Load)?;
+                self.bus.load(paddr, size)?
+            }
+            // the bytes may be on another page, or another device
+            false => (0..size / 8).try_fold(0, |value, i| {
+                let vaddr = addr.wrapping_add(i);
+                let paddr = self.translate(vaddr, Access::Load)?;
+                self.pmp_check(vaddr, paddr, 1, Access::Load)?;
+                Ok::<_, Exception>(value | self.bus.load(paddr, 8)? << (8 * i))
+            })?,
+        };
+        self.access_dcache(paddr, size);
+        if !self.observers.is_empty() {
+            self.observe(MmioAccess {
+                addr: paddr,
+                size,
+                value,
+                kind: AccessKind::Read,
+            });
+        }
+        Ok(value)
+    }
+
+    pub(crate) fn store(&mut self, addr: u64, size: u64, value: u64) -> Result<(), Exception> {
+        self.check_triggers(addr, size / 8, Access::Store)?;
+        let aligned = addr.is_multiple_of(size / 8);
+        if !aligned && self.misaligned == MisalignedPolicy::Trap {
+            Err(Exception::StoreAmoAddressMisaligned(addr))?
+        }
+        let paddr = self.translate(addr, Access::Store)?;
+        match aligned {
+            true => {
+                self.pmp_check(addr, paddr, size / 8, Access::Store)?;
+                self.bus.store(paddr, size, value)?
+            }
+            false => {
+                for i in 0..size / 8 {
+                    let vaddr = addr.wrapping_add(i);
+                    let paddr = self.translate(vaddr, Access::Store)?;
+                    self.pmp_check(vaddr, paddr, 1, Access::Store)?;
+                    self.bus.store(paddr, 8, (value >> (8 * i)) & 0xff)?;
+                }
+            }
+        }
+        self.access_dcache(paddr, size);
+        if !self.observers.is_empty() {
+            self.observe(MmioAccess {
+                addr: paddr,
+                size,
+                value,
+                kind: AccessKind::Write,
+            });
+        }
+        Ok(())
+    }
+
+    fn observe(&mut self, access: MmioAccess) {
+        let mmio = self.bus.is_mmio(access.addr);
+        for observer in self.observers.iter_mut() {
+            observer.on_memory(access.addr, access.size, access.kind);
+            if mmio {
+                observer.on_mmio(&access);
+            }
+        }
+    }
+
+    #[instrument(
+        skip(self),
+        fields(opcode, rd, rs1, rs2, funct3, funct7, imm, shamt, csr, csr_addr)
+    )]
+    fn execute(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        let Instruction {
+            raw: inst,
+            opcode,
+            rd,
+            rs1,
+            rs2,
+            funct3,
+            funct7,
+            len,
+        } = decoded;
+
+        tracing::Span::current().record("opcode", opcode);
+        tracing::Span::current().record("rd", rd);
+        tracing::Span::current().record("rs1", rs1);
+        tracing::Span::current().record("rs2", rs2);
+        tracing::Span::current().record("funct3", funct3);
+        tracing::Span::current().record("funct7", funct7);
+
+        // Reject instructions from extensions that aren't part of the configured isa, any of the
+        // listed extensions provides the instruction.
+        let required: &[Extension] = match (opcode, funct7) {
+            (0x33 | 0x3b, 0x1) if funct3 < 0x4 => &[Extension::M, Extension::Zmmul],
+            (0x33 | 0x3b, 0x1) => &[Extension::M],
+            (0x33, 0x7) => &[Extension::Zicond],
+            (0x2f, _) => &[Extension::A],
+            (0x07 | 0x27, _) if funct3 == 0b010 => &[Extension::F],
+            (0x07 | 0x27, _) if funct3 == 0b011 => &[Extension::D],
+            (0x07 | 0x27, _) if funct3 == 0b001 => &[Extension::Zfh],
+            // by the destination format, see execute_fp for conversions
+            (0x43 | 0x47 | 0x4b | 0x4f | 0x53, _) if funct7 & 0x3 == 0 => &[Extension::F],
+            (0x43 | 0x47 | 0x4b | 0x4f | 0x53, _) if funct7 & 0x3 == 1 => &[Extension::D],
+            (0x43 | 0x47 | 0x4b | 0x4f | 0x53, _) if funct7 & 0x3 == 2 => &[Extension::Zfh],
+            (0x33 | 0x3b, 0x10) => &[Extension::Zba],
+            // add.uw, slli.uw
+            (0x3b, 0x04) if funct3 == 0x0 => &[Extension::Zba],
+            (0x1b, 0x04 | 0x05) if funct3 == 0x1 => &[Extension::Zba],
+            // andn, orn, xnor and the rotations
+            (0x33, 0x20) if matches!(funct3, 0x4 | 0x6 | 0x7) => &[Extension::Zbb, Extension::Zbkb],
+            (0x33 | 0x3b, 0x30) => &[Extension::Zbb, Extension::Zbkb],
+            (0x33, 0x05) if funct3 >= 0x4 => &[Extension::Zbb],
+            // zext.h is packw with x0
+            (0x3b, 0x04) if funct3 == 0x4 && rs2 == 0 => &[Extension::Zbb, Extension::Zbkb],
+            (0x13 | 0x1b, 0x30) if funct3 == 0x1 => &[Extension::Zbb],
+            (0x13 | 0x1b, 0x30) if funct3 == 0x5 => &[Extension::Zbb, Extension::Zbkb],
+            // rori, rev8
+            (0x13, 0x31 | 0x35) if funct3 == 0x5 => &[Extension::Zbb, Extension::Zbkb],
+            // orc.b, brev8
+            (0x13, 0x14) if funct3 == 0x5 => &[Extension::Zbb],
+            (0x13, 0x34) if funct3 == 0x5 && rs2 == 0x7 => &[Extension::Zbkb],
+            // rev8 on RV32, zip and unzip
+            (0x13, 0x34) if funct3 == 0x5 && rs2 == 0x18 => &[Extension::Zbb, Extension::Zbkb],
+            (0x13, 0x04) if funct3 & 0x3 == 0x1 && rs2 == 0xf => &[Extension::Zbkb],
+            (0x33, 0x14) if funct3 == 0x2 || funct3 == 0x4 => &[Extension::Zbkx],
+            (0x33, 0x14 | 0x24 | 0x34) => &[Extension::Zbs],
+            (0x13, 0x14 | 0x15 | 0x24 | 0x25 | 0x34 | 0x35) if funct3 == 0x1 || funct3 == 0x5 => {
+                &[Extension::Zbs]
+            }
+            // pack, packh, packw
+            (0x33 | 0x3b, 0x04) => &[Extension::Zbkb],
+            (0x33, 0x05) => &[Extension::Zbkc],
+            // aes64es, aes64esm, aes64ds, aes64dsm and aes64ks2, then aes64im and aes64ks1i
+            (0x33, 0x19 | 0x1b) if funct3 == 0x0 => &[Extension::Zkne],
+            (0x33, 0x1d | 0x1f) if funct3 == 0x0 => &[Extension::Zknd],
+            (0x33, 0x3f) if funct3 == 0x0 => &[Extension::Zkne, Extension::Zknd],
+            (0x13, 0x18) if funct3 == 0x1 && rs2 == 0 => &[Extension::Zknd],
+            (0x13, 0x18) if funct3 == 0x1 => &[Extension::Zkne, Extension::Zknd],
+            // aes32esi, aes32esmi, aes32dsi and aes32dsmi, bs in the top bits
+            (0x33, _) if funct3 == 0x0 && matches!(funct7 & 0x1f, 0x11 | 0x13) => {
+                &[Extension::Zkne]
+            }
+            (0x33, _) if funct3 == 0x0 && matches!(funct7 & 0x1f, 0x15 | 0x17) => {
+                &[Extension::Zknd]
+            }
+            // the halves of the sha512 functions
+            (0x33, 0x28..=0x2b | 0x2e | 0x2f) if funct3 == 0x0 => &[Extension::Zknh],
+            (0x13, 0x08) if funct3 == 0x1 && rs2 < 8 => &[Extension::Zknh],
+            (0x13, 0x08) if funct3 == 0x1 => &[Extension::Zksh],
+            (0x33, _) if funct3 == 0x0 && funct7 & 0x1d == 0x18 => &[Extension::Zksed],
+            (0x0f, _) if funct3 == 0x1 => &[Extension::Zifencei],
+            (0x0f, _) if funct3 == 0x2 && rs2 == 4 => &[Extension::Zicboz],
+            (0x0f, _) if funct3 == 0x2 => &[Extension::Zicbom],
+            (0x07 | 0x27, _) if matches!(funct3, 0x0 | 0x5 | 0x6 | 0x7) => &[Extension::V],
+            (0x57, _) => &[Extension::V],
+            // wrs.nto, wrs.sto
+            (0x73, _) if inst == 0x00d00073 || inst == 0x01d00073 => &[Extension::Zawrs],
+            (0x73, _) => &[Extension::Zicsr],
+            _ => &[],
+        };
+        if !required.is_empty() && !required.iter().any(|ext| self.isa.has(*ext)) {
+            Err(Exception::IllegalInstruction(inst))?
+        }
+        // The word instructions, the doubleword accesses, shift amounts past
+        // 31 and the aes64 and whole sha512 instructions only exist on RV64.
+        let rv64_only = match opcode {
+            0x1b | 0x3b => true,
+            0x03 => funct3 == 0x3 || funct3 == 0x6,
+            0x23 | 0x2f => funct3 == 0x3,
+            0x13 => {
+                (funct3 == 0x1 || funct3 == 0x5) && funct7 & 1 != 0
+                    || funct3 == 0x1 && (funct7 == 0x18 || funct7 == 0x08 && (4..8).contains(&rs2))
+            }
+            0x33 => funct3 == 0x0 && matches!(funct7, 0x19 | 0x1b | 0x1d | 0x1f | 0x3f),
+            _ => false,
+        };
+        // and zip, unzip, the aes32 and the split sha512 ones on RV32
+        let rv32_only = match opcode {
+            0x13 => funct3 & 0x3 == 0x1 && funct7 == 0x04 && rs2 == 0xf,
+            0x33 => {
+                funct3 == 0x0
+                    && (matches!(funct7 & 0x1f, 0x11 | 0x13 | 0x15 | 0x17)
+                        || matches!(funct7, 0x28..=0x2b | 0x2e | 0x2f))
+            }
+            _ => false,
+        };
+        let rv32 = self.isa.xlen == Xlen::Rv32;
+        if rv64_only && rv32 || rv32_only && !rv32 {
+            Err(Exception::IllegalInstruction(inst))?
+        }
+
+        match opcode {
+            // load
+            0x03 => {
+                // imm[11:0] = inst[31:20]
+                let imm = ((inst as i32 as i64) >> 20) as u64;
+                tracing::Span::current().record("imm", imm);
+                let addr = self.unsigned(self.regs[rs1].wrapping_add(imm));
+
+                match funct3 {
+                    0x0 => {
+                        // lb
+                        debug!("LB");
+                        self.regs[rd] = self.load(addr, 8)? as i8 as i64 as u64;
+                    }
+                    0x1 => {
+                        // lh
+                        debug!("LH");
+                        self.regs[rd] = self.load(addr, 16)? as i16 as i64 as u64;
+                    }
+                    0x2 => {
+                        // lw
+                        debug!("LW");
+                        self.regs[rd] = self.load(addr, 32)? as i32 as i64 as u64;
+                    }
+                    0x3 => {
+                        // ld
+                        debug!("LD");
+                        self.regs[rd] = self.load(addr, 64)? as i64 as u64;
+                    }
+                    0x4 => {
+                        // lbu
+                        debug!("LBU");
+                        self.regs[rd] = self.load(addr, 8)?;
+                    }
+                    0x5 => {
+                        // lhu
+                        debug!("LHU");
+                        self.regs[rd] = self.load(addr, 16)?;
+                    }
+                    0x6 => {
+                        // lwu
+                        debug!("LWU");
+                        self.regs[rd] = self.load(addr, 32)?;
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                };
+            }
+            // store
+            0x23 => {
+                // imm[11:5|4:0] = inst[31:25|11:7]
+                let imm = (((inst & 0xfe000000) as i32 as i64 >> 20) as u64) | ((inst >> 7) & 0x1f);
+                tracing::Span::current().record("imm", imm);
+                let addr = self.unsigned(self.regs[rs1].wrapping_add(imm));
+
+                match funct3 {
+                    0x0 => {
+                        debug!("SB");
+                        self.store(addr, 8, self.regs[rs2])?
+                    }
+                    0x1 => {
+                        debug!("SH");
+                        self.store(addr, 16, self.regs[rs2])?
+                    }
+                    0x2 => {
+                        debug!("SW");
+                        self.store(addr, 32, self.regs[rs2])?
+                    }
+                    0x3 => {
+                        debug!("SD");
+                        self.store(addr, 64, self.regs[rs2])?
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            // base imm
+            0x13 => {
+                let imm = ((inst & 0xfff00000) as i32 as i64 >> 20) as u64;
+                tracing::Span::current().record("imm", imm);
+
+                // "The shift amount is encoded in the lower 6 bits of the I-immediate field for RV64I."
+                let shamt = (imm & 0x3f) as u32;
+                tracing::Span::current().record("shamt", shamt);
+
+                // The low bit of funct7 is shamt[5].
+                match (funct3, funct7 >> 1) {
+                    (0x0, _) => {
+                        // addi
+                        debug!("ADDI");
+                        self.regs[rd] = self.regs[rs1].wrapping_add(imm);
+                    }
+                    (0x4, _) => {
+                        // xori
+                        debug!("XORI");
+                        self.regs[rd] = self.regs[rs1].bitxor(imm);
+                    }
+                    (0x6, _) => {
+                        // ori
+                        debug!("ORI");
+                        self.regs[rd] = self.regs[rs1].bitor(imm);
+                    }
+                    (0x7, _) => {
+                        // andi
+                        debug!("ANDI");
+                        self.regs[rd] = self.regs[rs1].bitand(imm);
+                    }
+                    (0x1, 0x00) => {
+                        // slli
+                        debug!("SLLI");
+                        self.regs[rd] = self.regs[rs1].wrapping_shl(shamt);
+                    }
+                    (0x5, 0x00) => {
+                        // srli
+                        debug!("SRLI");
+                        self.regs[rd] = self.unsigned(self.regs[rs1]).wrapping_shr(shamt);
+                    }
+                    (0x5, 0x10) => {
+                        // srai
+                        debug!("SRAI");
+                        self.regs[rd] = (self.regs[rs1] as i64).wrapping_shr(shamt) as u64;
+                    }
+                    (0x1, 0x18) if funct7 == 0x30 => {
+                        // the unary operations, rs2 selects which
+                        let x = self.regs[rs1];
+                        self.regs[rd] = match rs2 {
+                            0 => {
+                                debug!("CLZ");
+                                x.leading_zeros() as u64
+                            }
+                            1 => {
+                                debug!("CTZ");
+                                x.trailing_zeros() as u64
+                            }
+                            2 => {
+                                debug!("CPOP");
+                                x.count_ones() as u64
+                            }
+                            4 => {
+                                debug!("SEXT.B");
+                                x as i8 as i64 as u64
+                            }
+                            5 => {
+                                debug!("SEXT.H");
+                                x as i16 as i64 as u64
+                            }
+                            _ => Err(Exception::IllegalInstruction(inst))?,
+                        };
+                    }
+                    (0x5, 0x18) => {
+                        debug!("RORI");
+                        self.regs[rd] = self.rotate_right(self.regs[rs1], shamt);
+                    }
+                    (0x5, 0x0a) if imm & 0xfff == 0x287 => {
+                        debug!("ORC.B");
+                        let bytes = self.regs[rs1]
+                            .to_le_bytes()
+                            .map(|b| if b != 0 { 0xff } else { 0 });
+                        self.regs[rd] = u64::from_le_bytes(bytes);
+                    }
+                    (0x5, 0x1a) if imm & 0xfff == 0x6b8 => {
+                        debug!("REV8");
+                        self.regs[rd] = self.regs[rs1].swap_bytes();
+                    }
+                    (0x5, 0x1a) if imm & 0xfff == 0x698 && self.isa.xlen == Xlen::Rv32 => {
+                        debug!("REV8");
+                        self.regs[rd] = (self.regs[rs1] as u32).swap_bytes() as u64;
+                    }
+                    (0x1, 0x02) if imm & 0xfff == 0x08f => {
+                        debug!("ZIP");
+                        self.regs[rd] = crypto::zip(self.regs[rs1]);
+                    }
+                    (0x5, 0x02) if imm & 0xfff == 0x08f => {
+                        debug!("UNZIP");
+                        self.regs[rd] = crypto::unzip(self.regs[rs1]);
+                    }
+                    (0x5, 0x1a) if imm & 0xfff == 0x687 => {
+                        debug!("BREV8");
+                        let bytes = self.regs[rs1].to_le_bytes().map(u8::reverse_bits);
+                        self.regs[rd] = u64::from_le_bytes(bytes);
+                    }
+                    (0x1, 0x0c) if imm & 0xfff == 0x300 => {
+                        debug!("AES64IM");
+                        self.regs[rd] = crypto::aes64im(self.regs[rs1]);
+                    }
+                    (0x1, 0x0c) if (imm >> 4) & 0xff == 0x31 => {
+                        debug!("AES64KS1I");
+                        self.regs[rd] = crypto::aes64ks1i(self.regs[rs1], imm & 0xf)
+                            .ok_or(Exception::IllegalInstruction(inst))?;
+                    }
+                    (0x1, 0x04) if funct7 == 0x08 => {
+                        // the hash functions, rs2 selects which
+                        let x = self.regs[rs1];
+                        self.regs[rd] = match rs2 {
+                            0 => {
+                                debug!("SHA256SUM0");
+                                crypto::sha256sum0(x)
+                            }
+                            1 => {
+                                debug!("SHA256SUM1");
+                                crypto::sha256sum1(x)
+                            }
+                            2 => {
+                                debug!("SHA256SIG0");
+                                crypto::sha256sig0(x)
+                            }
+                            3 => {
+                                debug!("SHA256SIG1");
+                                crypto::sha256sig1(x)
+                            }
+                            4 => {
+                                debug!("SHA512SUM0");
+                                crypto::sha512sum0(x)
+                            }
+                            5 => {
+                                debug!("SHA512SUM1");
+                                crypto::sha512sum1(x)
+                            }
+                            6 => {
+                                debug!("SHA512SIG0");
+                                crypto::sha512sig0(x)
+                            }
+                            7 => {
+                                debug!("SHA512SIG1");
+                                crypto::sha512sig1(x)
+                            }
+                            8 => {
+                                debug!("SM3P0");
+                                crypto::sm3p0(x)
+                            }
+                            9 => {
+                                debug!("SM3P1");
+                                crypto::sm3p1(x)
+                            }
+                            _ => Err(Exception::IllegalInstruction(inst))?,
+                        };
+                    }
+                    (0x1, 0x0a) => {
+                        debug!("BSETI");
+                        self.regs[rd] = self.regs[rs1] | 1 << shamt;
+                    }
+                    (0x1, 0x12) => {
+                        debug!("BCLRI");
+                        self.regs[rd] = self.regs[rs1] & !(1 << shamt);
+                    }
+                    (0x1, 0x1a) => {
+                        debug!("BINVI");
+                        self.regs[rd] = self.regs[rs1] ^ 1 << shamt;
+                    }
+                    (0x5, 0x12) => {
+                        debug!("BEXTI");
+                        self.regs[rd] = (self.regs[rs1] >> shamt) & 1;
+                    }
+                    (0x2, _) => {
+                        // slti
+                        debug!("SLTI");
+                        self.regs[rd] = ((self.regs[rs1] as i64) < (imm as i64)) as u64
+                    }
+                    (0x3, _) => {
+                        // sltiu
+                        debug!("SLTIU");
+                        self.regs[rd] = (self.regs[rs1] < imm) as u64
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            // base R
+            0x33 => {
+                // In RV64I, only the low 6 bits of rs2 are considered for the shift amount."
+                // RV32I takes the low 5.
+                let xlen = self.isa.xlen.bits();
+                let shamt = (self.regs[rs2] & (xlen as u64 - 1)) as u32;
+                tracing::Span::current().record("shamt", shamt);
+
+                match (funct3, funct7) {
+                    (0x0, 0x0) => {
+                        // add
+                        debug!("ADD");
+                        self.regs[rd] = self.regs[rs1].wrapping_add(self.regs[rs2]);
+                    }
+                    (0x0, 0x20) => {
+                        // sub
+                        debug!("SUB");
+                        self.regs[rd] = self.regs[rs1].wrapping_sub(self.regs[rs2]);
+                    }
+                    (0x4, 0x0) => {
+                        // xor
+                        debug!("XOR");
+                        self.regs[rd] = self.regs[rs1].bitxor(self.regs[rs2]);
+                    }
+                    (0x6, 0x0) => {
+                        // and
+                        debug!("OR");
+                        self.regs[rd] = self.regs[rs1].bitor(self.regs[rs2]);
+                    }
+                    (0x7, 0x0) => {
+                        // and
+                        debug!("AND");
+                        self.regs[rd] = self.regs[rs1].bitand(self.regs[rs2]);
+                    }
+                    (0x1, 0x0) => {
+                        // sll logical
+                        debug!("SLL");
+                        self.regs[rd] = self.regs[rs1].wrapping_shl(shamt);
+                    }
+                    (0x5, 0x0) => {
+                        // srl logical
+                        debug!("SRL");
+                        self.regs[rd] = self.unsigned(self.regs[rs1]).wrapping_shr(shamt);
+                    }
+                    (0x5, 0x20) => {
+                        // sra
+                        debug!("SRA");
+                        self.regs[rd] = (self.regs[rs1] as i64).wrapping_shr(shamt) as u64;
+                    }
+                    (0x2, 0x0) => {
+                        // slt
+                        debug!("SLT");
+                        self.regs[rd] = ((self.regs[rs1] as i64) < (self.regs[rs2] as i64)) as u64
+                    }
+                    (0x3, 0x0) => {
+                        // sltu
+                        debug!("SLTU");
+                        self.regs[rd] = (self.regs[rs1] < self.regs[rs2]) as u64
+                    }
+                    (0x2 | 0x4 | 0x6, 0x10) => {
+                        debug!("SH1ADD/SH2ADD/SH3ADD");
+                        let shift = funct3 >> 1;
+                        self.regs[rd] = (self.regs[rs1] << shift).wrapping_add(self.regs[rs2]);
+                    }
+                    (0x7, 0x20) => {
+                        debug!("ANDN");
+                        self.regs[rd] = self.regs[rs1] & !self.regs[rs2];
+                    }
+                    (0x6, 0x20) => {
+                        debug!("ORN");
+                        self.regs[rd] = self.regs[rs1] | !self.regs[rs2];
+                    }
+                    (0x4, 0x20) => {
+                        debug!("XNOR");
+                        self.regs[rd] = !(self.regs[rs1] ^ self.regs[rs2]);
+                    }
+                    (0x4, 0x5) => {
+                        debug!("MIN");
+                        self.regs[rd] = (self.regs[rs1] as i64).min(self.regs[rs2] as i64) as u64;
+                    }
+                    (0x5, 0x5) => {
+                        debug!("MINU");
+                        self.regs[rd] = self.regs[rs1].min(self.regs[rs2]);
+                    }
+                    (0x6, 0x5) => {
+                        debug!("MAX");
+                        self.regs[rd] = (self.regs[rs1] as i64).max(self.regs[rs2] as i64) as u64;
+                    }
+                    (0x7, 0x5) => {
+                        debug!("MAXU");
+                        self.regs[rd] = self.regs[rs1].max(self.regs[rs2]);
+                    }
+                    (0x1, 0x30) => {
+                        debug!("ROL");
+                        self.regs[rd] = self.rotate_right(self.regs[rs1], xlen - shamt);
+                    }
+                    (0x5, 0x30) => {
+                        debug!("ROR");
+                        self.regs[rd] = self.rotate_right(self.regs[rs1], shamt);
+                    }
+                    (0x1, 0x14) => {
+                        debug!("BSET");
+                        self.regs[rd] = self.regs[rs1] | 1 << shamt;
+                    }
+                    (0x1, 0x24) => {
+                        debug!("BCLR");
+                        self.regs[rd] = self.regs[rs1] & !(1 << shamt);
+                    }
+                    (0x1, 0x34) => {
+                        debug!("BINV");
+                        self.regs[rd] = self.regs[rs1] ^ 1 << shamt;
+                    }
+                    (0x5, 0x24) => {
+                        debug!("BEXT");
+                        self.regs[rd] = (self.regs[rs1] >> shamt) & 1;
+                    }
+                    (0x4, 0x04) => {
+                        debug!("PACK");
+                        self.regs[rd] = match self.isa.xlen {
+                            Xlen::Rv32 => self.regs[rs1] & 0xffff | (self.regs[rs2] & 0xffff) << 16,
+                            Xlen::Rv64 => self.regs[rs1] as u32 as u64 | self.regs[rs2] << 32,
+                        };
+                    }
+                    (0x7, 0x04) => {
+                        debug!("PACKH");
+                        self.regs[rd] = self.regs[rs1] & 0xff | (self.regs[rs2] & 0xff) << 8;
+                    }
+                    (0x1, 0x05) => {
+                        debug!("CLMUL");
+                        self.regs[rd] = crypto::clmul(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x3, 0x05) => {
+                        debug!("CLMULH");
+                        let (a, b) = (self.unsigned(self.regs[rs1]), self.unsigned(self.regs[rs2]));
+                        self.regs[rd] = match self.isa.xlen {
+                            Xlen::Rv32 => crypto::clmul(a, b) >> 32,
+                            Xlen::Rv64 => crypto::clmulh(a, b),
+                        };
+                    }
+                    (0x4, 0x14) => {
+                        debug!("XPERM8");
+                        // the bytes past XLEN index nothing
+                        let table = self.unsigned(self.regs[rs1]);
+                        self.regs[rd] = crypto::xperm8(table, self.regs[rs2]);
+                    }
+                    (0x2, 0x14) => {
+                        debug!("XPERM4");
+                        let table = self.unsigned(self.regs[rs1]);
+                        self.regs[rd] = crypto::xperm4(table, self.regs[rs2]);
+                    }
+                    (0x0, 0x19) => {
+                        debug!("AES64ES");
+                        self.regs[rd] = crypto::aes64es(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x1b) => {
+                        debug!("AES64ESM");
+                        self.regs[rd] = crypto::aes64esm(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x1d) => {
+                        debug!("AES64DS");
+                        self.regs[rd] = crypto::aes64ds(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x1f) => {
+                        debug!("AES64DSM");
+                        self.regs[rd] = crypto::aes64dsm(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x3f) => {
+                        debug!("AES64KS2");
+                        self.regs[rd] = crypto::aes64ks2(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x28) => {
+                        debug!("SHA512SUM0R");
+                        self.regs[rd] = crypto::sha512sum0r(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x29) => {
+                        debug!("SHA512SUM1R");
+                        self.regs[rd] = crypto::sha512sum1r(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x2a) => {
+                        debug!("SHA512SIG0L");
+                        self.regs[rd] = crypto::sha512sig0l(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x2b) => {
+                        debug!("SHA512SIG1L");
+                        self.regs[rd] = crypto::sha512sig1l(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x2e) => {
+                        debug!("SHA512SIG0H");
+                        self.regs[rd] = crypto::sha512sig0h(self.regs[rs1], self.regs[rs2]);
+                    }
+                    (0x0, 0x2f) => {
+                        debug!("SHA512SIG1H");
+                        self.regs[rd] = crypto::sha512sig1h(self.regs[rs1], self.regs[rs2]);
+                    }
+                    // bs, the byte to substitute, is in the top two bits of funct7
+                    (0x0, _) if funct7 & 0x1f == 0x11 => {
+                        debug!("AES32ESI");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::aes32esi(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x0, _) if funct7 & 0x1f == 0x13 => {
+                        debug!("AES32ESMI");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::aes32esmi(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x0, _) if funct7 & 0x1f == 0x15 => {
+                        debug!("AES32DSI");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::aes32dsi(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x0, _) if funct7 & 0x1f == 0x17 => {
+                        debug!("AES32DSMI");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::aes32dsmi(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x0, _) if funct7 & 0x1f == 0x18 => {
+                        debug!("SM4ED");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::sm4ed(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x0, _) if funct7 & 0x1f == 0x1a => {
+                        debug!("SM4KS");
+                        let bs = (funct7 >> 5) as u32;
+                        self.regs[rd] = crypto::sm4ks(self.regs[rs1], self.regs[rs2], bs);
+                    }
+                    (0x5, 0x7) => {
+                        debug!("CZERO.EQZ");
+
+                        if self.regs[rs2] == 0 {
+                            self.regs[rd] = 0;
+                        } else {
+                            self.regs[rd] = self.regs[rs1];
+                        }
+                    }
+                    (0x7, 0x7) => {
+                        debug!("CZERO.NEZ");
+
+                        if self.regs[rs2] != 0 {
+                            self.regs[rd] = 0;
+                        } else {
+                            self.regs[rd] = self.regs[rs1];
+                        }
+                    }
+                    (0x0, 0x1) => {
+                        // mul
+                        debug!("MUL");
+                        self.regs[rd] = self.regs[rs1].wrapping_mul(self.regs[rs2]);
+                    }
+                    (0x1, 0x1) => {
+                        // mulh
+                        debug!("MULH");
+                        self.regs[rd] = ((self.regs[rs1] as i64 as i128)
+                            .wrapping_mul(self.regs[rs2] as i64 as i128)
+                            >> xlen) as u64;
+                    }
+                    (0x3, 0x1) => {
+                        // mulhu
+                        debug!("MULHU");
+                        self.regs[rd] = ((self.unsigned(self.regs[rs1]) as u128)
+                            .wrapping_mul(self.unsigned(self.regs[rs2]) as u128)
+                            >> xlen) as u64;
+                    }
+                    (0x2, 0x1) => {
+                        // mulhsu
+                        debug!("MULHSU");
+                        self.regs[rd] = ((self.regs[rs1] as i64 as i128)
+                            .wrapping_mul(self.unsigned(self.regs[rs2]) as i128)
+                            >> xlen) as u64;
+                    }
+                    (0x4, 0x1) => {
+                        // div
+                        debug!("DIV");
+                        if self.regs[rs2] == 0 {
+                            self.regs[rd] = u64::MAX;
+                        } else {
+                            self.regs[rd] =
+                                (self.regs[rs1] as i64).wrapping_div(self.regs[rs2] as i64) as u64;
+                        }
+                    }
+                    (0x5, 0x1) => {
+                        // divu
+                        debug!("DIVU");
+                        if self.regs[rs2] == 0 {
+                            self.regs[rd] = u64::MAX;
+                        } else {
+                            self.regs[rd] = self
+                                .unsigned(self.regs[rs1])
+                                .wrapping_div(self.unsigned(self.regs[rs2]));
+                        }
+                    }
+                    (0x6, 0x1) => {
+                        // rem
+                        debug!("REM");
+                        if self.regs[rs2] == 0 {
+                            self.regs[rd] = self.regs[rs1];
+                        } else {
+                            self.regs[rd] =
+                                (self.regs[rs1] as i64).wrapping_rem(self.regs[rs2] as i64) as u64;
+                        }
+                    }
+                    (0x7, 0x1) => {
+                        // remu
+                        debug!("REMU");
+                        if self.regs[rs2] == 0 {
+                            self.regs[rd] = self.regs[rs1];
+                        } else {
+                            self.regs[rd] = self
+                                .unsigned(self.regs[rs1])
+                                .wrapping_rem(self.unsigned(self.regs[rs2]));
+                        }
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            0x3b => {
+                // addw and family
+                let shamt = (self.regs[rs2] & 0x1f) as u32;
+                match (funct3, funct7) {
+                    (0x0, 0x0) => {
+                        debug!("ADDW");
+                        self.regs[rd] =
+                            self.regs[rs1].wrapping_add(self.regs[rs2]) as i32 as i64 as u64;
+                    }
+                    (0x0, 0x20) => {
+                        debug!("SUBW");
+                        self.regs[rd] =
+                            self.regs[rs1].wrapping_sub(self.regs[rs2]) as i32 as i64 as u64;
+                    }
+                    (0x1, 0x00) => {
+                        debug!("SLLW");
+                        self.regs[rd] = (self.regs[rs1] as u32).wrapping_shl(shamt) as i32 as u64;
+                    }
+                    (0x5, 0x00) => {
+                        debug!("SRLW");
+                        self.regs[rd] = (self.regs[rs1] as u32).wrapping_shr(shamt) as i32 as u64;
+                    }
+                    (0x5, 0x20) => {
+                        debug!("SRAW");
+                        self.regs[rd] = ((self.regs[rs1] as i32) >> (shamt as i32)) as u64;
+                    }
+                    (0x0, 0x04) => {
+                        debug!("ADD.UW");
+                        self.regs[rd] = (self.regs[rs1] as u32 as u64).wrapping_add(self.regs[rs2]);
+                    }
+                    (0x2 | 0x4 | 0x6, 0x10) => {
+                        debug!("SH1ADD.UW/SH2ADD.UW/SH3ADD.UW");
+                        let shift = funct3 >> 1;
+                        self.regs[rd] =
+                            ((self.regs[rs1] as u32 as u64) << shift).wrapping_add(self.regs[rs2]);
+                    }
+                    (0x4, 0x04) if rs2 == 0 => {
+                        debug!("ZEXT.H");
+                        self.regs[rd] = self.regs[rs1] as u16 as u64;
+                    }
+                    (0x4, 0x04) => {
+                        debug!("PACKW");
+                        let packed = self.regs[rs1] as u16 as u32 | (self.regs[rs2] as u32) << 16;
+                        self.regs[rd] = packed as i32 as i64 as u64;
+                    }
+                    (0x1, 0x30) => {
+                        debug!("ROLW");
+                        self.regs[rd] = (self.regs[rs1] as u32).rotate_left(shamt) as i32 as u64;
+                    }
+                    (0x5, 0x30) => {
+                        debug!("RORW");
+                        self.regs[rd] = (self.regs[rs1] as u32).rotate_right(shamt) as i32 as u64;
+                    }
+                    (0x0, 0x1) => {
+                        debug!("MULW");
+                        self.regs[rd] = (self.regs[rs1] as i32).wrapping_mul(self.regs[rs2] as i32)
+                            as i64 as u64
+                    }
+                    (0x4, 0x1) => {
+                        debug!("DIVW");
+                        if self.regs[rs2] as u32 == 0 {
+                            self.regs[rd] = u64::MAX;
+                        } else {
+                            self.regs[rd] = (self.regs[rs1] as i32)
+                                .wrapping_div(self.regs[rs2] as i32)
+                                as i64 as u64
+                        }
+                    }
+                    (0x5, 0x1) => {
+                        debug!("DIVUW");
+                        if self.regs[rs2] as u32 == 0 {
+                            self.regs[rd] = u64::MAX;
+                        } else {
+                            self.regs[rd] = (self.regs[rs1] as u32)
+                                .wrapping_div(self.regs[rs2] as u32)
+                                as i32 as i64 as u64;
+                        }
+                    }
+                    (0x6, 0x1) => {
+                        debug!("REMW");
+                        if self.regs[rs2] as u32 == 0 {
+                            self.regs[rd] = self.regs[rs1] as i32 as i64 as u64;
+                        } else {
+                            self.regs[rd] = (self.regs[rs1] as i32)
+                                .wrapping_rem(self.regs[rs2] as i32)
+                                as i64 as u64;
+                        }
+                    }
+                    (0x7, 0x1) => {
+                        debug!("REMUW");
+                        if self.regs[rs2] as u32 == 0 {
+                            self.regs[rd] = self.regs[rs1] as i32 as i64 as u64;
+                        } else {
+                            self.regs[rd] = (self.regs[rs1] as u32)
+                                .wrapping_rem(self.regs[rs2] as u32)
+                                as i32 as i64 as u64;
+                        }
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            0x1b => {
+                // addiw and family
+
+                let imm = ((inst as i32 as i64) >> 20) as u64;
+                let shamt = (imm & 0x1f) as u32;
+
+                match (funct3, funct7) {
+                    (0x0, _) => {
+                        tracing::Span::current().record("imm", imm);
+                        debug!("ADDIW");
+                        self.regs[rd] = self.regs[rs1].wrapping_add(imm) as i32 as i64 as u64;
+                    }
+                    (0x1, 0x04 | 0x05) => {
+                        let shamt = (imm & 0x3f) as u32;
+                        tracing::Span::current().record("shamt", shamt);
+                        debug!("SLLI.UW");
+                        self.regs[rd] = (self.regs[rs1] as u32 as u64).wrapping_shl(shamt);
+                    }
+                    (0x1, 0x30) => {
+                        let x = self.regs[rs1] as u32;
+                        self.regs[rd] = match rs2 {
+                            0 => {
+                                debug!("CLZW");
+                                x.leading_zeros() as u64
+                            }
+                            1 => {
+                                debug!("CTZW");
+                                x.trailing_zeros() as u64
+                            }
+                            2 => {
+                                debug!("CPOPW");
+                                x.count_ones() as u64
+                            }
+                            _ => Err(Exception::IllegalInstruction(inst))?,
+                        };
+                    }
+                    (0x5, 0x30) => {
+                        tracing::Span::current().record("shamt", shamt);
+                        debug!("RORIW");
+                        self.regs[rd] = (self.regs[rs1] as u32).rotate_right(shamt) as i32 as u64;
+                    }
+                    (0x1, _) => {
+                        tracing::Span::current().record("shamt", shamt);
+                        debug!("SLLIW");
+                        self.regs[rd] = self.regs[rs1].wrapping_shl(shamt) as i32 as i64 as u64;
+                    }
+                    (0x5, 0) => {
+                        tracing::Span::current().record("shamt", shamt);
+                        debug!("SRLIW");
+                        self.regs[rd] =
+                            (self.regs[rs1] as u32).wrapping_shr(shamt) as i32 as i64 as u64;
+                    }
+                    (0x5, 0x20) => {
+                        tracing::Span::current().record("shamt", shamt);
+                        debug!("SRAIW");
+                        self.regs[rd] = (self.regs[rs1] as i32).wrapping_shr(shamt) as i64 as u64;
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            0x63 => {
+                // branching
+                // imm[12|10:5|4:1|11] = inst[31|30:25|11:8|7]
+                let imm = (((inst & 0x80000000) as i32 as i64 >> 19) as u64)
+                    | ((inst & 0x80) << 4) // imm[11]
+                    | ((inst >> 20) & 0x7e0) // imm[10:5]
+                    | ((inst >> 7) & 0x1e); // imm[4:1]
+                tracing::Span::current().record("imm", imm);
+
+                match funct3 {
+                    0x0 => {
+                        debug!("BEQ");
+
+                        if self.regs[rs1] == self.regs[rs2] {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    0x1 => {
+                        debug!("BNE");
+
+                        if self.regs[rs1] != self.regs[rs2] {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    0x4 => {
+                        debug!("BLT");
+
+                        if (self.regs[rs1] as i64) < (self.regs[rs2] as i64) {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    0x5 => {
+                        debug!("BGE");
+
+                        if (self.regs[rs1] as i64) >= (self.regs[rs2] as i64) {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    0x6 => {
+                        debug!("BLTU");
+
+                        if self.regs[rs1] < self.regs[rs2] {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    0x7 => {
+                        debug!("BGEU");
+
+                        if self.regs[rs1] >= self.regs[rs2] {
+                            self.pc =
+                                self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                        }
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            0x37 => {
+                // LUI
+                let imm32 = (inst & 0xfffff000) as i32 as i64 as u64;
+                tracing::Span::current().record("imm", imm32);
+                debug!("LUI");
+                self.regs[rd] = imm32;
+            }
+            0x17 => {
+                // AUIPC
+                let imm32 = (inst & 0xfffff000) as i32 as i64 as u64;
+                tracing::Span::current().record("imm", imm32);
+                debug!("AUIPC");
+                self.regs[rd] = self.pc.wrapping_sub(len).wrapping_add(imm32);
+            }
+            0x6f => {
+                // JAL
+                // imm[20|10:1|11|19:12] = inst[31|30:21|20|19:12]
+                let imm = (((inst & 0x80000000) as i32 as i64 >> 11) as u64) // imm[20]
+                    | (inst & 0xff000) // imm[19:12]
+                    | ((inst >> 9) & 0x800) // imm[11]
+                    | ((inst >> 20) & 0x7fe); // imm[10:1]
+                tracing::Span::current().record("imm", imm);
+                debug!("JAL");
+                let target = self.jump_target(self.pc.wrapping_add(imm).wrapping_sub(len))?;
+                self.regs[rd] = self.pc;
+                self.pc = target;
+            }
+            0x67 => {
+                // JALR
+                let imm = ((((inst & 0xfff00000) as i32) as i64) >> 20) as u64;
+                tracing::Span::current().record("imm", imm);
+
+                // rs1 is read before rd is written, they may be the same register.
+                let addr = self.jump_target(self.regs[rs1].wrapping_add(imm) & !1)?;
+                self.regs[rd] = self.pc;
+                self.pc = addr;
+                debug!("JALR");
+            }
+            0x73 => {
+                // csr
+                let csr_addr = ((inst & 0xfff00000) >> 20) as usize;
+                tracing::Span::current().record("csr_addr", csr_addr);
+                let imm = rs1 as u64;
+                // csrrs and csrrc only write with a nonzero rs1, or uimm
+                let writes = matches!(funct3, 0x1 | 0x5) || rs1 != 0;
+                if funct3 != 0x0 && !self.csr_accessible(csr_addr, writes) {
+                    Err(Exception::IllegalInstruction(inst))?
+                }
+                match funct3 {
+                    0x0 => match inst {
+                        0x00000073 => {
+                            debug!("ECALL");
+                            Err(match self.mode {
+                                Mode::User => Exception::EnvironmentCallFromUMode,
+                                Mode::Supervisor => Exception::EnvironmentCallFromSMode,
+                                Mode::Machine => Exception::EnvironmentCallFromMMode,
+                            })?
+                        }
+                        0x00100073 => {
+                            debug!("EBREAK");
+                            Err(Exception::Breakpoint(self.pc.wrapping_sub(len)))?
+                        }
+                        0x30200073 => {
+                            debug!("MRET");
+                            if self.mode < Mode::Machine {
+                                Err(Exception::IllegalInstruction(inst))?
+                            }
+                            let target = self.jump_target(self.csrs[MEPC] & self.epc_mask())?;
+                            // MIE = MPIE, MPIE = 1, MPP = U
+                            let mstatus = self.csrs[MSTATUS];
+                            let mode =
+                                Mode::from_bits((mstatus & STATUS_MPP) >> 11).unwrap_or(Mode::User);
+                            let mut mstatus = (mstatus & !(STATUS_MIE | STATUS_MPP))
+                                | (mstatus & STATUS_MPIE) >> 4
+                                | STATUS_MPIE;
+                            if mode < Mode::Machine {
+                                mstatus &= !STATUS_MPRV;
+                            }
+                            self.csrs[MSTATUS] = mstatus;
+                            // MTE = MPTE
+                            let tcontrol = self.csrs[TCONTROL];
+                            self.csrs[TCONTROL] = tcontrol & TCONTROL_MPTE | tcontrol >> 4;
+                            self.mode = mode;
+                            self.pc = target;
+                        }
+                        0x10200073 => {
+                            debug!("SRET");
+                            if self.mode == Mode::User
+                                || (self.mode == Mode::Supervisor
+                                    && self.csrs[MSTATUS] & STATUS_TSR != 0)
+                            {
+                                Err(Exception::IllegalInstruction(inst))?
+                            }
+                            let target = self.jump_target(self.csrs[SEPC] & self.epc_mask())?;
+                            // SIE = SPIE, SPIE = 1, SPP = U
+                            let mstatus = self.csrs[MSTATUS];
+                            let mode = match mstatus & STATUS_SPP != 0 {
+                                true => Mode::Supervisor,
+                                false => Mode::User,
+                            };
+                            self.csrs[MSTATUS] = (mstatus
+                                & !(STATUS_SIE | STATUS_SPP | STATUS_MPRV))
+                                | (mstatus & STATUS_SPIE) >> 4
+                                | STATUS_SPIE;
+                            self.mode = mode;
+                            self.pc = target;
+                        }
+                        0x10500073 | 0x00d00073 if self.wait_trapped() => {
+                            Err(Exception::IllegalInstruction(inst))?
+                        }
+                        0x10500073 => {
+                            // A hint as far as the hart goes, it retires and
+                            // the host decides how long to wait.
+                            debug!("WFI");
+                            self.idle = !self.interrupt_pending();
+                        }
+                        0x00d00073 => {
+                            // Waits like a wfi, as long as the reservation of
+                            // an earlier lr is held.
+                            debug!("WRS.NTO");
+                            self.idle = self.bus.holds_reservation(self.hart_id())
+                                && !self.interrupt_pending();
+                        }
+                        0x01d00073 => {
+                            // The short timeout runs out right away.
+                            debug!("WRS.STO");
+                        }
+                        _ if funct7 == 0x09 && rd == 0 => {
+                            if self.mode == Mode::User
+                                || (self.mode == Mode::Supervisor
+                                    && self.csrs[MSTATUS] & STATUS_TVM != 0)
+                            {
+                                Err(Exception::IllegalInstruction(inst))?
+                            }
+                            debug!("SFENCE.VMA");
+                            let addr = (rs1 != 0).then(|| self.regs[rs1]);
+                            let asid = (rs2 != 0).then(|| self.regs[rs2]);
+                            self.sfence_vma(addr, asid);
+                        }
+                        _ => Err(Exception::IllegalInstruction(inst))?,
+                    },
+                    0x1 => {
+                        // CSRRW
+
+                        // dont read if rd is 0
+                        if rd != 0 {
+                            let csr = self.load_csr(csr_addr);
+                            tracing::Span::current().record("csr", csr);
+
+                            self.store_csr(csr_addr, self.regs[rs1]);
+                            self.regs[rd] = csr;
+                        } else {
+                            self.store_csr(csr_addr, self.regs[rs1]);
+                        }
+                        debug!("CSRRW");
+                    }
+                    0x2 => {
+                        // CSRRS
+
+                        let csr = self.load_csr(csr_addr);
+                        tracing::Span::current().record("csr", csr);
+                        debug!("CSRRS");
+                        let mask = self.regs[rs1];
+                        self.regs[rd] = csr;
+                        if rs1 != 0 {
+                            self.store_csr(csr_addr, csr | mask);
+                        }
+                    }
+                    0x3 => {
+                        // CSRRC
+                        let csr = self.load_csr(csr_addr);
+                        tracing::Span::current().record("csr", csr);
+                        debug!("CSRRC");
+                        let mask = self.regs[rs1];
+                        self.regs[rd] = csr;
+                        if rs1 != 0 {
+                            self.store_csr(csr_addr, csr & !mask);
+                        }
+                    }
+                    0x5 => {
+                        // CSRRWI
+
+                        // dont read if rd is 0
+                        if rd != 0 {
+                            let csr = self.load_csr(csr_addr);
+                            tracing::Span::current().record("csr", csr);
+                            self.store_csr(csr_addr, imm);
+                            self.regs[rd] = csr;
+                        } else {
+                            self.store_csr(csr_addr, imm);
+                        }
+                        debug!("CSRRWI");
+                    }
+                    0x6 => {
+                        // CSRRSI
+
+                        let csr = self.load_csr(csr_addr);
+                        tracing::Span::current().record("csr", csr);
+
+                        self.regs[rd] = csr;
+                        if imm != 0 {
+                            self.store_csr(csr_addr, csr | imm);
+                        }
+                        debug!("CSRRWSI");
+                    }
+                    0x7 => {
+                        // CSRRCI
+
+                        let csr = self.load_csr(csr_addr);
+                        tracing::Span::current().record("csr", csr);
+                        debug!("CSRRCI");
+                        self.regs[rd] = csr;
+                        if imm != 0 {
+                            self.store_csr(csr_addr, csr & !imm);
+                        }
+                    }
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                }
+            }
+            0x2f => {
+                // atomic extension, aq and rl don't matter as a hart's
+                // accesses are performed in order
+                let funct5 = (funct7 >> 2) & 0x1f;
+                let size = match funct3 {
+                    0b010 => 32,
+                    0b011 => 64,
+                    _ => Err(Exception::IllegalInstruction(inst))?,
+                };
+                // words are sign extended into the registers, which also
+                // orders them right for the 64-bit comparisons below
+                let extend = |x: u64| match size {
+                    32 => x as i32 as i64 as u64,
+                    _ => x,
+                };
+                let addr = self.unsigned(self.regs[rs1]);
+                // the triggers come before the alignment and translation
+                // faults, and an amo both loads and stores
+                if funct5 != 0b00011 {
+                    self.check_triggers(addr, size / 8, Access::Load)?;
+                }
+                if funct5 != 0b00010 {
+                    self.check_triggers(addr, size / 8, Access::Store)?;
+                }
+
+                match funct5 {
+                    0b00010 => {
+                        debug!("LR");
+                        /* LR loads a word from the address in rs1, places the
+                        sign-extended value in rd, and registers a reservation set
+                        that subsumes the bytes in the addressed word. */
+                        if !addr.is_multiple_of(size / 8) {
+                            Err(Exception::LoadAddressMisaligned(addr))?;
+                        }
+                        self.regs[rd] = extend(self.load(addr, size)?);
+                        let paddr = self.translate(addr, Access::Load)?;
+                        self.bus.reserve(self.hart_id(), paddr);
+                    }
+                    0b00011 => {
+                        debug!("SC");
+                        /* SC writes rs2 to the address in rs1 only if the
+                        reservation is still valid, writing zero to rd on success
+                        and a nonzero value otherwise. The reservation is dropped
+                        either way. */
+                        if !addr.is_multiple_of(size / 8) {
+                            Err(Exception::StoreAmoAddressMisaligned(addr))?;
+                        }
+                        let paddr = self.translate(addr, Access::Store)?;
+                        if self.bus.take_reservation(self.hart_id(), paddr) {
+                            self.store(addr, size, self.regs[rs2])?;
+                            self.regs[rd] = 0;
+                        } else {
+                            self.regs[rd] = 1;
+                        }
+                    }
+                    _ => {
+                        /* load a data value from the address in rs1, place the value into register rd, apply
+                        a binary operator to the loaded value and the original value in rs2, then store the result back to the
+                        original address in rs1.  */
+                        let op: fn(u64, u64) -> u64 = match funct5 {
+                            0x1 => |src, _| src,
+                            0x0 => |src, data| src.wrapping_add(data),
+                            0x4 => |src, data| src ^ data,
+                            0x0c => |src, data| src & data,
+                            0x8 => |src, data| src | data,
+                            0x10 => |src, data| (src as i64).min(data as i64) as u64,
+                            0x14 => |src, data| (src as i64).max(data as i64) as u64,
+                            0x18 => |src, data| src.min(data),
+                            0x1c => |src, data| src.max(data),
+                            _ => Err(Exception::IllegalInstruction(inst))?,
+                        };
+                        if !addr.is_multiple_of(size / 8) {
+                            Err(Exception::StoreAmoAddressMisaligned(addr))?;
+                        }
+                        debug!("AMO {:#07b}", funct5);
+                        // an amo needs write permission, and faults as a
+                        // store when it can't read
+                        self.translate(addr, Access::Store)?;
+                        let data = self
+                            .load(addr, size)
+                            .map_err(|_| Exception::StoreAmoAccessFault(addr))?;
+                        let data = extend(data);
+                        self.store(addr, size, op(extend(self.regs[rs2]), data))?;
+                        self.regs[rd] = data;
+                    }
+                }
+            }
+            0x07 | 0x27 if matches!(funct3, 0x0 | 0x5 | 0x6 | 0x7) => {
+                self.execute_vector(decoded)?
+            }
+            0x57 => self.execute_vector(decoded)?,
+            0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53 => self.execute_fp(decoded)?,
+            0x0f => match funct3 {
+                // fence w, 0
+                0x0 if inst == 0x0100000f && self.isa.has(Extension::Zihintpause) => {
+                    debug!("PAUSE");
+                    self.hooks.run_pause(&HookContext {
+                        pc: self.pc.wrapping_sub(len),
+                        inst: decoded,
+                        regs: &self.regs,
+                    });
+                }
+                0x0 => {
+                    // accesses are performed in order, there is nothing to wait for
+                    debug!("FENCE");
+                }
+                0x1 => {
+                    debug!("FENCE.I");
+                    self.flush_icache();
+                }
+                0x2 if rd == 0 && funct7 == 0 && matches!(rs2, 0 | 1 | 2 | 4) => {
+                    // cbo.*, inst[24:20] selects the operation, checked like a
+                    // store to the whole block
+                    let addr = self.unsigned(self.regs[rs1]);
+                    if rs2 == 4 {
+                        self.check_triggers(addr & !(CACHE_BLOCK - 1), CACHE_BLOCK, Access::Store)?;
+                    }
+                    // a block never crosses a page
+                    let block = self.translate(addr, Access::Store)? & !(CACHE_BLOCK - 1);
+                    self.pmp_check(addr, block, CACHE_BLOCK, Access::Store)?;
+                    // only dram is cacheable
+                    if self.bus.is_mmio(block) || !self.bus.dram.contains(block) {
+                        Err(Exception::StoreAmoAccessFault(addr))?
+                    }
+                    match rs2 {
+                        // the cache models hold no data, there is nothing to
+                        // write back or discard
+                        0 => debug!("CBO.INVAL"),
+                        1 => debug!("CBO.CLEAN"),
+                        2 => debug!("CBO.FLUSH"),
+                        _ => {
+                            debug!("CBO.ZERO");
+                            for offset in (0..CACHE_BLOCK).step_by(8) {
+                                self.bus.store(block + offset, 64, 0)?;
+                            }
+                            self.access_dcache(block, 64);
+                        }
+                    }
+                }
+                _ => Err(Exception::IllegalInstruction(inst))?,
+            },
+            _ => Err(Exception::IllegalInstruction(inst))?,
+        }
+
+        // page 554
+
+        Ok(())
+    }
+
+    /// Writes the register file, four registers per line, followed by the
+    /// floating point registers if the isa has them.
+    pub fn write_registers(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        let abi = [
+            "zero", " ra ", " sp ", " gp ", " tp ", " t0 ", " t1 ", " t2 ", " s0 ", " s1 ", " a0 ",
+            " a1 ", " a2 ", " a3 ", " a4 ", " a5 ", " a6 ", " a7 ", " s2 ", " s3 ", " s4 ", " s5 ",
+            " s6 ", " s7 ", " s8 ", " s9 ", " s10", " s11", " t3 ", " t4 ", " t5 ", " t6 ",
+        ];
+
+        for (i, r) in self.regs.iter().enumerate() {
+            write!(f, "x{:02} ({}) = {:>#18x} | ", i, abi[i], r)?;
+            if (i + 1) % 4 == 0 {
+                writeln!(f)?;
+            }
+        }
+        writeln!(f)?;
+
+        if self.isa.has(Extension::F) {
+            let abi = [
+                " ft0", " ft1", " ft2", " ft3", " ft4", " ft5", " ft6", " ft7", " fs0", " fs1",
+                " fa0", " fa1", " fa2", " fa3", " fa4", " fa5", " fa6", " fa7", " fs2", " fs3",
+                " fs4", " fs5", " fs6", " fs7", " fs8", " fs9", "fs10", "fs11", " ft8", " ft9",
+                "ft10", "ft11",
+            ];
+            for (i, r) in self.fregs.iter().enumerate() {
+                write!(f, "f{:02} ({}) = {:>#18x} | ", i, abi[i], r)?;
+                if (i + 1) % 4 == 0 {
+                    writeln!(f)?;
+                }
+            }
+            writeln!(f, "fcsr = {:#04x}", self.csrs[FCSR])?;
+        }
+
+        if self.isa.has(Extension::V) {
+            let vlenb = self.vector.vlenb() as usize;
+            for (i, reg) in self.vector.regs.chunks(vlenb).enumerate() {
+                write!(f, "v{i:02} = 0x")?;
+                for byte in reg.iter().rev() {
+                    write!(f, "{byte:02x}")?;
+                }
+                writeln!(f)?;
+            }
+            writeln!(f, "vl = {}, vtype = {:#x}", self.csrs[VL], self.csrs[VTYPE])?;
+        }
+        Ok(())
+    }
+
+    /// Writes every non zero csr, four per line.
+    pub fn write_csr(&self, f: &mut impl fmt::Write) -> fmt::Result {
+        for (i, x) in self
+            .csrs
+            .iter()
+            .enumerate()
+            .filter(|x| x.1 != &0)
+            .enumerate()
+        {
+            write!(f, "{:02} = {:>#18x} | ", x.0, x.1)?;
+            if (i + 1) % 4 == 0 {
+                writeln!(f)?;
+            }
+        }
+        writeln!(f)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn dump_registers(&self) {
+        let mut out = alloc::string::String::new();
+        self.write_registers(&mut out).unwrap();
+        print!("{out}");
+    }
+
+    #[cfg(feature = "std")]
+    pub fn dump_csr(&self) {
+        let mut out = alloc::string::String::new();
+        self.write_csr(&mut out).unwrap();
+        print!("{out}");
+    }
+}
+
+/// Whether the supervisor timer interrupt of a hart with `csrs` is pending
+/// at `now`, when Sstc is enabled.
+pub(crate) fn stimecmp_pending(csrs: &[u64; 4096], now: u64) -> Option<bool> {
+    (csrs[MENVCFG] & MENVCFG_STCE != 0).then(|| now >= csrs[STIMECMP])
+}
+
+#[cfg(feature = "std")]
+fn default_clock() -> Box<dyn Clock> {
+    Box::new(crate::time::WallClock::default())
+}
+
+#[cfg(not(feature = "std"))]
+fn default_clock() -> Box<dyn Clock> {
+    Box::new(crate::time::InstretClock)
+}
@@ -0,0 +1,333 @@
+//! The scalar cryptography extensions: the AES, SHA-2, SM3 and SM4
+//! instructions of Zkn and Zks, and the carry-less multiplication and
+//! crossbar permutations of Zbkc and Zbkx. The `aes32` and split `sha512`
+//! forms are RV32 only, the `aes64` and whole `sha512` ones RV64 only.
+//!
+//! An AES state is two registers, the low and high halves of the 128-bit
+//! state in the byte order of FIPS 197, and a round takes two instructions,
+//! one per half of the result.
+
+/// Multiplication in GF(2^8) modulo the AES polynomial.
+const fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        a = (a << 1) ^ if a & 0x80 != 0 { 0x1b } else { 0 };
+        b >>= 1;
+    }
+    product
+}
+
+/// The AES S-box: the multiplicative inverse, x^254, then the affine map.
+const AES_SBOX: [u8; 256] = {
+    let mut sbox = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut x = 1;
+        let mut n = 0;
+        while n < 254 {
+            x = gmul(x, i as u8);
+            n += 1;
+        }
+        sbox[i] =
+            x ^ x.rotate_left(1) ^ x.rotate_left(2) ^ x.rotate_left(3) ^ x.rotate_left(4) ^ 0x63;
+        i += 1;
+    }
+    sbox
+};
+
+const AES_INV_SBOX: [u8; 256] = {
+    let mut inv = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv[AES_SBOX[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv
+};
+
+const AES_RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+#[rustfmt::skip]
+const SM4_SBOX: [u8; 256] = [
+    0xd6, 0x90, 0xe9, 0xfe, 0xcc, 0xe1, 0x3d, 0xb7, 0x16, 0xb6, 0x14, 0xc2, 0x28, 0xfb, 0x2c, 0x05,
+    0x2b, 0x67, 0x9a, 0x76, 0x2a, 0xbe, 0x04, 0xc3, 0xaa, 0x44, 0x13, 0x26, 0x49, 0x86, 0x06, 0x99,
+    0x9c, 0x42, 0x50, 0xf4, 0x91, 0xef, 0x98, 0x7a, 0x33, 0x54, 0x0b, 0x43, 0xed, 0xcf, 0xac, 0x62,
+    0xe4, 0xb3, 0x1c, 0xa9, 0xc9, 0x08, 0xe8, 0x95, 0x80, 0xdf, 0x94, 0xfa, 0x75, 0x8f, 0x3f, 0xa6,
+    0x47, 0x07, 0xa7, 0xfc, 0xf3, 0x73, 0x17, 0xba, 0x83, 0x59, 0x3c, 0x19, 0xe6, 0x85, 0x4f, 0xa8,
+    0x68, 0x6b, 0x81, 0xb2, 0x71, 0x64, 0xda, 0x8b, 0xf8, 0xeb, 0x0f, 0x4b, 0x70, 0x56, 0x9d, 0x35,
+    0x1e, 0x24, 0x0e, 0x5e, 0x63, 0x58, 0xd1, 0xa2, 0x25, 0x22, 0x7c, 0x3b, 0x01, 0x21, 0x78, 0x87,
+    0xd4, 0x00, 0x46, 0x57, 0x9f, 0xd3, 0x27, 0x52, 0x4c, 0x36, 0x02, 0xe7, 0xa0, 0xc4, 0xc8, 0x9e,
+    0xea, 0xbf, 0x8a, 0xd2, 0x40, 0xc7, 0x38, 0xb5, 0xa3, 0xf7, 0xf2, 0xce, 0xf9, 0x61, 0x15, 0xa1,
+    0xe0, 0xae, 0x5d, 0xa4, 0x9b, 0x34, 0x1a, 0x55, 0xad, 0x93, 0x32, 0x30, 0xf5, 0x8c, 0xb1, 0xe3,
+    0x1d, 0xf6, 0xe2, 0x2e, 0x82, 0x66, 0xca, 0x60, 0xc0, 0x29, 0x23, 0xab, 0x0d, 0x53, 0x4e, 0x6f,
+    0xd5, 0xdb, 0x37, 0x45, 0xde, 0xfd, 0x8e, 0x2f, 0x03, 0xff, 0x6a, 0x72, 0x6d, 0x6c, 0x5b, 0x51,
+    0x8d, 0x1b, 0xaf, 0x92, 0xbb, 0xdd, 0xbc, 0x7f, 0x11, 0xd9, 0x5c, 0x41, 0x1f, 0x10, 0x5a, 0xd8,
+    0x0a, 0xc1, 0x31, 0x88, 0xa5, 0xcd, 0x7b, 0xbd, 0x2d, 0x74, 0xd0, 0x12, 0xb8, 0xe5, 0xb4, 0xb0,
+    0x89, 0x69, 0x97, 0x4a, 0x0c, 0x96, 0x77, 0x7e, 0x65, 0xb9, 0xf1, 0x09, 0xc5, 0x6e, 0xc6, 0x84,
+    0x18, 0xf0, 0x7d, 0xec, 0x3a, 0xdc, 0x4d, 0x20, 0x79, 0xee, 0x5f, 0x3e, 0xd7, 0xcb, 0x39, 0x48,
+];
+
+fn sext32(x: u32) -> u64 {
+    x as i32 as i64 as u64
+}
+
+/// The 64-bit value with the low words of `hi` and `lo` as its halves.
+fn join(hi: u64, lo: u64) -> u64 {
+    (hi as u32 as u64) << 32 | lo as u32 as u64
+}
+
+/// ShiftRows, or InvShiftRows, of the state `hi:lo` followed by SubBytes,
+/// keeping the low half.
+fn aes_round(lo: u64, hi: u64, inverse: bool) -> u64 {
+    let state = (((hi as u128) << 64) | lo as u128).to_le_bytes();
+    let mut out = [0; 8];
+    for (i, byte) in out.iter_mut().enumerate() {
+        let (row, column) = (i % 4, i / 4);
+        *byte = match inverse {
+            false => AES_SBOX[state[row + 4 * ((column + row) % 4)] as usize],
+            true => AES_INV_SBOX[state[row + 4 * ((column + 4 - row) % 4)] as usize],
+        };
+    }
+    u64::from_le_bytes(out)
+}
+
+/// MixColumns, or InvMixColumns, of both columns in `x`.
+fn aes_mix(x: u64, inverse: bool) -> u64 {
+    let coefficients = match inverse {
+        false => [2, 3, 1, 1],
+        true => [14, 11, 13, 9],
+    };
+    let mut out = x.to_le_bytes();
+    for (column, b) in x.to_le_bytes().chunks(4).enumerate() {
+        for row in 0..4 {
+            out[4 * column + row] = (0..4).fold(0, |acc, j| {
+                acc ^ gmul(coefficients[(j + 4 - row) % 4], b[j])
+            });
+        }
+    }
+    u64::from_le_bytes(out)
+}
+
+pub fn aes64es(rs1: u64, rs2: u64) -> u64 {
+    aes_round(rs1, rs2, false)
+}
+
+pub fn aes64esm(rs1: u64, rs2: u64) -> u64 {
+    aes_mix(aes_round(rs1, rs2, false), false)
+}
+
+pub fn aes64ds(rs1: u64, rs2: u64) -> u64 {
+    aes_round(rs1, rs2, true)
+}
+
+pub fn aes64dsm(rs1: u64, rs2: u64) -> u64 {
+    aes_mix(aes_round(rs1, rs2, true), true)
+}
+
+/// InvMixColumns, to turn encryption round keys into decryption ones.
+pub fn aes64im(rs1: u64) -> u64 {
+    aes_mix(rs1, true)
+}
+
+/// The first half of a key schedule step for round `rnum`, `None` past the
+/// last round. Round 10 only substitutes, for AES-256.
+pub fn aes64ks1i(rs1: u64, rnum: u64) -> Option<u64> {
+    let word = (rs1 >> 32) as u32;
+    let (word, rcon) = match rnum {
+        0..=9 => (word.rotate_right(8), AES_RCON[rnum as usize]),
+        0xa => (word, 0),
+        _ => return None,
+    };
+    let word = u32::from_le_bytes(word.to_le_bytes().map(|b| AES_SBOX[b as usize])) ^ rcon as u32;
+    Some(((word as u64) << 32) | word as u64)
+}
+
+pub fn aes64ks2(rs1: u64, rs2: u64) -> u64 {
+    let w0 = (rs1 >> 32) as u32 ^ rs2 as u32;
+    let w1 = w0 ^ (rs2 >> 32) as u32;
+    ((w1 as u64) << 32) | w0 as u64
+}
+
+/// Byte `bs` of `rs2` through the S-box, then MixColumns if `mix`, as the
+/// column it turns into with the byte in row `bs`, xored into `rs1`. A
+/// round is the four bytes of each column of the state.
+fn aes32(rs1: u64, rs2: u64, bs: u32, inverse: bool, mix: bool) -> u64 {
+    let shamt = bs * 8;
+    let byte = (rs2 >> shamt) as u8 as usize;
+    let x = match inverse {
+        false => AES_SBOX[byte],
+        true => AES_INV_SBOX[byte],
+    };
+    let column = match (mix, inverse) {
+        (false, _) => [x, 0, 0, 0],
+        (true, false) => [gmul(x, 2), x, x, gmul(x, 3)],
+        (true, true) => [gmul(x, 14), gmul(x, 9), gmul(x, 13), gmul(x, 11)],
+    };
+    sext32(u32::from_le_bytes(column).rotate_left(shamt) ^ rs1 as u32)
+}
+
+pub fn aes32esi(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    aes32(rs1, rs2, bs, false, false)
+}
+
+pub fn aes32esmi(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    aes32(rs1, rs2, bs, false, true)
+}
+
+pub fn aes32dsi(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    aes32(rs1, rs2, bs, true, false)
+}
+
+pub fn aes32dsmi(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    aes32(rs1, rs2, bs, true, true)
+}
+
+pub fn sha256sig0(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x.rotate_right(7) ^ x.rotate_right(18) ^ (x >> 3))
+}
+
+pub fn sha256sig1(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x.rotate_right(17) ^ x.rotate_right(19) ^ (x >> 10))
+}
+
+pub fn sha256sum0(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x.rotate_right(2) ^ x.rotate_right(13) ^ x.rotate_right(22))
+}
+
+pub fn sha256sum1(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x.rotate_right(6) ^ x.rotate_right(11) ^ x.rotate_right(25))
+}
+
+pub fn sha512sig0(x: u64) -> u64 {
+    x.rotate_right(1) ^ x.rotate_right(8) ^ (x >> 7)
+}
+
+pub fn sha512sig1(x: u64) -> u64 {
+    x.rotate_right(19) ^ x.rotate_right(61) ^ (x >> 6)
+}
+
+pub fn sha512sum0(x: u64) -> u64 {
+    x.rotate_right(28) ^ x.rotate_right(34) ^ x.rotate_right(39)
+}
+
+pub fn sha512sum1(x: u64) -> u64 {
+    x.rotate_right(14) ^ x.rotate_right(18) ^ x.rotate_right(41)
+}
+
+/// The low half of [`sha512sum0`] of `rs2:rs1`, or its high half with the
+/// operands the other way around.
+pub fn sha512sum0r(rs1: u64, rs2: u64) -> u64 {
+    sext32(sha512sum0(join(rs2, rs1)) as u32)
+}
+
+/// Like [`sha512sum0r`], for [`sha512sum1`].
+pub fn sha512sum1r(rs1: u64, rs2: u64) -> u64 {
+    sext32(sha512sum1(join(rs2, rs1)) as u32)
+}
+
+/// The low half of [`sha512sig0`] of `rs2:rs1`.
+pub fn sha512sig0l(rs1: u64, rs2: u64) -> u64 {
+    sext32(sha512sig0(join(rs2, rs1)) as u32)
+}
+
+/// The high half of [`sha512sig0`] of `rs1:rs2`.
+pub fn sha512sig0h(rs1: u64, rs2: u64) -> u64 {
+    sext32((sha512sig0(join(rs1, rs2)) >> 32) as u32)
+}
+
+/// The low half of [`sha512sig1`] of `rs2:rs1`.
+pub fn sha512sig1l(rs1: u64, rs2: u64) -> u64 {
+    sext32(sha512sig1(join(rs2, rs1)) as u32)
+}
+
+/// The high half of [`sha512sig1`] of `rs1:rs2`.
+pub fn sha512sig1h(rs1: u64, rs2: u64) -> u64 {
+    sext32((sha512sig1(join(rs1, rs2)) >> 32) as u32)
+}
+
+pub fn sm3p0(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x ^ x.rotate_left(9) ^ x.rotate_left(17))
+}
+
+pub fn sm3p1(rs1: u64) -> u64 {
+    let x = rs1 as u32;
+    sext32(x ^ x.rotate_left(15) ^ x.rotate_left(23))
+}
+
+/// Byte `bs` of `rs2` through the S-box and `linear`, rotated back into
+/// place and xored into `rs1`. Words hold the bytes of SM4's big endian
+/// words in memory order, as a little endian load leaves them.
+fn sm4(rs1: u64, rs2: u64, bs: u32, linear: fn(u32) -> u32) -> u64 {
+    let shamt = bs * 8;
+    let x = SM4_SBOX[(rs2 >> shamt) as u8 as usize] as u32;
+    sext32(linear(x).rotate_left(shamt) ^ rs1 as u32)
+}
+
+/// A round of encryption, with the linear transform L.
+pub fn sm4ed(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    sm4(rs1, rs2, bs, |x| {
+        x ^ (x << 8) ^ (x << 2) ^ (x << 18) ^ ((x & 0x3f) << 26) ^ ((x & 0xc0) << 10)
+    })
+}
+
+/// A step of the key schedule, with the linear transform L'.
+pub fn sm4ks(rs1: u64, rs2: u64, bs: u32) -> u64 {
+    sm4(rs1, rs2, bs, |x| {
+        x ^ ((x & 0x07) << 29) ^ ((x & 0xfe) << 7) ^ ((x & 0x01) << 23) ^ ((x & 0xf8) << 13)
+    })
+}
+
+/// The low half of the carry-less product.
+pub fn clmul(a: u64, b: u64) -> u64 {
+    (0..64)
+        .filter(|i| (b >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ (a << i))
+}
+
+/// The high half of the carry-less product.
+pub fn clmulh(a: u64, b: u64) -> u64 {
+    (1..64)
+        .filter(|i| (b >> i) & 1 != 0)
+        .fold(0, |acc, i| acc ^ (a >> (64 - i)))
+}
+
+/// Interleaves the halves of the low word of `rs1`, the low half in the
+/// even bits.
+pub fn zip(rs1: u64) -> u64 {
+    (0..16).fold(0, |acc, i| {
+        acc | ((rs1 >> i) & 1) << (2 * i) | ((rs1 >> (i + 16)) & 1) << (2 * i + 1)
+    })
+}
+
+/// The inverse of [`zip`].
+pub fn unzip(rs1: u64) -> u64 {
+    (0..16).fold(0, |acc, i| {
+        acc | ((rs1 >> (2 * i)) & 1) << i | ((rs1 >> (2 * i + 1)) & 1) << (i + 16)
+    })
+}
+
+/// Byte `i` of the result is the byte of `rs1` indexed by byte `i` of
+/// `rs2`, zero if that is out of range.
+pub fn xperm8(rs1: u64, rs2: u64) -> u64 {
+    let table = rs1.to_le_bytes();
+    u64::from_le_bytes(
+        rs2.to_le_bytes()
+            .map(|i| *table.get(i as usize).unwrap_or(&0)),
+    )
+}
+
+/// Like [`xperm8`], with nibbles.
+pub fn xperm4(rs1: u64, rs2: u64) -> u64 {
+    (0..16).fold(0, |acc, i| {
+        let index = (rs2 >> (4 * i)) & 0xf;
+        acc | ((rs1 >> (4 * index)) & 0xf) << (4 * i)
+    })
+}
@@ -0,0 +1,339 @@
+//! What reading and writing each of the 4096 csr addresses does, as a table
+//! of [`Csr`] descriptors. Most csrs are storage in [`Cpu::csrs`], with a
+//! write mask keeping their read-only and WARL fields at fixed values. Views
+//! of other csrs, and csrs whose writes have side effects, have callbacks
+//! instead.
+//!
+//! Whether the running mode may access a csr at all is up to the cpu, which
+//! looks at the privilege level its address encodes and at the enables.
+
+use crate::{
+    cpu::{
+        Cpu, FCSR, FFLAGS, FRM, INSTRET, INSTRETH, MCAUSE, MCOUNTEREN, MCOUNTINHIBIT, MCYCLE,
+        MCYCLEH, MEDELEG, MENVCFG, MENVCFGH, MENVCFG_STCE, MEPC, MIDELEG, MIE, MINSTRET, MINSTRETH,
+        MIP, MISA, MSTATUS, MTVEC, RDCYCLE, RDCYCLEH, RDTIME, RDTIMEH, SATP, SCAUSE, SCOUNTEREN,
+        SEPC, SIE, SIP, SSTATUS, STATUS_FS, STIMECMP, STIMECMPH, STVEC, VCSR, VL, VLENB, VTYPE,
+        VXRM, VXSAT,
+    },
+    hpm::{HPMCOUNTER3, HPMCOUNTER3H, HPM_COUNTERS, MHPMCOUNTER3, MHPMCOUNTER3H, MHPMEVENT3},
+    isa::{Extension, Xlen},
+    pmp::{PMPADDR0, PMPCFG0, PMPCFG_CSRS, PMP_ENTRIES},
+    trigger::{TCONTROL, TSELECT},
+};
+
+/// Reads csr `addr` in place of its storage.
+pub type Read = fn(&Cpu, usize) -> u64;
+/// Writes `value` to csr `addr` in place of its storage.
+pub type Write = fn(&mut Cpu, usize, u64);
+
+/// A csr descriptor.
+#[derive(Debug, Clone, Copy)]
+pub struct Csr {
+    /// The bits of the storage a read returns, the others read as 0.
+    pub read_mask: u64,
+    /// The bits of the storage a write changes, the others keep their value.
+    pub write_mask: u64,
+    /// Value of the storage when the cpu is created.
+    pub reset: u64,
+    /// Replaces the masked read of the storage.
+    pub read: Option<Read>,
+    /// Replaces the masked write of the storage.
+    pub write: Option<Write>,
+}
+
+impl Csr {
+    /// Storage any value can be written to.
+    pub const PLAIN: Csr = Csr {
+        read_mask: u64::MAX,
+        write_mask: u64::MAX,
+        reset: 0,
+        read: None,
+        write: None,
+    };
+
+    /// Storage only the bits of `write_mask` can be written to.
+    const fn masked(write_mask: u64) -> Csr {
+        Csr {
+            write_mask,
+            ..Csr::PLAIN
+        }
+    }
+
+    /// Read through `read`, and written through `write` if any, read-only
+    /// otherwise.
+    const fn view(read: Read, write: Option<Write>) -> Csr {
+        Csr {
+            read: Some(read),
+            write,
+            write_mask: 0,
+            ..Csr::PLAIN
+        }
+    }
+
+    /// Storage written through `write`.
+    const fn written(write: Write) -> Csr {
+        Csr {
+            write: Some(write),
+            ..Csr::PLAIN
+        }
+    }
+}
+
+/// The fields [`SSTATUS`] shows: SIE, SPIE, UBE, SPP, VS, FS, XS, SUM, MXR,
+/// UXL and SD.
+const SSTATUS_FIELDS: u64 = 0x8000_0003_000d_e762;
+/// The fields of [`MSTATUS`] a write changes: SIE, MIE, SPIE, MPIE, SPP, VS,
+/// MPP, FS, MPRV, SUM, MXR, TVM, TW and TSR. The hart is little endian only,
+/// has no custom extension state and has fixed xlens, so UBE, MBE, SBE, XS,
+/// UXL and SXL keep their values. SD follows FS and VS.
+const MSTATUS_WRITABLE: u64 = 0x007e_7faa;
+/// MPP in [`MSTATUS`], where 2 is reserved.
+const MSTATUS_MPP: u64 = 0b11 << 11;
+/// The vector state in [`MSTATUS`], dirty when all ones like FS.
+const STATUS_VS: u64 = 0b11 << 9;
+/// The interrupts [`MIE`] can enable.
+const MIE_WRITABLE: u64 = 0xaaa;
+/// The interrupts software can make pending in [`MIP`]: the supervisor ones.
+/// The machine ones follow the interrupt controllers only.
+const MIP_WRITABLE: u64 = 0x222;
+/// The interrupts [`SIP`] can make pending, only the supervisor software one.
+const SIP_WRITABLE: u64 = 1 << 1;
+/// The exceptions [`MEDELEG`] can delegate: those the hart raises, but the
+/// environment call from machine mode, which is never delegated.
+const MEDELEG_WRITABLE: u64 = 0xb3ff;
+/// The interrupts [`MIDELEG`] can delegate, the supervisor ones.
+const MIDELEG_WRITABLE: u64 = 0x222;
+/// The supervisor timer interrupt in [`MIP`].
+const MIP_STIP: u64 = 1 << 5;
+/// The exception code bits of [`MCAUSE`] and `scause`, the interrupt bit is
+/// the top one.
+const MCAUSE_CODE: u64 = 0x3f;
+
+/// The descriptors, indexed by address.
+pub static CSRS: [Csr; 4096] = table();
+
+const fn table() -> [Csr; 4096] {
+    let mut csrs = [Csr::PLAIN; 4096];
+
+    csrs[MISA] = Csr::view(|cpu, _| cpu.isa.misa(), Some(write_misa));
+    csrs[SSTATUS] = Csr::view(
+        |cpu, _| cpu.csrs[MSTATUS] & SSTATUS_FIELDS,
+        Some(|cpu, _, value| {
+            let writable = SSTATUS_FIELDS & MSTATUS_WRITABLE;
+            set_status(cpu, (cpu.csrs[MSTATUS] & !writable) | (value & writable));
+        }),
+    );
+    // the floating point state starts out initial, so it is on, and UXL =
+    // SXL = 64 bits
+    csrs[MSTATUS] = Csr {
+        reset: 2 << 34 | 2 << 32 | 1 << 13,
+        ..Csr::written(write_mstatus)
+    };
+    csrs[MIE] = Csr::masked(MIE_WRITABLE);
+    // direct and vectored only
+    csrs[MTVEC] = Csr::masked(!0b10);
+    csrs[MEPC] = Csr::masked(!1);
+    csrs[MCAUSE] = Csr::written(write_cause);
+    csrs[STVEC] = Csr::masked(!0b10);
+    csrs[SEPC] = Csr::masked(!1);
+    csrs[SCAUSE] = Csr::written(write_cause);
+    csrs[SATP] = Csr::written(|cpu, _, value| cpu.store_satp(value));
+    csrs[MEDELEG] = Csr::masked(MEDELEG_WRITABLE);
+    csrs[MIDELEG] = Csr::masked(MIDELEG_WRITABLE);
+    csrs[SIE] = Csr::view(
+        |cpu, _| cpu.csrs[MIE] & cpu.csrs[MIDELEG],
+        Some(|cpu, _, value| {
+            let delegated = cpu.csrs[MIDELEG];
+            cpu.csrs[MIE] = (cpu.csrs[MIE] & !delegated) | (value & delegated);
+        }),
+    );
+    csrs[SIP] = Csr::view(
+        |cpu, _| cpu.csrs[MIP] & cpu.csrs[MIDELEG],
+        Some(|cpu, _, value| {
+            let writable = SIP_WRITABLE & cpu.csrs[MIDELEG];
+            cpu.csrs[MIP] = (cpu.csrs[MIP] & !writable) | (value & writable);
+        }),
+    );
+    // With Sstc the supervisor timer interrupt follows `stimecmp` instead.
+    csrs[MIP] = Csr::written(|cpu, _, value| {
+        let fixed = match cpu.csrs[MENVCFG] & MENVCFG_STCE {
+            0 => 0,
+            _ => MIP_STIP,
+        };
+        let writable = MIP_WRITABLE & !fixed;
+        cpu.csrs[MIP] = (cpu.csrs[MIP] & !writable) | (value & writable);
+    });
+    csrs[MENVCFG] = Csr::written(|cpu, _, value| {
+        cpu.csrs[MENVCFG] = match cpu.isa.xlen {
+            Xlen::Rv32 => cpu.csrs[MENVCFG] & MENVCFG_STCE,
+            Xlen::Rv64 => value & MENVCFG_STCE,
+        };
+        cpu.poll_stimecmp();
+    });
+    csrs[MENVCFGH] = Csr::view(
+        |cpu, _| cpu.csrs[MENVCFG] >> 32,
+        Some(|cpu, _, value| {
+            cpu.csrs[MENVCFG] = (value << 32) & MENVCFG_STCE;
+            cpu.poll_stimecmp();
+        }),
+    );
+    csrs[STIMECMP] = Csr::written(|cpu, addr, value| {
+        write_low(cpu, addr, value);
+        cpu.poll_stimecmp();
+    });
+    csrs[STIMECMPH] = Csr::view(
+        |cpu, _| cpu.csrs[STIMECMP] >> 32,
+        Some(|cpu, _, value| {
+            write_high(cpu, STIMECMP, value);
+            cpu.poll_stimecmp();
+        }),
+    );
+
+    csrs[FFLAGS] = Csr::view(
+        |cpu, _| cpu.csrs[FCSR] & 0x1f,
+        Some(|cpu, _, value| write_fcsr(cpu, (cpu.csrs[FCSR] & !0x1f) | (value & 0x1f))),
+    );
+    csrs[FRM] = Csr::view(
+        |cpu, _| (cpu.csrs[FCSR] >> 5) & 0x7,
+        Some(|cpu, _, value| write_fcsr(cpu, (cpu.csrs[FCSR] & !0xe0) | ((value & 0x7) << 5))),
+    );
+    csrs[FCSR] = Csr::written(|cpu, _, value| write_fcsr(cpu, value & 0xff));
+    csrs[VXSAT] = Csr::view(
+        |cpu, _| cpu.csrs[VCSR] & 0x1,
+        Some(|cpu, _, value| cpu.csrs[VCSR] = (cpu.csrs[VCSR] & !0x1) | (value & 0x1)),
+    );
+    csrs[VXRM] = Csr::view(
+        |cpu, _| (cpu.csrs[VCSR] >> 1) & 0x3,
+        Some(|cpu, _, value| cpu.csrs[VCSR] = (cpu.csrs[VCSR] & !0x6) | ((value & 0x3) << 1)),
+    );
+    csrs[VCSR] = Csr::masked(0x7);
+    // set by vsetvl and friends only
+    csrs[VL] = Csr::masked(0);
+    csrs[VTYPE] = Csr::masked(0);
+    csrs[VLENB] = Csr::view(|cpu, _| cpu.vector.vlenb(), None);
+
+    csrs[MCOUNTEREN] = Csr::masked(0xffff_ffff);
+    csrs[SCOUNTEREN] = Csr::masked(0xffff_ffff);
+    // `time` can't be inhibited
+    csrs[MCOUNTINHIBIT] = Csr::masked(0xffff_fffd);
+    csrs[MCYCLE] = Csr::view(
+        |cpu, _| cpu.csrs[RDCYCLE],
+        Some(|cpu, _, x| write_low(cpu, RDCYCLE, x)),
+    );
+    csrs[MINSTRET] = Csr::view(
+        |cpu, _| cpu.csrs[INSTRET],
+        Some(|cpu, _, x| write_low(cpu, INSTRET, x)),
+    );
+    csrs[MCYCLEH] = Csr::view(
+        |cpu, _| cpu.csrs[RDCYCLE] >> 32,
+        Some(|cpu, _, x| write_high(cpu, RDCYCLE, x)),
+    );
+    csrs[MINSTRETH] = Csr::view(
+        |cpu, _| cpu.csrs[INSTRET] >> 32,
+        Some(|cpu, _, x| write_high(cpu, INSTRET, x)),
+    );
+    csrs[RDCYCLEH] = Csr::view(|cpu, _| cpu.csrs[RDCYCLE] >> 32, None);
+    csrs[RDTIMEH] = Csr::view(|cpu, _| cpu.csrs[RDTIME] >> 32, None);
+    csrs[INSTRETH] = Csr::view(|cpu, _| cpu.csrs[INSTRET] >> 32, None);
+
+    let mut i = 0;
+    while i < HPM_COUNTERS {
+        csrs[MHPMCOUNTER3 + i] = Csr::written(write_low);
+        csrs[MHPMCOUNTER3H + i] = Csr::view(
+            |cpu, addr| cpu.csrs[addr - MHPMCOUNTER3H + MHPMCOUNTER3] >> 32,
+            Some(|cpu, addr, value| write_high(cpu, addr - MHPMCOUNTER3H + MHPMCOUNTER3, value)),
+        );
+        csrs[HPMCOUNTER3 + i] = Csr::view(
+            |cpu, addr| cpu.csrs[addr - HPMCOUNTER3 + MHPMCOUNTER3],
+            None,
+        );
+        csrs[HPMCOUNTER3H + i] = Csr::view(
+            |cpu, addr| cpu.csrs[addr - HPMCOUNTER3H + MHPMCOUNTER3] >> 32,
+            None,
+        );
+        csrs[MHPMEVENT3 + i] = Csr::written(|cpu, addr, value| {
+            cpu.csrs[addr] = value;
+            cpu.hpm.select(addr - MHPMEVENT3, value);
+        });
+        i += 1;
+    }
+
+    let mut i = 0;
+    while i < PMPCFG_CSRS {
+        csrs[PMPCFG0 + i] = Csr::written(Cpu::store_pmpcfg);
+        i += 1;
+    }
+    let mut i = 0;
+    while i < PMP_ENTRIES {
+        csrs[PMPADDR0 + i] = Csr::written(Cpu::store_pmpaddr);
+        i += 1;
+    }
+
+    let mut addr = TSELECT;
+    while addr <= TCONTROL {
+        csrs[addr] = Csr::view(Cpu::load_trigger, Some(Cpu::store_trigger));
+        addr += 1;
+    }
+
+    csrs
+}
+
+/// A reserved MPP keeps the mode it held.
+fn write_mstatus(cpu: &mut Cpu, _addr: usize, value: u64) {
+    let mut writable = MSTATUS_WRITABLE;
+    if value & MSTATUS_MPP == 2 << 11 {
+        writable &= !MSTATUS_MPP;
+    }
+    set_status(cpu, (cpu.csrs[MSTATUS] & !writable) | (value & writable));
+}
+
+/// Sets [`MSTATUS`] to `mstatus`, with SD set when FS or VS is dirty.
+fn set_status(cpu: &mut Cpu, mstatus: u64) {
+    let sd = 1 << (cpu.isa.xlen.bits() - 1);
+    let dirty = mstatus & STATUS_FS == STATUS_FS || mstatus & STATUS_VS == STATUS_VS;
+    cpu.csrs[MSTATUS] = match dirty {
+        true => mstatus | sd,
+        false => mstatus & !sd,
+    };
+}
+
+/// `mcause` and `scause` keep the interrupt bit and the exception code.
+fn write_cause(cpu: &mut Cpu, addr: usize, value: u64) {
+    let interrupt = match cpu.isa.xlen {
+        Xlen::Rv32 => 1 << 31,
+        Xlen::Rv64 => 1 << 63,
+    };
+    cpu.csrs[addr] = value & (interrupt | MCAUSE_CODE);
+}
+
+/// A write changing `fcsr` dirties the floating point state.
+fn write_fcsr(cpu: &mut Cpu, value: u64) {
+    cpu.csrs[FCSR] = value;
+    cpu.dirty_fp();
+}
+
+/// Turning C off is dropped when the next instruction isn't aligned on 4
+/// bytes.
+fn write_misa(cpu: &mut Cpu, _addr: usize, value: u64) {
+    if let Some(isa) = cpu
+        .isa
+        .write_misa(value)
+        .filter(|x| x.has(Extension::C) || cpu.pc.is_multiple_of(4))
+    {
+        cpu.isa = isa;
+    }
+}
+
+/// Writes all of the 64-bit csr `addr`, only its low half on RV32, where the
+/// high half is a csr of its own.
+fn write_low(cpu: &mut Cpu, addr: usize, value: u64) {
+    cpu.csrs[addr] = match cpu.isa.xlen {
+        Xlen::Rv32 => (cpu.csrs[addr] & !0xffff_ffff) | (value & 0xffff_ffff),
+        Xlen::Rv64 => value,
+    };
+}
+
+/// Writes the high half of the 64-bit csr `addr`, for RV32.
+fn write_high(cpu: &mut Cpu, addr: usize, value: u64) {
+    cpu.csrs[addr] = (cpu.csrs[addr] & 0xffff_ffff) | (value << 32);
+}
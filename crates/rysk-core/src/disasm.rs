@@ -0,0 +1,366 @@
+use alloc::{format, string::String};
+
+use crate::{
+    compressed::{expand, is_compressed},
+    instruction::Instruction,
+};
+
+/// ABI names of the integer registers.
+pub const ABI: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+/// Name of the csr at `addr`, for those with one.
+pub fn csr_name(addr: usize) -> Option<&'static str> {
+    let name = match addr {
+        0x001 => "fflags",
+        0x002 => "frm",
+        0x003 => "fcsr",
+        0x008 => "vstart",
+        0x009 => "vxsat",
+        0x00a => "vxrm",
+        0x00f => "vcsr",
+        0x100 => "sstatus",
+        0x104 => "sie",
+        0x105 => "stvec",
+        0x106 => "scounteren",
+        0x140 => "sscratch",
+        0x141 => "sepc",
+        0x142 => "scause",
+        0x143 => "stval",
+        0x144 => "sip",
+        0x180 => "satp",
+        0x300 => "mstatus",
+        0x301 => "misa",
+        0x302 => "medeleg",
+        0x303 => "mideleg",
+        0x304 => "mie",
+        0x305 => "mtvec",
+        0x306 => "mcounteren",
+        0x320 => "mcountinhibit",
+        0x340 => "mscratch",
+        0x341 => "mepc",
+        0x342 => "mcause",
+        0x343 => "mtval",
+        0x344 => "mip",
+        0xb00 => "mcycle",
+        0xb02 => "minstret",
+        0xc00 => "cycle",
+        0xc01 => "time",
+        0xc02 => "instret",
+        0xc20 => "vl",
+        0xc21 => "vtype",
+        0xc22 => "vlenb",
+        0xf11 => "mvendorid",
+        0xf12 => "marchid",
+        0xf13 => "mimpid",
+        0xf14 => "mhartid",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn i_imm(inst: u64) -> i64 {
+    (inst as i32 as i64) >> 20
+}
+
+fn s_imm(inst: u64) -> i64 {
+    (((inst & 0xfe000000) as i32 as i64) >> 20) | ((inst >> 7) & 0x1f) as i64
+}
+
+fn b_imm(inst: u64) -> i64 {
+    (((inst & 0x80000000) as i32 as i64) >> 19)
+        | ((inst & 0x80) << 4) as i64
+        | ((inst >> 20) & 0x7e0) as i64
+        | ((inst >> 7) & 0x1e) as i64
+}
+
+fn j_imm(inst: u64) -> i64 {
+    (((inst & 0x80000000) as i32 as i64) >> 11)
+        | (inst & 0xff000) as i64
+        | ((inst >> 9) & 0x800) as i64
+        | ((inst >> 20) & 0x7fe) as i64
+}
+
+/// Disassembles the instruction at `pc`, returning `None` for encodings the
+/// emulator doesn't know. Compressed instructions show as their expansion.
+pub fn disassemble(inst: u64, pc: u64) -> Option<String> {
+    let inst = match is_compressed(inst) {
+        true => expand(inst as u16)? as u64,
+        false => inst,
+    };
+    let Instruction {
+        raw,
+        opcode,
+        rd,
+        rs1,
+        rs2,
+        funct3,
+        funct7,
+        ..
+    } = Instruction::decode(inst);
+    let (rd, rs1, rs2) = (ABI[rd], ABI[rs1], ABI[rs2]);
+
+    let text = match opcode {
+        0x03 => {
+            let name = ["lb", "lh", "lw", "ld", "lbu", "lhu", "lwu"].get(funct3 as usize)?;
+            format!("{name} {rd}, {}({rs1})", i_imm(raw))
+        }
+        0x23 => {
+            let name = ["sb", "sh", "sw", "sd"].get(funct3 as usize)?;
+            format!("{name} {rs2}, {}({rs1})", s_imm(raw))
+        }
+        0x13 => {
+            let imm = i_imm(raw);
+            let shamt = imm & 0x3f;
+            match (funct3, funct7 >> 1) {
+                (0x0, _) => format!("addi {rd}, {rs1}, {imm}"),
+                (0x2, _) => format!("slti {rd}, {rs1}, {imm}"),
+                (0x3, _) => format!("sltiu {rd}, {rs1}, {imm}"),
+                (0x4, _) => format!("xori {rd}, {rs1}, {imm}"),
+                (0x6, _) => format!("ori {rd}, {rs1}, {imm}"),
+                (0x7, _) => format!("andi {rd}, {rs1}, {imm}"),
+                (0x1, 0x00) => format!("slli {rd}, {rs1}, {shamt}"),
+                (0x5, 0x00) => format!("srli {rd}, {rs1}, {shamt}"),
+                (0x5, 0x10) => format!("srai {rd}, {rs1}, {shamt}"),
+                (0x1, 0x18) if funct7 == 0x30 => {
+                    let name = ["clz", "ctz", "cpop", "", "sext.b", "sext.h"]
+                        .get(shamt as usize)
+                        .filter(|name| !name.is_empty())?;
+                    format!("{name} {rd}, {rs1}")
+                }
+                (0x5, 0x18) => format!("rori {rd}, {rs1}, {shamt}"),
+                (0x5, 0x0a) if imm & 0xfff == 0x287 => format!("orc.b {rd}, {rs1}"),
+                // rev8 on RV64 and RV32
+                (0x5, 0x1a) if imm & 0xfff == 0x6b8 || imm & 0xfff == 0x698 => {
+                    format!("rev8 {rd}, {rs1}")
+                }
+                (0x1, 0x02) if imm & 0xfff == 0x08f => format!("zip {rd}, {rs1}"),
+                (0x5, 0x02) if imm & 0xfff == 0x08f => format!("unzip {rd}, {rs1}"),
+                (0x5, 0x1a) if imm & 0xfff == 0x687 => format!("brev8 {rd}, {rs1}"),
+                (0x1, 0x0c) if imm & 0xfff == 0x300 => format!("aes64im {rd}, {rs1}"),
+                (0x1, 0x0c) if (imm >> 4) & 0xff == 0x31 => {
+                    format!("aes64ks1i {rd}, {rs1}, {}", imm & 0xf)
+                }
+                (0x1, 0x04) if funct7 == 0x08 => {
+                    let name = [
+                        "sha256sum0",
+                        "sha256sum1",
+                        "sha256sig0",
+                        "sha256sig1",
+                        "sha512sum0",
+                        "sha512sum1",
+                        "sha512sig0",
+                        "sha512sig1",
+                        "sm3p0",
+                        "sm3p1",
+                    ]
+                    .get(shamt as usize)?;
+                    format!("{name} {rd}, {rs1}")
+                }
+                (0x1, 0x0a) => format!("bseti {rd}, {rs1}, {shamt}"),
+                (0x1, 0x12) => format!("bclri {rd}, {rs1}, {shamt}"),
+                (0x1, 0x1a) => format!("binvi {rd}, {rs1}, {shamt}"),
+                (0x5, 0x12) => format!("bexti {rd}, {rs1}, {shamt}"),
+                _ => return None,
+            }
+        }
+        0x1b => {
+            let imm = i_imm(raw);
+            let shamt = imm & 0x1f;
+            match (funct3, funct7) {
+                (0x0, _) => format!("addiw {rd}, {rs1}, {imm}"),
+                (0x1, 0x00) => format!("slliw {rd}, {rs1}, {shamt}"),
+                (0x5, 0x00) => format!("srliw {rd}, {rs1}, {shamt}"),
+                (0x5, 0x20) => format!("sraiw {rd}, {rs1}, {shamt}"),
+                (0x1, 0x04 | 0x05) => format!("slli.uw {rd}, {rs1}, {}", imm & 0x3f),
+                (0x1, 0x30) => {
+                    let name = ["clzw", "ctzw", "cpopw"].get(shamt as usize)?;
+                    format!("{name} {rd}, {rs1}")
+                }
+                (0x5, 0x30) => format!("roriw {rd}, {rs1}, {shamt}"),
+                _ => return None,
+            }
+        }
+        0x33 => {
+            let name = match (funct3, funct7) {
+                (0x0, 0x00) => "add",
+                (0x0, 0x20) => "sub",
+                (0x1, 0x00) => "sll",
+                (0x2, 0x00) => "slt",
+                (0x3, 0x00) => "sltu",
+                (0x4, 0x00) => "xor",
+                (0x5, 0x00) => "srl",
+                (0x5, 0x20) => "sra",
+                (0x6, 0x00) => "or",
+                (0x7, 0x00) => "and",
+                (0x0, 0x01) => "mul",
+                (0x1, 0x01) => "mulh",
+                (0x2, 0x01) => "mulhsu",
+                (0x3, 0x01) => "mulhu",
+                (0x4, 0x01) => "div",
+                (0x5, 0x01) => "divu",
+                (0x6, 0x01) => "rem",
+                (0x7, 0x01) => "remu",
+                (0x5, 0x07) => "czero.eqz",
+                (0x7, 0x07) => "czero.nez",
+                (0x2, 0x10) => "sh1add",
+                (0x4, 0x10) => "sh2add",
+                (0x6, 0x10) => "sh3add",
+                (0x4, 0x20) => "xnor",
+                (0x6, 0x20) => "orn",
+                (0x7, 0x20) => "andn",
+                (0x4, 0x05) => "min",
+                (0x5, 0x05) => "minu",
+                (0x6, 0x05) => "max",
+                (0x7, 0x05) => "maxu",
+                (0x1, 0x30) => "rol",
+                (0x5, 0x30) => "ror",
+                (0x1, 0x14) => "bset",
+                (0x1, 0x24) => "bclr",
+                (0x1, 0x34) => "binv",
+                (0x5, 0x24) => "bext",
+                (0x4, 0x04) => "pack",
+                (0x7, 0x04) => "packh",
+                (0x1, 0x05) => "clmul",
+                (0x3, 0x05) => "clmulh",
+                (0x4, 0x14) => "xperm8",
+                (0x2, 0x14) => "xperm4",
+                (0x0, 0x19) => "aes64es",
+                (0x0, 0x1b) => "aes64esm",
+                (0x0, 0x1d) => "aes64ds",
+                (0x0, 0x1f) => "aes64dsm",
+                (0x0, 0x3f) => "aes64ks2",
+                (0x0, 0x28) => "sha512sum0r",
+                (0x0, 0x29) => "sha512sum1r",
+                (0x0, 0x2a) => "sha512sig0l",
+                (0x0, 0x2b) => "sha512sig1l",
+                (0x0, 0x2e) => "sha512sig0h",
+                (0x0, 0x2f) => "sha512sig1h",
+                (0x0, _) if matches!(funct7 & 0x1f, 0x11 | 0x13 | 0x15 | 0x17) => {
+                    let name = ["aes32esi", "aes32esmi", "aes32dsi", "aes32dsmi"]
+                        [(funct7 >> 1) as usize & 0x3];
+                    return Some(format!("{name} {rd}, {rs1}, {rs2}, {}", funct7 >> 5));
+                }
+                (0x0, _) if funct7 & 0x1d == 0x18 => {
+                    let name = ["sm4ed", "sm4ks"][(funct7 >> 1) as usize & 1];
+                    return Some(format!("{name} {rd}, {rs1}, {rs2}, {}", funct7 >> 5));
+                }
+                _ => return None,
+            };
+            format!("{name} {rd}, {rs1}, {rs2}")
+        }
+        0x3b => {
+            let name = match (funct3, funct7) {
+                (0x0, 0x00) => "addw",
+                (0x0, 0x20) => "subw",
+                (0x1, 0x00) => "sllw",
+                (0x5, 0x00) => "srlw",
+                (0x5, 0x20) => "sraw",
+                (0x0, 0x01) => "mulw",
+                (0x4, 0x01) => "divw",
+                (0x5, 0x01) => "divuw",
+                (0x6, 0x01) => "remw",
+                (0x7, 0x01) => "remuw",
+                (0x0, 0x04) => "add.uw",
+                (0x2, 0x10) => "sh1add.uw",
+                (0x4, 0x10) => "sh2add.uw",
+                (0x6, 0x10) => "sh3add.uw",
+                (0x1, 0x30) => "rolw",
+                (0x5, 0x30) => "rorw",
+                (0x4, 0x04) if rs2 == "zero" => return Some(format!("zext.h {rd}, {rs1}")),
+                (0x4, 0x04) => "packw",
+                _ => return None,
+            };
+            format!("{name} {rd}, {rs1}, {rs2}")
+        }
+        0x63 => {
+            let name = match funct3 {
+                0x0 => "beq",
+                0x1 => "bne",
+                0x4 => "blt",
+                0x5 => "bge",
+                0x6 => "bltu",
+                0x7 => "bgeu",
+                _ => return None,
+            };
+            let target = pc.wrapping_add(b_imm(raw) as u64);
+            format!("{name} {rs1}, {rs2}, {target:#x}")
+        }
+        0x37 => format!("lui {rd}, {:#x}", (raw >> 12) & 0xfffff),
+        0x17 => format!("auipc {rd}, {:#x}", (raw >> 12) & 0xfffff),
+        0x6f => format!("jal {rd}, {:#x}", pc.wrapping_add(j_imm(raw) as u64)),
+        0x67 if funct3 == 0 => format!("jalr {rd}, {}({rs1})", i_imm(raw)),
+        0x0f => match funct3 {
+            0x0 if raw == 0x0100000f => String::from("pause"),
+            0x0 => String::from("fence"),
+            0x1 => String::from("fence.i"),
+            0x2 if funct7 == 0 && rd == "zero" => {
+                let name = match (raw >> 20) & 0x1f {
+                    0 => "cbo.inval",
+                    1 => "cbo.clean",
+                    2 => "cbo.flush",
+                    4 => "cbo.zero",
+                    _ => return None,
+                };
+                format!("{name} ({rs1})")
+            }
+            _ => return None,
+        },
+        0x73 => {
+            let csr = (raw >> 20) & 0xfff;
+            let uimm = (raw >> 15) & 0x1f;
+            match funct3 {
+                0x0 => match raw {
+                    0x00000073 => String::from("ecall"),
+                    0x00100073 => String::from("ebreak"),
+                    0x10200073 => String::from("sret"),
+                    0x30200073 => String::from("mret"),
+                    0x10500073 => String::from("wfi"),
+                    0x00d00073 => String::from("wrs.nto"),
+                    0x01d00073 => String::from("wrs.sto"),
+                    _ if raw >> 25 == 0x09 && raw & 0xf80 == 0 => {
+                        format!("sfence.vma {rs1}, {rs2}")
+                    }
+                    _ => return None,
+                },
+                0x1 => format!("csrrw {rd}, {csr:#x}, {rs1}"),
+                0x2 => format!("csrrs {rd}, {csr:#x}, {rs1}"),
+                0x3 => format!("csrrc {rd}, {csr:#x}, {rs1}"),
+                0x5 => format!("csrrwi {rd}, {csr:#x}, {uimm}"),
+                0x6 => format!("csrrsi {rd}, {csr:#x}, {uimm}"),
+                0x7 => format!("csrrci {rd}, {csr:#x}, {uimm}"),
+                _ => return None,
+            }
+        }
+        0x2f => {
+            let width = match funct3 {
+                0x2 => "w",
+                0x3 => "d",
+                _ => return None,
+            };
+            let name = match funct7 >> 2 {
+                0x02 if rs2 == "zero" => {
+                    return Some(format!("lr.{width} {rd}, ({rs1})"));
+                }
+                0x03 => "sc",
+                0x01 => "amoswap",
+                0x00 => "amoadd",
+                0x04 => "amoxor",
+                0x0c => "amoand",
+                0x08 => "amoor",
+                0x10 => "amomin",
+                0x14 => "amomax",
+                0x18 => "amominu",
+                0x1c => "amomaxu",
+                _ => return None,
+            };
+            format!("{name}.{width} {rd}, {rs2}, ({rs1})")
+        }
+        _ => return None,
+    };
+
+    Some(text)
+}
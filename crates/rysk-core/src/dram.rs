@@ -0,0 +1,84 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{bus::DRAM_BASE, exception::Exception};
+
+pub const DRAM_SIZE: u64 = 1024 * 1024 * 128; // 128MiB
+
+#[derive(Debug, Clone)]
+pub struct Dram {
+    pub dram: Vec<u8>,
+    /// Guest physical address of the first byte, [`DRAM_BASE`] unless relocated.
+    pub base: u64,
+}
+
+impl Dram {
+    pub fn new(code: Vec<u8>) -> Dram {
+        Self::with_size(code, DRAM_SIZE)
+    }
+
+    /// Creates a dram of `size` bytes with `code` loaded at the start.
+    pub fn with_size(code: Vec<u8>, size: u64) -> Dram {
+        let mut dram = vec![0; size as usize];
+        dram.splice(..code.len(), code);
+
+        Self {
+            dram,
+            base: DRAM_BASE,
+        }
+    }
+
+    /// Moves the dram to start at `base`.
+    pub fn at(mut self, base: u64) -> Dram {
+        self.base = base;
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.dram.len() as u64
+    }
+
+    /// Whether `addr` falls inside the dram.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr.checked_sub(self.base)
+            .is_some_and(|offset| offset < self.size())
+    }
+
+    /// Returns the index range backing an access of `size` bits, if it is in bounds.
+    #[inline]
+    fn range(&self, addr: u64, size: u64) -> Option<core::ops::Range<usize>> {
+        let bytes = match size {
+            8 | 16 | 32 | 64 => size / 8,
+            _ => return None,
+        };
+        let start = addr.checked_sub(self.base)?;
+        let end = start.checked_add(bytes)?;
+
+        if end > self.size() {
+            return None;
+        }
+
+        Some(start as usize..end as usize)
+    }
+
+    #[inline]
+    pub fn load(&self, addr: u64, size: u64) -> Result<u64, Exception> {
+        let range = self
+            .range(addr, size)
+            .ok_or(Exception::LoadAccessFault(addr))?;
+
+        let mut bytes = [0; 8];
+        bytes[..range.len()].copy_from_slice(&self.dram[range]);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    #[inline]
+    pub fn store(&mut self, addr: u64, size: u64, value: u64) -> Result<(), Exception> {
+        let range = self
+            .range(addr, size)
+            .ok_or(Exception::StoreAmoAccessFault(addr))?;
+
+        let len = range.len();
+        self.dram[range].copy_from_slice(&value.to_le_bytes()[..len]);
+        Ok(())
+    }
+}
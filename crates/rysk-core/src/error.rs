@@ -0,0 +1,42 @@
+use alloc::string::String;
+
+use thiserror::Error;
+
+use crate::{exception::Exception, isa::IsaError};
+
+/// Host side failures, as opposed to [`Exception`](crate::exception::Exception)s
+/// which are visible to the guest.
+#[derive(Debug, Error)]
+pub enum EmulatorError {
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("invalid isa: {0}")]
+    Isa(#[from] IsaError),
+    #[error("image of {image} bytes doesn't fit in {memory} bytes of memory")]
+    ImageTooLarge { image: u64, memory: u64 },
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("invalid elf: {0}")]
+    InvalidElf(String),
+    #[error("unhandled {exception} at pc {pc:#x}")]
+    UnhandledException { exception: Exception, pc: u64 },
+    #[error("invalid cache: {0}")]
+    InvalidCache(String),
+    #[error("invalid timing: {0}")]
+    InvalidTiming(String),
+    #[error("invalid vlen {0}, expected a power of two from 64 to 65536")]
+    InvalidVlen(u64),
+    #[error("invalid hart count {0}, expected 1 to {max}", max = crate::hart::MAX_HARTS)]
+    InvalidHarts(u64),
+    #[error("invalid schedule: {0}")]
+    InvalidSchedule(String),
+    #[error("invalid fault: {0}")]
+    InvalidFault(String),
+    #[error("plugin {0}")]
+    Plugin(String),
+    #[error("script error: {0}")]
+    Script(String),
+    #[error("{0} is not supported yet")]
+    Unsupported(&'static str),
+}
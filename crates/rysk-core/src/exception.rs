@@ -0,0 +1,112 @@
+use thiserror::Error;
+
+/// Architectural exceptions, as defined by the privileged spec. The payload is
+/// the value reported in `xtval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Exception {
+    #[error("instruction address misaligned at {0:#x}")]
+    InstructionAddressMisaligned(u64),
+    #[error("instruction access fault at {0:#x}")]
+    InstructionAccessFault(u64),
+    #[error("illegal instruction {0:#010x}")]
+    IllegalInstruction(u64),
+    #[error("breakpoint at {0:#x}")]
+    Breakpoint(u64),
+    #[error("load address misaligned at {0:#x}")]
+    LoadAddressMisaligned(u64),
+    #[error("load access fault at {0:#x}")]
+    LoadAccessFault(u64),
+    #[error("store/amo address misaligned at {0:#x}")]
+    StoreAmoAddressMisaligned(u64),
+    #[error("store/amo access fault at {0:#x}")]
+    StoreAmoAccessFault(u64),
+    #[error("environment call from u-mode")]
+    EnvironmentCallFromUMode,
+    #[error("environment call from s-mode")]
+    EnvironmentCallFromSMode,
+    #[error("environment call from m-mode")]
+    EnvironmentCallFromMMode,
+    #[error("instruction page fault at {0:#x}")]
+    InstructionPageFault(u64),
+    #[error("load page fault at {0:#x}")]
+    LoadPageFault(u64),
+    #[error("store/amo page fault at {0:#x}")]
+    StoreAmoPageFault(u64),
+}
+
+impl Exception {
+    /// The exception code written to `xcause`.
+    pub fn code(&self) -> u64 {
+        match self {
+            Exception::InstructionAddressMisaligned(_) => 0,
+            Exception::InstructionAccessFault(_) => 1,
+            Exception::IllegalInstruction(_) => 2,
+            Exception::Breakpoint(_) => 3,
+            Exception::LoadAddressMisaligned(_) => 4,
+            Exception::LoadAccessFault(_) => 5,
+            Exception::StoreAmoAddressMisaligned(_) => 6,
+            Exception::StoreAmoAccessFault(_) => 7,
+            Exception::EnvironmentCallFromUMode => 8,
+            Exception::EnvironmentCallFromSMode => 9,
+            Exception::EnvironmentCallFromMMode => 11,
+            Exception::InstructionPageFault(_) => 12,
+            Exception::LoadPageFault(_) => 13,
+            Exception::StoreAmoPageFault(_) => 15,
+        }
+    }
+
+    /// The value written to `xtval`.
+    pub fn value(&self) -> u64 {
+        match *self {
+            Exception::InstructionAddressMisaligned(x)
+            | Exception::InstructionAccessFault(x)
+            | Exception::IllegalInstruction(x)
+            | Exception::Breakpoint(x)
+            | Exception::LoadAddressMisaligned(x)
+            | Exception::LoadAccessFault(x)
+            | Exception::StoreAmoAddressMisaligned(x)
+            | Exception::StoreAmoAccessFault(x)
+            | Exception::InstructionPageFault(x)
+            | Exception::LoadPageFault(x)
+            | Exception::StoreAmoPageFault(x) => x,
+            Exception::EnvironmentCallFromUMode
+            | Exception::EnvironmentCallFromSMode
+            | Exception::EnvironmentCallFromMMode => 0,
+        }
+    }
+}
+
+/// Asynchronous interrupts, identified by their `xcause` code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SupervisorSoftware,
+    MachineSoftware,
+    SupervisorTimer,
+    MachineTimer,
+    SupervisorExternal,
+    MachineExternal,
+}
+
+impl Interrupt {
+    /// All of them, the highest priority first.
+    pub const BY_PRIORITY: [Interrupt; 6] = [
+        Interrupt::MachineExternal,
+        Interrupt::MachineSoftware,
+        Interrupt::MachineTimer,
+        Interrupt::SupervisorExternal,
+        Interrupt::SupervisorSoftware,
+        Interrupt::SupervisorTimer,
+    ];
+
+    /// The interrupt code, also the bit position in `mip`/`mie`.
+    pub fn code(&self) -> u64 {
+        match self {
+            Interrupt::SupervisorSoftware => 1,
+            Interrupt::MachineSoftware => 3,
+            Interrupt::SupervisorTimer => 5,
+            Interrupt::MachineTimer => 7,
+            Interrupt::SupervisorExternal => 9,
+            Interrupt::MachineExternal => 11,
+        }
+    }
+}
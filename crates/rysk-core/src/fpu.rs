@@ -0,0 +1,821 @@
+//! The F, D and Zfh extensions. The floating point registers are 64 bits
+//! wide and single and half precision values are NaN-boxed in them: the
+//! upper bits are all ones, and a register which isn't a valid box reads as
+//! the canonical NaN in operations of the narrower format.
+//!
+//! Arithmetic is done in software on the bit patterns rather than with the
+//! host's floats, which only round to nearest even, don't report exception
+//! flags and aren't all available without `std`. Every operation computes
+//! its exact result as a significand and an exponent, with the bits far past
+//! the rounding position folded into a sticky bit, and [`Format::round`]
+//! rounds that once in the requested mode. NaN results are always the
+//! canonical NaN.
+
+use core::cmp::Ordering;
+
+use tracing::debug;
+
+use crate::{
+    cpu::{Cpu, FCSR, MSTATUS, STATUS_FS},
+    exception::Exception,
+    instruction::Instruction,
+    isa::Extension,
+};
+
+/// Inexact.
+pub const NX: u64 = 1 << 0;
+/// Underflow.
+pub const UF: u64 = 1 << 1;
+/// Overflow.
+pub const OF: u64 = 1 << 2;
+/// Divide by zero.
+pub const DZ: u64 = 1 << 3;
+/// Invalid operation.
+pub const NV: u64 = 1 << 4;
+
+/// Rounding modes, as encoded in `frm` and in the rm field of instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl Rounding {
+    /// The static rounding mode encoded as `bits`, `None` for the reserved
+    /// encodings and for dynamic rounding.
+    pub fn from_bits(bits: u64) -> Option<Self> {
+        match bits {
+            0 => Some(Rounding::NearestEven),
+            1 => Some(Rounding::TowardZero),
+            2 => Some(Rounding::Down),
+            3 => Some(Rounding::Up),
+            4 => Some(Rounding::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+/// An IEEE 754 binary interchange format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const HALF: Format = Format {
+    exp_bits: 5,
+    frac_bits: 10,
+};
+
+pub const SINGLE: Format = Format {
+    exp_bits: 8,
+    frac_bits: 23,
+};
+
+pub const DOUBLE: Format = Format {
+    exp_bits: 11,
+    frac_bits: 52,
+};
+
+/// A finite non zero value, `sig * 2^exp`.
+type Unpacked = (bool, i32, u128);
+
+impl Format {
+    /// Size in bits.
+    pub fn width(self) -> u64 {
+        (1 + self.exp_bits + self.frac_bits) as u64
+    }
+
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    fn max_exp(self) -> u64 {
+        (1 << self.exp_bits) - 1
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    fn sign_bit(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn sign(self, x: u64) -> bool {
+        x & self.sign_bit() != 0
+    }
+
+    fn exp(self, x: u64) -> u64 {
+        (x >> self.frac_bits) & self.max_exp()
+    }
+
+    pub fn canonical_nan(self) -> u64 {
+        self.max_exp() << self.frac_bits | 1 << (self.frac_bits - 1)
+    }
+
+    fn infinity(self, sign: bool) -> u64 {
+        self.zero(sign) | self.max_exp() << self.frac_bits
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        if sign {
+            self.sign_bit()
+        } else {
+            0
+        }
+    }
+
+    pub fn is_nan(self, x: u64) -> bool {
+        self.exp(x) == self.max_exp() && x & self.frac_mask() != 0
+    }
+
+    fn is_signaling(self, x: u64) -> bool {
+        self.is_nan(x) && x & 1 << (self.frac_bits - 1) == 0
+    }
+
+    fn is_inf(self, x: u64) -> bool {
+        self.exp(x) == self.max_exp() && x & self.frac_mask() == 0
+    }
+
+    fn is_zero(self, x: u64) -> bool {
+        x & !self.sign_bit() == 0
+    }
+
+    fn unpack(self, x: u64) -> Unpacked {
+        let frac = (x & self.frac_mask()) as u128;
+        let exp = self.exp(x) as i32;
+        let shift = self.bias() + self.frac_bits as i32;
+        match exp {
+            0 => (self.sign(x), 1 - shift, frac),
+            _ => (self.sign(x), exp - shift, frac | 1 << self.frac_bits),
+        }
+    }
+
+    /// The canonical NaN, raising NV if any of `operands` is signaling.
+    fn nan(self, operands: &[u64], flags: &mut u64) -> u64 {
+        if operands.iter().any(|x| self.is_signaling(*x)) {
+            *flags |= NV;
+        }
+        self.canonical_nan()
+    }
+
+    /// Rounds `sign * sig * 2^exp` to the format.
+    pub fn round(self, sign: bool, exp: i32, sig: u128, rm: Rounding, flags: &mut u64) -> u64 {
+        if sig == 0 {
+            return self.zero(sign);
+        }
+        let p = self.frac_bits as i32;
+        let emin = 1 - self.bias();
+        // exponent of the leading bit
+        let top = exp + (127 - sig.leading_zeros() as i32);
+        // weight of the last bit kept, subnormals keep fewer bits
+        let mut lsb = (top - p).max(emin - p);
+        let (mut kept, inexact) = round_at(sign, exp, sig, lsb, rm);
+        if kept >> (p + 1) != 0 {
+            // rounded up to the next power of two
+            kept >>= 1;
+            lsb += 1;
+        }
+        if inexact {
+            *flags |= NX;
+            // tininess is detected after rounding, with an unbounded exponent
+            let (unbounded, _) = round_at(sign, exp, sig, top - p, rm);
+            if top < emin && !(top == emin - 1 && unbounded >> (p + 1) != 0) {
+                *flags |= UF;
+            }
+        }
+
+        let sign_bit = self.zero(sign);
+        if kept >> p == 0 {
+            // subnormal, or zero
+            return sign_bit | kept as u64;
+        }
+        let biased = lsb + p + self.bias();
+        if biased >= self.max_exp() as i32 {
+            *flags |= OF | NX;
+            let infinite = match rm {
+                Rounding::NearestEven | Rounding::NearestMaxMagnitude => true,
+                Rounding::TowardZero => false,
+                Rounding::Down => sign,
+                Rounding::Up => !sign,
+            };
+            return match infinite {
+                true => self.infinity(sign),
+                false => self.infinity(sign) - 1,
+            };
+        }
+        sign_bit | (biased as u64) << self.frac_bits | kept as u64 & self.frac_mask()
+    }
+
+    /// Exact zero result of an addition of operands with opposite signs, or
+    /// of two zeros.
+    fn zero_sum(self, a: bool, b: bool, rm: Rounding) -> u64 {
+        self.zero(if a == b { a } else { rm == Rounding::Down })
+    }
+
+    pub fn add(self, a: u64, b: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], flags);
+        }
+        match (self.is_inf(a), self.is_inf(b)) {
+            (true, true) if self.sign(a) != self.sign(b) => {
+                *flags |= NV;
+                return self.canonical_nan();
+            }
+            (true, _) => return a,
+            (_, true) => return b,
+            _ => {}
+        }
+        match (self.is_zero(a), self.is_zero(b)) {
+            (true, true) => return self.zero_sum(self.sign(a), self.sign(b), rm),
+            (true, false) => return b,
+            (false, true) => return a,
+            _ => {}
+        }
+        match add(self.unpack(a), self.unpack(b)) {
+            (_, _, 0) => self.zero_sum(false, true, rm),
+            (sign, exp, sig) => self.round(sign, exp, sig, rm, flags),
+        }
+    }
+
+    pub fn sub(self, a: u64, b: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        match self.is_nan(b) {
+            true => self.nan(&[a, b], flags),
+            false => self.add(a, b ^ self.sign_bit(), rm, flags),
+        }
+    }
+
+    pub fn mul(self, a: u64, b: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], flags);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        if (self.is_inf(a) && self.is_zero(b)) || (self.is_zero(a) && self.is_inf(b)) {
+            *flags |= NV;
+            return self.canonical_nan();
+        }
+        if self.is_inf(a) || self.is_inf(b) {
+            return self.infinity(sign);
+        }
+        if self.is_zero(a) || self.is_zero(b) {
+            return self.zero(sign);
+        }
+        let (_, ea, ma) = self.unpack(a);
+        let (_, eb, mb) = self.unpack(b);
+        self.round(sign, ea + eb, ma * mb, rm, flags)
+    }
+
+    pub fn div(self, a: u64, b: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        if self.is_nan(a) || self.is_nan(b) {
+            return self.nan(&[a, b], flags);
+        }
+        let sign = self.sign(a) != self.sign(b);
+        match (
+            self.is_inf(a),
+            self.is_inf(b),
+            self.is_zero(a),
+            self.is_zero(b),
+        ) {
+            (true, true, _, _) | (_, _, true, true) => {
+                *flags |= NV;
+                return self.canonical_nan();
+            }
+            (true, _, _, _) => return self.infinity(sign),
+            (_, true, _, _) | (_, _, true, _) => return self.zero(sign),
+            (_, _, _, true) => {
+                *flags |= DZ;
+                return self.infinity(sign);
+            }
+            _ => {}
+        }
+        let (_, ea, ma) = self.unpack(a);
+        let (_, eb, mb) = self.unpack(b);
+        // plenty of quotient bits past the precision of the format
+        let shift = ma.leading_zeros() - 1;
+        let n = ma << shift;
+        let sig = (n / mb) | (n % mb != 0) as u128;
+        self.round(sign, ea - shift as i32 - eb, sig, rm, flags)
+    }
+
+    pub fn sqrt(self, a: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        if self.is_nan(a) {
+            return self.nan(&[a], flags);
+        }
+        if self.is_zero(a) {
+            return a;
+        }
+        if self.sign(a) {
+            *flags |= NV;
+            return self.canonical_nan();
+        }
+        if self.is_inf(a) {
+            return a;
+        }
+        let (_, exp, sig) = self.unpack(a);
+        // the exponent has to be even to take its half
+        let mut shift = sig.leading_zeros() as i32 - 2;
+        if (exp - shift) % 2 != 0 {
+            shift += 1;
+        }
+        let n = sig << shift;
+        let root = n.isqrt();
+        let sig = root | (root * root != n) as u128;
+        self.round(false, (exp - shift) / 2, sig, rm, flags)
+    }
+
+    /// `a * b + c`, the signs of the product and of the addend flipped as
+    /// the fused instructions ask.
+    pub fn fma(
+        self,
+        (a, b, c): (u64, u64, u64),
+        (negate_product, negate_addend): (bool, bool),
+        rm: Rounding,
+        flags: &mut u64,
+    ) -> u64 {
+        // invalid even when the addend is a quiet NaN
+        if (self.is_inf(a) && self.is_zero(b)) || (self.is_zero(a) && self.is_inf(b)) {
+            *flags |= NV;
+            return self.canonical_nan();
+        }
+        if self.is_nan(a) || self.is_nan(b) || self.is_nan(c) {
+            return self.nan(&[a, b, c], flags);
+        }
+        let product = (self.sign(a) != self.sign(b)) != negate_product;
+        let c = if negate_addend {
+            c ^ self.sign_bit()
+        } else {
+            c
+        };
+        if self.is_inf(a) || self.is_inf(b) {
+            if self.is_inf(c) && self.sign(c) != product {
+                *flags |= NV;
+                return self.canonical_nan();
+            }
+            return self.infinity(product);
+        }
+        if self.is_inf(c) {
+            return c;
+        }
+        if self.is_zero(a) || self.is_zero(b) {
+            return match self.is_zero(c) {
+                true => self.zero_sum(product, self.sign(c), rm),
+                false => c,
+            };
+        }
+        let (_, ea, ma) = self.unpack(a);
+        let (_, eb, mb) = self.unpack(b);
+        if self.is_zero(c) {
+            return self.round(product, ea + eb, ma * mb, rm, flags);
+        }
+        match add((product, ea + eb, ma * mb), self.unpack(c)) {
+            (_, _, 0) => self.zero_sum(false, true, rm),
+            (sign, exp, sig) => self.round(sign, exp, sig, rm, flags),
+        }
+    }
+
+    /// Orders numbers, with the two zeros equal. Neither is a NaN.
+    fn compare(self, a: u64, b: u64) -> Ordering {
+        if self.is_zero(a) && self.is_zero(b) {
+            return Ordering::Equal;
+        }
+        match (self.sign(a), self.sign(b)) {
+            (false, false) => a.cmp(&b),
+            (true, true) => b.cmp(&a),
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+        }
+    }
+
+    /// `feq`, quiet: only signaling NaNs are invalid.
+    pub fn eq(self, a: u64, b: u64, flags: &mut u64) -> bool {
+        if self.is_nan(a) || self.is_nan(b) {
+            self.nan(&[a, b], flags);
+            return false;
+        }
+        self.compare(a, b) == Ordering::Equal
+    }
+
+    /// `flt`, or `fle` when `equal`. Signaling: any NaN is invalid.
+    pub fn lt(self, a: u64, b: u64, equal: bool, flags: &mut u64) -> bool {
+        if self.is_nan(a) || self.is_nan(b) {
+            *flags |= NV;
+            return false;
+        }
+        match self.compare(a, b) {
+            Ordering::Less => true,
+            Ordering::Equal => equal,
+            Ordering::Greater => false,
+        }
+    }
+
+    /// `fmin`, or `fmax` when `max`. A single NaN operand is ignored and -0
+    /// is smaller than +0.
+    pub fn min(self, a: u64, b: u64, max: bool, flags: &mut u64) -> u64 {
+        match (self.is_nan(a), self.is_nan(b)) {
+            (true, true) => return self.nan(&[a, b], flags),
+            (true, false) => {
+                self.nan(&[a], flags);
+                return b;
+            }
+            (false, true) => {
+                self.nan(&[b], flags);
+                return a;
+            }
+            _ => {}
+        }
+        let order = match self.compare(a, b) {
+            Ordering::Equal => self.sign(b).cmp(&self.sign(a)),
+            order => order,
+        };
+        match (order == Ordering::Less) != max {
+            true => a,
+            false => b,
+        }
+    }
+
+    /// `fclass`, a single bit set for the category of `x`.
+    pub fn class(self, x: u64) -> u64 {
+        let sign = self.sign(x);
+        let bit = match (self.exp(x), x & self.frac_mask()) {
+            _ if self.is_nan(x) => match self.is_signaling(x) {
+                true => 8,
+                false => 9,
+            },
+            (e, _) if e == self.max_exp() => 7,
+            (0, 0) => 4,
+            (0, _) => 5,
+            _ => 6,
+        };
+        match (bit, sign) {
+            (8 | 9, _) | (_, false) => 1 << bit,
+            (_, true) => 1 << (7 - bit),
+        }
+    }
+
+    /// Converts `x` to the format `to`, NaNs to its canonical NaN.
+    pub fn convert(self, to: Format, x: u64, rm: Rounding, flags: &mut u64) -> u64 {
+        if self.is_nan(x) {
+            if self.is_signaling(x) {
+                *flags |= NV;
+            }
+            return to.canonical_nan();
+        }
+        if self.is_inf(x) {
+            return to.infinity(self.sign(x));
+        }
+        if self.is_zero(x) {
+            return to.zero(self.sign(x));
+        }
+        let (sign, exp, sig) = self.unpack(x);
+        to.round(sign, exp, sig, rm, flags)
+    }
+
+    /// `fsgnj`, `fsgnjn` and `fsgnjx`, selected by `funct3`.
+    pub fn inject_sign(self, a: u64, b: u64, funct3: u64) -> Option<u64> {
+        let sign = match funct3 {
+            0 => b,
+            1 => !b,
+            2 => a ^ b,
+            _ => return None,
+        } & self.sign_bit();
+        Some(a & !self.sign_bit() | sign)
+    }
+
+    /// Converts to an integer of `bits` bits, signed or not, sign extended
+    /// to 64 bits. Values out of range saturate, NaNs to the largest.
+    pub fn to_int(self, x: u64, signed: bool, bits: u32, rm: Rounding, flags: &mut u64) -> u64 {
+        let (min, max): (i128, i128) = match signed {
+            true => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+            false => (0, (1 << bits) - 1),
+        };
+        let value = if self.is_nan(x) {
+            Err(max)
+        } else if self.is_inf(x) {
+            Err(if self.sign(x) { min } else { max })
+        } else if self.is_zero(x) {
+            Ok(0)
+        } else {
+            let (sign, exp, sig) = self.unpack(x);
+            // magnitudes from 2^64 on don't fit anything
+            if exp + (127 - sig.leading_zeros() as i32) >= 64 {
+                Err(if sign { min } else { max })
+            } else {
+                let (kept, inexact) = round_at(sign, exp, sig, 0, rm);
+                let value = if sign { -(kept as i128) } else { kept as i128 };
+                match (min..=max).contains(&value) {
+                    true => {
+                        if inexact {
+                            *flags |= NX;
+                        }
+                        Ok(value)
+                    }
+                    false => Err(if sign { min } else { max }),
+                }
+            }
+        };
+        let value = value.unwrap_or_else(|saturated| {
+            *flags |= NV;
+            saturated
+        });
+        match bits {
+            32 => value as i32 as i64 as u64,
+            _ => value as u64,
+        }
+    }
+
+    /// Converts the low `bits` bits of `x`, a signed integer or not.
+    pub fn from_int(self, x: u64, signed: bool, bits: u32, rm: Rounding, flags: &mut u64) -> u64 {
+        let (sign, magnitude) = match (signed, bits) {
+            (true, 32) => ((x as i32) < 0, (x as i32).unsigned_abs() as u64),
+            (true, _) => ((x as i64) < 0, (x as i64).unsigned_abs()),
+            (false, 32) => (false, x as u32 as u64),
+            (false, _) => (false, x),
+        };
+        self.round(sign, 0, magnitude as u128, rm, flags)
+    }
+}
+
+/// The format selected by the `fmt` field of an instruction.
+fn format(fmt: u64, inst: u64) -> Result<Format, Exception> {
+    match fmt {
+        0 => Ok(SINGLE),
+        1 => Ok(DOUBLE),
+        2 => Ok(HALF),
+        _ => Err(Exception::IllegalInstruction(inst)),
+    }
+}
+
+/// Rounds `sig * 2^exp` to a multiple of `2^lsb`, returning the multiple
+/// and whether that was inexact.
+fn round_at(sign: bool, exp: i32, sig: u128, lsb: i32, rm: Rounding) -> (u128, bool) {
+    let shift = lsb - exp;
+    if shift <= 0 {
+        return (sig << -shift, false);
+    }
+    let (kept, rest) = match shift {
+        1..=127 => (sig >> shift, sig & ((1 << shift) - 1)),
+        _ => (0, sig),
+    };
+    if rest == 0 {
+        return (kept, false);
+    }
+    // anything shifted further than 128 bits is below half of the lsb
+    let half = match shift {
+        1..=128 => rest.cmp(&(1 << (shift - 1))),
+        _ => Ordering::Less,
+    };
+    let up = match rm {
+        Rounding::NearestEven => half == Ordering::Greater || (half.is_eq() && kept & 1 == 1),
+        Rounding::NearestMaxMagnitude => half != Ordering::Less,
+        Rounding::TowardZero => false,
+        Rounding::Down => sign,
+        Rounding::Up => !sign,
+    };
+    (kept + up as u128, true)
+}
+
+/// Shifts right, or-ing the bits shifted out into the lowest one.
+fn shift_right_jam(x: u128, n: u32) -> u128 {
+    match n {
+        0 => x,
+        1..=127 => x >> n | (x & ((1 << n) - 1) != 0) as u128,
+        _ => (x != 0) as u128,
+    }
+}
+
+/// Sum of two values. Past 125 bits it is only as exact as rounding to any
+/// of the formats needs.
+fn add(a: Unpacked, b: Unpacked) -> Unpacked {
+    // leading bits at 125, leaving room for the carry
+    let normalize = |(sign, exp, sig): Unpacked| {
+        let shift = sig.leading_zeros() as i32 - 2;
+        (sign, exp - shift, sig << shift)
+    };
+    let (a, b) = (normalize(a), normalize(b));
+    let ((sa, exp, ma), (sb, eb, mb)) = if a.1 >= b.1 { (a, b) } else { (b, a) };
+    let mb = shift_right_jam(mb, (exp - eb) as u32);
+    match (sa == sb, ma >= mb) {
+        (true, _) => (sa, exp, ma + mb),
+        (false, true) => (sa, exp, ma - mb),
+        (false, false) => (sb, exp, mb - ma),
+    }
+}
+
+impl Cpu {
+    /// Reads a floating point register holding a `fmt` value, a narrower
+    /// value which isn't NaN-boxed reads as the canonical NaN.
+    fn read_fp(&self, fmt: Format, r: usize) -> u64 {
+        let value = self.fregs[r];
+        match fmt.width() {
+            64 => value,
+            width if value >> width == u64::MAX >> width => value & !(u64::MAX << width),
+            _ => fmt.canonical_nan(),
+        }
+    }
+
+    /// Writes a `fmt` value, NaN-boxing it if it is narrower than a register.
+    fn write_fp(&mut self, fmt: Format, r: usize, value: u64) {
+        self.fregs[r] = match fmt.width() {
+            64 => value,
+            width => value | u64::MAX << width,
+        };
+        self.dirty_fp();
+    }
+
+    /// Marks the floating point state dirty in `mstatus.FS`, and so in SD,
+    /// after the registers or `fcsr` changed.
+    pub(crate) fn dirty_fp(&mut self) {
+        self.csrs[MSTATUS] |= STATUS_FS | 1 << (self.isa.xlen.bits() - 1);
+    }
+
+    /// The rounding mode of an instruction, `frm` for dynamic rounding.
+    fn rounding(&self, rm: u64, inst: u64) -> Result<Rounding, Exception> {
+        let rm = match rm {
+            0b111 => (self.csrs[FCSR] >> 5) & 0x7,
+            _ => rm,
+        };
+        Rounding::from_bits(rm).ok_or(Exception::IllegalInstruction(inst))
+    }
+
+    /// Executes the loads, stores and computational instructions of the
+    /// floating point extensions, which are illegal while `mstatus.FS` is
+    /// off.
+    pub(crate) fn execute_fp(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        let Instruction {
+            raw: inst,
+            opcode,
+            rd,
+            rs1,
+            rs2,
+            funct3,
+            funct7,
+            ..
+        } = decoded;
+        let illegal = Exception::IllegalInstruction(inst);
+        if self.csrs[MSTATUS] & STATUS_FS == 0 {
+            Err(illegal)?
+        }
+        let mut flags = 0;
+
+        match opcode {
+            0x07 => {
+                let fmt = match funct3 {
+                    0b001 => HALF,
+                    0b010 => SINGLE,
+                    0b011 => DOUBLE,
+                    _ => Err(illegal)?,
+                };
+                debug!("FL");
+                let offset = (inst as i32 as i64 >> 20) as u64;
+                let value = self.load(self.regs[rs1].wrapping_add(offset), fmt.width())?;
+                self.write_fp(fmt, rd, value);
+            }
+            0x27 => {
+                let fmt = match funct3 {
+                    0b001 => HALF,
+                    0b010 => SINGLE,
+                    0b011 => DOUBLE,
+                    _ => Err(illegal)?,
+                };
+                debug!("FS");
+                let offset =
+                    ((inst & 0xfe000000) as i32 as i64 >> 20) as u64 | ((inst >> 7) & 0x1f);
+                let addr = self.regs[rs1].wrapping_add(offset);
+                self.store(addr, fmt.width(), self.fregs[rs2])?;
+            }
+            0x43 | 0x47 | 0x4b | 0x4f => {
+                let fmt = format(funct7 & 0x3, inst)?;
+                let rm = self.rounding(funct3, inst)?;
+                let rs3 = (inst >> 27) as usize;
+                let operands = (
+                    self.read_fp(fmt, rs1),
+                    self.read_fp(fmt, rs2),
+                    self.read_fp(fmt, rs3),
+                );
+                // fmadd, fmsub, fnmsub, fnmadd
+                let negate = match opcode {
+                    0x43 => (false, false),
+                    0x47 => (false, true),
+                    0x4b => (true, false),
+                    _ => (true, true),
+                };
+                debug!("FMA");
+                let value = fmt.fma(operands, negate, rm, &mut flags);
+                self.write_fp(fmt, rd, value);
+            }
+            0x53 => {
+                let fmt = format(funct7 & 0x3, inst)?;
+                let (a, b) = (self.read_fp(fmt, rs1), self.read_fp(fmt, rs2));
+                match funct7 >> 2 {
+                    0x00..=0x03 => {
+                        let rm = self.rounding(funct3, inst)?;
+                        let value = match funct7 >> 2 {
+                            0x00 => {
+                                debug!("FADD");
+                                fmt.add(a, b, rm, &mut flags)
+                            }
+                            0x01 => {
+                                debug!("FSUB");
+                                fmt.sub(a, b, rm, &mut flags)
+                            }
+                            0x02 => {
+                                debug!("FMUL");
+                                fmt.mul(a, b, rm, &mut flags)
+                            }
+                            _ => {
+                                debug!("FDIV");
+                                fmt.div(a, b, rm, &mut flags)
+                            }
+                        };
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x0b if rs2 == 0 => {
+                        debug!("FSQRT");
+                        let rm = self.rounding(funct3, inst)?;
+                        let value = fmt.sqrt(a, rm, &mut flags);
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x04 => {
+                        debug!("FSGNJ");
+                        let value = fmt.inject_sign(a, b, funct3).ok_or(illegal)?;
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x05 if funct3 < 2 => {
+                        debug!("FMIN/FMAX");
+                        let value = fmt.min(a, b, funct3 == 1, &mut flags);
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x08 if rs2 < 3 && rs2 as u64 != funct7 & 0x3 => {
+                        debug!("FCVT between formats");
+                        let rm = self.rounding(funct3, inst)?;
+                        let from = format(rs2 as u64, inst)?;
+                        // execute only checks the extension of the destination
+                        let source = match rs2 {
+                            0 => Extension::F,
+                            1 => Extension::D,
+                            _ => Extension::Zfh,
+                        };
+                        if !self.isa.has(source) {
+                            Err(illegal)?
+                        }
+                        let value = from.convert(fmt, self.read_fp(from, rs1), rm, &mut flags);
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x14 => {
+                        debug!("FLE/FLT/FEQ");
+                        self.regs[rd] = match funct3 {
+                            0 => fmt.lt(a, b, true, &mut flags),
+                            1 => fmt.lt(a, b, false, &mut flags),
+                            2 => fmt.eq(a, b, &mut flags),
+                            _ => Err(illegal)?,
+                        } as u64;
+                    }
+                    0x18 if rs2 < 4 => {
+                        debug!("FCVT to an integer");
+                        let rm = self.rounding(funct3, inst)?;
+                        let bits = if rs2 < 2 { 32 } else { 64 };
+                        self.regs[rd] = fmt.to_int(a, rs2 % 2 == 0, bits, rm, &mut flags);
+                    }
+                    0x1a if rs2 < 4 => {
+                        debug!("FCVT from an integer");
+                        let rm = self.rounding(funct3, inst)?;
+                        let bits = if rs2 < 2 { 32 } else { 64 };
+                        let value =
+                            fmt.from_int(self.regs[rs1], rs2 % 2 == 0, bits, rm, &mut flags);
+                        self.write_fp(fmt, rd, value);
+                    }
+                    0x1c if rs2 == 0 && funct3 == 0 => {
+                        debug!("FMV.X");
+                        // the raw bits, whether they are NaN-boxed or not
+                        self.regs[rd] = match fmt.width() {
+                            16 => self.fregs[rs1] as i16 as i64 as u64,
+                            32 => self.fregs[rs1] as i32 as i64 as u64,
+                            _ => self.fregs[rs1],
+                        };
+                    }
+                    0x1c if rs2 == 0 && funct3 == 1 => {
+                        debug!("FCLASS");
+                        self.regs[rd] = fmt.class(a);
+                    }
+                    0x1e if rs2 == 0 && funct3 == 0 => {
+                        debug!("FMV.F");
+                        let value = self.regs[rs1] & (u64::MAX >> (64 - fmt.width()));
+                        self.write_fp(fmt, rd, value);
+                    }
+                    _ => Err(illegal)?,
+                }
+            }
+            _ => Err(illegal)?,
+        }
+
+        if flags & !self.csrs[FCSR] != 0 {
+            self.csrs[FCSR] |= flags;
+            self.dirty_fp();
+        }
+        Ok(())
+    }
+}
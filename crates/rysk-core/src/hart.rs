@@ -0,0 +1,94 @@
+//! Multiple harts on one bus. A [`Cpu`] executes a single hart at a time:
+//! the state of the others is parked in [`Hart`]s and swapped in with
+//! [`Cpu::switch`], so everything inspecting the cpu (hooks, observers, the
+//! debuggers) sees whichever hart is running. The bus, clock, hooks and
+//! timing models are shared by the harts, the TLB is flushed on a switch.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+    cpu::{stimecmp_pending, Cpu, Mode, MHARTID, MIP},
+    exception::Interrupt,
+    trigger::{Trigger, TRIGGERS},
+};
+
+/// Most harts a machine can have.
+pub const MAX_HARTS: u64 = 64;
+
+/// Architectural state of a hart which isn't running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hart {
+    pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    /// Contents of the vector registers.
+    pub vregs: Vec<u8>,
+    pub pc: u64,
+    pub csrs: Box<[u64; 4096]>,
+    pub mode: Mode,
+    pub cycles: u64,
+    pub retired: u64,
+    pub triggers: [Trigger; TRIGGERS],
+    /// Not scheduled until something starts it, e.g. SBI `hart_start`.
+    pub stopped: bool,
+}
+
+impl Hart {
+    pub fn id(&self) -> u64 {
+        self.csrs[MHARTID]
+    }
+
+    /// Makes the supervisor timer interrupt pending as `stimecmp` says at
+    /// `now`, if Sstc is enabled, see [`Cpu::poll_stimecmp`].
+    pub fn poll_stimecmp(&mut self, now: u64) {
+        let bit = 1 << Interrupt::SupervisorTimer.code();
+        match stimecmp_pending(&self.csrs, now) {
+            Some(true) => self.csrs[MIP] |= bit,
+            Some(false) => self.csrs[MIP] &= !bit,
+            None => {}
+        }
+    }
+}
+
+impl Cpu {
+    /// Id of the running hart.
+    pub fn hart_id(&self) -> u64 {
+        self.csrs[MHARTID]
+    }
+
+    /// A copy of the running hart as hart `id`, with its id in `mhartid` and
+    /// a0 as a boot loader would leave it.
+    pub fn clone_hart(&self, id: u64) -> Hart {
+        let mut hart = Hart {
+            regs: self.regs,
+            fregs: self.fregs,
+            vregs: self.vector.regs.clone(),
+            pc: self.pc,
+            csrs: Box::new(self.csrs),
+            mode: self.mode,
+            cycles: self.cycles,
+            retired: self.retired,
+            triggers: self.triggers,
+            stopped: false,
+        };
+        hart.regs[10] = id;
+        hart.csrs[MHARTID] = id;
+        hart
+    }
+
+    /// Parks the running hart in `hart` and runs the one it held instead.
+    pub fn switch(&mut self, hart: &mut Hart) {
+        core::mem::swap(&mut self.regs, &mut hart.regs);
+        core::mem::swap(&mut self.fregs, &mut hart.fregs);
+        core::mem::swap(&mut self.vector.regs, &mut hart.vregs);
+        core::mem::swap(&mut self.pc, &mut hart.pc);
+        core::mem::swap(&mut self.csrs, &mut *hart.csrs);
+        core::mem::swap(&mut self.mode, &mut hart.mode);
+        core::mem::swap(&mut self.cycles, &mut hart.cycles);
+        core::mem::swap(&mut self.retired, &mut hart.retired);
+        core::mem::swap(&mut self.triggers, &mut hart.triggers);
+        self.tlb.flush();
+        if let Some(pipeline) = &mut self.pipeline {
+            pipeline.flush();
+        }
+    }
+}
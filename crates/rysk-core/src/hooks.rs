@@ -0,0 +1,82 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::fmt;
+
+use crate::instruction::Instruction;
+
+/// What a hook gets to see about the instruction being executed.
+#[derive(Debug, Clone, Copy)]
+pub struct HookContext<'a> {
+    /// Address of the instruction.
+    pub pc: u64,
+    pub inst: Instruction,
+    /// Register file, before execution for pre hooks and after it for post hooks.
+    pub regs: &'a [u64; 32],
+}
+
+pub type Hook = Box<dyn FnMut(&HookContext) + Send>;
+
+/// Closures run around every instruction, see [`Hooks::pre`] and [`Hooks::post`],
+/// and on spin-wait hints, see [`Hooks::pause`].
+#[derive(Default)]
+pub struct Hooks {
+    pre: Vec<Hook>,
+    post: Vec<Hook>,
+    pause: Vec<Hook>,
+}
+
+impl Hooks {
+    /// Registers a closure called before an instruction executes.
+    pub fn pre(&mut self, hook: impl FnMut(&HookContext) + Send + 'static) {
+        self.pre.push(Box::new(hook));
+    }
+
+    /// Registers a closure called after an instruction retires. Instructions
+    /// raising an exception don't retire.
+    pub fn post(&mut self, hook: impl FnMut(&HookContext) + Send + 'static) {
+        self.post.push(Box::new(hook));
+    }
+
+    /// Registers a closure called when a `pause` executes, e.g. to yield the
+    /// host thread while the guest spins.
+    pub fn pause(&mut self, hook: impl FnMut(&HookContext) + Send + 'static) {
+        self.pause.push(Box::new(hook));
+    }
+
+    pub fn clear(&mut self) {
+        self.pre.clear();
+        self.post.clear();
+        self.pause.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pre.is_empty() && self.post.is_empty() && self.pause.is_empty()
+    }
+
+    pub(crate) fn run_pre(&mut self, ctx: &HookContext) {
+        for hook in &mut self.pre {
+            hook(ctx);
+        }
+    }
+
+    pub(crate) fn run_post(&mut self, ctx: &HookContext) {
+        for hook in &mut self.post {
+            hook(ctx);
+        }
+    }
+
+    pub(crate) fn run_pause(&mut self, ctx: &HookContext) {
+        for hook in &mut self.pause {
+            hook(ctx);
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("pre", &self.pre.len())
+            .field("post", &self.post.len())
+            .field("pause", &self.pause.len())
+            .finish()
+    }
+}
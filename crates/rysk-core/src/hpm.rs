@@ -0,0 +1,85 @@
+//! Programmable performance counters: `mhpmcounter3`-`mhpmcounter31` each
+//! count the event whose id is written to the matching `mhpmevent`, and read
+//! back through the unprivileged `hpmcounter` aliases.
+
+/// `mhpmcounter3`, the others follow.
+pub const MHPMCOUNTER3: usize = 0xB03;
+/// `mhpmevent3`, the others follow.
+pub const MHPMEVENT3: usize = 0x323;
+/// `hpmcounter3`, a read-only alias of `mhpmcounter3`.
+pub const HPMCOUNTER3: usize = 0xC03;
+/// `mhpmcounter3h`, the high half of `mhpmcounter3` on RV32, the others
+/// follow.
+pub const MHPMCOUNTER3H: usize = 0xB83;
+/// `hpmcounter3h`, a read-only alias of `mhpmcounter3h`.
+pub const HPMCOUNTER3H: usize = 0xC83;
+pub const HPM_COUNTERS: usize = 29;
+
+/// Event ids accepted by `mhpmevent`, other values count nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// Loads retired, including atomics other than `sc`.
+    Load = 1,
+    /// Stores retired, including atomics other than `lr`.
+    Store = 2,
+    /// Conditional branches retired.
+    Branch = 3,
+    /// Conditional branches retired which were taken.
+    TakenBranch = 4,
+    /// Exceptions raised.
+    Exception = 5,
+    /// Misses of the instruction cache model.
+    ICacheMiss = 6,
+    /// Misses of the data cache model.
+    DCacheMiss = 7,
+    /// Interrupts taken.
+    Interrupt = 8,
+    /// Fetches which missed the TLB and walked the page tables.
+    ITlbMiss = 9,
+    /// Loads and stores which missed the TLB and walked the page tables.
+    DTlbMiss = 10,
+    /// `sfence.vma`s executed.
+    TlbFence = 11,
+}
+
+const EVENTS: usize = 12;
+
+/// Which counters select each event, kept in sync with the `mhpmevent` csrs.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Hpm {
+    /// Bit `i` of entry `e` is set when counter `3 + i` counts event `e`.
+    counters: [u32; EVENTS],
+}
+
+impl Hpm {
+    /// Rebuilds the selection from the `mhpmevent` values in `csrs`, e.g.
+    /// after restoring them from a snapshot.
+    pub fn from_csrs(csrs: &[u64]) -> Self {
+        let mut hpm = Self::default();
+        for i in 0..HPM_COUNTERS {
+            hpm.select(i, csrs[MHPMEVENT3 + i]);
+        }
+        hpm
+    }
+
+    /// Makes counter `3 + index` count `event`.
+    pub fn select(&mut self, index: usize, event: u64) {
+        for counters in &mut self.counters {
+            *counters &= !(1 << index);
+        }
+        if let Some(counters) = self.counters.get_mut(event as usize).filter(|_| event != 0) {
+            *counters |= 1 << index;
+        }
+    }
+
+    /// Whether any counter counts anything.
+    pub fn is_active(&self) -> bool {
+        self.counters.iter().any(|x| *x != 0)
+    }
+
+    /// Counters selecting `event`, as indexes from counter 3.
+    pub fn counters(&self, event: Event) -> impl Iterator<Item = usize> {
+        let mask = self.counters[event as usize];
+        (0..HPM_COUNTERS).filter(move |i| mask & (1 << i) != 0)
+    }
+}
@@ -0,0 +1,30 @@
+/// The common fields of a 32-bit instruction. Which of them are meaningful
+/// depends on the format of the instruction. Compressed instructions are
+/// decoded from their 32-bit expansion, see [`crate::compressed`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Instruction {
+    pub raw: u64,
+    pub opcode: u64,
+    pub rd: usize,
+    pub rs1: usize,
+    pub rs2: usize,
+    pub funct3: u64,
+    pub funct7: u64,
+    /// Size in bytes, 2 for a compressed instruction.
+    pub len: u64,
+}
+
+impl Instruction {
+    pub fn decode(inst: u64) -> Self {
+        Self {
+            raw: inst,
+            opcode: inst & 0x7f,
+            rd: ((inst >> 7) & 0x1f) as usize,
+            rs1: ((inst >> 15) & 0x1f) as usize,
+            rs2: ((inst >> 20) & 0x1f) as usize,
+            funct3: (inst >> 12) & 0x7,
+            funct7: (inst >> 25) & 0x7f,
+            len: 4,
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+
+use thiserror::Error;
+
+/// Width of the integer registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    Rv32,
+    Rv64,
+}
+
+impl Xlen {
+    pub fn bits(self) -> u32 {
+        match self {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        }
+    }
+}
+
+/// The ISA extensions the emulator knows how to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+    I,
+    M,
+    A,
+    F,
+    D,
+    C,
+    V,
+    Zicsr,
+    Zicntr,
+    Zicond,
+    Zifencei,
+    Zicbom,
+    Zicboz,
+    /// The `pause` hint, a fence otherwise.
+    Zihintpause,
+    /// Wait on reservation set, `wrs.nto` and `wrs.sto`.
+    Zawrs,
+    /// The multiplications of M, without the divisions.
+    Zmmul,
+    Zfh,
+    Zba,
+    Zbb,
+    Zbs,
+    Zbkb,
+    Zbkc,
+    Zbkx,
+    Zknd,
+    Zkne,
+    Zknh,
+    Zksed,
+    Zksh,
+}
+
+impl Extension {
+    /// Single letter extensions, in canonical order.
+    const LETTERS: &'static [(char, Extension)] = &[
+        ('i', Extension::I),
+        ('m', Extension::M),
+        ('a', Extension::A),
+        ('f', Extension::F),
+        ('d', Extension::D),
+        ('c', Extension::C),
+        ('v', Extension::V),
+    ];
+
+    /// Multi letter extensions, as written after an underscore.
+    const NAMED: &'static [(&'static str, Extension)] = &[
+        ("zicsr", Extension::Zicsr),
+        ("zicntr", Extension::Zicntr),
+        ("zicond", Extension::Zicond),
+        ("zifencei", Extension::Zifencei),
+        ("zicbom", Extension::Zicbom),
+        ("zicboz", Extension::Zicboz),
+        ("zihintpause", Extension::Zihintpause),
+        ("zawrs", Extension::Zawrs),
+        ("zmmul", Extension::Zmmul),
+        ("zfh", Extension::Zfh),
+        ("zba", Extension::Zba),
+        ("zbb", Extension::Zbb),
+        ("zbs", Extension::Zbs),
+        ("zbkb", Extension::Zbkb),
+        ("zbkc", Extension::Zbkc),
+        ("zbkx", Extension::Zbkx),
+        ("zknd", Extension::Zknd),
+        ("zkne", Extension::Zkne),
+        ("zknh", Extension::Zknh),
+        ("zksed", Extension::Zksed),
+        ("zksh", Extension::Zksh),
+    ];
+
+    /// Extensions implemented for RV32 as well, the rest assume RV64.
+    const RV32: &'static [Extension] = &[
+        Extension::I,
+        Extension::M,
+        Extension::A,
+        Extension::C,
+        Extension::Zicsr,
+        Extension::Zicntr,
+        Extension::Zicond,
+        Extension::Zifencei,
+        Extension::Zihintpause,
+        Extension::Zawrs,
+        Extension::Zmmul,
+        Extension::Zbkb,
+        Extension::Zbkc,
+        Extension::Zbkx,
+        Extension::Zknd,
+        Extension::Zkne,
+        Extension::Zknh,
+        Extension::Zksed,
+        Extension::Zksh,
+    ];
+
+    fn bit(self) -> u64 {
+        1 << self as u64
+    }
+}
+
+/// The `misa` bit of the extension named `letter`.
+fn misa_bit(letter: char) -> u64 {
+    1 << (letter as u8 - b'a')
+}
+
+/// An ISA configuration, e.g. `rv64ima_zicsr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Isa {
+    pub xlen: Xlen,
+    extensions: u64,
+    /// The extensions configured, which writes to `misa` turn on and off.
+    configured: u64,
+}
+
+impl Isa {
+    pub fn has(&self, ext: Extension) -> bool {
+        self.extensions & ext.bit() != 0
+    }
+
+    pub fn with(mut self, ext: Extension) -> Self {
+        self.extensions |= ext.bit();
+        self.configured |= ext.bit();
+        self
+    }
+
+    pub fn without(mut self, ext: Extension) -> Self {
+        self.extensions &= !ext.bit();
+        self.configured &= !ext.bit();
+        self
+    }
+
+    /// The value of `misa`: MXL, a bit per single letter extension enabled,
+    /// B for Zba, Zbb and Zbs together, and S and U as the harts have both
+    /// modes.
+    pub fn misa(&self) -> u64 {
+        let mxl = match self.xlen {
+            Xlen::Rv32 => 1 << 30,
+            Xlen::Rv64 => 2 << 62,
+        };
+        let letters = Extension::LETTERS
+            .iter()
+            .filter(|(_, e)| self.has(*e))
+            .fold(0, |misa, (c, _)| misa | misa_bit(*c));
+        let b = [Extension::Zba, Extension::Zbb, Extension::Zbs]
+            .iter()
+            .all(|e| self.has(*e));
+        let b = if b { misa_bit('b') } else { 0 };
+        mxl | letters | b | misa_bit('s') | misa_bit('u')
+    }
+
+    /// The ISA a write of `value` to `misa` leaves: the single letter
+    /// extensions configured, but I, are enabled by their bits, the rest of
+    /// `value` is ignored. None if it would enable D without F.
+    pub fn write_misa(&self, value: u64) -> Option<Self> {
+        let mut isa = *self;
+        for (c, ext) in Extension::LETTERS {
+            if *ext == Extension::I || self.configured & ext.bit() == 0 {
+                continue;
+            }
+            match value & misa_bit(*c) != 0 {
+                true => isa.extensions |= ext.bit(),
+                false => isa.extensions &= !ext.bit(),
+            }
+        }
+        (!isa.has(Extension::D) || isa.has(Extension::F)).then_some(isa)
+    }
+}
+
+impl Default for Isa {
+    /// Everything currently implemented.
+    fn default() -> Self {
+        "rv64imafdcv_zicsr_zicntr_zicond_zifencei_zicbom_zicboz_zihintpause_zawrs_zfh_zba_zbb_zbs_zkn_zks"
+            .parse()
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum IsaError {
+    /// The string doesn't start with a supported `rvXX` base.
+    #[error("unsupported base isa in '{0}'")]
+    UnsupportedBase(String),
+    /// The base integer extension is missing.
+    #[error("isa string must include the 'i' base")]
+    MissingBase,
+    #[error("unsupported extension '{0}'")]
+    UnsupportedExtension(String),
+    #[error("extension '{0}' isn't supported on rv32")]
+    UnsupportedOnRv32(String),
+}
+
+impl FromStr for Isa {
+    type Err = IsaError;
+
+    /// Parses an ISA string such as `rv64imac_zicsr_zicond` or `rv32imc`. `g` expands to
+    /// `imafd_zicsr_zifencei`, `zkn` to `zbkb_zbkc_zbkx_zkne_zknd_zknh` and
+    /// `zks` to `zbkb_zbkc_zbkx_zksed_zksh`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let (xlen, rest) = match (lower.strip_prefix("rv32"), lower.strip_prefix("rv64")) {
+            (Some(rest), _) => (Xlen::Rv32, rest),
+            (_, Some(rest)) => (Xlen::Rv64, rest),
+            _ => return Err(IsaError::UnsupportedBase(s.to_string())),
+        };
+
+        let mut isa = Isa {
+            xlen,
+            extensions: 0,
+            configured: 0,
+        };
+
+        let mut parts = rest.split('_');
+        let letters = parts.next().unwrap_or_default();
+
+        for c in letters.chars() {
+            let expanded: &[&str] = match c {
+                'g' => &["i", "m", "a", "f", "d", "zicsr", "zifencei"],
+                _ => &[],
+            };
+
+            if !expanded.is_empty() {
+                for name in expanded {
+                    isa = isa.with(parse_extension(name)?);
+                }
+                continue;
+            }
+
+            let ext = Extension::LETTERS
+                .iter()
+                .find(|(l, _)| *l == c)
+                .map(|(_, e)| *e)
+                .ok_or_else(|| IsaError::UnsupportedExtension(c.to_string()))?;
+            isa = isa.with(ext);
+        }
+
+        for name in parts.filter(|x| !x.is_empty()) {
+            let expanded: &[&str] = match name {
+                "zkn" => &["zbkb", "zbkc", "zbkx", "zkne", "zknd", "zknh"],
+                "zks" => &["zbkb", "zbkc", "zbkx", "zksed", "zksh"],
+                _ => core::slice::from_ref(&name),
+            };
+            for name in expanded {
+                isa = isa.with(parse_extension(name)?);
+            }
+        }
+
+        if !isa.has(Extension::I) {
+            return Err(IsaError::MissingBase);
+        }
+
+        if isa.xlen == Xlen::Rv32 {
+            let unsupported = Extension::LETTERS
+                .iter()
+                .map(|(c, e)| (c.to_string(), *e))
+                .chain(Extension::NAMED.iter().map(|(n, e)| (n.to_string(), *e)))
+                .find(|(_, e)| isa.has(*e) && !Extension::RV32.contains(e));
+            if let Some((name, _)) = unsupported {
+                return Err(IsaError::UnsupportedOnRv32(name));
+            }
+        }
+
+        Ok(isa)
+    }
+}
+
+fn parse_extension(name: &str) -> Result<Extension, IsaError> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if let Some((_, e)) = Extension::LETTERS.iter().find(|(l, _)| *l == c) {
+            return Ok(*e);
+        }
+    }
+
+    Extension::NAMED
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, e)| *e)
+        .ok_or_else(|| IsaError::UnsupportedExtension(name.to_string()))
+}
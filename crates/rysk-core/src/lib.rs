@@ -0,0 +1,32 @@
+//! The emulator core: decoder, executor and memory model.
+//!
+//! This crate is `no_std` + `alloc` when built without the default `std`
+//! feature, so it doesn't touch the host beyond what a [`Clock`](time::Clock)
+//! provides. Host devices, machine construction and the CLI live in `rysk`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod bus;
+pub mod cache;
+pub mod compressed;
+pub mod cpu;
+pub mod crypto;
+pub mod csr;
+pub mod disasm;
+pub mod dram;
+pub mod error;
+pub mod exception;
+pub mod fpu;
+pub mod hart;
+pub mod hooks;
+pub mod hpm;
+pub mod instruction;
+pub mod isa;
+pub mod mmu;
+pub mod observer;
+pub mod pmp;
+pub mod time;
+pub mod timing;
+pub mod trigger;
+pub mod vector;
@@ -0,0 +1,513 @@
+//! Address translation through the Sv39, Sv48 and Sv57 page tables `satp`
+//! selects. Machine mode accesses, and every access while `satp.MODE` is
+//! bare, use physical addresses. Walks of the page tables are cached in a
+//! [`Tlb`]. A page without the accessed bit, or the dirty bit for a store,
+//! either raises a page fault for the software to set them or has them set
+//! by the walk, as Svadu does, see [`AdPolicy`].
+
+use alloc::{collections::BTreeMap, format, string::String};
+use core::{fmt, str::FromStr};
+
+use crate::{
+    cpu::{Cpu, Mode, MSTATUS, SATP, STATUS_MPRV, STATUS_MXR, STATUS_SUM},
+    exception::Exception,
+    hpm::Event,
+    isa::Xlen,
+};
+
+/// What a translated access does, which selects the permission it needs and
+/// the exception it raises.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Fetch,
+    Load,
+    /// Stores and atomics.
+    Store,
+}
+
+impl Access {
+    fn page_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionPageFault(addr),
+            Access::Load => Exception::LoadPageFault(addr),
+            Access::Store => Exception::StoreAmoPageFault(addr),
+        }
+    }
+
+    pub(crate) fn access_fault(self, addr: u64) -> Exception {
+        match self {
+            Access::Fetch => Exception::InstructionAccessFault(addr),
+            Access::Load => Exception::LoadAccessFault(addr),
+            Access::Store => Exception::StoreAmoAccessFault(addr),
+        }
+    }
+}
+
+/// What an access to a page without the accessed bit, or without the dirty
+/// bit for a store, does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AdPolicy {
+    /// Raises a page fault, the handler sets the bits.
+    #[default]
+    Fault,
+    /// Sets the bits in the page table entry, as Svadu does.
+    Update,
+}
+
+impl FromStr for AdPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fault" => Ok(Self::Fault),
+            "update" => Ok(Self::Update),
+            _ => Err(format!(
+                "unknown accessed/dirty policy {s}, expected fault or update"
+            )),
+        }
+    }
+}
+
+/// The translation schemes `satp.MODE` selects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Bare,
+    Sv39,
+    Sv48,
+    Sv57,
+}
+
+impl Scheme {
+    /// The scheme `satp.MODE` encodes, none for the ones not implemented.
+    pub fn from_mode(mode: u64) -> Option<Self> {
+        match mode {
+            0 => Some(Scheme::Bare),
+            8 => Some(Scheme::Sv39),
+            9 => Some(Scheme::Sv48),
+            10 => Some(Scheme::Sv57),
+            _ => None,
+        }
+    }
+
+    /// Levels of page table, 9 bits of virtual page number each.
+    pub fn levels(self) -> u32 {
+        match self {
+            Scheme::Bare => 0,
+            Scheme::Sv39 => 3,
+            Scheme::Sv48 => 4,
+            Scheme::Sv57 => 5,
+        }
+    }
+}
+
+/// Bits of a page offset.
+const PAGE_SHIFT: u32 = 12;
+pub(crate) const PAGE_SIZE: u64 = 1 << PAGE_SHIFT;
+const PTE_SIZE: u64 = 8;
+
+/// Fields of a page table entry.
+const PTE_V: u64 = 1 << 0;
+const PTE_R: u64 = 1 << 1;
+const PTE_W: u64 = 1 << 2;
+const PTE_X: u64 = 1 << 3;
+const PTE_U: u64 = 1 << 4;
+const PTE_G: u64 = 1 << 5;
+const PTE_A: u64 = 1 << 6;
+const PTE_D: u64 = 1 << 7;
+/// The bits past the physical page number, reserved without Svpbmt and
+/// Svnapot.
+const PTE_RESERVED: u64 = 0x3ff << 54;
+
+/// Fields of an RV64 `satp`, the mode is bits 63:60.
+const SATP_PPN: u64 = (1 << 44) - 1;
+const SATP_ASID_SHIFT: u32 = 44;
+const SATP_ASID: u64 = 0xffff;
+
+/// The fields of `satp`: the translation scheme, the address space id the
+/// TLB entries are tagged with and the physical page of the root table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Satp {
+    pub scheme: Scheme,
+    pub asid: u64,
+    pub ppn: u64,
+}
+
+impl Satp {
+    /// Decodes `value`, none if it selects a scheme which isn't implemented.
+    /// RV32 has none but bare, the ASID is bits 30:22 and the page 21:0.
+    pub fn decode(value: u64, xlen: Xlen) -> Option<Self> {
+        match xlen {
+            Xlen::Rv32 => (value & (1 << 31) == 0).then_some(Satp {
+                scheme: Scheme::Bare,
+                asid: (value >> 22) & 0x1ff,
+                ppn: value & 0x3f_ffff,
+            }),
+            Xlen::Rv64 => Some(Satp {
+                scheme: Scheme::from_mode(value >> 60)?,
+                asid: (value >> SATP_ASID_SHIFT) & SATP_ASID,
+                ppn: value & SATP_PPN,
+            }),
+        }
+    }
+}
+
+/// Entries of the [`Tlb`].
+pub const TLB_ENTRIES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TlbEntry {
+    asid: u64,
+    /// Mapped in every address space, whatever the ASID.
+    global: bool,
+    vpn: u64,
+    /// The low bits of `vpn` within the superpage of the mapping, 0 for a
+    /// 4 KiB page.
+    superpage: u64,
+    /// The physical page `vpn` maps to, a 4 KiB one within a superpage.
+    ppn: u64,
+    /// The leaf entry, for its permissions, and its physical address.
+    pte: u64,
+    pte_addr: u64,
+}
+
+/// Hits and misses of the translations of one kind of access.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TlbCounts {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// The iTLB and dTLB counts of one address space.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AsidCounts {
+    pub itlb: TlbCounts,
+    pub dtlb: TlbCounts,
+}
+
+/// Translations of the last walks, direct mapped on the virtual page number
+/// and tagged with the ASID, so switching address spaces doesn't flush it.
+/// Permissions are checked again on every hit, as the privilege level, `SUM`
+/// and `MXR` change without a fence. `sfence.vma` and writes to `satp`
+/// changing the scheme flush it.
+///
+/// Fetches and data accesses share the entries, but are counted apart as the
+/// iTLB and the dTLB.
+#[derive(Debug, Clone)]
+pub struct Tlb {
+    entries: [Option<TlbEntry>; TLB_ENTRIES],
+    /// Translations of fetches.
+    pub itlb: TlbCounts,
+    /// Translations of loads and stores.
+    pub dtlb: TlbCounts,
+    /// `sfence.vma`s executed.
+    pub fences: u64,
+    /// The `sfence.vma`s limited to an address space, by ASID.
+    pub asid_fences: BTreeMap<u64, u64>,
+    /// The translations of each address space, by ASID.
+    pub asids: BTreeMap<u64, AsidCounts>,
+}
+
+impl Default for Tlb {
+    fn default() -> Self {
+        Self {
+            entries: [None; TLB_ENTRIES],
+            itlb: TlbCounts::default(),
+            dtlb: TlbCounts::default(),
+            fences: 0,
+            asid_fences: BTreeMap::new(),
+            asids: BTreeMap::new(),
+        }
+    }
+}
+
+impl Tlb {
+    fn lookup(&self, asid: u64, vpn: u64) -> Option<TlbEntry> {
+        self.entries[vpn as usize % TLB_ENTRIES]
+            .filter(|x| (x.global || x.asid == asid) && x.vpn == vpn)
+    }
+
+    fn insert(&mut self, entry: TlbEntry) {
+        self.entries[entry.vpn as usize % TLB_ENTRIES] = Some(entry);
+    }
+
+    /// Drops every entry, keeping the counters.
+    pub fn flush(&mut self) {
+        self.entries = [None; TLB_ENTRIES];
+    }
+
+    /// Drops the entries mapping the virtual page `vpn` and those of the
+    /// address space `asid`, all of them for none. The global mappings stay
+    /// when only some address space is flushed.
+    pub fn invalidate(&mut self, vpn: Option<u64>, asid: Option<u64>) {
+        for slot in &mut self.entries {
+            let Some(entry) = slot else {
+                continue;
+            };
+            let page = vpn.is_none_or(|x| x & !entry.superpage == entry.vpn & !entry.superpage);
+            let space = asid.is_none_or(|x| !entry.global && x == entry.asid);
+            if page && space {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Counts a hit or a miss of `access` in the address space `asid`.
+    fn record(&mut self, asid: u64, access: Access, hit: bool) {
+        let space = self.asids.entry(asid).or_default();
+        let (total, space) = match access {
+            Access::Fetch => (&mut self.itlb, &mut space.itlb),
+            Access::Load | Access::Store => (&mut self.dtlb, &mut space.dtlb),
+        };
+        for counts in [total, space] {
+            match hit {
+                true => counts.hits += 1,
+                false => counts.misses += 1,
+            }
+        }
+    }
+
+    /// Hits of both the iTLB and the dTLB.
+    pub fn hits(&self) -> u64 {
+        self.itlb.hits + self.dtlb.hits
+    }
+
+    /// Misses of both the iTLB and the dTLB.
+    pub fn misses(&self) -> u64 {
+        self.itlb.misses + self.dtlb.misses
+    }
+
+    pub fn accesses(&self) -> u64 {
+        self.hits() + self.misses()
+    }
+
+    /// Fraction of the translations which hit, 0 before any.
+    pub fn hit_rate(&self) -> f64 {
+        match self.accesses() {
+            0 => 0.0,
+            n => self.hits() as f64 / n as f64,
+        }
+    }
+}
+
+impl fmt::Display for Tlb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} translations, {} hits, {} misses ({:.2}% hit rate), \
+             itlb {} hits, {} misses, dtlb {} hits, {} misses, {} sfence.vma",
+            self.accesses(),
+            self.hits(),
+            self.misses(),
+            self.hit_rate() * 100.0,
+            self.itlb.hits,
+            self.itlb.misses,
+            self.dtlb.hits,
+            self.dtlb.misses,
+            self.fences,
+        )?;
+        for (i, (asid, fences)) in self.asid_fences.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{sep}{fences} for asid {asid}")?;
+        }
+        if !self.asid_fences.is_empty() {
+            write!(f, ")")?;
+        }
+        for (asid, counts) in &self.asids {
+            write!(
+                f,
+                ", asid {asid} itlb {} hits, {} misses, dtlb {} hits, {} misses",
+                counts.itlb.hits, counts.itlb.misses, counts.dtlb.hits, counts.dtlb.misses,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The virtual page number of `addr` under `scheme`, without the bits past
+/// the virtual address.
+fn virtual_page(addr: u64, scheme: Scheme) -> u64 {
+    let va_bits = PAGE_SHIFT + 9 * scheme.levels();
+    (addr & ((1 << va_bits) - 1)) >> PAGE_SHIFT
+}
+
+/// The accessed and dirty bits `access` needs set.
+fn tracking(access: Access) -> u64 {
+    match access {
+        Access::Store => PTE_A | PTE_D,
+        _ => PTE_A,
+    }
+}
+
+/// Whether the leaf `pte` allows `access` at `mode`.
+fn permitted(pte: u64, access: Access, mode: Mode, mstatus: u64) -> bool {
+    let allowed = match access {
+        Access::Fetch => pte & PTE_X != 0,
+        Access::Load => pte & PTE_R != 0 || (mstatus & STATUS_MXR != 0 && pte & PTE_X != 0),
+        Access::Store => pte & PTE_W != 0,
+    };
+    let privileged = match mode {
+        Mode::User => pte & PTE_U != 0,
+        // The supervisor only reaches user pages with SUM, and never
+        // executes them.
+        _ => pte & PTE_U == 0 || (access != Access::Fetch && mstatus & STATUS_SUM != 0),
+    };
+    allowed && privileged
+}
+
+impl Cpu {
+    /// The fields of `satp`, which writes keep to the implemented schemes.
+    pub fn satp(&self) -> Satp {
+        Satp::decode(self.csrs[SATP], self.isa.xlen).unwrap_or(Satp {
+            scheme: Scheme::Bare,
+            asid: 0,
+            ppn: 0,
+        })
+    }
+
+    /// The translation scheme `satp` selects.
+    pub fn scheme(&self) -> Scheme {
+        self.satp().scheme
+    }
+
+    /// Writes `satp`, ignoring values selecting a scheme which isn't
+    /// implemented. Changing the scheme flushes the TLB, changing the address
+    /// space doesn't.
+    pub(crate) fn store_satp(&mut self, value: u64) {
+        let Some(satp) = Satp::decode(value, self.isa.xlen) else {
+            return;
+        };
+        if satp.scheme != self.scheme() {
+            self.tlb.flush();
+        }
+        self.csrs[SATP] = value;
+    }
+
+    /// The privilege level `access` is made at: loads and stores of machine
+    /// mode take the one of `mstatus.MPP` when `mstatus.MPRV` is set.
+    pub(crate) fn effective_mode(&self, access: Access) -> Mode {
+        let mstatus = self.csrs[MSTATUS];
+        match access {
+            Access::Load | Access::Store
+                if self.mode == Mode::Machine && mstatus & STATUS_MPRV != 0 =>
+            {
+                Mode::from_bits((mstatus >> 11) & 0b11).unwrap_or(Mode::User)
+            }
+            _ => self.mode,
+        }
+    }
+
+    /// Translates the virtual address `addr` of `access` to a physical one.
+    pub fn translate(&mut self, addr: u64, access: Access) -> Result<u64, Exception> {
+        let mode = self.effective_mode(access);
+        let satp = self.satp();
+        let scheme = satp.scheme;
+        if mode == Mode::Machine || scheme == Scheme::Bare {
+            return Ok(addr);
+        }
+
+        // The bits past the virtual address are copies of its top bit.
+        let va_bits = PAGE_SHIFT + 9 * scheme.levels();
+        let top = (addr as i64) >> (va_bits - 1);
+        if top != 0 && top != -1 {
+            return Err(access.page_fault(addr));
+        }
+
+        let mstatus = self.csrs[MSTATUS];
+        let vpn = virtual_page(addr, scheme);
+        let offset = addr & ((1 << PAGE_SHIFT) - 1);
+        let tracked = tracking(access);
+        if let Some(entry) = self.tlb.lookup(satp.asid, vpn) {
+            if permitted(entry.pte, access, mode, mstatus) && entry.pte & tracked == tracked {
+                self.tlb.record(satp.asid, access, true);
+                return Ok((entry.ppn << PAGE_SHIFT) | offset);
+            }
+        }
+        self.tlb.record(satp.asid, access, false);
+        self.count(match access {
+            Access::Fetch => Event::ITlbMiss,
+            Access::Load | Access::Store => Event::DTlbMiss,
+        });
+
+        let mut entry = self.walk(addr, access, satp)?;
+        if !permitted(entry.pte, access, mode, mstatus) {
+            return Err(access.page_fault(addr));
+        }
+        if entry.pte & tracked != tracked {
+            if self.ad_policy == AdPolicy::Fault {
+                return Err(access.page_fault(addr));
+            }
+            // the walk's store is checked by PMP as the supervisor's
+            if !self.pmp_allows(entry.pte_addr, PTE_SIZE, Access::Store, Mode::Supervisor) {
+                return Err(access.access_fault(addr));
+            }
+            entry.pte |= tracked;
+            self.bus
+                .store(entry.pte_addr, 64, entry.pte)
+                .map_err(|_| access.access_fault(addr))?;
+        }
+        self.tlb.insert(entry);
+        Ok((entry.ppn << PAGE_SHIFT) | offset)
+    }
+
+    /// Flushes the translations of `addr` and those of the address space
+    /// `asid`, as `sfence.vma` does, all of them for none.
+    pub fn sfence_vma(&mut self, addr: Option<u64>, asid: Option<u64>) {
+        let scheme = self.scheme();
+        let asid = asid.map(|x| x & SATP_ASID);
+        self.tlb.fences += 1;
+        if let Some(asid) = asid {
+            *self.tlb.asid_fences.entry(asid).or_default() += 1;
+        }
+        self.count(Event::TlbFence);
+        self.tlb
+            .invalidate(addr.map(|x| virtual_page(x, scheme)), asid);
+    }
+
+    /// Walks the page tables for `addr`, returning the mapping of its 4 KiB
+    /// page. It is global if an entry on the way is.
+    fn walk(&mut self, addr: u64, access: Access, satp: Satp) -> Result<TlbEntry, Exception> {
+        let mut table = satp.ppn << PAGE_SHIFT;
+        let mut global = false;
+        for level in (0..satp.scheme.levels()).rev() {
+            let shift = PAGE_SHIFT + 9 * level;
+            let vpn = (addr >> shift) & 0x1ff;
+            // the walk is checked by PMP as supervisor loads
+            let pte_addr = table + vpn * PTE_SIZE;
+            if !self.pmp_allows(pte_addr, PTE_SIZE, Access::Load, Mode::Supervisor) {
+                return Err(access.access_fault(addr));
+            }
+            let pte = self
+                .bus
+                .load(pte_addr, 64)
+                .map_err(|_| access.access_fault(addr))?;
+
+            if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) || pte & PTE_RESERVED != 0
+            {
+                return Err(access.page_fault(addr));
+            }
+            let ppn = (pte >> 10) & SATP_PPN;
+            global |= pte & PTE_G != 0;
+            if pte & (PTE_R | PTE_X) == 0 {
+                // a pointer to the next level
+                table = ppn << PAGE_SHIFT;
+                continue;
+            }
+
+            // A superpage is aligned on its size.
+            let pages = (1 << (9 * level)) - 1;
+            if ppn & pages != 0 {
+                return Err(access.page_fault(addr));
+            }
+            return Ok(TlbEntry {
+                asid: satp.asid,
+                global,
+                vpn: virtual_page(addr, satp.scheme),
+                superpage: pages,
+                ppn: ppn | ((addr >> PAGE_SHIFT) & pages),
+                pte,
+                pte_addr,
+            });
+        }
+        Err(access.page_fault(addr))
+    }
+}
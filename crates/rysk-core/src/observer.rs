@@ -0,0 +1,93 @@
+use alloc::{boxed::Box, vec::Vec};
+use core::{any::Any, fmt};
+
+use crate::{
+    exception::{Exception, Interrupt},
+    instruction::Instruction,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A memory access that was routed to a device rather than dram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmioAccess {
+    pub addr: u64,
+    /// Access width in bits.
+    pub size: u64,
+    /// Value loaded or stored.
+    pub value: u64,
+    pub kind: AccessKind,
+}
+
+/// Instrumentation driven by the [`Cpu`](crate::cpu::Cpu). Every callback
+/// defaults to doing nothing, so observers only implement what they need.
+///
+/// Observers are owned by the cpu; use [`Observers::get`] to get one back, e.g. to read collected statistics after a run.
+pub trait ExecutionObserver: Any + Send {
+    /// An instruction at `pc` retired.
+    fn on_instruction(&mut self, _pc: u64, _inst: &Instruction) {}
+
+    /// The instruction which just retired took `cycles` cycles, as counted
+    /// in `mcycle` including the stalls of the cache and pipeline models.
+    fn on_cycles(&mut self, _cycles: u64) {}
+
+    /// The instruction at `pc` raised an exception.
+    fn on_trap(&mut self, _pc: u64, _exception: &Exception) {}
+
+    /// An interrupt was taken while about to execute `pc`.
+    fn on_interrupt(&mut self, _pc: u64, _interrupt: Interrupt) {}
+
+    /// A device raised or lowered the line of `interrupt`, see
+    /// [`Cpu::set_pending`](crate::cpu::Cpu::set_pending).
+    fn on_pending(&mut self, _interrupt: Interrupt, _pending: bool) {}
+
+    /// A data load or store of `size` bits at `addr` succeeded, to dram or
+    /// a device. Instruction fetches aren't reported.
+    fn on_memory(&mut self, _addr: u64, _size: u64, _kind: AccessKind) {}
+
+    fn on_mmio(&mut self, _access: &MmioAccess) {}
+
+    /// A csr was read or written, with the value read or written.
+    fn on_csr_access(&mut self, _csr: usize, _value: u64, _kind: AccessKind) {}
+}
+
+/// The observers attached to a cpu, driven in registration order.
+#[derive(Default)]
+pub struct Observers(Vec<Box<dyn ExecutionObserver>>);
+
+impl Observers {
+    pub fn add(&mut self, observer: impl ExecutionObserver) {
+        self.0.push(Box::new(observer));
+    }
+
+    /// Returns the first registered observer of type `T`.
+    pub fn get<T: ExecutionObserver>(&self) -> Option<&T> {
+        self.0
+            .iter()
+            .find_map(|x| (x.as_ref() as &dyn Any).downcast_ref())
+    }
+
+    pub fn get_mut<T: ExecutionObserver>(&mut self) -> Option<&mut T> {
+        self.0
+            .iter_mut()
+            .find_map(|x| (x.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn iter_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn ExecutionObserver>> {
+        self.0.iter_mut()
+    }
+}
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Observers").field(&self.0.len()).finish()
+    }
+}
@@ -0,0 +1,168 @@
+//! Physical memory protection: the `pmpcfg` and `pmpaddr` csrs describe up
+//! to 64 regions of physical memory, each with the accesses it allows. The
+//! lowest numbered region holding any byte of an access decides it, and must
+//! hold all of them. Regions only restrict machine mode when locked.
+//!
+//! As long as no region is enabled every access is allowed, so programs not
+//! setting up PMP run below machine mode unrestricted. Once one is, the
+//! accesses below machine mode no region matches fail.
+
+use crate::{
+    cpu::{Cpu, Mode},
+    exception::Exception,
+    isa::Xlen,
+    mmu::Access,
+};
+
+/// `pmpcfg0`, the others follow. RV64 only has the even ones, each holding
+/// the configuration of 8 entries.
+pub const PMPCFG0: usize = 0x3A0;
+pub const PMPCFG_CSRS: usize = 16;
+/// `pmpaddr0`, the others follow.
+pub const PMPADDR0: usize = 0x3B0;
+pub const PMP_ENTRIES: usize = 64;
+
+/// Fields of an entry's configuration.
+const PMP_R: u8 = 1 << 0;
+const PMP_W: u8 = 1 << 1;
+const PMP_X: u8 = 1 << 2;
+const PMP_A: u8 = 0b11 << 3;
+const PMP_L: u8 = 1 << 7;
+
+/// Address matching modes, from `PMP_A`, besides 0 for off.
+const PMP_TOR: u8 = 1;
+const PMP_NA4: u8 = 2;
+const PMP_NAPOT: u8 = 3;
+
+impl Cpu {
+    /// The csr and byte within it holding the configuration of `entry`.
+    fn pmpcfg_location(&self, entry: usize) -> (usize, usize) {
+        match self.isa.xlen {
+            Xlen::Rv32 => (PMPCFG0 + entry / 4, entry % 4),
+            Xlen::Rv64 => (PMPCFG0 + entry / 8 * 2, entry % 8),
+        }
+    }
+
+    fn pmpcfg(&self, entry: usize) -> u8 {
+        let (csr, byte) = self.pmpcfg_location(entry);
+        (self.csrs[csr] >> (8 * byte)) as u8
+    }
+
+    /// The byte range entry `entry` matches, none when it is off.
+    fn pmp_range(&self, entry: usize) -> Option<(u64, u64)> {
+        let addr = self.csrs[PMPADDR0 + entry];
+        match (self.pmpcfg(entry) & PMP_A) >> 3 {
+            PMP_TOR => {
+                let start = match entry {
+                    0 => 0,
+                    _ => self.csrs[PMPADDR0 + entry - 1] << 2,
+                };
+                Some((start, addr << 2))
+            }
+            PMP_NA4 => Some((addr << 2, (addr << 2) + 4)),
+            PMP_NAPOT => {
+                // the trailing ones encode the size, 8 bytes for none
+                let ones = addr.trailing_ones();
+                let start = (addr & !((1 << ones) - 1)) << 2;
+                Some((start, start + (8 << ones)))
+            }
+            // off
+            _ => None,
+        }
+    }
+
+    /// Whether `mode` may make `access` to the `bytes` at the physical
+    /// address `addr`.
+    pub(crate) fn pmp_allows(&self, addr: u64, bytes: u64, access: Access, mode: Mode) -> bool {
+        let configured = self.csrs[PMPCFG0..PMPCFG0 + PMPCFG_CSRS]
+            .iter()
+            .any(|x| *x != 0);
+        if !configured {
+            return true;
+        }
+
+        let end = addr.saturating_add(bytes);
+        let mut enabled = false;
+        for entry in 0..PMP_ENTRIES {
+            let Some((start, limit)) = self.pmp_range(entry) else {
+                continue;
+            };
+            enabled = true;
+            if end <= start || addr >= limit {
+                continue;
+            }
+            if addr < start || end > limit {
+                // some of the bytes only
+                return false;
+            }
+            let cfg = self.pmpcfg(entry);
+            if mode == Mode::Machine && cfg & PMP_L == 0 {
+                return true;
+            }
+            let needed = match access {
+                Access::Fetch => PMP_X,
+                Access::Load => PMP_R,
+                Access::Store => PMP_W,
+            };
+            return cfg & needed != 0;
+        }
+        mode == Mode::Machine || !enabled
+    }
+
+    /// Checks `access` to the `bytes` at the physical address `addr` at the
+    /// privilege level of the running hart, raising an access fault at the
+    /// virtual address `vaddr` if it isn't allowed.
+    pub fn pmp_check(
+        &self,
+        vaddr: u64,
+        addr: u64,
+        bytes: u64,
+        access: Access,
+    ) -> Result<(), Exception> {
+        match self.pmp_allows(addr, bytes, access, self.effective_mode(access)) {
+            true => Ok(()),
+            false => Err(access.access_fault(vaddr)),
+        }
+    }
+
+    /// Writes `pmpcfg` csr `addr`, leaving the entries which are locked, and
+    /// the odd csrs of RV64, which don't exist, as they were.
+    pub(crate) fn store_pmpcfg(&mut self, addr: usize, value: u64) {
+        let index = addr - PMPCFG0;
+        let entries = match self.isa.xlen {
+            Xlen::Rv32 => 4,
+            Xlen::Rv64 if index % 2 == 1 => return,
+            Xlen::Rv64 => 8,
+        };
+        let mut cfg = self.csrs[addr];
+        for byte in 0..entries {
+            let shift = 8 * byte;
+            if (cfg >> shift) as u8 & PMP_L != 0 {
+                continue;
+            }
+            let mut new = (value >> shift) as u8 & (PMP_R | PMP_W | PMP_X | PMP_A | PMP_L);
+            // write without read is reserved
+            if new & (PMP_R | PMP_W) == PMP_W {
+                new &= !PMP_W;
+            }
+            cfg = (cfg & !(0xff << shift)) | (u64::from(new) << shift);
+        }
+        self.csrs[addr] = cfg;
+    }
+
+    /// Writes `pmpaddr` csr `addr`, unless its entry is locked, or is the
+    /// start of a locked top of range entry.
+    pub(crate) fn store_pmpaddr(&mut self, addr: usize, value: u64) {
+        let entry = addr - PMPADDR0;
+        let locked = |e: usize| e < PMP_ENTRIES && self.pmpcfg(e) & PMP_L != 0;
+        let tor = |e: usize| e < PMP_ENTRIES && (self.pmpcfg(e) & PMP_A) >> 3 == PMP_TOR;
+        if locked(entry) || (locked(entry + 1) && tor(entry + 1)) {
+            return;
+        }
+        // bits 55:2 of a physical address, 33:2 on RV32
+        self.csrs[addr] = match self.isa.xlen {
+            Xlen::Rv32 => value & 0xffff_ffff,
+            Xlen::Rv64 => value & ((1 << 54) - 1),
+        };
+    }
+}
@@ -0,0 +1,41 @@
+/// Ticks of [`WallClock`] per second, the timebase frequency of QEMU virt.
+pub const TIMEBASE_FREQUENCY: u64 = 10_000_000;
+
+/// Source of the value read through the `time` csr.
+pub trait Clock: Send + core::fmt::Debug {
+    /// Current time, given the number of instructions retired so far.
+    fn now(&mut self, instret: u64) -> u64;
+}
+
+/// Deterministic clock ticking once per retired instruction.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct InstretClock;
+
+impl Clock for InstretClock {
+    fn now(&mut self, instret: u64) -> u64 {
+        instret
+    }
+}
+
+/// Host wall clock, in ticks of [`TIMEBASE_FREQUENCY`] since creation.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for WallClock {
+    fn default() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for WallClock {
+    fn now(&mut self, _instret: u64) -> u64 {
+        (self.start.elapsed().as_nanos() / (1_000_000_000 / u128::from(TIMEBASE_FREQUENCY))) as u64
+    }
+}
@@ -0,0 +1,141 @@
+//! Cycle accounting for a classic 5-stage in-order pipeline (fetch, decode,
+//! execute, memory, writeback) with full forwarding. Every instruction takes
+//! one cycle plus the stalls it causes:
+//!
+//! - a load followed by an instruction reading its result stalls for the
+//!   load-use penalty, as the value is only forwarded after the memory stage
+//! - taken branches and jumps flush the instructions fetched behind them
+//! - multiplications and divisions occupy the execute stage for their latency
+
+use alloc::{format, string::String};
+use core::{fmt, str::FromStr};
+
+use crate::{error::EmulatorError, instruction::Instruction};
+
+/// Extra cycles charged by the [`Pipeline`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub load_use: u64,
+    /// Cycles lost when a taken branch or a jump resolves in execute.
+    pub branch: u64,
+    pub mul: u64,
+    pub div: u64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            load_use: 1,
+            branch: 2,
+            mul: 2,
+            div: 32,
+        }
+    }
+}
+
+impl FromStr for PipelineConfig {
+    type Err = EmulatorError;
+
+    /// Parses comma separated `KEY=CYCLES` overrides of the defaults, e.g.
+    /// `branch=3,div=20`. The keys are `load-use`, `branch`, `mul` and `div`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for field in s.split(',').filter(|x| !x.is_empty()) {
+            let invalid =
+                || EmulatorError::InvalidTiming(format!("expected KEY=CYCLES, got {field}"));
+            let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+            let value = value.parse().map_err(|_| invalid())?;
+            match key {
+                "load-use" => config.load_use = value,
+                "branch" => config.branch = value,
+                "mul" => config.mul = value,
+                "div" => config.div = value,
+                _ => return Err(EmulatorError::InvalidTiming(String::from(key))),
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    pub config: PipelineConfig,
+    /// Destination of the previous instruction if it was a load.
+    load: Option<usize>,
+    /// Cycles lost to load-use hazards.
+    pub load_use_stalls: u64,
+    /// Cycles lost to taken branches and jumps.
+    pub branch_flushes: u64,
+    /// Cycles spent waiting for the multiplier and divider.
+    pub execute_stalls: u64,
+}
+
+impl Pipeline {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Cycles beyond the first taken by `inst` at `pc`, which retired and
+    /// continued to `next`.
+    pub fn retire(&mut self, inst: &Instruction, pc: u64, next: u64) -> u64 {
+        let (rs1, rs2) = sources(inst);
+        let mut cycles = 0;
+
+        if let Some(rd) = self.load.take() {
+            if rs1 == Some(rd) || rs2 == Some(rd) {
+                cycles += self.config.load_use;
+                self.load_use_stalls += self.config.load_use;
+            }
+        }
+        if matches!(inst.opcode, 0x03 | 0x2f) && inst.rd != 0 {
+            self.load = Some(inst.rd);
+        }
+
+        if matches!(inst.opcode, 0x63 | 0x67 | 0x6f) && next != pc.wrapping_add(inst.len) {
+            cycles += self.config.branch;
+            self.branch_flushes += self.config.branch;
+        }
+
+        if matches!(inst.opcode, 0x33 | 0x3b) && inst.funct7 == 0x01 {
+            let latency = if inst.funct3 < 4 {
+                self.config.mul
+            } else {
+                self.config.div
+            };
+            cycles += latency;
+            self.execute_stalls += latency;
+        }
+
+        cycles
+    }
+
+    /// Forgets the instructions in flight, e.g. when a trap is taken.
+    pub fn flush(&mut self) {
+        self.load = None;
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} load-use, {} branch and {} multiply/divide stall cycles",
+            self.load_use_stalls, self.branch_flushes, self.execute_stalls
+        )
+    }
+}
+
+/// Registers read by `inst`, `x0` excluded as it's never written.
+fn sources(inst: &Instruction) -> (Option<usize>, Option<usize>) {
+    let reg = |r: usize| (r != 0).then_some(r);
+    match inst.opcode {
+        0x33 | 0x3b | 0x23 | 0x63 | 0x2f => (reg(inst.rs1), reg(inst.rs2)),
+        0x03 | 0x13 | 0x1b | 0x67 => (reg(inst.rs1), None),
+        // csrrw, csrrs and csrrc, the immediate forms don't read rs1.
+        0x73 if (1..=3).contains(&inst.funct3) => (reg(inst.rs1), None),
+        _ => (None, None),
+    }
+}
@@ -0,0 +1,192 @@
+//! Sdtrig debug triggers: `tselect` picks one of [`TRIGGERS`] triggers,
+//! which `tdata1` and `tdata2` then configure. Each is an address match
+//! trigger (`mcontrol6`) firing on fetches, loads or stores at the address
+//! in `tdata2`, or one of its range, in the privilege levels it selects.
+//!
+//! A trigger fires before the access: the instruction raises a breakpoint
+//! exception with the address in `xtval`. In machine mode that only happens
+//! while `tcontrol.MTE` is set, which a trap into machine mode clears and
+//! `mret` restores, so the handler doesn't trigger itself. There is no debug
+//! mode, the action entering it instead stops whatever runs the cpu, see
+//! [`Cpu::trigger_halt`].
+
+use crate::{
+    cpu::{Cpu, Mode},
+    exception::Exception,
+    mmu::Access,
+};
+
+pub const TSELECT: usize = 0x7A0;
+pub const TDATA1: usize = 0x7A1;
+pub const TDATA2: usize = 0x7A2;
+/// Textra, which isn't implemented, so it reads as 0.
+pub const TDATA3: usize = 0x7A3;
+/// The trigger types `tdata1` accepts, and the version of the spec.
+pub const TINFO: usize = 0x7A4;
+/// Whether the triggers fire in machine mode, see the module.
+pub const TCONTROL: usize = 0x7A5;
+pub const TRIGGERS: usize = 4;
+
+/// Fields of [`TCONTROL`]: the machine mode enable, and its value before
+/// the last trap into machine mode.
+pub(crate) const TCONTROL_MTE: u64 = 1 << 3;
+pub(crate) const TCONTROL_MPTE: u64 = 1 << 7;
+
+/// `tdata1` types, in its top 4 bits.
+const TYPE_MCONTROL6: u64 = 6;
+const TYPE_DISABLED: u64 = 15;
+
+/// Fields of `mcontrol6`.
+const HIT0: u64 = 1 << 22;
+const ACTION: u64 = 0xf << 12;
+const MATCH: u64 = 0xf << 7;
+const M: u64 = 1 << 6;
+const S: u64 = 1 << 4;
+const U: u64 = 1 << 3;
+const EXECUTE: u64 = 1 << 2;
+const STORE: u64 = 1 << 1;
+const LOAD: u64 = 1 << 0;
+
+/// Actions, from [`ACTION`].
+const ACTION_BREAKPOINT: u64 = 0;
+const ACTION_DEBUG: u64 = 1;
+
+/// Address matching, from [`MATCH`]: `tdata2` itself, the naturally aligned
+/// power of two range its trailing ones encode, at or above it, below it.
+const MATCH_EQUAL: u64 = 0;
+const MATCH_NAPOT: u64 = 1;
+const MATCH_GE: u64 = 2;
+const MATCH_LT: u64 = 3;
+
+/// A trigger, as `tdata1` and `tdata2` configure it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Trigger {
+    /// The `mcontrol6` fields below the type, none while disabled.
+    pub control: Option<u64>,
+    /// The address compared against, `tdata2`.
+    pub address: u64,
+}
+
+impl Trigger {
+    /// Whether an access to the `bytes` at `addr` holds a matching byte.
+    fn matches(&self, control: u64, addr: u64, bytes: u64) -> bool {
+        let last = addr.wrapping_add(bytes - 1);
+        match (control & MATCH) >> 7 {
+            MATCH_EQUAL => (addr..=last).contains(&self.address),
+            MATCH_NAPOT => {
+                // all ones is the whole address space
+                let mask = 1u64
+                    .checked_shl(self.address.trailing_ones() + 1)
+                    .map_or(u64::MAX, |x| x - 1);
+                let start = self.address & !mask;
+                addr <= start | mask && last >= start
+            }
+            MATCH_GE => last >= self.address,
+            MATCH_LT => addr < self.address,
+            _ => false,
+        }
+    }
+}
+
+impl Cpu {
+    fn trigger_type_shift(&self) -> u64 {
+        u64::from(self.isa.xlen.bits()) - 4
+    }
+
+    /// Reads trigger csr `addr`, of the selected trigger for the `tdata`
+    /// ones.
+    pub(crate) fn load_trigger(&self, addr: usize) -> u64 {
+        match addr {
+            TDATA1 => self.load_tdata1(),
+            TDATA2 => self.triggers[self.csrs[TSELECT] as usize].address,
+            // version 1.0
+            TINFO => 1 << 24 | 1 << TYPE_MCONTROL6 | 1 << TYPE_DISABLED,
+            TSELECT | TCONTROL => self.csrs[addr],
+            _ => 0,
+        }
+    }
+
+    /// Writes trigger csr `addr`. Selecting a trigger which doesn't exist
+    /// leaves `tselect` as it was.
+    pub(crate) fn store_trigger(&mut self, addr: usize, value: u64) {
+        match addr {
+            TSELECT if (value as usize) < TRIGGERS => self.csrs[TSELECT] = value,
+            TDATA1 => self.store_tdata1(value),
+            TDATA2 => {
+                let address = self.unsigned(value);
+                self.triggers[self.csrs[TSELECT] as usize].address = address;
+            }
+            TCONTROL => self.csrs[TCONTROL] = value & (TCONTROL_MTE | TCONTROL_MPTE),
+            _ => {}
+        }
+    }
+
+    fn load_tdata1(&self) -> u64 {
+        let trigger = &self.triggers[self.csrs[TSELECT] as usize];
+        match trigger.control {
+            Some(control) => (TYPE_MCONTROL6 << self.trigger_type_shift()) | control,
+            None => TYPE_DISABLED << self.trigger_type_shift(),
+        }
+    }
+
+    /// Types other than `mcontrol6` disable the trigger, and unsupported
+    /// fields read as 0.
+    fn store_tdata1(&mut self, value: u64) {
+        let shift = self.trigger_type_shift();
+        let trigger = &mut self.triggers[self.csrs[TSELECT] as usize];
+        if (value >> shift) & 0xf != TYPE_MCONTROL6 {
+            trigger.control = None;
+            return;
+        }
+        let mut control = value & (HIT0 | ACTION | MATCH | M | S | U | EXECUTE | STORE | LOAD);
+        if (control & ACTION) >> 12 > ACTION_DEBUG {
+            control &= !ACTION;
+        }
+        if (control & MATCH) >> 7 > MATCH_LT {
+            control &= !MATCH;
+        }
+        trigger.control = Some(control);
+    }
+
+    /// Fires the first trigger matching `access` to the `bytes` at the
+    /// virtual address `addr` in the running mode, raising a breakpoint
+    /// exception.
+    pub(crate) fn check_triggers(
+        &mut self,
+        addr: u64,
+        bytes: u64,
+        access: Access,
+    ) -> Result<(), Exception> {
+        if self.triggers.iter().all(|x| x.control.is_none()) {
+            return Ok(());
+        }
+        let kind = match access {
+            Access::Fetch => EXECUTE,
+            Access::Load => LOAD,
+            Access::Store => STORE,
+        };
+        let mode = match self.mode {
+            Mode::Machine => M,
+            Mode::Supervisor => S,
+            Mode::User => U,
+        };
+        let machine_enabled = self.csrs[TCONTROL] & TCONTROL_MTE != 0;
+        for trigger in &mut self.triggers {
+            let Some(control) = trigger.control else {
+                continue;
+            };
+            let action = (control & ACTION) >> 12;
+            if control & kind == 0
+                || control & mode == 0
+                || (mode == M && action == ACTION_BREAKPOINT && !machine_enabled)
+                || !trigger.matches(control, addr, bytes)
+            {
+                continue;
+            }
+            trigger.control = Some(control | HIT0);
+            self.trigger_halt = action == ACTION_DEBUG;
+            return Err(Exception::Breakpoint(addr));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,437 @@
+//! A subset of the V extension: `vsetvli`, `vsetivli` and `vsetvl`, the
+//! unit-stride and strided loads and stores, and the integer arithmetic,
+//! compares and reductions, with 8 to 64-bit elements and any LMUL.
+//!
+//! Masked off and tail elements are always left undisturbed, which both
+//! the agnostic and the undisturbed policies allow. A load or store which
+//! faults leaves the index of the faulting element in `vstart`, and resumes
+//! from there when executed again.
+
+use alloc::{vec, vec::Vec};
+
+use tracing::debug;
+
+use crate::{
+    cpu::{Cpu, VL, VSTART, VTYPE},
+    error::EmulatorError,
+    exception::Exception,
+    instruction::Instruction,
+};
+
+/// VLEN of a cpu unless configured otherwise.
+pub const DEFAULT_VLEN: u64 = 128;
+
+/// Widest element, ELEN.
+const ELEN: u64 = 64;
+
+/// `vtype` with only `vill` set, after an unsupported configuration.
+const VILL: u64 = 1 << 63;
+
+/// The vector register file, v0 to v31.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vector {
+    vlen: u64,
+    /// The registers one after the other, `vlenb` bytes each. A register
+    /// group is contiguous, and its elements are little endian.
+    pub regs: Vec<u8>,
+}
+
+impl Vector {
+    /// Registers of `vlen` bits, a power of two from 64 to 65536.
+    pub fn new(vlen: u64) -> Result<Self, EmulatorError> {
+        if !vlen.is_power_of_two() || !(ELEN..=1 << 16).contains(&vlen) {
+            return Err(EmulatorError::InvalidVlen(vlen));
+        }
+        Ok(Self {
+            vlen,
+            regs: vec![0; (vlen / 8 * 32) as usize],
+        })
+    }
+
+    pub fn vlen(&self) -> u64 {
+        self.vlen
+    }
+
+    /// Size of a register in bytes, as read from the `vlenb` csr.
+    pub fn vlenb(&self) -> u64 {
+        self.vlen / 8
+    }
+
+    fn offset(&self, reg: usize, index: u64, sew: u64) -> usize {
+        reg * self.vlenb() as usize + (index * sew / 8) as usize
+    }
+
+    /// Element `index` of the group starting at `reg`, zero extended.
+    pub fn element(&self, reg: usize, index: u64, sew: u64) -> u64 {
+        let offset = self.offset(reg, index, sew);
+        let mut bytes = [0; 8];
+        bytes[..sew as usize / 8].copy_from_slice(&self.regs[offset..offset + sew as usize / 8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    pub fn set_element(&mut self, reg: usize, index: u64, sew: u64, value: u64) {
+        let offset = self.offset(reg, index, sew);
+        let len = sew as usize / 8;
+        self.regs[offset..offset + len].copy_from_slice(&value.to_le_bytes()[..len]);
+    }
+
+    /// Bit `index` of the mask in `reg`.
+    pub fn mask(&self, reg: usize, index: u64) -> bool {
+        self.regs[self.offset(reg, index / 8, 8)] >> (index % 8) & 1 != 0
+    }
+
+    pub fn set_mask(&mut self, reg: usize, index: u64, value: bool) {
+        let offset = self.offset(reg, index / 8, 8);
+        let bit = 1 << (index % 8);
+        match value {
+            true => self.regs[offset] |= bit,
+            false => self.regs[offset] &= !bit,
+        }
+    }
+
+    /// Whether element `index` is active, for masked instructions.
+    fn active(&self, vm: bool, index: u64) -> bool {
+        vm || self.mask(0, index)
+    }
+}
+
+impl Default for Vector {
+    fn default() -> Self {
+        Self::new(DEFAULT_VLEN).unwrap()
+    }
+}
+
+/// The fields of `vtype` which are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VType {
+    /// Element width in bits.
+    sew: u64,
+    /// log2 of LMUL, from -3 to 3.
+    lmul: i32,
+}
+
+impl VType {
+    /// `None` for the reserved encodings and `vill`.
+    fn decode(vtype: u64) -> Option<Self> {
+        let vsew = (vtype >> 3) & 0x7;
+        // vlmul 4 is reserved, the high bits hold vill
+        if vsew > 3 || vtype & 0x7 == 4 || vtype >> 8 != 0 {
+            return None;
+        }
+        let sew = 8 << vsew;
+        let lmul = ((vtype as i32 & 0x7) << 29) >> 29;
+        // a fractional LMUL must leave room for an element of every width
+        if lmul < 0 && sew > ELEN >> -lmul {
+            return None;
+        }
+        Some(Self { sew, lmul })
+    }
+
+    /// Elements in a register group.
+    fn vlmax(self, vlen: u64) -> u64 {
+        match self.lmul {
+            0.. => (vlen / self.sew) << self.lmul,
+            _ => (vlen / self.sew) >> -self.lmul,
+        }
+    }
+
+    /// log2 of the multiplier of a group of `eew`-bit elements holding as
+    /// many elements as a group of `sew`-bit ones.
+    fn emul(self, eew: u64) -> i32 {
+        self.lmul + eew.trailing_zeros() as i32 - self.sew.trailing_zeros() as i32
+    }
+}
+
+/// Sign extends the low `sew` bits of `x`.
+fn sext(x: u64, sew: u64) -> i64 {
+    ((x << (64 - sew)) as i64) >> (64 - sew)
+}
+
+fn truncate(x: u64, sew: u64) -> u64 {
+    x & (u64::MAX >> (64 - sew))
+}
+
+// funct3 of OP-V, where the second operand comes from
+const OPIVV: u64 = 0b000;
+const OPMVV: u64 = 0b010;
+const OPIVI: u64 = 0b011;
+const OPIVX: u64 = 0b100;
+const OPMVX: u64 = 0b110;
+const OPCFG: u64 = 0b111;
+
+/// What an arithmetic instruction does with its elements.
+enum Operation {
+    /// `vd[i] = op(vs2[i], operand)`.
+    Element(fn(u64, u64, u64) -> u64),
+    /// `vd[i] = op(vs2[i], operand, vd[i])`.
+    Accumulate(fn(u64, u64, u64) -> u64),
+    /// Sets bit `i` of the mask in `vd` to `op(vs2[i], operand)`.
+    Compare(fn(u64, u64, u64) -> bool),
+    /// `vmerge` and `vmv.v`.
+    Merge,
+}
+
+impl Cpu {
+    fn vtype(&self, inst: u64) -> Result<VType, Exception> {
+        VType::decode(self.csrs[VTYPE]).ok_or(Exception::IllegalInstruction(inst))
+    }
+
+    /// Executes the instructions of the V extension, on the OP-V opcode and
+    /// the vector widths of the floating point loads and stores.
+    pub(crate) fn execute_vector(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        match decoded.opcode {
+            0x57 if decoded.funct3 == OPCFG => self.vset(decoded),
+            0x57 => self.vector_arithmetic(decoded),
+            _ => self.vector_memory(decoded),
+        }
+    }
+
+    fn vset(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        let Instruction {
+            raw: inst,
+            rd,
+            rs1,
+            rs2,
+            ..
+        } = decoded;
+        let (vtype, avl) = match inst >> 30 {
+            0b00 | 0b01 => {
+                debug!("VSETVLI");
+                ((inst >> 20) & 0x7ff, None)
+            }
+            0b11 => {
+                debug!("VSETIVLI");
+                ((inst >> 20) & 0x3ff, Some(rs1 as u64))
+            }
+            _ if inst >> 25 == 0x40 => {
+                debug!("VSETVL");
+                (self.regs[rs2], None)
+            }
+            _ => Err(Exception::IllegalInstruction(inst))?,
+        };
+        let avl = match (avl, rs1, rd) {
+            (Some(avl), _, _) => avl,
+            (None, 1.., _) => self.regs[rs1],
+            (None, 0, 1..) => u64::MAX,
+            // keeps vl, as far as the new vtype allows
+            (None, 0, 0) => self.csrs[VL],
+        };
+        let vl = match VType::decode(vtype) {
+            Some(decoded) => {
+                self.csrs[VTYPE] = vtype;
+                avl.min(decoded.vlmax(self.vector.vlen()))
+            }
+            None => {
+                self.csrs[VTYPE] = VILL;
+                0
+            }
+        };
+        self.csrs[VL] = vl;
+        self.csrs[VSTART] = 0;
+        self.regs[rd] = vl;
+        Ok(())
+    }
+
+    fn vector_memory(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        let Instruction {
+            raw: inst,
+            opcode,
+            rd: vd,
+            rs1,
+            rs2,
+            funct3,
+            ..
+        } = decoded;
+        let illegal = Exception::IllegalInstruction(inst);
+        let vtype = self.vtype(inst)?;
+        let vm = (inst >> 25) & 1 != 0;
+        let (nf, mew, mop) = (inst >> 29, (inst >> 28) & 1, (inst >> 26) & 0x3);
+        let eew = match funct3 {
+            0b000 => 8,
+            0b101 => 16,
+            0b110 => 32,
+            _ => 64,
+        };
+        let stride = match mop {
+            // unit-stride, without the whole register and mask forms
+            0b00 if rs2 == 0 => eew / 8,
+            0b10 => self.regs[rs2],
+            _ => Err(illegal)?,
+        };
+        let emul = vtype.emul(eew);
+        if nf != 0 || mew != 0 || !(-3..=3).contains(&emul) || !vd.is_multiple_of(1 << emul.max(0))
+        {
+            Err(illegal)?
+        }
+        // masked loads can't overwrite the mask
+        if opcode == 0x07 && !vm && vd == 0 {
+            Err(illegal)?
+        }
+
+        let base = self.regs[rs1];
+        for i in self.csrs[VSTART]..self.csrs[VL] {
+            if !self.vector.active(vm, i) {
+                continue;
+            }
+            let addr = base.wrapping_add(i.wrapping_mul(stride));
+            let result = match opcode {
+                0x07 => self
+                    .load(addr, eew)
+                    .map(|value| self.vector.set_element(vd, i, eew, value)),
+                _ => self.store(addr, eew, self.vector.element(vd, i, eew)),
+            };
+            if let Err(e) = result {
+                self.csrs[VSTART] = i;
+                return Err(e);
+            }
+        }
+        debug!(eew, stride, "vector load/store");
+        self.csrs[VSTART] = 0;
+        Ok(())
+    }
+
+    fn vector_arithmetic(&mut self, decoded: Instruction) -> Result<(), Exception> {
+        let Instruction {
+            raw: inst,
+            rd: vd,
+            rs1,
+            rs2: vs2,
+            funct3,
+            ..
+        } = decoded;
+        let illegal = Exception::IllegalInstruction(inst);
+        let VType { sew, lmul } = self.vtype(inst)?;
+        let vm = (inst >> 25) & 1 != 0;
+        let funct6 = inst >> 26;
+        let vl = self.csrs[VL];
+
+        match (funct3, funct6) {
+            (OPMVV, 0x10) if vm && rs1 == 0 => {
+                debug!("VMV.X.S");
+                self.regs[decoded.rd] = sext(self.vector.element(vs2, 0, sew), sew) as u64;
+                return Ok(());
+            }
+            (OPMVX, 0x10) if vm && vs2 == 0 => {
+                debug!("VMV.S.X");
+                if self.csrs[VSTART] < vl {
+                    self.vector.set_element(vd, 0, sew, self.regs[rs1]);
+                }
+                self.csrs[VSTART] = 0;
+                return Ok(());
+            }
+            (OPMVV, 0x00) => {
+                debug!("VREDSUM.VS");
+                if self.csrs[VSTART] != 0 || !vs2.is_multiple_of(1 << lmul.max(0)) {
+                    Err(illegal)?
+                }
+                if vl > 0 {
+                    let sum = (0..vl)
+                        .filter(|i| self.vector.active(vm, *i))
+                        .map(|i| self.vector.element(vs2, i, sew))
+                        .fold(self.vector.element(rs1, 0, sew), u64::wrapping_add);
+                    self.vector.set_element(vd, 0, sew, sum);
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
+
+        let vector_or_scalar = matches!(funct3, OPIVV | OPIVX);
+        let scalar_or_immediate = matches!(funct3, OPIVX | OPIVI);
+        let operation = match (funct3, funct6) {
+            (OPIVV | OPIVX | OPIVI, 0x00) => Operation::Element(|a, b, _| a.wrapping_add(b)),
+            (OPIVV | OPIVX, 0x02) => Operation::Element(|a, b, _| a.wrapping_sub(b)),
+            (OPIVX | OPIVI, 0x03) => Operation::Element(|a, b, _| b.wrapping_sub(a)),
+            (_, 0x04) if vector_or_scalar => Operation::Element(|a, b, _| a.min(b)),
+            (_, 0x05) if vector_or_scalar => {
+                Operation::Element(|a, b, sew| match sext(a, sew) < sext(b, sew) {
+                    true => a,
+                    false => b,
+                })
+            }
+            (_, 0x06) if vector_or_scalar => Operation::Element(|a, b, _| a.max(b)),
+            (_, 0x07) if vector_or_scalar => {
+                Operation::Element(|a, b, sew| match sext(a, sew) > sext(b, sew) {
+                    true => a,
+                    false => b,
+                })
+            }
+            (OPIVV | OPIVX | OPIVI, 0x09) => Operation::Element(|a, b, _| a & b),
+            (OPIVV | OPIVX | OPIVI, 0x0a) => Operation::Element(|a, b, _| a | b),
+            (OPIVV | OPIVX | OPIVI, 0x0b) => Operation::Element(|a, b, _| a ^ b),
+            // vmv.v takes no vs2
+            (OPIVV | OPIVX | OPIVI, 0x17) if !vm || vs2 == 0 => Operation::Merge,
+            (OPIVV | OPIVX | OPIVI, 0x18) => Operation::Compare(|a, b, _| a == b),
+            (OPIVV | OPIVX | OPIVI, 0x19) => Operation::Compare(|a, b, _| a != b),
+            (_, 0x1a) if vector_or_scalar => Operation::Compare(|a, b, _| a < b),
+            (_, 0x1b) if vector_or_scalar => {
+                Operation::Compare(|a, b, sew| sext(a, sew) < sext(b, sew))
+            }
+            (OPIVV | OPIVX | OPIVI, 0x1c) => Operation::Compare(|a, b, _| a <= b),
+            (OPIVV | OPIVX | OPIVI, 0x1d) => {
+                Operation::Compare(|a, b, sew| sext(a, sew) <= sext(b, sew))
+            }
+            (_, 0x1e) if scalar_or_immediate => Operation::Compare(|a, b, _| a > b),
+            (_, 0x1f) if scalar_or_immediate => {
+                Operation::Compare(|a, b, sew| sext(a, sew) > sext(b, sew))
+            }
+            (OPIVV | OPIVX | OPIVI, 0x25) => Operation::Element(|a, b, sew| a << (b & (sew - 1))),
+            (OPIVV | OPIVX | OPIVI, 0x28) => Operation::Element(|a, b, sew| a >> (b & (sew - 1))),
+            (OPIVV | OPIVX | OPIVI, 0x29) => {
+                Operation::Element(|a, b, sew| (sext(a, sew) >> (b & (sew - 1))) as u64)
+            }
+            (OPMVV | OPMVX, 0x25) => Operation::Element(|a, b, _| a.wrapping_mul(b)),
+            (OPMVV | OPMVX, 0x2d) => {
+                Operation::Accumulate(|a, b, d| a.wrapping_mul(b).wrapping_add(d))
+            }
+            _ => Err(illegal)?,
+        };
+        debug!(funct3, funct6, "vector arithmetic");
+
+        // register groups start at a multiple of their size, and only masks
+        // may be written over the mask
+        let aligned = |r: usize| r.is_multiple_of(1 << lmul.max(0));
+        let writes_mask = matches!(operation, Operation::Compare(_));
+        if !aligned(vs2)
+            || (matches!(funct3, OPIVV | OPMVV) && !aligned(rs1))
+            || (!writes_mask && (!aligned(vd) || (!vm && vd == 0)))
+        {
+            Err(illegal)?
+        }
+
+        let operand = |cpu: &Self, i: u64| match funct3 {
+            OPIVV | OPMVV => cpu.vector.element(rs1, i, sew),
+            // shifts take the immediate unsigned
+            OPIVI if matches!(funct6, 0x25 | 0x28 | 0x29) => rs1 as u64,
+            OPIVI => truncate(((rs1 as i64) << 59 >> 59) as u64, sew),
+            _ => truncate(cpu.regs[rs1], sew),
+        };
+        for i in self.csrs[VSTART]..vl {
+            let b = operand(self, i);
+            if let Operation::Merge = operation {
+                // vmv.v copies the operand, vmerge where the mask is set
+                let value = match vm || self.vector.mask(0, i) {
+                    true => b,
+                    false => self.vector.element(vs2, i, sew),
+                };
+                self.vector.set_element(vd, i, sew, value);
+                continue;
+            }
+            if !self.vector.active(vm, i) {
+                continue;
+            }
+            let a = self.vector.element(vs2, i, sew);
+            match operation {
+                Operation::Element(op) => self.vector.set_element(vd, i, sew, op(a, b, sew)),
+                Operation::Accumulate(op) => {
+                    let d = self.vector.element(vd, i, sew);
+                    self.vector.set_element(vd, i, sew, op(a, b, d));
+                }
+                Operation::Compare(op) => self.vector.set_mask(vd, i, op(a, b, sew)),
+                Operation::Merge => unreachable!(),
+            }
+        }
+        self.csrs[VSTART] = 0;
+        Ok(())
+    }
+}
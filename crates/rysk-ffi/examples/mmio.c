@@ -0,0 +1,42 @@
+/*
+ * Runs a tiny program storing to a device implemented in C.
+ *
+ *   cargo build -p rysk-ffi
+ *   cc crates/rysk-ffi/examples/mmio.c -Icrates/rysk-ffi/include \
+ *     -Ltarget/debug -lrysk_ffi -o mmio && LD_LIBRARY_PATH=target/debug ./mmio
+ */
+#include <inttypes.h>
+#include <stdio.h>
+
+#include "rysk.h"
+
+static int32_t load(void *user, uint64_t offset, uint32_t size, uint64_t *value) {
+  *value = *(uint64_t *)user;
+  return 0;
+}
+
+static int32_t store(void *user, uint64_t offset, uint32_t size, uint64_t value) {
+  printf("device store: offset=%" PRIu64 " size=%u value=%" PRIu64 "\n", offset, size, value);
+  *(uint64_t *)user = value;
+  return 0;
+}
+
+int main(void) {
+  /* lui t0, 0x10000; addi t1, zero, 42; sw t1, 0(t0) */
+  const uint32_t program[] = {0x100002b7, 0x02a00313, 0x0062a023};
+  uint64_t device = 0;
+
+  RyskMachine *m = rysk_machine_new(0, "rv64ima_zicsr");
+  rysk_register_mmio(m, 0x10000000, 0x100, load, store, &device);
+  rysk_load_image(m, (const uint8_t *)program, sizeof(program));
+
+  uint64_t retired = 0;
+  RyskException exception;
+  if (rysk_run(m, 100, &retired, &exception) == RYSK_ERR_EXCEPTION) {
+    printf("stopped after %" PRIu64 " instructions: cause=%" PRIu64 " tval=%#" PRIx64 "\n",
+           retired, exception.cause, exception.tval);
+  }
+
+  rysk_machine_free(m);
+  return device == 42 ? 0 : 1;
+}
@@ -0,0 +1,82 @@
+/* C API for the rysk RISC-V emulator. Link against librysk_ffi. */
+#ifndef RYSK_H
+#define RYSK_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RYSK_API_VERSION 1
+
+#define RYSK_OK 0
+#define RYSK_ERR_INVALID -1
+/* The instruction raised an exception, see rysk_step. */
+#define RYSK_ERR_EXCEPTION -2
+#define RYSK_ERR_MEMORY -3
+/* The instruction didn't do what the design retired, see rysk_check_commit. */
+#define RYSK_ERR_MISMATCH -4
+
+typedef struct RyskMachine RyskMachine;
+
+typedef struct RyskException {
+  /* Value written to mcause. */
+  uint64_t cause;
+  /* Value written to mtval. */
+  uint64_t tval;
+} RyskException;
+
+/* An executed instruction, for co-simulation against a hardware design. */
+typedef struct RyskCommit {
+  uint64_t pc;
+  uint32_t inst;
+  /* Register written, 0 for none. */
+  uint32_t rd;
+  uint64_t rd_value;
+  /* Non zero if the instruction raised an exception instead of retiring. */
+  uint32_t trapped;
+  uint64_t cause;
+  uint64_t tval;
+} RyskCommit;
+
+/* Return 0 on success, anything else raises an access fault. Sizes are in bits. */
+typedef int32_t (*RyskMmioLoad)(void *user, uint64_t offset, uint32_t size, uint64_t *value);
+typedef int32_t (*RyskMmioStore)(void *user, uint64_t offset, uint32_t size, uint64_t value);
+
+uint32_t rysk_api_version(void);
+
+/* memory = 0 and isa = NULL select the defaults. Returns NULL on invalid arguments. */
+RyskMachine *rysk_machine_new(uint64_t memory, const char *isa);
+void rysk_machine_free(RyskMachine *m);
+
+/* Copies a raw image to the start of dram, where execution begins. */
+int32_t rysk_load_image(RyskMachine *m, const uint8_t *data, size_t len);
+
+/* exception and retired may be NULL. */
+int32_t rysk_step(RyskMachine *m, RyskException *exception);
+int32_t rysk_run(RyskMachine *m, uint64_t max, uint64_t *retired, RyskException *exception);
+
+uint64_t rysk_get_pc(RyskMachine *m);
+int32_t rysk_set_pc(RyskMachine *m, uint64_t pc);
+int32_t rysk_get_reg(RyskMachine *m, uint32_t index, uint64_t *value);
+int32_t rysk_set_reg(RyskMachine *m, uint32_t index, uint64_t value);
+
+int32_t rysk_read_mem(RyskMachine *m, uint64_t addr, uint8_t *buf, size_t len);
+int32_t rysk_write_mem(RyskMachine *m, uint64_t addr, const uint8_t *data, size_t len);
+
+/* Maps [base, base + size) to the callbacks, which get user back. */
+int32_t rysk_register_mmio(RyskMachine *m, uint64_t base, uint64_t size, RyskMmioLoad load,
+                           RyskMmioStore store, void *user);
+
+/* Executes an instruction, reporting exceptions in the commit. */
+int32_t rysk_step_commit(RyskMachine *m, RyskCommit *commit);
+/* Returns RYSK_ERR_MISMATCH if the instruction differs from expected. actual may be NULL. */
+int32_t rysk_check_commit(RyskMachine *m, const RyskCommit *expected, RyskCommit *actual);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
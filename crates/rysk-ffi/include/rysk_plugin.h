@@ -0,0 +1,37 @@
+/* ABI of rysk device plugins, shared libraries loaded with --plugin. */
+#ifndef RYSK_PLUGIN_H
+#define RYSK_PLUGIN_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RYSK_PLUGIN_ABI_VERSION 1
+
+/* Return 0 on success, anything else raises an access fault. Sizes are in bits. */
+typedef int32_t (*RyskPluginLoad)(void *user, uint64_t offset, uint32_t size, uint64_t *value);
+typedef int32_t (*RyskPluginStore)(void *user, uint64_t offset, uint32_t size, uint64_t value);
+/* Releases user when the machine is dropped, may be NULL. */
+typedef void (*RyskPluginFree)(void *user);
+
+typedef struct RyskPluginHost {
+  uint32_t abi_version;
+  /* Passed back to the functions below. */
+  void *context;
+  /* Maps [base, base + size) to the callbacks, which get user back. Returns 0 on success. */
+  int32_t (*map)(void *context, uint64_t base, uint64_t size, RyskPluginLoad load,
+                 RyskPluginStore store, RyskPluginFree free, void *user);
+} RyskPluginHost;
+
+/* Exported by the plugin: return RYSK_PLUGIN_ABI_VERSION. */
+uint32_t rysk_plugin_abi_version(void);
+/* Exported by the plugin: map the devices, args is from `--plugin path,args`. Return 0 on success. */
+int32_t rysk_plugin_init(const RyskPluginHost *host, const char *args);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,441 @@
+//! Stable C API for embedding rysk, declared in `include/rysk.h`.
+//!
+//! Every function taking a `*mut RyskMachine` expects a pointer returned by
+//! [`rysk_machine_new`] that hasn't been freed yet. Functions returning
+//! `int32_t` return [`RYSK_OK`] on success and a negative `RYSK_ERR_*` code
+//! otherwise.
+
+use std::{
+    ffi::{c_char, c_void, CStr},
+    ptr, slice,
+};
+
+use rysk::{
+    bus::{Device, DRAM_BASE},
+    cosim::{self, Commit},
+    exception::Exception,
+    machine::Machine,
+};
+
+/// Bumped whenever the C API changes incompatibly.
+pub const RYSK_API_VERSION: u32 = 1;
+
+pub const RYSK_OK: i32 = 0;
+pub const RYSK_ERR_INVALID: i32 = -1;
+/// The instruction raised an exception, see `rysk_step`.
+pub const RYSK_ERR_EXCEPTION: i32 = -2;
+pub const RYSK_ERR_MEMORY: i32 = -3;
+/// The instruction didn't do what the design retired, see `rysk_check_commit`.
+pub const RYSK_ERR_MISMATCH: i32 = -4;
+
+/// Opaque handle to a machine.
+pub struct RyskMachine {
+    machine: Machine,
+}
+
+/// Exception raised by the last instruction.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RyskException {
+    /// Value written to `mcause`.
+    pub cause: u64,
+    /// Value written to `mtval`.
+    pub tval: u64,
+}
+
+impl From<Exception> for RyskException {
+    fn from(e: Exception) -> Self {
+        Self {
+            cause: e.code(),
+            tval: e.value(),
+        }
+    }
+}
+
+/// An executed instruction, for co-simulation against a hardware design.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RyskCommit {
+    pub pc: u64,
+    pub inst: u32,
+    /// Register written, 0 for none.
+    pub rd: u32,
+    pub rd_value: u64,
+    /// Non zero if the instruction raised an exception instead of retiring.
+    pub trapped: u32,
+    pub cause: u64,
+    pub tval: u64,
+}
+
+impl From<Commit> for RyskCommit {
+    fn from(c: Commit) -> Self {
+        let (rd, rd_value) = c.write.unwrap_or_default();
+        let (cause, tval) = c.trap.unwrap_or_default();
+        Self {
+            pc: c.pc,
+            inst: c.inst,
+            rd: rd as u32,
+            rd_value,
+            trapped: c.trap.is_some() as u32,
+            cause,
+            tval,
+        }
+    }
+}
+
+impl From<RyskCommit> for Commit {
+    fn from(c: RyskCommit) -> Self {
+        Self {
+            pc: c.pc,
+            inst: c.inst,
+            write: (c.rd != 0).then_some((c.rd as usize, c.rd_value)),
+            trap: (c.trapped != 0).then_some((c.cause, c.tval)),
+        }
+    }
+}
+
+/// Called for loads from a region registered with `rysk_register_mmio`.
+/// Returns 0 and writes the value on success, anything else raises an access fault.
+pub type RyskMmioLoad =
+    unsafe extern "C" fn(user: *mut c_void, offset: u64, size: u32, value: *mut u64) -> i32;
+/// Called for stores to a region registered with `rysk_register_mmio`.
+/// Returns 0 on success, anything else raises an access fault.
+pub type RyskMmioStore =
+    unsafe extern "C" fn(user: *mut c_void, offset: u64, size: u32, value: u64) -> i32;
+
+struct CallbackDevice {
+    base: u64,
+    load: RyskMmioLoad,
+    store: RyskMmioStore,
+    user: *mut c_void,
+}
+
+// The C side is responsible for `user` being usable from whichever thread
+// drives the machine.
+unsafe impl Send for CallbackDevice {}
+
+impl Device for CallbackDevice {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let mut value = 0;
+        match unsafe { (self.load)(self.user, offset, size as u32, &mut value) } {
+            0 => Ok(value),
+            _ => Err(Exception::LoadAccessFault(self.base + offset)),
+        }
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        match unsafe { (self.store)(self.user, offset, size as u32, value) } {
+            0 => Ok(()),
+            _ => Err(Exception::StoreAmoAccessFault(self.base + offset)),
+        }
+    }
+}
+
+unsafe fn machine<'a>(m: *mut RyskMachine) -> Option<&'a mut Machine> {
+    m.as_mut().map(|x| &mut x.machine)
+}
+
+#[no_mangle]
+pub extern "C" fn rysk_api_version() -> u32 {
+    RYSK_API_VERSION
+}
+
+/// Creates a machine with `memory` bytes of dram (0 for the default) running
+/// `isa` (NULL for the default). Returns NULL on invalid arguments.
+///
+/// # Safety
+///
+/// `isa` must be NULL or a valid NUL terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_machine_new(memory: u64, isa: *const c_char) -> *mut RyskMachine {
+    let mut builder = Machine::builder();
+    if memory != 0 {
+        builder = builder.memory(memory);
+    }
+    if !isa.is_null() {
+        match CStr::from_ptr(isa).to_str() {
+            Ok(isa) => builder = builder.isa(isa),
+            Err(_) => return ptr::null_mut(),
+        }
+    }
+
+    match builder.build() {
+        Ok(machine) => Box::into_raw(Box::new(RyskMachine { machine })),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `m` must be NULL or a live machine, which is no longer usable afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_machine_free(m: *mut RyskMachine) {
+    if !m.is_null() {
+        drop(Box::from_raw(m));
+    }
+}
+
+/// Copies a raw image to the start of dram, where execution begins.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_load_image(m: *mut RyskMachine, data: *const u8, len: usize) -> i32 {
+    rysk_write_mem(m, DRAM_BASE, data, len)
+}
+
+/// Executes one instruction. On [`RYSK_ERR_EXCEPTION`] the exception is
+/// written to `exception` if it isn't NULL.
+///
+/// # Safety
+///
+/// `exception` must be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_step(m: *mut RyskMachine, exception: *mut RyskException) -> i32 {
+    let Some(machine) = machine(m) else {
+        return RYSK_ERR_INVALID;
+    };
+
+    match machine.cpu.step() {
+        Ok(()) => RYSK_OK,
+        Err(e) => {
+            if let Some(out) = exception.as_mut() {
+                *out = e.into();
+            }
+            RYSK_ERR_EXCEPTION
+        }
+    }
+}
+
+/// Executes up to `max` instructions, stopping at the first exception like
+/// [`rysk_step`]. The number of retired instructions is written to `retired`
+/// if it isn't NULL.
+///
+/// # Safety
+///
+/// `retired` and `exception` must be NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_run(
+    m: *mut RyskMachine,
+    max: u64,
+    retired: *mut u64,
+    exception: *mut RyskException,
+) -> i32 {
+    let mut count = 0;
+    let mut status = RYSK_OK;
+
+    while count < max {
+        status = rysk_step(m, exception);
+        if status != RYSK_OK {
+            break;
+        }
+        count += 1;
+    }
+
+    if let Some(out) = retired.as_mut() {
+        *out = count;
+    }
+    status
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_get_pc(m: *mut RyskMachine) -> u64 {
+    machine(m).map_or(0, |m| m.cpu.pc)
+}
+
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_set_pc(m: *mut RyskMachine, pc: u64) -> i32 {
+    match machine(m) {
+        Some(m) => {
+            m.cpu.pc = pc;
+            RYSK_OK
+        }
+        None => RYSK_ERR_INVALID,
+    }
+}
+
+/// Reads integer register `index`, or writes 0 to `value` for x0.
+///
+/// # Safety
+///
+/// `value` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_get_reg(m: *mut RyskMachine, index: u32, value: *mut u64) -> i32 {
+    match (machine(m), value.as_mut()) {
+        (Some(m), Some(value)) if index < 32 => {
+            *value = m.cpu.regs[index as usize];
+            RYSK_OK
+        }
+        _ => RYSK_ERR_INVALID,
+    }
+}
+
+/// Writes integer register `index`. Writes to x0 are ignored.
+///
+/// # Safety
+///
+/// See the crate documentation.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_set_reg(m: *mut RyskMachine, index: u32, value: u64) -> i32 {
+    match machine(m) {
+        Some(m) if index < 32 => {
+            if index != 0 {
+                m.cpu.regs[index as usize] = value;
+            }
+            RYSK_OK
+        }
+        _ => RYSK_ERR_INVALID,
+    }
+}
+
+/// Reads `len` bytes of guest memory at `addr`, going through the bus like the
+/// cpu would.
+///
+/// # Safety
+///
+/// `buf` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_read_mem(
+    m: *mut RyskMachine,
+    addr: u64,
+    buf: *mut u8,
+    len: usize,
+) -> i32 {
+    let Some(machine) = machine(m) else {
+        return RYSK_ERR_INVALID;
+    };
+    if len == 0 {
+        return RYSK_OK;
+    }
+    if buf.is_null() {
+        return RYSK_ERR_INVALID;
+    }
+
+    let buf = slice::from_raw_parts_mut(buf, len);
+    for (i, byte) in buf.iter_mut().enumerate() {
+        match machine.cpu.bus.load(addr.wrapping_add(i as u64), 8) {
+            Ok(x) => *byte = x as u8,
+            Err(_) => return RYSK_ERR_MEMORY,
+        }
+    }
+    RYSK_OK
+}
+
+/// Writes `len` bytes of guest memory at `addr`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_write_mem(
+    m: *mut RyskMachine,
+    addr: u64,
+    data: *const u8,
+    len: usize,
+) -> i32 {
+    let Some(machine) = machine(m) else {
+        return RYSK_ERR_INVALID;
+    };
+    if len == 0 {
+        return RYSK_OK;
+    }
+    if data.is_null() {
+        return RYSK_ERR_INVALID;
+    }
+
+    let data = slice::from_raw_parts(data, len);
+    for (i, byte) in data.iter().enumerate() {
+        if machine
+            .cpu
+            .bus
+            .store(addr.wrapping_add(i as u64), 8, *byte as u64)
+            .is_err()
+        {
+            return RYSK_ERR_MEMORY;
+        }
+    }
+    RYSK_OK
+}
+
+/// Maps `[base, base + size)` to the given callbacks, which receive `user`
+/// back along with the offset into the region and the access width in bits.
+///
+/// # Safety
+///
+/// The callbacks must be safe to call with `user` until the machine is freed.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_register_mmio(
+    m: *mut RyskMachine,
+    base: u64,
+    size: u64,
+    load: Option<RyskMmioLoad>,
+    store: Option<RyskMmioStore>,
+    user: *mut c_void,
+) -> i32 {
+    match (machine(m), load, store) {
+        (Some(m), Some(load), Some(store)) if size != 0 => {
+            m.cpu.bus.map(
+                base,
+                size,
+                CallbackDevice {
+                    base,
+                    load,
+                    store,
+                    user,
+                },
+            );
+            RYSK_OK
+        }
+        _ => RYSK_ERR_INVALID,
+    }
+}
+
+/// Executes one instruction and writes what it did to `commit`. Exceptions
+/// are reported in the commit rather than as [`RYSK_ERR_EXCEPTION`].
+///
+/// # Safety
+///
+/// `commit` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_step_commit(m: *mut RyskMachine, commit: *mut RyskCommit) -> i32 {
+    match (machine(m), commit.as_mut()) {
+        (Some(m), Some(commit)) => {
+            *commit = cosim::step(m).into();
+            RYSK_OK
+        }
+        _ => RYSK_ERR_INVALID,
+    }
+}
+
+/// Executes one instruction and compares it with `expected`, what the
+/// design retired. Returns [`RYSK_ERR_MISMATCH`] if they differ, writing
+/// what rysk did to `actual` if it isn't NULL.
+///
+/// # Safety
+///
+/// `expected` must be readable, `actual` NULL or writable.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_check_commit(
+    m: *mut RyskMachine,
+    expected: *const RyskCommit,
+    actual: *mut RyskCommit,
+) -> i32 {
+    let (Some(m), Some(expected)) = (machine(m), expected.as_ref()) else {
+        return RYSK_ERR_INVALID;
+    };
+    let (status, commit) = match cosim::check(m, &(*expected).into()) {
+        Ok(commit) => (RYSK_OK, commit),
+        Err(commit) => (RYSK_ERR_MISMATCH, commit),
+    };
+    if let Some(out) = actual.as_mut() {
+        *out = commit.into();
+    }
+    status
+}
@@ -0,0 +1,124 @@
+use std::{ffi::c_void, ptr};
+
+use rysk_ffi::*;
+
+unsafe extern "C" fn load(user: *mut c_void, _offset: u64, _size: u32, value: *mut u64) -> i32 {
+    *value = *(user as *mut u64);
+    0
+}
+
+unsafe extern "C" fn store(user: *mut c_void, offset: u64, size: u32, value: u64) -> i32 {
+    if offset != 0 || size != 32 {
+        return 1;
+    }
+    *(user as *mut u64) = value;
+    0
+}
+
+fn program(insts: &[u32]) -> Vec<u8> {
+    insts.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[test]
+fn run_with_mmio_callbacks() {
+    unsafe {
+        let m = rysk_machine_new(1024 * 1024, c"rv64ima_zicsr".as_ptr());
+        assert!(!m.is_null());
+
+        let mut device = 0u64;
+        assert_eq!(
+            rysk_register_mmio(
+                m,
+                0x1000_0000,
+                0x100,
+                Some(load),
+                Some(store),
+                &mut device as *mut u64 as *mut c_void
+            ),
+            RYSK_OK
+        );
+
+        // lui t0, 0x10000; addi t1, zero, 42; sw t1, 0(t0); lw t2, 0(t0); sb t1, 1(t0)
+        let code = program(&[0x100002b7, 0x02a00313, 0x0062a023, 0x0002a383, 0x006280a3]);
+        assert_eq!(rysk_load_image(m, code.as_ptr(), code.len()), RYSK_OK);
+
+        let mut retired = 0;
+        let mut exception = RyskException::default();
+        assert_eq!(
+            rysk_run(m, 100, &mut retired, &mut exception),
+            RYSK_ERR_EXCEPTION
+        );
+        assert_eq!(retired, 4);
+        // The byte store is refused by the callback.
+        assert_eq!(exception.cause, 7);
+        assert_eq!(exception.tval, 0x1000_0001);
+        assert_eq!(device, 42);
+
+        let mut value = 0;
+        assert_eq!(rysk_get_reg(m, 7, &mut value), RYSK_OK);
+        assert_eq!(value, 42);
+        assert_eq!(rysk_get_reg(m, 32, &mut value), RYSK_ERR_INVALID);
+
+        rysk_machine_free(m);
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    unsafe {
+        assert!(rysk_machine_new(0, c"rv128i".as_ptr()).is_null());
+
+        let m = rysk_machine_new(1024 * 1024, ptr::null());
+        assert_eq!(rysk_get_pc(m), 0x8000_0000);
+        assert_eq!(rysk_set_pc(m, 0x8000_0004), RYSK_OK);
+        assert_eq!(rysk_get_pc(m), 0x8000_0004);
+
+        assert_eq!(rysk_set_reg(m, 5, 7), RYSK_OK);
+        assert_eq!(rysk_set_reg(m, 0, 7), RYSK_OK);
+        let mut value = 1;
+        rysk_get_reg(m, 0, &mut value);
+        assert_eq!(value, 0);
+
+        let data = [1u8, 2, 3, 4];
+        assert_eq!(rysk_write_mem(m, 0x8000_0010, data.as_ptr(), 4), RYSK_OK);
+        let mut buf = [0u8; 4];
+        assert_eq!(rysk_read_mem(m, 0x8000_0010, buf.as_mut_ptr(), 4), RYSK_OK);
+        assert_eq!(buf, data);
+        assert_eq!(rysk_read_mem(m, 0x10, buf.as_mut_ptr(), 4), RYSK_ERR_MEMORY);
+
+        rysk_machine_free(m);
+    }
+}
+
+#[test]
+fn lockstep_commits() {
+    unsafe {
+        let m = rysk_machine_new(0, ptr::null());
+        // li a0, 5; ecall
+        let code = program(&[0x00500513, 0x00000073]);
+        assert_eq!(rysk_load_image(m, code.as_ptr(), code.len()), RYSK_OK);
+
+        let expected = RyskCommit {
+            pc: 0x8000_0000,
+            inst: 0x00500513,
+            rd: 10,
+            rd_value: 5,
+            ..RyskCommit::default()
+        };
+        let mut actual = RyskCommit::default();
+        assert_eq!(rysk_check_commit(m, &expected, &mut actual), RYSK_OK);
+        assert_eq!(actual.rd_value, 5);
+
+        assert_eq!(rysk_step_commit(m, &mut actual), RYSK_OK);
+        assert_eq!(
+            (actual.pc, actual.trapped, actual.cause),
+            (0x8000_0004, 1, 11)
+        );
+        assert_eq!(
+            rysk_check_commit(m, &expected, ptr::null_mut()),
+            RYSK_ERR_MISMATCH
+        );
+        assert_eq!(rysk_step_commit(m, ptr::null_mut()), RYSK_ERR_INVALID);
+        rysk_machine_free(m);
+    }
+}
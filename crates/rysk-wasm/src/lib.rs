@@ -0,0 +1,156 @@
+//! WebAssembly bindings for running rysk in the browser, see `www/` for the
+//! frontend. Build with:
+//!
+//! ```sh
+//! cargo build -p rysk-wasm --release --target wasm32-unknown-unknown
+//! ```
+//!
+//! The exports use plain C types so they can be driven from JavaScript
+//! without any binding generator.
+
+use std::{fmt::Write, sync::Mutex};
+
+use rysk_core::{
+    bus::Bus, cpu::Cpu, dram::Dram, exception::Exception, isa::Isa, time::InstretClock,
+};
+
+/// Memory given to guests, smaller than the native default to keep the page light.
+pub const MEMORY: u64 = 16 * 1024 * 1024;
+
+/// A loaded program and the text shown on the virtual console.
+#[derive(Debug)]
+pub struct Session {
+    pub cpu: Cpu,
+    pub console: String,
+    pub stopped: bool,
+}
+
+impl Session {
+    pub fn new(code: Vec<u8>) -> Result<Self, String> {
+        if code.len() as u64 > MEMORY {
+            return Err(format!("image of {} bytes is too large", code.len()));
+        }
+
+        let mut cpu = Cpu::with_bus(Bus::new(Dram::with_size(code, MEMORY)), Isa::default());
+        // There is no wall clock on wasm32-unknown-unknown.
+        cpu.clock = Box::new(InstretClock);
+
+        Ok(Self {
+            cpu,
+            console: String::new(),
+            stopped: false,
+        })
+    }
+
+    /// Executes up to `max` instructions, returning how many were executed.
+    pub fn run(&mut self, max: u32) -> u32 {
+        let mut executed = 0;
+
+        while executed < max && !self.stopped {
+            executed += 1;
+
+            match self.cpu.step() {
+                Ok(()) => {}
+                // Returned from main.
+                Err(Exception::InstructionAccessFault(0)) => {
+                    self.stopped = true;
+                    self.console.push_str("program returned\n");
+                }
+                Err(e) => self.stop(e),
+            }
+        }
+
+        executed
+    }
+
+    fn stop(&mut self, exception: Exception) {
+        self.stopped = true;
+        let _ = writeln!(self.console, "stopped: {exception}");
+    }
+
+    pub fn dump(&mut self) {
+        let _ = self.cpu.write_registers(&mut self.console);
+    }
+}
+
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+fn with_session<T>(default: T, f: impl FnOnce(&mut Session) -> T) -> T {
+    match SESSION.lock().unwrap().as_mut() {
+        Some(session) => f(session),
+        None => default,
+    }
+}
+
+/// Allocates `len` bytes for the host to copy a program into, see [`rysk_load`].
+#[no_mangle]
+pub extern "C" fn rysk_alloc(len: usize) -> *mut u8 {
+    let mut buf = vec![0u8; len].into_boxed_slice();
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+/// Starts a new session with the program at `ptr`, taking ownership of the
+/// buffer. Returns 0 on success.
+///
+/// # Safety
+///
+/// `ptr` and `len` must come from a single [`rysk_alloc`] call.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_load(ptr: *mut u8, len: usize) -> i32 {
+    let code = Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)).into_vec();
+
+    match Session::new(code) {
+        Ok(session) => {
+            *SESSION.lock().unwrap() = Some(session);
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Executes up to `max` instructions, returning how many were executed.
+#[no_mangle]
+pub extern "C" fn rysk_run(max: u32) -> u32 {
+    with_session(0, |s| s.run(max))
+}
+
+/// Whether the program stopped.
+#[no_mangle]
+pub extern "C" fn rysk_stopped() -> bool {
+    with_session(true, |s| s.stopped)
+}
+
+#[no_mangle]
+pub extern "C" fn rysk_pc() -> u64 {
+    with_session(0, |s| s.cpu.pc)
+}
+
+#[no_mangle]
+pub extern "C" fn rysk_reg(index: u32) -> u64 {
+    with_session(0, |s| s.cpu.regs.get(index as usize).copied().unwrap_or(0))
+}
+
+/// Appends the register file to the console.
+#[no_mangle]
+pub extern "C" fn rysk_dump() {
+    with_session((), Session::dump)
+}
+
+/// Pointer to the console text, valid until the next call into rysk.
+#[no_mangle]
+pub extern "C" fn rysk_console_ptr() -> *const u8 {
+    with_session(std::ptr::null(), |s| s.console.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn rysk_console_len() -> usize {
+    with_session(0, |s| s.console.len())
+}
+
+/// Empties the console once the host displayed it.
+#[no_mangle]
+pub extern "C" fn rysk_console_clear() {
+    with_session((), |s| s.console.clear())
+}
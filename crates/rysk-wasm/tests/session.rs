@@ -0,0 +1,13 @@
+use rysk_wasm::Session;
+
+#[test]
+fn run_until_stopped() {
+    // addi x31, x0, 6
+    let code = 0x00600f93u32.to_le_bytes().to_vec();
+    let mut session = Session::new(code).unwrap();
+
+    assert_eq!(session.run(100), 2);
+    assert_eq!(session.cpu.regs[31], 6);
+    assert!(session.stopped);
+    assert!(session.console.starts_with("stopped: illegal instruction"));
+}
@@ -0,0 +1,90 @@
+// Frontend for rysk-wasm. Serve this directory together with rysk_wasm.wasm,
+// e.g. `python3 -m http.server`.
+
+const ABI = ["zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0",
+  "a1", "a2", "a3", "a4", "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7",
+  "s8", "s9", "s10", "s11", "t3", "t4", "t5", "t6"];
+
+// Instructions executed per animation frame.
+const BATCH = 100000;
+
+const { instance } = await WebAssembly.instantiateStreaming(fetch("rysk_wasm.wasm"));
+const rysk = instance.exports;
+
+const consoleEl = document.getElementById("console");
+const buttons = ["run", "step", "pause"].map((id) => document.getElementById(id));
+const [runButton, stepButton, pauseButton] = buttons;
+let running = false;
+
+function flushConsole() {
+  const len = rysk.rysk_console_len();
+  if (len === 0) return;
+  const bytes = new Uint8Array(rysk.memory.buffer, rysk.rysk_console_ptr(), len);
+  consoleEl.textContent += new TextDecoder().decode(bytes);
+  rysk.rysk_console_clear();
+}
+
+function hex(x) {
+  return "0x" + BigInt.asUintN(64, x).toString(16).padStart(16, "0");
+}
+
+function showRegisters() {
+  document.getElementById("pc").textContent = hex(rysk.rysk_pc());
+  const rows = [];
+  for (let i = 0; i < 32; i += 4) {
+    const cells = [];
+    for (let j = i; j < i + 4; j++) {
+      cells.push(`<td>x${j} (${ABI[j]})</td><td>${hex(rysk.rysk_reg(j))}</td>`);
+    }
+    rows.push(`<tr>${cells.join("")}</tr>`);
+  }
+  document.getElementById("regs").innerHTML = rows.join("");
+}
+
+function update() {
+  flushConsole();
+  showRegisters();
+  const stopped = rysk.rysk_stopped();
+  runButton.disabled = running || stopped;
+  stepButton.disabled = running || stopped;
+  pauseButton.disabled = !running;
+}
+
+function frame() {
+  if (!running) return;
+  rysk.rysk_run(BATCH);
+  if (rysk.rysk_stopped()) {
+    running = false;
+    rysk.rysk_dump();
+  }
+  update();
+  if (running) requestAnimationFrame(frame);
+}
+
+document.getElementById("file").addEventListener("change", async (event) => {
+  const data = new Uint8Array(await event.target.files[0].arrayBuffer());
+  const ptr = rysk.rysk_alloc(data.length);
+  new Uint8Array(rysk.memory.buffer, ptr, data.length).set(data);
+  consoleEl.textContent = "";
+  running = false;
+  if (rysk.rysk_load(ptr, data.length) !== 0) {
+    consoleEl.textContent = "failed to load the program\n";
+  }
+  update();
+});
+
+runButton.addEventListener("click", () => {
+  running = true;
+  update();
+  requestAnimationFrame(frame);
+});
+
+stepButton.addEventListener("click", () => {
+  rysk.rysk_run(1);
+  update();
+});
+
+pauseButton.addEventListener("click", () => {
+  running = false;
+  update();
+});
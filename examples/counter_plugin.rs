@@ -0,0 +1,73 @@
+//! A device plugin: a register which reads back the last value stored plus
+//! the number of stores so far. Mapped at the address given as its argument,
+//! 0x10000000 by default.
+//!
+//! ```sh
+//! cargo build --example counter_plugin
+//! rysk run image.bin --plugin target/debug/examples/libcounter_plugin.so,0x10001000
+//! ```
+
+use std::ffi::{c_char, c_void, CStr};
+
+use rysk::{
+    debugger::parse_number,
+    plugin::{RyskPluginHost, RYSK_PLUGIN_ABI_VERSION},
+};
+
+#[derive(Default)]
+struct Counter {
+    value: u64,
+    stores: u64,
+}
+
+unsafe extern "C" fn load(user: *mut c_void, _offset: u64, _size: u32, value: *mut u64) -> i32 {
+    let counter = &*(user as *mut Counter);
+    *value = counter.value + counter.stores;
+    0
+}
+
+unsafe extern "C" fn store(user: *mut c_void, offset: u64, _size: u32, value: u64) -> i32 {
+    if offset != 0 {
+        return 1;
+    }
+    let counter = &mut *(user as *mut Counter);
+    counter.value = value;
+    counter.stores += 1;
+    0
+}
+
+unsafe extern "C" fn free(user: *mut c_void) {
+    drop(Box::from_raw(user as *mut Counter));
+}
+
+#[no_mangle]
+pub extern "C" fn rysk_plugin_abi_version() -> u32 {
+    RYSK_PLUGIN_ABI_VERSION
+}
+
+/// # Safety
+///
+/// `host` and `args` must be valid, as rysk passes them.
+#[no_mangle]
+pub unsafe extern "C" fn rysk_plugin_init(host: *const RyskPluginHost, args: *const c_char) -> i32 {
+    let host = &*host;
+    let args = CStr::from_ptr(args).to_string_lossy();
+    let base = match args.as_ref() {
+        "" => 0x1000_0000,
+        args => match parse_number(args) {
+            Some(base) => base,
+            None => return 1,
+        },
+    };
+
+    let user = Box::into_raw(Box::<Counter>::default()) as *mut c_void;
+    (host.map)(
+        host.context,
+        base,
+        0x8,
+        Some(load),
+        Some(store),
+        Some(free),
+        user,
+    )
+}
@@ -0,0 +1,248 @@
+//! Software interrupts of the ACLINT, for inter-processor interrupts, and its
+//! machine timer. As on the QEMU virt machine, MSWI and MTIMER together are
+//! the CLINT at [`MSWI_BASE`]: the machine software interrupt of a hart is
+//! pending while its 32-bit `msip` register is 1, and its machine timer
+//! interrupt while `mtime` is at or past its `mtimecmp`. SSWI makes the
+//! supervisor software interrupt of a hart pending when 1 is written to its
+//! register, the hart then clears it in `sip`.
+//!
+//! `mtime` is the `time` csr, so it follows the [clock](rysk_core::time) of
+//! the cpu, and writes to it are ignored.
+
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+
+use crate::{
+    bus::Device,
+    cpu::{Cpu, MIP, RDTIME},
+    exception::{Exception, Interrupt},
+    hart::Hart,
+};
+
+pub const MSWI_BASE: u64 = 0x0200_0000;
+pub const MSWI_SIZE: u64 = 0x4000;
+pub const MTIMER_BASE: u64 = 0x0200_4000;
+pub const MTIMER_SIZE: u64 = 0x8000;
+pub const SSWI_BASE: u64 = 0x02f0_0000;
+pub const SSWI_SIZE: u64 = 0x4000;
+
+/// Offset of `mtime` in the MTIMER, after the `mtimecmp` of each hart.
+const MTIME: u64 = 0x7ff8;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Level of each hart's `msip`.
+    msip: Vec<bool>,
+    /// Writes to `setssip` not delivered to the hart yet.
+    ssip: Vec<bool>,
+}
+
+/// Registers of the MSWI and SSWI devices, see [`mswi`](Self::mswi) and
+/// [`sswi`](Self::sswi). Clones share them, the machine keeps one to deliver
+/// the interrupts.
+#[derive(Debug, Clone, Default)]
+pub struct SoftwareInterrupts {
+    inner: Arc<Mutex<Inner>>,
+    /// Set by the devices, so [`deliver`](Self::deliver) is cheap otherwise.
+    changed: Arc<AtomicBool>,
+}
+
+impl SoftwareInterrupts {
+    pub fn new(harts: u64) -> Self {
+        let harts = harts as usize;
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                msip: vec![false; harts],
+                ssip: vec![false; harts],
+            })),
+            changed: Arc::default(),
+        }
+    }
+
+    /// The MSWI device, to map at [`MSWI_BASE`].
+    pub fn mswi(&self) -> Mswi {
+        Mswi(self.clone())
+    }
+
+    /// The SSWI device, to map at [`SSWI_BASE`].
+    pub fn sswi(&self) -> Sswi {
+        Sswi(self.clone())
+    }
+
+    /// Makes the supervisor software interrupt of `hart` pending, as a write
+    /// to its SSWI register does.
+    pub fn send_supervisor(&self, hart: u64) {
+        if let Some(ssip) = self.inner.lock().unwrap().ssip.get_mut(hart as usize) {
+            *ssip = true;
+            self.changed.store(true, Ordering::Release);
+        }
+    }
+
+    /// Updates `mip` of the running hart and the parked ones if a register
+    /// was written since the last call.
+    pub fn deliver(&self, cpu: &mut Cpu, harts: &mut [Hart]) {
+        if !self.changed.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let running = cpu.hart_id() as usize;
+        if let Some(msip) = inner.msip.get(running) {
+            cpu.set_pending(Interrupt::MachineSoftware, *msip);
+        }
+        if let Some(ssip) = inner.ssip.get_mut(running) {
+            if std::mem::take(ssip) {
+                cpu.set_pending(Interrupt::SupervisorSoftware, true);
+            }
+        }
+        for hart in harts {
+            let id = hart.id() as usize;
+            let (Some(msip), Some(ssip)) = (inner.msip.get(id).copied(), inner.ssip.get_mut(id))
+            else {
+                continue;
+            };
+            let bit = 1 << Interrupt::MachineSoftware.code();
+            match msip {
+                true => hart.csrs[MIP] |= bit,
+                false => hart.csrs[MIP] &= !bit,
+            }
+            if std::mem::take(ssip) {
+                hart.csrs[MIP] |= 1 << Interrupt::SupervisorSoftware.code();
+            }
+        }
+    }
+}
+
+/// Offset of the register of a hart in a bank.
+fn hart(offset: u64) -> usize {
+    (offset / 4) as usize
+}
+
+/// `msip` registers, one per hart.
+#[derive(Debug)]
+pub struct Mswi(SoftwareInterrupts);
+
+impl Device for Mswi {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let inner = self.0.inner.lock().unwrap();
+        Ok(inner.msip.get(hart(offset)).copied().unwrap_or(false) as u64)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        if let Some(msip) = self.0.inner.lock().unwrap().msip.get_mut(hart(offset)) {
+            *msip = value & 1 != 0;
+            self.0.changed.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+}
+
+/// `setssip` registers, one per hart, which read as 0.
+#[derive(Debug)]
+pub struct Sswi(SoftwareInterrupts);
+
+impl Device for Sswi {
+    fn load(&mut self, _offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(0)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        if value & 1 != 0 {
+            self.0.send_supervisor(hart(offset) as u64);
+        }
+        Ok(())
+    }
+}
+
+/// `mtimecmp` registers, one per hart, and `mtime`. Clones share them, the
+/// machine keeps one to deliver the interrupts.
+#[derive(Debug, Clone)]
+pub struct Mtimer {
+    mtimecmp: Arc<Mutex<Vec<u64>>>,
+    mtime: Arc<AtomicU64>,
+    /// The earliest `mtimecmp` after `mtime`, when the next interrupt rises.
+    next: Arc<AtomicU64>,
+    /// Set by writes to `mtimecmp`, which can lower the interrupts too.
+    changed: Arc<AtomicBool>,
+}
+
+impl Mtimer {
+    /// Timers of `harts` harts, none of them due.
+    pub fn new(harts: u64) -> Self {
+        Self {
+            mtimecmp: Arc::new(Mutex::new(vec![u64::MAX; harts as usize])),
+            mtime: Arc::default(),
+            next: Arc::new(AtomicU64::new(u64::MAX)),
+            changed: Arc::default(),
+        }
+    }
+
+    /// Moves `mtime` to the `time` of the running hart, then updates the
+    /// machine timer interrupt in `mip` of it and the parked harts if one
+    /// rose or `mtimecmp` was written since the last call.
+    pub fn deliver(&self, cpu: &mut Cpu, harts: &mut [Hart]) {
+        let now = cpu.csrs[RDTIME];
+        self.mtime.store(now, Ordering::Relaxed);
+        if !self.changed.swap(false, Ordering::Acquire) && now < self.next.load(Ordering::Relaxed) {
+            return;
+        }
+        let mtimecmp = self.mtimecmp.lock().unwrap();
+        let next = mtimecmp.iter().copied().filter(|x| *x > now).min();
+        self.next.store(next.unwrap_or(u64::MAX), Ordering::Relaxed);
+        let due = |id: u64| mtimecmp.get(id as usize).is_some_and(|x| now >= *x);
+        cpu.set_pending(Interrupt::MachineTimer, due(cpu.hart_id()));
+        let bit = 1 << Interrupt::MachineTimer.code();
+        for hart in harts {
+            match due(hart.id()) {
+                true => hart.csrs[MIP] |= bit,
+                false => hart.csrs[MIP] &= !bit,
+            }
+        }
+    }
+
+    fn register(&self, offset: u64) -> u64 {
+        match offset {
+            MTIME => self.mtime.load(Ordering::Relaxed),
+            _ => {
+                let mtimecmp = self.mtimecmp.lock().unwrap();
+                mtimecmp.get((offset / 8) as usize).copied().unwrap_or(0)
+            }
+        }
+    }
+}
+
+impl Default for Mtimer {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Device for Mtimer {
+    // RV32 accesses the 64-bit registers a 32-bit half at a time
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let value = self.register(offset & !7) >> ((offset & 4) * 8);
+        Ok(match size {
+            64 => value,
+            _ => value & ((1 << size) - 1),
+        })
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        if offset & !7 == MTIME {
+            return Ok(());
+        }
+        let mut mtimecmp = self.mtimecmp.lock().unwrap();
+        let Some(register) = mtimecmp.get_mut((offset / 8) as usize) else {
+            return Ok(());
+        };
+        let shift = (offset & 4) * 8;
+        let mask = match size {
+            64 => u64::MAX,
+            _ => ((1 << size) - 1) << shift,
+        };
+        *register = *register & !mask | (value << shift) & mask;
+        self.changed.store(true, Ordering::Release);
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Background execution of slow host operations for devices. A device submits
+//! the blocking part of a request (a disk read, a network send) through its
+//! [`BackendHandle`], it runs on a worker thread, and the completion runs on
+//! the emulation thread at the next [`Machine::step`](crate::machine::Machine::step)
+//! where it can write guest memory and raise an interrupt. The guest keeps
+//! executing in the meantime.
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use crate::cpu::Cpu;
+
+type Job = Box<dyn FnOnce() -> Completion + Send>;
+type Completion = Box<dyn FnOnce(&mut Cpu) + Send>;
+
+/// Worker threads and the queue of finished requests.
+#[derive(Debug)]
+pub struct Backend {
+    handle: BackendHandle,
+    completions: Receiver<Completion>,
+}
+
+/// Submits requests to a [`Backend`], cheap to clone into devices.
+#[derive(Debug, Clone)]
+pub struct BackendHandle {
+    jobs: Sender<Job>,
+    /// Requests submitted but not completed yet.
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl Backend {
+    /// Starts `workers` threads, which exit once the backend and all handles
+    /// are dropped.
+    pub fn new(workers: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (done, completions) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for _ in 0..workers.max(1) {
+            let queue = queue.clone();
+            let done = done.clone();
+            thread::spawn(move || loop {
+                let job = match queue.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                };
+                if done.send(job()).is_err() {
+                    return;
+                }
+            });
+        }
+
+        Self {
+            handle: BackendHandle {
+                jobs,
+                in_flight: Arc::new(AtomicUsize::new(0)),
+            },
+            completions,
+        }
+    }
+
+    pub fn handle(&self) -> BackendHandle {
+        self.handle.clone()
+    }
+
+    /// Whether a device holds a handle and may submit requests.
+    pub fn is_used(&self) -> bool {
+        Arc::strong_count(&self.handle.in_flight) > 1
+    }
+
+    /// Runs the completions of finished requests, returning how many ran.
+    pub fn poll(&self, cpu: &mut Cpu) -> usize {
+        let mut n = 0;
+        while let Ok(complete) = self.completions.try_recv() {
+            complete(cpu);
+            self.handle.in_flight.fetch_sub(1, Ordering::Relaxed);
+            n += 1;
+        }
+        n
+    }
+
+    /// Blocks until every submitted request completed, running the completions.
+    pub fn drain(&self, cpu: &mut Cpu) {
+        while self.handle.in_flight() > 0 {
+            match self.completions.recv() {
+                Ok(complete) => {
+                    complete(cpu);
+                    self.handle.in_flight.fetch_sub(1, Ordering::Relaxed);
+                }
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+impl BackendHandle {
+    /// Runs `work` on a worker thread, then `complete` with its result on the
+    /// emulation thread.
+    pub fn submit<T: Send + 'static>(
+        &self,
+        work: impl FnOnce() -> T + Send + 'static,
+        complete: impl FnOnce(T, &mut Cpu) + Send + 'static,
+    ) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let job: Job = Box::new(move || {
+            let result = work();
+            Box::new(move |cpu: &mut Cpu| complete(result, cpu))
+        });
+        if self.jobs.send(job).is_err() {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,136 @@
+//! `cargo rysk test`: builds the tests of a package for a RISC-V target and
+//! runs every test executable under rysk, reporting like `cargo test`.
+//!
+//! ```sh
+//! cargo rysk test --target riscv64gc-unknown-none-elf -- --package firmware
+//! cargo rysk test target/riscv64gc-unknown-none-elf/debug/deps/firmware-*
+//! ```
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{self, ExitCode, Stdio},
+};
+
+use clap::{Args, Parser, Subcommand};
+use rysk::{
+    machine::{parse_size, EbreakPolicy, Machine},
+    runner::{self, Outcome, Report},
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "cargo", bin_name = "cargo")]
+enum Cargo {
+    #[command(subcommand, version)]
+    Rysk(Rysk),
+}
+
+/// Runs RISC-V programs under rysk.
+#[derive(Debug, Subcommand)]
+enum Rysk {
+    /// Build the tests with cargo and run each test executable.
+    Test(TestArgs),
+}
+
+#[derive(Debug, Args)]
+struct TestArgs {
+    /// Test executables to run instead of building them.
+    executables: Vec<PathBuf>,
+    /// Target the tests are built for.
+    #[arg(long, default_value = "riscv64gc-unknown-none-elf")]
+    target: String,
+    /// Size of the dram, e.g. 128M or 1G.
+    #[arg(long, value_parser = parse_memory)]
+    memory: Option<u64>,
+    /// ISA string, e.g. rv64ima_zicsr.
+    #[arg(long)]
+    isa: Option<String>,
+    /// Don't service semihosting calls.
+    #[arg(long)]
+    no_semihosting: bool,
+    /// What an ebreak outside of a semihosting call does: stop, or exit with a0.
+    #[arg(long, default_value = "stop")]
+    ebreak: EbreakPolicy,
+    /// Fail tests that don't stop within this many instructions.
+    #[arg(long, default_value_t = 100_000_000)]
+    max_instructions: u64,
+    /// Arguments passed to `cargo test --no-run`.
+    #[arg(last = true)]
+    cargo_args: Vec<String>,
+}
+
+impl TestArgs {
+    fn run(&self, path: &Path) -> Outcome {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => return Outcome::Failed(e.to_string()),
+        };
+        let mut builder = Machine::builder()
+            .elf(data)
+            .semihosting(!self.no_semihosting)
+            .ebreak(self.ebreak);
+        if let Some(memory) = self.memory {
+            builder = builder.memory(memory);
+        }
+        if let Some(isa) = &self.isa {
+            builder = builder.isa(isa);
+        }
+        match builder.build() {
+            Ok(mut machine) => runner::run(&mut machine, self.max_instructions),
+            Err(e) => Outcome::Failed(e.to_string()),
+        }
+    }
+
+    /// Builds the tests, returning the executables cargo reports.
+    fn build(&self) -> Result<Vec<PathBuf>, String> {
+        let cargo = env::var("CARGO").unwrap_or_else(|_| String::from("cargo"));
+        let output = process::Command::new(cargo)
+            .args([
+                "test",
+                "--no-run",
+                "--message-format=json-render-diagnostics",
+            ])
+            .args(["--target", &self.target])
+            .args(&self.cargo_args)
+            .stderr(Stdio::inherit())
+            .output()
+            .map_err(|e| format!("failed to run cargo: {e}"))?;
+        if !output.status.success() {
+            return Err(format!("cargo test --no-run failed with {}", output.status));
+        }
+        Ok(runner::test_executables(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+}
+
+fn parse_memory(s: &str) -> Result<u64, String> {
+    parse_size(s).ok_or_else(|| format!("invalid memory size {s}"))
+}
+
+fn main() -> ExitCode {
+    let Cargo::Rysk(Rysk::Test(args)) = Cargo::parse();
+
+    let executables = if args.executables.is_empty() {
+        match args.build() {
+            Ok(executables) => executables,
+            Err(e) => {
+                eprintln!("error: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        args.executables.clone()
+    };
+
+    let mut report = Report::new(executables.len());
+    for path in &executables {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        report.record(&name.to_string_lossy(), args.run(path));
+    }
+    if report.finish() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,52 @@
+//! Several machines in one process, networked through a shared [`Hub`] and
+//! run in turns of a fixed number of instructions so runs are reproducible.
+
+use crate::{
+    error::EmulatorError,
+    machine::{ExitReason, Machine, MachineBuilder, StopCondition},
+    net::{default_mac, Hub},
+};
+
+/// Instructions each machine runs before the next one gets its turn.
+pub const QUANTUM: u64 = 10_000;
+
+#[derive(Debug, Default)]
+pub struct Cluster {
+    pub hub: Hub,
+    pub machines: Vec<Machine>,
+}
+
+impl Cluster {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a machine with a network card on the hub, returning its index.
+    pub fn add(&mut self, builder: MachineBuilder) -> Result<usize, EmulatorError> {
+        let index = self.machines.len();
+        let mac = default_mac(index as u8);
+        self.machines.push(builder.nic(&self.hub, mac).build()?);
+        Ok(index)
+    }
+
+    /// Runs every machine until it stops, returning why each did.
+    pub fn run(&mut self) -> Vec<ExitReason> {
+        let turn = StopCondition {
+            max_instructions: Some(QUANTUM),
+            ..StopCondition::default()
+        };
+        let mut reasons = vec![None; self.machines.len()];
+        while reasons.iter().any(Option::is_none) {
+            for (machine, reason) in self.machines.iter_mut().zip(&mut reasons) {
+                if reason.is_some() {
+                    continue;
+                }
+                match machine.run_until(&turn) {
+                    ExitReason::MaxInstructions => {}
+                    stopped => *reason = Some(stopped),
+                }
+            }
+        }
+        reasons.into_iter().flatten().collect()
+    }
+}
@@ -0,0 +1,156 @@
+//! The host terminal as the guest console: stdin is read on a background
+//! thread and, when it is a terminal, put into raw mode so keystrokes such as
+//! Ctrl-C reach the guest. Ctrl-A x quits, Ctrl-A Ctrl-A sends a literal Ctrl-A.
+
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+/// Prefix of the escape sequences, Ctrl-A.
+pub const ESCAPE: u8 = 0x01;
+
+/// Keystrokes for the guest console devices.
+pub struct Console {
+    input: Receiver<u8>,
+    quit: Arc<AtomicBool>,
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl std::fmt::Debug for Console {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Console")
+            .field("quit", &self.quit)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Console {
+    /// Reads stdin, in raw mode until dropped if it is a terminal.
+    pub fn stdin() -> io::Result<Self> {
+        #[cfg(unix)]
+        let saved = raw_mode()?;
+        #[allow(unused_mut)]
+        let mut console = Self::from_reader(io::stdin());
+        #[cfg(unix)]
+        {
+            console.saved = saved;
+        }
+        Ok(console)
+    }
+
+    /// Reads keystrokes from `reader` instead of the terminal.
+    pub fn from_reader(mut reader: impl Read + Send + 'static) -> Self {
+        let (sender, input) = mpsc::channel();
+        let quit = Arc::new(AtomicBool::new(false));
+
+        let flag = quit.clone();
+        thread::spawn(move || {
+            let mut escaped = false;
+            let mut buf = [0; 64];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                for &c in &buf[..n] {
+                    match (escaped, c) {
+                        (false, ESCAPE) => {
+                            escaped = true;
+                            continue;
+                        }
+                        (true, b'x' | b'X') => {
+                            // Dropping the sender wakes up blocked readers.
+                            flag.store(true, Ordering::Relaxed);
+                            return;
+                        }
+                        (true, ESCAPE) | (false, _) => {
+                            if sender.send(c).is_err() {
+                                return;
+                            }
+                        }
+                        // Unknown sequences are dropped.
+                        (true, _) => {}
+                    }
+                    escaped = false;
+                }
+            }
+        });
+
+        Self {
+            input,
+            quit,
+            #[cfg(unix)]
+            saved: None,
+        }
+    }
+
+    /// Next keystroke, if one is waiting.
+    pub fn read(&self) -> Option<u8> {
+        self.input.try_recv().ok()
+    }
+
+    /// Waits for the next keystroke, `None` once the input ended or the user quit.
+    pub fn read_blocking(&self) -> Option<u8> {
+        self.input.recv().ok()
+    }
+
+    /// Fills `buf` with the keystrokes waiting after the next one, returning
+    /// how many were read, 0 once the input ended.
+    pub fn read_some(&self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        for x in buf.iter_mut() {
+            let c = if n == 0 {
+                self.read_blocking()
+            } else {
+                self.read()
+            };
+            match c {
+                Some(c) => *x = c,
+                None => break,
+            }
+            n += 1;
+        }
+        n
+    }
+
+    /// Whether Ctrl-A x was pressed.
+    pub fn quit_requested(&self) -> bool {
+        self.quit.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = &self.saved {
+            // SAFETY: restores the attributes read by `raw_mode`.
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) };
+        }
+    }
+}
+
+/// Switches a terminal stdin to raw mode, returning the attributes to restore.
+#[cfg(unix)]
+fn raw_mode() -> io::Result<Option<libc::termios>> {
+    // SAFETY: termios is plain data, filled in by tcgetattr before use.
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return Ok(None);
+        }
+        let mut saved = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        libc::cfmakeraw(&mut raw);
+        // Keep translating the guest's \n so host output stays readable.
+        raw.c_oflag |= libc::OPOST | libc::ONLCR;
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(saved))
+    }
+}
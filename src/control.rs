@@ -0,0 +1,516 @@
+//! HTTP control endpoint for a running machine, for dashboards and CI farms:
+//!
+//! - `GET /status`: state, pc, retired instructions and exit code
+//! - `POST /pause`, `POST /resume`, `POST /quit`
+//! - `GET /regs`: pc and x0-x31
+//! - `GET /mem?addr=A&len=N`: hex of N bytes at A
+//! - `POST /mem?addr=A` with hex in the body: writes it at A
+//! - `POST /interrupt?code=N[&clear]`: raises or lowers an interrupt in `mip`
+//! - `GET /snapshot`: the machine state, as `rysk snapshot` writes it
+//! - `GET /stats`: counters
+//! - `GET /gpio`: the levels of the pins, those the guest drives and their
+//!   levels, a bit each
+//! - `POST /gpio?pin=N&level=0|1`: drives an input pin
+//! - `GET /metrics`: retired instructions, MIPS, traps and device I/O in the
+//!   Prometheus text format, for scraping
+//!
+//! Replies are JSON, except for `/snapshot` and `/metrics`.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::{
+    cpu::{INSTRET, MIP, RDCYCLE, RDTIME},
+    debugger::parse_number,
+    gpio::GPIO_PINS,
+    machine::{ExitReason, Machine, StopCondition},
+    snapshot::Snapshot,
+    stats::Stats,
+};
+
+/// How many instructions run between checks for requests.
+const POLL_INTERVAL: u64 = 10_000;
+
+const ENDPOINTS: &[&str] = &[
+    "/status",
+    "/pause",
+    "/resume",
+    "/quit",
+    "/regs",
+    "/mem",
+    "/interrupt",
+    "/snapshot",
+    "/stats",
+    "/gpio",
+    "/metrics",
+];
+
+/// Largest read accepted by `/mem`.
+const MAX_READ: u64 = 1 << 20;
+
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn number(&self, name: &str) -> Result<u64, Response> {
+        let value = self
+            .param(name)
+            .ok_or_else(|| Response::error(400, &format!("missing {name}")))?;
+        parse_number(value).ok_or_else(|| Response::error(400, &format!("invalid {name}")))
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn json(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: body.into_bytes(),
+        }
+    }
+
+    fn ok() -> Self {
+        Self::json(String::from("{}"))
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            ..Self::json(format!("{{\"error\":{message:?}}}"))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Running,
+    Paused,
+    Quit,
+}
+
+/// Accepts control requests on a socket and services them while running a machine.
+#[derive(Debug)]
+pub struct Control {
+    addr: SocketAddr,
+    requests: Receiver<(Request, Sender<Response>)>,
+    state: State,
+}
+
+impl Control {
+    /// Listens on `addr`, requests are queued until [`run`](Self::run) services them.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        info!("control endpoint on http://{addr}");
+
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| serve_connection(stream, &sender));
+                if let Err(e) = result {
+                    debug!("control connection: {e}");
+                }
+            }
+        });
+
+        Ok(Self {
+            addr,
+            requests,
+            state: State::Running,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Starts paused, waiting for `/resume`.
+    pub fn paused(mut self) -> Self {
+        self.state = State::Paused;
+        self
+    }
+
+    /// Runs `machine` until it stops by itself or `/quit` is requested,
+    /// which is reported as [`ExitReason::HostRequest`]. A [`Stats`] observer
+    /// is attached if the machine has none, for `/metrics`.
+    pub fn run(&mut self, machine: &mut Machine) -> ExitReason {
+        if machine.cpu.observers.get::<Stats>().is_none() {
+            let stats = Stats::new().regions(&machine.cpu.bus);
+            machine.cpu.observers.add(stats);
+        }
+        let slice = StopCondition {
+            max_instructions: Some(POLL_INTERVAL),
+            ..StopCondition::default()
+        };
+        loop {
+            match self.state {
+                State::Quit => return ExitReason::HostRequest,
+                State::Paused => match self.requests.recv_timeout(Duration::from_millis(100)) {
+                    Ok((request, reply)) => self.reply(machine, request, reply),
+                    Err(RecvTimeoutError::Timeout) if !machine.quit_requested() => {}
+                    Err(_) => return ExitReason::HostRequest,
+                },
+                State::Running => {
+                    match machine.run_until(&slice) {
+                        ExitReason::MaxInstructions => {}
+                        reason => return reason,
+                    }
+                    while let Ok((request, reply)) = self.requests.try_recv() {
+                        self.reply(machine, request, reply);
+                    }
+                }
+            }
+        }
+    }
+
+    fn reply(&mut self, machine: &mut Machine, request: Request, reply: Sender<Response>) {
+        debug!(
+            method = request.method,
+            path = request.path,
+            "control request"
+        );
+        let response = self
+            .handle(machine, &request)
+            .unwrap_or_else(|response| response);
+        let _ = reply.send(response);
+    }
+
+    fn handle(&mut self, machine: &mut Machine, request: &Request) -> Result<Response, Response> {
+        let cpu = &mut machine.cpu;
+        let response = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => {
+                let state = match self.state {
+                    State::Running => "running",
+                    State::Paused => "paused",
+                    State::Quit => "quit",
+                };
+                let exit_code = match machine.exit_code() {
+                    Some(code) => code.to_string(),
+                    None => String::from("null"),
+                };
+                Response::json(format!(
+                    "{{\"state\":\"{state}\",\"pc\":{},\"instret\":{},\"exit_code\":{exit_code}}}",
+                    machine.cpu.pc, machine.cpu.csrs[INSTRET]
+                ))
+            }
+            ("POST", "/pause") => {
+                self.state = State::Paused;
+                Response::ok()
+            }
+            ("POST", "/resume") => {
+                self.state = State::Running;
+                Response::ok()
+            }
+            ("POST", "/quit") => {
+                self.state = State::Quit;
+                Response::ok()
+            }
+            ("GET", "/regs") => {
+                let regs = cpu.regs.map(|x| x.to_string()).join(",");
+                Response::json(format!("{{\"pc\":{},\"x\":[{regs}]}}", cpu.pc))
+            }
+            ("GET", "/mem") => {
+                let addr = request.number("addr")?;
+                let len = request.number("len")?;
+                if len > MAX_READ {
+                    return Err(Response::error(400, "len is too large"));
+                }
+                let mut data = String::new();
+                for i in 0..len {
+                    let byte = cpu
+                        .bus
+                        .load(addr.wrapping_add(i), 8)
+                        .map_err(|e| Response::error(400, &e.to_string()))?;
+                    let _ = write!(data, "{byte:02x}");
+                }
+                Response::json(format!("{{\"addr\":{addr},\"data\":\"{data}\"}}"))
+            }
+            ("POST", "/mem") => {
+                let addr = request.number("addr")?;
+                let data = parse_hex(&request.body)
+                    .ok_or_else(|| Response::error(400, "the body should be hex"))?;
+                for (i, byte) in data.iter().enumerate() {
+                    cpu.bus
+                        .store(addr.wrapping_add(i as u64), 8, *byte as u64)
+                        .map_err(|e| Response::error(400, &e.to_string()))?;
+                }
+                Response::ok()
+            }
+            ("POST", "/interrupt") => {
+                let code = request.number("code")?;
+                if code >= 64 {
+                    return Err(Response::error(400, "invalid code"));
+                }
+                match request.param("clear") {
+                    Some(_) => cpu.csrs[MIP] &= !(1 << code),
+                    None => cpu.csrs[MIP] |= 1 << code,
+                }
+                Response::ok()
+            }
+            ("GET", "/snapshot") => {
+                let mut body = Vec::new();
+                Snapshot::capture(cpu)
+                    .write_to(&mut body)
+                    .map_err(|e| Response::error(500, &e.to_string()))?;
+                Response {
+                    status: 200,
+                    content_type: "application/octet-stream",
+                    body,
+                }
+            }
+            ("GET", "/stats") => Response::json(format!(
+                "{{\"instret\":{},\"cycle\":{},\"time\":{},\"backend_in_flight\":{}}}",
+                cpu.csrs[INSTRET],
+                cpu.csrs[RDCYCLE],
+                cpu.csrs[RDTIME],
+                machine.backend.handle().in_flight()
+            )),
+            ("GET", "/gpio") => {
+                let gpio = machine.gpio.as_ref().ok_or_else(no_gpio)?;
+                Response::json(format!(
+                    "{{\"levels\":{},\"output_enable\":{},\"outputs\":{}}}",
+                    gpio.levels(),
+                    gpio.output_enable(),
+                    gpio.outputs()
+                ))
+            }
+            ("POST", "/gpio") => {
+                let gpio = machine.gpio.as_ref().ok_or_else(no_gpio)?;
+                let pin = request.number("pin")?;
+                if pin >= u64::from(GPIO_PINS) {
+                    return Err(Response::error(400, "invalid pin"));
+                }
+                let high = match request.param("level") {
+                    Some("0") => false,
+                    Some("1") => true,
+                    _ => return Err(Response::error(400, "level should be 0 or 1")),
+                };
+                gpio.set_input(pin as u32, high);
+                Response::ok()
+            }
+            ("GET", "/metrics") => Response {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: metrics(machine).into_bytes(),
+            },
+            (_, path) if ENDPOINTS.contains(&path) => Response::error(405, "method not allowed"),
+            _ => Response::error(404, "not found"),
+        };
+        Ok(response)
+    }
+}
+
+/// The metrics of `machine` in the Prometheus text exposition format, those
+/// of its [`Stats`] observer if it has one.
+fn metrics(machine: &Machine) -> String {
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(out, "# HELP rysk_{name} {help}\n# TYPE rysk_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "rysk_{name}{labels} {value}");
+        }
+    };
+    let value = |x: u64| vec![(String::new(), x.to_string())];
+
+    let cpu = &machine.cpu;
+    metric(
+        "instructions_retired_total",
+        "counter",
+        "Instructions retired by the hart.",
+        &value(cpu.csrs[INSTRET]),
+    );
+    metric(
+        "cycles_total",
+        "counter",
+        "Cycles of the hart.",
+        &value(cpu.csrs[RDCYCLE]),
+    );
+    metric(
+        "backend_in_flight",
+        "gauge",
+        "Requests in flight to the host backend.",
+        &value(machine.backend.handle().in_flight() as u64),
+    );
+
+    let Some(stats) = cpu.observers.get::<Stats>() else {
+        return out;
+    };
+    let c = &stats.counters;
+    metric(
+        "mips",
+        "gauge",
+        "Millions of instructions per second since the start of the run.",
+        &[(String::new(), format!("{:.3}", stats.mips()))],
+    );
+    metric(
+        "interrupts_total",
+        "counter",
+        "Interrupts taken.",
+        &value(c.interrupts),
+    );
+    metric(
+        "exceptions_total",
+        "counter",
+        "Exceptions taken.",
+        &value(c.exceptions),
+    );
+    metric(
+        "mmio_accesses_total",
+        "counter",
+        "Loads and stores to devices.",
+        &[
+            (String::from("{kind=\"read\"}"), c.mmio_reads.to_string()),
+            (String::from("{kind=\"write\"}"), c.mmio_writes.to_string()),
+        ],
+    );
+
+    let mut samples = Vec::new();
+    for region in stats.regions.iter().filter(|x| !x.traffic.is_empty()) {
+        let t = &region.traffic;
+        let kinds = [("load", &t.loads), ("store", &t.stores)];
+        for (kind, counts) in kinds {
+            for (width, count) in [8, 16, 32, 64].iter().zip(counts) {
+                samples.push((
+                    format!(
+                        "{{region=\"{}\",base=\"{:#x}\",kind=\"{kind}\",width=\"{width}\"}}",
+                        region.name, region.base
+                    ),
+                    count.to_string(),
+                ));
+            }
+        }
+    }
+    metric(
+        "memory_accesses_total",
+        "counter",
+        "Loads and stores by bus region and access width in bits.",
+        &samples,
+    );
+    out
+}
+
+fn no_gpio() -> Response {
+    Response::error(404, "the machine has no gpio")
+}
+
+fn parse_hex(body: &[u8]) -> Option<Vec<u8>> {
+    let body = std::str::from_utf8(body).ok()?.trim();
+    if body.len() % 2 != 0 {
+        return None;
+    }
+    (0..body.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(body.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reads one request, hands it to the machine thread and writes the reply.
+fn serve_connection(
+    stream: TcpStream,
+    requests: &Sender<(Request, Sender<Response>)>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let request = match read_request(&mut reader)? {
+        Some(request) => request,
+        None => return Ok(()),
+    };
+
+    let (reply, response) = mpsc::channel();
+    let response = match requests.send((request, reply)) {
+        Ok(()) => response
+            .recv()
+            .unwrap_or_else(|_| Response::error(503, "the machine stopped")),
+        Err(_) => Response::error(503, "the machine stopped"),
+    };
+    write_response(stream, &response)
+}
+
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        warn!(line, "malformed control request");
+        return Ok(None);
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let query = query
+        .split('&')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let (key, value) = x.split_once('=').unwrap_or((x, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; length.min(MAX_READ as usize * 2)];
+    reader.read_exact(&mut body)?;
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query,
+        body,
+    }))
+}
+
+fn write_response(mut stream: TcpStream, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
@@ -0,0 +1,254 @@
+//! Lockstep co-simulation, with rysk as the golden model of a hardware
+//! design under verification. The testbench retires an instruction in its
+//! simulator and hands rysk the [`Commit`] it saw; rysk executes the same
+//! instruction and compares the pc, the instruction bits, the register
+//! written and any trap. Only the running hart is compared.
+//!
+//! [`serve`] drives a machine from a socket, a command and a reply per line:
+//!
+//! - `step`: executes an instruction, replies with its commit
+//! - `check COMMIT`: executes an instruction, replies `ok` if its commit
+//!   matches, `mismatch COMMIT` with rysk's commit otherwise
+//! - `regs`: pc and x1-x31, in the commit format
+//! - `set pc|xN VALUE`: overrides state the design decides, e.g. a value
+//!   read from a device only the design models, replies `ok`
+//! - `mem ADDR LEN`: hex of LEN bytes at ADDR
+//! - `quit`
+//!
+//! Commits are `key=value` fields: `pc=0x80000000 inst=0x00150513 x10=0x1`,
+//! with `trap=CAUSE,TVAL` instead of the register when the instruction
+//! raised an exception. Errors reply `error MESSAGE`.
+//!
+//! The C API offers the same through `rysk_step_commit` and `rysk_check_commit`.
+
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, ToSocketAddrs},
+    str::FromStr,
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    compressed::{expand, expand_rv32, is_compressed},
+    debugger::parse_number,
+    isa::Xlen,
+    machine::Machine,
+};
+
+/// Largest read accepted by `mem`.
+const MAX_READ: u64 = 1 << 20;
+
+/// What an instruction did, as a core's retirement port reports it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Commit {
+    pub pc: u64,
+    pub inst: u32,
+    /// Register written and its new value, none for x0 and instructions
+    /// without a destination.
+    pub write: Option<(usize, u64)>,
+    /// `mcause` and `mtval` of the exception raised instead of retiring.
+    pub trap: Option<(u64, u64)>,
+}
+
+impl fmt::Display for Commit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pc={:#x} inst={:#010x}", self.pc, self.inst)?;
+        if let Some((rd, value)) = self.write {
+            write!(f, " x{rd}={value:#x}")?;
+        }
+        if let Some((cause, tval)) = self.trap {
+            write!(f, " trap={cause},{tval:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Commit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut commit = Commit::default();
+        let (mut pc, mut inst) = (None, None);
+        for field in s.split_whitespace() {
+            let invalid = || format!("invalid commit field {field}");
+            let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+            match key {
+                "pc" => pc = Some(parse_number(value).ok_or_else(invalid)?),
+                "inst" => {
+                    let value = parse_number(value).ok_or_else(invalid)?;
+                    inst = Some(u32::try_from(value).map_err(|_| invalid())?);
+                }
+                "trap" => {
+                    let (cause, tval) = value.split_once(',').ok_or_else(invalid)?;
+                    let cause = parse_number(cause).ok_or_else(invalid)?;
+                    let tval = parse_number(tval).ok_or_else(invalid)?;
+                    commit.trap = Some((cause, tval));
+                }
+                _ => {
+                    let rd = register(key).ok_or_else(invalid)?;
+                    let value = parse_number(value).ok_or_else(invalid)?;
+                    commit.write = (rd != 0).then_some((rd, value));
+                }
+            }
+        }
+        commit.pc = pc.ok_or("missing pc")?;
+        commit.inst = inst.ok_or("missing inst")?;
+        Ok(commit)
+    }
+}
+
+fn register(s: &str) -> Option<usize> {
+    s.strip_prefix('x')?.parse().ok().filter(|x| *x < 32)
+}
+
+/// Whether the instruction, a 32-bit one or the expansion of a compressed
+/// one, has a destination register.
+fn writes_rd(inst: u32) -> bool {
+    let funct3 = (inst >> 12) & 7;
+    match inst & 0x7f {
+        // loads, op-imm, auipc, op-imm-32, amo, op, lui, op-32, jalr, jal
+        0x03 | 0x13 | 0x17 | 0x1b | 0x2f | 0x33 | 0x37 | 0x3b | 0x67 | 0x6f => true,
+        // floating point compares, conversions to integers, fmv.x and fclass
+        0x53 => matches!(inst >> 27, 0x14 | 0x18 | 0x1c),
+        // vsetvl and friends, vmv.x.s
+        0x57 => funct3 == 7 || (funct3 == 2 && inst >> 26 == 0x10),
+        // csr instructions
+        0x73 => funct3 != 0,
+        _ => false,
+    }
+}
+
+/// Executes an instruction of the running hart and reports it. A `wfi`
+/// retires as a nop, the testbench decides when the design wakes up.
+pub fn step(machine: &mut Machine) -> Commit {
+    // the commit is the first instruction of the handler
+    machine.cpu.take_interrupt();
+    let pc = machine.cpu.pc;
+    let mut inst = machine.cpu.bus.load(pc, 32).unwrap_or(0) as u32;
+    // compressed instructions are reported as fetched, 16 bits wide
+    let expanded = match is_compressed(inst as u64) {
+        true => {
+            inst &= 0xffff;
+            let expand = match machine.cpu.isa.xlen {
+                Xlen::Rv32 => expand_rv32,
+                Xlen::Rv64 => expand,
+            };
+            expand(inst as u16).unwrap_or(0)
+        }
+        false => inst,
+    };
+    let trap = match machine.step_trap() {
+        Ok(trap) => trap,
+        Err(e) => Some(e),
+    }
+    .map(|e| (e.code(), e.value()));
+    let rd = ((expanded >> 7) & 0x1f) as usize;
+    let write =
+        (trap.is_none() && rd != 0 && writes_rd(expanded)).then(|| (rd, machine.cpu.regs[rd]));
+    Commit {
+        pc,
+        inst,
+        write,
+        trap,
+    }
+}
+
+/// Executes an instruction and compares it with what the design retired,
+/// returning rysk's commit either way.
+pub fn check(machine: &mut Machine, expected: &Commit) -> Result<Commit, Commit> {
+    let actual = step(machine);
+    match actual == *expected {
+        true => Ok(actual),
+        false => Err(actual),
+    }
+}
+
+/// Serves a single testbench connection on `addr`, until it quits or
+/// disconnects.
+pub fn serve(machine: &mut Machine, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("waiting for a testbench on {}", listener.local_addr()?);
+    serve_listener(machine, &listener)
+}
+
+/// Like [`serve`], with a bound listener, e.g. on port 0.
+pub fn serve_listener(machine: &mut Machine, listener: &TcpListener) -> io::Result<()> {
+    let (stream, peer) = listener.accept()?;
+    info!("testbench connected from {peer}");
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        debug!(line, "cosim command");
+        let Some(reply) = handle(machine, line.trim()) else {
+            return Ok(());
+        };
+        writeln!(writer, "{reply}")?;
+    }
+    Ok(())
+}
+
+/// Returns the reply, or `None` to end the session.
+fn handle(machine: &mut Machine, line: &str) -> Option<String> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    let reply = match command {
+        "step" => step(machine).to_string(),
+        "check" => match args.parse::<Commit>() {
+            Ok(expected) => match check(machine, &expected) {
+                Ok(_) => String::from("ok"),
+                Err(actual) => format!("mismatch {actual}"),
+            },
+            Err(e) => format!("error {e}"),
+        },
+        "regs" => {
+            let mut reply = format!("pc={:#x}", machine.cpu.pc);
+            for (i, value) in machine.cpu.regs.iter().enumerate().skip(1) {
+                reply += &format!(" x{i}={value:#x}");
+            }
+            reply
+        }
+        "set" => set(machine, args).unwrap_or_else(|e| format!("error {e}")),
+        "mem" => mem(machine, args).unwrap_or_else(|e| format!("error {e}")),
+        "quit" => return None,
+        _ => format!("error unknown command {command}"),
+    };
+    Some(reply)
+}
+
+fn set(machine: &mut Machine, args: &str) -> Result<String, String> {
+    let (target, value) = args
+        .split_once(' ')
+        .ok_or("expected a target and a value")?;
+    let value = parse_number(value.trim()).ok_or("invalid value")?;
+    match target {
+        "pc" => machine.cpu.pc = value,
+        _ => match register(target).ok_or("invalid register")? {
+            0 => {}
+            rd => machine.cpu.regs[rd] = value,
+        },
+    }
+    Ok(String::from("ok"))
+}
+
+fn mem(machine: &mut Machine, args: &str) -> Result<String, String> {
+    let (addr, len) = args
+        .split_once(' ')
+        .ok_or("expected an address and a length")?;
+    let addr = parse_number(addr).ok_or("invalid address")?;
+    let len = parse_number(len.trim()).ok_or("invalid length")?;
+    if len > MAX_READ {
+        return Err(format!("reads are limited to {MAX_READ} bytes"));
+    }
+    let mut hex = String::new();
+    for i in 0..len {
+        let byte = machine
+            .cpu
+            .bus
+            .load(addr.wrapping_add(i), 8)
+            .map_err(|e| e.to_string())?;
+        hex += &format!("{byte:02x}");
+    }
+    Ok(hex)
+}
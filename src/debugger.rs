@@ -0,0 +1,179 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+use crate::{compressed::is_compressed, cpu::Cpu, disasm::disassemble, exception::Exception};
+
+const HELP: &str = "\
+commands:
+  s, step [n]        execute n instructions (default 1)
+  c, continue        run until a breakpoint or an exception
+  b, break <addr>    set a breakpoint
+  d, delete <addr>   remove a breakpoint
+  r, regs            show the registers
+  x <addr> [n]       show n (default 4) words of memory
+  l, list [addr] [n] disassemble n (default 8) instructions
+  q, quit            exit
+";
+
+/// Interactive command line debugger.
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u64>,
+    /// Stop `continue` after this many instructions, so a runaway guest can't
+    /// hang the session.
+    pub max_instructions: Option<u64>,
+}
+
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u64),
+    Exception(Exception),
+    /// The guest executed the requested number of instructions.
+    Done,
+}
+
+impl Debugger {
+    /// Executes up to `count` instructions, stopping early at breakpoints
+    /// (other than the one at the starting pc) and exceptions.
+    pub fn step(&self, cpu: &mut Cpu, count: u64) -> Stop {
+        for i in 0..count {
+            if i != 0 && self.breakpoints.contains(&cpu.pc) {
+                return Stop::Breakpoint(cpu.pc);
+            }
+            if let Err(e) = cpu.step() {
+                return Stop::Exception(e);
+            }
+        }
+
+        if self.breakpoints.contains(&cpu.pc) {
+            return Stop::Breakpoint(cpu.pc);
+        }
+        Stop::Done
+    }
+
+    /// Reads commands from `input` until it ends or `quit` is entered.
+    pub fn repl(
+        &mut self,
+        cpu: &mut Cpu,
+        input: impl BufRead,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        write!(out, "(rysk) ")?;
+        out.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+
+            match (words.next(), words.next(), words.next()) {
+                (None, _, _) => {}
+                (Some("q" | "quit"), _, _) => break,
+                (Some("h" | "help"), _, _) => write!(out, "{HELP}")?,
+                (Some("s" | "step"), n, _) => match parse_or(n, 1) {
+                    Some(n) => {
+                        let stop = self.step(cpu, n);
+                        report(&mut out, cpu, stop)?;
+                    }
+                    None => writeln!(out, "invalid count")?,
+                },
+                (Some("c" | "continue"), _, _) => {
+                    let stop = self.step(cpu, self.max_instructions.unwrap_or(u64::MAX));
+                    report(&mut out, cpu, stop)?;
+                }
+                (Some("b" | "break"), Some(addr), _) => match parse_number(addr) {
+                    Some(addr) => {
+                        self.breakpoints.insert(addr);
+                        writeln!(out, "breakpoint at {addr:#x}")?;
+                    }
+                    None => writeln!(out, "invalid address")?,
+                },
+                (Some("d" | "delete"), Some(addr), _) => {
+                    match parse_number(addr).filter(|x| self.breakpoints.remove(x)) {
+                        Some(addr) => writeln!(out, "removed breakpoint at {addr:#x}")?,
+                        None => writeln!(out, "no such breakpoint")?,
+                    }
+                }
+                (Some("r" | "regs"), _, _) => {
+                    writeln!(out, "pc = {:#x}", cpu.pc)?;
+                    let mut regs = String::new();
+                    cpu.write_registers(&mut regs).unwrap();
+                    write!(out, "{regs}")?;
+                }
+                (Some("x"), Some(addr), n) => match (parse_number(addr), parse_or(n, 4)) {
+                    (Some(addr), Some(n)) => {
+                        for i in 0..n {
+                            let addr = addr.wrapping_add(i * 4);
+                            match cpu.bus.load(addr, 32) {
+                                Ok(x) => writeln!(out, "{addr:#x}: {x:#010x}")?,
+                                Err(e) => {
+                                    writeln!(out, "{e}")?;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    _ => writeln!(out, "invalid arguments")?,
+                },
+                (Some("l" | "list"), addr, n) => {
+                    let addr = addr.map_or(Some(cpu.pc), parse_number);
+                    match (addr, parse_or(n, 8)) {
+                        (Some(addr), Some(n)) => list(&mut out, cpu, addr, n)?,
+                        _ => writeln!(out, "invalid arguments")?,
+                    }
+                }
+                (Some(cmd), _, _) => writeln!(out, "unknown command '{cmd}', try 'help'")?,
+            }
+
+            write!(out, "(rysk) ")?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+fn report(out: &mut impl Write, cpu: &mut Cpu, stop: Stop) -> io::Result<()> {
+    match stop {
+        Stop::Breakpoint(addr) => writeln!(out, "breakpoint hit at {addr:#x}")?,
+        Stop::Exception(e) => writeln!(out, "exception: {e}")?,
+        Stop::Done => {}
+    }
+    let pc = cpu.pc;
+    list(out, cpu, pc, 1)
+}
+
+fn list(out: &mut impl Write, cpu: &mut Cpu, addr: u64, n: u64) -> io::Result<()> {
+    let mut addr = addr;
+    for _ in 0..n {
+        let Ok(inst) = cpu.bus.load(addr, 32) else {
+            writeln!(out, "{addr:#x}: <unmapped>")?;
+            break;
+        };
+        let (inst, len) = match is_compressed(inst) {
+            true => (inst & 0xffff, 2),
+            false => (inst, 4),
+        };
+        let text = disassemble(inst, addr).unwrap_or_else(|| String::from("<unknown>"));
+        match len {
+            2 => writeln!(out, "{addr:#x}: {inst:04x}      {text}")?,
+            _ => writeln!(out, "{addr:#x}: {inst:08x}  {text}")?,
+        }
+        addr = addr.wrapping_add(len);
+    }
+    Ok(())
+}
+
+/// Parses a decimal or `0x` prefixed hexadecimal number.
+pub fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_or(s: Option<&str>, default: u64) -> Option<u64> {
+    s.map_or(Some(default), parse_number)
+}
@@ -0,0 +1,283 @@
+//! A built-in strace for guests: every `ecall` is decoded as an SBI call
+//! (extension in a7, function in a6) or a Linux system call (number in a7),
+//! optionally logged with its arguments, and counted for a histogram.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use tracing::warn;
+
+use crate::hooks::{HookContext, Hooks};
+
+const ECALL: u64 = 0x00000073;
+
+/// How the registers of an `ecall` are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Abi {
+    /// Supervisor binary interface calls to the firmware.
+    Sbi,
+    /// System calls of a Linux program, as run by `rysk user`.
+    Linux,
+}
+
+/// A decoded call, ordered so the histogram groups extensions together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Call {
+    /// SBI extension or system call number.
+    pub id: u64,
+    /// SBI function, 0 for system calls.
+    pub function: u64,
+}
+
+struct Inner {
+    abi: Abi,
+    log: Option<Box<dyn Write + Send>>,
+    counts: BTreeMap<Call, u64>,
+}
+
+/// Clones share the same counts, so one can be kept to read them after
+/// [`attach`](Self::attach) hands another to the cpu.
+#[derive(Clone)]
+pub struct EcallTrace {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl fmt::Debug for EcallTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("EcallTrace")
+            .field("abi", &inner.abi)
+            .field("calls", &inner.counts.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl EcallTrace {
+    pub fn new(abi: Abi) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                abi,
+                log: None,
+                counts: BTreeMap::new(),
+            })),
+        }
+    }
+
+    /// Also writes a line per call, with its arguments, to `out`.
+    pub fn log(self, out: impl Write + Send + 'static) -> Self {
+        self.inner.lock().unwrap().log = Some(Box::new(out));
+        self
+    }
+
+    /// Traces the `ecall`s executed by the cpu owning `hooks`.
+    pub fn attach(&self, hooks: &mut Hooks) {
+        let trace = self.clone();
+        hooks.pre(move |ctx| {
+            if ctx.inst.raw == ECALL {
+                trace.record(ctx);
+            }
+        });
+    }
+
+    fn record(&self, ctx: &HookContext) {
+        let mut inner = self.inner.lock().unwrap();
+        let call = match inner.abi {
+            Abi::Sbi => Call {
+                id: ctx.regs[17],
+                function: ctx.regs[16],
+            },
+            Abi::Linux => Call {
+                id: ctx.regs[17],
+                function: 0,
+            },
+        };
+        *inner.counts.entry(call).or_default() += 1;
+
+        let name = name(inner.abi, call);
+        if let Some(out) = &mut inner.log {
+            let args: Vec<_> = ctx.regs[10..16].iter().map(|x| format!("{x:#x}")).collect();
+            let args = args.join(", ");
+            if let Err(e) = writeln!(out, "ecall {name}({args}) at pc {:#x}", ctx.pc) {
+                warn!("stopped logging ecalls: {e}");
+                inner.log = None;
+            }
+        }
+    }
+
+    /// Calls made, the most frequent first.
+    pub fn histogram(&self) -> Vec<(String, u64)> {
+        let inner = self.inner.lock().unwrap();
+        let mut calls: Vec<_> = inner
+            .counts
+            .iter()
+            .map(|(call, count)| (name(inner.abi, *call), *count))
+            .collect();
+        // Stable, so ties stay ordered by call.
+        calls.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        calls
+    }
+}
+
+impl fmt::Display for EcallTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let histogram = self.histogram();
+        let total: u64 = histogram.iter().map(|x| x.1).sum();
+        write!(f, "{total} ecalls")?;
+        for (name, count) in histogram {
+            write!(f, "\n{count:>10}  {name}")?;
+        }
+        Ok(())
+    }
+}
+
+/// `EXT.function` for SBI calls, the system call name for Linux, falling
+/// back to the numbers.
+pub fn name(abi: Abi, call: Call) -> String {
+    match abi {
+        Abi::Sbi => match sbi_name(call.id, call.function) {
+            (Some(ext), Some(function)) => format!("{ext}.{function}"),
+            (Some(ext), None) => format!("{ext}.{}", call.function),
+            _ => format!("sbi {:#x}.{}", call.id, call.function),
+        },
+        Abi::Linux => match syscall_name(call.id) {
+            Some(name) => String::from(name),
+            None => format!("syscall {}", call.id),
+        },
+    }
+}
+
+fn sbi_name(ext: u64, function: u64) -> (Option<&'static str>, Option<&'static str>) {
+    // Legacy extensions have a single function, ignoring a6.
+    let legacy = match ext {
+        0x00 => Some("set_timer"),
+        0x01 => Some("console_putchar"),
+        0x02 => Some("console_getchar"),
+        0x03 => Some("clear_ipi"),
+        0x04 => Some("send_ipi"),
+        0x05 => Some("remote_fence_i"),
+        0x06 => Some("remote_sfence_vma"),
+        0x07 => Some("remote_sfence_vma_asid"),
+        0x08 => Some("shutdown"),
+        _ => None,
+    };
+    if legacy.is_some() {
+        return (Some("legacy"), legacy);
+    }
+
+    let (ext, functions): (_, &[_]) = match ext {
+        0x10 => (
+            "BASE",
+            &[
+                "get_spec_version",
+                "get_impl_id",
+                "get_impl_version",
+                "probe_extension",
+                "get_mvendorid",
+                "get_marchid",
+                "get_mimpid",
+            ],
+        ),
+        0x54494D45 => ("TIME", &["set_timer"]),
+        0x735049 => ("IPI", &["send_ipi"]),
+        0x52464E43 => (
+            "RFNC",
+            &[
+                "remote_fence_i",
+                "remote_sfence_vma",
+                "remote_sfence_vma_asid",
+                "remote_hfence_gvma_vmid",
+                "remote_hfence_gvma",
+                "remote_hfence_vvma_asid",
+                "remote_hfence_vvma",
+            ],
+        ),
+        0x48534D => (
+            "HSM",
+            &["hart_start", "hart_stop", "hart_get_status", "hart_suspend"],
+        ),
+        0x53525354 => ("SRST", &["system_reset"]),
+        0x504D55 => (
+            "PMU",
+            &[
+                "num_counters",
+                "counter_get_info",
+                "counter_config_matching",
+                "counter_start",
+                "counter_stop",
+                "counter_fw_read",
+                "counter_fw_read_hi",
+            ],
+        ),
+        0x4442434E => (
+            "DBCN",
+            &["console_write", "console_read", "console_write_byte"],
+        ),
+        _ => return (None, None),
+    };
+    (Some(ext), functions.get(function as usize).copied())
+}
+
+fn syscall_name(nr: u64) -> Option<&'static str> {
+    let name = match nr {
+        17 => "getcwd",
+        23 => "dup",
+        24 => "dup3",
+        25 => "fcntl",
+        29 => "ioctl",
+        34 => "mkdirat",
+        35 => "unlinkat",
+        48 => "faccessat",
+        49 => "chdir",
+        56 => "openat",
+        57 => "close",
+        59 => "pipe2",
+        61 => "getdents64",
+        62 => "lseek",
+        63 => "read",
+        64 => "write",
+        65 => "readv",
+        66 => "writev",
+        67 => "pread64",
+        68 => "pwrite64",
+        78 => "readlinkat",
+        79 => "newfstatat",
+        80 => "fstat",
+        93 => "exit",
+        94 => "exit_group",
+        96 => "set_tid_address",
+        98 => "futex",
+        99 => "set_robust_list",
+        101 => "nanosleep",
+        113 => "clock_gettime",
+        129 => "kill",
+        130 => "tkill",
+        131 => "tgkill",
+        132 => "sigaltstack",
+        134 => "rt_sigaction",
+        135 => "rt_sigprocmask",
+        160 => "uname",
+        169 => "gettimeofday",
+        172 => "getpid",
+        173 => "getppid",
+        174 => "getuid",
+        175 => "geteuid",
+        176 => "getgid",
+        177 => "getegid",
+        178 => "gettid",
+        214 => "brk",
+        215 => "munmap",
+        220 => "clone",
+        221 => "execve",
+        222 => "mmap",
+        226 => "mprotect",
+        233 => "madvise",
+        261 => "prlimit64",
+        278 => "getrandom",
+        _ => return None,
+    };
+    Some(name)
+}
@@ -0,0 +1,287 @@
+//! Minimal ELF parsing: enough to load RISC-V executables and shared objects,
+//! 64 bit ones and the 32 bit ones of RV32.
+
+use crate::{error::EmulatorError, isa::Xlen};
+
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PT_PHDR: u32 = 6;
+const SHT_SYMTAB: u32 = 2;
+/// [`Segment::flags`] bit of executable segments.
+pub const PF_X: u32 = 1;
+/// [`Symbol::kind`] of functions.
+pub const STT_FUNC: u8 = 2;
+
+/// Type of the object, from `e_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfType {
+    /// Linked at a fixed address.
+    Executable,
+    /// Position independent, loaded at an arbitrary bias.
+    Dynamic,
+}
+
+/// An entry of the symbol table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u64,
+    pub size: u64,
+    /// `STT_*` type from the low bits of `st_info`.
+    pub kind: u8,
+}
+
+/// A `PT_LOAD` program header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub vaddr: u64,
+    pub offset: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    /// `PF_X`, `PF_W` and `PF_R` bits.
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf<'a> {
+    pub data: &'a [u8],
+    pub kind: ElfType,
+    /// From `EI_CLASS`, the width of the addresses and of the registers.
+    pub xlen: Xlen,
+    pub entry: u64,
+    /// File offset, entry size and count of the program headers.
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+    /// Virtual address of the program headers, if they are loaded.
+    pub phdr: Option<u64>,
+    pub segments: Vec<Segment>,
+    /// Path of the dynamic linker requested by `PT_INTERP`.
+    pub interpreter: Option<String>,
+}
+
+fn invalid(reason: &str) -> EmulatorError {
+    EmulatorError::InvalidElf(reason.to_string())
+}
+
+/// The bytes `[offset, offset + len)` of the file, if they don't overflow.
+fn range(offset: u64, len: u64) -> Result<std::ops::Range<usize>, EmulatorError> {
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| invalid("offset overflows"))?;
+    Ok(offset as usize..end as usize)
+}
+
+fn u16_at(data: &[u8], offset: u64) -> Result<u16, EmulatorError> {
+    let bytes = data
+        .get(range(offset, 2)?)
+        .ok_or_else(|| invalid("truncated"))?;
+    Ok(u16::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn u32_at(data: &[u8], offset: u64) -> Result<u32, EmulatorError> {
+    let bytes = data
+        .get(range(offset, 4)?)
+        .ok_or_else(|| invalid("truncated"))?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn u64_at(data: &[u8], offset: u64) -> Result<u64, EmulatorError> {
+    let bytes = data
+        .get(range(offset, 8)?)
+        .ok_or_else(|| invalid("truncated"))?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+/// An address or size field at `offset`, 4 bytes wide in 32 bit objects.
+fn word_at(data: &[u8], offset: u64, xlen: Xlen) -> Result<u64, EmulatorError> {
+    match xlen {
+        Xlen::Rv32 => u32_at(data, offset).map(u64::from),
+        Xlen::Rv64 => u64_at(data, offset),
+    }
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, EmulatorError> {
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(invalid("bad magic"));
+        }
+        // EI_CLASS and EI_DATA
+        let xlen = match data.get(4..6) {
+            Some([1, 1]) => Xlen::Rv32,
+            Some([2, 1]) => Xlen::Rv64,
+            _ => return Err(invalid("not a little endian 32 or 64 bit object")),
+        };
+        let is32 = xlen == Xlen::Rv32;
+        if u16_at(data, 18)? != EM_RISCV {
+            return Err(invalid("not a RISC-V object"));
+        }
+        let kind = match u16_at(data, 16)? {
+            2 => ElfType::Executable,
+            3 => ElfType::Dynamic,
+            _ => return Err(invalid("not an executable or shared object")),
+        };
+
+        let entry = word_at(data, 24, xlen)?;
+        let phoff = word_at(data, if is32 { 28 } else { 32 }, xlen)?;
+        let phentsize = u16_at(data, if is32 { 42 } else { 54 })?;
+        let phnum = u16_at(data, if is32 { 44 } else { 56 })?;
+
+        let mut segments = Vec::new();
+        let mut interpreter = None;
+        let mut phdr = None;
+        for i in 0..phnum as u64 {
+            let header = phoff
+                .checked_add(i * phentsize as u64)
+                .ok_or_else(|| invalid("program headers overflow"))?;
+            // The fields are the same words of either width, only p_flags
+            // moves: after p_type in 64 bit objects, last in 32 bit ones.
+            let field = |i: u64| word_at(data, header + i * (xlen.bits() as u64 / 8), xlen);
+            let offset = field(1)?;
+            let vaddr = field(2)?;
+            let filesz = field(4)?;
+            let memsz = field(5)?;
+            let flags = u32_at(data, header + if is32 { 24 } else { 4 })?;
+
+            match u32_at(data, header)? {
+                PT_LOAD => {
+                    if range(offset, filesz)?.end > data.len() {
+                        return Err(invalid("segment outside the file"));
+                    }
+                    if filesz > memsz {
+                        return Err(invalid("segment larger in the file than in memory"));
+                    }
+                    if vaddr.checked_add(memsz).is_none() {
+                        return Err(invalid("segment past the end of the address space"));
+                    }
+                    segments.push(Segment {
+                        vaddr,
+                        offset,
+                        filesz,
+                        memsz,
+                        flags,
+                    })
+                }
+                PT_INTERP => {
+                    let path = data
+                        .get(range(offset, filesz)?)
+                        .ok_or_else(|| invalid("interpreter outside the file"))?;
+                    let path = path.split(|x| *x == 0).next().unwrap_or_default();
+                    interpreter = Some(String::from_utf8_lossy(path).into_owned());
+                }
+                PT_PHDR => phdr = Some(vaddr),
+                _ => {}
+            }
+        }
+
+        // Without PT_PHDR, the headers are still mapped if a segment covers them.
+        let phdr = phdr.or_else(|| {
+            segments
+                .iter()
+                .find(|s| s.offset <= phoff && phoff < s.offset + s.filesz)
+                .map(|s| s.vaddr + (phoff - s.offset))
+        });
+
+        Ok(Self {
+            data,
+            kind,
+            xlen,
+            entry,
+            phoff,
+            phentsize,
+            phnum,
+            phdr,
+            segments,
+            interpreter,
+        })
+    }
+
+    /// Lowest and highest (exclusive) virtual addresses covered by the segments.
+    pub fn bounds(&self) -> (u64, u64) {
+        let start = self.segments.iter().map(|s| s.vaddr).min().unwrap_or(0);
+        let end = self
+            .segments
+            .iter()
+            .map(|s| s.vaddr + s.memsz)
+            .max()
+            .unwrap_or(0);
+        (start, end)
+    }
+
+    /// Symbols from the `.symtab` sections, empty if the object is stripped.
+    pub fn symbols(&self) -> Result<Vec<Symbol>, EmulatorError> {
+        let (data, xlen) = (self.data, self.xlen);
+        let is32 = xlen == Xlen::Rv32;
+        let shoff = word_at(data, if is32 { 32 } else { 40 }, xlen)?;
+        let shentsize = u16_at(data, if is32 { 46 } else { 58 })? as u64;
+        let shnum = u16_at(data, if is32 { 48 } else { 60 })? as u64;
+
+        let section = |i: u64| -> Result<(u32, u64, u64, u32), EmulatorError> {
+            let header = shoff
+                .checked_add(i * shentsize)
+                .ok_or_else(|| invalid("section headers overflow"))?;
+            Ok((
+                u32_at(data, header + 4)?,
+                word_at(data, header + if is32 { 16 } else { 24 }, xlen)?,
+                word_at(data, header + if is32 { 20 } else { 32 }, xlen)?,
+                u32_at(data, header + if is32 { 24 } else { 40 })?,
+            ))
+        };
+
+        let mut symbols = Vec::new();
+        for i in 0..shnum {
+            let (kind, offset, size, link) = section(i)?;
+            if kind != SHT_SYMTAB {
+                continue;
+            }
+            let (_, strtab, strsize, _) = section(link as u64)?;
+            let strings = data
+                .get(range(strtab, strsize)?)
+                .ok_or_else(|| invalid("string table outside the file"))?;
+
+            for entry in
+                (offset..range(offset, size)?.end as u64).step_by(if is32 { 16 } else { 24 })
+            {
+                let name = u32_at(data, entry)? as usize;
+                let name = strings.get(name..).unwrap_or_default();
+                let name = name.split(|x| *x == 0).next().unwrap_or_default();
+                if name.is_empty() {
+                    continue;
+                }
+                symbols.push(Symbol {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    value: word_at(data, entry + if is32 { 4 } else { 8 }, xlen)?,
+                    size: word_at(data, entry + if is32 { 8 } else { 16 }, xlen)?,
+                    kind: data
+                        .get(entry as usize + if is32 { 12 } else { 4 })
+                        .ok_or_else(|| invalid("symbol outside the file"))?
+                        & 0xf,
+                });
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Sized function symbols, by address without duplicates.
+    pub fn functions(&self) -> Result<Vec<Symbol>, EmulatorError> {
+        let mut functions: Vec<_> = self
+            .symbols()?
+            .into_iter()
+            .filter(|x| x.kind == STT_FUNC && x.size > 0)
+            .collect();
+        functions.sort_by_key(|x| x.value);
+        functions.dedup_by_key(|x| x.value);
+        Ok(functions)
+    }
+
+    /// Address of the symbol called `name`.
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols()
+            .ok()?
+            .into_iter()
+            .find(|x| x.name == name)
+            .map(|x| x.value)
+    }
+}
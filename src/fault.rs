@@ -0,0 +1,289 @@
+//! Fault injection campaigns for soft error studies. The program runs once
+//! undisturbed for the golden outcome, then once per fault with a single bit
+//! flipped in a register of the running hart or a byte of dram after a given
+//! number of instructions, and each run is classified against the golden one.
+
+use std::{fmt, ops::Range, str::FromStr};
+
+use crate::{
+    debugger::parse_number,
+    disasm::ABI,
+    error::EmulatorError,
+    exception::Exception,
+    instruction::Instruction,
+    machine::{ExitReason, Machine, MachineBuilder, StopCondition},
+    observer::ExecutionObserver,
+    random::Rng,
+};
+
+/// A faulty run taking this many times the instructions of the golden one
+/// is considered hung.
+const HANG_FACTOR: u64 = 2;
+/// Instructions allowed on top, so short programs can still take a detour.
+const HANG_SLACK: u64 = 1000;
+
+/// Where bits are flipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Integer registers, e.g. `x1-x31` or `a0`. Flips of x0 are masked by
+    /// construction, so it is best left out.
+    Registers(Range<usize>),
+    /// Bytes of dram, e.g. `0x80001000-0x80002000`.
+    Memory(Range<u64>),
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        if let (Some(start), Some(end)) = (register(start), register(end)) {
+            return match start <= end {
+                true => Ok(Self::Registers(start..end + 1)),
+                false => Err(format!("empty register range {s}")),
+            };
+        }
+        match (parse_number(start), parse_number(end)) {
+            (Some(start), Some(end)) if start == end => Ok(Self::Memory(start..start + 1)),
+            (Some(start), Some(end)) if start < end => Ok(Self::Memory(start..end)),
+            _ => Err(format!(
+                "invalid fault target {s}, expected registers such as x1-x31 or an address range"
+            )),
+        }
+    }
+}
+
+/// Index of `xN` or an ABI register name.
+fn register(s: &str) -> Option<usize> {
+    let index = match s.strip_prefix('x') {
+        Some(n) if !n.starts_with('0') || n == "0" => n.parse().ok()?,
+        _ => ABI.iter().position(|x| *x == s)?,
+    };
+    (index < 32).then_some(index)
+}
+
+/// The bit flipped by a fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Register(usize),
+    Memory(u64),
+}
+
+/// A transient fault, flipping `bit` of `location` once `at` instructions
+/// have executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub at: u64,
+    pub location: Location,
+    pub bit: u32,
+}
+
+impl Fault {
+    /// Flips the bit now.
+    pub fn inject(&self, machine: &mut Machine) -> Result<(), EmulatorError> {
+        match self.location {
+            Location::Register(0) => {}
+            Location::Register(r) => machine.cpu.regs[r] ^= 1 << self.bit,
+            Location::Memory(addr) => {
+                let dram = &mut machine.cpu.bus.dram;
+                if !dram.contains(addr) {
+                    return Err(EmulatorError::InvalidFault(format!(
+                        "{addr:#x} is outside dram"
+                    )));
+                }
+                dram.dram[(addr - dram.base) as usize] ^= 1 << self.bit;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location {
+            Location::Register(r) => write!(f, "x{r}")?,
+            Location::Memory(addr) => write!(f, "{addr:#x}")?,
+        }
+        write!(f, " bit {} at {}", self.bit, self.at)
+    }
+}
+
+/// How a faulty run ended, compared to the golden run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Ended like the golden run.
+    Masked,
+    /// Ended otherwise without noticing, e.g. with another exit code.
+    Corrupted(ExitReason),
+    /// Raised an exception the golden run didn't.
+    Crashed(Exception),
+    /// Didn't end within twice the instructions of the golden run.
+    Hung,
+}
+
+impl Outcome {
+    fn classify(golden: ExitReason, reason: ExitReason) -> Self {
+        match reason {
+            _ if reason == golden => Self::Masked,
+            ExitReason::MaxInstructions => Self::Hung,
+            ExitReason::Exception(exception) => Self::Crashed(exception),
+            reason => Self::Corrupted(reason),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Masked => "masked",
+            Self::Corrupted(_) => "corrupted",
+            Self::Crashed(_) => "crashed",
+            Self::Hung => "hung",
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Corrupted(reason) => write!(f, "corrupted, {reason:?}"),
+            Self::Crashed(exception) => write!(f, "crashed, {exception}"),
+            outcome => f.write_str(outcome.name()),
+        }
+    }
+}
+
+/// Faults drawn at random, reproducibly from `seed`.
+#[derive(Debug, Clone)]
+pub struct Campaign {
+    pub target: Target,
+    /// Instruction counts to inject at, defaults to the whole golden run.
+    pub window: Option<Range<u64>>,
+    pub runs: usize,
+    pub seed: u64,
+    /// Instructions the golden run may take.
+    pub max_instructions: u64,
+}
+
+/// Results of a [`Campaign`].
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub golden: ExitReason,
+    /// Executed by the golden run.
+    pub instructions: u64,
+    pub runs: Vec<(Fault, Outcome)>,
+}
+
+impl Campaign {
+    /// Runs the golden run and each fault on a fresh machine from `builder`.
+    pub fn run(&self, builder: &MachineBuilder) -> Result<Report, EmulatorError> {
+        let mut machine = builder.clone().build()?;
+        machine.cpu.observers.add(Steps::default());
+        let golden = machine.run_until(&StopCondition {
+            max_instructions: Some(self.max_instructions),
+            ..StopCondition::default()
+        });
+        if golden == ExitReason::MaxInstructions {
+            return Err(EmulatorError::InvalidFault(format!(
+                "the golden run didn't stop within {} instructions",
+                self.max_instructions
+            )));
+        }
+        let instructions = machine.cpu.observers.get::<Steps>().unwrap().0;
+        let window = self.window.clone().unwrap_or(0..instructions);
+        if window.is_empty() {
+            return Err(EmulatorError::InvalidFault(String::from(
+                "empty injection window",
+            )));
+        }
+        if let Target::Memory(range) = &self.target {
+            let dram = &machine.cpu.bus.dram;
+            if !dram.contains(range.start) || !dram.contains(range.end - 1) {
+                return Err(EmulatorError::InvalidFault(format!(
+                    "{:#x}-{:#x} is outside dram",
+                    range.start, range.end
+                )));
+            }
+        }
+        let budget = instructions * HANG_FACTOR + HANG_SLACK;
+
+        let mut rng = Rng::new(self.seed);
+        let mut runs = Vec::with_capacity(self.runs);
+        for _ in 0..self.runs {
+            let fault = self.draw(&mut rng, &window);
+            let mut machine = builder.clone().build()?;
+            let reason = match machine.run_until(&StopCondition {
+                max_instructions: Some(fault.at),
+                ..StopCondition::default()
+            }) {
+                ExitReason::MaxInstructions => {
+                    fault.inject(&mut machine)?;
+                    machine.run_until(&StopCondition {
+                        max_instructions: Some(budget.saturating_sub(fault.at)),
+                        ..StopCondition::default()
+                    })
+                }
+                // Ended before the fault, e.g. past the end of the window.
+                reason => reason,
+            };
+            runs.push((fault, Outcome::classify(golden, reason)));
+        }
+        Ok(Report {
+            golden,
+            instructions,
+            runs,
+        })
+    }
+
+    fn draw(&self, rng: &mut Rng, window: &Range<u64>) -> Fault {
+        let at = window.start + rng.below(window.end - window.start);
+        let (location, bits) = match &self.target {
+            Target::Registers(range) => {
+                let r = range.start + rng.below(range.len() as u64) as usize;
+                (Location::Register(r), 64)
+            }
+            Target::Memory(range) => {
+                let addr = range.start + rng.below(range.end - range.start);
+                (Location::Memory(addr), 8)
+            }
+        };
+        Fault {
+            at,
+            location,
+            bit: rng.below(bits) as u32,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "golden run: {:?} after {} instructions",
+            self.golden, self.instructions
+        )?;
+        let total = self.runs.len().max(1) as f64;
+        for name in ["masked", "corrupted", "crashed", "hung"] {
+            let count = self.runs.iter().filter(|x| x.1.name() == name).count();
+            write!(
+                f,
+                "\n{name:>10} {count:>8} {:>6.2}%",
+                count as f64 * 100.0 / total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts the instructions executed, retired or trapping, as
+/// [`StopCondition::max_instructions`] does.
+#[derive(Debug, Default)]
+struct Steps(u64);
+
+impl ExecutionObserver for Steps {
+    fn on_instruction(&mut self, _pc: u64, _inst: &Instruction) {
+        self.0 += 1;
+    }
+
+    fn on_trap(&mut self, _pc: u64, _exception: &Exception) {
+        self.0 += 1;
+    }
+}
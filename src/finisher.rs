@@ -0,0 +1,62 @@
+//! The SiFive test finisher of the QEMU virt machine: a guest ends the run by
+//! storing `0x5555` for success or `(code << 16) | 0x3333` for failure, or
+//! asks for a reboot with `0x7777`. Linux drives it as `syscon-poweroff` and
+//! `syscon-reboot`.
+
+use std::sync::{Arc, Mutex};
+
+use tracing::warn;
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where QEMU virt maps the finisher.
+pub const FINISHER_BASE: u64 = 0x10_0000;
+pub const FINISHER_SIZE: u64 = 0x1000;
+
+const FINISHER_FAIL: u64 = 0x3333;
+const FINISHER_PASS: u64 = 0x5555;
+const FINISHER_RESET: u64 = 0x7777;
+
+/// Clones share what the guest asked for, the machine keeps one while
+/// another is mapped.
+#[derive(Debug, Clone, Default)]
+pub struct TestFinisher {
+    exit_code: Arc<Mutex<Option<u64>>>,
+    reboot: Arc<Mutex<bool>>,
+}
+
+impl TestFinisher {
+    /// Exit code the guest passed, once it asked to stop.
+    pub fn exit_code(&self) -> Option<u64> {
+        *self.exit_code.lock().unwrap()
+    }
+
+    /// Whether the guest asked for a reboot.
+    pub fn reboot_requested(&self) -> bool {
+        *self.reboot.lock().unwrap()
+    }
+}
+
+impl Device for TestFinisher {
+    fn load(&mut self, _offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(0)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let code = match value & 0xffff {
+            FINISHER_PASS => 0,
+            // A failure with code 0 still has to fail.
+            FINISHER_FAIL => ((value >> 16) & 0xffff).max(1),
+            FINISHER_RESET => {
+                *self.reboot.lock().unwrap() = true;
+                return Ok(());
+            }
+            _ => {
+                warn!(offset, value, "unknown test finisher command");
+                return Ok(());
+            }
+        };
+        *self.exit_code.lock().unwrap() = Some(code);
+        Ok(())
+    }
+}
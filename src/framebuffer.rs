@@ -0,0 +1,138 @@
+//! A linear framebuffer, which Linux drives as a `simple-framebuffer` and
+//! bare-metal guests just write pixels to. A page of read-only registers
+//! tells its geometry, the pixels follow from [`FB_PIXELS`], each an
+//! `x8r8g8b8` word, row after row. See [`crate::vnc`] to look at it.
+
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the framebuffer is mapped, in a hole of the QEMU virt map.
+pub const FB_BASE: u64 = 0x1100_0000;
+/// Offset of the pixels.
+pub const FB_PIXELS: u64 = 0x1000;
+
+/// Registers, by offset: the size in pixels, the bytes from a row to the
+/// next and the format of the pixels, always [`FORMAT_XRGB8888`].
+const WIDTH: u64 = 0x0;
+const HEIGHT: u64 = 0x4;
+const STRIDE: u64 = 0x8;
+const FORMAT: u64 = 0xc;
+
+pub const FORMAT_XRGB8888: u64 = 0;
+
+#[derive(Debug)]
+struct Inner {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    /// Changes with every store to the pixels.
+    generation: u64,
+}
+
+/// A copy of what the framebuffer shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    /// `x8r8g8b8`, row after row.
+    pub pixels: Vec<u32>,
+    pub generation: u64,
+}
+
+/// The framebuffer. Clones share it, the machine keeps one for the displays
+/// while another is mapped.
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Framebuffer {
+    /// A black framebuffer of `width` by `height` pixels.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                width,
+                height,
+                pixels: vec![0; 4 * width as usize * height as usize],
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.inner.lock().unwrap().width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.inner.lock().unwrap().height
+    }
+
+    /// Changes with every store to the pixels, to tell when to redraw.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    pub fn frame(&self) -> Frame {
+        let inner = self.inner.lock().unwrap();
+        Frame {
+            width: inner.width,
+            height: inner.height,
+            pixels: inner
+                .pixels
+                .chunks_exact(4)
+                .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+                .collect(),
+            generation: inner.generation,
+        }
+    }
+}
+
+impl Device for Framebuffer {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let inner = self.inner.lock().unwrap();
+        let value = match offset {
+            WIDTH => inner.width.into(),
+            HEIGHT => inner.height.into(),
+            STRIDE => (4 * inner.width).into(),
+            FORMAT => FORMAT_XRGB8888,
+            FB_PIXELS.. => {
+                let start = (offset - FB_PIXELS) as usize;
+                let bytes = inner
+                    .pixels
+                    .get(start..start + size as usize / 8)
+                    .ok_or(Exception::LoadAccessFault(FB_BASE + offset))?;
+                bytes.iter().rev().fold(0, |x, b| x << 8 | u64::from(*b))
+            }
+            _ => 0,
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        // the registers are read only
+        if offset >= FB_PIXELS {
+            let mut inner = self.inner.lock().unwrap();
+            let (start, len) = ((offset - FB_PIXELS) as usize, size as usize / 8);
+            inner
+                .pixels
+                .get_mut(start..start + len)
+                .ok_or(Exception::StoreAmoAccessFault(FB_BASE + offset))?
+                .copy_from_slice(&value.to_le_bytes()[..len]);
+            inner.generation += 1;
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "framebuffer"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        let inner = self.inner.lock().unwrap();
+        Some(FB_BASE..FB_BASE + FB_PIXELS + inner.pixels.len() as u64)
+    }
+}
@@ -0,0 +1,342 @@
+//! A GDB remote serial protocol stub, enough for `target remote` to inspect
+//! memory and registers, single step, continue and use software breakpoints.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use tracing::{debug, info};
+
+use crate::{cpu::Cpu, exception::Exception};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0"><architecture>riscv:rv64</architecture></target>"#;
+
+/// How many instructions run between checks for a ctrl-c from gdb.
+const POLL_INTERVAL: u64 = 10_000;
+
+const SIGTRAP: u8 = 5;
+const SIGILL: u8 = 4;
+const SIGSEGV: u8 = 11;
+const SIGINT: u8 = 2;
+
+/// Serves a single gdb connection on `addr`, driving `cpu` until gdb detaches
+/// or kills the session.
+pub fn serve(cpu: &mut Cpu, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    info!("gdb connected from {peer}");
+
+    GdbStub::new(stream)?.run(cpu)
+}
+
+struct GdbStub {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    breakpoints: BTreeSet<u64>,
+}
+
+impl GdbStub {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            breakpoints: BTreeSet::new(),
+        })
+    }
+
+    fn run(&mut self, cpu: &mut Cpu) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            debug!(packet, "gdb packet");
+            let reply = match self.handle(cpu, &packet)? {
+                Some(reply) => reply,
+                None => return Ok(()),
+            };
+            self.write_packet(&reply)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the reply to send, or `None` to end the session.
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> io::Result<Option<String>> {
+        let (cmd, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match cmd {
+            "?" => format!("S{SIGTRAP:02x}"),
+            "g" => {
+                let mut out = String::new();
+                for x in cpu.regs.iter().chain([&cpu.pc]) {
+                    out.push_str(&hex_le(*x));
+                }
+                out
+            }
+            "G" => {
+                let values = parse_le_words(args);
+                for (i, value) in values.iter().enumerate().take(33) {
+                    set_reg(cpu, i, *value);
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|n| get_reg(cpu, n))
+            {
+                Some(x) => hex_le(x),
+                None => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, v)| {
+                    Some((
+                        usize::from_str_radix(n, 16).ok()?,
+                        *parse_le_words(v).first()?,
+                    ))
+                });
+                match parsed {
+                    Some((n, v)) if n <= 32 => {
+                        set_reg(cpu, n, v);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let mut out = String::new();
+                    for i in 0..len {
+                        match cpu.bus.load(addr.wrapping_add(i), 8) {
+                            Ok(x) => out.push_str(&format!("{x:02x}")),
+                            Err(_) if i == 0 => return Ok(Some("E14".to_string())),
+                            Err(_) => break,
+                        }
+                    }
+                    out
+                }
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args
+                    .split_once(':')
+                    .and_then(|(range, data)| Some((parse_addr_len(range)?, parse_bytes(data)?)));
+                match parsed {
+                    Some(((addr, _), data)) => {
+                        let ok = data.iter().enumerate().all(|(i, x)| {
+                            cpu.bus
+                                .store(addr.wrapping_add(i as u64), 8, *x as u64)
+                                .is_ok()
+                        });
+                        if ok { "OK" } else { "E14" }.to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "s" => {
+                let signal = match cpu.step() {
+                    Ok(()) => SIGTRAP,
+                    Err(e) => signal(&e),
+                };
+                format!("S{signal:02x}")
+            }
+            "c" => format!("S{:02x}", self.cont(cpu)?),
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(addr) => {
+                    if cmd == "Z" {
+                        self.breakpoints.insert(addr);
+                    } else {
+                        self.breakpoints.remove(&addr);
+                    }
+                    "OK".to_string()
+                }
+                // Only software breakpoints are supported.
+                None => String::new(),
+            },
+            "H" => "OK".to_string(),
+            "k" => return Ok(None),
+            "D" => {
+                self.write_packet("OK")?;
+                return Ok(None);
+            }
+            "q" => self.query(packet),
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=4000;qXfer:features:read+".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, len) = parse_addr_len(range).unwrap_or((0, 0));
+            let data = TARGET_XML.as_bytes();
+            let start = (offset as usize).min(data.len());
+            let end = (start + len as usize).min(data.len());
+            let prefix = if end == data.len() { 'l' } else { 'm' };
+            format!("{prefix}{}", String::from_utf8_lossy(&data[start..end]))
+        } else {
+            String::new()
+        }
+    }
+
+    /// Runs until a breakpoint, an exception or ctrl-c, returning the signal to report.
+    fn cont(&mut self, cpu: &mut Cpu) -> io::Result<u8> {
+        let mut executed = 0u64;
+        loop {
+            if let Err(e) = cpu.step() {
+                return Ok(signal(&e));
+            }
+            if self.breakpoints.contains(&cpu.pc) {
+                return Ok(SIGTRAP);
+            }
+
+            executed += 1;
+            if executed.is_multiple_of(POLL_INTERVAL) && self.interrupted()? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    /// Whether gdb sent a ctrl-c.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.writer.set_nonblocking(true)?;
+        let mut buf = [0];
+        let result = match self.writer.peek(&mut buf) {
+            Ok(1) if buf[0] == 0x03 => {
+                self.reader.read_exact(&mut buf)?;
+                Ok(true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        self.writer.set_nonblocking(false)?;
+        result
+    }
+
+    /// Reads the next packet, acknowledging it. Returns `None` when gdb hangs up.
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // Skip acks and stray ctrl-c until the start of a packet.
+            loop {
+                if self.reader.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                self.reader.read_exact(&mut byte)?;
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+
+            let mut checksum = [0; 2];
+            self.reader.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+            if expected == Some(checksum_of(&data)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.writer.write_all(b"-")?;
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        debug!(data, "gdb reply");
+        write!(self.writer, "${data}#{:02x}", checksum_of(data.as_bytes()))?;
+        self.writer.flush()
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x))
+}
+
+fn signal(e: &Exception) -> u8 {
+    match e {
+        Exception::IllegalInstruction(_) => SIGILL,
+        Exception::Breakpoint(_) => SIGTRAP,
+        _ => SIGSEGV,
+    }
+}
+
+/// Register numbering used by gdb: x0-x31 then pc.
+fn get_reg(cpu: &Cpu, n: usize) -> Option<u64> {
+    match n {
+        0..=31 => Some(cpu.regs[n]),
+        32 => Some(cpu.pc),
+        _ => None,
+    }
+}
+
+fn set_reg(cpu: &mut Cpu, n: usize, value: u64) {
+    match n {
+        1..=31 => cpu.regs[n] = value,
+        32 => cpu.pc = value,
+        _ => {}
+    }
+}
+
+fn hex_le(x: u64) -> String {
+    x.to_le_bytes().iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_le_words(s: &str) -> Vec<u64> {
+    parse_bytes(s)
+        .unwrap_or_default()
+        .chunks(8)
+        .map(|chunk| {
+            let mut buf = [0; 8];
+            buf[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(buf)
+        })
+        .collect()
+}
+
+fn parse_addr_len(s: &str) -> Option<(u64, u64)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((
+        u64::from_str_radix(addr, 16).ok()?,
+        u64::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+/// Parses `0,addr,kind` for software breakpoints.
+fn parse_breakpoint(s: &str) -> Option<u64> {
+    let mut parts = s.split(',');
+    if parts.next()? != "0" {
+        return None;
+    }
+    u64::from_str_radix(parts.next()?, 16).ok()
+}
@@ -0,0 +1,154 @@
+//! A GPIO block of [`GPIO_PINS`] pins, laid out as the SiFive one: each
+//! register holds a bit per pin. The guest drives the pins it sets as
+//! outputs, the host drives the others through [`Gpio::set_input`] or the
+//! `/gpio` endpoint of [`crate::control`], and watches the outputs with
+//! [`Gpio::outputs`]. An edge of an input raises [`GPIO_IRQ`] while its
+//! interrupt is enabled.
+
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use tracing::debug;
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the GPIO block is mapped, in a hole of the QEMU virt map.
+pub const GPIO_BASE: u64 = 0x1006_0000;
+pub const GPIO_SIZE: u64 = 0x1000;
+pub const GPIO_IRQ: u32 = 11;
+pub const GPIO_PINS: u32 = 32;
+
+/// Registers, by offset: the level of the pins, read only, which are
+/// outputs and the levels they drive, then the enable and pending bits of
+/// the rising and falling edge interrupts. Storing ones to a pending
+/// register clears them.
+const INPUT_VAL: u64 = 0x00;
+const OUTPUT_EN: u64 = 0x08;
+const OUTPUT_VAL: u64 = 0x0c;
+const RISE_IE: u64 = 0x18;
+const RISE_IP: u64 = 0x1c;
+const FALL_IE: u64 = 0x20;
+const FALL_IP: u64 = 0x24;
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// What the host drives.
+    inputs: u32,
+    output_en: u32,
+    output_val: u32,
+    rise_ie: u32,
+    rise_ip: u32,
+    fall_ie: u32,
+    fall_ip: u32,
+}
+
+impl Inner {
+    /// The level of every pin, outputs read back what they drive.
+    fn levels(&self) -> u32 {
+        self.output_en & self.output_val | !self.output_en & self.inputs
+    }
+
+    /// Latches the edges from `before` to the current levels.
+    fn edges(&mut self, before: u32) {
+        let after = self.levels();
+        self.rise_ip |= !before & after;
+        self.fall_ip |= before & !after;
+    }
+}
+
+/// Clones share the pins, the machine keeps one for the host while another
+/// is mapped.
+#[derive(Debug, Clone, Default)]
+pub struct Gpio {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Gpio {
+    /// Drives `pin` high or low, which the guest sees unless it drives the
+    /// pin itself.
+    pub fn set_input(&self, pin: u32, high: bool) {
+        assert!(pin < GPIO_PINS, "no gpio pin {pin}");
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.levels();
+        match high {
+            true => inner.inputs |= 1 << pin,
+            false => inner.inputs &= !(1 << pin),
+        }
+        inner.edges(before);
+    }
+
+    /// The level of every pin, a bit each.
+    pub fn levels(&self) -> u32 {
+        self.inner.lock().unwrap().levels()
+    }
+
+    /// The pins the guest drives, a bit each.
+    pub fn output_enable(&self) -> u32 {
+        self.inner.lock().unwrap().output_en
+    }
+
+    /// The levels the guest drives, only for the pins it set as outputs.
+    pub fn outputs(&self) -> u32 {
+        let inner = self.inner.lock().unwrap();
+        inner.output_en & inner.output_val
+    }
+
+    /// Whether `pin` is high.
+    pub fn level(&self, pin: u32) -> bool {
+        pin < GPIO_PINS && self.levels() & 1 << pin != 0
+    }
+}
+
+impl Device for Gpio {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let inner = self.inner.lock().unwrap();
+        let value = match offset {
+            INPUT_VAL => inner.levels(),
+            OUTPUT_EN => inner.output_en,
+            OUTPUT_VAL => inner.output_val,
+            RISE_IE => inner.rise_ie,
+            RISE_IP => inner.rise_ip,
+            FALL_IE => inner.fall_ie,
+            FALL_IP => inner.fall_ip,
+            _ => 0,
+        };
+        Ok(value.into())
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.levels();
+        let value = value as u32;
+        match offset {
+            OUTPUT_EN => inner.output_en = value,
+            OUTPUT_VAL => inner.output_val = value,
+            RISE_IE => inner.rise_ie = value,
+            RISE_IP => inner.rise_ip &= !value,
+            FALL_IE => inner.fall_ie = value,
+            FALL_IP => inner.fall_ip &= !value,
+            _ => return Ok(()),
+        }
+        if matches!(offset, OUTPUT_EN | OUTPUT_VAL) {
+            inner.edges(before);
+            let outputs = inner.output_en & inner.output_val;
+            debug!("gpio outputs {outputs:#010x}");
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "gpio"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(GPIO_BASE..GPIO_BASE + GPIO_SIZE)
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        let inner = self.inner.lock().unwrap();
+        let pending = inner.rise_ie & inner.rise_ip | inner.fall_ie & inner.fall_ip;
+        (pending != 0).then_some(GPIO_IRQ)
+    }
+}
@@ -0,0 +1,171 @@
+//! Coverage heatmap: counts the instructions executed in fixed size bins of
+//! the code and renders them as an SVG grid, one row per [`COLUMNS`] bins,
+//! shaded by the log of the count. Bins which never ran are grey, function
+//! starts are marked and named, and hovering a bin shows its range, function
+//! and count.
+
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{
+    elf::{Elf, Symbol, PF_X},
+    error::EmulatorError,
+    instruction::Instruction,
+    observer::ExecutionObserver,
+};
+
+/// Bins per row.
+pub const COLUMNS: u64 = 64;
+/// Most bins, larger code gets larger bins.
+const MAX_BINS: u64 = 64 * 1024;
+/// Side of a bin in pixels.
+const CELL: u64 = 10;
+/// Width of the address column on the left.
+const MARGIN: u64 = 100;
+/// Width of the function names column on the right.
+const LABELS: u64 = 300;
+
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    range: Range<u64>,
+    /// Bytes per bin, a power of two.
+    bin: u64,
+    counts: Vec<u64>,
+    functions: Vec<Symbol>,
+}
+
+impl Heatmap {
+    /// Covers `range`, with bins of at least an instruction.
+    pub fn new(range: Range<u64>) -> Self {
+        let size = range.end.saturating_sub(range.start).max(4);
+        let bin = size.div_ceil(MAX_BINS).next_power_of_two().max(4);
+        Self {
+            counts: vec![0; size.div_ceil(bin) as usize],
+            range,
+            bin,
+            functions: Vec::new(),
+        }
+    }
+
+    /// Covers the executable segments of `elf`, annotated with its functions.
+    pub fn from_elf(elf: &Elf) -> Result<Self, EmulatorError> {
+        let code = elf.segments.iter().filter(|x| x.flags & PF_X != 0);
+        let start = code.clone().map(|x| x.vaddr).min();
+        let end = code.map(|x| x.vaddr + x.memsz).max();
+        let (Some(start), Some(end)) = (start, end) else {
+            return Err(EmulatorError::InvalidElf(String::from(
+                "no executable segment",
+            )));
+        };
+        Ok(Self {
+            functions: elf.functions()?,
+            ..Self::new(start..end)
+        })
+    }
+
+    pub fn bin_size(&self) -> u64 {
+        self.bin
+    }
+
+    /// Instructions executed in each bin, from the start of the range.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    fn function(&self, addr: u64) -> Option<&Symbol> {
+        let i = self.functions.partition_point(|x| x.value <= addr);
+        self.functions[..i]
+            .last()
+            .filter(|x| addr < x.value + x.size)
+    }
+
+    pub fn write_svg(&self, mut out: impl Write) -> io::Result<()> {
+        let rows = (self.counts.len() as u64).div_ceil(COLUMNS);
+        let (width, height) = (MARGIN + COLUMNS * CELL + LABELS, rows * CELL + 2 * CELL);
+        let max = self.counts.iter().max().copied().unwrap_or(0);
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="{CELL}">"#
+        )?;
+        writeln!(
+            out,
+            r#"<text x="0" y="{CELL}">{:#x}-{:#x}, {} bytes per bin, max {max}</text>"#,
+            self.range.start, self.range.end, self.bin
+        )?;
+
+        for (i, count) in self.counts.iter().enumerate() {
+            let i = i as u64;
+            let (x, y) = (MARGIN + i % COLUMNS * CELL, (i / COLUMNS + 2) * CELL);
+            let start = self.range.start + i * self.bin;
+            if i.is_multiple_of(COLUMNS) {
+                writeln!(out, r#"<text x="0" y="{}">{start:#x}</text>"#, y + CELL - 1)?;
+            }
+            let function = self.function(start).map_or("", |x| x.name.as_str());
+            writeln!(
+                out,
+                r#"<rect x="{x}" y="{y}" width="{CELL}" height="{CELL}" fill="{}"><title>{start:#x}-{:#x} {}: {count}</title></rect>"#,
+                color(*count, max),
+                start + self.bin,
+                escape(function)
+            )?;
+        }
+
+        // Function starts, names stacked on the right of their row.
+        let mut last_row = None;
+        for function in &self.functions {
+            if !self.range.contains(&function.value) {
+                continue;
+            }
+            let i = (function.value - self.range.start) / self.bin;
+            let (x, y) = (MARGIN + i % COLUMNS * CELL, (i / COLUMNS + 2) * CELL);
+            writeln!(
+                out,
+                r#"<line x1="{x}" y1="{y}" x2="{x}" y2="{}" stroke="black"/>"#,
+                y + CELL
+            )?;
+            // One name per row, the others are in the tooltips.
+            if last_row != Some(i / COLUMNS) {
+                last_row = Some(i / COLUMNS);
+                writeln!(
+                    out,
+                    r#"<text x="{}" y="{}">{}</text>"#,
+                    MARGIN + COLUMNS * CELL + CELL,
+                    y + CELL - 1,
+                    escape(&function.name)
+                )?;
+            }
+        }
+        writeln!(out, "</svg>")
+    }
+}
+
+/// Light yellow to dark red on a log scale, grey for code which never ran.
+fn color(count: u64, max: u64) -> String {
+    if count == 0 {
+        return String::from("#dddddd");
+    }
+    let t = ((count as f64).ln_1p() / (max as f64).ln_1p()).clamp(0.0, 1.0);
+    let lerp = |a: f64, b: f64| (a + (b - a) * t).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        lerp(255.0, 128.0),
+        lerp(255.0, 0.0),
+        lerp(204.0, 38.0)
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+impl ExecutionObserver for Heatmap {
+    fn on_instruction(&mut self, pc: u64, _inst: &Instruction) {
+        if self.range.contains(&pc) {
+            self.counts[((pc - self.range.start) / self.bin) as usize] += 1;
+        }
+    }
+}
@@ -0,0 +1,284 @@
+//! Host-target interface: the `tohost`/`fromhost` protocol of Spike and the
+//! riscv-pk frontend, used by riscv-tests and newlib programs for console
+//! output, proxied system calls and exiting.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use tracing::{debug, warn};
+
+use crate::{console::Console, cpu::Cpu};
+
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+
+const EBADF: i64 = 9;
+const EFAULT: i64 = 14;
+const EINVAL: i64 = 22;
+const ENOSYS: i64 = 38;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFREG: u32 = 0o100000;
+
+/// Polled after every instruction: when the guest writes a command to
+/// `tohost` it is executed and acknowledged through `fromhost`.
+pub struct Htif {
+    pub tohost: u64,
+    pub fromhost: Option<u64>,
+    /// Exit code the guest passed, once it asked to stop.
+    pub exit_code: Option<u64>,
+    output: Box<dyn Write + Send>,
+    /// A console read waiting for a keystroke.
+    pending_read: Option<u64>,
+    /// Files opened by the guest, indexed by fd minus 3.
+    files: Vec<Option<File>>,
+}
+
+impl std::fmt::Debug for Htif {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Htif")
+            .field("tohost", &self.tohost)
+            .field("fromhost", &self.fromhost)
+            .field("exit_code", &self.exit_code)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Htif {
+    pub fn new(tohost: u64, fromhost: Option<u64>) -> Self {
+        Self {
+            tohost,
+            fromhost,
+            exit_code: None,
+            output: Box::new(io::stdout()),
+            pending_read: None,
+            files: Vec::new(),
+        }
+    }
+
+    /// Sends console output and writes to stdout/stderr to `output`.
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Handles a pending command, if any. Console reads are answered with
+    /// keystrokes from `console`, they never complete without one.
+    pub fn poll(&mut self, cpu: &mut Cpu, console: Option<&Console>) {
+        if let (Some(request), Some(fromhost)) = (self.pending_read, self.fromhost) {
+            if cpu.bus.load(fromhost, 64) == Ok(0) {
+                if let Some(c) = console.and_then(Console::read) {
+                    self.pending_read = None;
+                    let _ = cpu.bus.store(fromhost, 64, request | 0x100 | c as u64);
+                }
+            }
+        }
+
+        let value = match cpu.bus.load(self.tohost, 64) {
+            Ok(0) | Err(_) => return,
+            Ok(value) => value,
+        };
+        let _ = cpu.bus.store(self.tohost, 64, 0);
+
+        let device = value >> 56;
+        let command = (value >> 48) & 0xff;
+        let payload = value & 0xffff_ffff_ffff;
+        debug!(device, command, payload, "htif");
+
+        let response = match (device, command) {
+            (0, 0) if payload & 1 == 1 => {
+                self.exit_code = Some(payload >> 1);
+                return;
+            }
+            (0, 0) => {
+                self.syscall(cpu, console, payload);
+                1
+            }
+            (1, 0) => {
+                self.pending_read = Some(value & !0xffff_ffff_ffff);
+                return;
+            }
+            (1, 1) => {
+                let _ = self.output.write_all(&[payload as u8]);
+                let _ = self.output.flush();
+                value & !0xffff_ffff_ffff
+            }
+            _ => {
+                warn!(device, command, "unknown htif command");
+                return;
+            }
+        };
+
+        if let Some(fromhost) = self.fromhost {
+            let _ = cpu.bus.store(fromhost, 64, response);
+        }
+    }
+
+    /// Runs the system call described by the 8 words at `magic`, storing the
+    /// result in the first one.
+    fn syscall(&mut self, cpu: &mut Cpu, console: Option<&Console>, magic: u64) {
+        let mut args = [0; 8];
+        for (i, arg) in args.iter_mut().enumerate() {
+            match cpu.bus.load(magic + i as u64 * 8, 64) {
+                Ok(x) => *arg = x,
+                Err(_) => return,
+            }
+        }
+        let [nr, a0, a1, a2, a3, ..] = args;
+
+        let result = match nr {
+            SYS_EXIT => {
+                self.exit_code = Some(a0);
+                return;
+            }
+            SYS_WRITE => match read_guest(cpu, a1, a2) {
+                Some(buf) => match a0 {
+                    1 | 2 => self
+                        .output
+                        .write_all(&buf)
+                        .and_then(|_| self.output.flush())
+                        .map(|_| buf.len() as u64)
+                        .map_err(errno),
+                    _ => self
+                        .file(a0)
+                        .and_then(|f| f.write(&buf).map_err(errno))
+                        .map(|n| n as u64),
+                },
+                None => Err(EFAULT),
+            },
+            SYS_READ => {
+                let mut buf = vec![0; a2 as usize];
+                let read = match a0 {
+                    0 => match console {
+                        Some(console) => Ok(console.read_some(&mut buf)),
+                        None => io::stdin().read(&mut buf).map_err(errno),
+                    },
+                    _ => self.file(a0).and_then(|f| f.read(&mut buf).map_err(errno)),
+                };
+                read.and_then(|n| {
+                    write_guest(cpu, a1, &buf[..n])
+                        .then_some(n as u64)
+                        .ok_or(EFAULT)
+                })
+            }
+            // The frontend passes the length of the path in a2.
+            SYS_OPENAT => match read_guest(cpu, a1, a2) {
+                Some(path) => self.open(&path, a3),
+                None => Err(EFAULT),
+            },
+            SYS_CLOSE => match a0
+                .checked_sub(3)
+                .and_then(|i| self.files.get_mut(i as usize))
+            {
+                Some(file @ Some(_)) => {
+                    *file = None;
+                    Ok(0)
+                }
+                _ => Err(EBADF),
+            },
+            SYS_LSEEK => {
+                let pos = match a2 {
+                    0 => Some(SeekFrom::Start(a1)),
+                    1 => Some(SeekFrom::Current(a1 as i64)),
+                    2 => Some(SeekFrom::End(a1 as i64)),
+                    _ => None,
+                };
+                match pos {
+                    Some(pos) => self.file(a0).and_then(|f| f.seek(pos).map_err(errno)),
+                    None => Err(EINVAL),
+                }
+            }
+            SYS_FSTAT => {
+                let stat = match a0 {
+                    0..=2 => Ok((S_IFCHR | 0o620, 0)),
+                    _ => self
+                        .file(a0)
+                        .and_then(|f| f.metadata().map_err(errno))
+                        .map(|m| (S_IFREG | 0o644, m.len())),
+                };
+                stat.and_then(|(mode, size)| {
+                    write_guest(cpu, a1, &generic_stat(mode, size))
+                        .then_some(0)
+                        .ok_or(EFAULT)
+                })
+            }
+            _ => {
+                warn!(nr, "unimplemented htif syscall");
+                Err(ENOSYS)
+            }
+        };
+
+        let result = result.unwrap_or_else(|e| -e as u64);
+        let _ = cpu.bus.store(magic, 64, result);
+    }
+
+    fn file(&mut self, fd: u64) -> Result<&mut File, i64> {
+        fd.checked_sub(3)
+            .and_then(|i| self.files.get_mut(i as usize))
+            .and_then(|x| x.as_mut())
+            .ok_or(EBADF)
+    }
+
+    fn open(&mut self, path: &[u8], flags: u64) -> Result<u64, i64> {
+        let path = path.split(|x| *x == 0).next().unwrap_or_default();
+        let path = String::from_utf8_lossy(path).into_owned();
+
+        let mut options = OpenOptions::new();
+        match flags & 3 {
+            0 => options.read(true),
+            1 => options.write(true),
+            _ => options.read(true).write(true),
+        };
+        options
+            .create(flags & 0o100 != 0)
+            .truncate(flags & 0o1000 != 0)
+            .append(flags & 0o2000 != 0);
+
+        let file = options.open(path).map_err(errno)?;
+        let index = match self.files.iter().position(|x| x.is_none()) {
+            Some(index) => {
+                self.files[index] = Some(file);
+                index
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() - 1
+            }
+        };
+        Ok(index as u64 + 3)
+    }
+}
+
+fn errno(e: io::Error) -> i64 {
+    e.raw_os_error().unwrap_or(5) as i64
+}
+
+fn read_guest(cpu: &mut Cpu, addr: u64, len: u64) -> Option<Vec<u8>> {
+    (0..len)
+        .map(|i| cpu.bus.load(addr.wrapping_add(i), 8).ok().map(|x| x as u8))
+        .collect()
+}
+
+fn write_guest(cpu: &mut Cpu, addr: u64, data: &[u8]) -> bool {
+    data.iter().enumerate().all(|(i, x)| {
+        cpu.bus
+            .store(addr.wrapping_add(i as u64), 8, *x as u64)
+            .is_ok()
+    })
+}
+
+/// The generic `struct stat` as seen by the guest, only mode, size and blksize are filled.
+fn generic_stat(mode: u32, size: u64) -> [u8; 128] {
+    let mut stat = [0; 128];
+    stat[16..20].copy_from_slice(&mode.to_le_bytes());
+    stat[48..56].copy_from_slice(&size.to_le_bytes());
+    stat[56..60].copy_from_slice(&4096u32.to_le_bytes());
+    stat
+}
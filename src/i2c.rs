@@ -0,0 +1,188 @@
+//! An I2C master with the registers of the OpenCores one, as on the SiFive
+//! FU540, and the devices at its addresses, as the [`eeprom`] and the
+//! [`lm75`] temperature sensor. A command stored to the command register
+//! completes at once: the status tells whether the device acknowledged and
+//! raises [`I2C_IRQ`] while interrupts are enabled until the guest
+//! acknowledges it.
+
+pub mod eeprom;
+pub mod lm75;
+
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use tracing::debug;
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the I2C master is mapped, in a hole of the QEMU virt map.
+pub const I2C_BASE: u64 = 0x1003_0000;
+pub const I2C_SIZE: u64 = 0x1000;
+pub const I2C_IRQ: u32 = 13;
+
+/// Registers, by offset, 4 bytes apart: the clock prescaler, the control,
+/// the byte to transmit, or received when read, and the command, or the
+/// status when read.
+const PRER_LO: u64 = 0x00;
+const PRER_HI: u64 = 0x04;
+const CTR: u64 = 0x08;
+const TXR: u64 = 0x0c;
+const CR: u64 = 0x10;
+
+/// Control bits: the core, its interrupt.
+const CTR_EN: u8 = 0x80;
+const CTR_IEN: u8 = 0x40;
+
+/// Command bits: a start then the address in `txr`, a stop, a read, a write
+/// and acknowledging the interrupt. Whether the master acknowledges the
+/// bytes it reads doesn't matter to the devices.
+const CR_STA: u8 = 0x80;
+const CR_STO: u8 = 0x40;
+const CR_RD: u8 = 0x20;
+const CR_WR: u8 = 0x10;
+const CR_IACK: u8 = 0x01;
+
+/// Status bits: no acknowledge from the device, the bus busy between a
+/// start and a stop, and the interrupt flag.
+const SR_RXNACK: u8 = 0x80;
+const SR_BUSY: u8 = 0x40;
+const SR_IF: u8 = 0x01;
+
+/// A device at an address of the bus.
+pub trait I2cDevice: Send {
+    /// A start or repeated start addressed to it, to read from it if
+    /// `read`. Returns whether it acknowledges.
+    fn start(&mut self, _read: bool) -> bool {
+        true
+    }
+
+    /// Takes a byte from the master, returning whether it acknowledges.
+    fn write(&mut self, byte: u8) -> bool;
+
+    /// Sends the next byte to the master.
+    fn read(&mut self) -> u8;
+
+    /// A stop ended the transfer.
+    fn stop(&mut self) {}
+}
+
+pub struct I2c {
+    devices: BTreeMap<u8, Box<dyn I2cDevice>>,
+    prescale: u16,
+    control: u8,
+    tx: u8,
+    rx: u8,
+    status: u8,
+    /// The address of the device in the transfer.
+    target: Option<u8>,
+}
+
+impl fmt::Debug for I2c {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("I2c")
+            .field("devices", &self.devices.keys().collect::<Vec<_>>())
+            .field("status", &self.status)
+            .field("target", &self.target)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for I2c {
+    fn default() -> Self {
+        Self {
+            devices: BTreeMap::new(),
+            prescale: 0xffff,
+            control: 0,
+            tx: 0,
+            rx: 0,
+            status: 0,
+            target: None,
+        }
+    }
+}
+
+impl I2c {
+    /// Puts `device` at the 7-bit `address`.
+    pub fn attach(&mut self, address: u8, device: impl I2cDevice + 'static) {
+        assert!(address < 0x80, "invalid i2c address {address:#x}");
+        self.devices.insert(address, Box::new(device));
+    }
+
+    fn target(&mut self) -> Option<&mut Box<dyn I2cDevice>> {
+        self.devices.get_mut(&self.target?)
+    }
+
+    fn command(&mut self, command: u8) {
+        if command & CR_IACK != 0 {
+            self.status &= !SR_IF;
+        }
+        if self.control & CTR_EN == 0 || command & (CR_STA | CR_STO | CR_RD | CR_WR) == 0 {
+            return;
+        }
+
+        let mut ack = true;
+        if command & CR_STA != 0 && command & CR_WR != 0 {
+            let (address, read) = (self.tx >> 1, self.tx & 1 != 0);
+            self.status |= SR_BUSY;
+            self.target = Some(address);
+            ack = self.target().is_some_and(|x| x.start(read));
+            debug!(address, read, ack, "i2c start");
+        } else if command & CR_WR != 0 {
+            let byte = self.tx;
+            ack = self.target().is_some_and(|x| x.write(byte));
+        } else if command & CR_RD != 0 {
+            // nothing drives the bus, which stays high
+            self.rx = self.target().map_or(0xff, |x| x.read());
+        }
+        if command & CR_STO != 0 {
+            if let Some(device) = self.target() {
+                device.stop();
+            }
+            self.target = None;
+            self.status &= !SR_BUSY;
+        }
+        match ack {
+            true => self.status &= !SR_RXNACK,
+            false => self.status |= SR_RXNACK,
+        }
+        self.status |= SR_IF;
+    }
+}
+
+impl Device for I2c {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let value = match offset {
+            PRER_LO => self.prescale as u8,
+            PRER_HI => (self.prescale >> 8) as u8,
+            CTR => self.control,
+            TXR => self.rx,
+            CR => self.status,
+            _ => 0,
+        };
+        Ok(value.into())
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let value = value as u8;
+        match offset {
+            PRER_LO => self.prescale = self.prescale & 0xff00 | u16::from(value),
+            PRER_HI => self.prescale = self.prescale & 0xff | u16::from(value) << 8,
+            CTR => self.control = value & (CTR_EN | CTR_IEN),
+            TXR => self.tx = value,
+            CR => self.command(value),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "i2c"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(I2C_BASE..I2C_BASE + I2C_SIZE)
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        (self.control & CTR_IEN != 0 && self.status & SR_IF != 0).then_some(I2C_IRQ)
+    }
+}
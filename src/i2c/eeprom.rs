@@ -0,0 +1,77 @@
+//! A serial EEPROM as the 24C family: the bytes written after the address
+//! select where the next reads and writes go, one address byte up to 256
+//! bytes and two above. Writes wrap within the page, reads go on to the end
+//! of the chip and wrap to its start.
+
+use super::I2cDevice;
+
+/// Where an EEPROM answers, as the usual first 24C chip.
+pub const EEPROM_ADDRESS: u8 = 0x50;
+
+#[derive(Debug, Clone)]
+pub struct Eeprom {
+    data: Vec<u8>,
+    page: usize,
+    pointer: usize,
+    /// Address bytes still expected from the master in this write.
+    address: usize,
+}
+
+impl Eeprom {
+    /// An erased EEPROM of `size` bytes written in pages of `page` bytes.
+    pub fn new(size: usize, page: usize) -> Self {
+        Self::with_data(vec![0xff; size], page)
+    }
+
+    /// An EEPROM holding `data`.
+    pub fn with_data(data: Vec<u8>, page: usize) -> Self {
+        assert!(page.is_power_of_two() && page <= data.len());
+        Self {
+            data,
+            page,
+            pointer: 0,
+            address: 0,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn address_bytes(&self) -> usize {
+        if self.data.len() > 256 {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+impl I2cDevice for Eeprom {
+    fn start(&mut self, read: bool) -> bool {
+        self.address = if read { 0 } else { self.address_bytes() };
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        if self.address > 0 {
+            self.address -= 1;
+            // the high byte of two first
+            self.pointer = match self.address {
+                1 => usize::from(byte) << 8,
+                _ => self.pointer & !0xff | usize::from(byte),
+            } % self.data.len();
+            return true;
+        }
+        self.data[self.pointer] = byte;
+        let page = self.pointer & !(self.page - 1);
+        self.pointer = page | (self.pointer + 1) & (self.page - 1);
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let byte = self.data[self.pointer];
+        self.pointer = (self.pointer + 1) % self.data.len();
+        byte
+    }
+}
@@ -0,0 +1,119 @@
+//! An LM75 temperature sensor, whose temperature the host sets through a
+//! clone of it. The first byte written selects a register: the temperature,
+//! the configuration, the hysteresis and the overtemperature limit, each two
+//! bytes in half degrees but the one byte configuration.
+
+use std::sync::{Arc, Mutex};
+
+use super::I2cDevice;
+
+/// Where an LM75 answers with its address pins low.
+pub const LM75_ADDRESS: u8 = 0x48;
+
+const TEMPERATURE: u8 = 0;
+const CONFIG: u8 = 1;
+const HYSTERESIS: u8 = 2;
+const OVERTEMPERATURE: u8 = 3;
+
+#[derive(Debug)]
+struct Inner {
+    /// In half degrees Celsius.
+    temperature: i16,
+    config: u8,
+    hysteresis: i16,
+    overtemperature: i16,
+    pointer: u8,
+    /// Bytes read or written since the pointer was set.
+    index: usize,
+    /// The pointer follows the start of a write.
+    pointer_next: bool,
+}
+
+/// Clones share the sensor, the machine keeps one for the host while another
+/// is attached.
+#[derive(Debug, Clone)]
+pub struct Lm75 {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Default for Lm75 {
+    /// A sensor at 25 °C with the limits at reset, 75 and 80 °C.
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                temperature: 50,
+                config: 0,
+                hysteresis: 150,
+                overtemperature: 160,
+                pointer: TEMPERATURE,
+                index: 0,
+                pointer_next: false,
+            })),
+        }
+    }
+}
+
+/// Half degrees in the bytes of a register, the 9 high bits.
+fn encode(x: i16) -> [u8; 2] {
+    (x << 7).to_be_bytes()
+}
+
+impl Lm75 {
+    /// Sets the temperature in millidegrees Celsius, to the half degree and
+    /// within the -55 to 125 °C it measures.
+    pub fn set_temperature(&self, millicelsius: i32) {
+        let half = (millicelsius / 500).clamp(-110, 250);
+        self.inner.lock().unwrap().temperature = half as i16;
+    }
+
+    /// The temperature in millidegrees Celsius.
+    pub fn temperature(&self) -> i32 {
+        i32::from(self.inner.lock().unwrap().temperature) * 500
+    }
+}
+
+impl I2cDevice for Lm75 {
+    fn start(&mut self, read: bool) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pointer_next = !read;
+        inner.index = 0;
+        true
+    }
+
+    fn write(&mut self, byte: u8) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if std::mem::take(&mut inner.pointer_next) {
+            inner.pointer = byte & 3;
+            return true;
+        }
+        let index = inner.index;
+        inner.index += 1;
+        // the high byte first, then the bit of the half degree
+        let update = |x: i16| match index {
+            0 => (x & 1) | i16::from(byte as i8) << 1,
+            1 => x & !1 | i16::from(byte >> 7),
+            _ => x,
+        };
+        match inner.pointer {
+            CONFIG if index == 0 => inner.config = byte & 0x1f,
+            HYSTERESIS => inner.hysteresis = update(inner.hysteresis),
+            OVERTEMPERATURE => inner.overtemperature = update(inner.overtemperature),
+            // the temperature is read only
+            _ => {}
+        }
+        true
+    }
+
+    fn read(&mut self) -> u8 {
+        let mut inner = self.inner.lock().unwrap();
+        let bytes = match inner.pointer {
+            TEMPERATURE => encode(inner.temperature),
+            CONFIG => [inner.config; 2],
+            HYSTERESIS => encode(inner.hysteresis),
+            _ => encode(inner.overtemperature),
+        };
+        let byte = bytes[inner.index % 2];
+        inner.index += 1;
+        byte
+    }
+}
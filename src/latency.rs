@@ -0,0 +1,138 @@
+//! Interrupt latency: the instructions and cycles between a device raising
+//! an interrupt line and the guest taking the interrupt, per interrupt.
+
+use std::fmt;
+
+use crate::{exception::Interrupt, instruction::Instruction, observer::ExecutionObserver};
+
+/// Interrupt codes tracked, the standard ones fit.
+const CODES: usize = 16;
+
+/// Minimum, maximum and total of a series of measurements.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub min: u64,
+    pub max: u64,
+    pub total: u64,
+}
+
+impl Summary {
+    fn add(&mut self, value: u64, first: bool) {
+        self.min = if first { value } else { self.min.min(value) };
+        self.max = self.max.max(value);
+        self.total += value;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// Interrupts taken after being raised.
+    pub count: u64,
+    /// Raised and lowered again before being taken.
+    pub withdrawn: u64,
+    pub instructions: Summary,
+    pub cycles: Summary,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Time {
+    instructions: u64,
+    cycles: u64,
+}
+
+/// An observer measuring the latency of each interrupt.
+#[derive(Debug, Default)]
+pub struct InterruptLatency {
+    now: Time,
+    /// When each pending line was raised.
+    raised: [Option<Time>; CODES],
+    latencies: [Option<(Interrupt, Latency)>; CODES],
+}
+
+impl InterruptLatency {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Statistics of the interrupts which were raised, by code.
+    pub fn latencies(&self) -> impl Iterator<Item = (Interrupt, Latency)> + '_ {
+        self.latencies.iter().flatten().copied()
+    }
+
+    fn latency(&mut self, interrupt: Interrupt) -> Option<&mut Latency> {
+        let entry = self.latencies.get_mut(interrupt.code() as usize)?;
+        Some(&mut entry.get_or_insert((interrupt, Latency::default())).1)
+    }
+}
+
+impl fmt::Display for InterruptLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<20} {:>8} {:>9} {:>26} {:>26}",
+            "interrupt", "taken", "withdrawn", "instructions min/avg/max", "cycles min/avg/max"
+        )?;
+        for (interrupt, latency) in self.latencies() {
+            let summary = |s: Summary| match latency.count {
+                0 => String::from("-"),
+                n => format!("{}/{}/{}", s.min, s.total / n, s.max),
+            };
+            write!(
+                f,
+                "\n{:<20} {:>8} {:>9} {:>26} {:>26}",
+                format!("{interrupt:?}"),
+                latency.count,
+                latency.withdrawn,
+                summary(latency.instructions),
+                summary(latency.cycles)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ExecutionObserver for InterruptLatency {
+    fn on_instruction(&mut self, _pc: u64, _inst: &Instruction) {
+        self.now.instructions += 1;
+    }
+
+    fn on_cycles(&mut self, cycles: u64) {
+        self.now.cycles += cycles;
+    }
+
+    fn on_pending(&mut self, interrupt: Interrupt, pending: bool) {
+        let now = self.now;
+        let code = interrupt.code() as usize;
+        if code >= CODES {
+            return;
+        }
+        if pending {
+            self.raised[code] = Some(now);
+            self.latency(interrupt);
+        } else if self.raised[code].take().is_some() {
+            if let Some(latency) = self.latency(interrupt) {
+                latency.withdrawn += 1;
+            }
+        }
+    }
+
+    fn on_interrupt(&mut self, _pc: u64, interrupt: Interrupt) {
+        let now = self.now;
+        let Some(raised) = self
+            .raised
+            .get_mut(interrupt.code() as usize)
+            .and_then(Option::take)
+        else {
+            // Still pending from an earlier raise, already measured.
+            return;
+        };
+        if let Some(latency) = self.latency(interrupt) {
+            let first = latency.count == 0;
+            latency.count += 1;
+            latency
+                .instructions
+                .add(now.instructions - raised.instructions, first);
+            latency.cycles.add(now.cycles - raised.cycles, first);
+        }
+    }
+}
@@ -1,3 +1,51 @@
-pub mod bus;
-pub mod cpu;
-pub mod dram;
+//! Host side of rysk: machine construction and devices backed by the host.
+//! The emulator core is re-exported from [`rysk_core`].
+
+pub use rysk_core::{
+    bus, cache, compressed, cpu, crypto, csr, disasm, dram, error, exception, fpu, hart, hooks,
+    hpm, instruction, isa, mmu, observer, pmp, time, timing, trigger, vector,
+};
+
+pub mod aclint;
+pub mod backend;
+pub mod cluster;
+pub mod console;
+pub mod control;
+pub mod cosim;
+pub mod debugger;
+pub mod ecall;
+pub mod elf;
+pub mod fault;
+pub mod finisher;
+pub mod framebuffer;
+pub mod gdb;
+pub mod gpio;
+pub mod heatmap;
+pub mod htif;
+pub mod i2c;
+pub mod latency;
+pub mod machine;
+pub mod net;
+pub mod pflash;
+pub mod plic;
+#[cfg(unix)]
+pub mod plugin;
+pub mod profile;
+pub mod random;
+pub mod runner;
+pub mod sbi;
+pub mod schedule;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod semihosting;
+pub mod snapshot;
+pub mod spi;
+pub mod stats;
+pub mod throttle;
+pub mod trace;
+pub mod uart;
+#[cfg(target_os = "linux")]
+pub mod user;
+pub mod virtio;
+pub mod vnc;
+pub mod watchdog;
@@ -0,0 +1,1277 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io,
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use crate::plugin::{self, PluginSpec};
+#[cfg(feature = "script")]
+use crate::script::Script;
+#[cfg(unix)]
+use crate::virtio::p9::Virtio9p;
+use crate::{
+    aclint::{
+        Mtimer, SoftwareInterrupts, MSWI_BASE, MSWI_SIZE, MTIMER_BASE, MTIMER_SIZE, SSWI_BASE,
+        SSWI_SIZE,
+    },
+    backend::Backend,
+    bus::Bus,
+    cache::{Cache, CacheConfig},
+    console::Console,
+    cpu::{Cpu, MisalignedPolicy, MCOUNTEREN, MIE, MIP, RDTIME},
+    dram::{Dram, DRAM_SIZE},
+    ecall::EcallTrace,
+    elf::Elf,
+    error::EmulatorError,
+    exception::Exception,
+    finisher::{TestFinisher, FINISHER_BASE, FINISHER_SIZE},
+    framebuffer::Framebuffer,
+    gpio::Gpio,
+    hart::{Hart, MAX_HARTS},
+    htif::Htif,
+    i2c::{
+        eeprom::{Eeprom, EEPROM_ADDRESS},
+        lm75::{Lm75, LM75_ADDRESS},
+        I2c,
+    },
+    instruction::Instruction,
+    isa::{Isa, Xlen},
+    mmu::AdPolicy,
+    net::{Hub, Nic, NIC_BASE, NIC_SIZE},
+    pflash::Pflash,
+    plic::Plic,
+    random::{Jitter, Rng},
+    sbi::{Action, Sbi},
+    schedule::{self, Turn},
+    semihosting::Semihosting,
+    spi::{sd::SdCard, Spi},
+    throttle::Throttle,
+    time::InstretClock,
+    timing::{Pipeline, PipelineConfig},
+    uart::Uart,
+    vector::Vector,
+    virtio::{
+        self, blk::VirtioBlk, input::Input, net::VirtioNet, rng::VirtioRng, Virtio, VirtioDevice,
+        VIRTIO_SLOTS,
+    },
+    watchdog::{Watchdog, WatchdogAction},
+};
+
+#[allow(non_upper_case_globals)]
+pub const KiB: u64 = 1024;
+#[allow(non_upper_case_globals)]
+pub const MiB: u64 = 1024 * KiB;
+#[allow(non_upper_case_globals)]
+pub const GiB: u64 = 1024 * MiB;
+
+/// Instructions a hart runs before the next one gets its turn, unless
+/// [`MachineBuilder::quantum`] says otherwise.
+pub const HART_QUANTUM: u64 = 1000;
+
+/// Longest a `wfi` sleeps under [`WfiPolicy::Sleep`] by default.
+pub const WFI_TIMEOUT: Duration = Duration::from_millis(10);
+
+/// How often a sleeping `wfi` polls the devices for an interrupt.
+const WFI_POLL: Duration = Duration::from_micros(100);
+
+/// A fully wired emulated machine.
+#[derive(Debug)]
+pub struct Machine {
+    /// Executes the running hart.
+    pub cpu: Cpu,
+    /// The other harts, in the order they run next, see [`crate::hart`].
+    pub harts: Vec<Hart>,
+    /// Instructions executed by the running hart in its turn.
+    turn: u64,
+    quantum: u64,
+    /// Turns taken so far, when recording, see [`crate::schedule`].
+    schedule: Option<Vec<Turn>>,
+    /// Turns still to replay, the first is the running hart's.
+    replay: Option<VecDeque<Turn>>,
+    /// Every hart stopped, so nothing runs anymore.
+    halted: bool,
+    /// Delays the interrupts of the devices, see [`MachineBuilder::irq_jitter`].
+    jitter: Option<Jitter>,
+    /// Paces the run to a clock frequency, see [`crate::throttle`].
+    pub throttle: Option<Throttle>,
+    /// Present when the program defines a `tohost` symbol.
+    pub htif: Option<Htif>,
+    pub semihosting: Option<Semihosting>,
+    /// Services the `ecall`s, see [`crate::sbi`].
+    pub sbi: Option<Sbi>,
+    /// Mapped at [`FINISHER_BASE`].
+    pub finisher: TestFinisher,
+    /// Inter-processor interrupts, mapped at [`MSWI_BASE`] and [`SSWI_BASE`].
+    pub ipi: SoftwareInterrupts,
+    /// Machine timers, mapped at [`MTIMER_BASE`].
+    pub mtimer: Mtimer,
+    /// Mapped at [`PLIC_BASE`](crate::plic::PLIC_BASE), routes the lines of
+    /// the devices to the external interrupts.
+    pub plic: Plic,
+    /// Mapped at [`UART_BASE`](crate::uart::UART_BASE), fed the keystrokes
+    /// of the [`console`](Self::console).
+    pub uart: Uart,
+    /// The virtio devices, by slot, see [`crate::virtio`].
+    pub virtio: Vec<Virtio>,
+    /// The framebuffer, if the machine has one, for the displays.
+    pub framebuffer: Option<Framebuffer>,
+    /// Where the host sends keyboard and pointer events, with virtio-input.
+    pub input: Option<Input>,
+    /// Mapped at [`WATCHDOG_BASE`](crate::watchdog::WATCHDOG_BASE), if the
+    /// machine has one.
+    pub watchdog: Option<Watchdog>,
+    /// Mapped at [`GPIO_BASE`](crate::gpio::GPIO_BASE), if the machine has
+    /// one, for the host to drive and watch the pins.
+    pub gpio: Option<Gpio>,
+    /// The temperature sensor on the I2C bus, if the machine has one, for
+    /// the host to set the temperature.
+    pub temperature_sensor: Option<Lm75>,
+    /// What an `ebreak` does when [`run_until`](Self::run_until) meets one.
+    pub ebreak: EbreakPolicy,
+    /// What the host does while every hart waits in a `wfi`.
+    pub wfi: WfiPolicy,
+    /// Harts which ended their turn in a `wfi` in a row.
+    idle_harts: u64,
+    /// Counts the `ecall`s, see [`crate::ecall`].
+    pub ecalls: Option<EcallTrace>,
+    /// Keystrokes for the console devices, the run stops on Ctrl-A x.
+    pub console: Option<Console>,
+    /// Runs slow device I/O in the background, see [`crate::backend`].
+    pub backend: Backend,
+    #[cfg(feature = "script")]
+    pub script: Option<Script>,
+    /// The last data access, once a run watched them.
+    accesses: Option<DataAccesses>,
+}
+
+/// The pc and the address of a data access, see [`Machine::run_until`].
+type DataAccesses = Arc<Mutex<Option<(u64, u64)>>>;
+
+impl Machine {
+    pub fn builder() -> MachineBuilder {
+        MachineBuilder::default()
+    }
+
+    /// A machine running `cpu` with nothing else wired, which
+    /// [`MachineBuilder::build`] goes on from.
+    fn new(cpu: Cpu) -> Self {
+        Self {
+            cpu,
+            harts: Vec::new(),
+            turn: 0,
+            quantum: HART_QUANTUM,
+            schedule: None,
+            replay: None,
+            halted: false,
+            jitter: None,
+            throttle: None,
+            htif: None,
+            semihosting: None,
+            sbi: None,
+            finisher: TestFinisher::default(),
+            ipi: SoftwareInterrupts::default(),
+            mtimer: Mtimer::default(),
+            plic: Plic::default(),
+            uart: Uart::default(),
+            virtio: Vec::new(),
+            framebuffer: None,
+            input: None,
+            watchdog: None,
+            gpio: None,
+            temperature_sensor: None,
+            ebreak: EbreakPolicy::default(),
+            wfi: WfiPolicy::default(),
+            idle_harts: 0,
+            ecalls: None,
+            console: None,
+            backend: Backend::default(),
+            #[cfg(feature = "script")]
+            script: None,
+            accesses: None,
+        }
+    }
+
+    /// Executes an instruction and services the host interfaces. Exceptions
+    /// the guest installed a handler for are taken as traps, the others
+    /// returned, see [`Cpu::trap`].
+    pub fn step(&mut self) -> Result<(), Exception> {
+        self.step_trap().map(|_| ())
+    }
+
+    /// Like [`Machine::step`], also returning the exception the instruction
+    /// raised if the guest took it as a trap, see [`Cpu::trap`].
+    pub fn step_trap(&mut self) -> Result<Option<Exception>, Exception> {
+        if let Some(throttle) = &mut self.throttle {
+            throttle.tick();
+        }
+        #[cfg(feature = "script")]
+        if let Some(script) = &self.script {
+            if script.breakpoint(&mut self.cpu) {
+                return Ok(None);
+            }
+        }
+
+        let mut action = Action::Return;
+        let mut trap = None;
+        match (self.cpu.step(), &mut self.semihosting) {
+            (Err(exception @ Exception::Breakpoint(_)), _)
+                if std::mem::take(&mut self.cpu.trigger_halt) =>
+            {
+                return Err(exception);
+            }
+            (Err(Exception::Breakpoint(pc)), Some(semihosting))
+                if semihosting.is_call(&mut self.cpu, pc) =>
+            {
+                semihosting.call(&mut self.cpu, self.console.as_ref());
+                // Skip the ebreak and the trailing srai.
+                self.cpu.pc = pc + 8;
+            }
+            (Err(Exception::EnvironmentCallFromSMode | Exception::EnvironmentCallFromMMode), _)
+                if self.sbi.is_some() =>
+            {
+                let sbi = self.sbi.as_mut().unwrap();
+                action = sbi.call(&mut self.cpu, &mut self.harts, self.console.as_ref());
+                self.cpu.pc += 4;
+            }
+            #[cfg(feature = "script")]
+            (Err(exception), _)
+                if self
+                    .script
+                    .as_ref()
+                    .is_some_and(|x| x.trap(&mut self.cpu, &exception)) => {}
+            (Err(Exception::Breakpoint(pc)), _) if self.ebreak == EbreakPolicy::Exit => {
+                return Err(Exception::Breakpoint(pc));
+            }
+            (Err(exception), _) => {
+                self.cpu.trap(exception)?;
+                trap = Some(exception);
+            }
+            (Ok(()), _) => {}
+        }
+
+        if let Some(htif) = &mut self.htif {
+            htif.poll(&mut self.cpu, self.console.as_ref());
+        }
+        if self.jitter.as_mut().is_none_or(Jitter::ready) {
+            self.poll_interrupts();
+        }
+
+        self.turn += 1;
+        if action == Action::StopHart {
+            self.stop_hart();
+        } else if self.turn >= self.quantum() {
+            self.idle_harts = 0;
+            self.next_hart();
+        }
+        Ok(trap)
+    }
+
+    /// Maps `device` in the next free virtio slot.
+    pub fn add_virtio(&mut self, device: impl VirtioDevice + 'static) -> Result<(), EmulatorError> {
+        let slot = self.virtio.len() as u64;
+        if slot == VIRTIO_SLOTS {
+            return Err(EmulatorError::Unsupported("more than 8 virtio devices"));
+        }
+        let device = Virtio::new(slot, device);
+        self.cpu.bus.attach(device.clone()).unwrap();
+        self.virtio.push(device);
+        Ok(())
+    }
+
+    /// Makes the interrupts of the devices, timers and other harts pending.
+    fn poll_interrupts(&mut self) {
+        self.cpu.bus.tick();
+        for device in &self.virtio {
+            if let Some(console) = &self.console {
+                device.poll_console(console);
+            }
+            device.process(&mut self.cpu.bus);
+        }
+        if let Some(console) = &self.console {
+            self.uart.poll(console);
+        }
+        self.backend.poll(&mut self.cpu);
+        self.ipi.deliver(&mut self.cpu, &mut self.harts);
+        self.mtimer.deliver(&mut self.cpu, &mut self.harts);
+        self.plic.sample(&self.cpu.bus);
+        self.plic.deliver(&mut self.cpu, &mut self.harts);
+        if let Some(sbi) = &mut self.sbi {
+            sbi.poll(&mut self.cpu, &mut self.harts);
+        }
+        // the running hart polls its own stimecmp as time moves
+        let now = self.cpu.csrs[RDTIME];
+        for hart in &mut self.harts {
+            hart.poll_stimecmp(now);
+        }
+        if let Some(watchdog) = &self.watchdog {
+            watchdog.poll(now);
+        }
+    }
+
+    /// Ends the turn of a hart which executed a `wfi` with nothing pending.
+    /// Once every hart did, the host waits as [`WfiPolicy`] says.
+    fn wait_for_interrupt(&mut self) {
+        // A replayed turn lasts as long as it did.
+        if self.replay.is_some() {
+            return;
+        }
+        self.idle_harts += 1;
+        let running = 1 + self.harts.iter().filter(|x| !x.stopped).count() as u64;
+        if self.idle_harts >= running {
+            self.idle_harts = 0;
+            self.sleep();
+        }
+        self.next_hart();
+    }
+
+    fn sleep(&mut self) {
+        // Nothing enabled can wake a hart up, e.g. a wfi before the kernel
+        // sets up its interrupts, so don't bother waiting.
+        let enabled = |csrs: &[u64; 4096]| csrs[MIE] != 0;
+        if !enabled(&self.cpu.csrs)
+            && !self.harts.iter().any(|x| enabled(&x.csrs))
+            && self.cpu.bus.reservations.is_empty()
+        {
+            return;
+        }
+        let timeout = match self.wfi {
+            WfiPolicy::Nop => return,
+            WfiPolicy::Yield => return thread::yield_now(),
+            WfiPolicy::Sleep(timeout) => timeout,
+        };
+        let deadline = Instant::now() + timeout;
+        // a wrs.nto also wakes up when a device drops its reservation
+        let reserved = self.cpu.bus.reservations.len();
+        let woken = |machine: &Self| {
+            machine.cpu.interrupt_pending()
+                || machine.cpu.bus.reservations.len() < reserved
+                || machine.harts.iter().any(|x| x.csrs[MIP] & x.csrs[MIE] != 0)
+        };
+        while !woken(self) && !self.quit_requested() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            thread::sleep(WFI_POLL.min(deadline - now));
+            // the timers compare against time, which only moves as
+            // instructions retire otherwise
+            self.cpu.csrs[RDTIME] = self.cpu.clock.now(self.cpu.retired);
+            self.cpu.poll_stimecmp();
+            self.poll_interrupts();
+        }
+    }
+
+    /// Instructions the running hart gets in this turn, as replayed while
+    /// the schedule lasts.
+    fn quantum(&self) -> u64 {
+        match self.replay.as_ref().and_then(VecDeque::front) {
+            Some(turn) => turn.instructions,
+            None => self.quantum,
+        }
+    }
+
+    /// Ends the turn of the running hart, running the next one which isn't
+    /// stopped round-robin, or the next one of the replayed schedule.
+    pub fn next_hart(&mut self) {
+        if let Some(i) = self.end_turn() {
+            self.switch_to(i);
+        }
+    }
+
+    /// Records the turn which ended, returning the index in `harts` of the
+    /// hart running next, none to keep running the same one.
+    fn end_turn(&mut self) -> Option<usize> {
+        let turn = Turn {
+            hart: self.cpu.hart_id(),
+            instructions: std::mem::take(&mut self.turn),
+        };
+        if let Some(turns) = &mut self.schedule {
+            schedule::push(turns, turn);
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.pop_front();
+            if let Some(next) = replay.front() {
+                return self.harts.iter().position(|x| x.id() == next.hart);
+            }
+        }
+        self.harts.iter().position(|x| !x.stopped)
+    }
+
+    /// Turns taken so far, including the one of the running hart, when
+    /// recording with [`MachineBuilder::record_schedule`].
+    pub fn schedule(&self) -> Option<Vec<Turn>> {
+        let mut turns = self.schedule.clone()?;
+        let turn = Turn {
+            hart: self.cpu.hart_id(),
+            instructions: self.turn,
+        };
+        schedule::push(&mut turns, turn);
+        Some(turns)
+    }
+
+    /// Stops the running hart until another one starts it.
+    fn stop_hart(&mut self) {
+        let next = self.end_turn();
+        match next.or_else(|| self.harts.iter().position(|x| !x.stopped)) {
+            Some(i) => {
+                self.switch_to(i);
+                // Queued before the harts which were skipped.
+                let parked = self.harts.len() - 1 - i;
+                self.harts[parked].stopped = true;
+            }
+            None => self.halted = true,
+        }
+    }
+
+    /// Runs `harts[i]`, queueing the harts before it after the one which ran.
+    fn switch_to(&mut self, i: usize) {
+        self.cpu.switch(&mut self.harts[i]);
+        self.harts[..=i].rotate_left(i);
+        self.harts.rotate_left(i + 1);
+    }
+
+    /// Where the pc and the data address of each load, store or atomic go as
+    /// it begins, through a pre hook added the first time.
+    fn watch_accesses(&mut self) -> DataAccesses {
+        if let Some(accesses) = &self.accesses {
+            return accesses.clone();
+        }
+        let accesses = DataAccesses::default();
+        let (slot, xlen) = (accesses.clone(), self.cpu.isa.xlen);
+        self.cpu.hooks.pre(move |ctx| {
+            *slot.lock().unwrap() = data_address(&ctx.inst, ctx.regs, xlen).map(|x| (ctx.pc, x));
+        });
+        self.accesses = Some(accesses.clone());
+        accesses
+    }
+
+    /// Runs until the guest shuts down, raises an exception or the user quits.
+    pub fn run(&mut self) -> ExitReason {
+        self.run_until(&StopCondition::default())
+    }
+
+    /// Runs until the guest shuts down, raises an exception nothing handles,
+    /// the user quits or `stop` is met. A breakpoint at the current pc doesn't
+    /// stop the first instruction, so a stopped machine can be resumed.
+    pub fn run_until(&mut self, stop: &StopCondition) -> ExitReason {
+        let mut executed = 0;
+        loop {
+            if let Some(code) = self.exit_code() {
+                return ExitReason::Shutdown(code);
+            }
+            if self.reboot_requested() {
+                return ExitReason::Reboot;
+            }
+            if let Some(watchdog) = self.watchdog.as_ref().filter(|x| x.expired()) {
+                return match watchdog.action() {
+                    WatchdogAction::Reset => ExitReason::Reboot,
+                    WatchdogAction::Halt => ExitReason::Halted,
+                };
+            }
+            if self.quit_requested() {
+                return ExitReason::HostRequest;
+            }
+            if self.halted {
+                return ExitReason::Halted;
+            }
+            if stop.max_instructions.is_some_and(|max| executed >= max) {
+                return ExitReason::MaxInstructions;
+            }
+
+            let pc = self.cpu.pc;
+            if executed > 0 && stop.breakpoints.contains(&pc) {
+                return ExitReason::Breakpoint(pc);
+            }
+            let accesses = match stop.watchpoints.is_empty() {
+                true => None,
+                false => Some(self.watch_accesses()),
+            };
+
+            executed += 1;
+            match self.step() {
+                Ok(()) if self.cpu.idle => {
+                    if stop.wfi {
+                        return ExitReason::Wfi;
+                    }
+                    self.wait_for_interrupt();
+                }
+                Ok(()) => {}
+                Err(Exception::Breakpoint(_)) if self.ebreak == EbreakPolicy::Exit => {
+                    return ExitReason::Shutdown(self.cpu.regs[10]);
+                }
+                Err(exception) => return ExitReason::Exception(exception),
+            }
+            let access = accesses.and_then(|x| x.lock().unwrap().take());
+            if let Some((pc, addr)) =
+                access.filter(|(_, addr)| stop.watchpoints.iter().any(|x| x.contains(addr)))
+            {
+                return ExitReason::Watchpoint { pc, addr };
+            }
+        }
+    }
+
+    /// Whether the user asked to quit from the console.
+    pub fn quit_requested(&self) -> bool {
+        self.console.as_ref().is_some_and(Console::quit_requested)
+    }
+
+    /// Exit code the guest reported through the test finisher, htif,
+    /// semihosting or the SBI firmware.
+    pub fn exit_code(&self) -> Option<u64> {
+        let htif = self.htif.as_ref().and_then(|x| x.exit_code);
+        let semihosting = self.semihosting.as_ref().and_then(|x| x.exit_code);
+        let sbi = self.sbi.as_ref().and_then(|x| x.exit_code);
+        self.finisher.exit_code().or(htif).or(semihosting).or(sbi)
+    }
+
+    /// Whether the guest asked for a reboot through the test finisher or the
+    /// SBI firmware.
+    pub fn reboot_requested(&self) -> bool {
+        self.finisher.reboot_requested() || self.sbi.as_ref().is_some_and(|x| x.reboot_requested)
+    }
+}
+
+/// When [`Machine::run_until`] stops, besides the guest finishing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StopCondition {
+    /// Instructions to execute at most.
+    pub max_instructions: Option<u64>,
+    /// Stop before executing these addresses.
+    pub breakpoints: BTreeSet<u64>,
+    /// Stop after a load, store or atomic accessing one of these ranges.
+    pub watchpoints: Vec<Range<u64>>,
+    /// Stop after a `wfi` with no interrupt pending, otherwise the hart
+    /// waits for one as [`WfiPolicy`] says.
+    pub wfi: bool,
+}
+
+/// Why [`Machine::run_until`] returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// About to execute a breakpoint address.
+    Breakpoint(u64),
+    /// The instruction at `pc` accessed `addr`, inside a watchpoint.
+    Watchpoint {
+        pc: u64,
+        addr: u64,
+    },
+    /// The guest asked to stop with this exit code, through the test finisher,
+    /// htif, semihosting, the SBI firmware or an `ebreak` under
+    /// [`EbreakPolicy::Exit`].
+    Shutdown(u64),
+    /// The guest asked for a reboot, through the test finisher or the SBI
+    /// firmware, or the watchdog reset it. The machine isn't reset, the host builds a new one if it wants.
+    Reboot,
+    MaxInstructions,
+    /// Executed a `wfi` with no interrupt pending.
+    Wfi,
+    /// The user asked to quit, e.g. with Ctrl-A x on the console.
+    HostRequest,
+    /// Every hart stopped, through SBI `hart_stop`, or the watchdog halted
+    /// the machine.
+    Halted,
+    /// An exception nothing handled, the pc is left at the faulting instruction.
+    /// A jump to an unmapped address, such as returning to 0 from the entry
+    /// point, is an [`Exception::InstructionAccessFault`].
+    Exception(Exception),
+}
+
+/// What an `ebreak` outside of a semihosting call does when no debugger is attached.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EbreakPolicy {
+    /// Stop with [`ExitReason::Exception`], leaving the pc at the `ebreak`.
+    #[default]
+    Stop,
+    /// Shut down with a0 as the exit code.
+    Exit,
+}
+
+/// What the host does when every hart waits in a `wfi` with no interrupt
+/// pending. The `wfi` completes after it either way, as it may on hardware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WfiPolicy {
+    /// Nothing, the guest spins in its idle loop.
+    Nop,
+    /// Yields the host thread.
+    Yield,
+    /// Sleeps the host thread until an interrupt is pending, polling the
+    /// devices, or for this long at most.
+    Sleep(Duration),
+}
+
+impl Default for WfiPolicy {
+    fn default() -> Self {
+        Self::Sleep(WFI_TIMEOUT)
+    }
+}
+
+/// Firmware servicing the `ecall`s of the guest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Firmware {
+    /// None, an `ecall` stops with [`ExitReason::Exception`].
+    #[default]
+    None,
+    /// The built-in SBI implementation, see [`crate::sbi`].
+    Builtin,
+}
+
+impl FromStr for Firmware {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "builtin" => Ok(Self::Builtin),
+            _ => Err(format!("unknown firmware {s}, expected none or builtin")),
+        }
+    }
+}
+
+impl FromStr for WfiPolicy {
+    type Err = String;
+
+    /// `nop`, `yield`, `sleep`, or `sleep=MS` with a timeout in milliseconds.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            _ if s == "nop" => Ok(Self::Nop),
+            _ if s == "yield" => Ok(Self::Yield),
+            _ if s == "sleep" => Ok(Self::default()),
+            Some(("sleep", ms)) => ms
+                .parse()
+                .map(|ms| Self::Sleep(Duration::from_millis(ms)))
+                .map_err(|_| format!("invalid wfi timeout {ms}")),
+            _ => Err(format!(
+                "unknown wfi policy {s}, expected nop, yield, sleep or sleep=MS"
+            )),
+        }
+    }
+}
+
+impl FromStr for EbreakPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(Self::Stop),
+            "exit" => Ok(Self::Exit),
+            _ => Err(format!("unknown ebreak policy {s}, expected stop or exit")),
+        }
+    }
+}
+
+/// Address accessed by the load, store or atomic `inst`, from the registers
+/// before it executes, if it is one.
+fn data_address(inst: &Instruction, regs: &[u64; 32], xlen: Xlen) -> Option<u64> {
+    let raw = inst.raw;
+    let offset = match inst.opcode {
+        // loads, I-type immediate
+        0x03 => (raw as i32 >> 20) as u64,
+        // stores, S-type immediate
+        0x23 => (((raw & 0xfe000000) as i32 >> 20) as u64) | ((raw >> 7) & 0x1f),
+        // atomics
+        0x2f => 0,
+        _ => return None,
+    };
+    let addr = regs[inst.rs1].wrapping_add(offset);
+    Some(match xlen {
+        Xlen::Rv32 => addr as u32 as u64,
+        Xlen::Rv64 => addr,
+    })
+}
+
+/// Declarative construction of a [`Machine`].
+///
+/// ```
+/// use rysk::machine::{Machine, MiB};
+///
+/// let machine = Machine::builder()
+///     .memory(16 * MiB)
+///     .isa("rv64ima_zicsr")
+///     .image(vec![0x93, 0x0e, 0x20, 0x00])
+///     .build()
+///     .unwrap();
+/// assert_eq!(machine.cpu.bus.dram.size(), 16 * MiB);
+/// ```
+#[derive(Debug, Clone)]
+pub struct MachineBuilder {
+    memory: u64,
+    isa: Option<String>,
+    image: Vec<u8>,
+    elf: Option<Vec<u8>>,
+    semihosting: bool,
+    firmware: Firmware,
+    ebreak: EbreakPolicy,
+    wfi: WfiPolicy,
+    misaligned: MisalignedPolicy,
+    ad_policy: AdPolicy,
+    nic: Option<(Hub, [u8; 6])>,
+    virtio_net: Option<(Hub, [u8; 6])>,
+    virtio_rng: Option<u64>,
+    framebuffer: Option<(u32, u32)>,
+    virtio_input: bool,
+    watchdog: Option<WatchdogAction>,
+    gpio: bool,
+    sd_card: Option<PathBuf>,
+    pflash: Option<PathBuf>,
+    i2c: bool,
+    #[cfg(unix)]
+    shares: Vec<(String, PathBuf)>,
+    icache: Option<CacheConfig>,
+    dcache: Option<CacheConfig>,
+    pipeline: Option<PipelineConfig>,
+    vlen: Option<u64>,
+    ecalls: Option<EcallTrace>,
+    harts: u64,
+    quantum: u64,
+    record_schedule: bool,
+    replay_schedule: Option<Vec<Turn>>,
+    seed: Option<u64>,
+    irq_jitter: u64,
+    frequency: Option<u64>,
+    deterministic: bool,
+    drives: Vec<PathBuf>,
+    uart_stdio: bool,
+    #[cfg(unix)]
+    plugins: Vec<PluginSpec>,
+    #[cfg(feature = "script")]
+    script: Option<PathBuf>,
+}
+
+impl Default for MachineBuilder {
+    fn default() -> Self {
+        Self {
+            memory: DRAM_SIZE,
+            isa: None,
+            image: Vec::new(),
+            elf: None,
+            semihosting: false,
+            firmware: Firmware::default(),
+            ebreak: EbreakPolicy::default(),
+            wfi: WfiPolicy::default(),
+            misaligned: MisalignedPolicy::default(),
+            ad_policy: AdPolicy::default(),
+            nic: None,
+            virtio_net: None,
+            virtio_rng: None,
+            framebuffer: None,
+            virtio_input: false,
+            watchdog: None,
+            gpio: false,
+            sd_card: None,
+            pflash: None,
+            i2c: false,
+            #[cfg(unix)]
+            shares: Vec::new(),
+            icache: None,
+            dcache: None,
+            pipeline: None,
+            vlen: None,
+            ecalls: None,
+            harts: 1,
+            quantum: HART_QUANTUM,
+            record_schedule: false,
+            replay_schedule: None,
+            seed: None,
+            irq_jitter: 0,
+            frequency: None,
+            deterministic: false,
+            drives: Vec::new(),
+            uart_stdio: false,
+            #[cfg(unix)]
+            plugins: Vec::new(),
+            #[cfg(feature = "script")]
+            script: None,
+        }
+    }
+}
+
+impl MachineBuilder {
+    /// Size of the dram in bytes.
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.memory = bytes;
+        self
+    }
+
+    /// ISA string to execute, e.g. `rv64ima_zicsr`. Defaults to everything implemented.
+    pub fn isa(mut self, isa: &str) -> Self {
+        self.isa = Some(isa.to_string());
+        self
+    }
+
+    /// Raw binary loaded at the start of dram, where execution begins.
+    pub fn image(mut self, image: Vec<u8>) -> Self {
+        self.image = image;
+        self
+    }
+
+    /// ELF executable whose segments are loaded into dram, execution begins at
+    /// its entry point. Replaces any raw [`image`](Self::image).
+    pub fn elf(mut self, data: Vec<u8>) -> Self {
+        self.elf = Some(data);
+        self
+    }
+
+    /// Services semihosting calls made by the guest.
+    pub fn semihosting(mut self, enabled: bool) -> Self {
+        self.semihosting = enabled;
+        self
+    }
+
+    /// Firmware servicing `ecall`s. With [`Firmware::Builtin`] secondary
+    /// harts start stopped, waiting for `hart_start`.
+    pub fn firmware(mut self, firmware: Firmware) -> Self {
+        self.firmware = firmware;
+        self
+    }
+
+    /// What an `ebreak` which isn't a semihosting call does.
+    pub fn ebreak(mut self, policy: EbreakPolicy) -> Self {
+        self.ebreak = policy;
+        self
+    }
+
+    /// What the host does while the harts wait in a `wfi`.
+    pub fn wfi(mut self, policy: WfiPolicy) -> Self {
+        self.wfi = policy;
+        self
+    }
+
+    /// What loads and stores not aligned on their size do.
+    pub fn misaligned(mut self, policy: MisalignedPolicy) -> Self {
+        self.misaligned = policy;
+        self
+    }
+
+    /// Whether translations set the accessed and dirty bits of the page
+    /// table entries, or raise page faults for the software to.
+    pub fn ad_policy(mut self, policy: AdPolicy) -> Self {
+        self.ad_policy = policy;
+        self
+    }
+
+    /// Maps a network card on a port of `hub` at [`NIC_BASE`], see [`crate::net`].
+    pub fn nic(mut self, hub: &Hub, mac: [u8; 6]) -> Self {
+        self.nic = Some((hub.clone(), mac));
+        self
+    }
+
+    /// Adds a virtio-net device on a port of `hub`, see [`crate::virtio`].
+    pub fn virtio_net(mut self, hub: &Hub, mac: [u8; 6]) -> Self {
+        self.virtio_net = Some((hub.clone(), mac));
+        self
+    }
+
+    /// Adds a virtio-rng device whose bytes are drawn from `seed`, the same
+    /// seed giving the guest the same entropy.
+    pub fn virtio_rng(mut self, seed: u64) -> Self {
+        self.virtio_rng = Some(seed);
+        self
+    }
+
+    /// Maps a framebuffer of `width` by `height` pixels, see
+    /// [`crate::framebuffer`].
+    pub fn framebuffer(mut self, width: u32, height: u32) -> Self {
+        self.framebuffer = Some((width, height));
+        self
+    }
+
+    /// Adds a virtio-input keyboard and tablet, fed through
+    /// [`Machine::input`].
+    pub fn virtio_input(mut self, enabled: bool) -> Self {
+        self.virtio_input = enabled;
+        self
+    }
+
+    /// Maps a watchdog which does `action` once the guest stops feeding it,
+    /// see [`crate::watchdog`].
+    pub fn watchdog(mut self, action: WatchdogAction) -> Self {
+        self.watchdog = Some(action);
+        self
+    }
+
+    /// Maps a GPIO block, whose pins the host reaches through
+    /// [`Machine::gpio`].
+    pub fn gpio(mut self, enabled: bool) -> Self {
+        self.gpio = enabled;
+        self
+    }
+
+    /// Maps an SPI master with an SD card on its first chip select, holding
+    /// the image at `path`, see [`crate::spi::sd`].
+    pub fn sd_card(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sd_card = Some(path.into());
+        self
+    }
+
+    /// Maps a CFI flash holding the image at `path`, which the guest
+    /// programs and erases in place, see [`crate::pflash`].
+    pub fn pflash(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pflash = Some(path.into());
+        self
+    }
+
+    /// Maps an I2C master with a 256 byte EEPROM and an LM75 temperature
+    /// sensor, see [`crate::i2c`] and [`Machine::temperature_sensor`].
+    pub fn i2c(mut self, enabled: bool) -> Self {
+        self.i2c = enabled;
+        self
+    }
+
+    /// Shares the host directory `dir` through a virtio-9p device, which the
+    /// guest mounts by `tag`. May be called again for more directories.
+    #[cfg(unix)]
+    pub fn share(mut self, tag: &str, dir: impl Into<PathBuf>) -> Self {
+        self.shares.push((tag.to_string(), dir.into()));
+        self
+    }
+
+    /// Models an instruction cache fed by every fetch, see [`crate::cache`].
+    pub fn icache(mut self, config: CacheConfig) -> Self {
+        self.icache = Some(config);
+        self
+    }
+
+    /// Models a data cache fed by every load and store to dram.
+    pub fn dcache(mut self, config: CacheConfig) -> Self {
+        self.dcache = Some(config);
+        self
+    }
+
+    /// Counts cycles as a 5-stage in-order pipeline would, see [`crate::timing`].
+    pub fn pipeline(mut self, config: PipelineConfig) -> Self {
+        self.pipeline = Some(config);
+        self
+    }
+
+    /// Width of the vector registers in bits, [`DEFAULT_VLEN`](crate::vector::DEFAULT_VLEN)
+    /// unless set.
+    pub fn vlen(mut self, bits: u64) -> Self {
+        self.vlen = Some(bits);
+        self
+    }
+
+    /// Traces the `ecall`s of the guest.
+    pub fn ecall_trace(mut self, trace: EcallTrace) -> Self {
+        self.ecalls = Some(trace);
+        self
+    }
+
+    /// Harts sharing the bus, all starting at the entry point with their
+    /// `mhartid` in a0. Defaults to 1.
+    pub fn harts(mut self, harts: u64) -> Self {
+        self.harts = harts;
+        self
+    }
+
+    /// Instructions each hart runs before the next one gets its turn,
+    /// defaults to [`HART_QUANTUM`].
+    pub fn quantum(mut self, instructions: u64) -> Self {
+        self.quantum = instructions;
+        self
+    }
+
+    /// Records the turns the harts take, see [`Machine::schedule`].
+    pub fn record_schedule(mut self, enabled: bool) -> Self {
+        self.record_schedule = enabled;
+        self
+    }
+
+    /// Runs the harts in the turns of a recorded schedule, then round-robin
+    /// once it is over.
+    pub fn replay_schedule(mut self, turns: Vec<Turn>) -> Self {
+        self.replay_schedule = Some(turns);
+        self
+    }
+
+    /// Fills the registers but sp and a0, and the dram outside of the image,
+    /// with values drawn from `seed` instead of zeros, to find guest code
+    /// relying on the reset state. The same seed gives the same state.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Holds the interrupts raised by devices, timers and other harts back
+    /// for up to `instructions`, a random delay drawn from the
+    /// [`seed`](Self::seed), or from 0 without one, each time.
+    pub fn irq_jitter(mut self, instructions: u64) -> Self {
+        self.irq_jitter = instructions;
+        self
+    }
+
+    /// Paces the guest to `hz` instructions per second of host time instead
+    /// of running as fast as possible.
+    pub fn frequency(mut self, hz: u64) -> Self {
+        self.frequency = Some(hz);
+        self
+    }
+
+    /// Advances time, the `time` csr and `mtime`, once per retired
+    /// instruction instead of with the host clock, so runs repeat exactly.
+    pub fn deterministic(mut self, enabled: bool) -> Self {
+        self.deterministic = enabled;
+        self
+    }
+
+    /// Attaches the disk image at `path` as a virtio-blk device, after the
+    /// other virtio devices.
+    pub fn drive(mut self, path: impl Into<PathBuf>) -> Self {
+        self.drives.push(path.into());
+        self
+    }
+
+    /// Connects the UART to the host terminal: it receives the keystrokes of
+    /// stdin, in raw mode while the machine lives, and transmits to stdout.
+    pub fn uart_stdio(mut self) -> Self {
+        self.uart_stdio = true;
+        self
+    }
+
+    /// Loads device models from the shared library at `path`, passing it `args`.
+    #[cfg(unix)]
+    pub fn plugin(mut self, path: impl Into<PathBuf>, args: &str) -> Self {
+        self.plugins.push(PluginSpec {
+            path: path.into(),
+            args: args.to_string(),
+        });
+        self
+    }
+
+    /// Rhai script attaching callbacks to breakpoints, MMIO ranges and traps,
+    /// see [`crate::script`].
+    #[cfg(feature = "script")]
+    pub fn script(mut self, path: impl Into<PathBuf>) -> Self {
+        self.script = Some(path.into());
+        self
+    }
+
+    pub fn build(self) -> Result<Machine, EmulatorError> {
+        if !(1..=MAX_HARTS).contains(&self.harts) {
+            return Err(EmulatorError::InvalidHarts(self.harts));
+        }
+        if self.quantum == 0 {
+            return Err(EmulatorError::InvalidSchedule(String::from(
+                "the quantum can't be zero",
+            )));
+        }
+
+        let isa = match &self.isa {
+            Some(isa) => isa.parse()?,
+            None => Isa::default(),
+        };
+
+        let mut rng = self.seed.map(Rng::new);
+        let mut machine = match &self.elf {
+            Some(data) => Self::load_elf(data, self.memory, isa, rng.as_mut())?,
+            None => {
+                if self.image.len() as u64 > self.memory {
+                    return Err(EmulatorError::ImageTooLarge {
+                        image: self.image.len() as u64,
+                        memory: self.memory,
+                    });
+                }
+
+                let loaded = self.image.len();
+                let mut dram = Dram::with_size(self.image, self.memory);
+                if let Some(rng) = &mut rng {
+                    rng.fill(&mut dram.dram[loaded..]);
+                }
+                let bus = Bus::new(dram);
+                Machine::new(Cpu::with_bus(bus, isa))
+            }
+        };
+        machine.throttle = self.frequency.filter(|x| *x > 0).map(Throttle::new);
+        machine.quantum = self.quantum;
+        machine.schedule = self.record_schedule.then(Vec::new);
+        machine.replay = self.replay_schedule.clone().map(VecDeque::from);
+        machine.semihosting = self.semihosting.then(Semihosting::default);
+        machine.ebreak = self.ebreak;
+        machine.wfi = self.wfi;
+        machine.cpu.misaligned = self.misaligned;
+        if self.deterministic {
+            machine.cpu.clock = Box::new(InstretClock);
+        }
+        machine.cpu.ad_policy = self.ad_policy;
+        machine.cpu.icache = self.icache.map(Cache::new).transpose()?;
+        machine.cpu.dcache = self.dcache.map(Cache::new).transpose()?;
+        machine.cpu.pipeline = self.pipeline.map(Pipeline::new);
+        if let Some(vlen) = self.vlen {
+            machine.cpu.vector = Vector::new(vlen)?;
+        }
+        let sbi = self.firmware == Firmware::Builtin;
+        if sbi {
+            // As OpenSBI does, the counters are left to the supervisor.
+            machine.cpu.csrs[MCOUNTEREN] = 0xffff_ffff;
+        }
+        machine.harts = (1..self.harts)
+            .map(|id| Hart {
+                stopped: sbi,
+                ..machine.cpu.clone_hart(id)
+            })
+            .collect();
+        if let Some(rng) = &mut rng {
+            rng.registers(&mut machine.cpu.regs);
+            for hart in &mut machine.harts {
+                rng.registers(&mut hart.regs);
+            }
+        }
+        if self.irq_jitter > 0 {
+            let rng = Rng::new(rng.as_mut().map_or(0, Rng::next_u64));
+            machine.jitter = Some(Jitter::new(rng, self.irq_jitter));
+        }
+        if let Some(trace) = &self.ecalls {
+            trace.attach(&mut machine.cpu.hooks);
+        }
+        machine.ecalls = self.ecalls.clone();
+        let finisher = machine.finisher.clone();
+        machine.cpu.bus.map(FINISHER_BASE, FINISHER_SIZE, finisher);
+        machine.ipi = SoftwareInterrupts::new(self.harts);
+        let (mswi, sswi) = (machine.ipi.mswi(), machine.ipi.sswi());
+        machine.cpu.bus.map(MSWI_BASE, MSWI_SIZE, mswi);
+        machine.cpu.bus.map(SSWI_BASE, SSWI_SIZE, sswi);
+        machine.mtimer = Mtimer::new(self.harts);
+        let mtimer = machine.mtimer.clone();
+        machine.cpu.bus.map(MTIMER_BASE, MTIMER_SIZE, mtimer);
+        machine.plic = Plic::new(self.harts);
+        machine.cpu.bus.attach(machine.plic.clone()).unwrap();
+        machine.cpu.bus.attach(machine.uart.clone()).unwrap();
+        if sbi {
+            machine.sbi = Some(Sbi::new(machine.ipi.clone(), self.harts));
+        }
+        if let Some((hub, mac)) = &self.nic {
+            let nic = Nic::new(hub.port(), *mac);
+            machine.cpu.bus.map(NIC_BASE, NIC_SIZE, nic);
+        }
+        if let Some((width, height)) = self.framebuffer {
+            let framebuffer = Framebuffer::new(width, height);
+            machine.cpu.bus.attach(framebuffer.clone()).unwrap();
+            machine.framebuffer = Some(framebuffer);
+        }
+        if let Some(action) = self.watchdog {
+            let watchdog = Watchdog::new(action);
+            machine.cpu.bus.attach(watchdog.clone()).unwrap();
+            machine.watchdog = Some(watchdog);
+        }
+        if self.gpio {
+            let gpio = Gpio::default();
+            machine.cpu.bus.attach(gpio.clone()).unwrap();
+            machine.gpio = Some(gpio);
+        }
+        if let Some(path) = &self.sd_card {
+            let mut spi = Spi::default();
+            spi.attach(0, SdCard::open(path)?);
+            machine.cpu.bus.attach(spi).unwrap();
+        }
+        if let Some(path) = &self.pflash {
+            machine.cpu.bus.attach(Pflash::open(path)?).unwrap();
+        }
+        if self.i2c {
+            let mut i2c = I2c::default();
+            let sensor = Lm75::default();
+            i2c.attach(EEPROM_ADDRESS, Eeprom::new(256, 8));
+            i2c.attach(LM75_ADDRESS, sensor.clone());
+            machine.cpu.bus.attach(i2c).unwrap();
+            machine.temperature_sensor = Some(sensor);
+        }
+        if let Some((hub, mac)) = &self.virtio_net {
+            machine.add_virtio(VirtioNet::new(hub.port(), *mac))?;
+        }
+        if let Some(seed) = self.virtio_rng {
+            machine.add_virtio(VirtioRng::new(seed))?;
+        }
+        if self.virtio_input {
+            let (input, keyboard, tablet) = virtio::input::devices();
+            machine.add_virtio(keyboard)?;
+            machine.add_virtio(tablet)?;
+            machine.input = Some(input);
+        }
+        #[cfg(unix)]
+        for (tag, dir) in &self.shares {
+            machine.add_virtio(Virtio9p::new(tag, dir))?;
+        }
+        for path in &self.drives {
+            machine.add_virtio(VirtioBlk::open(path)?)?;
+        }
+        if self.uart_stdio {
+            machine.uart.set_output(io::stdout());
+            machine.console = Some(Console::stdin()?);
+        }
+        #[cfg(unix)]
+        for spec in &self.plugins {
+            plugin::load(&mut machine.cpu.bus, spec)?;
+        }
+        #[cfg(feature = "script")]
+        if let Some(path) = &self.script {
+            machine.script = Some(Script::from_file(path, &mut machine.cpu.bus)?);
+        }
+
+        Ok(machine)
+    }
+
+    fn load_elf(
+        data: &[u8],
+        memory: u64,
+        isa: Isa,
+        rng: Option<&mut Rng>,
+    ) -> Result<Machine, EmulatorError> {
+        let elf = Elf::parse(data)?;
+        if elf.xlen != isa.xlen {
+            return Err(EmulatorError::InvalidElf(format!(
+                "a {} bit object doesn't run on an rv{} isa",
+                elf.xlen.bits(),
+                isa.xlen.bits()
+            )));
+        }
+        let mut dram = Dram::with_size(Vec::new(), memory);
+        if let Some(rng) = rng {
+            rng.fill(&mut dram.dram);
+        }
+
+        for segment in &elf.segments {
+            let start = segment.vaddr.wrapping_sub(dram.base) as usize;
+            let memory = dram
+                .dram
+                .get_mut(start..start.saturating_add(segment.memsz as usize))
+                .ok_or_else(|| {
+                    EmulatorError::InvalidElf(format!(
+                        "segment at {:#x} is outside dram",
+                        segment.vaddr
+                    ))
+                })?;
+            let data = &elf.data[segment.offset as usize..][..segment.filesz as usize];
+            memory[..data.len()].copy_from_slice(data);
+            memory[data.len()..].fill(0);
+        }
+
+        let htif = elf
+            .symbol("tohost")
+            .map(|tohost| Htif::new(tohost, elf.symbol("fromhost")));
+        let mut cpu = Cpu::with_bus(Bus::new(dram), isa);
+        cpu.pc = elf.entry;
+
+        let mut machine = Machine::new(cpu);
+        machine.htif = htif;
+        Ok(machine)
+    }
+}
+
+/// Parses a memory size such as `128M`, `4KiB`, `1G` or a plain byte count.
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let unit = match suffix.to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => KiB,
+        "m" | "mb" | "mib" => MiB,
+        "g" | "gb" | "gib" => GiB,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
@@ -1,32 +1,868 @@
-use std::{env, fs::File, io::Read};
+use std::{
+    collections::hash_map::RandomState,
+    ffi::OsString,
+    fs::{self, File},
+    hash::{BuildHasher, Hasher},
+    io::{self, BufReader, BufWriter, IsTerminal},
+    net::TcpListener,
+    ops::Range,
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use rysk::cpu::Cpu;
+use clap::{Args, Parser, Subcommand};
+use rysk::{
+    bus::DRAM_BASE,
+    cache::CacheConfig,
+    cluster::Cluster,
+    compressed::is_compressed,
+    console::Console,
+    control::Control,
+    cosim,
+    cpu::{MisalignedPolicy, INSTRET, RDCYCLE},
+    debugger::{parse_number, Debugger},
+    disasm::disassemble,
+    ecall::{Abi, EcallTrace},
+    elf::Elf,
+    fault::{Campaign, Target},
+    gdb,
+    heatmap::Heatmap,
+    latency::InterruptLatency,
+    machine::{
+        parse_size, EbreakPolicy, ExitReason, Firmware, Machine, MachineBuilder, WfiPolicy,
+        HART_QUANTUM,
+    },
+    mmu::AdPolicy,
+    net::{default_mac, Hub},
+    profile::Profiler,
+    runner::{self, Report},
+    schedule,
+    snapshot::Snapshot,
+    stats::{Period, Stats},
+    throttle::parse_frequency,
+    timing::PipelineConfig,
+    trace::TraceStream,
+    virtio::console::VirtioConsole,
+    vnc,
+    watchdog::WatchdogAction,
+};
 use tracing::Level;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
-fn main() -> Result<(), std::io::Error> {
+/// Functions listed by `--profile`.
+const PROFILE_LINES: usize = 30;
+
+/// A RISC-V emulator.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Enable trace logging, including every bus access.
+    #[arg(long, global = true)]
+    trace: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run an image until it finishes or raises an exception.
+    Run {
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Wait for a gdb connection on this port instead of running freely.
+        #[arg(long, value_name = "PORT")]
+        gdb: Option<u16>,
+        /// Serve the HTTP control API on this address, e.g. 127.0.0.1:8000.
+        #[arg(long, value_name = "ADDR", conflicts_with = "gdb")]
+        control: Option<String>,
+        /// With --control, wait for a resume request before running.
+        #[arg(long, requires = "control")]
+        paused: bool,
+        /// Resume from a snapshot instead of the start of the image.
+        #[arg(long, value_name = "FILE")]
+        restore: Option<PathBuf>,
+    },
+    /// Run an image under the interactive debugger.
+    Debug {
+        #[command(flatten)]
+        machine: MachineArgs,
+    },
+    /// Disassemble a raw image.
+    Disasm {
+        image: PathBuf,
+        /// Address of the first instruction.
+        #[arg(long, default_value_t = DRAM_BASE, value_parser = parse_address)]
+        base: u64,
+    },
+    /// Run test images, which pass if they exit through htif with status zero
+    /// or stop with a0 set to zero.
+    Test {
+        images: Vec<PathBuf>,
+        #[command(flatten)]
+        options: MachineOptions,
+        /// Fail tests that don't stop within this many instructions.
+        #[arg(long, default_value_t = 10_000_000)]
+        max_instructions: u64,
+    },
+    /// Run several images at once, networked through a virtual Ethernet hub.
+    Cluster {
+        images: Vec<PathBuf>,
+        #[command(flatten)]
+        options: MachineOptions,
+    },
+    /// Run a Linux RISC-V program, servicing its system calls on the host.
+    #[cfg(target_os = "linux")]
+    User {
+        program: PathBuf,
+        /// Size of the guest address space.
+        #[arg(long, value_parser = parse_memory)]
+        memory: Option<u64>,
+        #[arg(long)]
+        isa: Option<String>,
+        /// Prefix for absolute paths, where the dynamic linker and libraries are found.
+        #[arg(short = 'L', long)]
+        sysroot: Option<PathBuf>,
+        /// Set a guest environment variable, `KEY=VALUE`, may be repeated.
+        #[arg(short = 'E', long = "env", value_parser = parse_env)]
+        env: Vec<(String, String)>,
+        /// Print a histogram of the system calls made at exit.
+        #[arg(long)]
+        ecall_summary: bool,
+        /// Log every system call with its arguments to stderr, like strace.
+        #[arg(long)]
+        ecall_log: bool,
+        /// Arguments passed to the program after `--`.
+        #[arg(last = true)]
+        args: Vec<OsString>,
+    },
+    /// Inject random bit flips, one per run of the image, and report how each
+    /// run ended compared to a run without faults.
+    Fault {
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Registers or dram to flip bits in, e.g. x1-x31, a0 or
+        /// 0x80001000-0x80002000.
+        #[arg(long, default_value = "x1-x31")]
+        target: Target,
+        /// Faults to inject, a run each.
+        #[arg(long, default_value_t = 100)]
+        runs: usize,
+        /// Instruction counts to inject at, `START-END`, defaults to the whole run.
+        #[arg(long, value_parser = parse_window)]
+        window: Option<Range<u64>>,
+        /// Seed drawing the faults, the same seed injects the same faults.
+        #[arg(long, default_value_t = 0)]
+        fault_seed: u64,
+        /// Fail if the run without faults doesn't stop within this many instructions.
+        #[arg(long, default_value_t = 10_000_000)]
+        max_instructions: u64,
+    },
+    /// Run an image in lockstep with a hardware simulator, which sends the
+    /// instructions it retires over a socket for rysk to check.
+    Cosim {
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Address to wait for the testbench on.
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:9100")]
+        listen: String,
+    },
+    /// Run an image for a number of instructions and save a snapshot.
+    Snapshot {
+        #[command(flatten)]
+        machine: MachineArgs,
+        /// Instructions to execute before taking the snapshot.
+        #[arg(long)]
+        after: u64,
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+#[derive(Debug, Args)]
+struct MachineArgs {
+    /// ELF executable, or raw binary loaded at the start of dram.
+    image: PathBuf,
+    #[command(flatten)]
+    options: MachineOptions,
+}
+
+impl MachineArgs {
+    fn build(&self) -> Result<Machine, Box<dyn std::error::Error>> {
+        self.options.build(fs::read(&self.image)?)
+    }
+}
+
+#[derive(Debug, Args)]
+struct MachineOptions {
+    /// Size of the dram, e.g. 128M or 1G.
+    #[arg(long, value_parser = parse_memory)]
+    memory: Option<u64>,
+    /// ISA string, e.g. rv64ima_zicsr or rv32imc.
+    #[arg(long)]
+    isa: Option<String>,
+    /// Randomize the registers and the dram outside the image from this seed,
+    /// instead of starting from zeros.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Delay the interrupts of devices, timers and other harts by a random
+    /// number of instructions up to this, drawn from --seed.
+    #[arg(long, value_name = "INSTRUCTIONS", default_value_t = 0)]
+    irq_jitter: u64,
+    /// Pace the guest to a clock frequency, e.g. 50mhz, retiring an
+    /// instruction per cycle, instead of running as fast as possible.
+    #[arg(long, value_parser = parse_freq)]
+    freq: Option<u64>,
+    /// Advance time once per retired instruction instead of with the host
+    /// clock, so runs repeat exactly.
+    #[arg(long)]
+    deterministic: bool,
+    /// Number of harts, which all start at the entry point.
+    #[arg(long, value_name = "HARTS", default_value_t = 1)]
+    smp: u64,
+    /// Instructions each hart runs before the next one gets its turn.
+    #[arg(long, value_name = "INSTRUCTIONS", default_value_t = HART_QUANTUM)]
+    quantum: u64,
+    /// Write the turns the harts took to this file at exit, for --replay-schedule.
+    #[arg(long, value_name = "FILE")]
+    record_schedule: Option<PathBuf>,
+    /// Run the harts in the turns recorded by --record-schedule.
+    #[arg(long, value_name = "FILE")]
+    replay_schedule: Option<PathBuf>,
+    /// Disk image to attach as a virtio-blk device, may be repeated.
+    #[arg(long)]
+    drive: Vec<PathBuf>,
+    /// Service semihosting calls.
+    #[arg(long)]
+    semihosting: bool,
+    /// What an ebreak outside of a semihosting call does: stop, or exit with a0.
+    #[arg(long, default_value = "stop")]
+    ebreak: EbreakPolicy,
+    /// What the host does while the harts wait in a wfi: nop, yield, or
+    /// sleep until an interrupt, at most 10ms or sleep=MS.
+    #[arg(long, default_value = "sleep")]
+    wfi: WfiPolicy,
+    /// What misaligned loads and stores do: emulate them a byte at a time, or
+    /// trap with an address misaligned exception.
+    #[arg(long, default_value = "emulate")]
+    misaligned: MisalignedPolicy,
+    /// What accesses to pages without the accessed or dirty bit they need
+    /// do: fault, for the kernel to set it, or update the page table entry.
+    #[arg(long, default_value = "fault")]
+    ad_bits: AdPolicy,
+    /// Firmware servicing ecalls: none, or builtin for an SBI implementation
+    /// so S-mode kernels boot without OpenSBI.
+    #[arg(long, default_value = "none")]
+    firmware: Firmware,
+    /// Model an instruction cache, `SIZE,WAYS,LINE[,MISS_PENALTY]`, e.g. 32K,8,64,10.
+    #[arg(long, value_name = "CONFIG")]
+    icache: Option<CacheConfig>,
+    /// Model a data cache, `SIZE,WAYS,LINE[,MISS_PENALTY]`.
+    #[arg(long, value_name = "CONFIG")]
+    dcache: Option<CacheConfig>,
+    /// Count cycles as a 5-stage in-order pipeline, optionally overriding its
+    /// latencies, e.g. --pipeline=branch=3,load-use=1,mul=4,div=20.
+    #[arg(long, value_name = "LATENCIES", num_args = 0..=1, require_equals = true, default_missing_value = "")]
+    pipeline: Option<PipelineConfig>,
+    /// Width of the vector registers in bits, a power of two from 64 to 65536.
+    #[arg(long, value_name = "BITS")]
+    vlen: Option<u64>,
+    /// Print a flat profile of the retired instructions and cycles by
+    /// function at exit, from the symbols of the ELF.
+    #[arg(long)]
+    profile: bool,
+    /// Print a summary of the run at exit, with the memory traffic by
+    /// access width and bus region.
+    #[arg(long)]
+    stats: bool,
+    /// Write an SVG heatmap of the executed code to this file at exit.
+    #[arg(long, value_name = "FILE")]
+    heatmap: Option<PathBuf>,
+    /// Print a progress line every so often, e.g. 10s or 500M instructions.
+    #[arg(long, value_name = "PERIOD")]
+    stats_interval: Option<Period>,
+    /// Write the progress lines to a file instead of stderr.
+    #[arg(long, value_name = "FILE", requires = "stats_interval")]
+    stats_file: Option<PathBuf>,
+    /// Print the latency of each interrupt, from a device raising it to the
+    /// guest taking it, at exit.
+    #[arg(long)]
+    irq_latency: bool,
+    /// Print a histogram of the SBI calls made by the guest at exit.
+    #[arg(long)]
+    ecall_summary: bool,
+    /// Log every SBI call with its arguments to stderr.
+    #[arg(long)]
+    ecall_log: bool,
+    /// Stream a CBOR trace of instructions, traps, MMIO and interrupts to an
+    /// analyzer listening on this address.
+    #[arg(long, value_name = "ADDR")]
+    trace_stream: Option<String>,
+    /// Add a network card linked to another rysk process, `LOCAL,PEER` UDP addresses.
+    #[arg(long, value_name = "LOCAL,PEER", value_parser = parse_link)]
+    net_udp: Option<(String, String)>,
+    /// Add a virtio-net device on `user` networking, with NAT through the
+    /// host, or on the host TAP interface `tap=NAME`.
+    #[arg(long, value_name = "BACKEND", value_parser = parse_netdev)]
+    virtio_net: Option<NetBackend>,
+    /// Add a virtio-console on `stdio`, reading the same keystrokes as the
+    /// UART, or for the clients of a TCP socket listening on `tcp=ADDR`.
+    #[arg(long, value_name = "BACKEND", value_parser = parse_console)]
+    virtio_console: Option<ConsoleBackend>,
+    /// Add a virtio-rng device, drawing from host entropy or, to make the
+    /// run reproducible, from `--virtio-rng=SEED`.
+    #[arg(long, value_name = "SEED", num_args = 0..=1, require_equals = true)]
+    virtio_rng: Option<Option<u64>>,
+    /// Map a framebuffer of `WIDTHxHEIGHT` pixels.
+    #[arg(long, value_name = "WIDTHxHEIGHT", value_parser = parse_resolution)]
+    framebuffer: Option<(u32, u32)>,
+    /// Show the framebuffer to VNC viewers connecting to this address, e.g.
+    /// 127.0.0.1:5900.
+    #[arg(long, value_name = "ADDR", requires = "framebuffer")]
+    vnc: Option<String>,
+    /// Add a virtio-input keyboard and tablet, fed by the VNC viewers.
+    #[arg(long, requires = "vnc")]
+    virtio_input: bool,
+    /// Map a watchdog which, when the guest stops feeding it, ends the run as
+    /// a `reset` or a `halt`.
+    #[arg(long, value_name = "ACTION")]
+    watchdog: Option<WatchdogAction>,
+    /// Map a GPIO block, whose pins --control serves at /gpio.
+    #[arg(long)]
+    gpio: bool,
+    /// Map an SPI master with an SD card holding this image, written in place.
+    #[arg(long, value_name = "FILE")]
+    sd_card: Option<PathBuf>,
+    /// Map a CFI flash at 0x20000000 holding this image, written back as the
+    /// guest programs and erases it.
+    #[arg(long, value_name = "FILE")]
+    pflash: Option<PathBuf>,
+    /// Map an I2C master with an EEPROM at 0x50 and an LM75 temperature
+    /// sensor at 0x48.
+    #[arg(long)]
+    i2c: bool,
+    /// Share a host directory through virtio-9p, `[TAG=]DIR` with the tag
+    /// the guest mounts it by, `rysk` by default. May be repeated.
+    #[cfg(unix)]
+    #[arg(long, value_name = "[TAG=]DIR", value_parser = parse_share)]
+    share: Vec<(String, PathBuf)>,
+    /// Load device models from a shared library, `PATH[,ARGS]`, may be repeated.
+    #[cfg(unix)]
+    #[arg(long, value_parser = parse_plugin)]
+    plugin: Vec<(PathBuf, String)>,
+    /// Load every shared library in a directory as a plugin.
+    #[cfg(unix)]
+    #[arg(long)]
+    plugin_dir: Option<PathBuf>,
+    /// Rhai script hooking breakpoints, MMIO ranges and traps.
+    #[cfg(feature = "script")]
+    #[arg(long)]
+    script: Option<PathBuf>,
+}
+
+impl MachineOptions {
+    fn build(&self, image: Vec<u8>) -> Result<Machine, Box<dyn std::error::Error>> {
+        let profiler = match self.profile {
+            true => Some(Profiler::from_elf(&Elf::parse(&image)?)?),
+            false => None,
+        };
+        let heatmap = match (&self.heatmap, image.starts_with(b"\x7fELF")) {
+            (None, _) => None,
+            (Some(_), true) => Some(Heatmap::from_elf(&Elf::parse(&image)?)?),
+            (Some(_), false) => Some(Heatmap::new(DRAM_BASE..DRAM_BASE + image.len() as u64)),
+        };
+        let mut builder = self.builder(image)?;
+        if let Some((local, peer)) = &self.net_udp {
+            let hub = Hub::default();
+            hub.link_udp(local.as_str(), peer.as_str())?;
+            builder = builder.nic(&hub, default_mac(0));
+        }
+        if let Some(backend) = &self.virtio_net {
+            let hub = Hub::default();
+            match backend {
+                NetBackend::User => hub.link_user(),
+                #[cfg(target_os = "linux")]
+                NetBackend::Tap(name) => hub.link_tap(name)?,
+            }
+            builder = builder.virtio_net(&hub, default_mac(0));
+        }
+        if let Some((width, height)) = self.framebuffer {
+            builder = builder.framebuffer(width, height);
+        }
+        builder = builder.virtio_input(self.virtio_input);
+        #[cfg(unix)]
+        for (tag, dir) in &self.share {
+            builder = builder.share(tag, dir);
+        }
+        if let Some(seed) = self.virtio_rng {
+            let seed = seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+            builder = builder.virtio_rng(seed);
+        }
+        let mut machine = builder.build()?;
+        match &self.virtio_console {
+            Some(ConsoleBackend::Stdio) => machine.add_virtio(VirtioConsole::stdio())?,
+            Some(ConsoleBackend::Tcp(addr)) => {
+                machine.add_virtio(VirtioConsole::listen(TcpListener::bind(addr)?))?
+            }
+            None => {}
+        }
+        if let (Some(addr), Some(framebuffer)) = (&self.vnc, &machine.framebuffer) {
+            vnc::serve(
+                TcpListener::bind(addr)?,
+                framebuffer.clone(),
+                machine.input.clone(),
+            );
+        }
+        if let Some(profiler) = profiler {
+            machine.cpu.observers.add(profiler);
+        }
+        if let Some(heatmap) = heatmap {
+            machine.cpu.observers.add(heatmap);
+        }
+        self.attach(&mut machine)?;
+        Ok(machine)
+    }
+
+    /// Everything but the network card and the host connections.
+    fn builder(&self, image: Vec<u8>) -> Result<MachineBuilder, Box<dyn std::error::Error>> {
+        let mut builder = if image.starts_with(b"\x7fELF") {
+            Machine::builder().elf(image)
+        } else {
+            Machine::builder().image(image)
+        };
+        if let Some(memory) = self.memory {
+            builder = builder.memory(memory);
+        }
+        if let Some(isa) = &self.isa {
+            builder = builder.isa(isa);
+        }
+        builder = builder
+            .harts(self.smp)
+            .quantum(self.quantum)
+            .record_schedule(self.record_schedule.is_some())
+            .irq_jitter(self.irq_jitter)
+            .deterministic(self.deterministic);
+        if let Some(path) = &self.replay_schedule {
+            builder = builder.replay_schedule(schedule::read(BufReader::new(File::open(path)?))?);
+        }
+        if let Some(hz) = self.freq {
+            builder = builder.frequency(hz);
+        }
+        if let Some(seed) = self.seed {
+            builder = builder.seed(seed);
+        }
+        for drive in &self.drive {
+            builder = builder.drive(drive);
+        }
+        if let Some(config) = self.icache {
+            builder = builder.icache(config);
+        }
+        if let Some(config) = self.dcache {
+            builder = builder.dcache(config);
+        }
+        if let Some(config) = self.pipeline {
+            builder = builder.pipeline(config);
+        }
+        if let Some(vlen) = self.vlen {
+            builder = builder.vlen(vlen);
+        }
+        if let Some(trace) = ecall_trace(Abi::Sbi, self.ecall_summary, self.ecall_log) {
+            builder = builder.ecall_trace(trace);
+        }
+        #[cfg(unix)]
+        {
+            if let Some(dir) = &self.plugin_dir {
+                for path in rysk::plugin::discover(dir)? {
+                    builder = builder.plugin(path, "");
+                }
+            }
+            for (path, args) in &self.plugin {
+                builder = builder.plugin(path, args);
+            }
+        }
+        #[cfg(feature = "script")]
+        if let Some(path) = &self.script {
+            builder = builder.script(path);
+        }
+        if let Some(action) = self.watchdog {
+            builder = builder.watchdog(action);
+        }
+        if let Some(path) = &self.sd_card {
+            builder = builder.sd_card(path);
+        }
+        if let Some(path) = &self.pflash {
+            builder = builder.pflash(path);
+        }
+        Ok(builder
+            .gpio(self.gpio)
+            .i2c(self.i2c)
+            .semihosting(self.semihosting)
+            .ebreak(self.ebreak)
+            .wfi(self.wfi)
+            .misaligned(self.misaligned)
+            .ad_policy(self.ad_bits)
+            .firmware(self.firmware))
+    }
+
+    fn attach(&self, machine: &mut Machine) -> io::Result<()> {
+        let stats = match (self.stats_interval, &self.stats_file) {
+            (Some(period), Some(path)) => Some(Stats::periodic(period, File::create(path)?)),
+            (Some(period), None) => Some(Stats::periodic(period, io::stderr())),
+            (None, _) => self.stats.then(Stats::new),
+        };
+        if let Some(stats) = stats {
+            machine.cpu.observers.add(stats.regions(&machine.cpu.bus));
+        }
+        if self.irq_latency {
+            machine.cpu.observers.add(InterruptLatency::new());
+        }
+        if let Some(addr) = &self.trace_stream {
+            machine.cpu.observers.add(TraceStream::connect(addr)?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got {s}"))
+}
+
+fn parse_link(s: &str) -> Result<(String, String), String> {
+    s.split_once(',')
+        .map(|(local, peer)| (local.to_string(), peer.to_string()))
+        .ok_or_else(|| format!("expected LOCAL,PEER, got {s}"))
+}
+
+#[derive(Debug, Clone)]
+enum NetBackend {
+    User,
+    #[cfg(target_os = "linux")]
+    Tap(String),
+}
+
+fn parse_netdev(s: &str) -> Result<NetBackend, String> {
+    match s.split_once('=') {
+        None if s == "user" => Ok(NetBackend::User),
+        #[cfg(target_os = "linux")]
+        Some(("tap", name)) => Ok(NetBackend::Tap(name.to_string())),
+        _ => Err(format!("expected user or tap=NAME, got {s}")),
+    }
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let parse = |x: &str| x.parse().ok().filter(|x| (1..=4096).contains(x));
+    match s.split_once('x') {
+        Some((width, height)) => parse(width).zip(parse(height)),
+        None => None,
+    }
+    .ok_or_else(|| format!("expected WIDTHxHEIGHT up to 4096x4096, got {s}"))
+}
+
+#[derive(Debug, Clone)]
+enum ConsoleBackend {
+    Stdio,
+    Tcp(String),
+}
+
+fn parse_console(s: &str) -> Result<ConsoleBackend, String> {
+    match s.split_once('=') {
+        None if s == "stdio" => Ok(ConsoleBackend::Stdio),
+        Some(("tcp", addr)) => Ok(ConsoleBackend::Tcp(addr.to_string())),
+        _ => Err(format!("expected stdio or tcp=ADDR, got {s}")),
+    }
+}
+
+#[cfg(unix)]
+fn parse_share(s: &str) -> Result<(String, PathBuf), String> {
+    let (tag, dir) = s.split_once('=').unwrap_or(("rysk", s));
+    match fs::metadata(dir) {
+        Ok(meta) if meta.is_dir() => Ok((tag.to_string(), PathBuf::from(dir))),
+        _ => Err(format!("{dir} isn't a directory")),
+    }
+}
+
+#[cfg(unix)]
+fn parse_plugin(s: &str) -> Result<(PathBuf, String), String> {
+    let (path, args) = s.split_once(',').unwrap_or((s, ""));
+    Ok((PathBuf::from(path), args.to_string()))
+}
+
+fn ecall_trace(abi: Abi, summary: bool, log: bool) -> Option<EcallTrace> {
+    match (summary, log) {
+        (_, true) => Some(EcallTrace::new(abi).log(io::stderr())),
+        (true, false) => Some(EcallTrace::new(abi)),
+        (false, false) => None,
+    }
+}
+
+/// Prints the statistics of the modeled caches and pipeline, and of the TLB
+/// if anything was translated.
+fn report_timing(machine: &Machine) {
+    let cpu = &machine.cpu;
+    for (name, cache) in [("icache", &cpu.icache), ("dcache", &cpu.dcache)] {
+        if let Some(cache) = cache {
+            eprintln!("rysk: {name}: {cache}");
+        }
+    }
+    if cpu.tlb.accesses() != 0 {
+        eprintln!("rysk: tlb: {}", cpu.tlb);
+    }
+    if let Some(pipeline) = &cpu.pipeline {
+        let (cycles, instructions) = (cpu.csrs[RDCYCLE], cpu.csrs[INSTRET]);
+        eprintln!(
+            "rysk: pipeline: {cycles} cycles for {instructions} instructions ({:.2} CPI), {pipeline}",
+            cycles as f64 / instructions.max(1) as f64
+        );
+    }
+}
+
+fn parse_window(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s.split_once('-').unwrap_or((s, s));
+    match (parse_number(start), parse_number(end)) {
+        (Some(start), Some(end)) if start <= end => Ok(start..end + 1),
+        _ => Err(format!("invalid window '{s}', expected START-END")),
+    }
+}
+
+fn parse_freq(s: &str) -> Result<u64, String> {
+    parse_frequency(s).ok_or_else(|| format!("invalid frequency '{s}'"))
+}
+
+fn parse_memory(s: &str) -> Result<u64, String> {
+    parse_size(s).ok_or_else(|| format!("invalid memory size '{s}'"))
+}
+
+fn parse_address(s: &str) -> Result<u64, String> {
+    parse_number(s).ok_or_else(|| format!("invalid address '{s}'"))
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let level = if cli.trace { Level::TRACE } else { Level::INFO };
     tracing::subscriber::set_global_default(
         FmtSubscriber::builder()
             .with_env_filter(EnvFilter::from_default_env())
-            .with_max_level(Level::DEBUG)
+            .with_max_level(level)
             .pretty()
             .finish(),
     )
     .unwrap();
 
-    let args: Vec<String> = env::args().collect();
+    match cli.command {
+        Command::Run {
+            machine,
+            gdb,
+            control,
+            paused,
+            restore,
+        } => {
+            let heatmap_path = machine.options.heatmap.clone();
+            let schedule_path = machine.options.record_schedule.clone();
+            // --control attaches its own for /metrics.
+            let print_stats = machine.options.stats || machine.options.stats_interval.is_some();
+            let mut machine = machine.build()?;
+            if let Some(path) = restore {
+                Snapshot::read_from(BufReader::new(File::open(path)?))?
+                    .restore(&mut machine.cpu)?;
+            }
 
-    if args.len() != 2 {
-        panic!("Usage: rysk <filename>");
+            let reason = match gdb {
+                Some(port) => {
+                    gdb::serve(&mut machine.cpu, ("127.0.0.1", port))?;
+                    None
+                }
+                None => {
+                    if io::stdin().is_terminal() {
+                        eprintln!("rysk: press Ctrl-A x to quit");
+                    }
+                    machine.console = Some(Console::stdin()?);
+                    let reason = match control {
+                        Some(addr) => {
+                            let control = Control::bind(addr)?;
+                            let mut control = if paused { control.paused() } else { control };
+                            control.run(&mut machine)
+                        }
+                        None => machine.run(),
+                    };
+                    // Restores the terminal.
+                    machine.console = None;
+                    Some(reason)
+                }
+            };
+            report_timing(&machine);
+            if let Some(stats) = machine.cpu.observers.get::<Stats>().filter(|_| print_stats) {
+                eprintln!("rysk: {stats}");
+            }
+            if let Some(latency) = machine.cpu.observers.get::<InterruptLatency>() {
+                eprintln!("rysk: {latency}");
+            }
+            if let Some(ecalls) = &machine.ecalls {
+                eprintln!("rysk: {ecalls}");
+            }
+            if let (Some(heatmap), Some(path)) =
+                (machine.cpu.observers.get::<Heatmap>(), &heatmap_path)
+            {
+                heatmap.write_svg(BufWriter::new(File::create(path)?))?;
+            }
+            if let Some(profiler) = machine.cpu.observers.get::<Profiler>() {
+                profiler.write_report(io::stderr().lock(), PROFILE_LINES)?;
+            }
+            if let (Some(turns), Some(path)) = (machine.schedule(), &schedule_path) {
+                schedule::write(&turns, BufWriter::new(File::create(path)?))?;
+            }
+            match reason {
+                Some(ExitReason::Shutdown(code)) => return Ok(ExitCode::from(code as u8)),
+                Some(ExitReason::HostRequest) => return Ok(ExitCode::SUCCESS),
+                Some(ExitReason::Reboot) => {
+                    eprintln!("rysk: the guest asked for a reboot, stopping");
+                    return Ok(ExitCode::SUCCESS);
+                }
+                Some(ExitReason::Exception(exception)) => {
+                    eprintln!("rysk: stopped by {exception} at pc {:#x}", machine.cpu.pc)
+                }
+                _ => {}
+            }
+            machine.cpu.dump_registers();
+            machine.cpu.dump_csr();
+        }
+        Command::Debug { machine } => {
+            let mut machine = machine.build()?;
+            Debugger::default().repl(&mut machine.cpu, io::stdin().lock(), io::stdout())?;
+        }
+        Command::Disasm { image, base } => {
+            let image = fs::read(image)?;
+            let mut offset = 0;
+            while offset < image.len() {
+                let mut buf = [0; 4];
+                let word = &image[offset..image.len().min(offset + 4)];
+                buf[..word.len()].copy_from_slice(word);
+                let inst = u32::from_le_bytes(buf) as u64;
+                let addr = base + offset as u64;
+                let (inst, len) = match is_compressed(inst) {
+                    true => (inst & 0xffff, 2),
+                    false => (inst, 4),
+                };
+                let text = disassemble(inst, addr).unwrap_or_else(|| String::from("<unknown>"));
+                match len {
+                    2 => println!("{addr:#x}: {inst:04x}      {text}"),
+                    _ => println!("{addr:#x}: {inst:08x}  {text}"),
+                }
+                offset += len;
+            }
+        }
+        Command::Test {
+            images,
+            options,
+            max_instructions,
+        } => {
+            let mut report = Report::new(images.len());
+            for path in &images {
+                let mut machine = options.build(fs::read(path)?)?;
+                report.record(
+                    &path.display().to_string(),
+                    runner::run(&mut machine, max_instructions),
+                );
+            }
+            if !report.finish() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Cluster { images, options } => {
+            let mut cluster = Cluster::new();
+            if let Some((local, peer)) = &options.net_udp {
+                cluster.hub.link_udp(local.as_str(), peer.as_str())?;
+            }
+            for path in &images {
+                let index = cluster.add(options.builder(fs::read(path)?)?)?;
+                options.attach(&mut cluster.machines[index])?;
+            }
+            let mut status = ExitCode::SUCCESS;
+            for (path, reason) in images.iter().zip(cluster.run()) {
+                println!("{}: {reason:?}", path.display());
+                if reason != ExitReason::Shutdown(0) {
+                    status = ExitCode::FAILURE;
+                }
+            }
+            return Ok(status);
+        }
+        #[cfg(target_os = "linux")]
+        Command::User {
+            program,
+            memory,
+            isa,
+            sysroot,
+            env,
+            ecall_summary,
+            ecall_log,
+            args,
+        } => {
+            let mut builder = rysk::user::UserMode::builder(program).args(args).host_env();
+            for (key, value) in env {
+                builder = builder.env(key, value);
+            }
+            if let Some(memory) = memory {
+                builder = builder.memory(memory);
+            }
+            if let Some(isa) = isa {
+                builder = builder.isa(&isa);
+            }
+            if let Some(sysroot) = sysroot {
+                builder = builder.sysroot(sysroot);
+            }
+            let mut user = builder.build()?;
+            let ecalls = ecall_trace(Abi::Linux, ecall_summary, ecall_log);
+            if let Some(trace) = &ecalls {
+                trace.attach(&mut user.cpu.hooks);
+            }
+            let status = user.run()?;
+            if let Some(trace) = ecalls.filter(|_| ecall_summary) {
+                eprintln!("rysk: {trace}");
+            }
+            return Ok(ExitCode::from(status as u8));
+        }
+        Command::Fault {
+            machine,
+            target,
+            runs,
+            window,
+            fault_seed,
+            max_instructions,
+        } => {
+            let campaign = Campaign {
+                target,
+                window,
+                runs,
+                seed: fault_seed,
+                max_instructions,
+            };
+            let report = campaign.run(&machine.options.builder(fs::read(&machine.image)?)?)?;
+            for (fault, outcome) in &report.runs {
+                println!("{fault}: {outcome}");
+            }
+            println!("{report}");
+        }
+        Command::Cosim { machine, listen } => {
+            let mut machine = machine.build()?;
+            cosim::serve(&mut machine, listen)?;
+        }
+        Command::Snapshot {
+            machine,
+            after,
+            output,
+        } => {
+            let mut machine = machine.build()?;
+            for _ in 0..after {
+                if let Err(e) = machine.cpu.step() {
+                    return Err(format!("exception before the snapshot: {e}").into());
+                }
+            }
+            Snapshot::capture(&machine.cpu).write_to(BufWriter::new(File::create(output)?))?;
+        }
     }
-    let mut file = File::open(&args[1])?;
-    let mut code = Vec::new();
-    file.read_to_end(&mut code)?;
-
-    let mut cpu = Cpu::new(code);
-    cpu.run()?;
-    cpu.dump_registers();
-    cpu.dump_csr();
 
-    Ok(())
+    Ok(ExitCode::SUCCESS)
 }
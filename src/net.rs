@@ -0,0 +1,326 @@
+//! Virtual Ethernet: a [`Hub`] repeats every frame sent by one of its ports
+//! to all the others. Ports are [`Nic`]s and virtio-net devices of machines
+//! in the same process, UDP links to the hub of another rysk process, a
+//! host TAP interface, or the user-mode network of [`user`].
+//!
+//! The [`Nic`] registers, 32 bits wide:
+//!
+//! - `0x00`, `0x04`: MAC address, bytes 0-3 then 4-5
+//! - `0x08`: length of the oldest received frame, 0 when there is none
+//! - `0x0c`: writing drops the oldest received frame
+//! - `0x10`: writing N sends the first N bytes of the transmit buffer
+//! - `0x1000`: the oldest received frame, read-only
+//! - `0x2000`: the transmit buffer
+//!
+//! There is no interrupt, the guest polls the receive length.
+
+pub mod user;
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::{ToSocketAddrs, UdpSocket},
+    sync::{mpsc, Arc, Mutex},
+    thread,
+};
+
+use tracing::{debug, warn};
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the builder maps a [`Nic`].
+pub const NIC_BASE: u64 = 0x1004_0000;
+pub const NIC_SIZE: u64 = 0x3000;
+
+/// Largest Ethernet frame without the FCS.
+pub const MAX_FRAME: usize = 1514;
+/// Frames queued for a port before new ones are dropped.
+const QUEUE: usize = 256;
+
+const MAC_LO: u64 = 0x00;
+const MAC_HI: u64 = 0x04;
+const RX_LEN: u64 = 0x08;
+const RX_POP: u64 = 0x0c;
+const TX_LEN: u64 = 0x10;
+const RX_BUF: u64 = 0x1000;
+const TX_BUF: u64 = 0x2000;
+
+/// Locally administered MAC address of the `n`-th machine, as QEMU numbers them.
+pub fn default_mac(n: u8) -> [u8; 6] {
+    [0x52, 0x54, 0x00, 0x12, 0x34, 0x56 + n]
+}
+
+enum Endpoint {
+    Queue(VecDeque<Vec<u8>>),
+    Udp(UdpSocket),
+    /// Handled on the host by a thread, see [`Hub::link_host`].
+    Host(mpsc::Sender<Vec<u8>>),
+}
+
+/// Clones share the same hub.
+#[derive(Clone, Default)]
+pub struct Hub {
+    ports: Arc<Mutex<Vec<Endpoint>>>,
+}
+
+impl std::fmt::Debug for Hub {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hub")
+            .field("ports", &self.ports.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl Hub {
+    /// Adds a port queueing the frames sent by the other ports.
+    pub fn port(&self) -> Port {
+        let mut ports = self.ports.lock().unwrap();
+        ports.push(Endpoint::Queue(VecDeque::new()));
+        Port {
+            hub: self.clone(),
+            id: ports.len() - 1,
+        }
+    }
+
+    /// Links this hub to the hub of another process: frames are exchanged
+    /// as UDP datagrams between `local` and `peer`.
+    pub fn link_udp(&self, local: impl ToSocketAddrs, peer: impl ToSocketAddrs) -> io::Result<()> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(peer)?;
+        self.link(socket)
+    }
+
+    /// Like [`link_udp`](Self::link_udp) with a socket already connected to the peer.
+    pub fn link(&self, socket: UdpSocket) -> io::Result<()> {
+        let receiver = socket.try_clone()?;
+
+        let port = {
+            let mut ports = self.ports.lock().unwrap();
+            ports.push(Endpoint::Udp(socket));
+            Port {
+                hub: self.clone(),
+                id: ports.len() - 1,
+            }
+        };
+        thread::spawn(move || {
+            let mut buf = [0; MAX_FRAME];
+            loop {
+                match receiver.recv(&mut buf) {
+                    Ok(n) => port.send(&buf[..n]),
+                    // A peer which isn't up yet refuses the datagrams.
+                    Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {}
+                    Err(e) => {
+                        warn!("network link stopped: {e}");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Adds a port whose frames `handle` takes on a thread of its own, with
+    /// the port to answer from, and returns the port.
+    pub fn link_host(
+        &self,
+        mut handle: impl FnMut(&Arc<Port>, Vec<u8>) + Send + 'static,
+    ) -> Arc<Port> {
+        let (sender, receiver) = mpsc::channel();
+        let port = {
+            let mut ports = self.ports.lock().unwrap();
+            ports.push(Endpoint::Host(sender));
+            Arc::new(Port {
+                hub: self.clone(),
+                id: ports.len() - 1,
+            })
+        };
+        let answer = port.clone();
+        thread::spawn(move || {
+            for frame in receiver {
+                handle(&answer, frame);
+            }
+        });
+        port
+    }
+
+    /// Links this hub to the host TAP interface `name`, which must exist
+    /// and be usable by the user, e.g. made with `ip tuntap add mode tap`.
+    #[cfg(target_os = "linux")]
+    pub fn link_tap(&self, name: &str) -> io::Result<()> {
+        use std::{
+            fs::OpenOptions,
+            io::{Read, Write},
+            os::fd::AsRawFd,
+        };
+
+        const TUNSETIFF: libc::c_ulong = 0x4004_54ca;
+        const IFF_TAP: libc::c_short = 0x0002;
+        const IFF_NO_PI: libc::c_short = 0x1000;
+
+        let tap = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")?;
+        if name.len() >= libc::IFNAMSIZ {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "tap name too long",
+            ));
+        }
+        // SAFETY: ifreq is plain data, the name is nul terminated as it is
+        // shorter than IFNAMSIZ.
+        unsafe {
+            let mut ifreq: libc::ifreq = std::mem::zeroed();
+            for (dst, src) in ifreq.ifr_name.iter_mut().zip(name.bytes()) {
+                *dst = src as libc::c_char;
+            }
+            ifreq.ifr_ifru.ifru_flags = IFF_TAP | IFF_NO_PI;
+            if libc::ioctl(tap.as_raw_fd(), TUNSETIFF as _, &ifreq) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut reader = tap.try_clone()?;
+        let mut writer = tap;
+        let port = self.link_host(move |_, frame| {
+            if let Err(e) = writer.write_all(&frame) {
+                debug!("dropped a frame: {e}");
+            }
+        });
+        thread::spawn(move || {
+            let mut buf = [0; MAX_FRAME];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(n) => port.send(&buf[..n]),
+                    Err(e) => {
+                        warn!("tap link stopped: {e}");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+/// A connection to a [`Hub`].
+#[derive(Debug)]
+pub struct Port {
+    hub: Hub,
+    id: usize,
+}
+
+impl Port {
+    /// Repeats `frame` to every other port of the hub.
+    pub fn send(&self, frame: &[u8]) {
+        if frame.len() > MAX_FRAME {
+            return;
+        }
+        let mut ports = self.hub.ports.lock().unwrap();
+        for (id, endpoint) in ports.iter_mut().enumerate() {
+            match endpoint {
+                _ if id == self.id => {}
+                Endpoint::Queue(queue) if queue.len() < QUEUE => queue.push_back(frame.to_vec()),
+                Endpoint::Queue(_) => debug!(port = id, "dropped a frame"),
+                Endpoint::Udp(socket) => {
+                    if let Err(e) = socket.send(frame) {
+                        debug!("dropped a frame: {e}");
+                    }
+                }
+                Endpoint::Host(sender) => {
+                    let _ = sender.send(frame.to_vec());
+                }
+            }
+        }
+    }
+
+    /// Oldest frame sent to this port.
+    pub fn recv(&self) -> Option<Vec<u8>> {
+        match &mut self.hub.ports.lock().unwrap()[self.id] {
+            Endpoint::Queue(queue) => queue.pop_front(),
+            Endpoint::Udp(_) | Endpoint::Host(_) => None,
+        }
+    }
+}
+
+/// A minimal polled network card on a hub port.
+#[derive(Debug)]
+pub struct Nic {
+    mac: [u8; 6],
+    port: Port,
+    rx: Option<Vec<u8>>,
+    tx: Vec<u8>,
+}
+
+impl Nic {
+    pub fn new(port: Port, mac: [u8; 6]) -> Self {
+        Self {
+            mac,
+            port,
+            rx: None,
+            tx: vec![0; MAX_FRAME],
+        }
+    }
+
+    fn rx(&mut self) -> &[u8] {
+        if self.rx.is_none() {
+            self.rx = self.port.recv();
+        }
+        self.rx.as_deref().unwrap_or_default()
+    }
+}
+
+/// Little endian value of `size` bits at `offset` in `buf`, if it fits.
+fn read(buf: &[u8], offset: u64, size: u64) -> Option<u64> {
+    let bytes = buf.get(offset as usize..)?.get(..size as usize / 8)?;
+    Some(bytes.iter().rev().fold(0, |x, b| x << 8 | *b as u64))
+}
+
+impl Device for Nic {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let mac = self.mac;
+        let value = match offset {
+            MAC_LO => Some(u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]) as u64),
+            MAC_HI => Some(u16::from_le_bytes([mac[4], mac[5]]) as u64),
+            RX_LEN => Some(self.rx().len() as u64),
+            RX_POP | TX_LEN => Some(0),
+            RX_BUF.. if offset < TX_BUF => {
+                let offset = (offset - RX_BUF) as usize;
+                let bytes = size as usize / 8;
+                let rx = self.rx();
+                // Past the end of the frame reads as zeroes.
+                (offset + bytes <= MAX_FRAME).then(|| {
+                    (offset..offset + bytes)
+                        .rev()
+                        .fold(0, |x, i| x << 8 | *rx.get(i).unwrap_or(&0) as u64)
+                })
+            }
+            TX_BUF.. => read(&self.tx, offset - TX_BUF, size),
+            _ => None,
+        };
+        value.ok_or(Exception::LoadAccessFault(NIC_BASE + offset))
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        match offset {
+            RX_POP => self.rx = None,
+            TX_LEN => match self.tx.get(..value as usize) {
+                Some(frame) => self.port.send(frame),
+                None => warn!(len = value, "frame too long"),
+            },
+            TX_BUF.. => {
+                let bytes = value.to_le_bytes();
+                let Some(buf) = self
+                    .tx
+                    .get_mut((offset - TX_BUF) as usize..)
+                    .and_then(|x| x.get_mut(..size as usize / 8))
+                else {
+                    return Err(Exception::StoreAmoAccessFault(NIC_BASE + offset));
+                };
+                buf.copy_from_slice(&bytes[..buf.len()]);
+            }
+            _ => return Err(Exception::StoreAmoAccessFault(NIC_BASE + offset)),
+        }
+        Ok(())
+    }
+}
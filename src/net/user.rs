@@ -0,0 +1,281 @@
+//! User-mode networking, like QEMU's: the guest is alone on 10.0.2.0/24
+//! behind a gateway at [`GATEWAY`], which answers ARP, pings and DHCP,
+//! handing out [`GUEST`], and forwards UDP through sockets of the host, as
+//! NAT does. The gateway address reaches the host itself. TCP isn't
+//! forwarded, nor is anything but IPv4.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::Arc,
+    thread,
+};
+
+use tracing::{debug, warn};
+
+use super::{Hub, Port, MAX_FRAME};
+
+pub const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+pub const GUEST: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+pub const NETMASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 0);
+/// The MAC address the gateway answers ARP with, as QEMU's.
+pub const GATEWAY_MAC: [u8; 6] = [0x52, 0x55, 0x0a, 0x00, 0x02, 0x02];
+
+const BROADCAST_MAC: [u8; 6] = [0xff; 6];
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_ARP: u16 = 0x0806;
+const PROTOCOL_ICMP: u8 = 1;
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+
+const DHCP_SERVER: u16 = 67;
+const DHCP_CLIENT: u16 = 68;
+const DHCP_MAGIC: [u8; 4] = [99, 130, 83, 99];
+const DHCP_DISCOVER: u8 = 1;
+const DHCP_OFFER: u8 = 2;
+const DHCP_REQUEST: u8 = 3;
+const DHCP_ACK: u8 = 5;
+/// Seconds the address is leased for.
+const DHCP_LEASE: u32 = 86400;
+
+impl Hub {
+    /// Links this hub to a user-mode network, see [`user`](self).
+    pub fn link_user(&self) {
+        let mut gateway = Gateway::default();
+        self.link_host(move |port, frame| gateway.handle(port, &frame));
+    }
+}
+
+/// The gateway, with a socket for each UDP port of the guest it forwards.
+#[derive(Default)]
+struct Gateway {
+    sockets: HashMap<u16, UdpSocket>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+fn ip_at(data: &[u8], at: usize) -> Ipv4Addr {
+    Ipv4Addr::new(data[at], data[at + 1], data[at + 2], data[at + 3])
+}
+
+/// The internet checksum of `data`.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|x| u32::from(x[0]) << 8 | u32::from(*x.get(1).unwrap_or(&0)))
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+fn ethernet(dst: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&GATEWAY_MAC);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn ipv4(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0x45, 0];
+    packet.extend_from_slice(&(20 + payload.len() as u16).to_be_bytes());
+    // no fragments
+    packet.extend_from_slice(&[0, 0, 0x40, 0, 64, protocol, 0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// A UDP datagram from `src` to `dst`, without a checksum.
+fn udp(src: (Ipv4Addr, u16), dst: (Ipv4Addr, u16), data: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + data.len());
+    datagram.extend_from_slice(&src.1.to_be_bytes());
+    datagram.extend_from_slice(&dst.1.to_be_bytes());
+    datagram.extend_from_slice(&(8 + data.len() as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    ipv4(src.0, dst.0, PROTOCOL_UDP, &datagram)
+}
+
+impl Gateway {
+    fn handle(&mut self, port: &Arc<Port>, frame: &[u8]) {
+        if frame.len() < 14 {
+            return;
+        }
+        let mac: [u8; 6] = frame[6..12].try_into().unwrap();
+        let payload = &frame[14..];
+        let reply = match u16_at(frame, 12) {
+            ETHERTYPE_ARP => self.arp(payload),
+            ETHERTYPE_IPV4 => self.ipv4(port, mac, payload),
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            port.send(&reply);
+        }
+    }
+
+    /// Answers the requests for the address of the gateway.
+    fn arp(&self, request: &[u8]) -> Option<Vec<u8>> {
+        if request.len() < 28 || u16_at(request, 6) != 1 || ip_at(request, 24) != GATEWAY {
+            return None;
+        }
+        let mut reply = request[..28].to_vec();
+        reply[7] = 2;
+        reply[8..14].copy_from_slice(&GATEWAY_MAC);
+        reply[14..18].copy_from_slice(&GATEWAY.octets());
+        reply[18..28].copy_from_slice(&request[8..18]);
+        Some(ethernet(
+            request[8..14].try_into().unwrap(),
+            ETHERTYPE_ARP,
+            &reply,
+        ))
+    }
+
+    fn ipv4(&mut self, port: &Arc<Port>, mac: [u8; 6], packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return None;
+        }
+        let header = usize::from(packet[0] & 0xf) * 4;
+        let total = usize::from(u16_at(packet, 2)).min(packet.len());
+        let payload = packet.get(header..total)?;
+        let (src, dst) = (ip_at(packet, 12), ip_at(packet, 16));
+        match packet[9] {
+            // echo requests to the gateway
+            PROTOCOL_ICMP if dst == GATEWAY && payload.first() == Some(&8) => {
+                let mut reply = payload.to_vec();
+                reply[0] = 0;
+                reply[2..4].fill(0);
+                let sum = checksum(&reply);
+                reply[2..4].copy_from_slice(&sum.to_be_bytes());
+                Some(ethernet(
+                    mac,
+                    ETHERTYPE_IPV4,
+                    &ipv4(GATEWAY, src, PROTOCOL_ICMP, &reply),
+                ))
+            }
+            PROTOCOL_UDP if payload.len() >= 8 => {
+                let (sport, dport) = (u16_at(payload, 0), u16_at(payload, 2));
+                let len = usize::from(u16_at(payload, 4)).clamp(8, payload.len());
+                let data = &payload[8..len];
+                if dport == DHCP_SERVER {
+                    return dhcp(data);
+                }
+                self.forward(port, mac, sport, (dst, dport), data);
+                None
+            }
+            PROTOCOL_TCP => {
+                debug!(%dst, "tcp isn't forwarded");
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Sends `data` from the guest's `sport` to `dst` through the socket of
+    /// `sport`, whose datagrams go back to the guest.
+    fn forward(
+        &mut self,
+        port: &Arc<Port>,
+        mac: [u8; 6],
+        sport: u16,
+        dst: (Ipv4Addr, u16),
+        data: &[u8],
+    ) {
+        let host = match dst.0 {
+            GATEWAY => Ipv4Addr::LOCALHOST,
+            ip => ip,
+        };
+        let socket = match self.sockets.entry(sport) {
+            Entry::Occupied(x) => x.into_mut(),
+            Entry::Vacant(x) => {
+                let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)) {
+                    Ok(x) => x,
+                    Err(e) => return warn!("user network socket: {e}"),
+                };
+                let Ok(receiver) = socket.try_clone() else {
+                    return;
+                };
+                let port = port.clone();
+                thread::spawn(move || {
+                    let mut buf = [0; MAX_FRAME];
+                    while let Ok((n, SocketAddr::V4(from))) = receiver.recv_from(&mut buf) {
+                        let ip = match *from.ip() {
+                            ip if ip.is_loopback() => GATEWAY,
+                            ip => ip,
+                        };
+                        let datagram = udp((ip, from.port()), (GUEST, sport), &buf[..n]);
+                        port.send(&ethernet(mac, ETHERTYPE_IPV4, &datagram));
+                    }
+                });
+                x.insert(socket)
+            }
+        };
+        if let Err(e) = socket.send_to(data, (host, dst.1)) {
+            debug!(%host, port = dst.1, "dropped a datagram: {e}");
+        }
+    }
+}
+
+/// Answers a DHCP discover with an offer and a request with an ack, of
+/// [`GUEST`].
+fn dhcp(request: &[u8]) -> Option<Vec<u8>> {
+    if request.len() < 240 || request[0] != 1 || request[236..240] != DHCP_MAGIC {
+        return None;
+    }
+    let mut options = &request[240..];
+    let mut kind = None;
+    while let [code, rest @ ..] = options {
+        match (*code, rest) {
+            (255, _) => break,
+            (0, _) => options = rest,
+            (code, [len, rest @ ..]) if rest.len() >= usize::from(*len) => {
+                if code == 53 && *len == 1 {
+                    kind = Some(rest[0]);
+                }
+                options = &rest[usize::from(*len)..];
+            }
+            _ => break,
+        }
+    }
+    let kind = match kind? {
+        DHCP_DISCOVER => DHCP_OFFER,
+        DHCP_REQUEST => DHCP_ACK,
+        _ => return None,
+    };
+
+    let mut reply = vec![0; 240];
+    reply[0] = 2;
+    // the hardware type, transaction id and flags of the request
+    reply[1..3].copy_from_slice(&request[1..3]);
+    reply[4..8].copy_from_slice(&request[4..8]);
+    reply[10..12].copy_from_slice(&request[10..12]);
+    reply[16..20].copy_from_slice(&GUEST.octets());
+    reply[20..24].copy_from_slice(&GATEWAY.octets());
+    reply[28..44].copy_from_slice(&request[28..44]);
+    reply[236..240].copy_from_slice(&DHCP_MAGIC);
+    reply.extend_from_slice(&[53, 1, kind, 54, 4]);
+    reply.extend_from_slice(&GATEWAY.octets());
+    reply.extend_from_slice(&[51, 4]);
+    reply.extend_from_slice(&DHCP_LEASE.to_be_bytes());
+    reply.extend_from_slice(&[1, 4]);
+    reply.extend_from_slice(&NETMASK.octets());
+    reply.extend_from_slice(&[3, 4]);
+    reply.extend_from_slice(&GATEWAY.octets());
+    reply.push(255);
+
+    let datagram = udp(
+        (GATEWAY, DHCP_SERVER),
+        (Ipv4Addr::BROADCAST, DHCP_CLIENT),
+        &reply,
+    );
+    Some(ethernet(BROADCAST_MAC, ETHERTYPE_IPV4, &datagram))
+}
@@ -0,0 +1,285 @@
+//! A CFI parallel flash with the Intel command set, as the `pflash_cfi01` of
+//! QEMU virt, holding a host image which programs and erases write back to.
+//! Firmware reads it as memory, and switches it to the other modes by
+//! storing commands anywhere in it:
+//!
+//! - `0xff`: read the array
+//! - `0x70`, `0x50`: read the status register, clear its errors
+//! - `0x90`, `0x98`: read the identifier, the CFI query table
+//! - `0x40` or `0x10`, then the data: program, which only clears bits
+//! - `0x20` then `0xd0` at the sector: erase it to `0xff`
+//! - `0xe8`, the count of words less one, the words, then `0xd0`: program
+//!   through the write buffer
+//! - `0x60` then `0x01` or `0xd0`: lock or unlock, which is ignored
+//!
+//! A command out of sequence sets the error bits of the status register.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::Path,
+};
+
+use tracing::warn;
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where QEMU virt maps its first flash bank.
+pub const PFLASH_BASE: u64 = 0x2000_0000;
+/// Largest image, the size of the bank.
+pub const PFLASH_MAX: u64 = 32 << 20;
+pub const SECTOR_SIZE: u64 = 256 << 10;
+/// Bytes of the bus to the flash, the stride of the identifier and query
+/// tables.
+const BANK_WIDTH: u64 = 4;
+/// Largest write buffer, in bytes.
+const BUFFER_SIZE: u64 = 64;
+
+const READ_ARRAY: u8 = 0xff;
+const READ_STATUS: u8 = 0x70;
+const CLEAR_STATUS: u8 = 0x50;
+const READ_ID: u8 = 0x90;
+const QUERY: u8 = 0x98;
+const PROGRAM: u8 = 0x40;
+const PROGRAM_ALT: u8 = 0x10;
+const ERASE: u8 = 0x20;
+const WRITE_BUFFER: u8 = 0xe8;
+const CONFIRM: u8 = 0xd0;
+const LOCK: u8 = 0x60;
+const LOCK_SET: u8 = 0x01;
+
+/// Status register bits.
+const STATUS_READY: u8 = 0x80;
+const STATUS_ERASE_ERROR: u8 = 0x20;
+const STATUS_PROGRAM_ERROR: u8 = 0x10;
+
+/// Intel manufacturer and device codes.
+const MANUFACTURER: u8 = 0x89;
+const DEVICE: u8 = 0x18;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    ReadArray,
+    Status,
+    Identify,
+    Query,
+    /// The next store is the data to program.
+    Program,
+    /// Waiting for the confirmation of the erase.
+    Erase,
+    /// The next store is the count of words to buffer.
+    BufferCount,
+    /// Buffering `remaining` more words, then waiting for the confirmation.
+    Buffer {
+        remaining: u64,
+        data: Vec<(u64, u8)>,
+    },
+    Lock,
+}
+
+#[derive(Debug)]
+pub struct Pflash {
+    file: File,
+    data: Vec<u8>,
+    mode: Mode,
+    status: u8,
+}
+
+impl Pflash {
+    /// A flash holding the image at `path`, a whole number of sectors up to
+    /// [`PFLASH_MAX`].
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let len = data.len() as u64;
+        if len == 0 || !len.is_multiple_of(SECTOR_SIZE) || len > PFLASH_MAX {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "pflash image of {len} bytes, expected a multiple of {SECTOR_SIZE} up to {PFLASH_MAX}"
+                ),
+            ));
+        }
+        Ok(Self {
+            file,
+            data,
+            mode: Mode::ReadArray,
+            status: STATUS_READY,
+        })
+    }
+
+    /// A byte of the CFI query table.
+    fn query(&self, index: u64) -> u8 {
+        let size = self.data.len() as u64;
+        let sectors = (size / SECTOR_SIZE - 1).to_le_bytes();
+        let sector = (SECTOR_SIZE / 256).to_le_bytes();
+        match index {
+            0x10 => b'Q',
+            0x11 => b'R',
+            0x12 => b'Y',
+            // the Intel command set, without an extended table
+            0x13 => 0x01,
+            // 2.7 to 3.6 volts
+            0x1b => 0x27,
+            0x1c => 0x36,
+            // typical and largest times to program a word, the buffer and
+            // to erase a sector, as powers of two
+            0x1f => 0x07,
+            0x20 => 0x07,
+            0x21 => 0x0a,
+            0x23..=0x25 => 0x04,
+            0x27 => size.trailing_zeros() as u8,
+            // x8 and x16
+            0x28 => 0x02,
+            0x2a => BUFFER_SIZE.trailing_zeros() as u8,
+            // a single region of uniform sectors
+            0x2c => 1,
+            0x2d => sectors[0],
+            0x2e => sectors[1],
+            0x2f => sector[0],
+            0x30 => sector[1],
+            _ => 0,
+        }
+    }
+
+    /// Writes `range` of the array back to the image.
+    fn persist(&mut self, range: Range<usize>) {
+        let result = self
+            .file
+            .seek(SeekFrom::Start(range.start as u64))
+            .and_then(|_| self.file.write_all(&self.data[range]));
+        if let Err(e) = result {
+            warn!("pflash write back: {e}");
+            self.status |= STATUS_PROGRAM_ERROR;
+        }
+    }
+
+    /// Programs `bytes` at `offset`, clearing bits only.
+    fn program(&mut self, offset: u64, bytes: &[(u64, u8)]) {
+        let (mut start, mut end) = (usize::MAX, 0);
+        for &(addr, byte) in bytes {
+            let i = (offset + addr) as usize;
+            if let Some(x) = self.data.get_mut(i) {
+                *x &= byte;
+                start = start.min(i);
+                end = end.max(i + 1);
+            }
+        }
+        if start < end {
+            self.persist(start..end);
+        }
+    }
+
+    fn erase(&mut self, offset: u64) {
+        let start = (offset - offset % SECTOR_SIZE) as usize;
+        let end = start + SECTOR_SIZE as usize;
+        self.data[start..end].fill(0xff);
+        self.persist(start..end);
+    }
+
+    /// A command out of sequence.
+    fn sequence_error(&mut self) {
+        self.status |= STATUS_ERASE_ERROR | STATUS_PROGRAM_ERROR;
+        self.mode = Mode::Status;
+    }
+}
+
+/// The bytes of a store of `size` bits, by offset.
+fn bytes(size: u64, value: u64) -> Vec<(u64, u8)> {
+    (0..size / 8)
+        .map(|i| (i, (value >> (8 * i)) as u8))
+        .collect()
+}
+
+impl Device for Pflash {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let value = match self.mode {
+            Mode::ReadArray => {
+                let start = offset as usize;
+                let bytes = self
+                    .data
+                    .get(start..start + size as usize / 8)
+                    .ok_or(Exception::LoadAccessFault(PFLASH_BASE + offset))?;
+                bytes.iter().rev().fold(0, |x, b| x << 8 | u64::from(*b))
+            }
+            Mode::Identify => match offset / BANK_WIDTH {
+                0 => MANUFACTURER.into(),
+                1 => DEVICE.into(),
+                _ => 0,
+            },
+            Mode::Query => self.query(offset / BANK_WIDTH).into(),
+            _ => self.status.into(),
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        let command = value as u8;
+        match std::mem::replace(&mut self.mode, Mode::Status) {
+            Mode::Program => self.program(offset, &bytes(size, value)),
+            Mode::Erase if command == CONFIRM => self.erase(offset),
+            Mode::Erase => self.sequence_error(),
+            Mode::BufferCount => {
+                let words = (value & 0xffff) + 1;
+                match words * BANK_WIDTH <= BUFFER_SIZE {
+                    true => {
+                        self.mode = Mode::Buffer {
+                            remaining: words,
+                            data: Vec::new(),
+                        }
+                    }
+                    false => self.sequence_error(),
+                }
+            }
+            Mode::Buffer { remaining: 0, data } => match command {
+                CONFIRM => self.program(0, &data),
+                _ => self.sequence_error(),
+            },
+            Mode::Buffer {
+                remaining,
+                mut data,
+            } => {
+                data.extend(bytes(size, value).iter().map(|(i, x)| (offset + i, *x)));
+                self.mode = Mode::Buffer {
+                    remaining: remaining - 1,
+                    data,
+                };
+            }
+            Mode::Lock => match command {
+                LOCK_SET | CONFIRM => {}
+                _ => self.sequence_error(),
+            },
+            Mode::ReadArray | Mode::Status | Mode::Identify | Mode::Query => {
+                self.mode = match command {
+                    READ_ARRAY => Mode::ReadArray,
+                    READ_STATUS => Mode::Status,
+                    CLEAR_STATUS => {
+                        self.status = STATUS_READY;
+                        Mode::ReadArray
+                    }
+                    READ_ID => Mode::Identify,
+                    QUERY => Mode::Query,
+                    PROGRAM | PROGRAM_ALT => Mode::Program,
+                    ERASE => Mode::Erase,
+                    WRITE_BUFFER => Mode::BufferCount,
+                    LOCK => Mode::Lock,
+                    _ => {
+                        warn!(offset, value, "unknown pflash command");
+                        Mode::ReadArray
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "pflash"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(PFLASH_BASE..PFLASH_BASE + self.data.len() as u64)
+    }
+}
@@ -0,0 +1,246 @@
+//! The platform-level interrupt controller of the QEMU virt machine, which
+//! routes the interrupt lines of the devices, see
+//! [`Device::pending_irq`], to the external interrupts of the harts. Each
+//! hart has two contexts, machine mode's driving `MEIP` and supervisor
+//! mode's driving `SEIP`, context `2 * hart` and `2 * hart + 1`.
+//!
+//! A source is pending while its line is high and it isn't claimed. A
+//! context raises its interrupt while a source it enables is pending with a
+//! priority above its threshold, claiming returns the highest priority one,
+//! and completing it lets it be pending again.
+
+use std::{
+    cmp::Reverse,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    bus::{Bus, Device},
+    cpu::{Cpu, MIP},
+    exception::{Exception, Interrupt},
+    hart::Hart,
+};
+
+/// Where QEMU virt maps the PLIC.
+pub const PLIC_BASE: u64 = 0x0c00_0000;
+pub const PLIC_SIZE: u64 = 0x0400_0000;
+/// Interrupt sources, from 1 as 0 means none.
+pub const PLIC_SOURCES: u32 = 96;
+
+/// Highest priority, 0 never interrupts.
+const MAX_PRIORITY: u32 = 7;
+
+/// Offsets of the register banks: the priority of each source, the pending
+/// bits, the enable bits of each context, and the threshold and
+/// claim/complete register of each context.
+const PENDING: u64 = 0x1000;
+const ENABLE: u64 = 0x2000;
+const ENABLE_STRIDE: u64 = 0x80;
+const CONTEXT: u64 = 0x20_0000;
+const CONTEXT_STRIDE: u64 = 0x1000;
+
+/// A bit per source.
+type Sources = u128;
+
+#[derive(Debug, Default)]
+struct Inner {
+    priority: Vec<u32>,
+    pending: Sources,
+    /// Claimed and not completed yet.
+    claimed: Sources,
+    /// Sources whose line is high.
+    lines: Sources,
+    enable: Vec<Sources>,
+    threshold: Vec<u32>,
+    /// The interrupt each context raises, as last delivered.
+    levels: Vec<bool>,
+    /// Something which can change the levels happened since they were
+    /// delivered.
+    changed: bool,
+}
+
+impl Inner {
+    fn update(&mut self) {
+        self.pending = self.lines & !self.claimed;
+        self.changed = true;
+    }
+
+    /// The highest priority source `context` takes, the lowest one of those.
+    fn best(&self, context: usize) -> Option<u32> {
+        let candidates = self.pending & self.enable[context];
+        (1..PLIC_SOURCES)
+            .filter(|x| candidates & (1 << x) != 0)
+            .filter(|x| self.priority[*x as usize] > self.threshold[context])
+            .max_by_key(|x| (self.priority[*x as usize], Reverse(*x)))
+    }
+
+    fn claim(&mut self, context: usize) -> u32 {
+        let Some(source) = self.best(context) else {
+            return 0;
+        };
+        self.claimed |= 1 << source;
+        self.update();
+        source
+    }
+
+    fn complete(&mut self, context: usize, source: u64) {
+        if source < u64::from(PLIC_SOURCES) && self.enable[context] & (1 << source) != 0 {
+            self.claimed &= !(1 << source);
+            self.update();
+        }
+    }
+
+    /// 32-bit word `word` of `sources`.
+    fn word(sources: Sources, word: u64) -> u64 {
+        (sources >> (32 * word)) as u64 & 0xffff_ffff
+    }
+}
+
+/// Registers of the PLIC. Clones share them, the machine keeps one to
+/// sample the lines and deliver the interrupts while another is mapped.
+#[derive(Debug, Clone, Default)]
+pub struct Plic {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Plic {
+    /// A PLIC for `harts` harts, with every source disabled.
+    pub fn new(harts: u64) -> Self {
+        let contexts = 2 * harts as usize;
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                priority: vec![0; PLIC_SOURCES as usize],
+                enable: vec![0; contexts],
+                threshold: vec![0; contexts],
+                levels: vec![false; contexts],
+                ..Inner::default()
+            })),
+        }
+    }
+
+    /// Reads the lines of the devices mapped on `bus`.
+    pub fn sample(&self, bus: &Bus) {
+        let lines = bus
+            .pending_irqs()
+            .filter(|x| (1..PLIC_SOURCES).contains(x))
+            .fold(0, |lines: Sources, x| lines | 1 << x);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.lines != lines {
+            inner.lines = lines;
+            inner.update();
+        }
+    }
+
+    /// Updates `MEIP` and `SEIP` in `mip` of the running hart and the parked
+    /// ones whose context changed since the last call. The others are left
+    /// alone, so what software wrote to `SEIP` stays until then.
+    pub fn deliver(&self, cpu: &mut Cpu, harts: &mut [Hart]) {
+        let mut inner = self.inner.lock().unwrap();
+        if !std::mem::take(&mut inner.changed) {
+            return;
+        }
+        for context in 0..inner.levels.len() {
+            let level = inner.best(context).is_some();
+            if inner.levels[context] == level {
+                continue;
+            }
+            inner.levels[context] = level;
+            let hart = context as u64 / 2;
+            let interrupt = match context % 2 {
+                0 => Interrupt::MachineExternal,
+                _ => Interrupt::SupervisorExternal,
+            };
+            if hart == cpu.hart_id() {
+                cpu.set_pending(interrupt, level);
+            } else if let Some(hart) = harts.iter_mut().find(|x| x.id() == hart) {
+                let bit = 1 << interrupt.code();
+                match level {
+                    true => hart.csrs[MIP] |= bit,
+                    false => hart.csrs[MIP] &= !bit,
+                }
+            }
+        }
+    }
+}
+
+impl Device for Plic {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        let contexts = inner.levels.len() as u64;
+        let value = match offset {
+            ..PENDING => inner
+                .priority
+                .get((offset / 4) as usize)
+                .copied()
+                .unwrap_or(0)
+                .into(),
+            PENDING..ENABLE => Inner::word(inner.pending, (offset - PENDING) / 4),
+            ENABLE..CONTEXT => {
+                let context = (offset - ENABLE) / ENABLE_STRIDE;
+                let word = (offset - ENABLE) % ENABLE_STRIDE / 4;
+                match inner.enable.get(context as usize) {
+                    Some(enable) => Inner::word(*enable, word),
+                    None => 0,
+                }
+            }
+            _ => {
+                let context = (offset - CONTEXT) / CONTEXT_STRIDE;
+                match (offset % CONTEXT_STRIDE, context < contexts) {
+                    (0, true) => inner.threshold[context as usize].into(),
+                    (4, true) => inner.claim(context as usize).into(),
+                    _ => 0,
+                }
+            }
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.changed = true;
+        let contexts = inner.levels.len() as u64;
+        match offset {
+            ..PENDING => {
+                let source = (offset / 4) as usize;
+                // source 0 doesn't exist
+                if let Some(priority) = inner.priority.get_mut(source).filter(|_| source > 0) {
+                    *priority = (value as u32).min(MAX_PRIORITY);
+                }
+            }
+            // the pending bits are read only
+            PENDING..ENABLE => {}
+            ENABLE..CONTEXT => {
+                let context = (offset - ENABLE) / ENABLE_STRIDE;
+                let word = (offset - ENABLE) % ENABLE_STRIDE / 4;
+                let sources = (1 << PLIC_SOURCES) - 2;
+                if let Some(enable) = inner.enable.get_mut(context as usize) {
+                    if word < Sources::BITS as u64 / 32 {
+                        let mask = 0xffff_ffff << (32 * word);
+                        let bits = Sources::from(value as u32) << (32 * word);
+                        *enable = (*enable & !mask | bits) & sources;
+                    }
+                }
+            }
+            _ => {
+                let context = (offset - CONTEXT) / CONTEXT_STRIDE;
+                match (offset % CONTEXT_STRIDE, context < contexts) {
+                    (0, true) => {
+                        inner.threshold[context as usize] = (value as u32).min(MAX_PRIORITY)
+                    }
+                    (4, true) => inner.complete(context as usize, value & 0xffff_ffff),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "plic"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(PLIC_BASE..PLIC_BASE + PLIC_SIZE)
+    }
+}
@@ -0,0 +1,250 @@
+//! Device models loaded from shared libraries at runtime.
+//!
+//! A plugin exports `rysk_plugin_abi_version`, which must return
+//! [`RYSK_PLUGIN_ABI_VERSION`], and `rysk_plugin_init`, which is called once
+//! with a [`RyskPluginHost`] to map its devices and the argument string given
+//! on the command line. See `crates/rysk-ffi/include/rysk_plugin.h` for the C
+//! declarations and `examples/counter_plugin.rs` for a plugin in Rust.
+
+use std::{
+    ffi::{c_char, c_void, CStr, CString},
+    fs,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use tracing::info;
+
+use crate::{
+    bus::{Bus, Device},
+    error::EmulatorError,
+    exception::Exception,
+};
+
+/// Bumped whenever the plugin ABI changes incompatibly.
+pub const RYSK_PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Returns 0 and writes the value on success, anything else raises an access fault.
+pub type RyskPluginLoad =
+    unsafe extern "C" fn(user: *mut c_void, offset: u64, size: u32, value: *mut u64) -> i32;
+/// Returns 0 on success, anything else raises an access fault.
+pub type RyskPluginStore =
+    unsafe extern "C" fn(user: *mut c_void, offset: u64, size: u32, value: u64) -> i32;
+/// Releases the `user` pointer of a device when the machine is dropped.
+pub type RyskPluginFree = unsafe extern "C" fn(user: *mut c_void);
+
+/// Maps `[base, base + size)` to the callbacks, returns 0 on success.
+pub type RyskPluginMap = unsafe extern "C" fn(
+    context: *mut c_void,
+    base: u64,
+    size: u64,
+    load: Option<RyskPluginLoad>,
+    store: Option<RyskPluginStore>,
+    free: Option<RyskPluginFree>,
+    user: *mut c_void,
+) -> i32;
+
+/// Services offered to `rysk_plugin_init`.
+#[repr(C)]
+pub struct RyskPluginHost {
+    pub abi_version: u32,
+    /// Passed back to the functions below.
+    pub context: *mut c_void,
+    pub map: RyskPluginMap,
+}
+
+/// Returns 0 on success, anything else fails the machine construction.
+pub type RyskPluginInit =
+    unsafe extern "C" fn(host: *const RyskPluginHost, args: *const c_char) -> i32;
+type RyskPluginAbiVersion = unsafe extern "C" fn() -> u32;
+
+/// A plugin to load and the argument string passed to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginSpec {
+    pub path: PathBuf,
+    pub args: String,
+}
+
+/// Handle returned by `dlopen`, closed once no device refers to it.
+struct Library(*mut c_void);
+
+// The handle is only used to look up symbols and close the library.
+unsafe impl Send for Library {}
+unsafe impl Sync for Library {}
+
+impl Library {
+    fn open(path: &Path) -> Result<Self, EmulatorError> {
+        let name = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| plugin_error(path, "path contains a nul byte"))?;
+        // SAFETY: the name is a valid C string, initializers of the library run here.
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(plugin_error(path, &dlerror()));
+        }
+        Ok(Self(handle))
+    }
+
+    fn symbol(&self, path: &Path, name: &CStr) -> Result<*mut c_void, EmulatorError> {
+        // SAFETY: the handle is open and the name is a valid C string.
+        let symbol = unsafe { libc::dlsym(self.0, name.as_ptr()) };
+        if symbol.is_null() {
+            return Err(plugin_error(
+                path,
+                &format!("missing symbol {}", name.to_string_lossy()),
+            ));
+        }
+        Ok(symbol)
+    }
+}
+
+impl Drop for Library {
+    fn drop(&mut self) {
+        // SAFETY: no device of the plugin is left to call into it.
+        unsafe { libc::dlclose(self.0) };
+    }
+}
+
+fn dlerror() -> String {
+    // SAFETY: dlerror returns null or a C string valid until the next call.
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        return String::from("unknown error");
+    }
+    unsafe { CStr::from_ptr(error) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn plugin_error(path: &Path, reason: &str) -> EmulatorError {
+    EmulatorError::Plugin(format!("{}: {reason}", path.display()))
+}
+
+/// A region mapped by a plugin.
+struct PluginDevice {
+    base: u64,
+    load: RyskPluginLoad,
+    store: RyskPluginStore,
+    free: Option<RyskPluginFree>,
+    user: *mut c_void,
+    _library: Arc<Library>,
+}
+
+// Plugins are responsible for `user` being usable from whichever thread
+// drives the machine.
+unsafe impl Send for PluginDevice {}
+
+impl Device for PluginDevice {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let mut value = 0;
+        match unsafe { (self.load)(self.user, offset, size as u32, &mut value) } {
+            0 => Ok(value),
+            _ => Err(Exception::LoadAccessFault(self.base + offset)),
+        }
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        match unsafe { (self.store)(self.user, offset, size as u32, value) } {
+            0 => Ok(()),
+            _ => Err(Exception::StoreAmoAccessFault(self.base + offset)),
+        }
+    }
+}
+
+impl Drop for PluginDevice {
+    fn drop(&mut self) {
+        if let Some(free) = self.free {
+            unsafe { free(self.user) };
+        }
+    }
+}
+
+/// State behind `RyskPluginHost::context` while `rysk_plugin_init` runs.
+struct Registration<'a> {
+    bus: &'a mut Bus,
+    library: Arc<Library>,
+}
+
+unsafe extern "C" fn map(
+    context: *mut c_void,
+    base: u64,
+    size: u64,
+    load: Option<RyskPluginLoad>,
+    store: Option<RyskPluginStore>,
+    free: Option<RyskPluginFree>,
+    user: *mut c_void,
+) -> i32 {
+    let registration = &mut *(context as *mut Registration);
+    let (Some(load), Some(store)) = (load, store) else {
+        return -1;
+    };
+    if size == 0 {
+        return -1;
+    }
+    registration.bus.map(
+        base,
+        size,
+        PluginDevice {
+            base,
+            load,
+            store,
+            free,
+            user,
+            _library: registration.library.clone(),
+        },
+    );
+    0
+}
+
+/// Loads the plugin at `spec.path` and lets it map its devices on `bus`.
+pub fn load(bus: &mut Bus, spec: &PluginSpec) -> Result<(), EmulatorError> {
+    let path = &spec.path;
+    let library = Arc::new(Library::open(path)?);
+
+    let version = library.symbol(path, c"rysk_plugin_abi_version")?;
+    // SAFETY: the plugin ABI defines the signature of the symbol.
+    let version = unsafe { std::mem::transmute::<*mut c_void, RyskPluginAbiVersion>(version)() };
+    if version != RYSK_PLUGIN_ABI_VERSION {
+        return Err(plugin_error(
+            path,
+            &format!("built for plugin ABI {version}, expected {RYSK_PLUGIN_ABI_VERSION}"),
+        ));
+    }
+
+    let init = library.symbol(path, c"rysk_plugin_init")?;
+    // SAFETY: as above.
+    let init = unsafe { std::mem::transmute::<*mut c_void, RyskPluginInit>(init) };
+    let args = CString::new(spec.args.as_str())
+        .map_err(|_| plugin_error(path, "arguments contain a nul byte"))?;
+
+    let mut registration = Registration {
+        bus,
+        library: library.clone(),
+    };
+    let host = RyskPluginHost {
+        abi_version: RYSK_PLUGIN_ABI_VERSION,
+        context: &mut registration as *mut Registration as *mut c_void,
+        map,
+    };
+    // SAFETY: `host` and `args` outlive the call.
+    match unsafe { init(&host, args.as_ptr()) } {
+        0 => {
+            info!(path = %path.display(), "loaded plugin");
+            Ok(())
+        }
+        status => Err(plugin_error(
+            path,
+            &format!("initialization failed with {status}"),
+        )),
+    }
+}
+
+/// Shared libraries in `dir`, in name order.
+pub fn discover(dir: &Path) -> Result<Vec<PathBuf>, EmulatorError> {
+    let mut plugins = fs::read_dir(dir)?
+        .map(|entry| entry.map(|x| x.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    plugins.retain(|x| x.extension() == Some(std::env::consts::DLL_EXTENSION.as_ref()));
+    plugins.sort();
+    Ok(plugins)
+}
@@ -0,0 +1,219 @@
+//! Flat profile of the guest by function, from the ELF symbol table and
+//! without any instrumentation of the guest. Each retired instruction and
+//! its cycles are charged to the function containing it (self), and to every
+//! function on the call stack (cumulative). Calls and returns are recognized
+//! by the link register conventions, `jal`/`jalr` writing `ra` or `t0` and
+//! `jalr` jumping to one of them.
+
+use std::io::{self, Write};
+
+use crate::{
+    elf::Elf, error::EmulatorError, instruction::Instruction, observer::ExecutionObserver,
+};
+
+const UNKNOWN: &str = "<unknown>";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+impl Cost {
+    fn since(self, earlier: Cost) -> Cost {
+        Cost {
+            instructions: self.instructions - earlier.instructions,
+            cycles: self.cycles - earlier.cycles,
+        }
+    }
+
+    fn add(&mut self, other: Cost) {
+        self.instructions += other.instructions;
+        self.cycles += other.cycles;
+    }
+}
+
+/// A line of the profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub self_cost: Cost,
+    /// Including the functions it called.
+    pub cumulative: Cost,
+}
+
+#[derive(Debug)]
+struct Function {
+    name: String,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Debug)]
+struct Frame {
+    function: usize,
+    entry: Cost,
+}
+
+/// An observer building the profile, see [`Profiler::entries`].
+#[derive(Debug)]
+pub struct Profiler {
+    /// Sorted by address, with [`UNKNOWN`] last for code outside of them.
+    functions: Vec<Function>,
+    self_cost: Vec<Cost>,
+    cumulative: Vec<Cost>,
+    /// Frames of each function on the stack, so a recursive function is only
+    /// charged once.
+    depth: Vec<usize>,
+    stack: Vec<Frame>,
+    total: Cost,
+    /// Function of the last instruction, which the cycles are charged to.
+    current: usize,
+    call: bool,
+    ret: bool,
+}
+
+impl Profiler {
+    /// Profiles the functions of `elf`, sized symbols of type `STT_FUNC`.
+    pub fn from_elf(elf: &Elf) -> Result<Self, EmulatorError> {
+        let functions = elf
+            .functions()?
+            .into_iter()
+            .map(|x| Function {
+                name: x.name,
+                start: x.value,
+                end: x.value + x.size,
+            })
+            .collect();
+        Ok(Self::new(functions))
+    }
+
+    fn new(mut functions: Vec<Function>) -> Self {
+        functions.push(Function {
+            name: UNKNOWN.to_string(),
+            start: u64::MAX,
+            end: u64::MAX,
+        });
+        let n = functions.len();
+        Self {
+            functions,
+            self_cost: vec![Cost::default(); n],
+            cumulative: vec![Cost::default(); n],
+            depth: vec![0; n],
+            stack: Vec::new(),
+            total: Cost::default(),
+            current: n - 1,
+            call: false,
+            ret: false,
+        }
+    }
+
+    fn lookup(&self, pc: u64) -> usize {
+        let current = &self.functions[self.current];
+        if (current.start..current.end).contains(&pc) {
+            return self.current;
+        }
+        let unknown = self.functions.len() - 1;
+        match self.functions[..unknown].partition_point(|x| x.start <= pc) {
+            0 => unknown,
+            i if pc < self.functions[i - 1].end => i - 1,
+            _ => unknown,
+        }
+    }
+
+    fn push(&mut self, function: usize) {
+        self.depth[function] += 1;
+        self.stack.push(Frame {
+            function,
+            entry: self.total,
+        });
+    }
+
+    fn pop(&mut self) {
+        // The entry function returning, or a return without a matching call.
+        if self.stack.len() <= 1 {
+            return;
+        }
+        let frame = self.stack.pop().unwrap();
+        self.depth[frame.function] -= 1;
+        if self.depth[frame.function] == 0 {
+            self.cumulative[frame.function].add(self.total.since(frame.entry));
+        }
+    }
+
+    /// Functions which executed, most expensive first. Functions still on the
+    /// stack are charged up to now.
+    pub fn entries(&self) -> Vec<Entry> {
+        let mut cumulative = self.cumulative.clone();
+        let mut seen = vec![false; self.functions.len()];
+        for frame in &self.stack {
+            if !std::mem::replace(&mut seen[frame.function], true) {
+                cumulative[frame.function].add(self.total.since(frame.entry));
+            }
+        }
+
+        let mut entries: Vec<_> = (0..self.functions.len())
+            .filter(|i| self.self_cost[*i].instructions > 0 || cumulative[*i].instructions > 0)
+            .map(|i| Entry {
+                name: self.functions[i].name.clone(),
+                self_cost: self.self_cost[i],
+                cumulative: cumulative[i],
+            })
+            .collect();
+        entries.sort_by(|a, b| {
+            (b.self_cost.cycles, b.self_cost.instructions)
+                .cmp(&(a.self_cost.cycles, a.self_cost.instructions))
+        });
+        entries
+    }
+
+    /// Writes the `limit` most expensive functions as a table.
+    pub fn write_report(&self, mut out: impl Write, limit: usize) -> io::Result<()> {
+        let total = self.total.cycles.max(1) as f64;
+        writeln!(
+            out,
+            "{:>7} {:>12} {:>12} {:>12} {:>12}  function",
+            "self %", "self instr", "cumul instr", "self cycles", "cumul cycles"
+        )?;
+        for entry in self.entries().iter().take(limit) {
+            writeln!(
+                out,
+                "{:>6.2}% {:>12} {:>12} {:>12} {:>12}  {}",
+                entry.self_cost.cycles as f64 * 100.0 / total,
+                entry.self_cost.instructions,
+                entry.cumulative.instructions,
+                entry.self_cost.cycles,
+                entry.cumulative.cycles,
+                entry.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl ExecutionObserver for Profiler {
+    fn on_instruction(&mut self, pc: u64, inst: &Instruction) {
+        if std::mem::take(&mut self.ret) {
+            self.pop();
+        }
+        let function = self.lookup(pc);
+        if std::mem::take(&mut self.call) || self.stack.is_empty() {
+            self.push(function);
+        }
+        self.current = function;
+        self.self_cost[function].instructions += 1;
+        self.total.instructions += 1;
+
+        let link = |r: usize| r == 1 || r == 5;
+        match inst.opcode {
+            0x6f | 0x67 if link(inst.rd) => self.call = true,
+            0x67 if inst.rd == 0 && link(inst.rs1) => self.ret = true,
+            _ => {}
+        }
+    }
+
+    fn on_cycles(&mut self, cycles: u64) {
+        self.self_cost[self.current].cycles += cycles;
+        self.total.cycles += cycles;
+    }
+}
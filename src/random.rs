@@ -0,0 +1,73 @@
+//! Seeded randomness for reproducible robustness runs, see
+//! [`MachineBuilder::seed`](crate::machine::MachineBuilder::seed): the reset
+//! state of the registers and of the dram outside the image, and the delay
+//! with which device interrupts reach the harts.
+
+/// SplitMix64, small and good enough to spread values out.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Below `n`, which isn't zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    pub fn fill(&mut self, bytes: &mut [u8]) {
+        for chunk in bytes.chunks_mut(8) {
+            let random = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&random[..chunk.len()]);
+        }
+    }
+
+    /// Randomizes the registers a boot loader doesn't set, everything but
+    /// sp and the hart id in a0.
+    pub fn registers(&mut self, regs: &mut [u64; 32]) {
+        for (r, reg) in regs.iter_mut().enumerate() {
+            if !matches!(r, 0 | 2 | 10) {
+                *reg = self.next_u64();
+            }
+        }
+    }
+}
+
+/// Holds device interrupts back for a random number of instructions, up to
+/// a maximum, so guest code racing them shows up.
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    rng: Rng,
+    max: u64,
+    countdown: u64,
+}
+
+impl Jitter {
+    pub fn new(rng: Rng, max: u64) -> Self {
+        Self {
+            rng,
+            max,
+            countdown: 0,
+        }
+    }
+
+    /// Whether the devices get to deliver their interrupts this instruction.
+    pub fn ready(&mut self) -> bool {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return false;
+        }
+        self.countdown = self.rng.below(self.max + 1);
+        true
+    }
+}
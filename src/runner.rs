@@ -0,0 +1,134 @@
+//! Test programs run to completion with a pass/fail convention, reported like
+//! `cargo test`. Used by `rysk test` and `cargo rysk test`.
+//!
+//! A test passes when it exits with 0 through the test finisher, htif or
+//! semihosting, or returns 0 from its entry point. Anything else, including
+//! not stopping within the instruction budget, fails it.
+
+use std::{path::PathBuf, time::Instant};
+
+use crate::{
+    exception::Exception,
+    machine::{ExitReason, Machine, StopCondition},
+};
+
+/// Result of a single test program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+/// Runs `machine` for up to `max_instructions` and judges how it stopped.
+pub fn run(machine: &mut Machine, max_instructions: u64) -> Outcome {
+    let stop = StopCondition {
+        max_instructions: Some(max_instructions),
+        ..StopCondition::default()
+    };
+    let status = match machine.run_until(&stop) {
+        ExitReason::Shutdown(code) => code,
+        // Returned from the entry point.
+        ExitReason::Exception(Exception::InstructionAccessFault(0)) => machine.cpu.regs[10],
+        ExitReason::MaxInstructions => {
+            return Outcome::Failed(format!(
+                "didn't stop within {max_instructions} instructions"
+            ))
+        }
+        ExitReason::Exception(exception) => {
+            return Outcome::Failed(format!("{exception} at pc {:#x}", machine.cpu.pc))
+        }
+        reason => return Outcome::Failed(format!("stopped with {reason:?}")),
+    };
+    match status {
+        0 => Outcome::Passed,
+        code => Outcome::Failed(format!("exit code {code}")),
+    }
+}
+
+/// Collects outcomes and prints them as `cargo test` does.
+#[derive(Debug)]
+pub struct Report {
+    passed: usize,
+    failures: Vec<(String, String)>,
+    start: Instant,
+}
+
+impl Report {
+    /// Prints the header for `count` tests.
+    pub fn new(count: usize) -> Self {
+        println!("\nrunning {count} tests");
+        Self {
+            passed: 0,
+            failures: Vec::new(),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, name: &str, outcome: Outcome) {
+        match outcome {
+            Outcome::Passed => {
+                println!("test {name} ... ok");
+                self.passed += 1;
+            }
+            Outcome::Failed(reason) => {
+                println!("test {name} ... FAILED");
+                self.failures.push((name.to_string(), reason));
+            }
+        }
+    }
+
+    /// Prints the failures and the summary, returning whether every test passed.
+    pub fn finish(self) -> bool {
+        if !self.failures.is_empty() {
+            println!("\nfailures:");
+            for (name, reason) in &self.failures {
+                println!("    {name}: {reason}");
+            }
+        }
+        let result = if self.failures.is_empty() {
+            "ok"
+        } else {
+            "FAILED"
+        };
+        println!(
+            "\ntest result: {result}. {} passed; {} failed; finished in {:.2}s\n",
+            self.passed,
+            self.failures.len(),
+            self.start.elapsed().as_secs_f64()
+        );
+        self.failures.is_empty()
+    }
+}
+
+/// Test executables built by `cargo test --no-run --message-format=json`,
+/// one JSON message per line.
+pub fn test_executables(messages: &str) -> Vec<PathBuf> {
+    messages
+        .lines()
+        .filter(|x| x.contains("\"reason\":\"compiler-artifact\"") && x.contains("\"test\":true"))
+        .filter_map(|x| json_string(x, "executable"))
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Value of the string field `key` in a single JSON object, searched textually.
+fn json_string(json: &str, key: &str) -> Option<String> {
+    let start = json.find(&format!("\"{key}\":\""))? + key.len() + 4;
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
@@ -0,0 +1,339 @@
+//! Built-in SBI firmware: `ecall`s are serviced by the host instead of
+//! trapping into M-mode firmware loaded in the guest, so S-mode kernels boot
+//! without OpenSBI. Implements the base, timer, IPI, hart state management,
+//! system reset and debug console extensions, and the legacy calls. With the
+//! firmware secondary harts start stopped, until the boot hart calls
+//! `hart_start`.
+//!
+//! Arguments are in a0-a5, the extension in a7 and the function in a6. The
+//! error is returned in a0 and the value in a1, legacy calls only return a0.
+
+use std::{
+    fmt,
+    io::{self, Write},
+};
+
+use tracing::warn;
+
+use crate::{
+    aclint::SoftwareInterrupts,
+    console::Console,
+    cpu::{Cpu, MIP, RDTIME},
+    exception::Interrupt,
+    hart::Hart,
+};
+
+pub const EXT_BASE: u64 = 0x10;
+pub const EXT_TIME: u64 = 0x54494d45;
+pub const EXT_IPI: u64 = 0x735049;
+pub const EXT_HSM: u64 = 0x48534d;
+pub const EXT_SRST: u64 = 0x53525354;
+pub const EXT_DBCN: u64 = 0x4442434e;
+
+/// Extensions `probe_extension` reports, besides the legacy ones.
+const EXTENSIONS: &[u64] = &[EXT_BASE, EXT_TIME, EXT_IPI, EXT_HSM, EXT_SRST, EXT_DBCN];
+
+const LEGACY_SET_TIMER: u64 = 0x00;
+const LEGACY_CONSOLE_PUTCHAR: u64 = 0x01;
+const LEGACY_CONSOLE_GETCHAR: u64 = 0x02;
+const LEGACY_CLEAR_IPI: u64 = 0x03;
+const LEGACY_SEND_IPI: u64 = 0x04;
+const LEGACY_SHUTDOWN: u64 = 0x08;
+
+const SUCCESS: u64 = 0;
+const ERR_FAILED: i64 = -1;
+const ERR_NOT_SUPPORTED: i64 = -2;
+const ERR_INVALID_PARAM: i64 = -3;
+const ERR_ALREADY_AVAILABLE: i64 = -6;
+
+/// Version 2.0 of the specification.
+const SPEC_VERSION: u64 = 2 << 24;
+/// Not a registered implementation id, `rysk` in ASCII.
+const IMPL_ID: u64 = 0x7279736b;
+const IMPL_VERSION: u64 = 1;
+
+const MVENDORID: usize = 0xf11;
+const MARCHID: usize = 0xf12;
+const MIMPID: usize = 0xf13;
+
+/// States returned by `hart_get_status`.
+const HART_STARTED: u64 = 0;
+const HART_STOPPED: u64 = 1;
+
+/// `system_reset` type and reason.
+const RESET_SHUTDOWN: u64 = 0;
+const RESET_COLD_REBOOT: u64 = 1;
+const RESET_WARM_REBOOT: u64 = 2;
+const REASON_NONE: u64 = 0;
+
+/// A `hart_mask_base` selecting every hart.
+const ALL_HARTS: u64 = u64::MAX;
+
+/// What the calling hart does after an SBI call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Continues after the `ecall`.
+    Return,
+    /// Stops until another hart starts it again.
+    StopHart,
+}
+
+pub struct Sbi {
+    /// Exit code the guest passed to `system_reset` or the legacy shutdown,
+    /// 0 unless the reason was a failure.
+    pub exit_code: Option<u64>,
+    /// Whether the guest asked `system_reset` for a reboot, cold or warm.
+    pub reboot_requested: bool,
+    output: Box<dyn Write + Send>,
+    ipi: SoftwareInterrupts,
+    /// `set_timer` deadline of each hart, by id.
+    timers: Vec<Option<u64>>,
+    /// The earliest of them.
+    next_timer: Option<u64>,
+}
+
+impl fmt::Debug for Sbi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Sbi")
+            .field("exit_code", &self.exit_code)
+            .field("reboot_requested", &self.reboot_requested)
+            .field("next_timer", &self.next_timer)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Sbi {
+    /// Firmware sending its IPIs through `ipi`, for `harts` harts.
+    pub fn new(ipi: SoftwareInterrupts, harts: u64) -> Self {
+        Self {
+            exit_code: None,
+            reboot_requested: false,
+            output: Box::new(io::stdout()),
+            ipi,
+            timers: vec![None; harts as usize],
+            next_timer: None,
+        }
+    }
+
+    /// Sends console output to `output` instead of stdout.
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Services the call the running hart made to the firmware, with the
+    /// other harts of the machine and keystrokes from `console`. The pc is
+    /// left at the `ecall`.
+    pub fn call(&mut self, cpu: &mut Cpu, harts: &mut [Hart], console: Option<&Console>) -> Action {
+        let ext = cpu.regs[17];
+        if ext <= LEGACY_SHUTDOWN {
+            cpu.regs[10] = self.legacy(cpu, harts, console, ext);
+            return Action::Return;
+        }
+
+        let [a0, a1, a2] = [cpu.regs[10], cpu.regs[11], cpu.regs[12]];
+        let result = match (ext, cpu.regs[16]) {
+            (EXT_BASE, 0) => Ok(SPEC_VERSION),
+            (EXT_BASE, 1) => Ok(IMPL_ID),
+            (EXT_BASE, 2) => Ok(IMPL_VERSION),
+            (EXT_BASE, 3) => Ok((a0 <= LEGACY_SHUTDOWN || EXTENSIONS.contains(&a0)) as u64),
+            (EXT_BASE, 4) => Ok(cpu.csrs[MVENDORID]),
+            (EXT_BASE, 5) => Ok(cpu.csrs[MARCHID]),
+            (EXT_BASE, 6) => Ok(cpu.csrs[MIMPID]),
+            (EXT_TIME, 0) => {
+                self.set_timer(cpu, a0);
+                Ok(0)
+            }
+            (EXT_IPI, 0) => self.send_ipi(cpu, harts, a0, a1),
+            (EXT_HSM, 0) => hart_start(cpu, harts, a0, a1, a2),
+            (EXT_HSM, 1) => return Action::StopHart,
+            (EXT_HSM, 2) => hart_status(cpu, harts, a0),
+            (EXT_SRST, 0) => self.system_reset(a0, a1),
+            (EXT_DBCN, 0) => self.console_write(cpu, a0, a1, a2),
+            (EXT_DBCN, 1) => console_read(cpu, console, a0, a1, a2),
+            (EXT_DBCN, 2) => self.write(&[a0 as u8]).map(|_| 0),
+            _ => Err(ERR_NOT_SUPPORTED),
+        };
+        let (error, value) = match result {
+            Ok(value) => (SUCCESS, value),
+            Err(error) => (error as u64, 0),
+        };
+        cpu.regs[10] = error;
+        cpu.regs[11] = value;
+        Action::Return
+    }
+
+    /// Services a legacy call, returning a0.
+    fn legacy(
+        &mut self,
+        cpu: &mut Cpu,
+        harts: &[Hart],
+        console: Option<&Console>,
+        ext: u64,
+    ) -> u64 {
+        let a0 = cpu.regs[10];
+        let result = match ext {
+            LEGACY_SET_TIMER => {
+                self.set_timer(cpu, a0);
+                Ok(0)
+            }
+            LEGACY_CONSOLE_PUTCHAR => self.write(&[a0 as u8]).map(|_| 0),
+            LEGACY_CONSOLE_GETCHAR => {
+                Ok(console.and_then(Console::read).map_or(u64::MAX, u64::from))
+            }
+            LEGACY_CLEAR_IPI => {
+                cpu.set_pending(Interrupt::SupervisorSoftware, false);
+                Ok(0)
+            }
+            // A null mask selects every hart.
+            LEGACY_SEND_IPI if a0 == 0 => self.send_ipi(cpu, harts, 0, ALL_HARTS),
+            LEGACY_SEND_IPI => match cpu.bus.load(a0, 64) {
+                Ok(mask) => self.send_ipi(cpu, harts, mask, 0),
+                Err(_) => Err(ERR_INVALID_PARAM),
+            },
+            LEGACY_SHUTDOWN => {
+                self.exit_code = Some(0);
+                Ok(0)
+            }
+            // Remote fences, there are no TLBs or instruction caches to flush.
+            _ => Ok(0),
+        };
+        result.unwrap_or_else(|error| error as u64)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), i64> {
+        self.output
+            .write_all(data)
+            .and_then(|_| self.output.flush())
+            .map_err(|e| {
+                warn!("sbi console: {e}");
+                ERR_FAILED
+            })
+    }
+
+    fn set_timer(&mut self, cpu: &mut Cpu, deadline: u64) {
+        cpu.set_pending(Interrupt::SupervisorTimer, false);
+        if let Some(timer) = self.timers.get_mut(cpu.hart_id() as usize) {
+            *timer = Some(deadline);
+        }
+        self.next_timer = self.timers.iter().flatten().min().copied();
+    }
+
+    /// Makes the supervisor timer interrupt of the harts whose `set_timer`
+    /// deadline passed pending.
+    pub fn poll(&mut self, cpu: &mut Cpu, harts: &mut [Hart]) {
+        let now = cpu.csrs[RDTIME];
+        if self.next_timer.is_none_or(|next| now < next) {
+            return;
+        }
+        let running = cpu.hart_id() as usize;
+        for (id, timer) in self.timers.iter_mut().enumerate() {
+            if timer.is_none_or(|deadline| now < deadline) {
+                continue;
+            }
+            *timer = None;
+            if id == running {
+                cpu.set_pending(Interrupt::SupervisorTimer, true);
+            } else if let Some(hart) = harts.iter_mut().find(|x| x.id() == id as u64) {
+                hart.csrs[MIP] |= 1 << Interrupt::SupervisorTimer.code();
+            }
+        }
+        self.next_timer = self.timers.iter().flatten().min().copied();
+    }
+
+    fn send_ipi(&mut self, cpu: &Cpu, harts: &[Hart], mask: u64, base: u64) -> Result<u64, i64> {
+        let exists = |id: u64| id == cpu.hart_id() || harts.iter().any(|x| x.id() == id);
+        let targets: Vec<_> = if base == ALL_HARTS {
+            (0..self.timers.len() as u64).collect()
+        } else {
+            (0..64)
+                .filter(|i| mask & (1 << i) != 0)
+                .map(|i| base.checked_add(i).ok_or(ERR_INVALID_PARAM))
+                .collect::<Result<_, _>>()?
+        };
+        if !targets.iter().all(|id| exists(*id)) {
+            return Err(ERR_INVALID_PARAM);
+        }
+        for id in targets {
+            self.ipi.send_supervisor(id);
+        }
+        Ok(0)
+    }
+
+    fn system_reset(&mut self, kind: u64, reason: u64) -> Result<u64, i64> {
+        match kind {
+            RESET_SHUTDOWN => {
+                self.exit_code = Some((reason != REASON_NONE) as u64);
+                Ok(0)
+            }
+            RESET_COLD_REBOOT | RESET_WARM_REBOOT => {
+                self.reboot_requested = true;
+                Ok(0)
+            }
+            _ => Err(ERR_INVALID_PARAM),
+        }
+    }
+
+    fn console_write(&mut self, cpu: &mut Cpu, len: u64, lo: u64, hi: u64) -> Result<u64, i64> {
+        let addr = lo | hi << 32;
+        let data = (0..len)
+            .map(|i| cpu.bus.load(addr.wrapping_add(i), 8).map(|x| x as u8))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ERR_INVALID_PARAM)?;
+        self.write(&data)?;
+        Ok(len)
+    }
+}
+
+/// Reads the keystrokes waiting, without blocking.
+fn console_read(
+    cpu: &mut Cpu,
+    console: Option<&Console>,
+    len: u64,
+    lo: u64,
+    hi: u64,
+) -> Result<u64, i64> {
+    let addr = lo | hi << 32;
+    let mut read = 0;
+    while read < len {
+        let Some(c) = console.and_then(Console::read) else {
+            break;
+        };
+        cpu.bus
+            .store(addr.wrapping_add(read), 8, c as u64)
+            .map_err(|_| ERR_INVALID_PARAM)?;
+        read += 1;
+    }
+    Ok(read)
+}
+
+fn hart_start(cpu: &Cpu, harts: &mut [Hart], id: u64, addr: u64, opaque: u64) -> Result<u64, i64> {
+    if cpu.hart_id() == id {
+        return Err(ERR_ALREADY_AVAILABLE);
+    }
+    let hart = harts
+        .iter_mut()
+        .find(|x| x.id() == id)
+        .ok_or(ERR_INVALID_PARAM)?;
+    if !hart.stopped {
+        return Err(ERR_ALREADY_AVAILABLE);
+    }
+    hart.stopped = false;
+    hart.pc = addr;
+    // in the mode of the caller, as the firmware would return to it
+    hart.mode = cpu.mode;
+    hart.regs[10] = id;
+    hart.regs[11] = opaque;
+    Ok(0)
+}
+
+fn hart_status(cpu: &Cpu, harts: &[Hart], id: u64) -> Result<u64, i64> {
+    if cpu.hart_id() == id {
+        return Ok(HART_STARTED);
+    }
+    match harts.iter().find(|x| x.id() == id) {
+        Some(hart) if hart.stopped => Ok(HART_STOPPED),
+        Some(_) => Ok(HART_STARTED),
+        None => Err(ERR_INVALID_PARAM),
+    }
+}
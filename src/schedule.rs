@@ -0,0 +1,58 @@
+//! Order in which the harts ran, for replaying an SMP run exactly. Harts
+//! take turns round-robin for a quantum of instructions, and a turn ends
+//! early on a `wfi` or when the hart stops, which depends on how fast the
+//! devices and the host clock are. A recorded schedule pins every turn, so a
+//! replay interleaves the harts the same way whatever the timing.
+//!
+//! The text format has a turn per line, the hart id and the instructions it
+//! executed separated by a space.
+
+use std::io::{self, BufRead, Write};
+
+use crate::error::EmulatorError;
+
+/// Instructions executed by a hart before the next one ran.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Turn {
+    pub hart: u64,
+    pub instructions: u64,
+}
+
+/// Appends a turn, merged with the last one if the same hart kept running.
+pub(crate) fn push(turns: &mut Vec<Turn>, turn: Turn) {
+    match turns.last_mut() {
+        Some(last) if last.hart == turn.hart => last.instructions += turn.instructions,
+        _ if turn.instructions > 0 => turns.push(turn),
+        _ => {}
+    }
+}
+
+pub fn write(turns: &[Turn], mut out: impl Write) -> io::Result<()> {
+    for turn in turns {
+        writeln!(out, "{} {}", turn.hart, turn.instructions)?;
+    }
+    out.flush()
+}
+
+pub fn read(input: impl BufRead) -> Result<Vec<Turn>, EmulatorError> {
+    let mut turns = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split_whitespace().map(str::parse);
+        match (fields.next(), fields.next(), fields.next()) {
+            (Some(Ok(hart)), Some(Ok(instructions)), None) => {
+                turns.push(Turn { hart, instructions })
+            }
+            _ => {
+                return Err(EmulatorError::InvalidSchedule(format!(
+                    "line {}: expected a hart and a count, got {line:?}",
+                    i + 1
+                )))
+            }
+        }
+    }
+    Ok(turns)
+}
@@ -0,0 +1,314 @@
+//! Rhai scripts attached to a machine with `--script`. At load time the script
+//! registers callbacks:
+//!
+//! ```rhai
+//! // Make the function at 0x80000100 return 0.
+//! on_breakpoint(0x80000100, |cpu| {
+//!     cpu[10] = 0;
+//!     cpu.pc = cpu[1];
+//! });
+//! // A device answering 42 to every load.
+//! on_mmio(0x10000000, 0x100, |offset, size| 42, |offset, size, value| print(value));
+//! // Returning true resumes at cpu.pc instead of stopping.
+//! on_trap(|cpu, cause, tval| false);
+//! ```
+//!
+//! Breakpoint and trap callbacks get the cpu, which is only usable during the
+//! call: `cpu[i]` and `cpu.pc` read and write registers, `cpu.load(addr, bits)`
+//! and `cpu.store(addr, bits, value)` access memory.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+use tracing::warn;
+
+use crate::{
+    bus::{Bus, Device},
+    cpu::Cpu,
+    error::EmulatorError,
+    exception::Exception,
+};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// The cpu as seen by callbacks.
+#[derive(Clone)]
+struct CpuHandle {
+    cpu: *mut Cpu,
+    /// Cleared once the callback returns, the pointer is stale from then on.
+    valid: Arc<AtomicBool>,
+}
+
+// The handle is only dereferenced while the callback it was made for runs,
+// on the thread stepping the machine.
+unsafe impl Send for CpuHandle {}
+unsafe impl Sync for CpuHandle {}
+
+impl CpuHandle {
+    fn cpu(&mut self) -> ScriptResult<&mut Cpu> {
+        if !self.valid.load(Ordering::Relaxed) {
+            return Err("the cpu is only usable during the callback".into());
+        }
+        // SAFETY: `valid` is set only while the callback holding the
+        // exclusive borrow of the cpu runs.
+        Ok(unsafe { &mut *self.cpu })
+    }
+
+    fn reg(&mut self, index: INT) -> ScriptResult<INT> {
+        let cpu = self.cpu()?;
+        match cpu.regs.get(index as usize) {
+            Some(x) => Ok(*x as INT),
+            None => Err(format!("no register x{index}").into()),
+        }
+    }
+
+    fn set_reg(&mut self, index: INT, value: INT) -> ScriptResult<()> {
+        let cpu = self.cpu()?;
+        match index {
+            0 => Ok(()),
+            1..=31 => {
+                cpu.regs[index as usize] = value as u64;
+                Ok(())
+            }
+            _ => Err(format!("no register x{index}").into()),
+        }
+    }
+
+    fn load(&mut self, addr: INT, bits: INT) -> ScriptResult<INT> {
+        let cpu = self.cpu()?;
+        cpu.bus
+            .load(addr as u64, bits as u64)
+            .map(|x| x as INT)
+            .map_err(|e| e.to_string().into())
+    }
+
+    fn store(&mut self, addr: INT, bits: INT, value: INT) -> ScriptResult<()> {
+        let cpu = self.cpu()?;
+        cpu.bus
+            .store(addr as u64, bits as u64, value as u64)
+            .map_err(|e| e.to_string().into())
+    }
+}
+
+/// Callbacks collected while the script is evaluated.
+#[derive(Default)]
+struct Registry {
+    breakpoints: BTreeMap<u64, Vec<FnPtr>>,
+    mmio: Vec<(u64, u64, FnPtr, FnPtr)>,
+    traps: Vec<FnPtr>,
+}
+
+struct Shared {
+    engine: Engine,
+    ast: AST,
+}
+
+/// A loaded script and its breakpoint and trap callbacks.
+pub struct Script {
+    shared: Arc<Shared>,
+    breakpoints: BTreeMap<u64, Vec<FnPtr>>,
+    traps: Vec<FnPtr>,
+}
+
+impl std::fmt::Debug for Script {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Script")
+            .field("breakpoints", &self.breakpoints.keys().collect::<Vec<_>>())
+            .field("traps", &self.traps.len())
+            .finish_non_exhaustive()
+    }
+}
+
+fn script_error(e: impl std::fmt::Display) -> EmulatorError {
+    EmulatorError::Script(e.to_string())
+}
+
+impl Script {
+    pub fn from_file(path: &Path, bus: &mut Bus) -> Result<Self, EmulatorError> {
+        let source = std::fs::read_to_string(path)?;
+        Self::new(&source, bus)
+    }
+
+    /// Evaluates `source`, mapping the devices it registers on `bus`.
+    pub fn new(source: &str, bus: &mut Bus) -> Result<Self, EmulatorError> {
+        let registry = Arc::new(Mutex::new(Registry::default()));
+        let mut engine = Engine::new();
+
+        engine
+            .register_type_with_name::<CpuHandle>("Cpu")
+            .register_get_set(
+                "pc",
+                |cpu: &mut CpuHandle| cpu.cpu().map(|x| x.pc as INT),
+                |cpu: &mut CpuHandle, pc: INT| cpu.cpu().map(|x| x.pc = pc as u64),
+            )
+            .register_indexer_get_set(CpuHandle::reg, CpuHandle::set_reg)
+            .register_fn("load", CpuHandle::load)
+            .register_fn("store", CpuHandle::store);
+
+        let r = registry.clone();
+        engine.register_fn("on_breakpoint", move |addr: INT, callback: FnPtr| {
+            let mut r = r.lock().unwrap();
+            r.breakpoints.entry(addr as u64).or_default().push(callback);
+        });
+        let r = registry.clone();
+        engine.register_fn(
+            "on_mmio",
+            move |base: INT, size: INT, load: FnPtr, store: FnPtr| {
+                let mut r = r.lock().unwrap();
+                r.mmio.push((base as u64, size as u64, load, store));
+            },
+        );
+        let r = registry.clone();
+        engine.register_fn("on_trap", move |callback: FnPtr| {
+            r.lock().unwrap().traps.push(callback);
+        });
+
+        let ast = engine.compile(source).map_err(script_error)?;
+        engine.run_ast(&ast).map_err(script_error)?;
+
+        // Later registrations, from inside callbacks, aren't picked up.
+        let registry = std::mem::take(&mut *registry.lock().unwrap());
+        let shared = Arc::new(Shared { engine, ast });
+        for (base, size, load, store) in registry.mmio {
+            bus.map(
+                base,
+                size,
+                ScriptDevice {
+                    base,
+                    load,
+                    store,
+                    shared: shared.clone(),
+                },
+            );
+        }
+
+        Ok(Self {
+            shared,
+            breakpoints: registry.breakpoints,
+            traps: registry.traps,
+        })
+    }
+
+    /// Runs the callbacks for a breakpoint at `cpu.pc`, returning whether
+    /// they moved the pc so the instruction there should be skipped.
+    pub fn breakpoint(&self, cpu: &mut Cpu) -> bool {
+        let pc = cpu.pc;
+        let Some(callbacks) = self.breakpoints.get(&pc) else {
+            return false;
+        };
+        for callback in callbacks {
+            if let Err(e) = self.call::<Dynamic>(cpu, callback, ()) {
+                warn!(pc, "breakpoint script failed: {e}");
+            }
+        }
+        cpu.pc != pc
+    }
+
+    /// Runs the trap callbacks, returning whether one of them handled the
+    /// exception so execution can resume at `cpu.pc`.
+    pub fn trap(&self, cpu: &mut Cpu, exception: &Exception) -> bool {
+        let mut handled = false;
+        for callback in &self.traps {
+            let args = (exception.code() as INT, exception.value() as INT);
+            match self.call::<Dynamic>(cpu, callback, args) {
+                Ok(result) => handled |= result.as_bool().unwrap_or(false),
+                Err(e) => warn!("trap script failed: {e}"),
+            }
+        }
+        handled
+    }
+
+    fn call<T: Clone + Send + Sync + 'static>(
+        &self,
+        cpu: &mut Cpu,
+        callback: &FnPtr,
+        args: impl CallArgs,
+    ) -> ScriptResult<T> {
+        let handle = CpuHandle {
+            cpu,
+            valid: Arc::new(AtomicBool::new(true)),
+        };
+        let valid = handle.valid.clone();
+        let result = args.call(&self.shared, callback, handle);
+        valid.store(false, Ordering::Relaxed);
+        result
+    }
+}
+
+/// Arguments following the cpu in a callback.
+trait CallArgs {
+    fn call<T: Clone + Send + Sync + 'static>(
+        self,
+        shared: &Shared,
+        callback: &FnPtr,
+        cpu: CpuHandle,
+    ) -> ScriptResult<T>;
+}
+
+impl CallArgs for () {
+    fn call<T: Clone + Send + Sync + 'static>(
+        self,
+        shared: &Shared,
+        callback: &FnPtr,
+        cpu: CpuHandle,
+    ) -> ScriptResult<T> {
+        callback.call(&shared.engine, &shared.ast, (cpu,))
+    }
+}
+
+impl CallArgs for (INT, INT) {
+    fn call<T: Clone + Send + Sync + 'static>(
+        self,
+        shared: &Shared,
+        callback: &FnPtr,
+        cpu: CpuHandle,
+    ) -> ScriptResult<T> {
+        callback.call(&shared.engine, &shared.ast, (cpu, self.0, self.1))
+    }
+}
+
+/// A region registered with `on_mmio`.
+struct ScriptDevice {
+    base: u64,
+    load: FnPtr,
+    store: FnPtr,
+    shared: Arc<Shared>,
+}
+
+impl Device for ScriptDevice {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let shared = &self.shared;
+        match self
+            .load
+            .call::<INT>(&shared.engine, &shared.ast, (offset as INT, size as INT))
+        {
+            Ok(value) => Ok(value as u64),
+            Err(e) => {
+                warn!(offset, "mmio load script failed: {e}");
+                Err(Exception::LoadAccessFault(self.base + offset))
+            }
+        }
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        let shared = &self.shared;
+        let args = (offset as INT, size as INT, value as INT);
+        match self
+            .store
+            .call::<Dynamic>(&shared.engine, &shared.ast, args)
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                warn!(offset, "mmio store script failed: {e}");
+                Err(Exception::StoreAmoAccessFault(self.base + offset))
+            }
+        }
+    }
+}
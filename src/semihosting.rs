@@ -0,0 +1,327 @@
+//! RISC-V semihosting: an `ebreak` between `slli x0, x0, 0x1f` and
+//! `srai x0, x0, 7` asks the host to perform the ARM semihosting operation in
+//! a0, with a pointer to its arguments in a1.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, warn};
+
+use crate::{console::Console, cpu::Cpu};
+
+const SLLI_X0_1F: u64 = 0x01f01013;
+const SRAI_X0_7: u64 = 0x40705013;
+
+const SYS_OPEN: u64 = 0x01;
+const SYS_CLOSE: u64 = 0x02;
+const SYS_WRITEC: u64 = 0x03;
+const SYS_WRITE0: u64 = 0x04;
+const SYS_WRITE: u64 = 0x05;
+const SYS_READ: u64 = 0x06;
+const SYS_READC: u64 = 0x07;
+const SYS_ISERROR: u64 = 0x08;
+const SYS_ISTTY: u64 = 0x09;
+const SYS_SEEK: u64 = 0x0a;
+const SYS_FLEN: u64 = 0x0c;
+const SYS_REMOVE: u64 = 0x0e;
+const SYS_RENAME: u64 = 0x0f;
+const SYS_CLOCK: u64 = 0x10;
+const SYS_TIME: u64 = 0x11;
+const SYS_ERRNO: u64 = 0x13;
+const SYS_GET_CMDLINE: u64 = 0x15;
+const SYS_HEAPINFO: u64 = 0x16;
+const SYS_EXIT: u64 = 0x18;
+const SYS_EXIT_EXTENDED: u64 = 0x20;
+const SYS_ELAPSED: u64 = 0x30;
+const SYS_TICKFREQ: u64 = 0x31;
+
+/// Reason passed to `SYS_EXIT` for a normal exit, the subcode is the status.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x20026;
+
+/// Handle of an open file, `:tt` opens the console.
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+/// Services semihosting calls against the host.
+pub struct Semihosting {
+    /// Exit code the guest passed, once it asked to stop.
+    pub exit_code: Option<u64>,
+    /// Returned by `SYS_GET_CMDLINE`.
+    pub cmdline: String,
+    output: Box<dyn Write + Send>,
+    handles: Vec<Option<Handle>>,
+    errno: i32,
+    start: Instant,
+}
+
+impl std::fmt::Debug for Semihosting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Semihosting")
+            .field("exit_code", &self.exit_code)
+            .field("cmdline", &self.cmdline)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Semihosting {
+    fn default() -> Self {
+        Self {
+            exit_code: None,
+            cmdline: String::new(),
+            output: Box::new(io::stdout()),
+            handles: Vec::new(),
+            errno: 0,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Semihosting {
+    /// Sends console output to `output` instead of stdout and stderr.
+    pub fn with_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// Whether the `ebreak` at `pc` is part of the semihosting sequence.
+    pub fn is_call(&self, cpu: &mut Cpu, pc: u64) -> bool {
+        cpu.bus.load(pc.wrapping_sub(4), 32) == Ok(SLLI_X0_1F)
+            && cpu.bus.load(pc.wrapping_add(4), 32) == Ok(SRAI_X0_7)
+    }
+
+    /// Performs the operation in a0, leaving the result in a0. Console input
+    /// comes from `console` when given, stdin otherwise.
+    pub fn call(&mut self, cpu: &mut Cpu, console: Option<&Console>) {
+        let op = cpu.regs[10];
+        let params = cpu.regs[11];
+        debug!(op, params, "semihosting");
+
+        cpu.regs[10] = self.operation(cpu, console, op, params).unwrap_or(u64::MAX);
+    }
+
+    /// Returns `None` for failures which report -1 to the guest.
+    fn operation(
+        &mut self,
+        cpu: &mut Cpu,
+        console: Option<&Console>,
+        op: u64,
+        params: u64,
+    ) -> Option<u64> {
+        let mut arg = |i: u64| cpu.bus.load(params + i * 8, 64).ok();
+
+        match op {
+            SYS_OPEN => {
+                let (name, mode, len) = (arg(0)?, arg(1)?, arg(2)?);
+                let name = read_string(cpu, name, len)?;
+                self.open(&name, mode)
+            }
+            SYS_CLOSE => {
+                let handle = arg(0)?;
+                self.handles.get_mut(handle as usize)?.take().map(|_| 0)
+            }
+            SYS_WRITEC => {
+                let c = cpu.bus.load(params, 8).ok()? as u8;
+                self.output.write_all(&[c]).ok()?;
+                self.output.flush().ok().map(|_| 0)
+            }
+            SYS_WRITE0 => {
+                let mut s = Vec::new();
+                loop {
+                    match cpu.bus.load(params + s.len() as u64, 8).ok()? {
+                        0 => break,
+                        c => s.push(c as u8),
+                    }
+                }
+                self.output.write_all(&s).ok()?;
+                self.output.flush().ok().map(|_| 0)
+            }
+            SYS_WRITE => {
+                let (handle, buf, len) = (arg(0)?, arg(1)?, arg(2)?);
+                let data = read_bytes(cpu, buf, len)?;
+                let written = match self.handles.get_mut(handle as usize)? {
+                    Some(Handle::Stdout | Handle::Stderr) => self
+                        .output
+                        .write_all(&data)
+                        .and_then(|_| self.output.flush())
+                        .map(|_| data.len()),
+                    Some(Handle::File(file)) => file.write(&data),
+                    _ => return Some(len),
+                };
+                // The result is the number of bytes not written.
+                Some(len - self.check(written).unwrap_or(0) as u64)
+            }
+            SYS_READ => {
+                let (handle, buf, len) = (arg(0)?, arg(1)?, arg(2)?);
+                let mut data = vec![0; len as usize];
+                let read = match self.handles.get_mut(handle as usize)? {
+                    Some(Handle::Stdin) => read_console(console, &mut data),
+                    Some(Handle::File(file)) => file.read(&mut data),
+                    _ => return Some(len),
+                };
+                let read = self.check(read).unwrap_or(0);
+                write_bytes(cpu, buf, &data[..read])?;
+                Some(len - read as u64)
+            }
+            SYS_READC => {
+                let mut c = [0];
+                let read = read_console(console, &mut c);
+                self.check(read).filter(|n| *n == 1).map(|_| c[0] as u64)
+            }
+            SYS_ISERROR => Some(((arg(0)? as i64) < 0) as u64),
+            SYS_ISTTY => match self.handles.get(arg(0)? as usize)? {
+                Some(Handle::File(_)) => Some(0),
+                Some(_) => Some(1),
+                None => None,
+            },
+            SYS_SEEK => {
+                let (handle, pos) = (arg(0)?, arg(1)?);
+                let Some(Handle::File(file)) = self.handles.get_mut(handle as usize)? else {
+                    return None;
+                };
+                let seek = file.seek(SeekFrom::Start(pos));
+                self.check(seek).map(|_| 0)
+            }
+            SYS_FLEN => {
+                let handle = arg(0)?;
+                let Some(Handle::File(file)) = self.handles.get_mut(handle as usize)? else {
+                    return None;
+                };
+                let metadata = file.metadata();
+                self.check(metadata).map(|x| x.len())
+            }
+            SYS_REMOVE => {
+                let (name, len) = (arg(0)?, arg(1)?);
+                let name = read_string(cpu, name, len)?;
+                match fs::remove_file(name) {
+                    Ok(()) => Some(0),
+                    Err(e) => Some(e.raw_os_error().unwrap_or(-1) as u64),
+                }
+            }
+            SYS_RENAME => {
+                let (from, from_len, to, to_len) = (arg(0)?, arg(1)?, arg(2)?, arg(3)?);
+                let from = read_string(cpu, from, from_len)?;
+                let to = read_string(cpu, to, to_len)?;
+                match fs::rename(from, to) {
+                    Ok(()) => Some(0),
+                    Err(e) => Some(e.raw_os_error().unwrap_or(-1) as u64),
+                }
+            }
+            // Centiseconds since the start of execution.
+            SYS_CLOCK => Some(self.start.elapsed().as_millis() as u64 / 10),
+            SYS_TIME => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|x| x.as_secs()),
+            SYS_ERRNO => Some(self.errno as u64),
+            SYS_GET_CMDLINE => {
+                let (buf, len) = (arg(0)?, arg(1)?);
+                let cmdline = self.cmdline.as_bytes();
+                if cmdline.len() as u64 >= len {
+                    return None;
+                }
+                write_bytes(cpu, buf, cmdline)?;
+                write_bytes(cpu, buf + cmdline.len() as u64, &[0])?;
+                cpu.bus
+                    .store(params + 8, 64, cmdline.len() as u64)
+                    .ok()
+                    .map(|_| 0)
+            }
+            // Zeroes tell the runtime to use its linker defined heap and stack.
+            SYS_HEAPINFO => {
+                let block = arg(0)?;
+                write_bytes(cpu, block, &[0; 32]).map(|_| 0)
+            }
+            SYS_EXIT | SYS_EXIT_EXTENDED => {
+                let (reason, subcode) = (arg(0)?, arg(1)?);
+                self.exit_code = Some(if reason == ADP_STOPPED_APPLICATION_EXIT {
+                    subcode
+                } else {
+                    1
+                });
+                Some(0)
+            }
+            SYS_ELAPSED => {
+                let ticks = self.start.elapsed().as_micros() as u64;
+                write_bytes(cpu, params, &ticks.to_le_bytes()).map(|_| 0)
+            }
+            SYS_TICKFREQ => Some(1_000_000),
+            _ => {
+                warn!(op, "unimplemented semihosting operation");
+                None
+            }
+        }
+    }
+
+    /// Records the errno of a failed host operation.
+    fn check<T>(&mut self, result: io::Result<T>) -> Option<T> {
+        result
+            .map_err(|e| self.errno = e.raw_os_error().unwrap_or(-1))
+            .ok()
+    }
+
+    fn open(&mut self, name: &str, mode: u64) -> Option<u64> {
+        let handle = if name == ":tt" {
+            match mode {
+                0..=3 => Handle::Stdin,
+                4..=7 => Handle::Stdout,
+                _ => Handle::Stderr,
+            }
+        } else {
+            // The modes follow fopen: r, rb, r+, r+b, w, wb, w+, w+b, a, ab, a+, a+b.
+            let mut options = OpenOptions::new();
+            let plus = mode & 2 != 0;
+            match mode >> 2 {
+                0 => options.read(true).write(plus),
+                1 => options.write(true).read(plus).create(true).truncate(true),
+                2 => options.append(true).read(plus).create(true),
+                _ => return None,
+            };
+            let file = options.open(name);
+            Handle::File(self.check(file)?)
+        };
+
+        let index = match self.handles.iter().position(|x| x.is_none()) {
+            Some(index) => index,
+            None => {
+                self.handles.push(None);
+                self.handles.len() - 1
+            }
+        };
+        self.handles[index] = Some(handle);
+        Some(index as u64)
+    }
+}
+
+/// Reads stdin, through `console` when it owns it.
+fn read_console(console: Option<&Console>, buf: &mut [u8]) -> io::Result<usize> {
+    match console {
+        Some(console) => Ok(console.read_some(buf)),
+        None => io::stdin().read(buf),
+    }
+}
+
+fn read_bytes(cpu: &mut Cpu, addr: u64, len: u64) -> Option<Vec<u8>> {
+    (0..len)
+        .map(|i| cpu.bus.load(addr.wrapping_add(i), 8).ok().map(|x| x as u8))
+        .collect()
+}
+
+fn read_string(cpu: &mut Cpu, addr: u64, len: u64) -> Option<String> {
+    read_bytes(cpu, addr, len).map(|x| String::from_utf8_lossy(&x).into_owned())
+}
+
+fn write_bytes(cpu: &mut Cpu, addr: u64, data: &[u8]) -> Option<()> {
+    for (i, x) in data.iter().enumerate() {
+        cpu.bus
+            .store(addr.wrapping_add(i as u64), 8, *x as u64)
+            .ok()?;
+    }
+    Some(())
+}
@@ -0,0 +1,208 @@
+use std::io::{Read, Write};
+
+use crate::{
+    cpu::{Cpu, Mode, INSTRET, RDCYCLE},
+    error::EmulatorError,
+    hpm::Hpm,
+    trigger::{Trigger, TRIGGERS},
+};
+
+const MAGIC: &[u8; 8] = b"RYSKSNAP";
+const VERSION: u32 = 5;
+/// Granularity at which dram is saved, all zero pages are skipped.
+const PAGE_SIZE: usize = 4096;
+
+/// Architectural state of a machine: pc, privilege mode, registers, csrs,
+/// debug triggers and dram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub pc: u64,
+    pub mode: Mode,
+    pub regs: [u64; 32],
+    pub fregs: [u64; 32],
+    /// Contents of the vector registers.
+    pub vregs: Vec<u8>,
+    pub csrs: Vec<u64>,
+    pub triggers: [Trigger; TRIGGERS],
+    /// Size of the dram in bytes.
+    pub memory: u64,
+    /// Non zero dram pages, as (offset, contents).
+    pub pages: Vec<(u64, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn capture(cpu: &Cpu) -> Self {
+        let pages = cpu
+            .bus
+            .dram
+            .dram
+            .chunks(PAGE_SIZE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(|x| *x != 0))
+            .map(|(i, page)| ((i * PAGE_SIZE) as u64, page.to_vec()))
+            .collect();
+
+        Self {
+            pc: cpu.pc,
+            mode: cpu.mode,
+            regs: cpu.regs,
+            fregs: cpu.fregs,
+            vregs: cpu.vector.regs.clone(),
+            csrs: cpu.csrs.to_vec(),
+            triggers: cpu.triggers,
+            memory: cpu.bus.dram.size(),
+            pages,
+        }
+    }
+
+    /// Restores the snapshot into `cpu`, whose dram must have the same size.
+    pub fn restore(&self, cpu: &mut Cpu) -> Result<(), EmulatorError> {
+        if cpu.bus.dram.size() != self.memory {
+            return Err(EmulatorError::InvalidSnapshot(format!(
+                "snapshot has {} bytes of memory but the machine has {}",
+                self.memory,
+                cpu.bus.dram.size()
+            )));
+        }
+
+        if cpu.vector.regs.len() != self.vregs.len() {
+            return Err(EmulatorError::InvalidSnapshot(format!(
+                "snapshot has a vlen of {} bits but the machine has {}",
+                self.vregs.len() / 4,
+                cpu.vector.vlen()
+            )));
+        }
+
+        cpu.pc = self.pc;
+        cpu.mode = self.mode;
+        cpu.regs = self.regs;
+        cpu.fregs = self.fregs;
+        cpu.vector.regs.copy_from_slice(&self.vregs);
+        cpu.csrs.copy_from_slice(&self.csrs);
+        cpu.triggers = self.triggers;
+        cpu.hpm = Hpm::from_csrs(&cpu.csrs);
+        cpu.tlb.flush();
+        // only the counters are saved, which may have been inhibited
+        cpu.cycles = cpu.csrs[RDCYCLE];
+        cpu.retired = cpu.csrs[INSTRET];
+
+        let dram = &mut cpu.bus.dram.dram;
+        dram.fill(0);
+        for (offset, page) in &self.pages {
+            let offset = *offset as usize;
+            dram[offset..offset + page.len()].copy_from_slice(page);
+        }
+        cpu.bus.reservations.clear();
+
+        Ok(())
+    }
+
+    pub fn write_to(&self, mut w: impl Write) -> Result<(), EmulatorError> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.pc.to_le_bytes())?;
+        w.write_all(&(self.mode as u64).to_le_bytes())?;
+        for x in self.regs.iter().chain(&self.fregs).chain(&self.csrs) {
+            w.write_all(&x.to_le_bytes())?;
+        }
+        for trigger in &self.triggers {
+            let control = trigger.control.map_or([0, 0], |x| [1, x]);
+            for x in control.iter().chain([&trigger.address]) {
+                w.write_all(&x.to_le_bytes())?;
+            }
+        }
+        w.write_all(&(self.vregs.len() as u64).to_le_bytes())?;
+        w.write_all(&self.vregs)?;
+        w.write_all(&self.memory.to_le_bytes())?;
+        w.write_all(&(self.pages.len() as u64).to_le_bytes())?;
+        for (offset, page) in &self.pages {
+            w.write_all(&offset.to_le_bytes())?;
+            w.write_all(&(page.len() as u64).to_le_bytes())?;
+            w.write_all(page)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(mut r: impl Read) -> Result<Self, EmulatorError> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(EmulatorError::InvalidSnapshot("bad magic".to_string()));
+        }
+
+        let mut version = [0; 4];
+        r.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != VERSION {
+            return Err(EmulatorError::InvalidSnapshot(format!(
+                "unsupported version {version}"
+            )));
+        }
+
+        let pc = read_u64(&mut r)?;
+        let mode = read_u64(&mut r)?;
+        let mode = Mode::from_bits(mode)
+            .ok_or_else(|| EmulatorError::InvalidSnapshot(format!("invalid mode {mode}")))?;
+        let mut regs = [0; 32];
+        for x in &mut regs {
+            *x = read_u64(&mut r)?;
+        }
+        let mut fregs = [0; 32];
+        for x in &mut fregs {
+            *x = read_u64(&mut r)?;
+        }
+        let csrs = (0..4096)
+            .map(|_| read_u64(&mut r))
+            .collect::<Result<_, _>>()?;
+        let mut triggers = [Trigger::default(); TRIGGERS];
+        for trigger in &mut triggers {
+            let enabled = read_u64(&mut r)? != 0;
+            let control = read_u64(&mut r)?;
+            trigger.control = enabled.then_some(control);
+            trigger.address = read_u64(&mut r)?;
+        }
+        let len = read_u64(&mut r)?;
+        // 32 registers of up to 65536 bits
+        if len > 32 << 13 {
+            return Err(EmulatorError::InvalidSnapshot(format!(
+                "{len} bytes of vector registers"
+            )));
+        }
+        let mut vregs = vec![0; len as usize];
+        r.read_exact(&mut vregs)?;
+        let memory = read_u64(&mut r)?;
+
+        let count = read_u64(&mut r)?;
+        let mut pages = Vec::new();
+        for _ in 0..count {
+            let offset = read_u64(&mut r)?;
+            let len = read_u64(&mut r)?;
+            if offset.checked_add(len).is_none_or(|end| end > memory) {
+                return Err(EmulatorError::InvalidSnapshot(format!(
+                    "page at {offset:#x} is outside memory"
+                )));
+            }
+            let mut page = vec![0; len as usize];
+            r.read_exact(&mut page)?;
+            pages.push((offset, page));
+        }
+
+        Ok(Self {
+            pc,
+            mode,
+            regs,
+            fregs,
+            vregs,
+            csrs,
+            triggers,
+            memory,
+            pages,
+        })
+    }
+}
+
+fn read_u64(r: &mut impl Read) -> Result<u64, EmulatorError> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
@@ -0,0 +1,222 @@
+//! An SPI master with the registers of the SiFive one, and the devices on
+//! its chip selects, as the [`sd`] card. A byte stored to `txdata` is
+//! exchanged at once with the selected device, the byte it sent back waits
+//! in the receive FIFO for the guest to read `rxdata`.
+//!
+//! In the `auto` chip select mode the device is selected for a single byte,
+//! in `hold` it stays selected until the mode or the chip select changes,
+//! and in `off` no device sees the bytes, which read back as `0xff`.
+
+pub mod sd;
+
+use std::{collections::VecDeque, fmt, ops::Range};
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the SPI master is mapped, in a hole of the QEMU virt map.
+pub const SPI_BASE: u64 = 0x1007_0000;
+pub const SPI_SIZE: u64 = 0x1000;
+pub const SPI_IRQ: u32 = 12;
+/// Chip selects, the devices the master may talk to.
+pub const SPI_CS: usize = 4;
+
+/// Registers, by offset. The clock and frame format are kept but don't
+/// change how bytes are exchanged.
+const SCKDIV: u64 = 0x00;
+const SCKMODE: u64 = 0x04;
+const CSID: u64 = 0x10;
+const CSDEF: u64 = 0x14;
+const CSMODE: u64 = 0x18;
+const DELAY0: u64 = 0x28;
+const DELAY1: u64 = 0x2c;
+const FMT: u64 = 0x40;
+const TXDATA: u64 = 0x48;
+const RXDATA: u64 = 0x4c;
+const TXMARK: u64 = 0x50;
+const RXMARK: u64 = 0x54;
+const IE: u64 = 0x70;
+const IP: u64 = 0x74;
+
+const CSMODE_AUTO: u32 = 0;
+const CSMODE_HOLD: u32 = 2;
+
+/// Set in `rxdata` when the FIFO is empty.
+const RX_EMPTY: u64 = 1 << 31;
+const FIFO_DEPTH: usize = 8;
+
+/// `ip` bits: fewer bytes to send than `txmark`, more received than `rxmark`.
+const IP_TXWM: u32 = 1 << 0;
+const IP_RXWM: u32 = 1 << 1;
+
+/// A device on a chip select.
+pub trait SpiDevice: Send {
+    /// Exchanges a byte while selected, returning the one it sends back.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    /// Its chip select went inactive, ending a transaction.
+    fn deselect(&mut self) {}
+}
+
+pub struct Spi {
+    devices: [Option<Box<dyn SpiDevice>>; SPI_CS],
+    sckdiv: u32,
+    sckmode: u32,
+    csid: u32,
+    csdef: u32,
+    csmode: u32,
+    delay: [u32; 2],
+    fmt: u32,
+    txmark: u32,
+    rxmark: u32,
+    ie: u32,
+    rx: VecDeque<u8>,
+    /// The chip select held active, in the `hold` mode.
+    selected: Option<usize>,
+}
+
+impl fmt::Debug for Spi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Spi")
+            .field("csid", &self.csid)
+            .field("csmode", &self.csmode)
+            .field("selected", &self.selected)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Spi {
+    fn default() -> Self {
+        Self {
+            devices: Default::default(),
+            sckdiv: 3,
+            sckmode: 0,
+            csid: 0,
+            csdef: (1 << SPI_CS) - 1,
+            csmode: CSMODE_AUTO,
+            delay: [0x0001_0001, 0x0000_0001],
+            fmt: 0x0008_0000,
+            txmark: 0,
+            rxmark: 0,
+            ie: 0,
+            rx: VecDeque::new(),
+            selected: None,
+        }
+    }
+}
+
+impl Spi {
+    /// Puts `device` on chip select `cs`.
+    pub fn attach(&mut self, cs: usize, device: impl SpiDevice + 'static) {
+        self.devices[cs] = Some(Box::new(device));
+    }
+
+    /// Deselects the device held active, if any.
+    fn release(&mut self) {
+        if let Some(device) = self
+            .selected
+            .take()
+            .and_then(|cs| self.devices[cs].as_mut())
+        {
+            device.deselect();
+        }
+    }
+
+    fn transmit(&mut self, byte: u8) {
+        let cs = self.csid as usize;
+        let received = match self.devices.get_mut(cs).and_then(Option::as_mut) {
+            Some(device) if self.csmode == CSMODE_HOLD => {
+                self.selected = Some(cs);
+                device.transfer(byte)
+            }
+            Some(device) if self.csmode == CSMODE_AUTO => {
+                let received = device.transfer(byte);
+                device.deselect();
+                received
+            }
+            _ => 0xff,
+        };
+        // the guest sends faster than it reads, the oldest byte is lost
+        if self.rx.len() == FIFO_DEPTH {
+            self.rx.pop_front();
+        }
+        self.rx.push_back(received);
+    }
+
+    fn ip(&self) -> u32 {
+        let mut ip = 0;
+        // the transmit FIFO is always empty
+        if self.txmark > 0 {
+            ip |= IP_TXWM;
+        }
+        if self.rx.len() > self.rxmark as usize {
+            ip |= IP_RXWM;
+        }
+        ip
+    }
+}
+
+impl Device for Spi {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let value = match offset {
+            SCKDIV => self.sckdiv,
+            SCKMODE => self.sckmode,
+            CSID => self.csid,
+            CSDEF => self.csdef,
+            CSMODE => self.csmode,
+            DELAY0 => self.delay[0],
+            DELAY1 => self.delay[1],
+            FMT => self.fmt,
+            // never full
+            TXDATA => 0,
+            RXDATA => return Ok(self.rx.pop_front().map_or(RX_EMPTY, u64::from)),
+            TXMARK => self.txmark,
+            RXMARK => self.rxmark,
+            IE => self.ie,
+            IP => self.ip(),
+            _ => 0,
+        };
+        Ok(value.into())
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let value = value as u32;
+        match offset {
+            SCKDIV => self.sckdiv = value & 0xfff,
+            SCKMODE => self.sckmode = value & 0x3,
+            CSID => {
+                if value != self.csid {
+                    self.release();
+                }
+                self.csid = value;
+            }
+            CSDEF => self.csdef = value,
+            CSMODE => {
+                if value & 0x3 != CSMODE_HOLD {
+                    self.release();
+                }
+                self.csmode = value & 0x3;
+            }
+            DELAY0 => self.delay[0] = value,
+            DELAY1 => self.delay[1] = value,
+            FMT => self.fmt = value,
+            TXDATA => self.transmit(value as u8),
+            TXMARK => self.txmark = value & 0x7,
+            RXMARK => self.rxmark = value & 0x7,
+            IE => self.ie = value & (IP_TXWM | IP_RXWM),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "spi"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(SPI_BASE..SPI_BASE + SPI_SIZE)
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        (self.ie & self.ip() != 0).then_some(SPI_IRQ)
+    }
+}
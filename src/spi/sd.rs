@@ -0,0 +1,335 @@
+//! An SD card in SPI mode, backed by a host image read and written in place.
+//! It is a high capacity card, addressed by 512 byte blocks, ready as soon as
+//! the driver asks with `ACMD41`. Single and multiple block reads and writes
+//! are supported, the CRCs the driver sends aren't checked.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use tracing::{debug, warn};
+
+use super::SpiDevice;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// R1 response bits.
+const R1_IDLE: u8 = 1 << 0;
+const R1_ILLEGAL_COMMAND: u8 = 1 << 2;
+const R1_ADDRESS_ERROR: u8 = 1 << 5;
+const R1_PARAMETER_ERROR: u8 = 1 << 6;
+
+/// Data tokens.
+const START_BLOCK: u8 = 0xfe;
+const START_MULTIPLE: u8 = 0xfc;
+const STOP_TRAN: u8 = 0xfd;
+/// Data response of an accepted and of a failed write.
+const DATA_ACCEPTED: u8 = 0x05;
+const DATA_WRITE_ERROR: u8 = 0x0d;
+/// Data error token of a read past the end of the card.
+const OUT_OF_RANGE: u8 = 0x08;
+
+/// Powered up, high capacity, 2.7 to 3.6 volts.
+const OCR: u32 = 0xc0ff_8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for a command.
+    Command,
+    /// Sending blocks from `next` until `CMD12`.
+    ReadMultiple { next: u64 },
+    /// Waiting for the data token of the block to write at `block`.
+    WriteToken { block: u64, multiple: bool },
+    /// Receiving the block, then its CRC.
+    WriteData { block: u64, multiple: bool },
+}
+
+pub struct SdCard {
+    image: File,
+    blocks: u64,
+    idle: bool,
+    /// The next command is an application command.
+    app: bool,
+    state: State,
+    /// The bytes of the command or block being received.
+    input: Vec<u8>,
+    /// What the card sends next, `0xff` once it is empty.
+    output: VecDeque<u8>,
+}
+
+impl fmt::Debug for SdCard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SdCard")
+            .field("blocks", &self.blocks)
+            .field("state", &self.state)
+            .finish_non_exhaustive()
+    }
+}
+
+/// CRC16-CCITT of a data block.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+impl SdCard {
+    /// A card holding the image at `path`, whose size is rounded down to
+    /// whole blocks.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let image = OpenOptions::new().read(true).write(true).open(path)?;
+        let blocks = image.metadata()?.len() / BLOCK_SIZE as u64;
+        Ok(Self {
+            image,
+            blocks,
+            idle: true,
+            app: false,
+            state: State::Command,
+            input: Vec::new(),
+            output: VecDeque::new(),
+        })
+    }
+
+    /// The card specific data, version 2.0.
+    fn csd(&self) -> [u8; 16] {
+        // in units of 512 KiB
+        let size = (self.blocks / 1024).max(1) - 1;
+        let fields: [(u32, u128); 12] = [
+            (126, 1),
+            (112, 0x0e),
+            (96, 0x32),
+            (84, 0x5b5),
+            (80, 9),
+            (48, size.min(0x3f_ffff).into()),
+            (46, 1),
+            (39, 0x7f),
+            (26, 2),
+            (22, 9),
+            (1, 0),
+            (0, 1),
+        ];
+        fields
+            .iter()
+            .fold(0u128, |csd, (lsb, value)| csd | value << lsb)
+            .to_be_bytes()
+    }
+
+    /// The card identification: manufacturer, product `rysk`, revision 1.0.
+    fn cid() -> [u8; 16] {
+        let mut cid = [0; 16];
+        cid[1..3].copy_from_slice(b"RY");
+        cid[3..8].copy_from_slice(b"rysk ");
+        cid[8] = 0x10;
+        cid[15] = 1;
+        cid
+    }
+
+    fn r1(&self, flags: u8) -> u8 {
+        flags | if self.idle { R1_IDLE } else { 0 }
+    }
+
+    /// Queues a data block: the start token, the bytes and their CRC.
+    fn send_block(&mut self, data: &[u8]) {
+        self.output.push_back(START_BLOCK);
+        self.output.extend(data);
+        self.output.extend(crc16(data).to_be_bytes());
+    }
+
+    fn read_block(&mut self, block: u64) -> io::Result<[u8; BLOCK_SIZE]> {
+        let mut data = [0; BLOCK_SIZE];
+        self.image
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.image.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_block(&mut self, block: u64, data: &[u8]) -> io::Result<()> {
+        self.image
+            .seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+        self.image.write_all(data)
+    }
+
+    fn command(&mut self, index: u8, arg: u32) {
+        let app = std::mem::take(&mut self.app);
+        debug!(index, arg, app, "sd command");
+        let block = u64::from(arg);
+        let r1 = match (app, index) {
+            // GO_IDLE_STATE
+            (_, 0) => {
+                self.idle = true;
+                self.r1(0)
+            }
+            // SEND_IF_COND, echoing the voltage and the check pattern
+            (_, 8) => {
+                let r1 = self.r1(0);
+                self.output.push_back(r1);
+                self.output
+                    .extend([0, 0, (arg >> 8) as u8 & 0xf, arg as u8]);
+                return;
+            }
+            // SEND_CSD, SEND_CID
+            (_, 9 | 10) => {
+                let r1 = self.r1(0);
+                self.output.push_back(r1);
+                let data = if index == 9 { self.csd() } else { Self::cid() };
+                return self.send_block(&data);
+            }
+            // STOP_TRANSMISSION, after a stuff byte
+            (_, 12) => {
+                self.state = State::Command;
+                self.output.clear();
+                self.output.push_back(0xff);
+                self.r1(0)
+            }
+            // SEND_STATUS, an R2
+            (_, 13) => {
+                let r1 = self.r1(0);
+                self.output.extend([r1, 0]);
+                return;
+            }
+            // SET_BLOCKLEN, fixed for high capacity cards
+            (_, 16) if arg as usize == BLOCK_SIZE => self.r1(0),
+            (_, 16) => self.r1(R1_PARAMETER_ERROR),
+            // READ_SINGLE_BLOCK, READ_MULTIPLE_BLOCK
+            (_, 17 | 18) if block >= self.blocks => self.r1(R1_ADDRESS_ERROR),
+            (_, 17) => {
+                let r1 = self.r1(0);
+                self.output.push_back(r1);
+                match self.read_block(block) {
+                    Ok(data) => self.send_block(&data),
+                    Err(e) => {
+                        warn!("sd read of block {block}: {e}");
+                        self.output.push_back(OUT_OF_RANGE);
+                    }
+                }
+                return;
+            }
+            (_, 18) => {
+                self.state = State::ReadMultiple { next: block };
+                self.r1(0)
+            }
+            // WRITE_BLOCK, WRITE_MULTIPLE_BLOCK
+            (_, 24 | 25) if block >= self.blocks => self.r1(R1_ADDRESS_ERROR),
+            (_, 24 | 25) => {
+                self.state = State::WriteToken {
+                    block,
+                    multiple: index == 25,
+                };
+                self.r1(0)
+            }
+            // SD_SEND_OP_COND, done at once
+            (true, 41) => {
+                self.idle = false;
+                self.r1(0)
+            }
+            // APP_CMD
+            (_, 55) => {
+                self.app = true;
+                self.r1(0)
+            }
+            // READ_OCR
+            (_, 58) => {
+                let r1 = self.r1(0);
+                self.output.push_back(r1);
+                self.output.extend(OCR.to_be_bytes());
+                return;
+            }
+            // CRC_ON_OFF
+            (_, 59) => self.r1(0),
+            _ => self.r1(R1_ILLEGAL_COMMAND),
+        };
+        self.output.push_back(r1);
+    }
+
+    /// Takes a byte the driver sent.
+    fn receive(&mut self, byte: u8) {
+        match self.state {
+            State::Command | State::ReadMultiple { .. } => {
+                // a command starts with a 0 then a 1 bit
+                if self.input.is_empty() && byte & 0xc0 != 0x40 {
+                    return;
+                }
+                self.input.push(byte);
+                if self.input.len() == 6 {
+                    let arg = u32::from_be_bytes(self.input[1..5].try_into().unwrap());
+                    let index = self.input[0] & 0x3f;
+                    self.input.clear();
+                    self.command(index, arg);
+                }
+            }
+            State::WriteToken { block, multiple } => match byte {
+                START_BLOCK | START_MULTIPLE => self.state = State::WriteData { block, multiple },
+                STOP_TRAN if multiple => {
+                    self.state = State::Command;
+                    // busy for a byte
+                    self.output.push_back(0);
+                }
+                _ => {}
+            },
+            State::WriteData { block, multiple } => {
+                self.input.push(byte);
+                // the block and its CRC
+                if self.input.len() < BLOCK_SIZE + 2 {
+                    return;
+                }
+                let data = std::mem::take(&mut self.input);
+                let response = match self.write_block(block, &data[..BLOCK_SIZE]) {
+                    Ok(()) => DATA_ACCEPTED,
+                    Err(e) => {
+                        warn!("sd write of block {block}: {e}");
+                        DATA_WRITE_ERROR
+                    }
+                };
+                self.output.extend([response, 0]);
+                self.state = match multiple && block + 1 < self.blocks {
+                    true => State::WriteToken {
+                        block: block + 1,
+                        multiple,
+                    },
+                    false => State::Command,
+                };
+            }
+        }
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        if let State::ReadMultiple { next } = self.state {
+            if self.output.is_empty() {
+                match self.read_block(next) {
+                    Ok(data) if next < self.blocks => {
+                        self.send_block(&data);
+                        self.state = State::ReadMultiple { next: next + 1 };
+                    }
+                    _ => {
+                        self.output.push_back(OUT_OF_RANGE);
+                        self.state = State::Command;
+                    }
+                }
+            }
+        }
+        let sent = self.output.pop_front().unwrap_or(0xff);
+        self.receive(byte);
+        sent
+    }
+
+    fn deselect(&mut self) {
+        // a command cut short is dropped
+        if matches!(self.state, State::Command) {
+            self.input.clear();
+        }
+    }
+}
@@ -0,0 +1,342 @@
+//! Run statistics: an observer counting retired instructions, traps and
+//! memory traffic by access width and bus region and csr accesses, which can print a progress
+//! line every so often during long runs and a summary at the end.
+
+use std::{
+    fmt,
+    io::Write,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    bus::Bus,
+    disasm::csr_name,
+    exception::{Exception, Interrupt},
+    instruction::Instruction,
+    observer::{AccessKind, ExecutionObserver, MmioAccess},
+};
+
+/// Csrs listed by the summary, the most accessed first.
+const HOT_CSRS: usize = 10;
+
+/// Instructions between checks of the clock for [`Period::Time`].
+const CLOCK_CHECK: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub instructions: u64,
+    pub interrupts: u64,
+    pub exceptions: u64,
+    pub mmio_reads: u64,
+    pub mmio_writes: u64,
+}
+
+/// Loads and stores to a region, by access width.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Traffic {
+    /// 8, 16, 32 and 64 bit loads.
+    pub loads: [u64; 4],
+    pub stores: [u64; 4],
+    /// Accesses not aligned to their width.
+    pub misaligned: u64,
+}
+
+impl Traffic {
+    pub fn is_empty(&self) -> bool {
+        self.loads.iter().chain(&self.stores).all(|x| *x == 0)
+    }
+}
+
+/// A part of the bus that traffic is counted for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// `dram`, `mmio` or `other` for what falls outside of the known regions.
+    pub name: &'static str,
+    pub base: u64,
+    pub size: u64,
+    pub traffic: Traffic,
+}
+
+impl Region {
+    fn new(name: &'static str, base: u64, size: u64) -> Self {
+        Self {
+            name,
+            base,
+            size,
+            traffic: Traffic::default(),
+        }
+    }
+
+    fn contains(&self, addr: u64) -> bool {
+        addr.wrapping_sub(self.base) < self.size
+    }
+}
+
+/// How often [`Stats`] prints a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Period {
+    Time(Duration),
+    Instructions(u64),
+}
+
+impl FromStr for Period {
+    type Err = String;
+
+    /// Parses seconds such as `10s`, or instructions such as `50M` (millions),
+    /// `1G` or a plain count.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected e.g. 10s or 100M, got {s}");
+        let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let number: u64 = number.parse().map_err(|_| invalid())?;
+        let instructions = |multiplier: u64| {
+            number
+                .checked_mul(multiplier)
+                .map(Period::Instructions)
+                .ok_or_else(invalid)
+        };
+        let period = match unit {
+            "s" => Period::Time(Duration::from_secs(number)),
+            "" => instructions(1)?,
+            "k" | "K" => instructions(1_000)?,
+            "m" | "M" => instructions(1_000_000)?,
+            "g" | "G" => instructions(1_000_000_000)?,
+            _ => return Err(invalid()),
+        };
+        match period {
+            Period::Time(d) if d.is_zero() => Err(invalid()),
+            Period::Instructions(0) => Err(invalid()),
+            period => Ok(period),
+        }
+    }
+}
+
+/// Counts what the cpu does, see [`Stats::periodic`] for progress lines.
+pub struct Stats {
+    pub counters: Counters,
+    /// Devices first as they take precedence over dram, `other` last.
+    pub regions: Vec<Region>,
+    /// Reads and writes of each csr.
+    pub csrs: Vec<[u64; 2]>,
+    started: Instant,
+    report: Option<Report>,
+}
+
+struct Report {
+    period: Period,
+    out: Box<dyn Write + Send>,
+    /// When and at which counts the last line was printed.
+    last: (Instant, Counters),
+}
+
+impl fmt::Debug for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stats")
+            .field("counters", &self.counters)
+            .field("regions", &self.regions.len())
+            .field("period", &self.report.as_ref().map(|x| x.period))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            counters: Counters::default(),
+            regions: vec![Region::new("other", 0, u64::MAX)],
+            csrs: vec![[0; 2]; 4096],
+            started: Instant::now(),
+            report: None,
+        }
+    }
+
+    /// Also writes a progress line to `out` every `period`.
+    pub fn periodic(period: Period, out: impl Write + Send + 'static) -> Self {
+        let stats = Self::new();
+        Self {
+            report: Some(Report {
+                period,
+                out: Box::new(out),
+                last: (stats.started, Counters::default()),
+            }),
+            ..stats
+        }
+    }
+
+    /// Counts the traffic of the dram and each device mapped on `bus`
+    /// separately.
+    pub fn regions(mut self, bus: &Bus) -> Self {
+        let mut regions: Vec<_> = bus
+            .mmio
+            .iter()
+            .map(|x| Region::new("mmio", x.base, x.size))
+            .collect();
+        regions.push(Region::new("dram", bus.dram.base, bus.dram.size()));
+        regions.append(&mut self.regions);
+        self.regions = regions;
+        self
+    }
+
+    /// Time since the observer was created.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Millions of instructions per second since the observer was created.
+    pub fn mips(&self) -> f64 {
+        mips(self.counters.instructions, self.elapsed())
+    }
+
+    /// The `n` most accessed csrs, with their reads and writes.
+    pub fn hot_csrs(&self, n: usize) -> Vec<(usize, [u64; 2])> {
+        let mut csrs: Vec<_> = self
+            .csrs
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, x)| x != &[0, 0])
+            .collect();
+        csrs.sort_by_key(|(csr, [reads, writes])| (std::cmp::Reverse(reads + writes), *csr));
+        csrs.truncate(n);
+        csrs
+    }
+
+    fn poll(&mut self) {
+        let Some(report) = &mut self.report else {
+            return;
+        };
+        let (when, last) = report.last;
+        let executed = self.counters.instructions - last.instructions;
+        let due = match report.period {
+            Period::Instructions(n) => executed >= n,
+            Period::Time(d) => executed.is_multiple_of(CLOCK_CHECK) && when.elapsed() >= d,
+        };
+        if !due {
+            return;
+        }
+
+        let now = Instant::now();
+        let c = &self.counters;
+        let line = writeln!(
+            report.out,
+            "rysk: {:.1}s: {} instructions ({:.2} MIPS), {} interrupts, {} exceptions, {} MMIO reads, {} MMIO writes",
+            (now - self.started).as_secs_f64(),
+            c.instructions,
+            mips(executed, now - when),
+            c.interrupts,
+            c.exceptions,
+            c.mmio_reads,
+            c.mmio_writes
+        )
+        .and_then(|_| report.out.flush());
+        if let Err(e) = line {
+            tracing::warn!("stopped reporting statistics: {e}");
+            self.report = None;
+            return;
+        }
+        report.last = (now, *c);
+    }
+}
+
+impl fmt::Display for Stats {
+    /// Summary of the run, the traffic of regions which were accessed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.counters;
+        writeln!(
+            f,
+            "{} instructions in {:.1}s ({:.2} MIPS), {} interrupts, {} exceptions",
+            c.instructions,
+            self.elapsed().as_secs_f64(),
+            self.mips(),
+            c.interrupts,
+            c.exceptions
+        )?;
+        write!(f, "{:<28}", "region")?;
+        for name in ["lb", "lh", "lw", "ld", "sb", "sh", "sw", "sd"] {
+            write!(f, " {name:>10}")?;
+        }
+        write!(f, " {:>10}", "misaligned")?;
+        for region in self.regions.iter().filter(|x| !x.traffic.is_empty()) {
+            let t = &region.traffic;
+            let name = match region.name {
+                "other" => String::from("other"),
+                name => format!("{name} {:#x}-{:#x}", region.base, region.base + region.size),
+            };
+            write!(f, "\n{name:<28}")?;
+            for count in t.loads.iter().chain(&t.stores) {
+                write!(f, " {count:>10}")?;
+            }
+            write!(f, " {:>10}", t.misaligned)?;
+        }
+
+        let csrs = self.hot_csrs(HOT_CSRS);
+        if !csrs.is_empty() {
+            write!(f, "\n{:<28} {:>10} {:>10}", "csr", "reads", "writes")?;
+        }
+        for (csr, [reads, writes]) in csrs {
+            let name = match csr_name(csr) {
+                Some(name) => format!("{name} ({csr:#05x})"),
+                None => format!("{csr:#05x}"),
+            };
+            write!(f, "\n{name:<28} {reads:>10} {writes:>10}")?;
+        }
+        Ok(())
+    }
+}
+
+fn mips(instructions: u64, elapsed: Duration) -> f64 {
+    instructions as f64 / elapsed.as_secs_f64().max(1e-9) / 1e6
+}
+
+impl ExecutionObserver for Stats {
+    fn on_instruction(&mut self, _pc: u64, _inst: &Instruction) {
+        self.counters.instructions += 1;
+        self.poll();
+    }
+
+    fn on_trap(&mut self, _pc: u64, _exception: &Exception) {
+        self.counters.exceptions += 1;
+    }
+
+    fn on_interrupt(&mut self, _pc: u64, _interrupt: Interrupt) {
+        self.counters.interrupts += 1;
+    }
+
+    fn on_memory(&mut self, addr: u64, size: u64, kind: AccessKind) {
+        let Some(region) = self.regions.iter_mut().find(|x| x.contains(addr)) else {
+            return;
+        };
+        let width = (size / 8).trailing_zeros() as usize;
+        let counts = match kind {
+            AccessKind::Read => &mut region.traffic.loads,
+            AccessKind::Write => &mut region.traffic.stores,
+        };
+        if let Some(count) = counts.get_mut(width) {
+            *count += 1;
+        }
+        if !addr.is_multiple_of(size / 8) {
+            region.traffic.misaligned += 1;
+        }
+    }
+
+    fn on_csr_access(&mut self, csr: usize, _value: u64, kind: AccessKind) {
+        let [reads, writes] = &mut self.csrs[csr];
+        match kind {
+            AccessKind::Read => *reads += 1,
+            AccessKind::Write => *writes += 1,
+        }
+    }
+
+    fn on_mmio(&mut self, access: &MmioAccess) {
+        match access.kind {
+            AccessKind::Read => self.counters.mmio_reads += 1,
+            AccessKind::Write => self.counters.mmio_writes += 1,
+        }
+    }
+}
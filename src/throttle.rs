@@ -0,0 +1,76 @@
+//! Pacing the guest to a clock frequency, one instruction per cycle, so
+//! timer driven code (blinking LEDs, RTOS ticks, timeouts) runs at the speed
+//! it would on hardware instead of as fast as the host allows.
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Falling further behind than this, e.g. after the run was paused, starts
+/// over from now rather than catching up at full speed.
+const MAX_LAG: Duration = Duration::from_millis(100);
+/// How often the pace is checked, sleeping less than this is imprecise.
+const PERIOD: Duration = Duration::from_millis(1);
+
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    hz: u64,
+    /// Instructions between checks, a [`PERIOD`] worth.
+    batch: u64,
+    start: Instant,
+    instructions: u64,
+}
+
+impl Throttle {
+    /// Paces to `hz` instructions per second, which isn't zero.
+    pub fn new(hz: u64) -> Self {
+        Self {
+            hz,
+            batch: (hz as u128 * PERIOD.as_nanos() / 1_000_000_000).max(1) as u64,
+            start: Instant::now(),
+            instructions: 0,
+        }
+    }
+
+    pub fn frequency(&self) -> u64 {
+        self.hz
+    }
+
+    /// Counts an instruction, sleeping when ahead of the clock.
+    pub fn tick(&mut self) {
+        self.instructions += 1;
+        if !self.instructions.is_multiple_of(self.batch) {
+            return;
+        }
+        let due = self.instructions as u128 * 1_000_000_000 / self.hz as u128;
+        let due = Duration::from_nanos(due as u64);
+        let elapsed = self.start.elapsed();
+        if let Some(ahead) = due.checked_sub(elapsed) {
+            thread::sleep(ahead);
+        } else if elapsed - due > MAX_LAG {
+            self.start = Instant::now();
+            self.instructions = 0;
+        }
+    }
+}
+
+/// Parses a frequency such as `50mhz`, `1GHz`, `32768hz` or a plain count
+/// per second.
+pub fn parse_frequency(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, suffix) = s.split_at(split);
+    let unit = match suffix.to_ascii_lowercase().as_str() {
+        "" | "hz" => 1,
+        "k" | "khz" => 1_000,
+        "m" | "mhz" => 1_000_000,
+        "g" | "ghz" => 1_000_000_000,
+        _ => return None,
+    };
+    number
+        .parse::<u64>()
+        .ok()?
+        .checked_mul(unit)
+        .filter(|x| *x > 0)
+}
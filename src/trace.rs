@@ -0,0 +1,346 @@
+//! Machine-readable execution trace for external analyzers, streamed as CBOR
+//! (RFC 8949) items. The stream starts with the header
+//! `{"format": "rysk-trace", "version": 1}` followed by one array per event:
+//!
+//! - `[0, pc, raw]`: the instruction `raw` at `pc` retired
+//! - `[1, pc, cause, tval]`: the instruction at `pc` raised an exception
+//! - `[2, pc, cause]`: an interrupt was taken while about to execute `pc`
+//! - `[3, addr, bits, value, write]`: a device was loaded from or stored to
+//!
+//! New event kinds may be added without bumping the version, readers should
+//! skip arrays with an unknown kind. [`TraceReader`] decodes the stream.
+
+use std::{
+    io::{self, BufWriter, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, SyncSender},
+    thread::{self, JoinHandle},
+};
+
+use tracing::warn;
+
+use crate::{
+    exception::{Exception, Interrupt},
+    instruction::Instruction,
+    observer::{AccessKind, ExecutionObserver, MmioAccess},
+};
+
+pub const TRACE_FORMAT: &str = "rysk-trace";
+pub const TRACE_VERSION: u64 = 1;
+
+/// Encoded events are handed to the writer thread in chunks of this size.
+const CHUNK: usize = 64 * 1024;
+/// Chunks in flight before the machine waits for the writer.
+const QUEUE: usize = 16;
+
+const UNSIGNED: u8 = 0;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+const FALSE: u64 = 20;
+const TRUE: u64 = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    Instruction {
+        pc: u64,
+        raw: u64,
+    },
+    Exception {
+        pc: u64,
+        cause: u64,
+        tval: u64,
+    },
+    Interrupt {
+        pc: u64,
+        cause: u64,
+    },
+    Mmio {
+        addr: u64,
+        bits: u64,
+        value: u64,
+        write: bool,
+    },
+}
+
+impl TraceEvent {
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let fields: &[u64] = match *self {
+            Self::Instruction { pc, raw } => &[0, pc, raw],
+            Self::Exception { pc, cause, tval } => &[1, pc, cause, tval],
+            Self::Interrupt { pc, cause } => &[2, pc, cause],
+            Self::Mmio {
+                addr,
+                bits,
+                value,
+                write,
+            } => {
+                head(buf, ARRAY, 5);
+                for x in [3, addr, bits, value] {
+                    head(buf, UNSIGNED, x);
+                }
+                head(buf, SIMPLE, if write { TRUE } else { FALSE });
+                return;
+            }
+        };
+        head(buf, ARRAY, fields.len() as u64);
+        for x in fields {
+            head(buf, UNSIGNED, *x);
+        }
+    }
+}
+
+/// Writes the initial byte of an item and its argument, in the shortest form.
+fn head(buf: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => buf.push(major | value as u8),
+        24..=0xff => buf.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            buf.push(major | 25);
+            buf.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            buf.push(major | 26);
+            buf.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            buf.push(major | 27);
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn text(buf: &mut Vec<u8>, s: &str) {
+    head(buf, TEXT, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// An observer streaming every event to a writer on a background thread, so
+/// a slow consumer only stalls the machine once the queue is full.
+pub struct TraceStream {
+    buf: Vec<u8>,
+    sender: Option<SyncSender<Vec<u8>>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for TraceStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceStream")
+            .field("buffered", &self.buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl TraceStream {
+    /// Connects to an analyzer listening on `addr`.
+    pub fn connect(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        let (sender, chunks) = mpsc::sync_channel::<Vec<u8>>(QUEUE);
+        let writer = thread::spawn(move || {
+            let mut writer = BufWriter::new(writer);
+            for chunk in chunks {
+                if let Err(e) = writer.write_all(&chunk) {
+                    warn!("trace stream stopped: {e}");
+                    return;
+                }
+            }
+            let _ = writer.flush();
+        });
+
+        let mut buf = Vec::with_capacity(CHUNK);
+        head(&mut buf, MAP, 2);
+        text(&mut buf, "format");
+        text(&mut buf, TRACE_FORMAT);
+        text(&mut buf, "version");
+        head(&mut buf, UNSIGNED, TRACE_VERSION);
+        Self {
+            buf,
+            sender: Some(sender),
+            writer: Some(writer),
+        }
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        event.encode(&mut self.buf);
+        if self.buf.len() >= CHUNK {
+            self.flush();
+        }
+    }
+
+    /// Hands the buffered events to the writer thread.
+    pub fn flush(&mut self) {
+        if self.buf.is_empty() {
+            return;
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK));
+        if let Some(sender) = &self.sender {
+            if sender.send(chunk).is_err() {
+                // The consumer went away, keep running without it.
+                self.sender = None;
+            }
+        }
+    }
+}
+
+impl Drop for TraceStream {
+    /// Flushes the remaining events and waits for them to be written.
+    fn drop(&mut self) {
+        self.flush();
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+impl ExecutionObserver for TraceStream {
+    fn on_instruction(&mut self, pc: u64, inst: &Instruction) {
+        self.record(TraceEvent::Instruction { pc, raw: inst.raw });
+    }
+
+    fn on_trap(&mut self, pc: u64, exception: &Exception) {
+        self.record(TraceEvent::Exception {
+            pc,
+            cause: exception.code(),
+            tval: exception.value(),
+        });
+    }
+
+    fn on_interrupt(&mut self, pc: u64, interrupt: Interrupt) {
+        self.record(TraceEvent::Interrupt {
+            pc,
+            cause: interrupt.code(),
+        });
+    }
+
+    fn on_mmio(&mut self, access: &MmioAccess) {
+        self.record(TraceEvent::Mmio {
+            addr: access.addr,
+            bits: access.size,
+            value: access.value,
+            write: access.kind == AccessKind::Write,
+        });
+    }
+}
+
+/// Decodes a trace stream, as written by [`TraceStream`].
+#[derive(Debug)]
+pub struct TraceReader<R> {
+    reader: R,
+    pub version: u64,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+impl<R: Read> TraceReader<R> {
+    /// Reads and checks the header.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let (major, entries) = read_head(&mut reader)?;
+        if major != MAP {
+            return Err(invalid("missing trace header"));
+        }
+        let (mut format, mut version) = (None, None);
+        for _ in 0..entries {
+            match read_text(&mut reader)?.as_str() {
+                "format" => format = Some(read_text(&mut reader)?),
+                "version" => version = Some(read_unsigned(&mut reader)?),
+                _ => return Err(invalid("unknown trace header field")),
+            }
+        }
+        if format.as_deref() != Some(TRACE_FORMAT) {
+            return Err(invalid("not a rysk trace"));
+        }
+        match version {
+            Some(version @ 1..=TRACE_VERSION) => Ok(Self { reader, version }),
+            _ => Err(invalid("unsupported trace version")),
+        }
+    }
+
+    /// Next event, `None` at the end of the stream. Unknown kinds are skipped.
+    pub fn next_event(&mut self) -> io::Result<Option<TraceEvent>> {
+        loop {
+            let (major, len) = match read_head(&mut self.reader) {
+                Ok(head) => head,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if major != ARRAY || len == 0 {
+                return Err(invalid("expected an event"));
+            }
+            let mut fields = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                fields.push(read_head(&mut self.reader)?);
+            }
+            let event = match fields.as_slice() {
+                [(UNSIGNED, 0), (UNSIGNED, pc), (UNSIGNED, raw)] => {
+                    TraceEvent::Instruction { pc: *pc, raw: *raw }
+                }
+                [(UNSIGNED, 1), (UNSIGNED, pc), (UNSIGNED, cause), (UNSIGNED, tval)] => {
+                    TraceEvent::Exception {
+                        pc: *pc,
+                        cause: *cause,
+                        tval: *tval,
+                    }
+                }
+                [(UNSIGNED, 2), (UNSIGNED, pc), (UNSIGNED, cause)] => TraceEvent::Interrupt {
+                    pc: *pc,
+                    cause: *cause,
+                },
+                [(UNSIGNED, 3), (UNSIGNED, addr), (UNSIGNED, bits), (UNSIGNED, value), (SIMPLE, write @ (FALSE | TRUE))] => {
+                    TraceEvent::Mmio {
+                        addr: *addr,
+                        bits: *bits,
+                        value: *value,
+                        write: *write == TRUE,
+                    }
+                }
+                [(UNSIGNED, 0..=3), ..] => return Err(invalid("malformed event")),
+                _ => continue,
+            };
+            return Ok(Some(event));
+        }
+    }
+}
+
+fn read_head(reader: &mut impl Read) -> io::Result<(u8, u64)> {
+    let mut initial = [0];
+    reader.read_exact(&mut initial)?;
+    let (major, info) = (initial[0] >> 5, initial[0] & 0x1f);
+    let value = match info {
+        0..=23 => info as u64,
+        24..=27 => {
+            let mut bytes = [0; 8];
+            let len = 1 << (info - 24);
+            reader.read_exact(&mut bytes[8 - len..])?;
+            u64::from_be_bytes(bytes)
+        }
+        _ => return Err(invalid("unsupported CBOR item")),
+    };
+    Ok((major, value))
+}
+
+fn read_unsigned(reader: &mut impl Read) -> io::Result<u64> {
+    match read_head(reader)? {
+        (UNSIGNED, value) => Ok(value),
+        _ => Err(invalid("expected an unsigned integer")),
+    }
+}
+
+fn read_text(reader: &mut impl Read) -> io::Result<String> {
+    let (major, len) = read_head(reader)?;
+    if major != TEXT || len > 256 {
+        return Err(invalid("expected a short string"));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("invalid string"))
+}
@@ -0,0 +1,259 @@
+//! The NS16550A UART of the QEMU virt machine, the console of most guests.
+//! What the guest transmits goes to stdout, or wherever
+//! [`set_output`](Uart::set_output) says, and the keystrokes of the machine
+//! console fill the receive FIFO. The line is always clear and transmitting
+//! is instant, so the divisor latch only holds whatever the guest wrote.
+//!
+//! The keystrokes only go to the UART once the guest accessed it, so a guest
+//! reading them through the firmware or HTIF doesn't lose any.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Write},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use tracing::warn;
+
+use crate::{bus::Device, console::Console, exception::Exception};
+
+/// Where QEMU virt maps the UART.
+pub const UART_BASE: u64 = 0x1000_0000;
+pub const UART_SIZE: u64 = 0x100;
+/// The interrupt source of the UART, as on QEMU virt.
+pub const UART_IRQ: u32 = 10;
+
+/// Depth of the receive FIFO.
+const FIFO_SIZE: usize = 16;
+
+/// Registers, by offset. With `LCR.DLAB` set, the first two are the divisor
+/// latch instead.
+const RBR_THR: u64 = 0;
+const IER: u64 = 1;
+const IIR_FCR: u64 = 2;
+const LCR: u64 = 3;
+const MCR: u64 = 4;
+const LSR: u64 = 5;
+const MSR: u64 = 6;
+const SCR: u64 = 7;
+
+/// Interrupts `IER` enables: received data available and transmitter
+/// holding register empty. The line and modem status never change, so
+/// theirs never fire.
+const IER_RDA: u8 = 1 << 0;
+const IER_THRE: u8 = 1 << 1;
+const IER_MASK: u8 = 0xf;
+
+/// Values of `IIR`, from the highest priority, and the bits telling the
+/// FIFOs are enabled.
+const IIR_NONE: u8 = 0x01;
+const IIR_RDA: u8 = 0x04;
+const IIR_THRE: u8 = 0x02;
+const IIR_FIFO: u8 = 0xc0;
+
+/// Fields of `FCR`.
+const FCR_ENABLE: u8 = 1 << 0;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+/// `MCR` loops the transmitter back to the receiver, and its outputs back
+/// to the modem status inputs.
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+/// Fields of `LSR`: data ready, and both the holding register and the
+/// transmitter are empty.
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+
+/// `MSR` with carrier detect, data set ready and clear to send asserted.
+const MSR_CONNECTED: u8 = 0xb0;
+
+struct Inner {
+    rx: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+    fifo: bool,
+    /// The holding register emptied since `IIR` last reported it.
+    thre: bool,
+    /// The guest accessed a register, see the module.
+    used: bool,
+    output: Box<dyn Write + Send>,
+}
+
+impl Inner {
+    fn interrupt(&self) -> u8 {
+        if self.ier & IER_RDA != 0 && !self.rx.is_empty() {
+            IIR_RDA
+        } else if self.ier & IER_THRE != 0 && self.thre {
+            IIR_THRE
+        } else {
+            IIR_NONE
+        }
+    }
+
+    fn transmit(&mut self, c: u8) {
+        if self.mcr & MCR_LOOPBACK != 0 {
+            if self.rx.len() < FIFO_SIZE {
+                self.rx.push_back(c);
+            }
+        } else if let Err(err) = self
+            .output
+            .write_all(&[c])
+            .and_then(|_| self.output.flush())
+        {
+            warn!(%err, "uart output failed");
+        }
+        self.thre = true;
+    }
+}
+
+/// Clones share the registers, the machine keeps one to feed the console
+/// to while another is mapped.
+#[derive(Clone)]
+pub struct Uart {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl fmt::Debug for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Uart")
+            .field("rx", &inner.rx)
+            .field("ier", &inner.ier)
+            .field("lcr", &inner.lcr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                rx: VecDeque::new(),
+                ier: 0,
+                lcr: 0,
+                mcr: 0,
+                scr: 0,
+                divisor: 0,
+                fifo: false,
+                thre: false,
+                used: false,
+                output: Box::new(io::stdout()),
+            })),
+        }
+    }
+}
+
+impl Uart {
+    /// Sends what the guest transmits to `output` instead of stdout.
+    pub fn set_output(&self, output: impl Write + Send + 'static) {
+        self.inner.lock().unwrap().output = Box::new(output);
+    }
+
+    /// Receives `data` as if it came down the line, dropping what doesn't
+    /// fit in the FIFO.
+    pub fn receive(&self, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        let room = FIFO_SIZE - inner.rx.len();
+        inner.rx.extend(data.iter().take(room));
+    }
+
+    /// Moves the keystrokes waiting on `console` into the FIFO while it has
+    /// room, once the guest used the UART.
+    pub fn poll(&self, console: &Console) {
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.used {
+            return;
+        }
+        while inner.rx.len() < FIFO_SIZE {
+            let Some(c) = console.read() else {
+                break;
+            };
+            inner.rx.push_back(c);
+        }
+    }
+}
+
+impl Device for Uart {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.used = true;
+        let dlab = inner.lcr & LCR_DLAB != 0;
+        let value = match offset {
+            RBR_THR if dlab => inner.divisor as u8,
+            IER if dlab => (inner.divisor >> 8) as u8,
+            RBR_THR => inner.rx.pop_front().unwrap_or(0),
+            IER => inner.ier,
+            IIR_FCR => {
+                let interrupt = inner.interrupt();
+                // reading it acknowledges the holding register emptied
+                if interrupt == IIR_THRE {
+                    inner.thre = false;
+                }
+                interrupt | if inner.fifo { IIR_FIFO } else { 0 }
+            }
+            LCR => inner.lcr,
+            MCR => inner.mcr,
+            LSR => LSR_THRE | LSR_TEMT | if inner.rx.is_empty() { 0 } else { LSR_DR },
+            // loopback routes DTR, RTS, OUT1 and OUT2 to DSR, CTS, RI and DCD
+            MSR if inner.mcr & MCR_LOOPBACK != 0 => {
+                let mcr = inner.mcr;
+                (mcr & 0x1) << 5 | (mcr & 0x2) << 3 | (mcr & 0x4) << 4 | (mcr & 0x8) << 4
+            }
+            MSR => MSR_CONNECTED,
+            SCR => inner.scr,
+            _ => 0,
+        };
+        Ok(value.into())
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.used = true;
+        let dlab = inner.lcr & LCR_DLAB != 0;
+        let value = value as u8;
+        match offset {
+            RBR_THR if dlab => inner.divisor = inner.divisor & 0xff00 | u16::from(value),
+            IER if dlab => inner.divisor = inner.divisor & 0xff | u16::from(value) << 8,
+            RBR_THR => inner.transmit(value),
+            IER => {
+                // enabling it reports the holding register empty right away
+                if value & !inner.ier & IER_THRE != 0 {
+                    inner.thre = true;
+                }
+                inner.ier = value & IER_MASK;
+            }
+            IIR_FCR => {
+                inner.fifo = value & FCR_ENABLE != 0;
+                if value & FCR_CLEAR_RX != 0 {
+                    inner.rx.clear();
+                }
+            }
+            LCR => inner.lcr = value,
+            MCR => inner.mcr = value & 0x1f,
+            SCR => inner.scr = value,
+            // LSR and MSR are read only
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "uart"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(UART_BASE..UART_BASE + UART_SIZE)
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        (self.inner.lock().unwrap().interrupt() != IIR_NONE).then_some(UART_IRQ)
+    }
+}
@@ -0,0 +1,802 @@
+//! Linux user mode emulation in the style of qemu-user: a RISC-V ELF program
+//! is loaded into a flat address space and its system calls are serviced by
+//! the host.
+
+use std::{
+    ffi::{CString, OsStr},
+    fs, io,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    bus::Bus,
+    cpu::Cpu,
+    dram::Dram,
+    elf::{Elf, ElfType},
+    error::EmulatorError,
+    exception::Exception,
+    isa::{Isa, Xlen},
+    machine::MiB,
+};
+
+/// Start of the guest address space. Lower addresses are unmapped so null
+/// pointer dereferences fault.
+pub const USER_BASE: u64 = 0x10000;
+pub const PAGE_SIZE: u64 = 4096;
+/// Space reserved at the top of memory for the stack, mmap allocations go below it.
+pub const STACK_SIZE: u64 = 8 * MiB;
+
+const AT_NULL: u64 = 0;
+const AT_PHDR: u64 = 3;
+const AT_PHENT: u64 = 4;
+const AT_PHNUM: u64 = 5;
+const AT_PAGESZ: u64 = 6;
+const AT_BASE: u64 = 7;
+const AT_ENTRY: u64 = 9;
+const AT_RANDOM: u64 = 25;
+const AT_EXECFN: u64 = 31;
+
+/// Host environment variables passed on by [`UserModeBuilder::host_env`],
+/// the rest describe the host rather than the guest.
+pub const HOST_ENV: &[&str] = &[
+    "HOME", "LANG", "LANGUAGE", "LC_ALL", "LC_CTYPE", "LOGNAME", "PATH", "PWD", "SHELL", "TERM",
+    "TZ", "USER",
+];
+
+// System call numbers of the generic syscall table used by riscv64.
+const SYS_GETCWD: u64 = 17;
+const SYS_DUP: u64 = 23;
+const SYS_DUP3: u64 = 24;
+const SYS_FCNTL: u64 = 25;
+const SYS_IOCTL: u64 = 29;
+const SYS_MKDIRAT: u64 = 34;
+const SYS_UNLINKAT: u64 = 35;
+const SYS_FACCESSAT: u64 = 48;
+const SYS_CHDIR: u64 = 49;
+const SYS_OPENAT: u64 = 56;
+const SYS_CLOSE: u64 = 57;
+const SYS_PIPE2: u64 = 59;
+const SYS_GETDENTS64: u64 = 61;
+const SYS_LSEEK: u64 = 62;
+const SYS_READ: u64 = 63;
+const SYS_WRITE: u64 = 64;
+const SYS_READV: u64 = 65;
+const SYS_WRITEV: u64 = 66;
+const SYS_PREAD64: u64 = 67;
+const SYS_PWRITE64: u64 = 68;
+const SYS_READLINKAT: u64 = 78;
+const SYS_NEWFSTATAT: u64 = 79;
+const SYS_FSTAT: u64 = 80;
+const SYS_EXIT: u64 = 93;
+const SYS_EXIT_GROUP: u64 = 94;
+const SYS_SET_TID_ADDRESS: u64 = 96;
+const SYS_FUTEX: u64 = 98;
+const SYS_SET_ROBUST_LIST: u64 = 99;
+const SYS_NANOSLEEP: u64 = 101;
+const SYS_CLOCK_GETTIME: u64 = 113;
+const SYS_KILL: u64 = 129;
+const SYS_TKILL: u64 = 130;
+const SYS_TGKILL: u64 = 131;
+const SYS_SIGALTSTACK: u64 = 132;
+const SYS_RT_SIGACTION: u64 = 134;
+const SYS_RT_SIGPROCMASK: u64 = 135;
+const SYS_UNAME: u64 = 160;
+const SYS_GETTIMEOFDAY: u64 = 169;
+const SYS_GETPID: u64 = 172;
+const SYS_GETPPID: u64 = 173;
+const SYS_GETUID: u64 = 174;
+const SYS_GETEUID: u64 = 175;
+const SYS_GETGID: u64 = 176;
+const SYS_GETEGID: u64 = 177;
+const SYS_GETTID: u64 = 178;
+const SYS_BRK: u64 = 214;
+const SYS_MUNMAP: u64 = 215;
+const SYS_MMAP: u64 = 222;
+const SYS_MPROTECT: u64 = 226;
+const SYS_MADVISE: u64 = 233;
+const SYS_PRLIMIT64: u64 = 261;
+const SYS_GETRANDOM: u64 = 278;
+
+// Flags of the generic ABI which differ on some hosts.
+const O_DIRECTORY: u64 = 0o200000;
+const O_NOFOLLOW: u64 = 0o400000;
+const O_DIRECT: u64 = 0o40000;
+const O_LARGEFILE: u64 = 0o100000;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+const RLIMIT_STACK: u64 = 3;
+
+/// A system call result, the error is a positive errno.
+type SysResult = Result<u64, i32>;
+
+/// Converts the return value of a host call, reading errno on failure.
+fn host(ret: i64) -> SysResult {
+    if ret < 0 {
+        Err(io::Error::last_os_error()
+            .raw_os_error()
+            .unwrap_or(libc::EIO))
+    } else {
+        Ok(ret as u64)
+    }
+}
+
+fn page_align(x: u64) -> u64 {
+    x.next_multiple_of(PAGE_SIZE)
+}
+
+/// A Linux program running on an emulated cpu.
+#[derive(Debug)]
+pub struct UserMode {
+    pub cpu: Cpu,
+    /// Host path of the program, reported through `/proc/self/exe`.
+    exe: PathBuf,
+    sysroot: Option<PathBuf>,
+    brk_start: u64,
+    brk: u64,
+    /// Lowest mmap allocation, the area grows down from below the stack.
+    mmap_bottom: u64,
+}
+
+/// Loads a program for [`UserMode`].
+#[derive(Debug, Clone)]
+pub struct UserModeBuilder {
+    program: PathBuf,
+    memory: u64,
+    isa: Option<String>,
+    sysroot: Option<PathBuf>,
+    /// Arguments after argv[0], which is the program path.
+    args: Vec<Vec<u8>>,
+    env: Vec<Vec<u8>>,
+}
+
+impl UserModeBuilder {
+    /// Size of the guest address space in bytes.
+    pub fn memory(mut self, bytes: u64) -> Self {
+        self.memory = bytes;
+        self
+    }
+
+    pub fn isa(mut self, isa: &str) -> Self {
+        self.isa = Some(isa.to_string());
+        self
+    }
+
+    /// Directory prepended to absolute paths opened by the guest when the
+    /// file exists there, used to find the dynamic linker and libraries.
+    pub fn sysroot(mut self, path: impl Into<PathBuf>) -> Self {
+        self.sysroot = Some(path.into());
+        self
+    }
+
+    /// Appends an argument to argv.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().as_bytes().to_vec());
+        self
+    }
+
+    pub fn args<I>(self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        args.into_iter().fold(self, Self::arg)
+    }
+
+    /// Sets an environment variable of the guest, replacing an earlier value.
+    pub fn env(mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> Self {
+        let key = key.as_ref().as_bytes();
+        self.env
+            .retain(|x| !(x.starts_with(key) && x.get(key.len()) == Some(&b'=')));
+        let mut var = key.to_vec();
+        var.push(b'=');
+        var.extend(value.as_ref().as_bytes());
+        self.env.push(var);
+        self
+    }
+
+    /// Copies the [`HOST_ENV`] variables which are set on the host.
+    pub fn host_env(self) -> Self {
+        HOST_ENV
+            .iter()
+            .filter_map(|key| std::env::var_os(key).map(|value| (key, value)))
+            .fold(self, |this, (key, value)| this.env(key, value))
+    }
+
+    pub fn build(self) -> Result<UserMode, EmulatorError> {
+        let isa = match &self.isa {
+            Some(isa) => isa.parse()?,
+            None => Isa::default(),
+        };
+
+        let data = fs::read(&self.program)?;
+        let elf = Elf::parse(&data)?;
+        if isa.xlen != Xlen::Rv64 || elf.xlen != Xlen::Rv64 {
+            return Err(EmulatorError::Unsupported(
+                "running 32 bit programs in user mode",
+            ));
+        }
+
+        let bus = Bus::new(Dram::with_size(Vec::new(), self.memory).at(USER_BASE));
+        let mut user = UserMode {
+            cpu: Cpu::with_bus(bus, isa),
+            exe: fs::canonicalize(&self.program)?,
+            sysroot: self.sysroot,
+            brk_start: 0,
+            brk: 0,
+            mmap_bottom: (USER_BASE + self.memory).saturating_sub(STACK_SIZE),
+        };
+
+        let bias = match elf.kind {
+            ElfType::Executable => 0,
+            ElfType::Dynamic => USER_BASE,
+        };
+        user.load(&elf, bias)?;
+        user.brk_start = page_align(biased(elf.bounds().1, bias)?);
+        user.brk = user.brk_start;
+        if user.brk > user.mmap_bottom {
+            return Err(EmulatorError::ImageTooLarge {
+                image: user.brk - USER_BASE,
+                memory: self.memory,
+            });
+        }
+
+        let mut entry = biased(elf.entry, bias)?;
+        let mut interp_base = 0;
+        if let Some(interpreter) = &elf.interpreter {
+            let data = fs::read(user.host_path(interpreter.as_bytes()))?;
+            let interp = Elf::parse(&data)?;
+            if interp.kind != ElfType::Dynamic {
+                return Err(EmulatorError::InvalidElf(format!(
+                    "interpreter {interpreter} isn't position independent"
+                )));
+            }
+
+            let (start, end) = interp.bounds();
+            let base = user
+                .mmap_alloc(page_align(end - start))
+                .ok_or(EmulatorError::ImageTooLarge {
+                    image: end - start,
+                    memory: self.memory,
+                })?
+                .checked_sub(start)
+                .ok_or_else(|| {
+                    EmulatorError::InvalidElf(format!(
+                        "interpreter {interpreter} starts above its load address"
+                    ))
+                })?;
+            user.load(&interp, base)?;
+            interp_base = base;
+            entry = biased(interp.entry, base)?;
+        }
+
+        let auxv = [
+            (AT_PHDR, elf.phdr.map_or(Ok(0), |x| biased(x, bias))?),
+            (AT_PHENT, elf.phentsize as u64),
+            (AT_PHNUM, elf.phnum as u64),
+            (AT_PAGESZ, PAGE_SIZE),
+            (AT_BASE, interp_base),
+            (AT_ENTRY, biased(elf.entry, bias)?),
+        ];
+        let mut argv = vec![self.program.as_os_str().as_bytes()];
+        argv.extend(self.args.iter().map(Vec::as_slice));
+        let env = self.env.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        user.setup_stack(&argv, &env, &auxv)?;
+        user.cpu.pc = entry;
+
+        Ok(user)
+    }
+}
+
+/// `addr` of an object loaded at `bias`.
+fn biased(addr: u64, bias: u64) -> Result<u64, EmulatorError> {
+    addr.checked_add(bias).ok_or_else(|| {
+        EmulatorError::InvalidElf(format!("address {addr:#x} overflows once loaded"))
+    })
+}
+
+impl UserMode {
+    pub fn builder(program: impl Into<PathBuf>) -> UserModeBuilder {
+        UserModeBuilder {
+            program: program.into(),
+            memory: 512 * MiB,
+            isa: None,
+            sysroot: None,
+            args: Vec::new(),
+            env: Vec::new(),
+        }
+    }
+
+    /// Runs the program until it exits, returning its exit status.
+    pub fn run(&mut self) -> Result<i32, EmulatorError> {
+        loop {
+            match self.cpu.step() {
+                Ok(()) => {}
+                Err(
+                    Exception::EnvironmentCallFromUMode
+                    | Exception::EnvironmentCallFromSMode
+                    | Exception::EnvironmentCallFromMMode,
+                ) => {
+                    self.cpu.pc += 4;
+                    if let Some(status) = self.syscall() {
+                        return Ok(status);
+                    }
+                }
+                Err(exception) => {
+                    return Err(EmulatorError::UnhandledException {
+                        exception,
+                        pc: self.cpu.pc,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Copies the segments of `elf` to memory, offset by `bias`.
+    fn load(&mut self, elf: &Elf, bias: u64) -> Result<(), EmulatorError> {
+        for segment in &elf.segments {
+            let addr = biased(segment.vaddr, bias)?;
+            let mem = self.guest(addr, segment.memsz).map_err(|_| {
+                EmulatorError::InvalidElf(format!("segment at {addr:#x} is outside memory"))
+            })?;
+            let data = &elf.data[segment.offset as usize..][..segment.filesz as usize];
+            mem[..data.len()].copy_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Lays out argc, argv, envp and auxv at the top of memory as the kernel
+    /// does, adding `AT_RANDOM` and `AT_EXECFN` to `auxv`.
+    fn setup_stack(
+        &mut self,
+        args: &[&[u8]],
+        env: &[&[u8]],
+        auxv: &[(u64, u64)],
+    ) -> Result<(), EmulatorError> {
+        let dram = &self.cpu.bus.dram;
+        let mut sp = dram.base + dram.size();
+        let overflow = || EmulatorError::Unsupported("a stack this large");
+
+        let mut push_str = |this: &mut Self, s: &[u8]| {
+            sp -= s.len() as u64 + 1;
+            let mem = this.guest(sp, s.len() as u64 + 1).map_err(|_| overflow())?;
+            mem[..s.len()].copy_from_slice(s);
+            mem[s.len()] = 0;
+            Ok::<_, EmulatorError>(sp)
+        };
+        // glibc seeds the stack protector and pointer guard from these bytes.
+        let mut random = [0u8; 16];
+        // SAFETY: the buffer is valid for its length.
+        if unsafe { libc::getrandom(random.as_mut_ptr().cast(), random.len(), 0) } != 16 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let random = push_str(self, &random)?;
+        let execfn = push_str(self, args[0])?;
+        let args = args
+            .iter()
+            .map(|x| push_str(self, x))
+            .collect::<Result<Vec<_>, _>>()?;
+        let env = env
+            .iter()
+            .map(|x| push_str(self, x))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut words = vec![args.len() as u64];
+        words.extend(&args);
+        words.push(0);
+        words.extend(&env);
+        words.push(0);
+        for (key, value) in auxv {
+            words.extend([key, value]);
+        }
+        words.extend([AT_RANDOM, random, AT_EXECFN, execfn]);
+        words.extend([AT_NULL, 0]);
+
+        sp = (sp - words.len() as u64 * 8) & !0xf;
+        let mem = self
+            .guest(sp, words.len() as u64 * 8)
+            .map_err(|_| overflow())?;
+        for (chunk, word) in mem.chunks_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        self.cpu.regs[2] = sp;
+
+        Ok(())
+    }
+
+    /// Guest memory at `[addr, addr + len)`.
+    fn guest(&mut self, addr: u64, len: u64) -> Result<&mut [u8], i32> {
+        let dram = &mut self.cpu.bus.dram;
+        let start = addr.checked_sub(dram.base).ok_or(libc::EFAULT)?;
+        let end = start
+            .checked_add(len)
+            .filter(|end| *end <= dram.size())
+            .ok_or(libc::EFAULT)?;
+        Ok(&mut dram.dram[start as usize..end as usize])
+    }
+
+    fn guest_ptr(&mut self, addr: u64, len: u64) -> Result<*mut libc::c_void, i32> {
+        Ok(self.guest(addr, len)?.as_mut_ptr().cast())
+    }
+
+    /// Reads a nul terminated string from guest memory.
+    fn guest_str(&mut self, addr: u64) -> Result<Vec<u8>, i32> {
+        let dram = &self.cpu.bus.dram;
+        let start = addr.checked_sub(dram.base).ok_or(libc::EFAULT)? as usize;
+        let bytes = dram.dram.get(start..).ok_or(libc::EFAULT)?;
+        let len = bytes
+            .iter()
+            .take(libc::PATH_MAX as usize)
+            .position(|x| *x == 0)
+            .ok_or(libc::ENAMETOOLONG)?;
+        Ok(bytes[..len].to_vec())
+    }
+
+    fn write_guest(&mut self, addr: u64, data: &[u8]) -> Result<(), i32> {
+        self.guest(addr, data.len() as u64)?.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_u64(&mut self, addr: u64) -> Result<u64, i32> {
+        Ok(u64::from_le_bytes(self.guest(addr, 8)?.try_into().unwrap()))
+    }
+
+    /// Resolves a guest path against the sysroot.
+    fn host_path(&self, path: &[u8]) -> PathBuf {
+        let path = Path::new(OsStr::from_bytes(path));
+        if let (Some(sysroot), Ok(relative)) = (&self.sysroot, path.strip_prefix("/")) {
+            let candidate = sysroot.join(relative);
+            if candidate.exists() {
+                return candidate;
+            }
+        }
+        path.to_path_buf()
+    }
+
+    fn guest_path(&mut self, addr: u64) -> Result<CString, i32> {
+        let path = self.guest_str(addr)?;
+        let path = self.host_path(&path);
+        CString::new(path.into_os_string().into_vec()).map_err(|_| libc::EINVAL)
+    }
+
+    fn mmap_alloc(&mut self, len: u64) -> Option<u64> {
+        let addr = self.mmap_bottom.checked_sub(len)?;
+        if addr < self.brk {
+            return None;
+        }
+        self.mmap_bottom = addr;
+        Some(addr)
+    }
+
+    /// Services the system call in a7, returning the exit status if the
+    /// program exited.
+    fn syscall(&mut self) -> Option<i32> {
+        let nr = self.cpu.regs[17];
+        let [a0, a1, a2, a3, a4, a5] = self.cpu.regs[10..16].try_into().unwrap();
+        debug!(nr, a0, a1, a2, a3, "syscall");
+
+        let result = match nr {
+            SYS_EXIT | SYS_EXIT_GROUP => return Some(a0 as i32),
+            SYS_KILL | SYS_TKILL | SYS_TGKILL => {
+                let signal = if nr == SYS_TGKILL { a2 } else { a1 };
+                if signal == 0 {
+                    Ok(0)
+                } else {
+                    // Signals aren't delivered, they all terminate the program.
+                    return Some(128 + signal as i32);
+                }
+            }
+            _ => self.dispatch(nr, [a0, a1, a2, a3, a4, a5]),
+        };
+
+        self.cpu.regs[10] = match result {
+            Ok(x) => x,
+            Err(errno) => -(errno as i64) as u64,
+        };
+        None
+    }
+
+    fn dispatch(&mut self, nr: u64, args: [u64; 6]) -> SysResult {
+        let [a0, a1, a2, a3, a4, a5] = args;
+        let fd = a0 as i32 as libc::c_long;
+        let dirfd = fd;
+
+        // SAFETY: pointers passed to the host come from `guest`, so they are in
+        // bounds of dram for the length given to the call.
+        unsafe {
+            match nr {
+                SYS_READ | SYS_WRITE | SYS_PREAD64 | SYS_PWRITE64 => {
+                    let buf = self.guest_ptr(a1, a2)?;
+                    let sysno = match nr {
+                        SYS_READ => libc::SYS_read,
+                        SYS_WRITE => libc::SYS_write,
+                        SYS_PREAD64 => libc::SYS_pread64,
+                        _ => libc::SYS_pwrite64,
+                    };
+                    host(libc::syscall(sysno, fd, buf, a2, a3))
+                }
+                SYS_READV | SYS_WRITEV => {
+                    let mut iov = Vec::new();
+                    for i in 0..a2.min(libc::UIO_MAXIOV as u64) {
+                        let entry = a1.checked_add(i * 16).ok_or(libc::EFAULT)?;
+                        let base = self.read_u64(entry)?;
+                        let len = self.read_u64(entry.checked_add(8).ok_or(libc::EFAULT)?)?;
+                        iov.push(libc::iovec {
+                            iov_base: self.guest_ptr(base, len)?,
+                            iov_len: len as usize,
+                        });
+                    }
+                    let sysno = if nr == SYS_READV {
+                        libc::SYS_readv
+                    } else {
+                        libc::SYS_writev
+                    };
+                    host(libc::syscall(sysno, fd, iov.as_ptr(), iov.len()))
+                }
+                SYS_OPENAT => {
+                    let path = self.guest_path(a1)?;
+                    host(libc::syscall(
+                        libc::SYS_openat,
+                        dirfd,
+                        path.as_ptr(),
+                        open_flags(a2),
+                        a3,
+                    ))
+                }
+                // Keep the host's standard streams open for the emulator's own output.
+                SYS_CLOSE if a0 <= 2 => Ok(0),
+                SYS_CLOSE => host(libc::syscall(libc::SYS_close, fd)),
+                SYS_LSEEK => host(libc::syscall(libc::SYS_lseek, fd, a1, a2)),
+                SYS_DUP => host(libc::syscall(libc::SYS_dup, fd)),
+                SYS_DUP3 => host(libc::syscall(libc::SYS_dup3, fd, a1, open_flags(a2))),
+                SYS_FCNTL => match a1 as i32 {
+                    libc::F_DUPFD
+                    | libc::F_DUPFD_CLOEXEC
+                    | libc::F_GETFD
+                    | libc::F_SETFD
+                    | libc::F_GETFL
+                    | libc::F_SETFL => host(libc::syscall(libc::SYS_fcntl, fd, a1, a2)),
+                    _ => Err(libc::EINVAL),
+                },
+                SYS_IOCTL => {
+                    let size = match a1 {
+                        0x5401..=0x5404 => 36,         // TCGETS, TCSETS{,W,F}
+                        0x5413 | 0x5414 => 8,          // TIOC{G,S}WINSZ
+                        0x540f | 0x5410 | 0x5421 => 4, // TIOC{G,S}PGRP, FIONBIO
+                        _ => return Err(libc::ENOTTY),
+                    };
+                    let arg = self.guest_ptr(a2, size)?;
+                    host(libc::syscall(libc::SYS_ioctl, fd, a1, arg))
+                }
+                SYS_PIPE2 => {
+                    let fds = self.guest_ptr(a0, 8)?;
+                    host(libc::syscall(libc::SYS_pipe2, fds, open_flags(a1)))
+                }
+                SYS_GETDENTS64 => {
+                    let buf = self.guest_ptr(a1, a2)?;
+                    host(libc::syscall(libc::SYS_getdents64, fd, buf, a2))
+                }
+                SYS_MKDIRAT => {
+                    let path = self.guest_path(a1)?;
+                    host(libc::syscall(libc::SYS_mkdirat, dirfd, path.as_ptr(), a2))
+                }
+                SYS_UNLINKAT => {
+                    let path = self.guest_path(a1)?;
+                    host(libc::syscall(libc::SYS_unlinkat, dirfd, path.as_ptr(), a2))
+                }
+                SYS_FACCESSAT => {
+                    let path = self.guest_path(a1)?;
+                    host(libc::syscall(libc::SYS_faccessat, dirfd, path.as_ptr(), a2))
+                }
+                SYS_CHDIR => {
+                    let path = self.guest_path(a0)?;
+                    host(libc::syscall(libc::SYS_chdir, path.as_ptr()))
+                }
+                SYS_GETCWD => {
+                    let buf = self.guest_ptr(a0, a1)?;
+                    host(libc::syscall(libc::SYS_getcwd, buf, a1))
+                }
+                SYS_READLINKAT => {
+                    let path = self.guest_str(a1)?;
+                    if path == b"/proc/self/exe" {
+                        let exe = self.exe.as_os_str().as_bytes().to_vec();
+                        let len = exe.len().min(a3 as usize);
+                        self.write_guest(a2, &exe[..len])?;
+                        Ok(len as u64)
+                    } else {
+                        let path = self.guest_path(a1)?;
+                        let buf = self.guest_ptr(a2, a3)?;
+                        host(libc::syscall(
+                            libc::SYS_readlinkat,
+                            dirfd,
+                            path.as_ptr(),
+                            buf,
+                            a3,
+                        ))
+                    }
+                }
+                SYS_NEWFSTATAT | SYS_FSTAT => {
+                    let mut st: libc::stat = std::mem::zeroed();
+                    if nr == SYS_FSTAT {
+                        host(libc::fstat(fd as i32, &mut st) as i64)?;
+                        self.write_stat(a1, &st)?;
+                    } else {
+                        let path = self.guest_path(a1)?;
+                        host(
+                            libc::fstatat(dirfd as i32, path.as_ptr(), &mut st, a3 as i32) as i64,
+                        )?;
+                        self.write_stat(a2, &st)?;
+                    }
+                    Ok(0)
+                }
+                SYS_CLOCK_GETTIME => {
+                    let ts = self.guest_ptr(a1, 16)?;
+                    host(libc::syscall(libc::SYS_clock_gettime, a0, ts))
+                }
+                SYS_GETTIMEOFDAY => {
+                    let tv = if a0 == 0 {
+                        std::ptr::null_mut()
+                    } else {
+                        self.guest_ptr(a0, 16)?
+                    };
+                    host(libc::syscall(libc::SYS_gettimeofday, tv, 0))
+                }
+                SYS_NANOSLEEP => {
+                    let req = self.guest_ptr(a0, 16)?;
+                    let rem = if a1 == 0 {
+                        std::ptr::null_mut()
+                    } else {
+                        self.guest_ptr(a1, 16)?
+                    };
+                    host(libc::syscall(libc::SYS_nanosleep, req, rem))
+                }
+                SYS_GETRANDOM => {
+                    let buf = self.guest_ptr(a0, a1)?;
+                    host(libc::syscall(libc::SYS_getrandom, buf, a1, a2))
+                }
+                SYS_UNAME => {
+                    let mut buf = [0u8; 65 * 6];
+                    let fields: [&[u8]; 5] = [b"Linux", b"rysk", b"6.1.0", b"#1 SMP", b"riscv64"];
+                    for (i, field) in fields.iter().enumerate() {
+                        buf[i * 65..][..field.len()].copy_from_slice(field);
+                    }
+                    self.write_guest(a0, &buf)?;
+                    Ok(0)
+                }
+                SYS_GETPID | SYS_GETTID | SYS_SET_TID_ADDRESS => Ok(libc::getpid() as u64),
+                SYS_GETPPID => Ok(libc::getppid() as u64),
+                SYS_GETUID => Ok(libc::getuid() as u64),
+                SYS_GETEUID => Ok(libc::geteuid() as u64),
+                SYS_GETGID => Ok(libc::getgid() as u64),
+                SYS_GETEGID => Ok(libc::getegid() as u64),
+                SYS_BRK => {
+                    if a0 >= self.brk_start && a0 <= self.mmap_bottom {
+                        if a0 > self.brk {
+                            let brk = self.brk;
+                            self.guest(brk, a0 - brk)?.fill(0);
+                        }
+                        self.brk = a0;
+                    }
+                    Ok(self.brk)
+                }
+                SYS_MMAP => self.mmap(a0, a1, a3, a4 as i32, a5),
+                // Memory is never given back and protections aren't enforced.
+                SYS_MUNMAP | SYS_MPROTECT | SYS_MADVISE => Ok(0),
+                SYS_FUTEX => match a1 & 0x7f {
+                    // There is only one thread, nobody would wake a waiter.
+                    0 | 9 => Err(libc::EAGAIN),
+                    _ => Ok(0),
+                },
+                SYS_SET_ROBUST_LIST | SYS_SIGALTSTACK => Ok(0),
+                SYS_RT_SIGACTION => {
+                    if a2 != 0 {
+                        self.guest(a2, 32)?.fill(0);
+                    }
+                    Ok(0)
+                }
+                SYS_RT_SIGPROCMASK => {
+                    if a2 != 0 {
+                        self.guest(a2, 8)?.fill(0);
+                    }
+                    Ok(0)
+                }
+                SYS_PRLIMIT64 => {
+                    if a3 != 0 {
+                        let old = if a1 == RLIMIT_STACK {
+                            [STACK_SIZE, libc::RLIM_INFINITY]
+                        } else {
+                            let mut limit: libc::rlimit = std::mem::zeroed();
+                            host(libc::getrlimit(a1 as _, &mut limit) as i64)?;
+                            [limit.rlim_cur, limit.rlim_max]
+                        };
+                        let mut buf = [0; 16];
+                        buf[..8].copy_from_slice(&old[0].to_le_bytes());
+                        buf[8..].copy_from_slice(&old[1].to_le_bytes());
+                        self.write_guest(a3, &buf)?;
+                    }
+                    Ok(0)
+                }
+                _ => {
+                    warn!(nr, "unimplemented syscall");
+                    Err(libc::ENOSYS)
+                }
+            }
+        }
+    }
+
+    fn mmap(&mut self, addr: u64, len: u64, flags: u64, fd: i32, offset: u64) -> SysResult {
+        if len == 0 || !offset.is_multiple_of(PAGE_SIZE) {
+            return Err(libc::EINVAL);
+        }
+        let len = page_align(len);
+
+        let addr = if flags & MAP_FIXED != 0 {
+            if !addr.is_multiple_of(PAGE_SIZE) {
+                return Err(libc::EINVAL);
+            }
+            addr
+        } else {
+            self.mmap_alloc(len).ok_or(libc::ENOMEM)?
+        };
+
+        let mem = self.guest(addr, len).map_err(|_| libc::ENOMEM)?;
+        mem.fill(0);
+        if flags & MAP_ANONYMOUS == 0 {
+            // Mappings are private copies, the file is read once.
+            // SAFETY: `mem` is `len` bytes of dram.
+            host(
+                unsafe { libc::pread(fd, mem.as_mut_ptr().cast(), len as usize, offset as i64) }
+                    as i64,
+            )?;
+        }
+
+        Ok(addr)
+    }
+
+    /// Writes `st` as the generic `struct stat` used by riscv64.
+    // The field types of the host's stat vary between architectures.
+    #[allow(clippy::unnecessary_cast)]
+    fn write_stat(&mut self, addr: u64, st: &libc::stat) -> Result<(), i32> {
+        let mut buf = Vec::with_capacity(128);
+        buf.extend((st.st_dev as u64).to_le_bytes());
+        buf.extend((st.st_ino as u64).to_le_bytes());
+        buf.extend((st.st_mode as u32).to_le_bytes());
+        buf.extend((st.st_nlink as u32).to_le_bytes());
+        buf.extend((st.st_uid as u32).to_le_bytes());
+        buf.extend((st.st_gid as u32).to_le_bytes());
+        buf.extend((st.st_rdev as u64).to_le_bytes());
+        buf.extend(0u64.to_le_bytes());
+        buf.extend((st.st_size as i64).to_le_bytes());
+        buf.extend((st.st_blksize as i32).to_le_bytes());
+        buf.extend(0i32.to_le_bytes());
+        buf.extend((st.st_blocks as i64).to_le_bytes());
+        for (sec, nsec) in [
+            (st.st_atime, st.st_atime_nsec),
+            (st.st_mtime, st.st_mtime_nsec),
+            (st.st_ctime, st.st_ctime_nsec),
+        ] {
+            buf.extend((sec as i64).to_le_bytes());
+            buf.extend((nsec as u64).to_le_bytes());
+        }
+        buf.extend(0u64.to_le_bytes());
+        self.write_guest(addr, &buf)
+    }
+}
+
+/// Translates open flags from the generic ABI to the host's.
+fn open_flags(flags: u64) -> u64 {
+    let mut host = flags & !(O_DIRECTORY | O_NOFOLLOW | O_DIRECT | O_LARGEFILE);
+    for (guest, native) in [
+        (O_DIRECTORY, libc::O_DIRECTORY),
+        (O_NOFOLLOW, libc::O_NOFOLLOW),
+        (O_DIRECT, libc::O_DIRECT),
+        (O_LARGEFILE, libc::O_LARGEFILE),
+    ] {
+        if flags & guest != 0 {
+            host |= native as u64;
+        }
+    }
+    host
+}
@@ -0,0 +1,457 @@
+//! VirtIO devices on the MMIO transport, version 2, in the slots QEMU virt
+//! has for them: slot `n` is mapped at `VIRTIO_BASE + n * VIRTIO_SIZE` and
+//! raises PLIC source `VIRTIO_IRQ + n`.
+//!
+//! A [`VirtioDevice`] only implements what is specific to it, the transport
+//! negotiates the features and sets up the split virtqueues it processes.
+//! The machine keeps a clone of each [`Virtio`] and has them process their
+//! queues each time it polls for interrupts, as they need the dram.
+
+pub mod blk;
+pub mod console;
+pub mod input;
+pub mod net;
+#[cfg(unix)]
+pub mod p9;
+pub mod rng;
+
+use std::{
+    fmt,
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    bus::{Bus, Device, Dma},
+    console::Console,
+    exception::Exception,
+};
+
+pub const VIRTIO_BASE: u64 = 0x1000_1000;
+pub const VIRTIO_SIZE: u64 = 0x1000;
+pub const VIRTIO_IRQ: u32 = 1;
+pub const VIRTIO_SLOTS: u64 = 8;
+
+/// Device ids.
+pub const DEVICE_NET: u32 = 1;
+pub const DEVICE_BLOCK: u32 = 2;
+pub const DEVICE_CONSOLE: u32 = 3;
+pub const DEVICE_RNG: u32 = 4;
+pub const DEVICE_9P: u32 = 9;
+pub const DEVICE_INPUT: u32 = 18;
+
+/// Features every device has: the modern interface.
+const F_VERSION_1: u64 = 1 << 32;
+
+/// Largest queue the driver may set up.
+const QUEUE_SIZE: u16 = 256;
+
+/// Registers, by offset.
+const MAGIC: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const VENDOR_ID: u64 = 0x00c;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const DRIVER_FEATURES: u64 = 0x020;
+const DRIVER_FEATURES_SEL: u64 = 0x024;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM_MAX: u64 = 0x034;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC_LOW: u64 = 0x080;
+const QUEUE_DESC_HIGH: u64 = 0x084;
+const QUEUE_DRIVER_LOW: u64 = 0x090;
+const QUEUE_DRIVER_HIGH: u64 = 0x094;
+const QUEUE_DEVICE_LOW: u64 = 0x0a0;
+const QUEUE_DEVICE_HIGH: u64 = 0x0a4;
+const CONFIG_GENERATION: u64 = 0x0fc;
+const CONFIG: u64 = 0x100;
+
+/// "virt" and "QEMU", little endian.
+const MAGIC_VALUE: u64 = 0x7472_6976;
+const VENDOR_QEMU: u64 = 0x554d_4551;
+
+/// `Status` once the driver is ready to use the device.
+const STATUS_DRIVER_OK: u32 = 1 << 2;
+
+/// `InterruptStatus` bits, a used buffer and a configuration change.
+const INTERRUPT_USED: u32 = 1 << 0;
+const INTERRUPT_CONFIG: u32 = 1 << 1;
+
+/// Descriptor flags.
+const DESC_NEXT: u16 = 1 << 0;
+const DESC_WRITE: u16 = 1 << 1;
+
+/// What a device type implements, the transport does the rest.
+pub trait VirtioDevice: Send {
+    fn device_id(&self) -> u32;
+
+    /// Features of the device, [`F_VERSION_1`] aside.
+    fn features(&self) -> u64 {
+        0
+    }
+
+    /// Number of virtqueues.
+    fn queues(&self) -> usize;
+
+    /// Byte `offset` of the configuration space.
+    fn read_config(&self, _offset: u64) -> u8 {
+        0
+    }
+
+    fn write_config(&mut self, _offset: u64, _value: u8) {}
+
+    /// Uses the buffers the driver made available and the device has data
+    /// for, returning whether it used any. Called while the driver has the
+    /// device running.
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool;
+
+    /// Takes keystrokes from the machine console, for the devices reading
+    /// it. Called while the driver has the device running.
+    fn poll_console(&mut self, _console: &Console) {}
+
+    /// Whether the configuration changed since the last call, which raises
+    /// a configuration change interrupt.
+    fn config_changed(&mut self) -> bool {
+        false
+    }
+
+    /// Goes back to the reset state, when the driver writes 0 to `Status`.
+    fn reset(&mut self) {}
+}
+
+/// A split virtqueue, as the driver set it up.
+#[derive(Debug, Default, Clone)]
+pub struct Queue {
+    num: u16,
+    ready: bool,
+    desc: u64,
+    driver: u64,
+    device: u64,
+    /// Next entry of the available ring to take.
+    last_avail: u16,
+    /// Next entry of the used ring to fill.
+    used: u16,
+}
+
+/// A descriptor chain taken from a [`Queue`].
+#[derive(Debug, Default)]
+pub struct Chain {
+    head: u16,
+    /// Buffers the device reads, then the ones it writes.
+    readable: Vec<(u64, u32)>,
+    writable: Vec<(u64, u32)>,
+}
+
+impl Chain {
+    /// The bytes of the buffers the device reads.
+    pub fn read(&self, dma: &Dma<'_>) -> Result<Vec<u8>, Exception> {
+        let mut data = Vec::new();
+        for &(addr, len) in &self.readable {
+            let start = data.len();
+            data.resize(start + len as usize, 0);
+            dma.read(addr, &mut data[start..])?;
+        }
+        Ok(data)
+    }
+
+    /// Writes as much of `data` as fits into the buffers the device writes,
+    /// returning how much did.
+    pub fn write(&self, dma: &mut Dma<'_>, data: &[u8]) -> Result<usize, Exception> {
+        let mut written = 0;
+        for &(addr, len) in &self.writable {
+            let chunk = &data[written..][..(len as usize).min(data.len() - written)];
+            dma.write(addr, chunk)?;
+            written += chunk.len();
+            if written == data.len() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+
+    /// How many bytes the buffers the device writes hold.
+    pub fn writable_len(&self) -> usize {
+        self.writable.iter().map(|x| x.1 as usize).sum()
+    }
+}
+
+impl Queue {
+    fn load(dma: &Dma<'_>, addr: u64, bytes: usize) -> Result<u64, Exception> {
+        let mut buf = [0; 8];
+        dma.read(addr, &mut buf[..bytes])?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    /// Whether the driver made a buffer available which wasn't taken yet.
+    pub fn has_available(&self, dma: &Dma<'_>) -> bool {
+        self.ready && Self::load(dma, self.driver + 2, 2).is_ok_and(|x| x as u16 != self.last_avail)
+    }
+
+    /// Takes the next descriptor chain the driver made available.
+    pub fn pop(&mut self, dma: &Dma<'_>) -> Option<Chain> {
+        if !self.has_available(dma) {
+            return None;
+        }
+        let slot = u64::from(self.last_avail % self.num);
+        let head = Self::load(dma, self.driver + 4 + 2 * slot, 2).ok()? as u16;
+        self.last_avail = self.last_avail.wrapping_add(1);
+
+        let mut chain = Chain {
+            head,
+            ..Chain::default()
+        };
+        let mut index = head;
+        // a chain is at most as long as the table, even if it loops
+        for _ in 0..self.num {
+            if index >= self.num {
+                warn!(index, "virtio descriptor out of the table");
+                return None;
+            }
+            let desc = self.desc + 16 * u64::from(index);
+            let addr = Self::load(dma, desc, 8).ok()?;
+            let len = Self::load(dma, desc + 8, 4).ok()? as u32;
+            let flags = Self::load(dma, desc + 12, 2).ok()? as u16;
+            match flags & DESC_WRITE {
+                0 => chain.readable.push((addr, len)),
+                _ => chain.writable.push((addr, len)),
+            }
+            if flags & DESC_NEXT == 0 {
+                break;
+            }
+            index = Self::load(dma, desc + 14, 2).ok()? as u16;
+        }
+        Some(chain)
+    }
+
+    /// Gives `chain` back to the driver, with `len` bytes written to it.
+    pub fn push(&mut self, dma: &mut Dma<'_>, chain: Chain, len: usize) {
+        let slot = u64::from(self.used % self.num);
+        let entry = self.device + 4 + 8 * slot;
+        let mut bytes = [0; 8];
+        bytes[..4].copy_from_slice(&u32::from(chain.head).to_le_bytes());
+        bytes[4..].copy_from_slice(&(len as u32).to_le_bytes());
+        self.used = self.used.wrapping_add(1);
+        let written = dma
+            .write(entry, &bytes)
+            .and_then(|_| dma.write(self.device + 2, &self.used.to_le_bytes()));
+        if let Err(e) = written {
+            warn!("virtio used ring outside dram: {e:?}");
+        }
+    }
+}
+
+struct Transport {
+    device: Box<dyn VirtioDevice>,
+    irq: u32,
+    features_sel: u32,
+    driver_features: u64,
+    driver_features_sel: u32,
+    queue_sel: u32,
+    queues: Vec<Queue>,
+    status: u32,
+    interrupt: u32,
+    config_generation: u32,
+}
+
+impl Transport {
+    fn features(&self) -> u64 {
+        self.device.features() | F_VERSION_1
+    }
+
+    fn queue(&mut self) -> Option<&mut Queue> {
+        self.queues.get_mut(self.queue_sel as usize)
+    }
+
+    fn reset(&mut self) {
+        self.device.reset();
+        self.driver_features = 0;
+        self.queues = vec![Queue::default(); self.device.queues()];
+        self.status = 0;
+        self.interrupt = 0;
+    }
+}
+
+/// A [`VirtioDevice`] behind the MMIO transport. Clones share it, the
+/// machine keeps one to process the queues while another is mapped.
+#[derive(Clone)]
+pub struct Virtio {
+    inner: Arc<Mutex<Transport>>,
+    base: u64,
+}
+
+impl fmt::Debug for Virtio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Virtio")
+            .field("base", &self.base)
+            .field("device_id", &inner.device.device_id())
+            .field("status", &inner.status)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Virtio {
+    /// `device` in slot `slot`, see the module.
+    pub fn new(slot: u64, device: impl VirtioDevice + 'static) -> Self {
+        let queues = vec![Queue::default(); device.queues()];
+        Self {
+            inner: Arc::new(Mutex::new(Transport {
+                device: Box::new(device),
+                irq: VIRTIO_IRQ + slot as u32,
+                features_sel: 0,
+                driver_features: 0,
+                driver_features_sel: 0,
+                queue_sel: 0,
+                queues,
+                status: 0,
+                interrupt: 0,
+                config_generation: 0,
+            })),
+            base: VIRTIO_BASE + slot * VIRTIO_SIZE,
+        }
+    }
+
+    /// Has the device use the buffers it can, raising its interrupt if it
+    /// did, once the driver has it running.
+    pub fn process(&self, bus: &mut Bus) {
+        let inner = &mut *self.inner.lock().unwrap();
+        if inner.status & STATUS_DRIVER_OK == 0 {
+            return;
+        }
+        if inner.device.process(&mut inner.queues, &mut bus.dma()) {
+            inner.interrupt |= INTERRUPT_USED;
+        }
+        if inner.device.config_changed() {
+            inner.config_generation = inner.config_generation.wrapping_add(1);
+            inner.interrupt |= INTERRUPT_CONFIG;
+        }
+    }
+
+    /// Has the device take keystrokes from `console`, once the driver has it
+    /// running.
+    pub fn poll_console(&self, console: &Console) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.status & STATUS_DRIVER_OK != 0 {
+            inner.device.poll_console(console);
+        }
+    }
+}
+
+impl Device for Virtio {
+    fn load(&mut self, offset: u64, size: u64) -> Result<u64, Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        if offset >= CONFIG {
+            let offset = offset - CONFIG;
+            let value = (0..size / 8).rev().fold(0, |x, i| {
+                x << 8 | u64::from(inner.device.read_config(offset + i))
+            });
+            return Ok(value);
+        }
+        let value = match offset {
+            MAGIC => MAGIC_VALUE,
+            VERSION => 2,
+            DEVICE_ID => inner.device.device_id().into(),
+            VENDOR_ID => VENDOR_QEMU,
+            DEVICE_FEATURES => match inner.features_sel {
+                0 => inner.features() & 0xffff_ffff,
+                1 => inner.features() >> 32,
+                _ => 0,
+            },
+            QUEUE_NUM_MAX => match inner.queue() {
+                Some(_) => QUEUE_SIZE.into(),
+                None => 0,
+            },
+            QUEUE_READY => inner.queue().is_some_and(|x| x.ready).into(),
+            INTERRUPT_STATUS => inner.interrupt.into(),
+            STATUS => inner.status.into(),
+            CONFIG_GENERATION => inner.config_generation.into(),
+            _ => 0,
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, size: u64, value: u64) -> Result<(), Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        if offset >= CONFIG {
+            for i in 0..size / 8 {
+                inner
+                    .device
+                    .write_config(offset - CONFIG + i, (value >> (8 * i)) as u8);
+            }
+            return Ok(());
+        }
+        let value32 = value as u32;
+        let high = |x: u64| x & 0xffff_ffff | value << 32;
+        let low = |x: u64| x & !0xffff_ffff | value & 0xffff_ffff;
+        match offset {
+            DEVICE_FEATURES_SEL => inner.features_sel = value32,
+            DRIVER_FEATURES => {
+                let features = inner.features();
+                match inner.driver_features_sel {
+                    0 => inner.driver_features = low(inner.driver_features) & features,
+                    1 => inner.driver_features = high(inner.driver_features) & features,
+                    _ => {}
+                }
+            }
+            DRIVER_FEATURES_SEL => inner.driver_features_sel = value32,
+            QUEUE_SEL => inner.queue_sel = value32,
+            QUEUE_NUM => {
+                if let Some(queue) = inner.queue() {
+                    // a power of two, at most the maximum
+                    if value32.is_power_of_two() && value32 <= QUEUE_SIZE.into() {
+                        queue.num = value32 as u16;
+                    }
+                }
+            }
+            QUEUE_READY => {
+                if let Some(queue) = inner.queue() {
+                    queue.ready = value & 1 != 0 && queue.num != 0;
+                }
+            }
+            QUEUE_DESC_LOW | QUEUE_DESC_HIGH | QUEUE_DRIVER_LOW | QUEUE_DRIVER_HIGH
+            | QUEUE_DEVICE_LOW | QUEUE_DEVICE_HIGH => {
+                if let Some(queue) = inner.queue() {
+                    let field = match offset & !4 {
+                        QUEUE_DESC_LOW => &mut queue.desc,
+                        QUEUE_DRIVER_LOW => &mut queue.driver,
+                        _ => &mut queue.device,
+                    };
+                    *field = match offset & 4 {
+                        0 => low(*field),
+                        _ => high(*field),
+                    };
+                }
+            }
+            // the queues are processed as the machine polls anyway
+            QUEUE_NOTIFY => debug!(queue = value, "virtio notify"),
+            INTERRUPT_ACK => inner.interrupt &= !value32,
+            STATUS => match value32 {
+                0 => inner.reset(),
+                _ => inner.status = value32,
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "virtio"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(self.base..self.base + VIRTIO_SIZE)
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        let inner = self.inner.lock().unwrap();
+        (inner.interrupt != 0).then_some(inner.irq)
+    }
+}
@@ -0,0 +1,161 @@
+//! virtio-blk on a host disk image, with its capacity in the configuration
+//! and flushes written through to the image. The image is read and written
+//! in place, a size which isn't a whole number of sectors rounds down.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use tracing::warn;
+
+use super::{Queue, VirtioDevice, DEVICE_BLOCK};
+use crate::bus::Dma;
+
+/// Bytes in a sector, the unit of the requests and of the capacity.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// The device takes flush requests.
+const F_FLUSH: u64 = 1 << 9;
+
+/// Request types.
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+const T_GET_ID: u32 = 8;
+
+/// Request status, the last byte the device writes.
+const S_OK: u8 = 0;
+const S_IOERR: u8 = 1;
+const S_UNSUPP: u8 = 2;
+
+/// `virtio_blk_req` header: the type, a reserved word and the sector.
+const HEADER: usize = 16;
+/// Bytes of the identifier answering `T_GET_ID`.
+const ID_SIZE: usize = 20;
+
+#[derive(Debug)]
+pub struct VirtioBlk {
+    file: File,
+    /// Capacity in sectors.
+    sectors: u64,
+}
+
+impl VirtioBlk {
+    /// A disk holding the image at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let sectors = file.metadata()?.len() / SECTOR_SIZE;
+        Ok(Self { file, sectors })
+    }
+
+    /// Where the `len` bytes at `sector` start in the image, if they are
+    /// inside the disk.
+    fn offset(&self, sector: u64, len: usize) -> Option<u64> {
+        let end = sector.checked_mul(SECTOR_SIZE)?.checked_add(len as u64)?;
+        (end <= self.sectors * SECTOR_SIZE).then_some(sector * SECTOR_SIZE)
+    }
+
+    fn read(&mut self, sector: u64, len: usize) -> io::Result<Vec<u8>> {
+        let offset = self
+            .offset(sector, len)
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        let mut data = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&mut self, sector: u64, data: &[u8]) -> io::Result<()> {
+        let offset = self
+            .offset(sector, data.len())
+            .ok_or(io::ErrorKind::InvalidInput)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(data)
+    }
+
+    /// The data and the status answering the request in `header`, with
+    /// `data` the bytes the driver wrote after it and `writable` how many
+    /// its buffers hold before the status.
+    fn request(&mut self, header: &[u8], data: &[u8], writable: usize) -> (Vec<u8>, u8) {
+        let kind = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let sector = u64::from_le_bytes(header[8..16].try_into().unwrap());
+        let result = match kind {
+            T_IN => self.read(sector, writable),
+            T_OUT => self.write(sector, data).map(|_| Vec::new()),
+            T_FLUSH => self.file.sync_data().map(|_| Vec::new()),
+            T_GET_ID => {
+                let mut id = b"rysk".to_vec();
+                id.resize(ID_SIZE.min(writable), 0);
+                Ok(id)
+            }
+            _ => return (Vec::new(), S_UNSUPP),
+        };
+        match result {
+            Ok(data) => (data, S_OK),
+            Err(e) => {
+                warn!(kind, sector, "virtio-blk request: {e}");
+                (Vec::new(), S_IOERR)
+            }
+        }
+    }
+}
+
+impl VirtioDevice for VirtioBlk {
+    fn device_id(&self) -> u32 {
+        DEVICE_BLOCK
+    }
+
+    fn features(&self) -> u64 {
+        F_FLUSH
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        // the capacity, the only field without a feature of its own
+        self.sectors
+            .to_le_bytes()
+            .get(offset as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[0].pop(dma) {
+            let writable = chain.writable_len();
+            let reply = match chain.read(dma) {
+                Ok(request) if request.len() >= HEADER && writable > 0 => {
+                    let (mut data, status) =
+                        self.request(&request[..HEADER], &request[HEADER..], writable - 1);
+                    // the status goes in the last byte
+                    data.resize(writable - 1, 0);
+                    data.push(status);
+                    data
+                }
+                Ok(_) => {
+                    warn!("virtio-blk request without a header or a status");
+                    Vec::new()
+                }
+                Err(e) => {
+                    warn!("virtio-blk request outside dram: {e:?}");
+                    Vec::new()
+                }
+            };
+            let len = match chain.write(dma, &reply) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("virtio-blk buffer outside dram: {e:?}");
+                    0
+                }
+            };
+            queues[0].push(dma, chain, len);
+            used = true;
+        }
+        used
+    }
+}
@@ -0,0 +1,193 @@
+//! virtio-console with a single port, the `hvc` console of Linux. It reads
+//! the keystrokes of the machine console and writes to stdout as the UART
+//! does, or talks to a client of a TCP socket instead. The emergency write
+//! register of the configuration prints a byte before the queues are set up.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tracing::{debug, warn};
+
+use super::{Queue, VirtioDevice, DEVICE_CONSOLE};
+use crate::{bus::Dma, console::Console};
+
+/// The driver may write bytes to `emerg_wr` in the configuration.
+const F_EMERG_WRITE: u64 = 1 << 2;
+
+/// Offset of `emerg_wr`, past `cols`, `rows` and `max_nr_ports`.
+const EMERG_WR: u64 = 8;
+
+const RX: usize = 0;
+const TX: usize = 1;
+
+/// Keystrokes taken from the machine console ahead of the driver.
+const RX_SIZE: usize = 256;
+
+enum Input {
+    /// The keystrokes of the machine console.
+    Console,
+    /// What the client of the socket sends.
+    Socket(Receiver<Vec<u8>>),
+}
+
+pub struct VirtioConsole {
+    input: Input,
+    output: Box<dyn Write + Send>,
+    /// Received bytes waiting for the driver to make a buffer available.
+    rx: VecDeque<u8>,
+}
+
+impl fmt::Debug for VirtioConsole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtioConsole")
+            .field("rx", &self.rx.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The client connected to the socket, if any.
+#[derive(Clone, Default)]
+struct Client(Arc<Mutex<Option<TcpStream>>>);
+
+impl Write for Client {
+    /// Drops `buf` when no client is connected.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut client = self.0.lock().unwrap();
+        if let Some(stream) = &mut *client {
+            if let Err(e) = stream.write_all(buf) {
+                debug!("virtio-console client gone: {e}");
+                *client = None;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl VirtioConsole {
+    /// A console on the machine console and stdout.
+    pub fn stdio() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// A console on the machine console, writing to `output`.
+    pub fn new(output: impl Write + Send + 'static) -> Self {
+        Self {
+            input: Input::Console,
+            output: Box::new(output),
+            rx: VecDeque::new(),
+        }
+    }
+
+    /// A console for the clients of `listener`, one at a time. What the
+    /// guest writes while none is connected is lost.
+    pub fn listen(listener: TcpListener) -> Self {
+        let (sender, input) = mpsc::channel();
+        let client = Client::default();
+        let output = client.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(x) => x,
+                    Err(e) => return warn!("virtio-console accept: {e}"),
+                };
+                *client.0.lock().unwrap() = stream.try_clone().ok();
+                let mut buf = [0; 256];
+                while let Ok(n @ 1..) = stream.read(&mut buf) {
+                    if sender.send(buf[..n].to_vec()).is_err() {
+                        return;
+                    }
+                }
+                *client.0.lock().unwrap() = None;
+            }
+        });
+        Self {
+            input: Input::Socket(input),
+            output: Box::new(output),
+            rx: VecDeque::new(),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioConsole {
+    fn device_id(&self) -> u32 {
+        DEVICE_CONSOLE
+    }
+
+    fn features(&self) -> u64 {
+        F_EMERG_WRITE
+    }
+
+    fn queues(&self) -> usize {
+        2
+    }
+
+    fn write_config(&mut self, offset: u64, value: u8) {
+        if offset == EMERG_WR {
+            let _ = self.output.write_all(&[value]);
+            let _ = self.output.flush();
+        }
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[TX].pop(dma) {
+            match chain.read(dma) {
+                Ok(data) => {
+                    let _ = self.output.write_all(&data);
+                    let _ = self.output.flush();
+                }
+                Err(e) => warn!("virtio-console buffer outside dram: {e:?}"),
+            }
+            queues[TX].push(dma, chain, 0);
+            used = true;
+        }
+
+        if let Input::Socket(input) = &self.input {
+            self.rx.extend(input.try_iter().flatten());
+        }
+        while !self.rx.is_empty() {
+            let Some(chain) = queues[RX].pop(dma) else {
+                break;
+            };
+            let len = match chain.write(dma, self.rx.make_contiguous()) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("virtio-console buffer outside dram: {e:?}");
+                    0
+                }
+            };
+            self.rx.drain(..len);
+            queues[RX].push(dma, chain, len);
+            used = true;
+        }
+        used
+    }
+
+    fn poll_console(&mut self, console: &Console) {
+        if let Input::Console = self.input {
+            while self.rx.len() < RX_SIZE {
+                let Some(c) = console.read() else {
+                    break;
+                };
+                self.rx.push_back(c);
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.rx.clear();
+    }
+}
@@ -0,0 +1,259 @@
+//! virtio-input, a keyboard and a tablet, an absolute pointer, fed with the
+//! events the host sends through an [`Input`], as those from the viewers of
+//! [`crate::vnc`]. Events wait for the driver to make buffers available,
+//! the oldest ones are dropped past [`MAX_PENDING`].
+
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use super::{Queue, VirtioDevice, DEVICE_INPUT};
+use crate::bus::Dma;
+
+/// Largest value of the axes of the tablet, which spans the whole screen.
+pub const TABLET_MAX: u32 = 0x7fff;
+
+/// Events kept for a driver which is slow to take them.
+pub const MAX_PENDING: usize = 256;
+
+/// What the configuration shows, as selected by the driver.
+const CFG_ID_NAME: u8 = 0x01;
+const CFG_ID_DEVIDS: u8 = 0x03;
+const CFG_EV_BITS: u8 = 0x11;
+const CFG_ABS_INFO: u8 = 0x12;
+/// Offset of the data in the configuration, past select, subsel, size and
+/// padding.
+const CFG_DATA: u64 = 8;
+
+/// Linux event types and codes.
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+
+const BUS_VIRTUAL: u16 = 0x06;
+
+const EVENTQ: usize = 0;
+const STATUSQ: usize = 1;
+
+/// An event of the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A key pressed or released, by its Linux key code.
+    Key { code: u16, down: bool },
+    /// The pointer at `x`, `y`, up to [`TABLET_MAX`], with the `buttons`
+    /// held: left, middle and right from the low bit, then the wheel up and
+    /// down.
+    Pointer { x: u32, y: u32, buttons: u8 },
+}
+
+/// Sends the events of the host to the keyboard and the tablet.
+#[derive(Debug, Clone)]
+pub struct Input {
+    keyboard: Sender<InputEvent>,
+    tablet: Sender<InputEvent>,
+}
+
+impl Input {
+    pub fn send(&self, event: InputEvent) {
+        let device = match event {
+            InputEvent::Key { .. } => &self.keyboard,
+            InputEvent::Pointer { .. } => &self.tablet,
+        };
+        // the machine is gone
+        let _ = device.send(event);
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    Keyboard,
+    /// With the buttons held at the last event.
+    Tablet {
+        buttons: u8,
+    },
+}
+
+#[derive(Debug)]
+pub struct VirtioInput {
+    kind: Kind,
+    events: Receiver<InputEvent>,
+    /// `virtio_input_event`s waiting for a buffer.
+    pending: VecDeque<[u8; 8]>,
+    select: u8,
+    subsel: u8,
+}
+
+/// A keyboard and a tablet, and where to send them events.
+pub fn devices() -> (Input, VirtioInput, VirtioInput) {
+    let (keyboard, keyboard_events) = mpsc::channel();
+    let (tablet, tablet_events) = mpsc::channel();
+    (
+        Input { keyboard, tablet },
+        VirtioInput::new(Kind::Keyboard, keyboard_events),
+        VirtioInput::new(Kind::Tablet { buttons: 0 }, tablet_events),
+    )
+}
+
+fn input_event(kind: u16, code: u16, value: i32) -> [u8; 8] {
+    let mut x = [0; 8];
+    x[..2].copy_from_slice(&kind.to_le_bytes());
+    x[2..4].copy_from_slice(&code.to_le_bytes());
+    x[4..].copy_from_slice(&value.to_le_bytes());
+    x
+}
+
+/// A bitmap with `bits` set.
+fn bitmap(bits: impl IntoIterator<Item = u16>) -> Vec<u8> {
+    let mut x = Vec::new();
+    for bit in bits {
+        let byte = usize::from(bit / 8);
+        if x.len() <= byte {
+            x.resize(byte + 1, 0);
+        }
+        x[byte] |= 1 << (bit % 8);
+    }
+    x
+}
+
+impl VirtioInput {
+    fn new(kind: Kind, events: Receiver<InputEvent>) -> Self {
+        Self {
+            kind,
+            events,
+            pending: VecDeque::new(),
+            select: 0,
+            subsel: 0,
+        }
+    }
+
+    /// What the configuration holds for the current selection.
+    fn config(&self) -> Vec<u8> {
+        let keyboard = matches!(self.kind, Kind::Keyboard);
+        match (self.select, self.subsel) {
+            (CFG_ID_NAME, _) if keyboard => b"rysk keyboard".to_vec(),
+            (CFG_ID_NAME, _) => b"rysk tablet".to_vec(),
+            (CFG_ID_DEVIDS, _) => [BUS_VIRTUAL, 0, 1 + u16::from(!keyboard), 1]
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect(),
+            // every key code below the buttons
+            (CFG_EV_BITS, 1) if keyboard => bitmap(1..0x100),
+            (CFG_EV_BITS, 1) => bitmap([BTN_LEFT, BTN_RIGHT, BTN_MIDDLE]),
+            (CFG_EV_BITS, 2) if !keyboard => bitmap([REL_WHEEL]),
+            (CFG_EV_BITS, 3) if !keyboard => bitmap([ABS_X, ABS_Y]),
+            // min, max, fuzz, flat and resolution
+            (CFG_ABS_INFO, 0 | 1) if !keyboard => [0, TABLET_MAX, 0, 0, 0]
+                .iter()
+                .flat_map(|x| x.to_le_bytes())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn push(&mut self, event: InputEvent) {
+        match (&mut self.kind, event) {
+            (Kind::Keyboard, InputEvent::Key { code, down }) => {
+                self.pending.push_back(key_event(code, down));
+            }
+            (Kind::Tablet { buttons: held }, InputEvent::Pointer { x, y, buttons }) => {
+                let changed = *held ^ buttons;
+                *held = buttons;
+                self.pending.push_back(input_event(EV_ABS, ABS_X, x as i32));
+                self.pending.push_back(input_event(EV_ABS, ABS_Y, y as i32));
+                for (bit, code) in [(0, BTN_LEFT), (1, BTN_MIDDLE), (2, BTN_RIGHT)] {
+                    if changed & 1 << bit != 0 {
+                        self.pending
+                            .push_back(key_event(code, buttons & 1 << bit != 0));
+                    }
+                }
+                // the wheel turns as its buttons are pressed
+                for (bit, value) in [(3, 1), (4, -1)] {
+                    if changed & buttons & 1 << bit != 0 {
+                        self.pending
+                            .push_back(input_event(EV_REL, REL_WHEEL, value));
+                    }
+                }
+            }
+            _ => return,
+        }
+        self.pending.push_back(input_event(EV_SYN, SYN_REPORT, 0));
+        while self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+    }
+}
+
+fn key_event(code: u16, down: bool) -> [u8; 8] {
+    input_event(EV_KEY, code, down.into())
+}
+
+impl VirtioDevice for VirtioInput {
+    fn device_id(&self) -> u32 {
+        DEVICE_INPUT
+    }
+
+    fn queues(&self) -> usize {
+        2
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        match offset {
+            0 => self.select,
+            1 => self.subsel,
+            2 => self.config().len() as u8,
+            CFG_DATA.. => self
+                .config()
+                .get((offset - CFG_DATA) as usize)
+                .copied()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    fn write_config(&mut self, offset: u64, value: u8) {
+        match offset {
+            0 => self.select = value,
+            1 => self.subsel = value,
+            _ => {}
+        }
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        // the leds of the keyboard, which aren't shown
+        while let Some(chain) = queues[STATUSQ].pop(dma) {
+            queues[STATUSQ].push(dma, chain, 0);
+            used = true;
+        }
+
+        while let Ok(event) = self.events.try_recv() {
+            self.push(event);
+        }
+        while !self.pending.is_empty() {
+            let Some(chain) = queues[EVENTQ].pop(dma) else {
+                break;
+            };
+            let event = self.pending.pop_front().unwrap();
+            let len = chain.write(dma, &event).unwrap_or(0);
+            queues[EVENTQ].push(dma, chain, len);
+            used = true;
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.pending.clear();
+        if let Kind::Tablet { buttons } = &mut self.kind {
+            *buttons = 0;
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! virtio-net on a port of a [`Hub`](crate::net::Hub), with the MAC address
+//! in its configuration and no offloads.
+
+use tracing::warn;
+
+use super::{Queue, VirtioDevice, DEVICE_NET};
+use crate::{
+    bus::Dma,
+    net::{Port, MAX_FRAME},
+};
+
+/// The device has a MAC address in its configuration.
+const F_MAC: u64 = 1 << 5;
+
+/// `virtio_net_hdr` of the modern interface, in front of every frame.
+const HEADER: usize = 12;
+
+const RX: usize = 0;
+const TX: usize = 1;
+
+#[derive(Debug)]
+pub struct VirtioNet {
+    mac: [u8; 6],
+    port: Port,
+    /// Received frame waiting for the driver to make a buffer available.
+    rx: Option<Vec<u8>>,
+}
+
+impl VirtioNet {
+    pub fn new(port: Port, mac: [u8; 6]) -> Self {
+        Self {
+            mac,
+            port,
+            rx: None,
+        }
+    }
+}
+
+impl VirtioDevice for VirtioNet {
+    fn device_id(&self) -> u32 {
+        DEVICE_NET
+    }
+
+    fn features(&self) -> u64 {
+        F_MAC
+    }
+
+    fn queues(&self) -> usize {
+        2
+    }
+
+    fn read_config(&self, offset: u64) -> u8 {
+        self.mac.get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[TX].pop(dma) {
+            match chain.read(dma) {
+                Ok(data) if data.len() >= HEADER && data.len() - HEADER <= MAX_FRAME => {
+                    self.port.send(&data[HEADER..]);
+                }
+                Ok(data) => warn!(len = data.len(), "virtio-net frame of a wrong size"),
+                Err(e) => warn!("virtio-net frame outside dram: {e:?}"),
+            }
+            queues[TX].push(dma, chain, 0);
+            used = true;
+        }
+
+        loop {
+            if self.rx.is_none() {
+                self.rx = self.port.recv();
+            }
+            let Some(frame) = &self.rx else {
+                break;
+            };
+            let Some(chain) = queues[RX].pop(dma) else {
+                break;
+            };
+            // a single buffer
+            let mut data = vec![0; HEADER];
+            data[10] = 1;
+            data.extend_from_slice(frame);
+            let len = match chain.write(dma, &data) {
+                Ok(len) if len == data.len() => len,
+                Ok(_) => {
+                    warn!(len = frame.len(), "virtio-net receive buffer too small");
+                    0
+                }
+                Err(e) => {
+                    warn!("virtio-net receive buffer outside dram: {e:?}");
+                    0
+                }
+            };
+            queues[RX].push(dma, chain, len);
+            self.rx = None;
+            used = true;
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.rx = None;
+    }
+}
@@ -0,0 +1,671 @@
+//! virtio-9p sharing a host directory over 9P2000.L, which Linux mounts with
+//! `mount -t 9p -o trans=virtio TAG DIR`. Requests are answered as they are
+//! taken, so a flush never has anything left to cancel.
+//!
+//! Walks stay within the directory, `..` stops at its root, but symbolic
+//! links are followed by the host wherever they point, as with QEMU's
+//! `security_model=none`. Files belong to the user running rysk, the owners
+//! the guest asks for are ignored, and there are no extended attributes.
+
+use std::{
+    collections::HashMap,
+    ffi::CString,
+    fs::{self, DirBuilder, File, FileTimes, Metadata, OpenOptions, Permissions},
+    io,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+    },
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{debug, warn};
+
+use super::{Queue, VirtioDevice, DEVICE_9P};
+use crate::bus::Dma;
+
+/// The configuration has the mount tag.
+const F_MOUNT_TAG: u64 = 1 << 0;
+
+const VERSION: &str = "9P2000.L";
+/// Largest message the device takes, which bounds reads and writes.
+const MAX_MSIZE: u32 = 0x8_0000;
+/// Size, type and tag of a message, and the count of a read.
+const HEADER: usize = 7;
+const IO_HEADER: u32 = HEADER as u32 + 4;
+
+/// Requests, the reply to each is the next type.
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TRENAME: u8 = 20;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLOCK: u8 = 52;
+const TGETLOCK: u8 = 54;
+const TLINK: u8 = 70;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TFLUSH: u8 = 108;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+const TREMOVE: u8 = 122;
+
+/// Types of a qid.
+const QT_DIR: u8 = 0x80;
+const QT_SYMLINK: u8 = 0x02;
+const QT_FILE: u8 = 0;
+
+/// The fields of `Tgetattr` always filled, everything but the birth time,
+/// generation and data version.
+const GETATTR_BASIC: u64 = 0x7ff;
+
+/// Fields of `Tsetattr`.
+const SETATTR_MODE: u32 = 1 << 0;
+const SETATTR_SIZE: u32 = 1 << 3;
+const SETATTR_ATIME: u32 = 1 << 4;
+const SETATTR_MTIME: u32 = 1 << 5;
+const SETATTR_ATIME_SET: u32 = 1 << 7;
+const SETATTR_MTIME_SET: u32 = 1 << 8;
+
+/// Flags of `Tlopen` and `Tlcreate`, as Linux numbers them.
+const O_ACCMODE: u32 = 3;
+const O_WRONLY: u32 = 1;
+const O_RDWR: u32 = 2;
+const O_EXCL: u32 = 0o200;
+const O_TRUNC: u32 = 0o1000;
+const O_APPEND: u32 = 0o2000;
+
+const AT_REMOVEDIR: u32 = 0x200;
+
+/// `Rgetlock` says nothing holds the lock.
+const LOCK_UNLOCKED: u8 = 2;
+
+/// `statfs` type of a 9p mount.
+const V9FS_MAGIC: u32 = 0x0102_1997;
+
+/// Linux error numbers, which 9P2000.L replies with.
+type Errno = u32;
+const EIO: Errno = 5;
+const EBADF: Errno = 9;
+const EINVAL: Errno = 22;
+const EOPNOTSUPP: Errno = 95;
+
+fn errno(e: io::Error) -> Errno {
+    e.raw_os_error().map_or(EIO, |x| x as Errno)
+}
+
+/// A file the guest walked to, by its path in the directory.
+#[derive(Debug, Default)]
+struct Fid {
+    path: PathBuf,
+    file: Option<File>,
+    /// The entries of a directory being read, kept from the first read so
+    /// the offsets stay valid.
+    entries: Option<Vec<Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    qid: [u8; 13],
+    kind: u8,
+    name: Vec<u8>,
+}
+
+/// The fields of a request, in order.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], Errno> {
+        if self.0.len() < n {
+            return Err(EINVAL);
+        }
+        let (bytes, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Errno> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Errno> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, Errno> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, Errno> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<&'a str, Errno> {
+        let len = self.u16()?.into();
+        std::str::from_utf8(self.bytes(len)?).map_err(|_| EINVAL)
+    }
+}
+
+/// The fields of a reply.
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, x: u8) -> &mut Self {
+        self.0.push(x);
+        self
+    }
+
+    fn u16(&mut self, x: u16) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, x: u32) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, x: u64) -> &mut Self {
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, x: &[u8]) -> &mut Self {
+        self.u16(x.len() as u16);
+        self.0.extend_from_slice(x);
+        self
+    }
+
+    fn bytes(&mut self, x: &[u8]) -> &mut Self {
+        self.0.extend_from_slice(x);
+        self
+    }
+}
+
+fn qid(meta: &Metadata) -> [u8; 13] {
+    let kind = match meta.file_type() {
+        x if x.is_dir() => QT_DIR,
+        x if x.is_symlink() => QT_SYMLINK,
+        _ => QT_FILE,
+    };
+    let mut qid = [0; 13];
+    qid[0] = kind;
+    // the version changes with the file
+    qid[1..5].copy_from_slice(&(meta.mtime() as u32 ^ meta.mtime_nsec() as u32).to_le_bytes());
+    qid[5..].copy_from_slice(&meta.ino().to_le_bytes());
+    qid
+}
+
+/// `name` in `dir`, if it names a single entry.
+fn child(dir: &Path, name: &str) -> Result<PathBuf, Errno> {
+    match name {
+        "" | "." | ".." => Err(EINVAL),
+        _ if name.contains('/') => Err(EINVAL),
+        _ => Ok(dir.join(name)),
+    }
+}
+
+fn time(sec: u64, nsec: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::new(sec, nsec as u32)
+}
+
+#[derive(Debug)]
+pub struct Virtio9p {
+    root: PathBuf,
+    tag: String,
+    msize: u32,
+    fids: HashMap<u32, Fid>,
+}
+
+impl Virtio9p {
+    /// Shares `root` with the guest, which mounts it by `tag`.
+    pub fn new(tag: &str, root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            tag: tag.to_string(),
+            msize: MAX_MSIZE,
+            fids: HashMap::new(),
+        }
+    }
+
+    fn host(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn fid(&mut self, fid: u32) -> Result<&mut Fid, Errno> {
+        self.fids.get_mut(&fid).ok_or(EBADF)
+    }
+
+    /// The path of `fid`, on the host.
+    fn fid_host(&mut self, fid: u32) -> Result<PathBuf, Errno> {
+        let path = self.fid(fid)?.path.clone();
+        Ok(self.host(&path))
+    }
+
+    fn stat(&self, path: &Path) -> Result<Metadata, Errno> {
+        fs::symlink_metadata(self.host(path)).map_err(errno)
+    }
+
+    /// Answers `request`, a whole message.
+    fn handle(&mut self, request: &[u8]) -> Vec<u8> {
+        let mut r = Reader(request);
+        let header = (r.u32(), r.u8(), r.u16());
+        let (Ok(_), Ok(kind), Ok(tag)) = header else {
+            return Vec::new();
+        };
+        let mut w = Writer::default();
+        let kind = match self.dispatch(kind, &mut r, &mut w) {
+            Ok(()) => kind + 1,
+            Err(e) => {
+                debug!(kind, errno = e, "9p error");
+                w = Writer::default();
+                w.u32(e);
+                RLERROR
+            }
+        };
+        let mut reply = Writer::default();
+        reply.u32((HEADER + w.0.len()) as u32).u8(kind).u16(tag);
+        reply.0.extend_from_slice(&w.0);
+        reply.0
+    }
+
+    fn dispatch(&mut self, kind: u8, r: &mut Reader, w: &mut Writer) -> Result<(), Errno> {
+        match kind {
+            TVERSION => {
+                let msize = r.u32()?;
+                let version = r.str()?;
+                self.msize = msize.clamp(IO_HEADER + 1, MAX_MSIZE);
+                self.fids.clear();
+                let version = match version.starts_with(VERSION) {
+                    true => VERSION,
+                    false => "unknown",
+                };
+                w.u32(self.msize).str(version.as_bytes());
+            }
+            TATTACH => {
+                let fid = r.u32()?;
+                let meta = self.stat(Path::new(""))?;
+                self.fids.insert(fid, Fid::default());
+                w.bytes(&qid(&meta));
+            }
+            TWALK => {
+                let (fid, newfid, names) = (r.u32()?, r.u32()?, r.u16()?);
+                let mut path = self.fid(fid)?.path.clone();
+                let mut qids = Vec::new();
+                for _ in 0..names {
+                    match r.str()? {
+                        "." => {}
+                        ".." => _ = path.pop(),
+                        name => path = child(&path, name)?,
+                    }
+                    match self.stat(&path) {
+                        Ok(meta) => qids.push(qid(&meta)),
+                        Err(e) if qids.is_empty() => return Err(e),
+                        // the qids of the names that exist
+                        Err(_) => break,
+                    }
+                }
+                if qids.len() == usize::from(names) {
+                    let fid = Fid {
+                        path,
+                        ..Fid::default()
+                    };
+                    self.fids.insert(newfid, fid);
+                }
+                w.u16(qids.len() as u16);
+                for qid in qids {
+                    w.bytes(&qid);
+                }
+            }
+            TGETATTR => {
+                let fid = r.u32()?;
+                let meta = fs::symlink_metadata(self.fid_host(fid)?).map_err(errno)?;
+                w.u64(GETATTR_BASIC)
+                    .bytes(&qid(&meta))
+                    .u32(meta.mode())
+                    .u32(meta.uid())
+                    .u32(meta.gid())
+                    .u64(meta.nlink())
+                    .u64(meta.rdev())
+                    .u64(meta.size())
+                    .u64(meta.blksize())
+                    .u64(meta.blocks())
+                    .u64(meta.atime() as u64)
+                    .u64(meta.atime_nsec() as u64)
+                    .u64(meta.mtime() as u64)
+                    .u64(meta.mtime_nsec() as u64)
+                    .u64(meta.ctime() as u64)
+                    .u64(meta.ctime_nsec() as u64)
+                    // birth time, generation and data version
+                    .bytes(&[0; 32]);
+            }
+            TSETATTR => {
+                let (fid, valid, mode) = (r.u32()?, r.u32()?, r.u32()?);
+                let (_uid, _gid, size) = (r.u32()?, r.u32()?, r.u64()?);
+                let atime = time(r.u64()?, r.u64()?);
+                let mtime = time(r.u64()?, r.u64()?);
+                let path = self.fid_host(fid)?;
+                if valid & SETATTR_MODE != 0 {
+                    fs::set_permissions(&path, Permissions::from_mode(mode & 0o7777))
+                        .map_err(errno)?;
+                }
+                if valid & SETATTR_SIZE != 0 {
+                    let file = OpenOptions::new().write(true).open(&path).map_err(errno)?;
+                    file.set_len(size).map_err(errno)?;
+                }
+                if valid & (SETATTR_ATIME | SETATTR_MTIME) != 0 {
+                    let now = SystemTime::now();
+                    let mut times = FileTimes::new();
+                    if valid & SETATTR_ATIME != 0 {
+                        times = times.set_accessed(match valid & SETATTR_ATIME_SET {
+                            0 => now,
+                            _ => atime,
+                        });
+                    }
+                    if valid & SETATTR_MTIME != 0 {
+                        times = times.set_modified(match valid & SETATTR_MTIME_SET {
+                            0 => now,
+                            _ => mtime,
+                        });
+                    }
+                    File::open(&path)
+                        .and_then(|x| x.set_times(times))
+                        .map_err(errno)?;
+                }
+            }
+            TLOPEN => {
+                let (fid, flags) = (r.u32()?, r.u32()?);
+                let path = self.fid_host(fid)?;
+                let meta = fs::metadata(&path).map_err(errno)?;
+                if !meta.is_dir() {
+                    let file = open(flags).open(&path).map_err(errno)?;
+                    self.fid(fid)?.file = Some(file);
+                }
+                w.bytes(&qid(&meta)).u32(0);
+            }
+            TLCREATE => {
+                let (fid, name, flags, mode) = (r.u32()?, r.str()?, r.u32()?, r.u32()?);
+                let path = child(&self.fid(fid)?.path, name)?;
+                let host = self.host(&path);
+                let mut options = open(flags);
+                match flags & O_EXCL {
+                    0 => options.create(true),
+                    _ => options.create_new(true),
+                };
+                let file = options.mode(mode & 0o7777).open(&host).map_err(errno)?;
+                let meta = file.metadata().map_err(errno)?;
+                *self.fid(fid)? = Fid {
+                    path,
+                    file: Some(file),
+                    entries: None,
+                };
+                w.bytes(&qid(&meta)).u32(0);
+            }
+            TREAD => {
+                let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+                let count = count.min(self.msize - IO_HEADER);
+                let file = self.fid(fid)?.file.as_ref().ok_or(EBADF)?;
+                let mut data = vec![0; count as usize];
+                let n = file.read_at(&mut data, offset).map_err(errno)?;
+                w.u32(n as u32).bytes(&data[..n]);
+            }
+            TWRITE => {
+                let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+                let data = r.bytes(count as usize)?;
+                let file = self.fid(fid)?.file.as_ref().ok_or(EBADF)?;
+                let n = file.write_at(data, offset).map_err(errno)?;
+                w.u32(n as u32);
+            }
+            TREADDIR => {
+                let (fid, offset, count) = (r.u32()?, r.u64()?, r.u32()?);
+                let count = count.min(self.msize - IO_HEADER) as usize;
+                if offset == 0 || self.fid(fid)?.entries.is_none() {
+                    let path = self.fid(fid)?.path.clone();
+                    let entries = self.entries(&path)?;
+                    self.fid(fid)?.entries = Some(entries);
+                }
+                let entries = self.fid(fid)?.entries.as_ref().unwrap();
+                let mut data = Writer::default();
+                for (i, entry) in entries.iter().enumerate().skip(offset as usize) {
+                    if data.0.len() + 24 + entry.name.len() > count {
+                        break;
+                    }
+                    data.bytes(&entry.qid)
+                        .u64(i as u64 + 1)
+                        .u8(entry.kind)
+                        .str(&entry.name);
+                }
+                w.u32(data.0.len() as u32).bytes(&data.0);
+            }
+            TSTATFS => {
+                let fid = r.u32()?;
+                let path =
+                    CString::new(self.fid_host(fid)?.as_os_str().as_bytes()).map_err(|_| EINVAL)?;
+                // SAFETY: statvfs is plain data, filled in by statvfs.
+                let mut st: libc::statvfs = unsafe { std::mem::zeroed() };
+                // SAFETY: path is a nul-terminated string.
+                if unsafe { libc::statvfs(path.as_ptr(), &mut st) } != 0 {
+                    return Err(errno(io::Error::last_os_error()));
+                }
+                w.u32(V9FS_MAGIC)
+                    .u32(st.f_bsize as u32)
+                    .u64(st.f_blocks as u64)
+                    .u64(st.f_bfree as u64)
+                    .u64(st.f_bavail as u64)
+                    .u64(st.f_files as u64)
+                    .u64(st.f_ffree as u64)
+                    .u64(st.f_fsid as u64)
+                    .u32(st.f_namemax as u32);
+            }
+            TMKDIR => {
+                let (fid, name, mode) = (r.u32()?, r.str()?, r.u32()?);
+                let path = child(&self.fid(fid)?.path, name)?;
+                DirBuilder::new()
+                    .mode(mode & 0o7777)
+                    .create(self.host(&path))
+                    .map_err(errno)?;
+                w.bytes(&qid(&self.stat(&path)?));
+            }
+            TSYMLINK => {
+                let (fid, name, target) = (r.u32()?, r.str()?, r.str()?);
+                let path = child(&self.fid(fid)?.path, name)?;
+                std::os::unix::fs::symlink(target, self.host(&path)).map_err(errno)?;
+                w.bytes(&qid(&self.stat(&path)?));
+            }
+            TREADLINK => {
+                let fid = r.u32()?;
+                let target = fs::read_link(self.fid_host(fid)?).map_err(errno)?;
+                w.str(target.as_os_str().as_bytes());
+            }
+            TLINK => {
+                let (dir, fid, name) = (r.u32()?, r.u32()?, r.str()?);
+                let path = child(&self.fid(dir)?.path, name)?;
+                fs::hard_link(self.fid_host(fid)?, self.host(&path)).map_err(errno)?;
+            }
+            TRENAME => {
+                let (fid, dir, name) = (r.u32()?, r.u32()?, r.str()?);
+                let path = child(&self.fid(dir)?.path, name)?;
+                fs::rename(self.fid_host(fid)?, self.host(&path)).map_err(errno)?;
+                self.fid(fid)?.path = path;
+            }
+            TRENAMEAT => {
+                let (dir, name) = (r.u32()?, r.str()?);
+                let from = child(&self.fid(dir)?.path, name)?;
+                let (dir, name) = (r.u32()?, r.str()?);
+                let to = child(&self.fid(dir)?.path, name)?;
+                fs::rename(self.host(&from), self.host(&to)).map_err(errno)?;
+            }
+            TUNLINKAT => {
+                let (dir, name, flags) = (r.u32()?, r.str()?, r.u32()?);
+                let path = child(&self.fid(dir)?.path, name)?;
+                match flags & AT_REMOVEDIR {
+                    0 => fs::remove_file(self.host(&path)),
+                    _ => fs::remove_dir(self.host(&path)),
+                }
+                .map_err(errno)?;
+            }
+            TREMOVE => {
+                let fid = r.u32()?;
+                let path = self.fid_host(fid)?;
+                self.fids.remove(&fid);
+                let meta = fs::symlink_metadata(&path).map_err(errno)?;
+                match meta.is_dir() {
+                    true => fs::remove_dir(path),
+                    false => fs::remove_file(path),
+                }
+                .map_err(errno)?;
+            }
+            TFSYNC => {
+                let fid = r.u32()?;
+                if let Some(file) = &self.fid(fid)?.file {
+                    file.sync_all().map_err(errno)?;
+                }
+            }
+            // the guest is alone on the directory
+            TLOCK => {
+                self.fid(r.u32()?)?;
+                w.u8(0);
+            }
+            TGETLOCK => {
+                self.fid(r.u32()?)?;
+                let _kind = r.u8()?;
+                let (start, length, proc_id) = (r.u64()?, r.u64()?, r.u32()?);
+                let client = r.str()?;
+                w.u8(LOCK_UNLOCKED)
+                    .u64(start)
+                    .u64(length)
+                    .u32(proc_id)
+                    .str(client.as_bytes());
+            }
+            TCLUNK => {
+                self.fids.remove(&r.u32()?).ok_or(EBADF)?;
+            }
+            TFLUSH => {}
+            _ => {
+                debug!(kind, "unsupported 9p request");
+                return Err(EOPNOTSUPP);
+            }
+        }
+        Ok(())
+    }
+
+    /// The entries of the directory at `path`, with `.` and `..`.
+    fn entries(&self, path: &Path) -> Result<Vec<Entry>, Errno> {
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let mut entries = Vec::new();
+        for (name, path) in [(".", path), ("..", parent)] {
+            entries.push(Entry {
+                qid: qid(&self.stat(path)?),
+                kind: libc::DT_DIR,
+                name: name.into(),
+            });
+        }
+        for entry in fs::read_dir(self.host(path)).map_err(errno)? {
+            let entry = entry.map_err(errno)?;
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let kind = match meta.file_type() {
+                x if x.is_dir() => libc::DT_DIR,
+                x if x.is_symlink() => libc::DT_LNK,
+                x if x.is_file() => libc::DT_REG,
+                _ => libc::DT_UNKNOWN,
+            };
+            entries.push(Entry {
+                qid: qid(&meta),
+                kind,
+                name: entry.file_name().as_bytes().to_vec(),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Options opening `path` with the `flags` of the guest.
+fn open(flags: u32) -> OpenOptions {
+    let mut options = OpenOptions::new();
+    match flags & O_ACCMODE {
+        O_WRONLY => options.write(true),
+        O_RDWR => options.read(true).write(true),
+        _ => options.read(true),
+    };
+    if flags & O_TRUNC != 0 {
+        options.write(true).truncate(true);
+    }
+    if flags & O_APPEND != 0 {
+        options.append(true);
+    }
+    options
+}
+
+impl VirtioDevice for Virtio9p {
+    fn device_id(&self) -> u32 {
+        DEVICE_9P
+    }
+
+    fn features(&self) -> u64 {
+        F_MOUNT_TAG
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    /// The length of the tag then the tag.
+    fn read_config(&self, offset: u64) -> u8 {
+        let len = (self.tag.len() as u16).to_le_bytes();
+        match offset {
+            0 | 1 => len[offset as usize],
+            _ => self
+                .tag
+                .as_bytes()
+                .get(offset as usize - 2)
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[0].pop(dma) {
+            let reply = match chain.read(dma) {
+                Ok(request) => self.handle(&request),
+                Err(e) => {
+                    warn!("virtio-9p request outside dram: {e:?}");
+                    Vec::new()
+                }
+            };
+            let len = match chain.write(dma, &reply) {
+                Ok(len) if len == reply.len() => len,
+                Ok(_) => {
+                    warn!(len = reply.len(), "virtio-9p reply buffer too small");
+                    0
+                }
+                Err(e) => {
+                    warn!("virtio-9p reply buffer outside dram: {e:?}");
+                    0
+                }
+            };
+            queues[0].push(dma, chain, len);
+            used = true;
+        }
+        used
+    }
+
+    fn reset(&mut self) {
+        self.fids.clear();
+    }
+}
@@ -0,0 +1,51 @@
+//! virtio-rng, the entropy device, drawing from a seeded [`Rng`] so a run
+//! with the same seed gets the same bytes.
+
+use tracing::warn;
+
+use super::{Queue, VirtioDevice, DEVICE_RNG};
+use crate::{bus::Dma, random::Rng};
+
+/// Most bytes given for a single request.
+const MAX_REQUEST: usize = 0x10000;
+
+#[derive(Debug)]
+pub struct VirtioRng {
+    rng: Rng,
+}
+
+impl VirtioRng {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioRng {
+    fn device_id(&self) -> u32 {
+        DEVICE_RNG
+    }
+
+    fn queues(&self) -> usize {
+        1
+    }
+
+    fn process(&mut self, queues: &mut [Queue], dma: &mut Dma<'_>) -> bool {
+        let mut used = false;
+        while let Some(chain) = queues[0].pop(dma) {
+            let mut data = vec![0; chain.writable_len().min(MAX_REQUEST)];
+            self.rng.fill(&mut data);
+            let len = match chain.write(dma, &data) {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!("virtio-rng buffer outside dram: {e:?}");
+                    0
+                }
+            };
+            queues[0].push(dma, chain, len);
+            used = true;
+        }
+        used
+    }
+}
@@ -0,0 +1,337 @@
+//! A VNC server showing a [`Framebuffer`], so any viewer is the window of
+//! the machine, e.g. `vncviewer localhost:5900`. It speaks RFB 3.3 to 3.8
+//! without authentication and sends whole frames in the raw encoding, once
+//! the guest changed them and the viewer asked for an update. The keys and
+//! the pointer of the viewer go to the virtio-input devices, if any, keys
+//! by their position on a US keyboard.
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use tracing::{debug, warn};
+
+use crate::{
+    framebuffer::{Frame, Framebuffer},
+    virtio::input::{Input, InputEvent, TABLET_MAX},
+};
+
+/// How often a viewer waiting for an update checks the framebuffer.
+const REFRESH: Duration = Duration::from_millis(16);
+
+const SECURITY_NONE: u8 = 1;
+
+/// Messages of the viewer.
+const SET_PIXEL_FORMAT: u8 = 0;
+const SET_ENCODINGS: u8 = 2;
+const UPDATE_REQUEST: u8 = 3;
+const KEY_EVENT: u8 = 4;
+const POINTER_EVENT: u8 = 5;
+const CUT_TEXT: u8 = 6;
+
+/// How the pixels are sent, only true colour of 32 bits per pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PixelFormat {
+    big_endian: bool,
+    max: [u16; 3],
+    shift: [u8; 3],
+}
+
+impl Default for PixelFormat {
+    /// The framebuffer's own.
+    fn default() -> Self {
+        Self {
+            big_endian: false,
+            max: [255; 3],
+            shift: [16, 8, 0],
+        }
+    }
+}
+
+impl PixelFormat {
+    fn encode(&self) -> [u8; 16] {
+        let mut x = [0; 16];
+        x[..4].copy_from_slice(&[32, 24, self.big_endian.into(), 1]);
+        for i in 0..3 {
+            x[4 + 2 * i..][..2].copy_from_slice(&self.max[i].to_be_bytes());
+            x[10 + i] = self.shift[i];
+        }
+        x
+    }
+
+    /// The format `x` asks for, if it is one the server sends: 32 bit true
+    /// color with every channel within the pixel.
+    fn decode(x: &[u8; 16]) -> Option<Self> {
+        if x[0] != 32 || x[3] == 0 || x[10..13].iter().any(|&shift| shift >= 32) {
+            return None;
+        }
+        let max = |i: usize| u16::from_be_bytes([x[4 + 2 * i], x[5 + 2 * i]]);
+        Some(Self {
+            big_endian: x[2] != 0,
+            max: [max(0), max(1), max(2)],
+            shift: [x[10], x[11], x[12]],
+        })
+    }
+
+    fn pixel(&self, xrgb: u32, out: &mut Vec<u8>) {
+        let mut value = 0;
+        for (i, from) in [16, 8, 0].into_iter().enumerate() {
+            let channel = (xrgb >> from & 0xff) * u32::from(self.max[i]) / 255;
+            value |= channel << self.shift[i];
+        }
+        out.extend_from_slice(&match self.big_endian {
+            true => value.to_be_bytes(),
+            false => value.to_le_bytes(),
+        });
+    }
+}
+
+enum Message {
+    SetPixelFormat([u8; 16]),
+    UpdateRequest {
+        incremental: bool,
+    },
+    Key {
+        down: bool,
+        keysym: u32,
+    },
+    Pointer {
+        buttons: u8,
+        x: u16,
+        y: u16,
+    },
+    /// Something the server has no use for.
+    Other,
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<Message> {
+    let mut kind = [0];
+    reader.read_exact(&mut kind)?;
+    let skip = |reader: &mut dyn Read, n: u64| {
+        io::copy(&mut reader.take(n), &mut io::sink()).map(|_| Message::Other)
+    };
+    match kind[0] {
+        SET_PIXEL_FORMAT => {
+            let mut x = [0; 19];
+            reader.read_exact(&mut x)?;
+            Ok(Message::SetPixelFormat(x[3..].try_into().unwrap()))
+        }
+        SET_ENCODINGS => {
+            let mut x = [0; 3];
+            reader.read_exact(&mut x)?;
+            skip(reader, 4 * u64::from(u16::from_be_bytes([x[1], x[2]])))
+        }
+        UPDATE_REQUEST => {
+            let mut x = [0; 9];
+            reader.read_exact(&mut x)?;
+            Ok(Message::UpdateRequest {
+                incremental: x[0] != 0,
+            })
+        }
+        KEY_EVENT => {
+            let mut x = [0; 7];
+            reader.read_exact(&mut x)?;
+            Ok(Message::Key {
+                down: x[0] != 0,
+                keysym: u32::from_be_bytes(x[3..].try_into().unwrap()),
+            })
+        }
+        POINTER_EVENT => {
+            let mut x = [0; 5];
+            reader.read_exact(&mut x)?;
+            Ok(Message::Pointer {
+                buttons: x[0],
+                x: u16::from_be_bytes([x[1], x[2]]),
+                y: u16::from_be_bytes([x[3], x[4]]),
+            })
+        }
+        CUT_TEXT => {
+            let mut x = [0; 7];
+            reader.read_exact(&mut x)?;
+            skip(
+                reader,
+                u32::from_be_bytes(x[3..].try_into().unwrap()).into(),
+            )
+        }
+        kind => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown vnc message {kind}"),
+        )),
+    }
+}
+
+/// Linux key codes of the X keysyms which aren't characters.
+const KEYSYMS: &[(u32, u16)] = &[
+    (0xff08, 14),  // BackSpace
+    (0xff09, 15),  // Tab
+    (0xff0d, 28),  // Return
+    (0xff1b, 1),   // Escape
+    (0xff50, 102), // Home
+    (0xff51, 105), // Left
+    (0xff52, 103), // Up
+    (0xff53, 106), // Right
+    (0xff54, 108), // Down
+    (0xff55, 104), // Page_Up
+    (0xff56, 109), // Page_Down
+    (0xff57, 107), // End
+    (0xff63, 110), // Insert
+    (0xffc8, 87),  // F11
+    (0xffc9, 88),  // F12
+    (0xffe1, 42),  // Shift_L
+    (0xffe2, 54),  // Shift_R
+    (0xffe3, 29),  // Control_L
+    (0xffe4, 97),  // Control_R
+    (0xffe5, 58),  // Caps_Lock
+    (0xffe9, 56),  // Alt_L
+    (0xffea, 100), // Alt_R
+    (0xffeb, 125), // Super_L
+    (0xffec, 126), // Super_R
+    (0xffff, 111), // Delete
+];
+
+/// Linux key codes of the printable characters, from space, the shifted
+/// ones on the same key as the others.
+const CHARACTERS: &[u8; 95] = &[
+    57, 2, 40, 4, 5, 6, 8, 40, 10, 11, 9, 13, 51, 12, 52, 53, // space to /
+    11, 2, 3, 4, 5, 6, 7, 8, 9, 10, // 0 to 9
+    39, 39, 51, 13, 52, 53, 3, // : to @
+    30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45,
+    21, 44, // A to Z
+    26, 43, 27, 7, 12, 41, // [ to `
+    30, 48, 46, 32, 18, 33, 34, 35, 23, 36, 37, 38, 50, 49, 24, 25, 16, 19, 31, 20, 22, 47, 17, 45,
+    21, 44, // a to z
+    26, 43, 27, 41, // { to ~
+];
+
+/// The Linux key code of the key `keysym` is on.
+fn keycode(keysym: u32) -> Option<u16> {
+    match keysym {
+        0x20..=0x7e => Some(CHARACTERS[keysym as usize - 0x20].into()),
+        // F1 to F10
+        0xffbe..=0xffc7 => Some(59 + (keysym - 0xffbe) as u16),
+        _ => KEYSYMS.iter().find(|x| x.0 == keysym).map(|x| x.1),
+    }
+}
+
+/// Shows `framebuffer` to the viewers connecting to `listener`, from a
+/// thread of their own each, sending their keys and pointer to `input`.
+pub fn serve(listener: TcpListener, framebuffer: Framebuffer, input: Option<Input>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(x) => x,
+                Err(e) => return warn!("vnc accept: {e}"),
+            };
+            let framebuffer = framebuffer.clone();
+            let input = input.clone();
+            thread::spawn(move || {
+                if let Err(e) = session(stream, &framebuffer, input.as_ref()) {
+                    debug!("vnc viewer gone: {e}");
+                }
+            });
+        }
+    });
+}
+
+fn session(
+    mut stream: TcpStream,
+    framebuffer: &Framebuffer,
+    input: Option<&Input>,
+) -> io::Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let mut version = [0; 12];
+    stream.read_exact(&mut version)?;
+    match &version {
+        // the server picks the security type
+        b"RFB 003.003\n" => stream.write_all(&u32::from(SECURITY_NONE).to_be_bytes())?,
+        _ => {
+            stream.write_all(&[1, SECURITY_NONE])?;
+            let mut chosen = [0];
+            stream.read_exact(&mut chosen)?;
+            // only 3.8 tells the security handshake went fine
+            if &version != b"RFB 003.007\n" {
+                stream.write_all(&0u32.to_be_bytes())?;
+            }
+        }
+    }
+    let mut shared = [0];
+    stream.read_exact(&mut shared)?;
+
+    let mut init = Vec::new();
+    init.extend_from_slice(&(framebuffer.width() as u16).to_be_bytes());
+    init.extend_from_slice(&(framebuffer.height() as u16).to_be_bytes());
+    init.extend_from_slice(&PixelFormat::default().encode());
+    init.extend_from_slice(&4u32.to_be_bytes());
+    init.extend_from_slice(b"rysk");
+    stream.write_all(&init)?;
+
+    let (sender, messages) = mpsc::channel();
+    let mut reader = stream.try_clone()?;
+    thread::spawn(move || {
+        while let Ok(message) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut format = PixelFormat::default();
+    // whether the viewer is waiting for an update, and only of what changed
+    let mut requested: Option<bool> = None;
+    let mut sent: Option<u64> = None;
+    loop {
+        match messages.recv_timeout(REFRESH) {
+            Ok(Message::SetPixelFormat(x)) => match PixelFormat::decode(&x) {
+                Some(x) => format = x,
+                None => warn!("vnc viewer asked for a pixel format other than 32 bits true colour"),
+            },
+            Ok(Message::UpdateRequest { incremental }) => {
+                requested = Some(incremental && requested.unwrap_or(true));
+            }
+            Ok(Message::Key { down, keysym }) => match (input, keycode(keysym)) {
+                (Some(input), Some(code)) => input.send(InputEvent::Key { code, down }),
+                (Some(_), None) => debug!(keysym, "vnc key without a key code"),
+                (None, _) => {}
+            },
+            Ok(Message::Pointer { buttons, x, y }) => {
+                let scale = |x: u16, size: u32| u32::from(x).min(size) * TABLET_MAX / size.max(1);
+                if let Some(input) = input {
+                    input.send(InputEvent::Pointer {
+                        x: scale(x, framebuffer.width() - 1),
+                        y: scale(y, framebuffer.height() - 1),
+                        buttons,
+                    });
+                }
+            }
+            Ok(Message::Other) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        let Some(incremental) = requested else {
+            continue;
+        };
+        if !incremental || sent != Some(framebuffer.generation()) {
+            let frame = framebuffer.frame();
+            stream.write_all(&update(&frame, &format))?;
+            sent = Some(frame.generation);
+            requested = None;
+        }
+    }
+}
+
+/// A framebuffer update with the whole `frame`.
+fn update(frame: &Frame, format: &PixelFormat) -> Vec<u8> {
+    let mut x = Vec::with_capacity(16 + 4 * frame.pixels.len());
+    // a single rectangle, at 0,0 and in the raw encoding
+    x.extend_from_slice(&[0, 0, 0, 1, 0, 0, 0, 0]);
+    x.extend_from_slice(&(frame.width as u16).to_be_bytes());
+    x.extend_from_slice(&(frame.height as u16).to_be_bytes());
+    x.extend_from_slice(&0i32.to_be_bytes());
+    for &pixel in &frame.pixels {
+        format.pixel(pixel, &mut x);
+    }
+    x
+}
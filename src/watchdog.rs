@@ -0,0 +1,149 @@
+//! A watchdog timer: once enabled, the guest has to feed it before its
+//! timeout runs out in ticks of the machine timer, or the run stops as if
+//! the machine reset or halted, see [`WatchdogAction`]. It only counts while
+//! the machine polls its devices, so a hung guest still bites.
+
+use std::{
+    ops::Range,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use tracing::warn;
+
+use crate::{bus::Device, exception::Exception};
+
+/// Where the watchdog is mapped, in a hole of the QEMU virt map.
+pub const WATCHDOG_BASE: u64 = 0x1005_0000;
+pub const WATCHDOG_SIZE: u64 = 0x1000;
+
+/// Registers, by offset: enable in bit 0 of the control, the timeout in
+/// ticks of [`TIMEBASE_FREQUENCY`](crate::time::TIMEBASE_FREQUENCY), the feed
+/// register taking [`WATCHDOG_KEY`] and the ticks left, read only.
+const CONTROL: u64 = 0x0;
+const TIMEOUT: u64 = 0x4;
+const FEED: u64 = 0x8;
+const REMAINING: u64 = 0xc;
+
+/// What feeding the watchdog stores, any other value is ignored.
+pub const WATCHDOG_KEY: u64 = 0x0d09_f00d;
+
+/// What the watchdog does when it runs out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WatchdogAction {
+    /// Stop with [`ExitReason::Reboot`](crate::machine::ExitReason::Reboot).
+    #[default]
+    Reset,
+    /// Stop with [`ExitReason::Halted`](crate::machine::ExitReason::Halted).
+    Halt,
+}
+
+impl FromStr for WatchdogAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reset" => Ok(Self::Reset),
+            "halt" => Ok(Self::Halt),
+            _ => Err(format!(
+                "unknown watchdog action {s}, expected reset or halt"
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    action: WatchdogAction,
+    enabled: bool,
+    timeout: u64,
+    /// When it runs out, while enabled.
+    deadline: u64,
+    /// The time of the last poll.
+    now: u64,
+    expired: bool,
+}
+
+/// Clones share the watchdog, the machine keeps one to poll while another
+/// is mapped.
+#[derive(Debug, Clone)]
+pub struct Watchdog {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Watchdog {
+    /// A disabled watchdog which does `action` once it runs out.
+    pub fn new(action: WatchdogAction) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                action,
+                enabled: false,
+                timeout: 0,
+                deadline: 0,
+                now: 0,
+                expired: false,
+            })),
+        }
+    }
+
+    pub fn action(&self) -> WatchdogAction {
+        self.inner.lock().unwrap().action
+    }
+
+    /// Whether it ran out, which it stays.
+    pub fn expired(&self) -> bool {
+        self.inner.lock().unwrap().expired
+    }
+
+    /// Moves it to `now`, in ticks of the machine timer.
+    pub fn poll(&self, now: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.now = now;
+        if inner.enabled && !inner.expired && now >= inner.deadline {
+            warn!(timeout = inner.timeout, "watchdog expired");
+            inner.expired = true;
+        }
+    }
+}
+
+impl Device for Watchdog {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        let inner = self.inner.lock().unwrap();
+        let value = match offset {
+            CONTROL => inner.enabled.into(),
+            TIMEOUT => inner.timeout,
+            REMAINING if inner.enabled => inner.deadline.saturating_sub(inner.now),
+            REMAINING => inner.timeout,
+            _ => 0,
+        };
+        Ok(value)
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        let mut inner = self.inner.lock().unwrap();
+        match offset {
+            CONTROL => {
+                let enabled = value & 1 != 0;
+                if enabled && !inner.enabled {
+                    inner.deadline = inner.now + inner.timeout;
+                }
+                inner.enabled = enabled;
+            }
+            TIMEOUT => inner.timeout = value & 0xffff_ffff,
+            FEED if value & 0xffff_ffff == WATCHDOG_KEY => {
+                inner.deadline = inner.now + inner.timeout;
+            }
+            FEED => warn!(value, "watchdog fed without the key"),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "watchdog"
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(WATCHDOG_BASE..WATCHDOG_BASE + WATCHDOG_SIZE)
+    }
+}
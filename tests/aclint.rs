@@ -0,0 +1,93 @@
+use rysk::{
+    aclint::{MSWI_BASE, MTIMER_BASE, SSWI_BASE},
+    cpu::MIP,
+    machine::{EbreakPolicy, ExitReason, Machine},
+};
+
+#[test]
+fn interrupts_another_hart() {
+    // Hart 0 sets msip and setssip of hart 1 then spins, hart 1 waits for
+    // both in mip and exits with its id:
+    //   csrr t0, mhartid; bnez t0, 1f; lui t1, MSWI; li t2, 1; sw t2, 4(t1)
+    //   lui t1, SSWI; sw t2, 4(t1); j .
+    //   1: csrr t2, mip; andi t3, t2, 10; li t4, 10; bne t3, t4, 1b; ebreak
+    let code = [
+        0xf14022f3u32,
+        0x00029e63,
+        0x02000337,
+        0x00100393,
+        0x00732223,
+        0x02f00337,
+        0x00732223,
+        0x0000006f,
+        0x344023f3,
+        0x00a3fe13,
+        0x00a00e93,
+        0xffde1ae3,
+        0x00100073,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .harts(2)
+        .ebreak(EbreakPolicy::Exit)
+        .build()
+        .unwrap();
+    assert_eq!(machine.run(), ExitReason::Shutdown(1));
+    assert_eq!(machine.cpu.csrs[MIP], 0b1010);
+    assert_eq!(machine.harts[0].csrs[MIP], 0);
+    assert_eq!(machine.cpu.bus.load(MSWI_BASE + 4, 32), Ok(1));
+    assert_eq!(machine.cpu.bus.load(SSWI_BASE + 4, 32), Ok(0));
+
+    // Lowering msip clears it, ssip stays until the hart clears it.
+    machine.cpu.bus.store(MSWI_BASE + 4, 32, 0).unwrap();
+    machine.ipi.deliver(&mut machine.cpu, &mut machine.harts);
+    assert_eq!(machine.cpu.csrs[MIP], 0b0010);
+
+    // Self IPI, and nothing for harts which don't exist.
+    machine.ipi.send_supervisor(0);
+    machine.ipi.send_supervisor(2);
+    machine.ipi.deliver(&mut machine.cpu, &mut machine.harts);
+    assert_eq!(machine.harts[0].csrs[MIP], 0b0010);
+}
+
+#[test]
+fn machine_timer() {
+    // j .
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .harts(2)
+        .deterministic(true)
+        .build()
+        .unwrap();
+    let mtip = 1 << 7;
+    let bus = &mut machine.cpu.bus;
+    bus.store(MTIMER_BASE, 64, 20).unwrap();
+    // the second hart's, a 32-bit half at a time
+    bus.store(MTIMER_BASE + 8, 32, 10).unwrap();
+    bus.store(MTIMER_BASE + 12, 32, 0).unwrap();
+    assert_eq!(bus.load(MTIMER_BASE + 8, 64), Ok(10));
+
+    // mtime is the time of the running hart, the parked one is due first
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.csrs[MIP] & mtip, 0);
+    assert_eq!(machine.harts[0].csrs[MIP] & mtip, mtip);
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.csrs[MIP] & mtip, mtip);
+    assert_eq!(machine.cpu.bus.load(MTIMER_BASE + 0x7ff8, 64), Ok(20));
+    // mtime follows time, writes to it are ignored
+    machine.cpu.bus.store(MTIMER_BASE + 0x7ff8, 64, 0).unwrap();
+    assert_eq!(machine.cpu.bus.load(MTIMER_BASE + 0x7ffc, 32), Ok(0));
+
+    // a later deadline lowers it
+    machine.cpu.bus.store(MTIMER_BASE, 64, 1000).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & mtip, 0);
+    assert_eq!(machine.harts[0].csrs[MIP] & mtip, mtip);
+}
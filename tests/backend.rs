@@ -0,0 +1,69 @@
+use std::{thread, time::Duration};
+
+use rysk::{
+    backend::BackendHandle,
+    bus::Device,
+    cpu::MIP,
+    exception::{Exception, Interrupt},
+    machine::Machine,
+};
+
+/// Storing an address starts a slow read which lands there in the background.
+struct SlowDisk(BackendHandle);
+
+impl Device for SlowDisk {
+    fn load(&mut self, _offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(self.0.in_flight() as u64)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u64, addr: u64) -> Result<(), Exception> {
+        self.0.submit(
+            || {
+                thread::sleep(Duration::from_millis(20));
+                0xdead_beef
+            },
+            move |data, cpu| {
+                cpu.bus.store(addr, 32, data).unwrap();
+                cpu.set_pending(Interrupt::MachineExternal, true);
+            },
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn completes_in_the_background() {
+    // j .
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .build()
+        .unwrap();
+    let handle = machine.backend.handle();
+    machine
+        .cpu
+        .bus
+        .map(0x1000_0000, 8, SlowDisk(handle.clone()));
+
+    machine.cpu.bus.store(0x1000_0000, 64, 0x8000_0100).unwrap();
+    assert_eq!(machine.cpu.bus.load(0x1000_0000, 64), Ok(1));
+
+    let mut steps = 0;
+    while machine.cpu.csrs[MIP] & 1 << 11 == 0 {
+        machine.step().unwrap();
+        steps += 1;
+    }
+    assert!(steps > 1, "the guest should run while the read is pending");
+    assert_eq!(machine.cpu.bus.load(0x8000_0100, 32), Ok(0xdead_beef));
+    assert_eq!(handle.in_flight(), 0);
+}
+
+#[test]
+fn drain() {
+    let mut machine = Machine::builder().build().unwrap();
+    let handle = machine.backend.handle();
+    for i in 0..4 {
+        handle.submit(move || i, |i, cpu| cpu.regs[5] += i);
+    }
+    machine.backend.drain(&mut machine.cpu);
+    assert_eq!(machine.cpu.regs[5], 6);
+}
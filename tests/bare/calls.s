@@ -0,0 +1,33 @@
+# Calls work twice, which calls leaf, then passes through the test finisher.
+# Symbols are typed and sized for the profiler.
+.globl _start
+.type _start, @function
+_start:
+  call work
+  call work
+  li t0, 0x100000
+  li t1, 0x5555
+  sw t1, 0(t0)
+1:
+  j 1b
+.size _start, . - _start
+
+.type work, @function
+work:
+  addi sp, sp, -16
+  sd ra, 8(sp)
+  li t2, 3
+2:
+  addi t2, t2, -1
+  bnez t2, 2b
+  call leaf
+  ld ra, 8(sp)
+  addi sp, sp, 16
+  ret
+.size work, . - work
+
+.type leaf, @function
+leaf:
+  nop
+  ret
+.size leaf, . - leaf
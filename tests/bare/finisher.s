@@ -0,0 +1,8 @@
+# Fails with exit code 5 through the test finisher.
+.globl _start
+_start:
+  li t0, 0x100000
+  li t1, (5 << 16) | 0x3333
+  sw t1, 0(t0)
+1:
+  j 1b
@@ -0,0 +1,27 @@
+# Reads a key from the htif console device and exits with it.
+.globl _start
+_start:
+  la t2, tohost
+  la t3, fromhost
+  li t1, 0x0100000000000000
+  sd t1, 0(t2)
+1:
+  ld t4, 0(t3)
+  beqz t4, 1b
+  sd zero, 0(t3)
+
+  andi a0, t4, 0xff
+  slli a0, a0, 1
+  ori a0, a0, 1
+  sd a0, 0(t2)
+2:
+  j 2b
+
+.data
+.balign 8
+.globl tohost
+tohost:
+  .dword 0
+.globl fromhost
+fromhost:
+  .dword 0
@@ -0,0 +1,57 @@
+# Hart 0 starts hart 1 through SBI HSM, hart 1 saves its a0 and a1 and
+# stops, then hart 0 waits for it to have stopped and passes through the
+# test finisher. The results of the calls are saved at `results`.
+.equ HSM, 0x48534d
+.globl _start
+_start:
+  la t0, results
+  li a7, HSM
+  # hart_get_status(1), stopped
+  li a6, 2
+  li a0, 1
+  ecall
+  sd a1, 0(t0)
+  # hart_start(1, secondary, 42)
+  li a6, 0
+  li a0, 1
+  la a1, secondary
+  li a2, 42
+  ecall
+  sd a0, 8(t0)
+  # Again, it is already started.
+  li a0, 1
+  ecall
+  sd a0, 16(t0)
+  # hart_start(7, ...), no such hart
+  li a0, 7
+  ecall
+  sd a0, 24(t0)
+1:
+  li a6, 2
+  li a0, 1
+  ecall
+  beqz a1, 1b
+  li t0, 0x100000
+  li t1, 0x5555
+  sw t1, 0(t0)
+2:
+  j 2b
+
+secondary:
+  la t0, seen
+  sd a0, 0(t0)
+  sd a1, 8(t0)
+  li a7, HSM
+  # hart_stop()
+  li a6, 1
+  ecall
+  unimp
+
+.data
+.align 3
+.globl results
+results:
+  .dword 0, 0, 0, 0
+.globl seen
+seen:
+  .dword 0, 0
@@ -0,0 +1,49 @@
+# Prints through a proxied write and the console device, then exits with the
+# result of the write (6).
+.globl _start
+_start:
+  la t0, magic
+  li t1, 64
+  sd t1, 0(t0)
+  li t1, 1
+  sd t1, 8(t0)
+  la t1, msg
+  sd t1, 16(t0)
+  li t1, 6
+  sd t1, 24(t0)
+
+  la t2, tohost
+  la t3, fromhost
+  sd t0, 0(t2)
+1:
+  ld t4, 0(t3)
+  beqz t4, 1b
+  sd zero, 0(t3)
+
+  # putchar('!') on the console device
+  li t1, 0x0101000000000021
+  sd t1, 0(t2)
+2:
+  ld t4, 0(t3)
+  beqz t4, 2b
+  sd zero, 0(t3)
+
+  ld a0, 0(t0)
+  slli a0, a0, 1
+  ori a0, a0, 1
+  sd a0, 0(t2)
+3:
+  j 3b
+
+.data
+.balign 8
+.globl tohost
+tohost:
+  .dword 0
+.globl fromhost
+fromhost:
+  .dword 0
+magic:
+  .zero 64
+msg:
+  .ascii "hello\n"
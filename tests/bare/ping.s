@@ -0,0 +1,27 @@
+# Broadcasts a frame with 42 as its first payload byte, then fails through
+# the test finisher with the first payload byte of the reply as exit code.
+.globl _start
+_start:
+  li s0, 0x10040000
+  li s1, 0x10042000
+  li s2, 0x10041000
+  li t0, -1
+  sw t0, 0(s1)
+  sh t0, 4(s1)
+  li t0, 42
+  sb t0, 14(s1)
+  li t0, 60
+  sw t0, 0x10(s0)
+1:
+  lw t0, 8(s0)
+  beqz t0, 1b
+  lbu a0, 14(s2)
+  sw zero, 0xc(s0)
+
+  slli a0, a0, 16
+  li t0, 0x3333
+  or a0, a0, t0
+  li t0, 0x100000
+  sw a0, 0(t0)
+2:
+  j 2b
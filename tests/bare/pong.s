@@ -0,0 +1,21 @@
+# Answers the first frame received with its first payload byte plus one,
+# then passes through the test finisher.
+.globl _start
+_start:
+  li s0, 0x10040000
+  li s1, 0x10042000
+  li s2, 0x10041000
+1:
+  lw t0, 8(s0)
+  beqz t0, 1b
+  lbu t1, 14(s2)
+  addi t1, t1, 1
+  sb t1, 14(s1)
+  li t0, 60
+  sw t0, 0x10(s0)
+
+  li t0, 0x100000
+  li t1, 0x5555
+  sw t1, 0(t0)
+2:
+  j 2b
@@ -0,0 +1,70 @@
+# Calls the built-in SBI firmware: console output through the legacy and
+# debug console calls, probes, a timer and an IPI to itself, then a shutdown
+# reporting a failure. The results of the calls are saved at `results`.
+.equ BASE, 0x10
+.equ TIME, 0x54494d45
+.equ IPI, 0x735049
+.equ SRST, 0x53525354
+.equ DBCN, 0x4442434e
+.globl _start
+_start:
+  la s0, results
+  # console_putchar('h')
+  li a7, 1
+  li a0, 'h'
+  ecall
+  # console_write(3, message)
+  li a7, DBCN
+  li a6, 0
+  li a0, 3
+  la a1, message
+  li a2, 0
+  ecall
+  sd a1, 0(s0)
+  # probe_extension(SRST) and an unknown one
+  li a7, BASE
+  li a6, 3
+  li a0, SRST
+  ecall
+  sd a1, 8(s0)
+  li a0, 0x1234
+  ecall
+  sd a1, 16(s0)
+  # set_timer(time + 1), then wait for STIP
+  csrr t0, time
+  addi a0, t0, 1
+  li a7, TIME
+  li a6, 0
+  ecall
+1:
+  csrr t0, mip
+  andi t0, t0, 0x20
+  beqz t0, 1b
+  # send_ipi(1, 0), then wait for SSIP
+  li a7, IPI
+  li a6, 0
+  li a0, 1
+  li a1, 0
+  ecall
+2:
+  csrr t0, mip
+  andi t0, t0, 2
+  beqz t0, 2b
+  csrr t0, mip
+  sd t0, 24(s0)
+  # system_reset(shutdown, system failure)
+  li a7, SRST
+  li a6, 0
+  li a0, 0
+  li a1, 1
+  ecall
+3:
+  j 3b
+
+.data
+message:
+  .ascii "i!\n"
+.align 3
+.globl results
+results:
+  .dword 0, 0, 0, 0
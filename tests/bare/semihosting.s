@@ -0,0 +1,35 @@
+# Prints through SYS_WRITE0 and SYS_WRITEC, then exits with status 3.
+.option norvc
+.globl _start
+_start:
+  li a0, 0x04
+  la a1, msg
+  call semihost
+
+  li a0, 0x03
+  la a1, bang
+  call semihost
+
+  li a0, 0x18
+  la a1, exit
+  call semihost
+1:
+  j 1b
+
+# The sequence has to stay uncompressed and within one page.
+.balign 16
+semihost:
+  slli zero, zero, 0x1f
+  ebreak
+  srai zero, zero, 7
+  ret
+
+.data
+.balign 8
+exit:
+  .dword 0x20026
+  .dword 3
+msg:
+  .asciz "hi\n"
+bang:
+  .byte '!'
@@ -0,0 +1,99 @@
+use rstest::rstest;
+use rysk::{bus::DRAM_BASE, cpu::Cpu, disasm::disassemble, exception::Exception, isa::Isa};
+
+/// Executes `inst` with `a` in t0 and `b` in t1, returning t2.
+fn execute(inst: u32, a: u64, b: u64) -> u64 {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = a;
+    cpu.regs[6] = b;
+    cpu.step().unwrap();
+    cpu.regs[7]
+}
+
+#[rstest]
+#[case::sh1add(0x2062a3b3, 3, 1, 7)]
+#[case::sh2add(0x2062c3b3, 3, 1, 13)]
+#[case::sh3add(0x2062e3b3, 3, 1, 25)]
+#[case::add_uw(0x086283bb, 0xffff_ffff_ffff_ffff, 1, 0x1_0000_0000)]
+#[case::sh1add_uw(0x2062a3bb, 0x1_8000_0000, 1, 0x1_0000_0001)]
+#[case::sh2add_uw(0x2062c3bb, 0x1_0000_0001, 0, 4)]
+#[case::sh3add_uw(0x2062e3bb, 0xffff_ffff, 0, 0x7_ffff_fff8)]
+// slli.uw t2, t0, 3
+#[case::slli_uw(0x0832939b, 0xf_ffff_ffff, 0, 0x7_ffff_fff8)]
+fn zba(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute(inst, a, b), expected);
+}
+
+#[rstest]
+#[case::andn(0x4062f3b3, 0b1100, 0b1010, 0b0100)]
+#[case::orn(0x4062e3b3, 0b1100, !0b1010, 0b1110)]
+#[case::xnor(0x4062c3b3, 0b1100, 0b1010, !0b0110)]
+#[case::clz(0x60029393, 1 << 40, 0, 23)]
+#[case::clz_zero(0x60029393, 0, 0, 64)]
+#[case::ctz(0x60129393, 1 << 40, 0, 40)]
+#[case::cpop(0x60229393, 0xf0f0, 0, 8)]
+#[case::clzw(0x6002939b, 0xffff_0000_0000_0100, 0, 23)]
+#[case::ctzw(0x6012939b, 1 << 40, 0, 32)]
+#[case::cpopw(0x6022939b, u64::MAX, 0, 32)]
+#[case::max(0x0a62e3b3, -1i64 as u64, 1, 1)]
+#[case::maxu(0x0a62f3b3, -1i64 as u64, 1, u64::MAX)]
+#[case::min(0x0a62c3b3, -1i64 as u64, 1, -1i64 as u64)]
+#[case::minu(0x0a62d3b3, -1i64 as u64, 1, 1)]
+#[case::sext_b(0x60429393, 0x180, 0, -128i64 as u64)]
+#[case::sext_h(0x60529393, 0x1_8000, 0, -32768i64 as u64)]
+#[case::zext_h(0x0802c3bb, u64::MAX, 0, 0xffff)]
+#[case::rol(0x606293b3, 0x8000_0000_0000_0001, 65, 3)]
+#[case::ror(0x6062d3b3, 3, 1, 0x8000_0000_0000_0001)]
+// rori t2, t0, 36
+#[case::rori(0x6242d393, 0xf, 0, 0xf000_0000)]
+#[case::rolw(0x606293bb, 0x8000_0001, 1, 3)]
+#[case::rorw(0x6062d3bb, 1, 1, 0xffff_ffff_8000_0000)]
+// roriw t2, t0, 4
+#[case::roriw(0x6042d39b, 0x1_0000_0001, 0, 0x1000_0000)]
+#[case::orc_b(0x2872d393, 0x0100_0000_0020_0000, 0, 0xff00_0000_00ff_0000)]
+#[case::rev8(0x6b82d393, 0x0102_0304_0506_0708, 0, 0x0807_0605_0403_0201)]
+fn zbb(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute(inst, a, b), expected);
+}
+
+#[rstest]
+#[case::bclr(0x486293b3, u64::MAX, 64 + 3, !0b1000)]
+#[case::bext(0x4862d3b3, 0b1000, 3, 1)]
+#[case::binv(0x686293b3, 0b1000, 3, 0)]
+#[case::bset(0x286293b3, 0, 63, 1 << 63)]
+// the immediate forms with bit 40
+#[case::bclri(0x4a829393, u64::MAX, 0, !(1 << 40))]
+#[case::bexti(0x4a82d393, 1 << 40, 0, 1)]
+#[case::binvi(0x6a829393, 1 << 40, 0, 0)]
+#[case::bseti(0x2a829393, 0, 0, 1 << 40)]
+fn zbs(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute(inst, a, b), expected);
+}
+
+#[rstest]
+// sh1add t2, t0, t1
+#[case::zba(0x2062a3b3, "rv64i")]
+// andn t2, t0, t1
+#[case::zbb(0x4062f3b3, "rv64i_zba_zbs")]
+// rev8 t2, t0
+#[case::rev8(0x6b82d393, "rv64i_zbs")]
+// bseti t2, t0, 40
+#[case::zbs(0x2a829393, "rv64i_zba_zbb")]
+// clz with the reserved rs2 3
+#[case::reserved_unary(0x60329393, "rv64i_zbb")]
+fn illegal(#[case] inst: u32, #[case] isa: &str) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.isa = isa.parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[rstest]
+#[case(0x2062a3b3, "sh1add t2, t0, t1")]
+#[case(0x0832939b, "slli.uw t2, t0, 3")]
+#[case(0x60229393, "cpop t2, t0")]
+#[case(0x0802c3bb, "zext.h t2, t0")]
+#[case(0x6b82d393, "rev8 t2, t0")]
+#[case(0x2a829393, "bseti t2, t0, 40")]
+fn disassembly(#[case] inst: u64, #[case] expected: &str) {
+    assert_eq!(disassemble(inst, DRAM_BASE).as_deref(), Some(expected));
+}
@@ -0,0 +1,233 @@
+use std::ops::Range;
+
+use rysk::{
+    bus::{Bus, Device, Dma, Iommu, DRAM_BASE, IOMMU_PAGE},
+    dram::Dram,
+    exception::Exception,
+    machine::Machine,
+};
+
+const BASE: u64 = 0x3000_0000;
+
+/// Counts its ticks, and raises source 3 once it stored a nonzero value.
+#[derive(Debug, Default)]
+struct Counter {
+    ticks: u64,
+    raised: bool,
+}
+
+impl Device for Counter {
+    fn load(&mut self, offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(self.ticks + offset)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        self.raised = value != 0;
+        Ok(())
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(BASE..BASE + 0x100)
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+
+    fn pending_irq(&self) -> Option<u32> {
+        self.raised.then_some(3)
+    }
+}
+
+/// A device without an address of its own.
+#[derive(Debug)]
+struct Unmapped;
+
+impl Device for Unmapped {
+    fn load(&mut self, _offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(7)
+    }
+
+    fn store(&mut self, _offset: u64, _size: u64, _value: u64) -> Result<(), Exception> {
+        Ok(())
+    }
+}
+
+const COPIER: u64 = 0x3000_1000;
+
+/// Copies `len` bytes from `src` to `dst` of guest memory once the length is
+/// stored, in its next tick.
+#[derive(Debug, Default)]
+struct Copier {
+    src: u64,
+    dst: u64,
+    len: u64,
+    faulted: bool,
+}
+
+impl Device for Copier {
+    fn load(&mut self, _offset: u64, _size: u64) -> Result<u64, Exception> {
+        Ok(self.faulted.into())
+    }
+
+    fn store(&mut self, offset: u64, _size: u64, value: u64) -> Result<(), Exception> {
+        match offset {
+            0 => self.src = value,
+            8 => self.dst = value,
+            _ => self.len = value,
+        }
+        Ok(())
+    }
+
+    fn address_range(&self) -> Option<Range<u64>> {
+        Some(COPIER..COPIER + 0x18)
+    }
+
+    fn dma(&mut self, dma: &mut Dma<'_>) {
+        if self.len == 0 {
+            return;
+        }
+        let mut buf = vec![0; std::mem::take(&mut self.len) as usize];
+        self.faulted = dma
+            .read(self.src, &mut buf)
+            .and_then(|_| dma.write(self.dst, &buf))
+            .is_err();
+    }
+}
+
+/// Maps the device pages at 0 and 1 to dram pages 3 and 1, the second read
+/// only.
+#[derive(Debug)]
+struct Window;
+
+impl Iommu for Window {
+    fn translate(&self, iova: u64, len: usize, write: bool) -> Option<u64> {
+        assert!(iova % IOMMU_PAGE + len as u64 <= IOMMU_PAGE);
+        let page = match iova / IOMMU_PAGE {
+            0 => 3,
+            1 if !write => 1,
+            _ => return None,
+        };
+        Some(DRAM_BASE + page * IOMMU_PAGE + iova % IOMMU_PAGE)
+    }
+}
+
+#[test]
+fn routes() {
+    let mut bus = Bus::new(Dram::new(vec![]));
+    assert!(bus.attach(Counter::default()).is_ok());
+    assert!(bus.attach(Unmapped).is_err());
+    bus.map(BASE + 0x100, 8, Unmapped);
+
+    assert_eq!(bus.load(BASE + 0x10, 64), Ok(0x10));
+    assert_eq!(bus.load(BASE + 0x100, 64), Ok(7));
+    assert_eq!(
+        bus.load(BASE + 0x108, 64),
+        Err(Exception::LoadAccessFault(BASE + 0x108))
+    );
+    assert!(bus.is_mmio(BASE + 0xff));
+    assert!(!bus.is_mmio(BASE + 0x108));
+}
+
+#[test]
+fn ticks_and_raises() {
+    let mut bus = Bus::new(Dram::new(vec![]));
+    bus.attach(Counter::default()).unwrap();
+    bus.tick();
+    bus.tick();
+    assert_eq!(bus.load(BASE, 64), Ok(2));
+    assert_eq!(bus.pending_irqs().count(), 0);
+    bus.store(BASE, 32, 1).unwrap();
+    assert_eq!(bus.pending_irqs().collect::<Vec<_>>(), [3]);
+}
+
+#[test]
+fn machine_ticks() {
+    // j .
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .build()
+        .unwrap();
+    machine.cpu.bus.attach(Counter::default()).unwrap();
+    for _ in 0..10 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.bus.load(BASE, 64), Ok(10));
+}
+
+#[test]
+fn dma_bounds() {
+    let mut bus = Bus::new(Dram::with_size(vec![], 0x1000));
+    let mut buf = [0; 8];
+    assert_eq!(bus.dma_write(DRAM_BASE + 0xff8, b"rysk-dma"), Ok(()));
+    assert_eq!(bus.dma_read(DRAM_BASE + 0xff8, &mut buf), Ok(()));
+    assert_eq!(&buf, b"rysk-dma");
+    assert_eq!(
+        bus.dma_read(DRAM_BASE + 0xffc, &mut buf),
+        Err(Exception::LoadAccessFault(DRAM_BASE + 0xffc))
+    );
+    assert_eq!(
+        bus.dma_write(DRAM_BASE - 4, &buf),
+        Err(Exception::StoreAmoAccessFault(DRAM_BASE - 4))
+    );
+    assert_eq!(
+        bus.dma_read(u64::MAX, &mut buf),
+        Err(Exception::LoadAccessFault(u64::MAX))
+    );
+}
+
+#[test]
+fn device_dma() {
+    let mut bus = Bus::new(Dram::new(vec![]));
+    bus.attach(Copier::default()).unwrap();
+    bus.dma_write(DRAM_BASE + 0x100, b"copied").unwrap();
+    bus.reserve(0, DRAM_BASE + 0x200);
+    bus.store(COPIER, 64, DRAM_BASE + 0x100).unwrap();
+    bus.store(COPIER + 8, 64, DRAM_BASE + 0x200).unwrap();
+    bus.store(COPIER + 0x10, 64, 6).unwrap();
+    // nothing moves before the tick
+    assert!(bus.holds_reservation(0));
+
+    bus.tick();
+    let mut buf = [0; 6];
+    bus.dma_read(DRAM_BASE + 0x200, &mut buf).unwrap();
+    assert_eq!(&buf, b"copied");
+    assert_eq!(bus.load(COPIER, 64), Ok(0));
+    assert!(!bus.holds_reservation(0));
+
+    bus.store(COPIER + 8, 64, 0x1000).unwrap();
+    bus.store(COPIER + 0x10, 64, 6).unwrap();
+    bus.tick();
+    assert_eq!(bus.load(COPIER, 64), Ok(1));
+}
+
+#[test]
+fn iommu() {
+    let mut bus = Bus::new(Dram::new(vec![]));
+    bus.dma_write(DRAM_BASE + 3 * IOMMU_PAGE + 0xffe, b"ab")
+        .unwrap();
+    bus.dma_write(DRAM_BASE + IOMMU_PAGE, b"cd").unwrap();
+    bus.iommu = Some(Box::new(Window));
+
+    // split where the pages are translated
+    let mut buf = [0; 4];
+    assert_eq!(bus.dma_read(0xffe, &mut buf), Ok(()));
+    assert_eq!(&buf, b"abcd");
+    assert_eq!(bus.dma_write(0x10, b"ef"), Ok(()));
+    assert_eq!(
+        bus.dma_write(IOMMU_PAGE, b"gh"),
+        Err(Exception::StoreAmoAccessFault(IOMMU_PAGE))
+    );
+    assert_eq!(
+        bus.dma_read(2 * IOMMU_PAGE - 2, &mut buf),
+        Err(Exception::LoadAccessFault(2 * IOMMU_PAGE - 2))
+    );
+
+    bus.iommu = None;
+    let mut buf = [0; 2];
+    bus.dma_read(DRAM_BASE + 3 * IOMMU_PAGE + 0x10, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"ef");
+    bus.dma_read(DRAM_BASE + IOMMU_PAGE, &mut buf).unwrap();
+    assert_eq!(&buf, b"cd");
+}
@@ -0,0 +1,140 @@
+use rysk::{
+    bus::DRAM_BASE,
+    cache::{Cache, CacheConfig},
+    cpu::{Cpu, CACHE_BLOCK, RDCYCLE},
+    exception::Exception,
+    hpm::{Hpm, MHPMCOUNTER3, MHPMEVENT3},
+    machine::Machine,
+};
+
+#[test]
+fn parses_configs() {
+    let config: CacheConfig = "32K,8,64,10".parse().unwrap();
+    assert_eq!(
+        config,
+        CacheConfig {
+            size: 32 * 1024,
+            ways: 8,
+            line: 64,
+            miss_penalty: 10,
+        }
+    );
+    assert_eq!("256,2,16".parse::<CacheConfig>().unwrap().miss_penalty, 0);
+    // Not a power of two number of sets, line not a power of two, missing line.
+    for config in ["3K,4,64", "4K,4,48", "32K,8", "32X,8,64"] {
+        assert!(config.parse::<CacheConfig>().is_err(), "{config}");
+    }
+}
+
+#[test]
+fn evicts_least_recently_used() {
+    // A single set of two lines.
+    let mut cache = Cache::new("128,2,64".parse().unwrap()).unwrap();
+    let hits: Vec<bool> = [0, 64, 8, 128, 0, 64]
+        .into_iter()
+        .map(|addr| cache.access(addr, 8))
+        .collect();
+    assert_eq!(hits, [false, false, true, false, true, false]);
+    assert_eq!((cache.hits, cache.misses), (2, 4));
+
+    // Straddles the lines at 64 and 128, only the first is present.
+    assert!(!cache.access(120, 16));
+    assert!(cache.access(120, 16));
+}
+
+#[test]
+fn charges_misses() {
+    //   li t1, 3
+    // 1:
+    //   ld t2, -8(sp)
+    //   addi t1, t1, -1
+    //   bnez t1, 1b
+    let code = [0x00300313u32, 0xff813383, 0xfff30313, 0xfe031ce3]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .icache("4K,2,64,10".parse().unwrap())
+        .dcache("4K,2,64,100".parse().unwrap())
+        .build()
+        .unwrap();
+    machine.cpu.csrs[MHPMEVENT3] = 6;
+    machine.cpu.csrs[MHPMEVENT3 + 1] = 7;
+    machine.cpu.hpm = Hpm::from_csrs(&machine.cpu.csrs);
+    for _ in 0..10 {
+        machine.cpu.step().unwrap();
+    }
+
+    let icache = machine.cpu.icache.as_ref().unwrap();
+    let dcache = machine.cpu.dcache.as_ref().unwrap();
+    assert_eq!((icache.hits, icache.misses), (9, 1));
+    assert_eq!((dcache.hits, dcache.misses), (2, 1));
+    assert_eq!(machine.cpu.csrs[RDCYCLE], 10 + 10 + 100);
+    assert_eq!(&machine.cpu.csrs[MHPMCOUNTER3..MHPMCOUNTER3 + 2], [1, 1]);
+}
+
+#[test]
+fn fence_i_invalidates() {
+    //   fence
+    //   fence.i
+    //   j -8
+    let code = [0x0ff0000fu32, 0x0000100f, 0xff9ff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .icache("4K,2,64".parse().unwrap())
+        .build()
+        .unwrap();
+    for _ in 0..4 {
+        machine.cpu.step().unwrap();
+    }
+
+    // The jump after the fence.i fetches from the emptied cache.
+    let icache = machine.cpu.icache.as_ref().unwrap();
+    assert_eq!((icache.hits, icache.misses), (2, 2));
+}
+
+/// `cbo.*` on the block of t0, inst[24:20] selects the operation.
+fn cbo(op: u32) -> Vec<u8> {
+    (op << 20 | 5 << 15 | 0x2 << 12 | 0x0f)
+        .to_le_bytes()
+        .to_vec()
+}
+
+#[test]
+fn cbo_zero() {
+    let mut cpu = Cpu::new(cbo(4));
+    let block = DRAM_BASE + 0x100;
+    for offset in (0..CACHE_BLOCK + 8).step_by(8) {
+        cpu.bus.store(block + offset, 64, u64::MAX).unwrap();
+    }
+    cpu.regs[5] = block + 0x13;
+    cpu.step().unwrap();
+    for offset in (0..CACHE_BLOCK).step_by(8) {
+        assert_eq!(cpu.bus.load(block + offset, 64).unwrap(), 0);
+    }
+    assert_eq!(cpu.bus.load(block + CACHE_BLOCK, 64).unwrap(), u64::MAX);
+}
+
+#[test]
+fn cbo_management_checks_the_address() {
+    for op in [0, 1, 2] {
+        let mut cpu = Cpu::new(cbo(op));
+        cpu.regs[5] = DRAM_BASE;
+        cpu.step().unwrap();
+
+        let mut cpu = Cpu::new(cbo(op));
+        cpu.regs[5] = 0x10;
+        assert_eq!(cpu.step(), Err(Exception::StoreAmoAccessFault(0x10)));
+    }
+
+    // cbo.zero without Zicboz, and the reserved operation 3
+    let mut cpu = Cpu::new(cbo(4));
+    cpu.isa = "rv64i_zicbom".parse().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x0042a00f)));
+    let mut cpu = Cpu::new(cbo(3));
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x0032a00f)));
+}
@@ -0,0 +1,100 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE, compressed::expand, cpu::Cpu, disasm::disassemble, exception::Exception,
+    isa::Isa,
+};
+
+#[rstest]
+#[case::addi4spn(0x0808, 0x01010513)]
+#[case::lw(0x41c8, 0x0045a503)]
+#[case::ld(0x6588, 0x0085b503)]
+#[case::sw(0xc1c8, 0x00a5a223)]
+#[case::sd(0xe588, 0x00a5b423)]
+#[case::fld(0x2588, 0x0085b507)]
+#[case::addi(0x157d, 0xfff50513)]
+#[case::addiw(0x2505, 0x0015051b)]
+#[case::li(0x5501, 0xfe000513)]
+#[case::addi16sp(0x7139, 0xfc010113)]
+#[case::lui(0x757d, 0xfffff537)]
+#[case::srli(0x917d, 0x03f55513)]
+#[case::srai(0x8505, 0x40155513)]
+#[case::andi(0x997d, 0xfff57513)]
+#[case::sub(0x8d0d, 0x40b50533)]
+#[case::xor(0x8d2d, 0x00b54533)]
+#[case::or(0x8d4d, 0x00b56533)]
+#[case::and(0x8d6d, 0x00b57533)]
+#[case::subw(0x9d0d, 0x40b5053b)]
+#[case::addw(0x9d2d, 0x00b5053b)]
+#[case::j(0xb001, 0x801ff06f)]
+#[case::beqz(0xd101, 0xf00500e3)]
+#[case::bnez(0xed7d, 0x0e051f63)]
+#[case::slli(0x157e, 0x03f51513)]
+#[case::lwsp(0x557e, 0x0fc12503)]
+#[case::ldsp(0x757e, 0x1f813503)]
+#[case::jr(0x8082, 0x00008067)]
+#[case::mv(0x852e, 0x00b00533)]
+#[case::ebreak(0x9002, 0x00100073)]
+#[case::jalr(0x9502, 0x000500e7)]
+#[case::add(0x952e, 0x00b50533)]
+#[case::swsp(0xdfaa, 0x0ea12e23)]
+#[case::sdsp(0xffaa, 0x1ea13c23)]
+#[case::fsdsp(0xa42a, 0x00a13427)]
+fn expansion(#[case] parcel: u16, #[case] expected: u32) {
+    assert_eq!(expand(parcel), Some(expected));
+}
+
+#[rstest]
+#[case::zero(0x0000)]
+#[case::quadrant0_reserved(0x8000)]
+#[case::addi16sp_zero(0x6101)]
+#[case::lui_zero(0x6501)]
+#[case::addiw_x0(0x2001)]
+#[case::lwsp_x0(0x4002)]
+#[case::jr_x0(0x8002)]
+#[case::ca_reserved(0x9d4d)]
+fn reserved(#[case] parcel: u16) {
+    assert_eq!(expand(parcel), None);
+}
+
+#[test]
+fn mixed_lengths() {
+    // c.li a0, 5; c.addi a0, 1; addi a1, zero, 3; c.add a0, a1; c.jalr a1
+    let code = [
+        0x15, 0x45, 0x05, 0x05, 0x93, 0x05, 0x30, 0x00, 0x2e, 0x95, 0x82, 0x95,
+    ];
+    let mut cpu = Cpu::new(code.to_vec());
+    for pc in [2, 4, 8, 10] {
+        cpu.step().unwrap();
+        assert_eq!(cpu.pc, DRAM_BASE + pc);
+    }
+    assert_eq!(cpu.regs[10], 9);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, 2);
+    assert_eq!(cpu.regs[1], DRAM_BASE + 12);
+}
+
+#[test]
+fn illegal_without_c() {
+    // c.nop
+    let mut cpu = Cpu::new(vec![0x01, 0x00]);
+    cpu.isa = "rv64i".parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x0001)));
+    assert_eq!(cpu.pc, DRAM_BASE);
+}
+
+#[test]
+fn illegal_reports_the_parcel() {
+    // c.fld fa0, 8(a1)
+    let mut cpu = Cpu::new(vec![0x88, 0x25]);
+    cpu.isa = "rv64ifc".parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x2588)));
+}
+
+#[test]
+fn disassemble_compressed() {
+    assert_eq!(
+        disassemble(0x157d, DRAM_BASE).as_deref(),
+        Some("addi a0, a0, -1")
+    );
+    assert_eq!(disassemble(0x0000, DRAM_BASE), None);
+}
@@ -0,0 +1,33 @@
+use rysk::{
+    console::Console,
+    machine::{ExitReason, Machine},
+};
+
+#[test]
+fn escapes() {
+    let console = Console::from_reader(&b"ab\x01\x01c\x01?d\x01xe"[..]);
+    let mut keys = Vec::new();
+    while let Some(c) = console.read_blocking() {
+        keys.push(c);
+    }
+    assert_eq!(keys, b"ab\x01cd");
+    assert!(console.quit_requested());
+}
+
+#[test]
+fn htif_getchar() {
+    let code = std::fs::read("tests/bare/getchar.elf").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+    machine.console = Some(Console::from_reader(&b"*"[..]));
+    assert_eq!(machine.run(), ExitReason::Shutdown(b'*' as u64));
+}
+
+#[test]
+fn quit_stops_the_run() {
+    let code = std::fs::read("tests/bare/getchar.elf").unwrap();
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+    machine.console = Some(Console::from_reader(&b"\x01x"[..]));
+    assert_eq!(machine.run(), ExitReason::HostRequest);
+    assert!(machine.quit_requested());
+    assert_eq!(machine.exit_code(), None);
+}
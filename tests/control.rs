@@ -0,0 +1,144 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    thread,
+};
+
+use rysk::{
+    control::Control,
+    cpu::MIP,
+    machine::{ExitReason, Machine},
+    snapshot::Snapshot,
+};
+
+fn request(addr: SocketAddr, method: &str, target: &str, body: &str) -> (u16, Vec<u8>) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "{method} {target} HTTP/1.1\r\nHost: rysk\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let split = response.windows(4).position(|x| x == b"\r\n\r\n").unwrap();
+    let head = String::from_utf8_lossy(&response[..split]).into_owned();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, response[split + 4..].to_vec())
+}
+
+fn text(response: (u16, Vec<u8>)) -> String {
+    assert_eq!(response.0, 200, "{}", String::from_utf8_lossy(&response.1));
+    String::from_utf8(response.1).unwrap()
+}
+
+#[test]
+fn pause_inspect_resume() {
+    // addi t0, t0, 1; j -4
+    let code = [0x00128293u32, 0xffdff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let mut control = Control::bind("127.0.0.1:0").unwrap().paused();
+    let addr = control.local_addr();
+
+    let runner = thread::spawn(move || {
+        assert_eq!(control.run(&mut machine), ExitReason::HostRequest);
+        machine
+    });
+
+    assert!(text(request(addr, "GET", "/status", "")).contains("\"state\":\"paused\""));
+    assert!(text(request(addr, "GET", "/regs", "")).starts_with("{\"pc\":2147483648,"));
+    assert_eq!(
+        text(request(addr, "GET", "/mem?addr=0x80000000&len=4", "")),
+        "{\"addr\":2147483648,\"data\":\"93821200\"}"
+    );
+    text(request(addr, "POST", "/mem?addr=0x80000100", "2a00"));
+    text(request(addr, "POST", "/interrupt?code=11", ""));
+
+    text(request(addr, "POST", "/resume", ""));
+    text(request(addr, "POST", "/pause", ""));
+    let stats = text(request(addr, "GET", "/stats", ""));
+    assert!(!stats.contains("\"instret\":0,"), "{stats}");
+
+    let (status, snapshot) = request(addr, "GET", "/snapshot", "");
+    assert_eq!(status, 200);
+    let snapshot = Snapshot::read_from(snapshot.as_slice()).unwrap();
+
+    assert_eq!(request(addr, "GET", "/nothing", "").0, 404);
+    assert_eq!(request(addr, "POST", "/regs", "").0, 405);
+    assert_eq!(request(addr, "GET", "/mem?addr=0x80000000", "").0, 400);
+    text(request(addr, "POST", "/quit", ""));
+
+    let mut machine = runner.join().unwrap();
+    assert_eq!(machine.cpu.bus.load(0x8000_0100, 16), Ok(0x2a));
+    assert_eq!(machine.cpu.csrs[MIP], 1 << 11);
+    assert!(machine.cpu.regs[5] > 0);
+
+    let mut restored = Machine::builder().build().unwrap();
+    snapshot.restore(&mut restored.cpu).unwrap();
+    assert_eq!(restored.cpu.regs[5], machine.cpu.regs[5]);
+}
+
+#[test]
+fn metrics() {
+    // sw t0, 0(t1); j -4
+    let code = [0x00532023u32, 0xffdff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.regs[6] = 0x8000_0100;
+    let mut control = Control::bind("127.0.0.1:0").unwrap();
+    let addr = control.local_addr();
+    let runner = thread::spawn(move || control.run(&mut machine));
+
+    text(request(addr, "POST", "/pause", ""));
+    let metrics = text(request(addr, "GET", "/metrics", ""));
+    text(request(addr, "POST", "/quit", ""));
+    assert_eq!(runner.join().unwrap(), ExitReason::HostRequest);
+
+    assert!(metrics.contains("# TYPE rysk_instructions_retired_total counter\n"));
+    assert!(
+        !metrics.contains("rysk_instructions_retired_total 0\n"),
+        "{metrics}"
+    );
+    assert!(metrics.contains("\nrysk_mips "), "{metrics}");
+    assert!(metrics.contains("rysk_interrupts_total 0\n"), "{metrics}");
+    assert!(metrics.contains("rysk_mmio_accesses_total{kind=\"write\"} 0\n"));
+    assert!(metrics.contains(
+        "rysk_memory_accesses_total{region=\"dram\",base=\"0x80000000\",kind=\"store\",width=\"32\"} "
+    ));
+}
+
+#[test]
+fn gpio() {
+    // j .
+    let code = 0x0000006fu32.to_le_bytes().to_vec();
+    let mut machine = Machine::builder().image(code).gpio(true).build().unwrap();
+    let gpio = machine.gpio.clone().unwrap();
+    let mut control = Control::bind("127.0.0.1:0").unwrap().paused();
+    let addr = control.local_addr();
+    let runner = thread::spawn(move || control.run(&mut machine));
+
+    text(request(addr, "POST", "/gpio?pin=4&level=1", ""));
+    assert_eq!(
+        text(request(addr, "GET", "/gpio", "")),
+        "{\"levels\":16,\"output_enable\":0,\"outputs\":0}"
+    );
+    assert_eq!(request(addr, "POST", "/gpio?pin=32&level=1", "").0, 400);
+    assert_eq!(request(addr, "POST", "/gpio?pin=1&level=2", "").0, 400);
+    text(request(addr, "POST", "/quit", ""));
+    assert_eq!(runner.join().unwrap(), ExitReason::HostRequest);
+    assert!(gpio.level(4));
+
+    let mut machine = Machine::builder().build().unwrap();
+    let mut control = Control::bind("127.0.0.1:0").unwrap().paused();
+    let addr = control.local_addr();
+    let runner = thread::spawn(move || control.run(&mut machine));
+    assert_eq!(request(addr, "GET", "/gpio", "").0, 404);
+    text(request(addr, "POST", "/quit", ""));
+    runner.join().unwrap();
+}
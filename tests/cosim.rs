@@ -0,0 +1,76 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    thread,
+};
+
+use rysk::{
+    cosim::{self, Commit},
+    machine::Machine,
+};
+
+fn machine() -> Machine {
+    // addi a0, a0, 5; sd a0, -8(sp); wfi; lw a1, 0(zero)
+    let code = [0x00550513u32, 0xfea13c23, 0x10500073, 0x00002583]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    Machine::builder().image(code).build().unwrap()
+}
+
+#[test]
+fn commits() {
+    let mut machine = machine();
+    let addi = cosim::step(&mut machine);
+    assert_eq!(addi.to_string(), "pc=0x80000000 inst=0x00550513 x10=0x5");
+    assert_eq!(addi.to_string().parse(), Ok(addi));
+
+    // What the design retired.
+    let expected = "pc=0x80000004 inst=0xfea13c23".parse().unwrap();
+    assert_eq!(
+        cosim::check(&mut machine, &expected).map(|x| x.pc),
+        Ok(0x80000004)
+    );
+    let wfi = cosim::step(&mut machine);
+    assert_eq!(wfi.write, None);
+    assert_eq!(wfi.trap, None);
+
+    let expected: Commit = "pc=0x8000000c inst=0x00002583 x11=0".parse().unwrap();
+    let actual = cosim::check(&mut machine, &expected).unwrap_err();
+    assert_eq!(
+        actual.to_string(),
+        "pc=0x8000000c inst=0x00002583 trap=5,0x0"
+    );
+    assert!("inst=0x13".parse::<Commit>().is_err());
+    assert!("pc=0 inst=0x13 x32=1".parse::<Commit>().is_err());
+}
+
+#[test]
+fn serves_a_testbench() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut machine = machine();
+        cosim::serve_listener(&mut machine, &listener).unwrap();
+        machine
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+    let mut send = |command: &str| {
+        writeln!(stream, "{command}").unwrap();
+        lines.next().unwrap().unwrap()
+    };
+    assert_eq!(send("check pc=0x80000000 inst=0x00550513 x10=0x5"), "ok");
+    assert_eq!(send("set x10 0x7"), "ok");
+    assert_eq!(
+        send("check pc=0x80000004 inst=0xfea13c23 x10=0x7"),
+        "mismatch pc=0x80000004 inst=0xfea13c23"
+    );
+    assert!(send("regs").starts_with("pc=0x80000008 x1=0x0 x2=0x88000000 "));
+    assert_eq!(send("mem 0x87fffff8 0x2"), "0700");
+    assert_eq!(send("step"), "pc=0x80000008 inst=0x10500073");
+    assert!(send("frobnicate").starts_with("error"));
+    writeln!(stream, "quit").unwrap();
+    assert_eq!(server.join().unwrap().cpu.pc, 0x8000000c);
+}
@@ -0,0 +1,335 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::Cpu,
+    disasm::disassemble,
+    exception::Exception,
+    isa::{Extension, Isa},
+};
+
+/// Executes `inst` with `a` in t0 and `b` in t1, returning t2.
+fn execute(inst: u32, a: u64, b: u64) -> u64 {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = a;
+    cpu.regs[6] = b;
+    cpu.step().unwrap();
+    cpu.regs[7]
+}
+
+const AES64ES: u32 = 0x326283b3;
+const AES64ESM: u32 = 0x366283b3;
+const AES64DS: u32 = 0x3a6283b3;
+const AES64DSM: u32 = 0x3e6283b3;
+const AES64IM: u32 = 0x30029393;
+const AES64KS1I: u32 = 0x31029393;
+const AES64KS2: u32 = 0x7e6283b3;
+const SM4ED: u32 = 0x306283b3;
+const SM4KS: u32 = 0x346283b3;
+
+/// The halves of a 128-bit block, as `ld` would load them.
+fn block(bytes: [u8; 16]) -> (u64, u64) {
+    let lo = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let hi = u64::from_le_bytes(bytes[8..].try_into().unwrap());
+    (lo, hi)
+}
+
+#[test]
+fn aes128() {
+    // FIPS 197, appendix C.1
+    let key = block(core::array::from_fn(|i| i as u8));
+    let plaintext = block(core::array::from_fn(|i| (i * 0x11) as u8));
+    let ciphertext = block([
+        0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30, 0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5,
+        0x5a,
+    ]);
+
+    let mut keys = vec![key];
+    for rnum in 0..10 {
+        let (k0, k1) = *keys.last().unwrap();
+        let t = execute(AES64KS1I | rnum << 20, k1, 0);
+        let k0 = execute(AES64KS2, t, k0);
+        let k1 = execute(AES64KS2, k0, k1);
+        keys.push((k0, k1));
+    }
+
+    let (mut s0, mut s1) = (plaintext.0 ^ keys[0].0, plaintext.1 ^ keys[0].1);
+    for (round, key) in keys.iter().enumerate().skip(1) {
+        let inst = if round < 10 { AES64ESM } else { AES64ES };
+        (s0, s1) = (execute(inst, s0, s1) ^ key.0, execute(inst, s1, s0) ^ key.1);
+    }
+    assert_eq!((s0, s1), ciphertext);
+
+    // the equivalent inverse cipher, with InvMixColumns applied to the keys
+    (s0, s1) = (s0 ^ keys[10].0, s1 ^ keys[10].1);
+    for (round, key) in keys.iter().enumerate().take(10).rev() {
+        let (inst, key) = match round {
+            0 => (AES64DS, *key),
+            _ => (
+                AES64DSM,
+                (execute(AES64IM, key.0, 0), execute(AES64IM, key.1, 0)),
+            ),
+        };
+        (s0, s1) = (execute(inst, s0, s1) ^ key.0, execute(inst, s1, s0) ^ key.1);
+    }
+    assert_eq!((s0, s1), plaintext);
+}
+
+#[test]
+fn sm4() {
+    // GB/T 32907, appendix A.1: the key and the plaintext are the same
+    let bytes = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ];
+    let words = |bytes: &[u8]| -> Vec<u64> {
+        bytes
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()) as u64)
+            .collect()
+    };
+    // four applications, a byte each, make up one of the round function
+    let round = |inst: u32, x: u64, t: u64| (0..4).fold(x, |x, bs| execute(inst | bs << 30, x, t));
+
+    let fk: [u32; 4] = [0xa3b1bac6, 0x56aa3350, 0x677d9197, 0xb27022dc];
+    let mut k: Vec<u64> = words(&bytes)
+        .iter()
+        .zip(fk)
+        .map(|(w, fk)| w ^ fk.swap_bytes() as u64)
+        .collect();
+    for i in 0..32 {
+        let ck = u32::from_le_bytes(core::array::from_fn(|j| ((4 * i + j) * 7) as u8)) as u64;
+        let t = k[i + 1] ^ k[i + 2] ^ k[i + 3] ^ ck;
+        k.push(round(SM4KS, k[i], t));
+    }
+
+    let mut x = words(&bytes);
+    for i in 0..32 {
+        let t = x[i + 1] ^ x[i + 2] ^ x[i + 3] ^ k[i + 4];
+        x.push(round(SM4ED, x[i], t));
+    }
+    let ciphertext: Vec<u8> = x[32..]
+        .iter()
+        .rev()
+        .flat_map(|w| (*w as u32).to_le_bytes())
+        .collect();
+    assert_eq!(
+        ciphertext,
+        [
+            0x68, 0x1e, 0xdf, 0x34, 0xd2, 0x06, 0x96, 0x5e, 0x86, 0xb3, 0xe9, 0x4f, 0x53, 0x6e,
+            0x42, 0x46
+        ]
+    );
+}
+
+#[rstest]
+#[case::sha256sum0(0x10029393, 0x22502030)]
+#[case::sha256sum1(0x10129393, 0x4216dcad)]
+#[case::sha256sig0(0x10229393, 0xffff_ffff_c5de_c4cc)]
+#[case::sha256sig1(0x10329393, 0xffff_ffff_f480_f13e)]
+#[case::sha512sum0(0x10429393, 0x7c57_a100_c7ec_1abb)]
+#[case::sha512sum1(0x10529393, 0x7031_1233_3475_5677)]
+#[case::sha512sig0(0x10629393, 0xf92c_77c6_c4f1_aa1b)]
+#[case::sha512sig1(0x10729393, 0x0a34_60db_bd43_17ac)]
+#[case::sm3p0(0x10829393, 0x5ee0_0abc)]
+#[case::sm3p1(0x10929393, 0xffff_ffff_8d89_cdc1)]
+fn hashes(#[case] inst: u32, #[case] expected: u64) {
+    assert_eq!(execute(inst, 0x1234_5678_9abc_def0, 0), expected);
+}
+
+#[rstest]
+#[case::pack(
+    0x0862c3b3,
+    0xaaaa_aaaa_1111_1111,
+    0xbbbb_bbbb_2222_2222,
+    0x2222_2222_1111_1111
+)]
+#[case::packh(0x0862f3b3, 0x1234, 0x5678, 0x7834)]
+#[case::packw(0x0862c3bb, 0x1234, 0x8765, 0xffff_ffff_8765_1234)]
+#[case::brev8(0x6872d393, 0x0102_0380, 0, 0x8040_c001)]
+#[case::clmul(0x0a6293b3, 0x8000_0000_0000_0003, 6, 0xa)]
+#[case::clmulh(0x0a62b3b3, 0x8000_0000_0000_0003, 6, 0x3)]
+#[case::clmulh_all_ones(0x0a62b3b3, u64::MAX, 0x8000_0000_0000_0001, 0x7fff_ffff_ffff_ffff)]
+#[case::xperm8(
+    0x2862c3b3,
+    0x0807_0605_0403_0201,
+    0x0008_0700_ff01_0203,
+    0x0100_0801_0002_0304
+)]
+#[case::xperm4(
+    0x2862a3b3,
+    0xfedc_ba98_7654_3210,
+    0x0123_4567_89ab_cdef,
+    0x0123_4567_89ab_cdef
+)]
+fn bit_manipulation(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute(inst, a, b), expected);
+}
+
+#[rstest]
+// aes64es t2, t0, t1
+#[case::zkne(AES64ES, "rv64i_zknd")]
+// aes64ds t2, t0, t1
+#[case::zknd(AES64DS, "rv64i_zkne")]
+// aes64im t2, t0
+#[case::aes64im(AES64IM, "rv64i_zkne")]
+// aes64ks1i t2, t0, 11
+#[case::reserved_round(0x31b29393, "rv64i_zkn")]
+// sha256sum0 t2, t0
+#[case::zknh(0x10029393, "rv64i_zks")]
+// sm3p0 t2, t0
+#[case::zksh(0x10829393, "rv64i_zkn")]
+// sm4ed t2, t0, t1, 0
+#[case::zksed(SM4ED, "rv64i_zkn")]
+// clmul t2, t0, t1
+#[case::zbkc(0x0a6293b3, "rv64i_zbb_zbkb")]
+// xperm8 t2, t0, t1
+#[case::zbkx(0x2862c3b3, "rv64i_zbs")]
+// pack t2, t0, t1
+#[case::zbkb(0x0862c3b3, "rv64i_zbb")]
+// ror t2, t0, t1
+#[case::ror(0x6062d3b3, "rv64i_zbkc")]
+// orc.b t2, t0, which Zbkb lacks
+#[case::orc_b(0x2872d393, "rv64i_zbkb")]
+fn illegal(#[case] inst: u32, #[case] isa: &str) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.isa = isa.parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+/// Executes `inst` on RV32 with `a` in t0 and `b` in t1, returning t2.
+fn execute_rv32(inst: u32, a: u64, b: u64) -> u64 {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.isa = "rv32i_zkn_zks".parse::<Isa>().unwrap();
+    cpu.regs[5] = a as i32 as i64 as u64;
+    cpu.regs[6] = b as i32 as i64 as u64;
+    cpu.step().unwrap();
+    cpu.regs[7]
+}
+
+/// The R-type `inst` with t2, t0 and t1, and `bs` in the top of funct7.
+const fn aes32(funct7: u32, bs: u32) -> u32 {
+    (bs << 5 | funct7) << 25 | 6 << 20 | 5 << 15 | 7 << 7 | 0x33
+}
+
+#[rstest]
+#[case::es(AES64ES, 0x11, false)]
+#[case::esm(AES64ESM, 0x13, false)]
+#[case::ds(AES64DS, 0x15, true)]
+#[case::dsm(AES64DSM, 0x17, true)]
+fn aes32_rounds(#[case] aes64: u32, #[case] funct7: u32, #[case] inverse: bool) {
+    // each column of the result gathers a byte from four columns of the
+    // state, shifted one way or the other
+    let state = block(core::array::from_fn(|i| (i * 0x25 + 7) as u8));
+    let columns = [state.0, state.0 >> 32, state.1, state.1 >> 32].map(|x| x as u32 as u64);
+    let expected = (
+        execute(aes64, state.0, state.1),
+        execute(aes64, state.1, state.0),
+    );
+    let round: Vec<u64> = (0..4)
+        .map(|column| {
+            (0..4).fold(0, |acc, row| {
+                let from = match inverse {
+                    false => (column + row) % 4,
+                    true => (column + 4 - row) % 4,
+                };
+                execute_rv32(aes32(funct7, row as u32), acc, columns[from]) as u32 as u64
+            })
+        })
+        .collect();
+    assert_eq!(
+        (round[0] | round[1] << 32, round[2] | round[3] << 32),
+        expected
+    );
+}
+
+#[rstest]
+// the low and then the high halves of sha512sum0, sha512sum1, sha512sig0 and sha512sig1
+#[case::sum0(0x10429393, 0x28, 0x28)]
+#[case::sum1(0x10529393, 0x29, 0x29)]
+#[case::sig0(0x10629393, 0x2a, 0x2e)]
+#[case::sig1(0x10729393, 0x2b, 0x2f)]
+fn sha512_halves(#[case] sha512: u32, #[case] low: u32, #[case] high: u32) {
+    let x = 0x0123_4567_89ab_cdef_u64;
+    let (lo, hi) = (x & 0xffff_ffff, x >> 32);
+    let inst = |funct7: u32| funct7 << 25 | 6 << 20 | 5 << 15 | 7 << 7 | 0x33;
+    let result =
+        execute_rv32(inst(low), lo, hi) as u32 as u64 | execute_rv32(inst(high), hi, lo) << 32;
+    assert_eq!(result, execute(sha512, x, 0));
+}
+
+#[rstest]
+#[case::zip(0x08f29393, 0x0000_ffff, 0, 0x5555_5555)]
+#[case::zip_high(0x08f29393, 0xffff_0000, 0, 0xffff_ffff_aaaa_aaaa)]
+#[case::unzip(0x08f2d393, 0x5555_5555, 0, 0xffff)]
+#[case::rev8(0x6982d393, 0x1234_5678, 0, 0x7856_3412)]
+#[case::pack(0x0862c3b3, 0x1234_5678, 0x9abc_def0, 0xffff_ffff_def0_5678)]
+#[case::ror(0x6062d3b3, 1, 1, 0xffff_ffff_8000_0000)]
+#[case::rol(0x606293b3, 0x8000_0000, 1, 1)]
+#[case::rori(0x6042d393, 0x1234_5678, 0, 0xffff_ffff_8123_4567)]
+#[case::clmul(0x0a6293b3, 0x8000_0003, 6, 0xa)]
+#[case::clmulh(0x0a62b3b3, 0x8000_0003, 6, 0x3)]
+// the table is the four bytes of t0, the sign extension isn't in it
+#[case::xperm8(0x2862c3b3, 0x8433_2211, 0x0004_0300, 0x1100_8411)]
+#[case::sm3p0(0x10829393, 0x9abc_def0, 0, 0x5ee0_0abc)]
+fn rv32(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute_rv32(inst, a, b), expected);
+}
+
+#[rstest]
+#[case::aes64es(AES64ES, "rv32i_zkn")]
+#[case::aes64im(AES64IM, "rv32i_zkn")]
+#[case::sha512sum0(0x10429393, "rv32i_zkn")]
+#[case::rev8_rv64(0x6b82d393, "rv32i_zbkb")]
+#[case::aes32esi(aes32(0x11, 0), "rv64i_zkn")]
+#[case::sha512sum0r(0x5062_83b3, "rv64i_zkn")]
+#[case::zip(0x08f29393, "rv64i_zbkb")]
+#[case::aes32esi_zknd(aes32(0x11, 0), "rv32i_zknd")]
+fn other_xlen(#[case] inst: u32, #[case] isa: &str) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.isa = isa.parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[test]
+fn shared_with_zbb() {
+    // andn t2, t0, t1; rev8 t2, t0 in Zbkb without Zbb
+    for inst in [0x4062f3b3u32, 0x6b82d393] {
+        let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+        cpu.isa = "rv64i_zbkb".parse::<Isa>().unwrap();
+        assert_eq!(cpu.step(), Ok(()));
+    }
+}
+
+#[test]
+fn shorthands() {
+    let zkn = "rv64i_zkn".parse::<Isa>().unwrap();
+    let zks = "rv64i_zks".parse::<Isa>().unwrap();
+    for ext in [Extension::Zbkb, Extension::Zbkc, Extension::Zbkx] {
+        assert!(zkn.has(ext) && zks.has(ext));
+    }
+    for ext in [Extension::Zkne, Extension::Zknd, Extension::Zknh] {
+        assert!(zkn.has(ext) && !zks.has(ext));
+    }
+    for ext in [Extension::Zksed, Extension::Zksh] {
+        assert!(zks.has(ext) && !zkn.has(ext));
+    }
+}
+
+#[rstest]
+#[case(AES64ESM, "aes64esm t2, t0, t1")]
+#[case(0x31a29393, "aes64ks1i t2, t0, 10")]
+#[case(0x10729393, "sha512sig1 t2, t0")]
+#[case(0xf06283b3, "sm4ed t2, t0, t1, 3")]
+#[case(0x746283b3, "sm4ks t2, t0, t1, 1")]
+#[case(0x0862c3bb, "packw t2, t0, t1")]
+#[case(0x6872d393, "brev8 t2, t0")]
+#[case(0x2862a3b3, "xperm4 t2, t0, t1")]
+#[case(aes32(0x13, 2), "aes32esmi t2, t0, t1, 2")]
+#[case(0x5c6283b3, "sha512sig0h t2, t0, t1")]
+#[case(0x08f2d393, "unzip t2, t0")]
+fn disassembly(#[case] inst: u32, #[case] expected: &str) {
+    assert_eq!(
+        disassemble(inst as u64, DRAM_BASE).as_deref(),
+        Some(expected)
+    );
+}
@@ -0,0 +1,57 @@
+use rstest::rstest;
+use rysk::{
+    cpu::{Cpu, FCSR, MCAUSE, MCOUNTINHIBIT, MEPC, MIE, MIP, MSTATUS, MTVEC, SCAUSE, SEPC, STVEC},
+    csr::{Csr, CSRS},
+};
+
+const MSCRATCH: usize = 0x340;
+
+#[rstest]
+// csrw mscratch, t0: storage
+#[case::plain(0x34029073, MSCRATCH, u64::MAX)]
+// csrw mcountinhibit, t0: a write mask
+#[case::masked(0x32029073, MCOUNTINHIBIT, 0xffff_fffd)]
+// csrw fflags, t0: a view of fcsr
+#[case::view(0x00129073, FCSR, 0x1f)]
+fn descriptors(#[case] inst: u32, #[case] csr: usize, #[case] expected: u64) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = u64::MAX;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[csr], expected);
+}
+
+#[rstest]
+#[case::mstatus(MSTATUS, 0, u64::MAX, 1 << 63 | 0x007e_7faa)]
+// a reserved MPP keeps the previous one
+#[case::mstatus_mpp(MSTATUS, 3 << 11, 2 << 11 | 1 << 3, 3 << 11 | 1 << 3)]
+#[case::mie(MIE, 0, u64::MAX, 0xaaa)]
+// machine mode can't make its own interrupts pending
+#[case::mip(MIP, 0, u64::MAX, 0x222)]
+#[case::mip_machine(MIP, 0x888, 0, 0x888)]
+#[case::mtvec_vectored(MTVEC, 0, 0x8000_0003, 0x8000_0001)]
+#[case::mtvec_reserved(MTVEC, 0, 0x8000_0002, 0x8000_0000)]
+#[case::mepc(MEPC, 0, 0x8000_0003, 0x8000_0002)]
+#[case::mcause(MCAUSE, 0, u64::MAX, 1 << 63 | 0x3f)]
+#[case::stvec_reserved(STVEC, 0, 0x8000_0002, 0x8000_0000)]
+#[case::sepc(SEPC, 0, 0x8000_0003, 0x8000_0002)]
+#[case::scause(SCAUSE, 0, u64::MAX, 1 << 63 | 0x3f)]
+fn warl(#[case] csr: usize, #[case] old: u64, #[case] value: u64, #[case] expected: u64) {
+    // csrw csr, t0
+    let inst = (csr as u32) << 20 | 5 << 15 | 1 << 12 | 0x73;
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.csrs[csr] = old;
+    cpu.regs[5] = value;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[csr], expected);
+}
+
+#[test]
+fn reset() {
+    let cpu = Cpu::new(vec![]);
+    for (addr, csr) in CSRS.iter().enumerate() {
+        assert_eq!(cpu.csrs[addr], csr.reset);
+    }
+    // UXL = SXL = 64 bits
+    assert_eq!(cpu.csrs[MSTATUS] >> 32 & 0xf, 0b1010);
+    assert_eq!(CSRS[MSCRATCH].write_mask, Csr::PLAIN.write_mask);
+}
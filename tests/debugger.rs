@@ -0,0 +1,90 @@
+use rysk::{
+    bus::DRAM_BASE,
+    debugger::{Debugger, Stop},
+    disasm::disassemble,
+    exception::Exception,
+    machine::{ExitReason, Machine, MiB},
+    snapshot::Snapshot,
+};
+
+fn addi() -> Machine {
+    let code = std::fs::read("tests/addi.bin").expect("did you run 'make test' ?");
+    Machine::builder().memory(MiB).image(code).build().unwrap()
+}
+
+#[test]
+fn disassemble_addi() {
+    assert_eq!(
+        disassemble(0x00200e93, DRAM_BASE).as_deref(),
+        Some("addi t4, zero, 2")
+    );
+    assert_eq!(
+        disassemble(0x01df0fb3, DRAM_BASE).as_deref(),
+        Some("add t6, t5, t4")
+    );
+    assert_eq!(disassemble(0, DRAM_BASE), None);
+}
+
+#[test]
+fn breakpoints() {
+    let mut machine = addi();
+    let mut debugger = Debugger::default();
+    debugger.breakpoints.insert(DRAM_BASE + 8);
+
+    assert_eq!(
+        debugger.step(&mut machine.cpu, 10),
+        Stop::Breakpoint(DRAM_BASE + 8)
+    );
+    assert_eq!(machine.cpu.regs[30], 4);
+    assert_eq!(machine.cpu.regs[31], 0);
+
+    assert_eq!(debugger.step(&mut machine.cpu, 1), Stop::Done);
+    assert_eq!(machine.cpu.regs[31], 6);
+    assert_eq!(
+        debugger.step(&mut machine.cpu, 1),
+        Stop::Exception(Exception::IllegalInstruction(0))
+    );
+}
+
+#[test]
+fn repl() {
+    let mut machine = addi();
+    let input = "b 0x80000008\nc\nr\nl 0x80000000 1\nq\n";
+    let mut out = Vec::new();
+    Debugger::default()
+        .repl(&mut machine.cpu, input.as_bytes(), &mut out)
+        .unwrap();
+
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("breakpoint hit at 0x80000008"));
+    assert!(out.contains("0x80000008: 01df0fb3  add t6, t5, t4"));
+    assert!(out.contains("0x80000000: 00200e93  addi t4, zero, 2"));
+    assert_eq!(machine.cpu.pc, DRAM_BASE + 8);
+}
+
+#[test]
+fn snapshot_roundtrip() {
+    let mut machine = addi();
+    machine.cpu.step().unwrap();
+    machine.cpu.step().unwrap();
+
+    let mut bytes = Vec::new();
+    Snapshot::capture(&machine.cpu)
+        .write_to(&mut bytes)
+        .unwrap();
+    let snapshot = Snapshot::read_from(bytes.as_slice()).unwrap();
+    assert_eq!(snapshot, Snapshot::capture(&machine.cpu));
+
+    assert!(matches!(machine.run(), ExitReason::Exception(_)));
+    assert_eq!(machine.cpu.regs[31], 6);
+
+    let mut restored = Machine::builder().memory(MiB).build().unwrap();
+    snapshot.restore(&mut restored.cpu).unwrap();
+    assert_eq!(restored.cpu.pc, DRAM_BASE + 8);
+    assert_eq!(restored.cpu.regs[31], 0);
+    assert!(matches!(restored.run(), ExitReason::Exception(_)));
+    assert_eq!(restored.cpu.regs[31], 6);
+
+    let mut small = Machine::builder().memory(4096).build().unwrap();
+    assert!(snapshot.restore(&mut small.cpu).is_err());
+}
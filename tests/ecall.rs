@@ -0,0 +1,88 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use rysk::{
+    ecall::{name, Abi, Call, EcallTrace},
+    exception::Exception,
+    machine::Machine,
+};
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn traces_sbi_calls() {
+    //   li a7, 0x54494d45    # TIME
+    //   li a6, 0             # set_timer
+    //   li a0, 0x1234
+    //   ecall
+    //   li a7, 1             # legacy console_putchar
+    //   ecall
+    let code = [
+        0x544958b7u32,
+        0xd458889b,
+        0x00000813,
+        0x00001537,
+        0x2345051b,
+        0x00000073,
+        0x00100893,
+        0x00000073,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let output = Output::default();
+    let trace = EcallTrace::new(Abi::Sbi).log(output.clone());
+    let mut machine = Machine::builder()
+        .image(code)
+        .ecall_trace(trace.clone())
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        loop {
+            match machine.cpu.step() {
+                Ok(()) => {}
+                Err(Exception::EnvironmentCallFromMMode) => break,
+                Err(e) => panic!("{e}"),
+            }
+        }
+        machine.cpu.pc += 4;
+    }
+
+    assert_eq!(
+        trace.histogram(),
+        [
+            (String::from("legacy.console_putchar"), 1),
+            (String::from("TIME.set_timer"), 1)
+        ]
+    );
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    assert!(
+        output.starts_with(
+            "ecall TIME.set_timer(0x1234, 0x0, 0x0, 0x0, 0x0, 0x0) at pc 0x80000014\n"
+        ),
+        "{output}"
+    );
+}
+
+#[test]
+fn names() {
+    let call = |id, function| Call { id, function };
+    assert_eq!(name(Abi::Linux, call(64, 0)), "write");
+    assert_eq!(name(Abi::Linux, call(9999, 0)), "syscall 9999");
+    assert_eq!(name(Abi::Sbi, call(0x10, 3)), "BASE.probe_extension");
+    assert_eq!(name(Abi::Sbi, call(0x10, 42)), "BASE.42");
+    assert_eq!(name(Abi::Sbi, call(0x0a000000, 1)), "sbi 0xa000000.1");
+}
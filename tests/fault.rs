@@ -0,0 +1,54 @@
+use rysk::{
+    fault::{Campaign, Location, Outcome, Target},
+    machine::{ExitReason, Machine},
+};
+
+#[test]
+fn campaign() {
+    let builder = Machine::builder().elf(include_bytes!("bare/calls.elf").to_vec());
+    let campaign = Campaign {
+        target: "ra".parse().unwrap(),
+        window: None,
+        runs: 30,
+        seed: 2,
+        max_instructions: 1000,
+    };
+    let report = campaign.run(&builder).unwrap();
+    assert_eq!(report.golden, ExitReason::Shutdown(0));
+    assert_eq!(report.instructions, 40);
+    assert_eq!(report.runs.len(), 30);
+    for (fault, _) in &report.runs {
+        assert_eq!(fault.location, Location::Register(1));
+        assert!(fault.at < 40 && fault.bit < 64);
+    }
+    // A flipped return address either isn't used anymore or jumps astray.
+    let outcomes = |x: fn(&Outcome) -> bool| report.runs.iter().filter(|(_, o)| x(o)).count();
+    assert!(outcomes(|x| *x == Outcome::Masked) > 0);
+    assert!(outcomes(|x| matches!(x, Outcome::Crashed(_))) > 0);
+
+    let again = campaign.run(&builder).unwrap();
+    assert_eq!(again.runs, report.runs);
+    assert!(report
+        .to_string()
+        .contains("golden run: Shutdown(0) after 40 instructions"));
+
+    let outside = Campaign {
+        target: "0x1000-0x2000".parse().unwrap(),
+        ..campaign
+    };
+    assert!(outside.run(&builder).is_err());
+}
+
+#[test]
+fn parse_target() {
+    assert_eq!("x1-x31".parse(), Ok(Target::Registers(1..32)));
+    assert_eq!("a0".parse(), Ok(Target::Registers(10..11)));
+    assert_eq!("sp-t2".parse(), Ok(Target::Registers(2..8)));
+    assert_eq!(
+        "0x80000000-0x80001000".parse(),
+        Ok(Target::Memory(0x8000_0000..0x8000_1000))
+    );
+    assert!("x7-x3".parse::<Target>().is_err());
+    assert!("x32".parse::<Target>().is_err());
+    assert!("nowhere".parse::<Target>().is_err());
+}
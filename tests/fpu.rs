@@ -0,0 +1,362 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, FCSR, MSTATUS},
+    exception::Exception,
+    fpu::{Format, Rounding, DOUBLE, DZ, HALF, NV, NX, OF, SINGLE, UF},
+    isa::Isa,
+};
+
+const ONE: u64 = 0x3f80_0000;
+const THREE: u64 = 0x4040_0000;
+const MAX: u64 = 0x7f7f_ffff;
+const INF: u64 = 0x7f80_0000;
+const QNAN: u64 = 0x7fc0_0000;
+const SNAN: u64 = 0x7fa0_0000;
+/// The upper half of a NaN-boxed single.
+const BOX: u64 = 0xffff_ffff_0000_0000;
+
+const ONE_D: u64 = 0x3ff0_0000_0000_0000;
+const THREE_D: u64 = 0x4008_0000_0000_0000;
+const QNAN_D: u64 = 0x7ff8_0000_0000_0000;
+
+const ONE_H: u64 = 0x3c00;
+const THREE_H: u64 = 0x4200;
+/// The upper bits of a NaN-boxed half.
+const BOX_H: u64 = 0xffff_ffff_ffff_0000;
+
+#[rstest]
+#[case::nearest(Rounding::NearestEven, 0x3eaa_aaab, NX)]
+#[case::toward_zero(Rounding::TowardZero, 0x3eaa_aaaa, NX)]
+#[case::down(Rounding::Down, 0x3eaa_aaaa, NX)]
+#[case::up(Rounding::Up, 0x3eaa_aaab, NX)]
+#[case::max_magnitude(Rounding::NearestMaxMagnitude, 0x3eaa_aaab, NX)]
+fn third(#[case] rm: Rounding, #[case] expected: u64, #[case] expected_flags: u64) {
+    let mut flags = 0;
+    assert_eq!(SINGLE.div(ONE, THREE, rm, &mut flags), expected);
+    assert_eq!(flags, expected_flags);
+}
+
+#[rstest]
+#[case::overflow(MAX, MAX, Rounding::NearestEven, INF, OF | NX)]
+#[case::overflow_toward_zero(MAX, MAX, Rounding::TowardZero, MAX, OF | NX)]
+#[case::underflow(0x0080_0001, 0x3f00_0000, Rounding::NearestEven, 0x0040_0000, UF | NX)]
+#[case::exact_subnormal(0x0080_0000, 0x3f00_0000, Rounding::NearestEven, 0x0040_0000, 0)]
+#[case::invalid(INF, 0, Rounding::NearestEven, QNAN, NV)]
+#[case::signaling(SNAN, ONE, Rounding::NearestEven, QNAN, NV)]
+#[case::quiet(QNAN, ONE, Rounding::NearestEven, QNAN, 0)]
+fn multiply(
+    #[case] a: u64,
+    #[case] b: u64,
+    #[case] rm: Rounding,
+    #[case] expected: u64,
+    #[case] expected_flags: u64,
+) {
+    let mut flags = 0;
+    assert_eq!(SINGLE.mul(a, b, rm, &mut flags), expected);
+    assert_eq!(flags, expected_flags);
+}
+
+#[test]
+fn special_values() {
+    let mut flags = 0;
+    assert_eq!(SINGLE.div(ONE, 0, Rounding::NearestEven, &mut flags), INF);
+    assert_eq!(flags, DZ);
+    // x - x is -0 only rounding down
+    assert_eq!(SINGLE.sub(ONE, ONE, Rounding::NearestEven, &mut flags), 0);
+    assert_eq!(
+        SINGLE.sub(ONE, ONE, Rounding::Down, &mut flags),
+        0x8000_0000
+    );
+    assert_eq!(
+        SINGLE.sqrt(0x4080_0000, Rounding::NearestEven, &mut flags),
+        0x4000_0000
+    );
+
+    let mut flags = 0;
+    assert_eq!(
+        SINGLE.sqrt(0xbf80_0000, Rounding::NearestEven, &mut flags),
+        QNAN
+    );
+    assert_eq!(flags, NV);
+    // the multiplicands are invalid whatever the addend
+    let mut flags = 0;
+    let fma = SINGLE.fma(
+        (INF, 0, QNAN),
+        (false, false),
+        Rounding::NearestEven,
+        &mut flags,
+    );
+    assert_eq!((fma, flags), (QNAN, NV));
+
+    let mut flags = 0;
+    assert_eq!(SINGLE.min(QNAN, ONE, false, &mut flags), ONE);
+    assert_eq!(SINGLE.min(0, 0x8000_0000, false, &mut flags), 0x8000_0000);
+    assert_eq!(SINGLE.min(0, 0x8000_0000, true, &mut flags), 0);
+    assert_eq!(flags, 0);
+}
+
+#[rstest]
+#[case::nearest(Rounding::NearestEven, 0x3fd5_5555_5555_5555)]
+#[case::up(Rounding::Up, 0x3fd5_5555_5555_5556)]
+fn third_double(#[case] rm: Rounding, #[case] expected: u64) {
+    let mut flags = 0;
+    assert_eq!(DOUBLE.div(ONE_D, THREE_D, rm, &mut flags), expected);
+    assert_eq!(flags, NX);
+}
+
+#[test]
+fn third_half() {
+    let mut flags = 0;
+    assert_eq!(
+        HALF.div(ONE_H, THREE_H, Rounding::NearestEven, &mut flags),
+        0x3555
+    );
+    assert_eq!(flags, NX);
+}
+
+#[rstest]
+#[case::widened(SINGLE, DOUBLE, 0xbfc0_0000, 0xbff8_0000_0000_0000, 0)]
+#[case::negative_zero(SINGLE, DOUBLE, 0x8000_0000, 0x8000_0000_0000_0000, 0)]
+#[case::signaling(SINGLE, DOUBLE, SNAN, QNAN_D, NV)]
+#[case::narrowed(DOUBLE, SINGLE, 0x3fd5_5555_5555_5555, 0x3eaa_aaab, NX)]
+#[case::overflow(DOUBLE, SINGLE, 0x47f0_0000_0000_0000, INF, OF | NX)]
+#[case::subnormal(DOUBLE, SINGLE, 0x36a0_0000_0000_0000, 0x0000_0001, 0)]
+#[case::quiet(DOUBLE, SINGLE, QNAN_D | 1, QNAN, 0)]
+#[case::half_widened(HALF, SINGLE, 0xbe00, 0xbfc0_0000, 0)]
+#[case::half_subnormal(SINGLE, HALF, 0x3380_0000, 0x0001, 0)]
+#[case::half_overflow(SINGLE, HALF, 0x477f_f000, 0x7c00, OF | NX)]
+#[case::half_signaling(HALF, DOUBLE, 0x7d00, QNAN_D, NV)]
+fn convert(
+    #[case] from: Format,
+    #[case] to: Format,
+    #[case] x: u64,
+    #[case] expected: u64,
+    #[case] expected_flags: u64,
+) {
+    let mut flags = 0;
+    assert_eq!(
+        from.convert(to, x, Rounding::NearestEven, &mut flags),
+        expected
+    );
+    assert_eq!(flags, expected_flags);
+}
+
+#[rstest]
+#[case::truncated(0x3fc0_0000, true, Rounding::TowardZero, 1, NX)]
+#[case::rounded(0x3fc0_0000, true, Rounding::NearestEven, 2, NX)]
+#[case::negative(0xbfc0_0000, true, Rounding::Down, -2i64 as u64, NX)]
+#[case::saturated(0x4f80_0000, true, Rounding::NearestEven, i32::MAX as u64, NV)]
+#[case::nan(QNAN, true, Rounding::NearestEven, i32::MAX as u64, NV)]
+#[case::unsigned_negative(0xbf80_0000, false, Rounding::NearestEven, 0, NV)]
+#[case::unsigned_small_negative(0xbe80_0000, false, Rounding::NearestEven, 0, NX)]
+// sign extended even when unsigned
+#[case::unsigned_large(0x4f00_0000, false, Rounding::NearestEven, 0xffff_ffff_8000_0000, 0)]
+fn to_word(
+    #[case] x: u64,
+    #[case] signed: bool,
+    #[case] rm: Rounding,
+    #[case] expected: u64,
+    #[case] expected_flags: u64,
+) {
+    let mut flags = 0;
+    assert_eq!(SINGLE.to_int(x, signed, 32, rm, &mut flags), expected);
+    assert_eq!(flags, expected_flags);
+}
+
+#[rstest]
+#[case::negative_infinity(0xff80_0000, 1 << 0)]
+#[case::negative_normal(0xbf80_0000, 1 << 1)]
+#[case::negative_subnormal(0x8000_0001, 1 << 2)]
+#[case::negative_zero(0x8000_0000, 1 << 3)]
+#[case::positive_zero(0, 1 << 4)]
+#[case::positive_subnormal(0x0000_0001, 1 << 5)]
+#[case::positive_normal(ONE, 1 << 6)]
+#[case::positive_infinity(INF, 1 << 7)]
+#[case::signaling(SNAN, 1 << 8)]
+#[case::quiet(QNAN, 1 << 9)]
+fn class(#[case] x: u64, #[case] expected: u64) {
+    assert_eq!(SINGLE.class(x), expected);
+}
+
+fn cpu(code: &[u32]) -> Cpu {
+    Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect())
+}
+
+#[test]
+fn load_compute_store() {
+    // flw fa0, 0(a0); fadd.s fa1, fa0, fa0; fsw fa1, 4(a0); fmv.x.w a1, fa1
+    let mut cpu = cpu(&[0x00052507, 0x00a575d3, 0x00b52227, 0xe00585d3]);
+    let addr = DRAM_BASE + 0x100;
+    cpu.bus.store(addr, 32, 0xbfc0_0000).unwrap();
+    cpu.regs[10] = addr;
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.bus.load(addr + 4, 32).unwrap(), 0xc040_0000);
+    // fmv.x.w sign extends
+    assert_eq!(cpu.regs[11], 0xffff_ffff_c040_0000);
+    assert_eq!(cpu.fregs[11], BOX | 0xc040_0000);
+    assert_eq!(cpu.csrs[FCSR], 0);
+}
+
+#[test]
+fn load_compute_store_double() {
+    // fld fa0, 0(a0); fdiv.d fa2, fa0, fa1; fsd fa2, 8(a0); fmv.x.d a1, fa2
+    let mut cpu = cpu(&[0x00053507, 0x1ab57653, 0x00c53427, 0xe20605d3]);
+    let addr = DRAM_BASE + 0x100;
+    cpu.bus.store(addr, 64, ONE_D).unwrap();
+    cpu.regs[10] = addr;
+    cpu.fregs[11] = THREE_D;
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.bus.load(addr + 8, 64).unwrap(), 0x3fd5_5555_5555_5555);
+    assert_eq!(cpu.regs[11], 0x3fd5_5555_5555_5555);
+    assert_eq!(cpu.csrs[FCSR], NX);
+}
+
+#[test]
+fn load_compute_store_half() {
+    // flh fa0, 0(a0); fadd.h fa2, fa0, fa1; fsh fa2, 2(a0); fmv.x.h a1, fa2
+    let mut cpu = cpu(&[0x00051507, 0x04b57653, 0x00c51127, 0xe40605d3]);
+    let addr = DRAM_BASE + 0x100;
+    cpu.bus.store(addr, 32, 0xffff_3e00).unwrap();
+    cpu.regs[10] = addr;
+    cpu.fregs[11] = BOX_H | 0xc400;
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    // 1.5 - 4
+    assert_eq!(cpu.fregs[10], BOX_H | 0x3e00);
+    assert_eq!(cpu.fregs[12], BOX_H | 0xc100);
+    assert_eq!(cpu.bus.load(addr, 32).unwrap(), 0xc100_3e00);
+    // fmv.x.h sign extends
+    assert_eq!(cpu.regs[11], 0xffff_ffff_ffff_c100);
+    assert_eq!(cpu.csrs[FCSR], 0);
+}
+
+#[test]
+fn half_conversions() {
+    // fcvt.s.h fa3, fa2; fcvt.h.d fa4, fa5; fcvt.w.h a1, fa2; fmv.h.x fa0, a0
+    let mut cpu = cpu(&[0x402606d3, 0x4417f753, 0xc40675d3, 0xf4050553]);
+    cpu.fregs[12] = BOX_H | THREE_H;
+    cpu.fregs[15] = ONE_D;
+    cpu.regs[10] = 0x1234_7e00;
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.fregs[13], BOX | THREE);
+    assert_eq!(cpu.fregs[14], BOX_H | ONE_H);
+    assert_eq!(cpu.regs[11], 3);
+    assert_eq!(cpu.fregs[10], BOX_H | 0x7e00);
+    // a single isn't a valid box for a half
+    cpu.fregs[12] = BOX | ONE;
+    cpu.pc -= 8;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[11], i32::MAX as u64);
+    assert_eq!(cpu.csrs[FCSR], NV);
+}
+
+#[test]
+fn nan_boxing() {
+    // fadd.s fa1, fa0, fa0; fmv.x.w a1, fa0
+    let mut cpu = cpu(&[0x00a575d3, 0xe00505d3]);
+    // a double where a single is expected isn't a valid box
+    cpu.fregs[10] = ONE_D;
+    cpu.step().unwrap();
+    assert_eq!(cpu.fregs[11], BOX | QNAN);
+    // fmv.x.w moves the bits as they are
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[11], 0);
+}
+
+#[test]
+fn convert_and_move() {
+    // fmv.d.x fa0, a1; fcvt.s.d fa2, fa0; fcvt.d.s fa3, fa2; fcvt.w.d a0, fa0, rtz
+    let mut cpu = cpu(&[0xf2058553, 0x40157653, 0x420606d3, 0xc2051553]);
+    cpu.regs[11] = 0xc004_0000_0000_0000;
+    for _ in 0..4 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.fregs[10], 0xc004_0000_0000_0000);
+    assert_eq!(cpu.fregs[12], BOX | 0xc020_0000);
+    assert_eq!(cpu.fregs[13], 0xc004_0000_0000_0000);
+    assert_eq!(cpu.regs[10], -2i64 as u64);
+    assert_eq!(cpu.csrs[FCSR], NX);
+}
+
+#[test]
+fn accrued_flags_and_dynamic_rounding() {
+    // fsrm a1; fdiv.s fa2, fa0, fa1; frflags a1
+    let mut cpu = cpu(&[0x00259073, 0x18b57653, 0x001025f3]);
+    cpu.fregs[10] = BOX | ONE;
+    cpu.fregs[11] = BOX | THREE;
+    cpu.regs[11] = 1;
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.fregs[12], BOX | 0x3eaa_aaaa);
+    assert_eq!(cpu.regs[11], NX);
+    assert_eq!(cpu.csrs[FCSR], 1 << 5 | NX);
+}
+
+#[rstest]
+// fadd.s fa1, fa0, fa0 with the reserved rm 5
+#[case::reserved_rounding(0x00a555d3, "rv64if_zicsr")]
+// fadd.s fa1, fa0, fa0 without the F extension
+#[case::without_f(0x00a575d3, "rv64i")]
+// fadd.d fa1, fa0, fa0 without the D extension
+#[case::without_d(0x02a575d3, "rv64if_zicsr")]
+// fcvt.s.d fa2, fa0 without the D extension
+#[case::narrowing_without_d(0x40157653, "rv64if_zicsr")]
+// fmt 3 is Q, which isn't supported
+#[case::quad(0x06a575d3, "rv64ifd_zicsr")]
+// fadd.h fa2, fa0, fa1 without Zfh
+#[case::without_zfh(0x04b57653, "rv64ifd_zicsr")]
+// flh fa0, 0(a0) without Zfh
+#[case::flh_without_zfh(0x00051507, "rv64ifd_zicsr")]
+// fcvt.s.h fa3, fa2 without Zfh
+#[case::widening_without_zfh(0x402606d3, "rv64ifd_zicsr")]
+// fcvt.h.d fa4, fa3 without D
+#[case::narrowing_without_d_to_half(0x4416f753, "rv64if_zicsr_zfh")]
+fn illegal(#[case] inst: u32, #[case] isa: &str) {
+    let mut cpu = cpu(&[inst]);
+    cpu.isa = isa.parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[test]
+fn invalid_dynamic_rounding() {
+    // fadd.s fa1, fa0, fa0 with frm set to the reserved 5
+    let mut cpu = cpu(&[0x00a575d3]);
+    cpu.csrs[FCSR] = 5 << 5;
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x00a575d3)));
+}
+
+#[rstest]
+// fadd.s fa1, fa0, fa0
+#[case::fadd(0x00a575d3)]
+// frcsr a0
+#[case::fcsr(0x00302573)]
+// fsflags a0
+#[case::fflags(0x00151573)]
+fn fs_off(#[case] inst: u32) {
+    let mut cpu = cpu(&[inst]);
+    cpu.csrs[MSTATUS] &= !(0b11 << 13);
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[rstest]
+// fadd.s fa1, fa0, fa0
+#[case::write(0x00a575d3)]
+// fsrm a0
+#[case::frm(0x00251573)]
+fn dirties_fs(#[case] inst: u32) {
+    let mut cpu = cpu(&[inst]);
+    assert_eq!(cpu.csrs[MSTATUS] >> 13 & 0b11, 1);
+    cpu.step().unwrap();
+    // FS = dirty, and SD with it
+    assert_eq!(cpu.csrs[MSTATUS] >> 13 & 0b11, 3);
+    assert_eq!(cpu.csrs[MSTATUS] >> 63, 1);
+}
@@ -0,0 +1,103 @@
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use rysk::{
+    framebuffer::{FB_BASE, FB_PIXELS},
+    machine::Machine,
+    vnc,
+};
+
+fn machine() -> Machine {
+    // j .
+    Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .framebuffer(4, 2)
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn registers_and_pixels() {
+    let mut machine = machine();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(FB_BASE, 32), Ok(4));
+    assert_eq!(bus.load(FB_BASE + 4, 32), Ok(2));
+    assert_eq!(bus.load(FB_BASE + 8, 32), Ok(16));
+    // read only
+    bus.store(FB_BASE, 32, 8).unwrap();
+    assert_eq!(bus.load(FB_BASE, 32), Ok(4));
+
+    // the second row, second pixel
+    bus.store(FB_BASE + FB_PIXELS + 20, 32, 0x00ff_8000)
+        .unwrap();
+    bus.store(FB_BASE + FB_PIXELS + 24, 8, 0x40).unwrap();
+    assert_eq!(bus.load(FB_BASE + FB_PIXELS + 20, 64), Ok(0x40_00ff_8000));
+    let frame = machine.framebuffer.as_ref().unwrap().frame();
+    assert_eq!(frame.pixels[5..7], [0x00ff_8000, 0x40]);
+    assert_eq!(frame.generation, 2);
+
+    // past the last pixel
+    assert!(bus.load(FB_BASE + FB_PIXELS + 32, 32).is_err());
+}
+
+/// Reads a framebuffer update of the 4x2 framebuffer, its pixels.
+fn update(client: &mut TcpStream) -> Vec<u32> {
+    let mut header = [0; 16];
+    client.read_exact(&mut header).unwrap();
+    assert_eq!(header, [0, 0, 0, 1, 0, 0, 0, 0, 0, 4, 0, 2, 0, 0, 0, 0]);
+    let mut pixels = [0; 32];
+    client.read_exact(&mut pixels).unwrap();
+    pixels
+        .chunks(4)
+        .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn vnc_viewer() {
+    let mut machine = machine();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    vnc::serve(listener, machine.framebuffer.clone().unwrap(), None);
+    machine
+        .cpu
+        .bus
+        .store(FB_BASE + FB_PIXELS, 32, 0x0012_3456)
+        .unwrap();
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut version = [0; 12];
+    client.read_exact(&mut version).unwrap();
+    assert_eq!(&version, b"RFB 003.008\n");
+    client.write_all(b"RFB 003.008\n").unwrap();
+    // no security
+    let mut security = [0; 2];
+    client.read_exact(&mut security).unwrap();
+    assert_eq!(security, [1, 1]);
+    client.write_all(&[1]).unwrap();
+    let mut result = [0; 4];
+    client.read_exact(&mut result).unwrap();
+    assert_eq!(result, [0; 4]);
+
+    // shared, then the geometry, the pixel format and the name
+    client.write_all(&[1]).unwrap();
+    let mut init = [0; 28];
+    client.read_exact(&mut init).unwrap();
+    assert_eq!(init[..4], [0, 4, 0, 2]);
+    assert_eq!(init[4..8], [32, 24, 0, 1]);
+    assert_eq!(&init[24..], b"rysk");
+
+    client.write_all(&[3, 0, 0, 0, 0, 0, 0, 4, 0, 2]).unwrap();
+    assert_eq!(update(&mut client)[..2], [0x0012_3456, 0]);
+
+    // an incremental update waits for a change
+    client.write_all(&[3, 1, 0, 0, 0, 0, 0, 4, 0, 2]).unwrap();
+    machine
+        .cpu
+        .bus
+        .store(FB_BASE + FB_PIXELS + 4, 32, 0x00ab_cdef)
+        .unwrap();
+    assert_eq!(update(&mut client)[..2], [0x0012_3456, 0x00ab_cdef]);
+}
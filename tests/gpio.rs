@@ -0,0 +1,59 @@
+use rysk::{
+    bus::Device,
+    gpio::{Gpio, GPIO_BASE, GPIO_IRQ},
+    machine::{ExitReason, Machine, StopCondition},
+};
+
+#[test]
+fn guest_drives_and_reads_pins() {
+    // lui t0, 0x10060; li t1, 1; sw t1, 8(t0); sw t1, 12(t0)
+    // 1: lw t2, 0(t0); j 1b
+    let code = [
+        0x100602b7u32,
+        0x00100313,
+        0x0062a423,
+        0x0062a623,
+        0x0002a383,
+        0xffdff06f,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).gpio(true).build().unwrap();
+    let stop = StopCondition {
+        max_instructions: Some(100),
+        ..StopCondition::default()
+    };
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    let gpio = machine.gpio.clone().unwrap();
+    assert_eq!(gpio.output_enable(), 1);
+    assert_eq!(gpio.outputs(), 1);
+    assert_eq!(machine.cpu.regs[7], 1);
+
+    gpio.set_input(1, true);
+    // the guest drives pin 0, whatever the host says
+    gpio.set_input(0, false);
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert_eq!(machine.cpu.regs[7], 0b11);
+    assert!(gpio.level(0));
+}
+
+#[test]
+fn edge_interrupts() {
+    let mut gpio = Gpio::default();
+    assert_eq!(gpio.address_range(), Some(GPIO_BASE..GPIO_BASE + 0x1000));
+    // rising edges of pin 2, falling ones of pin 3
+    gpio.store(0x18, 32, 1 << 2).unwrap();
+    gpio.store(0x20, 32, 1 << 3).unwrap();
+
+    gpio.set_input(3, true);
+    assert_eq!(gpio.pending_irq(), None);
+    gpio.set_input(2, true);
+    assert_eq!(gpio.pending_irq(), Some(GPIO_IRQ));
+    assert_eq!(gpio.load(0x1c, 32), Ok(0b1100));
+    gpio.store(0x1c, 32, 1 << 2).unwrap();
+    assert_eq!(gpio.pending_irq(), None);
+    gpio.set_input(3, false);
+    assert_eq!(gpio.load(0x24, 32), Ok(1 << 3));
+    assert_eq!(gpio.pending_irq(), Some(GPIO_IRQ));
+}
@@ -0,0 +1,34 @@
+use rysk::{
+    elf::Elf,
+    heatmap::Heatmap,
+    machine::{ExitReason, Machine},
+};
+
+#[test]
+fn counts_instructions_by_bin() {
+    let data = include_bytes!("bare/calls.elf").to_vec();
+    let heatmap = Heatmap::from_elf(&Elf::parse(&data).unwrap()).unwrap();
+    let mut machine = Machine::builder().elf(data).build().unwrap();
+    machine.cpu.observers.add(heatmap);
+    assert_eq!(machine.run(), ExitReason::Shutdown(0));
+
+    let heatmap = machine.cpu.observers.get::<Heatmap>().unwrap();
+    assert_eq!(heatmap.bin_size(), 4);
+    assert_eq!(heatmap.counts().iter().sum::<u64>(), 40);
+    assert_eq!(heatmap.counts()[0], 1);
+
+    let mut svg = Vec::new();
+    heatmap.write_svg(&mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<svg"));
+    assert!(svg.contains(">_start</text>"));
+    assert!(svg.contains(" work: "));
+    assert!(svg.trim_end().ends_with("</svg>"));
+}
+
+#[test]
+fn large_ranges_get_larger_bins() {
+    let heatmap = Heatmap::new(0x8000_0000..0x8100_0000);
+    assert_eq!(heatmap.bin_size(), 256);
+    assert_eq!(heatmap.counts().len(), 64 * 1024);
+}
@@ -0,0 +1,74 @@
+use std::sync::{Arc, Mutex};
+
+use rysk::{
+    bus::DRAM_BASE,
+    exception::Exception,
+    machine::{ExitReason, Machine},
+};
+
+#[test]
+fn pre_and_post_hooks() {
+    let code = std::fs::read("tests/addi.bin").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().image(code).build().unwrap();
+
+    let pre = Arc::new(Mutex::new(Vec::new()));
+    let post = Arc::new(Mutex::new(Vec::new()));
+
+    let log = pre.clone();
+    machine.cpu.hooks.pre(move |ctx| {
+        log.lock()
+            .unwrap()
+            .push((ctx.pc, ctx.inst.opcode, ctx.regs[31]));
+    });
+    let log = post.clone();
+    machine.cpu.hooks.post(move |ctx| {
+        log.lock()
+            .unwrap()
+            .push((ctx.pc, ctx.inst.opcode, ctx.regs[31]));
+    });
+
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0))
+    );
+
+    // addi, addi, add, then the zeroed memory after the program which doesn't retire.
+    let pre = pre.lock().unwrap();
+    let post = post.lock().unwrap();
+    assert_eq!(pre.len(), 4);
+    assert_eq!(post.len(), 3);
+    assert_eq!(pre[2], (DRAM_BASE + 8, 0x33, 0));
+    assert_eq!(post[2], (DRAM_BASE + 8, 0x33, 6));
+    assert_eq!(pre[3], (DRAM_BASE + 12, 0, 6));
+}
+
+#[test]
+fn pause_hook() {
+    // pause; pause; addi t0, t0, 1
+    let code: Vec<u8> = [0x0100000fu32, 0x0100000f, 0x00128293]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code.clone()).build().unwrap();
+    let pauses = Arc::new(Mutex::new(Vec::new()));
+    let log = pauses.clone();
+    machine
+        .cpu
+        .hooks
+        .pause(move |ctx| log.lock().unwrap().push(ctx.pc));
+    for _ in 0..3 {
+        machine.step().unwrap();
+    }
+    assert_eq!(*pauses.lock().unwrap(), [DRAM_BASE, DRAM_BASE + 4]);
+    assert_eq!(machine.cpu.regs[5], 1);
+
+    // Without Zihintpause it's the fence it encodes.
+    let mut machine = Machine::builder().image(code).isa("rv64i").build().unwrap();
+    let log = pauses.clone();
+    machine
+        .cpu
+        .hooks
+        .pause(move |ctx| log.lock().unwrap().push(ctx.pc));
+    machine.step().unwrap();
+    assert_eq!(pauses.lock().unwrap().len(), 2);
+}
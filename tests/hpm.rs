@@ -0,0 +1,114 @@
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{INSTRET, MCOUNTINHIBIT, MIE, MSTATUS, MTVEC, RDCYCLE, RDTIME},
+    exception::Interrupt,
+    hpm::{Hpm, MHPMCOUNTER3, MHPMEVENT3},
+    machine::Machine,
+    snapshot::Snapshot,
+    time::InstretClock,
+};
+
+fn program(words: &[u32]) -> Machine {
+    let code = words.iter().flat_map(|x| x.to_le_bytes()).collect();
+    Machine::builder().image(code).build().unwrap()
+}
+
+#[test]
+fn counts_selected_events() {
+    // mhpmevent3 = loads, mhpmevent4 = branches, mhpmevent5 = taken branches,
+    // then three iterations of a loop loading from the stack:
+    //   li t1, 3
+    // 1:
+    //   ld t2, -8(sp)
+    //   addi t1, t1, -1
+    //   bnez t1, 1b
+    //   csrr a0, hpmcounter3
+    //   csrr a1, hpmcounter4
+    //   csrr a2, mhpmcounter5
+    let mut machine = program(&[
+        0x00100293, 0x32329073, 0x00300293, 0x32429073, 0x00400293, 0x32529073, 0x00300313,
+        0xff813383, 0xfff30313, 0xfe031ce3, 0xc0302573, 0xc04025f3, 0xb0502673,
+    ]);
+    for _ in 0..19 {
+        machine.cpu.step().unwrap();
+    }
+    assert_eq!(&machine.cpu.regs[10..13], [3, 3, 2]);
+}
+
+#[test]
+fn counts_exceptions() {
+    let mut machine = program(&[0]);
+    machine.cpu.csrs[MHPMEVENT3 + 2] = 5;
+    // Written directly, so the selection has to be rebuilt.
+    machine.cpu.hpm = Hpm::from_csrs(&machine.cpu.csrs);
+    assert!(machine.cpu.step().is_err());
+    assert!(machine.cpu.step().is_err());
+    assert_eq!(machine.cpu.csrs[MHPMCOUNTER3 + 2], 2);
+
+    let snapshot = Snapshot::capture(&machine.cpu);
+    let mut restored = program(&[0]);
+    snapshot.restore(&mut restored.cpu).unwrap();
+    assert_eq!(restored.cpu.hpm, machine.cpu.hpm);
+}
+
+#[test]
+fn counts_interrupts() {
+    // nops, with the timer interrupt enabled and handled by the first
+    let mut machine = program(&[0x00000013; 4]);
+    machine.cpu.csrs[MTVEC] = DRAM_BASE;
+    machine.cpu.csrs[MHPMEVENT3] = 8;
+    machine.cpu.hpm = Hpm::from_csrs(&machine.cpu.csrs);
+    machine.cpu.csrs[MSTATUS] |= 1 << 3;
+    machine.cpu.csrs[MIE] = 1 << 7;
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MHPMCOUNTER3], 0);
+
+    machine.cpu.set_pending(Interrupt::MachineTimer, true);
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MHPMCOUNTER3], 1);
+}
+
+#[test]
+fn inhibited() {
+    // csrw mcountinhibit, t0; nop; nop; then an illegal instruction
+    let mut machine = program(&[0x32029073, 0x00000013, 0x00000013, 0]);
+    machine.cpu.regs[5] = u64::MAX;
+    machine.cpu.clock = Box::new(InstretClock);
+    machine.cpu.csrs[MHPMEVENT3] = 5;
+    machine.cpu.hpm = Hpm::from_csrs(&machine.cpu.csrs);
+    machine.cpu.step().unwrap();
+    // time can't be inhibited
+    assert_eq!(machine.cpu.csrs[MCOUNTINHIBIT], 0xffff_fffd);
+    let (cycle, instret) = (machine.cpu.csrs[RDCYCLE], machine.cpu.csrs[INSTRET]);
+
+    machine.cpu.step().unwrap();
+    machine.cpu.step().unwrap();
+    assert!(machine.cpu.step().is_err());
+    assert_eq!(machine.cpu.csrs[RDCYCLE], cycle);
+    assert_eq!(machine.cpu.csrs[INSTRET], instret);
+    assert_eq!(machine.cpu.csrs[MHPMCOUNTER3], 0);
+    assert_eq!(machine.cpu.csrs[RDTIME], 3);
+    // the illegal instruction took a cycle but didn't retire
+    assert_eq!((machine.cpu.cycles, machine.cpu.retired), (4, 3));
+}
+
+#[test]
+fn faulting_load_does_not_retire() {
+    // ld t0, 0(zero), outside dram
+    let mut machine = program(&[0x00003283]);
+    machine.cpu.csrs[MTVEC] = DRAM_BASE;
+    let instret = machine.cpu.csrs[INSTRET];
+    assert!(machine.cpu.step().is_err());
+    assert_eq!(machine.cpu.csrs[INSTRET], instret);
+    assert_eq!((machine.cpu.cycles, machine.cpu.retired), (1, 0));
+}
+
+#[test]
+fn written_minstret_holds() {
+    // csrw minstret, t0
+    let mut machine = program(&[0xb0229073]);
+    machine.cpu.regs[5] = 42;
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.cpu.csrs[INSTRET], 42);
+    assert_eq!(machine.cpu.retired, 1);
+}
@@ -0,0 +1,39 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use rysk::machine::{ExitReason, Machine};
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn syscalls_and_exit() {
+    let code = std::fs::read("tests/bare/htif.elf").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+
+    let output = Output::default();
+    let htif = machine.htif.take().expect("tohost should be found");
+    machine.htif = Some(htif.with_output(output.clone()));
+
+    assert_eq!(machine.run(), ExitReason::Shutdown(6));
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"hello\n!");
+}
+
+#[test]
+fn no_htif_without_tohost() {
+    let code = std::fs::read("tests/addi.bin").unwrap();
+    let machine = Machine::builder().image(code).build().unwrap();
+    assert!(machine.htif.is_none());
+}
@@ -0,0 +1,128 @@
+use rysk::{
+    bus::{Bus, Device},
+    i2c::{eeprom::Eeprom, I2c, I2cDevice, I2C_BASE, I2C_IRQ},
+    machine::Machine,
+};
+
+const CTR: u64 = I2C_BASE + 0x08;
+const TXR: u64 = I2C_BASE + 0x0c;
+const CR: u64 = I2C_BASE + 0x10;
+
+const STA: u64 = 0x80;
+const STO: u64 = 0x40;
+const RD: u64 = 0x20;
+const WR: u64 = 0x10;
+const NACK: u64 = 0x08;
+
+fn machine() -> Machine {
+    let mut machine = Machine::builder().i2c(true).build().unwrap();
+    machine.cpu.bus.store(CTR, 8, 0x80).unwrap();
+    machine
+}
+
+/// Runs a command, returning whether the device acknowledged.
+fn command(bus: &mut Bus, tx: Option<u8>, command: u64) -> bool {
+    if let Some(byte) = tx {
+        bus.store(TXR, 8, byte.into()).unwrap();
+    }
+    bus.store(CR, 8, command).unwrap();
+    let status = bus.load(CR, 8).unwrap();
+    assert_eq!(status & 1, 1, "the interrupt flag is set");
+    status & 0x80 == 0
+}
+
+/// Writes `bytes` to the device at `address`.
+fn write(bus: &mut Bus, address: u8, bytes: &[u8]) {
+    assert!(command(bus, Some(address << 1), STA | WR));
+    for (i, byte) in bytes.iter().enumerate() {
+        let stop = if i == bytes.len() - 1 { STO } else { 0 };
+        assert!(command(bus, Some(*byte), WR | stop));
+    }
+}
+
+/// Reads `n` bytes from the device at `address`.
+fn read(bus: &mut Bus, address: u8, n: usize) -> Vec<u8> {
+    assert!(command(bus, Some(address << 1 | 1), STA | WR));
+    (0..n)
+        .map(|i| {
+            let last = if i == n - 1 { STO | NACK } else { 0 };
+            command(bus, None, RD | last);
+            bus.load(TXR, 8).unwrap() as u8
+        })
+        .collect()
+}
+
+#[test]
+fn eeprom() {
+    let mut machine = machine();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(read(bus, 0x50, 2), [0xff, 0xff]);
+
+    write(bus, 0x50, &[0x10, 1, 2, 3]);
+    // set the address, then a repeated start to read
+    assert!(command(bus, Some(0x50 << 1), STA | WR));
+    assert!(command(bus, Some(0x0f), WR));
+    assert_eq!(read(bus, 0x50, 5), [0xff, 1, 2, 3, 0xff]);
+
+    // a write wraps within its page of 8 bytes
+    write(bus, 0x50, &[0x26, 0xa, 0xb, 0xc]);
+    write(bus, 0x50, &[0x20]);
+    assert_eq!(
+        read(bus, 0x50, 8),
+        [0xc, 0xff, 0xff, 0xff, 0xff, 0xff, 0xa, 0xb]
+    );
+}
+
+#[test]
+fn lm75() {
+    let mut machine = machine();
+    let sensor = machine.temperature_sensor.clone().unwrap();
+    let bus = &mut machine.cpu.bus;
+    // 25 °C
+    assert_eq!(read(bus, 0x48, 2), [0x19, 0x00]);
+    sensor.set_temperature(-10_500);
+    assert_eq!(read(bus, 0x48, 2), [0xf5, 0x80]);
+    assert_eq!(sensor.temperature(), -10_500);
+
+    // the overtemperature limit, 80 °C, set to 90.5 °C
+    write(bus, 0x48, &[3]);
+    assert_eq!(read(bus, 0x48, 2), [80, 0]);
+    write(bus, 0x48, &[3, 90, 0x80]);
+    assert_eq!(read(bus, 0x48, 2), [90, 0x80]);
+    // the temperature is read only
+    write(bus, 0x48, &[0, 1, 0]);
+    assert_eq!(read(bus, 0x48, 2), [0xf5, 0x80]);
+}
+
+#[test]
+fn missing_device_and_interrupt() {
+    let mut machine = machine();
+    let bus = &mut machine.cpu.bus;
+    assert!(!command(bus, Some(0x10 << 1), STA | WR | STO));
+    command(bus, Some(0x50 << 1), STA | WR);
+    // the bus is busy until the stop
+    assert_eq!(bus.load(CR, 8).unwrap() & 0x40, 0x40);
+    command(bus, None, STO);
+    assert_eq!(bus.load(CR, 8).unwrap() & 0x40, 0);
+
+    let mut i2c = I2c::default();
+    i2c.attach(0x50, Eeprom::new(256, 8));
+    i2c.store(0x08, 8, 0xc0).unwrap();
+    assert_eq!(i2c.pending_irq(), None);
+    i2c.store(0x0c, 8, 0x50 << 1).unwrap();
+    i2c.store(0x10, 8, STA | WR).unwrap();
+    assert_eq!(i2c.pending_irq(), Some(I2C_IRQ));
+    // acknowledged
+    i2c.store(0x10, 8, 0x01).unwrap();
+    assert_eq!(i2c.pending_irq(), None);
+}
+
+#[test]
+fn two_byte_addresses() {
+    let mut eeprom = Eeprom::new(4096, 32);
+    assert!(eeprom.start(false));
+    for byte in [0x01, 0x23, 0x42] {
+        eeprom.write(byte);
+    }
+    assert_eq!(eeprom.data()[0x123], 0x42);
+}
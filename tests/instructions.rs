@@ -1,11 +1,17 @@
 use std::{fs::File, io::Read};
 
 use rstest::rstest;
-use rysk::cpu::Cpu;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, MisalignedPolicy},
+    exception::Exception,
+};
 
 #[rstest]
 #[case::addi("tests/addi.bin", &[(31, 6)], &[], &[])]
-#[case::csr("tests/csr.bin", &[(5, 1), (6, 2), (7, 3)], &[], &[(256, 4), (261, 5), (321, 6), (768, 1), (773, 2), (833, 3)])]
+// mstatus bit 0 is reserved, as is the mtvec mode 2, and mepc is aligned
+#[case::csr("tests/csr.bin", &[(5, 1), (6, 2), (7, 2)], &[], &[(261, 5), (321, 6), (768, 0xa_0000_0000), (773, 0), (833, 2)])]
+#[case::shift("tests/shift.bin", &[(6, 1 << 44), (7, 16), (29, -4i64 as u64), (30, 0xf), (31, 0x8000_1018)], &[], &[])]
 #[case::fib("tests/fib.bin", &[(14, 1), (15, 0x37)], &[], &[])]
 fn run_test(
     #[case] path: &str,
@@ -18,7 +24,7 @@ fn run_test(
     file.read_to_end(&mut code).unwrap();
 
     let mut cpu = Cpu::new(code);
-    cpu.run().unwrap();
+    cpu.run();
 
     cpu.dump_registers();
     cpu.dump_csr();
@@ -37,3 +43,251 @@ fn run_test(
         assert_eq!(cpu.csrs[*addr], *value, "csrs mismatch");
     }
 }
+
+const MIN: u64 = i64::MIN as u64;
+const MIN_W: u64 = i32::MIN as i64 as u64;
+
+/// Executes an M extension instruction with `a` in t0 and `b` in t1,
+/// returning t2.
+fn muldiv(opcode: u32, funct3: u32, a: u64, b: u64) -> u64 {
+    let inst = 1 << 25 | 6 << 20 | 5 << 15 | funct3 << 12 | 7 << 7 | opcode;
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = a;
+    cpu.regs[6] = b;
+    cpu.step().unwrap();
+    cpu.regs[7]
+}
+
+#[rstest]
+#[case::mul(0, 3, -7i64 as u64, -21i64 as u64)]
+#[case::mulh(1, -1i64 as u64, -1i64 as u64, 0)]
+#[case::mulh_negative(1, MIN, 2, -1i64 as u64)]
+#[case::mulhsu(2, -1i64 as u64, u64::MAX, -1i64 as u64)]
+#[case::mulhu(3, u64::MAX, u64::MAX, u64::MAX - 1)]
+#[case::div(4, -7i64 as u64, 2, -3i64 as u64)]
+#[case::div_by_zero(4, 5, 0, u64::MAX)]
+#[case::div_overflow(4, MIN, -1i64 as u64, MIN)]
+#[case::divu(5, u64::MAX, 2, u64::MAX / 2)]
+#[case::divu_by_zero(5, 5, 0, u64::MAX)]
+#[case::rem(6, -7i64 as u64, 2, -1i64 as u64)]
+#[case::rem_by_zero(6, -7i64 as u64, 0, -7i64 as u64)]
+#[case::rem_overflow(6, MIN, -1i64 as u64, 0)]
+#[case::remu(7, 7, 4, 3)]
+#[case::remu_by_zero(7, 7, 0, 7)]
+fn m_extension(#[case] funct3: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(muldiv(0x33, funct3, a, b), expected);
+}
+
+#[rstest]
+#[case::mulw(0, 0x1_0000_0003, 0x8000_0000, MIN_W)]
+#[case::divw(4, -7i64 as u64, 2, -3i64 as u64)]
+#[case::divw_by_zero(4, 5, 0x1_0000_0000, u64::MAX)]
+#[case::divw_overflow(4, MIN_W, -1i64 as u64, MIN_W)]
+#[case::divuw(5, 0xffff_fffe, 1, -2i64 as u64)]
+#[case::divuw_by_zero(5, 5, 0, u64::MAX)]
+#[case::remw(6, -7i64 as u64, 2, -1i64 as u64)]
+#[case::remw_by_zero(6, 0x8000_0001, 0, 0xffff_ffff_8000_0001)]
+#[case::remw_overflow(6, MIN_W, -1i64 as u64, 0)]
+#[case::remuw(7, 0xffff_ffff, 0x1_0000_0000, 0xffff_ffff_ffff_ffff)]
+#[case::remuw_low_bits(7, 0x1_0000_0007, 4, 3)]
+fn m_extension_words(#[case] funct3: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(muldiv(0x3b, funct3, a, b), expected);
+}
+
+/// Scratch memory for the atomics, past the code.
+const SCRATCH: u64 = DRAM_BASE + 0x100;
+
+fn atomic(funct5: u32, funct3: u32) -> u32 {
+    funct5 << 27 | 6 << 20 | 5 << 15 | funct3 << 12 | 7 << 7 | 0x2f
+}
+
+/// Executes an AMO on `memory` at [`SCRATCH`] with `src` in t1, returning
+/// t2 and the memory after.
+fn amo(funct5: u32, funct3: u32, memory: u64, src: u64) -> (u64, u64) {
+    let mut cpu = Cpu::new(atomic(funct5, funct3).to_le_bytes().to_vec());
+    cpu.bus.store(SCRATCH, 64, memory).unwrap();
+    cpu.regs[5] = SCRATCH;
+    cpu.regs[6] = src;
+    cpu.step().unwrap();
+    (cpu.regs[7], cpu.bus.load(SCRATCH, 64).unwrap())
+}
+
+#[rstest]
+#[case::amoswap(0x01, 5, 7, 5, 7)]
+#[case::amoadd(0x00, 5, u64::MAX, 5, 4)]
+#[case::amoxor(0x04, 0b1100, 0b1010, 0b1100, 0b0110)]
+#[case::amoand(0x0c, 0b1100, 0b1010, 0b1100, 0b1000)]
+#[case::amoor(0x08, 0b1100, 0b1010, 0b1100, 0b1110)]
+#[case::amomin(0x10, 1, -1i64 as u64, 1, -1i64 as u64)]
+#[case::amomax(0x14, -1i64 as u64, 1, -1i64 as u64, 1)]
+#[case::amominu(0x18, 1, u64::MAX, 1, 1)]
+#[case::amomaxu(0x1c, 1, u64::MAX, 1, u64::MAX)]
+fn amo_doubles(
+    #[case] funct5: u32,
+    #[case] memory: u64,
+    #[case] src: u64,
+    #[case] rd: u64,
+    #[case] after: u64,
+) {
+    assert_eq!(amo(funct5, 0b011, memory, src), (rd, after));
+}
+
+#[rstest]
+#[case::amoswap(0x01, 0xaaaa_aaaa_8000_0000, 7, MIN_W, 0xaaaa_aaaa_0000_0007)]
+#[case::amoadd(0x00, 0xffff_ffff, 1, u64::MAX, 0)]
+#[case::amomin(0x10, 0x8000_0000, 1, MIN_W, 0x8000_0000)]
+#[case::amomax(0x14, 0x8000_0000, 1, MIN_W, 1)]
+#[case::amominu(0x18, 0x8000_0000, 0xffff_ffff_0000_0001, MIN_W, 1)]
+#[case::amomaxu(0x1c, 0x8000_0000, 0xffff_ffff_0000_0001, MIN_W, 0x8000_0000)]
+fn amo_words(
+    #[case] funct5: u32,
+    #[case] memory: u64,
+    #[case] src: u64,
+    #[case] rd: u64,
+    #[case] after: u64,
+) {
+    assert_eq!(amo(funct5, 0b010, memory, src), (rd, after));
+}
+
+#[test]
+fn lr_sc() {
+    // lr.w t2, (t0); sc.w t2, t1, (t0); sc.w t2, t1, (t0)
+    let code = [
+        atomic(0b00010, 0b010) & !(0x1f << 20),
+        atomic(0b00011, 0b010),
+        atomic(0b00011, 0b010),
+    ];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.bus.store(SCRATCH, 32, 0x8000_0000).unwrap();
+    cpu.regs[5] = SCRATCH;
+    cpu.regs[6] = 5;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], MIN_W);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0);
+    assert_eq!(cpu.bus.load(SCRATCH, 32).unwrap(), 5);
+    // the reservation went with the first sc
+    cpu.regs[6] = 6;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 1);
+    assert_eq!(cpu.bus.load(SCRATCH, 32).unwrap(), 5);
+}
+
+#[test]
+fn sc_fails_after_store() {
+    // lr.d t2, (t0); sc.d t2, t1, (t0)
+    let code = [
+        atomic(0b00010, 0b011) & !(0x1f << 20),
+        atomic(0b00011, 0b011),
+    ];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.regs[5] = SCRATCH;
+    cpu.regs[6] = 5;
+    cpu.step().unwrap();
+    // another hart writing the granule
+    cpu.bus.store(SCRATCH + 4, 32, 1).unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 1);
+    assert_eq!(cpu.bus.load(SCRATCH, 64).unwrap(), 1 << 32);
+}
+
+#[rstest]
+#[case::lr(atomic(0b00010, 0b011) & !(0x1f << 20), Exception::LoadAddressMisaligned(SCRATCH + 4))]
+#[case::sc(atomic(0b00011, 0b011), Exception::StoreAmoAddressMisaligned(SCRATCH + 4))]
+#[case::amoadd(atomic(0x00, 0b011), Exception::StoreAmoAddressMisaligned(SCRATCH + 4))]
+#[case::funct5(atomic(0x1f, 0b011), Exception::IllegalInstruction(atomic(0x1f, 0b011) as u64))]
+#[case::funct3(atomic(0x00, 0b000), Exception::IllegalInstruction(atomic(0x00, 0b000) as u64))]
+fn atomic_exceptions(#[case] inst: u32, #[case] expected: Exception) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = SCRATCH + 4;
+    assert_eq!(cpu.step().unwrap_err(), expected);
+    assert_eq!(cpu.regs[7], 0);
+}
+
+// ld t2, 0(t0); sd t1, 0(t0)
+const LD: u32 = 0x0002b383;
+const SD: u32 = 0x0062b023;
+
+#[test]
+fn misaligned_emulated() {
+    let mut cpu = Cpu::new([LD, SD].iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.bus.store(SCRATCH, 64, 0x8877_6655_4433_2211).unwrap();
+    cpu.bus.store(SCRATCH + 8, 64, 0xff).unwrap();
+    cpu.regs[5] = SCRATCH + 3;
+    cpu.regs[6] = 0x0102_0304_0506_0708;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0xff88_7766_5544);
+    cpu.step().unwrap();
+    assert_eq!(cpu.bus.load(SCRATCH, 64).unwrap(), 0x0405_0607_0833_2211);
+    assert_eq!(cpu.bus.load(SCRATCH + 8, 64).unwrap(), 0x0001_0203);
+}
+
+#[rstest]
+#[case::ld(LD, Exception::LoadAddressMisaligned(SCRATCH + 3))]
+#[case::sd(SD, Exception::StoreAmoAddressMisaligned(SCRATCH + 3))]
+fn misaligned_trapped(#[case] inst: u32, #[case] expected: Exception) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.misaligned = MisalignedPolicy::Trap;
+    cpu.regs[5] = SCRATCH + 3;
+    assert_eq!(cpu.step(), Err(expected));
+
+    // aligned accesses aren't affected
+    cpu.regs[5] = SCRATCH;
+    assert_eq!(cpu.step(), Ok(()));
+}
+
+#[test]
+fn misaligned_past_the_end() {
+    // the first byte outside of dram faults
+    let mut cpu = Cpu::new(LD.to_le_bytes().to_vec());
+    let end = cpu.bus.dram.base + cpu.bus.dram.size();
+    cpu.regs[5] = end - 3;
+    assert_eq!(cpu.step(), Err(Exception::LoadAccessFault(end)));
+}
+
+#[rstest]
+#[case::eqz_zero(0b101, 5, 0, 0)]
+#[case::eqz_nonzero(0b101, 5, 1 << 63, 5)]
+#[case::nez_zero(0b111, 5, 0, 5)]
+#[case::nez_nonzero(0b111, 5, 1 << 63, 0)]
+fn czero(#[case] funct3: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    // czero.eqz/czero.nez t2, t0, t1
+    let inst = 7 << 25 | 6 << 20 | 5 << 15 | funct3 << 12 | 7 << 7 | 0x33;
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = a;
+    cpu.regs[6] = b;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], expected);
+
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.isa = "rv64i".parse().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[rstest]
+#[case::csrrw(0b001, 0xf0, 0x3c, 0x3c)]
+#[case::csrrs(0b010, 0xf0, 0x3c, 0xfc)]
+#[case::csrrc(0b011, 0xf0, 0x3c, 0xc0)]
+#[case::csrrwi(0b101, 0xf0, 0x1c, 0x1c)]
+#[case::csrrsi(0b110, 0xf0, 0x1c, 0xfc)]
+#[case::csrrci(0b111, 0xf0, 0x1c, 0xe0)]
+fn csr_ops(#[case] funct3: u32, #[case] csr: u64, #[case] src: u64, #[case] expected: u64) {
+    // csrrX t1, mscratch, t0 or uimm
+    let src_field = if funct3 & 0b100 == 0 { 5 } else { src as u32 };
+    let inst = 0x340 << 20 | src_field << 15 | funct3 << 12 | 6 << 7 | 0x73;
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.csrs[0x340] = csr;
+    cpu.regs[5] = src;
+    cpu.step().unwrap();
+    assert_eq!((cpu.regs[6], cpu.csrs[0x340]), (csr, expected));
+
+    // with the source as the destination, the old value of the source counts
+    if funct3 & 0b100 == 0 {
+        let inst = 0x340 << 20 | 5 << 15 | funct3 << 12 | 5 << 7 | 0x73;
+        let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+        cpu.csrs[0x340] = csr;
+        cpu.regs[5] = src;
+        cpu.step().unwrap();
+        assert_eq!((cpu.regs[5], cpu.csrs[0x340]), (csr, expected));
+    }
+}
@@ -0,0 +1,60 @@
+use rysk::{
+    exception::Interrupt,
+    latency::{InterruptLatency, Latency, Summary},
+    machine::Machine,
+    observer::ExecutionObserver,
+};
+
+#[test]
+fn measures_raise_to_take() {
+    // nops
+    let code = [0x00000013u32; 16]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.observers.add(InterruptLatency::new());
+    let cpu = &mut machine.cpu;
+
+    for delay in [3, 5] {
+        cpu.set_pending(Interrupt::MachineTimer, true);
+        for _ in 0..delay {
+            cpu.step().unwrap();
+        }
+        // Taking interrupts isn't up to the cpu yet, stand in for it.
+        let latency = cpu.observers.get_mut::<InterruptLatency>().unwrap();
+        latency.on_interrupt(cpu.pc, Interrupt::MachineTimer);
+        cpu.set_pending(Interrupt::MachineTimer, false);
+    }
+    cpu.set_pending(Interrupt::MachineExternal, true);
+    cpu.set_pending(Interrupt::MachineExternal, false);
+
+    let latency = cpu.observers.get::<InterruptLatency>().unwrap();
+    let summary = Summary {
+        min: 3,
+        max: 5,
+        total: 8,
+    };
+    assert_eq!(
+        latency.latencies().collect::<Vec<_>>(),
+        [
+            (
+                Interrupt::MachineTimer,
+                Latency {
+                    count: 2,
+                    withdrawn: 0,
+                    instructions: summary,
+                    cycles: summary,
+                }
+            ),
+            (
+                Interrupt::MachineExternal,
+                Latency {
+                    withdrawn: 1,
+                    ..Latency::default()
+                }
+            )
+        ]
+    );
+    assert!(latency.to_string().contains("3/4/5"));
+}
@@ -0,0 +1,695 @@
+use std::time::{Duration, Instant};
+
+use rysk::{
+    aclint::MSWI_BASE,
+    cpu::{Mode, MHARTID, MIE, MIP, SATP},
+    elf::Elf,
+    error::EmulatorError,
+    exception::{Exception, Interrupt},
+    isa::{Extension, Isa, IsaError, Xlen},
+    machine::{
+        parse_size, EbreakPolicy, ExitReason, Firmware, GiB, KiB, Machine, MiB, StopCondition,
+        WfiPolicy, HART_QUANTUM,
+    },
+};
+
+#[test]
+fn parse_isa() {
+    let isa: Isa = "rv64ima_zicsr_zicond".parse().unwrap();
+    assert!(isa.has(Extension::M));
+    assert!(isa.has(Extension::Zicond));
+    assert!(!isa.has(Extension::Zicntr));
+
+    let isa: Isa = "rv64gc".parse().unwrap();
+    assert!(isa.has(Extension::D));
+    assert!(isa.has(Extension::Zifencei));
+    assert!(!isa.has(Extension::Zba));
+
+    let isa: Isa = "rv32imac_zicsr".parse().unwrap();
+    assert_eq!(isa.xlen, Xlen::Rv32);
+    assert!(isa.has(Extension::C));
+    assert_eq!(
+        "rv32imafd".parse::<Isa>(),
+        Err(IsaError::UnsupportedOnRv32("f".to_string()))
+    );
+    assert_eq!(
+        "rv128i".parse::<Isa>(),
+        Err(IsaError::UnsupportedBase("rv128i".to_string()))
+    );
+    assert_eq!("rv64m".parse::<Isa>(), Err(IsaError::MissingBase));
+    assert_eq!(
+        "rv64i_zfoo".parse::<Isa>(),
+        Err(IsaError::UnsupportedExtension("zfoo".to_string()))
+    );
+}
+
+#[test]
+fn misa() {
+    assert_eq!(Isa::default().misa(), 0x8000_0000_0034_112f);
+    let rv32: Isa = "rv32imac_zicsr".parse().unwrap();
+    assert_eq!(rv32.misa(), 0x4014_1105);
+
+    // only what was configured comes back, I never goes away
+    let isa: Isa = "rv64imafd".parse().unwrap();
+    let none = isa.write_misa(0).unwrap();
+    assert_eq!(none.misa(), 0x8000_0000_0014_0100);
+    assert_eq!(none.write_misa(u64::MAX), Some(isa));
+    // D without F
+    assert_eq!(isa.write_misa(isa.misa() & !(1 << 5)), None);
+}
+
+#[test]
+fn misa_write_disables() {
+    // csrw misa, t0; mul t2, t0, t1, with M off
+    let code = [0x30129073u32, 0x026283b3]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.regs[5] = Isa::default().misa() & !(1 << 12);
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0x026283b3))
+    );
+    assert!(!machine.cpu.isa.has(Extension::M));
+
+    // c.nop; csrw misa, t0 with C off, which the next pc being misaligned drops
+    let code = [0x01, 0x00, 0x73, 0x90, 0x12, 0x30].to_vec();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.regs[5] = Isa::default().misa() & !(1 << 2);
+    machine.cpu.step().unwrap();
+    machine.cpu.step().unwrap();
+    assert!(machine.cpu.isa.has(Extension::C));
+}
+
+#[test]
+fn builder_memory() {
+    let machine = Machine::builder().memory(4 * MiB).build().unwrap();
+    assert_eq!(machine.cpu.bus.dram.size(), 4 * MiB);
+    assert_eq!(machine.cpu.regs[2], machine.cpu.pc + 4 * MiB);
+
+    let err = Machine::builder()
+        .memory(2)
+        .image(vec![0; 4])
+        .build()
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        EmulatorError::ImageTooLarge {
+            image: 4,
+            memory: 2
+        }
+    ));
+}
+
+#[test]
+fn builder_uart_stdio() {
+    let machine = Machine::builder().build().unwrap();
+    assert!(machine.console.is_none());
+    let machine = Machine::builder().uart_stdio().build().unwrap();
+    assert!(machine.console.is_some());
+}
+
+#[test]
+fn builder_isa_gates_extensions() {
+    // mul x31, x30, x29
+    let mul = 0x03df0fb3u32.to_le_bytes().to_vec();
+
+    let mut machine = Machine::builder().isa("rv64i").image(mul).build().unwrap();
+    machine.cpu.regs[29] = 6;
+    machine.cpu.regs[30] = 7;
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0x03df0fb3))
+    );
+    assert_eq!(machine.cpu.regs[31], 0, "mul executed without M");
+
+    // mul x31, x30, x29; div x31, x30, x29 with only the multiplications
+    let mul_div = [0x03df0fb3u32, 0x03df4fb3]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .isa("rv64i_zmmul")
+        .image(mul_div)
+        .build()
+        .unwrap();
+    machine.cpu.regs[29] = 6;
+    machine.cpu.regs[30] = 7;
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0x03df4fb3))
+    );
+    assert_eq!(machine.cpu.regs[31], 42);
+
+    // fence; fence.i
+    let fences = [0x0ff0000fu32, 0x0000100f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .isa("rv64i")
+        .image(fences)
+        .build()
+        .unwrap();
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0x0000100f))
+    );
+}
+
+#[test]
+fn step_reports_exceptions() {
+    // ld x1, 0(x0)
+    let ld = 0x00003083u32.to_le_bytes().to_vec();
+    let mut machine = Machine::builder().image(ld).build().unwrap();
+    assert_eq!(machine.cpu.step(), Err(Exception::LoadAccessFault(0)));
+
+    // load with the reserved funct3=7
+    let inst = 0x00007003u32;
+    let mut machine = Machine::builder()
+        .image(inst.to_le_bytes().to_vec())
+        .build()
+        .unwrap();
+    assert_eq!(
+        machine.cpu.step(),
+        Err(Exception::IllegalInstruction(inst as u64))
+    );
+}
+
+#[test]
+fn parse_memory_size() {
+    assert_eq!(parse_size("4096"), Some(4096));
+    assert_eq!(parse_size("64K"), Some(64 * KiB));
+    assert_eq!(parse_size("128MiB"), Some(128 * MiB));
+    assert_eq!(parse_size("1g"), Some(GiB));
+    assert_eq!(parse_size("12T"), None);
+    assert_eq!(parse_size("M"), None);
+}
+
+#[test]
+fn run_until_stop_conditions() {
+    // li t0, 5; auipc t1, 0; sd t0, 256(t1); wfi; li t2, 1
+    let code: Vec<u8> = [
+        0x00500293u32,
+        0x00000317,
+        0x10533023,
+        0x10500073,
+        0x00100393,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code.clone()).build().unwrap();
+    let base = machine.cpu.pc;
+
+    let stop = StopCondition {
+        max_instructions: Some(1),
+        ..StopCondition::default()
+    };
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert_eq!(machine.cpu.pc, base + 4);
+
+    let stop = StopCondition {
+        breakpoints: [base + 4].into(),
+        ..StopCondition::default()
+    };
+    // Resuming from a breakpoint executes it.
+    assert_eq!(
+        machine.run_until(&stop),
+        ExitReason::Exception(Exception::IllegalInstruction(0))
+    );
+
+    let mut machine = Machine::builder().image(code.clone()).build().unwrap();
+    let stop = StopCondition {
+        breakpoints: [base + 8].into(),
+        watchpoints: vec![0..8, base + 0x100..base + 0x108],
+        wfi: true,
+        ..StopCondition::default()
+    };
+    assert_eq!(machine.run_until(&stop), ExitReason::Breakpoint(base + 8));
+    assert_eq!(
+        machine.run_until(&stop),
+        ExitReason::Watchpoint {
+            pc: base + 8,
+            addr: base + 0x104
+        }
+    );
+    assert_eq!(machine.cpu.pc, base + 12);
+    assert_eq!(machine.run_until(&stop), ExitReason::Wfi);
+    assert_eq!(machine.cpu.pc, base + 16);
+    assert_eq!(machine.cpu.regs[7], 0);
+
+    // Without the condition wfi doesn't stop.
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::IllegalInstruction(0))
+    );
+    assert_eq!(machine.cpu.regs[7], 1);
+}
+
+#[test]
+fn watchpoints_translate_the_pc() {
+    // c.sw s1, 0x40(s0), at a virtual address mapped by a gigapage
+    let mut machine = Machine::builder().image(vec![0x24, 0xc0]).build().unwrap();
+    let (base, root) = (machine.cpu.pc, machine.cpu.pc + 0x10_0000);
+    let pte = (base >> 12) << 10 | 0xcf;
+    machine.cpu.bus.store(root + 8, 64, pte).unwrap();
+    machine.cpu.csrs[SATP] = 8 << 60 | root >> 12;
+    machine.cpu.mode = Mode::Supervisor;
+    machine.cpu.pc = 0x4000_0000;
+    machine.cpu.regs[8] = 0x4000_0000;
+
+    let stop = StopCondition {
+        watchpoints: vec![0..8, 0x4000_0040..0x4000_0044],
+        ..StopCondition::default()
+    };
+    assert_eq!(
+        machine.run_until(&stop),
+        ExitReason::Watchpoint {
+            pc: 0x4000_0000,
+            addr: 0x4000_0040
+        }
+    );
+}
+
+#[test]
+fn wfi_sleeps_until_an_interrupt() {
+    // wfi; li t2, 1
+    let code: Vec<u8> = [0x10500073u32, 0x00100393]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let msip = 1 << Interrupt::MachineSoftware.code();
+    let stop = StopCondition {
+        max_instructions: Some(2),
+        ..StopCondition::default()
+    };
+
+    // Woken up by a device long before the timeout.
+    let mut machine = Machine::builder()
+        .image(code.clone())
+        .wfi(WfiPolicy::Sleep(Duration::from_secs(60)))
+        .build()
+        .unwrap();
+    machine.cpu.csrs[MIE] = msip;
+    machine.backend.handle().submit(
+        || std::thread::sleep(Duration::from_millis(20)),
+        |_, cpu| cpu.set_pending(Interrupt::MachineSoftware, true),
+    );
+    let start = Instant::now();
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert!(machine.cpu.interrupt_pending());
+    assert_eq!(machine.cpu.regs[7], 1);
+
+    // Nothing pending, the wfi completes after the timeout.
+    let mut machine = Machine::builder()
+        .image(code.clone())
+        .wfi(WfiPolicy::Sleep(Duration::from_millis(50)))
+        .build()
+        .unwrap();
+    machine.cpu.csrs[MIE] = msip;
+    let start = Instant::now();
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    // An interrupt already pending doesn't wait at all.
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.csrs[MIE] = msip;
+    machine.cpu.csrs[MIP] = msip;
+    machine.cpu.step().unwrap();
+    assert!(!machine.cpu.idle);
+
+    assert_eq!("nop".parse(), Ok(WfiPolicy::Nop));
+    assert_eq!(
+        "sleep=5".parse(),
+        Ok(WfiPolicy::Sleep(Duration::from_millis(5)))
+    );
+    assert!("sleep=x".parse::<WfiPolicy>().is_err());
+}
+
+#[test]
+fn wrs_waits_on_the_reservation() {
+    // auipc t0, 0; lr.w t1, (t0); wrs.nto; li t2, 1
+    let code: Vec<u8> = [0x00000297u32, 0x1002a32f, 0x00d00073, 0x00100393]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let stop = StopCondition {
+        max_instructions: Some(4),
+        ..StopCondition::default()
+    };
+
+    // A device storing to the reserved word wakes the hart up.
+    let mut machine = Machine::builder()
+        .image(code.clone())
+        .wfi(WfiPolicy::Sleep(Duration::from_secs(60)))
+        .build()
+        .unwrap();
+    let base = machine.cpu.pc;
+    machine.backend.handle().submit(
+        || std::thread::sleep(Duration::from_millis(20)),
+        move |_, cpu| cpu.bus.store(base, 32, 0).unwrap(),
+    );
+    let start = Instant::now();
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert!(start.elapsed() < Duration::from_secs(30));
+    assert_eq!(machine.cpu.regs[7], 1);
+
+    // Without a reservation there's nothing to wait for.
+    let mut machine = Machine::builder().image(code.clone()).build().unwrap();
+    machine.cpu.pc += 8;
+    machine.cpu.step().unwrap();
+    assert!(!machine.cpu.idle);
+
+    let mut machine = Machine::builder()
+        .image(code)
+        .isa("rv64ima_zicsr")
+        .build()
+        .unwrap();
+    machine.cpu.pc += 8;
+    assert_eq!(
+        machine.cpu.step(),
+        Err(Exception::IllegalInstruction(0x00d00073))
+    );
+}
+
+#[test]
+fn finisher_reboot() {
+    // lui t0, 0x100; li t1, 0x7777; sw t1, 0(t0); j .
+    let code: Vec<u8> = [
+        0x001002b7u32,
+        0x00007337,
+        0x77730313,
+        0x0062a023,
+        0x0000006f,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    assert_eq!(machine.run(), ExitReason::Reboot);
+    assert!(machine.finisher.reboot_requested());
+    assert_eq!(machine.exit_code(), None);
+}
+
+#[test]
+fn halt_semantics() {
+    let code = std::fs::read("tests/bare/finisher.elf").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+    assert_eq!(machine.run(), ExitReason::Shutdown(5));
+
+    // li a0, 3; ebreak
+    let code: Vec<u8> = [0x00300513u32, 0x00100073]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code.clone()).build().unwrap();
+    let base = machine.cpu.pc;
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::Breakpoint(base + 4))
+    );
+    let mut machine = Machine::builder()
+        .image(code)
+        .ebreak(EbreakPolicy::Exit)
+        .build()
+        .unwrap();
+    assert_eq!(machine.run(), ExitReason::Shutdown(3));
+
+    // ret, with ra still 0
+    let ret = 0x00008067u32.to_le_bytes().to_vec();
+    let mut machine = Machine::builder().image(ret).build().unwrap();
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::InstructionAccessFault(0))
+    );
+}
+
+#[test]
+fn harts_take_turns() {
+    // csrr t0, mhartid; slli t1, t0, 3; auipc t2, 1; add t1, t1, t2;
+    // addi t3, a0, 1; sd t3, 0(t1); j .
+    let code = [
+        0xf14022f3u32,
+        0x00329313,
+        0x00001397,
+        0x00730333,
+        0x00150e13,
+        0x01c33023,
+        0x0000006f,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).harts(4).build().unwrap();
+    let base = machine.cpu.pc;
+    assert_eq!(machine.harts.len(), 3);
+    assert_eq!(machine.cpu.hart_id(), 0);
+
+    let stop = StopCondition {
+        max_instructions: Some(4 * HART_QUANTUM),
+        ..StopCondition::default()
+    };
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    // Back to hart 0 for the next turn.
+    assert_eq!(machine.cpu.hart_id(), 0);
+    for id in 0..4 {
+        assert_eq!(machine.cpu.bus.load(base + 0x1008 + id * 8, 64), Ok(id + 1));
+    }
+    let ids: Vec<_> = machine.harts.iter().map(|x| x.id()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    assert!(machine.harts.iter().all(|x| x.pc == base + 24));
+    assert_eq!(machine.harts[2].csrs[MHARTID], 3);
+
+    assert!(matches!(
+        Machine::builder().harts(0).build(),
+        Err(EmulatorError::InvalidHarts(0))
+    ));
+}
+
+#[test]
+fn sbi_hart_state_management() {
+    let data = std::fs::read("tests/bare/hsm.elf").unwrap();
+    let elf = Elf::parse(&data).unwrap();
+    let (results, seen) = (elf.symbol("results").unwrap(), elf.symbol("seen").unwrap());
+    let mut machine = Machine::builder()
+        .elf(data.clone())
+        .harts(2)
+        .firmware(Firmware::Builtin)
+        .build()
+        .unwrap();
+    assert!(machine.harts[0].stopped);
+    assert_eq!(machine.run(), ExitReason::Shutdown(0));
+
+    let mut load = |addr| machine.cpu.bus.load(addr, 64).unwrap();
+    // Stopped, started, already available and invalid param.
+    let results: Vec<_> = (0..4).map(|i| load(results + i * 8) as i64).collect();
+    assert_eq!(results, [1, 0, -6, -3]);
+    assert_eq!([load(seen), load(seen + 8)], [1, 42]);
+    assert!(machine.harts[0].stopped);
+
+    // Without the firmware every hart starts at the entry point.
+    let mut machine = Machine::builder().elf(data).harts(2).build().unwrap();
+    assert!(!machine.harts[0].stopped);
+    assert!(matches!(
+        machine.run(),
+        ExitReason::Exception(Exception::EnvironmentCallFromMMode)
+    ));
+
+    // li a7, HSM; li a6, 1; hart_stop() on the only hart.
+    let code = [0x004858b7u32, 0x34d8889b, 0x00100813, 0x00000073]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .firmware(Firmware::Builtin)
+        .build()
+        .unwrap();
+    assert_eq!(machine.run(), ExitReason::Halted);
+}
+
+#[test]
+fn reservations_are_per_hart() {
+    // Hart 0: lr.d t0, (a1); sc.d t1, a2, (a1); lr.d t0, (a1); sc.d t1, a2, (a1)
+    // Hart 1, at +0x10: sc.d t1, a2, (a1); sd zero, 0(a1); sd zero, 8(a1)
+    let code = [
+        0x1005b2afu32,
+        0x18c5b32f,
+        0x1005b2af,
+        0x18c5b32f,
+        0x18c5b32f,
+        0x0005b023,
+        0x0005b423,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).harts(2).build().unwrap();
+    let base = machine.cpu.pc;
+    let addr = base + 0x800;
+    machine.harts[0].pc = base + 0x10;
+    for hart in [&mut machine.cpu.regs, &mut machine.harts[0].regs] {
+        hart[11] = addr;
+        hart[12] = 7;
+    }
+
+    // The reservation of hart 0 isn't one of hart 1, and storing the same
+    // value from hart 1 still breaks it.
+    machine.step().unwrap();
+    machine.next_hart();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.regs[6], 1);
+    machine.step().unwrap();
+    machine.next_hart();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.regs[6], 1);
+    assert_eq!(machine.cpu.bus.load(addr, 64), Ok(0));
+
+    // A store next to the granule doesn't.
+    machine.step().unwrap();
+    machine.next_hart();
+    machine.step().unwrap();
+    machine.next_hart();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.regs[6], 0);
+    assert_eq!(machine.cpu.bus.load(addr, 64), Ok(7));
+    assert!(machine.cpu.bus.reservations.is_empty());
+}
+
+#[test]
+fn seeded_reset_state() {
+    let image = vec![0x13, 0, 0, 0];
+    let build = |seed| {
+        Machine::builder()
+            .image(image.clone())
+            .memory(64 * KiB)
+            .harts(2)
+            .seed(seed)
+            .build()
+            .unwrap()
+    };
+    let (a, b, c) = (build(1), build(1), build(2));
+    assert_eq!(a.cpu.regs, b.cpu.regs);
+    assert_eq!(a.cpu.bus.dram.dram, b.cpu.bus.dram.dram);
+    assert_ne!(a.cpu.regs, c.cpu.regs);
+    assert_ne!(a.cpu.regs, a.harts[0].regs);
+
+    let zeros = Machine::builder().image(image.clone()).memory(64 * KiB);
+    let zeros = zeros.build().unwrap();
+    for r in [0, 2, 10] {
+        assert_eq!(a.cpu.regs[r], zeros.cpu.regs[r]);
+    }
+    assert_eq!(a.harts[0].regs[10], 1);
+    assert!(a.cpu.regs[1..].iter().filter(|x| **x == 0).count() <= 2);
+    assert_eq!(a.cpu.bus.dram.dram[..4], image[..]);
+    assert!(a.cpu.bus.dram.dram[4..].iter().any(|x| *x != 0));
+
+    // Segments and their bss stay as the ELF has them.
+    let data = include_bytes!("bare/calls.elf").to_vec();
+    let elf = Elf::parse(&data).unwrap();
+    let seeded = Machine::builder()
+        .elf(data.clone())
+        .seed(3)
+        .build()
+        .unwrap();
+    let plain = Machine::builder().elf(data.clone()).build().unwrap();
+    for segment in &elf.segments {
+        let start = (segment.vaddr - seeded.cpu.bus.dram.base) as usize;
+        let range = start..start + segment.memsz as usize;
+        assert_eq!(
+            seeded.cpu.bus.dram.dram[range.clone()],
+            plain.cpu.bus.dram.dram[range]
+        );
+    }
+}
+
+#[test]
+fn irq_jitter_delays_delivery() {
+    // Steps until a software interrupt raised now is pending.
+    let delay = |jitter| {
+        let code = [0x00000013u32; 64]
+            .iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let mut machine = Machine::builder()
+            .image(code)
+            .seed(7)
+            .irq_jitter(jitter)
+            .build()
+            .unwrap();
+        // Settle the first, immediate delivery.
+        machine.step().unwrap();
+        machine.cpu.bus.store(MSWI_BASE, 32, 1).unwrap();
+        let mut steps = 1;
+        machine.step().unwrap();
+        while machine.cpu.csrs[MIP] == 0 {
+            steps += 1;
+            machine.step().unwrap();
+        }
+        steps
+    };
+    assert_eq!(delay(0), 1);
+    let delays: Vec<_> = (0..3).map(|_| delay(20)).collect();
+    assert!(delays[0] <= 21);
+    assert_eq!(delays, [delays[0]; 3]);
+}
+
+/// A 64 bit executable with an empty program header table at `phoff`,
+/// `phnum` of them, and a load segment at the first.
+fn elf64(phoff: u64, phnum: u16, segment: [u64; 4]) -> Vec<u8> {
+    let mut data = vec![0; 64 + 56];
+    data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    data[16..18].copy_from_slice(&2u16.to_le_bytes());
+    data[18..20].copy_from_slice(&243u16.to_le_bytes());
+    data[32..40].copy_from_slice(&phoff.to_le_bytes());
+    data[54..56].copy_from_slice(&56u16.to_le_bytes());
+    data[56..58].copy_from_slice(&phnum.to_le_bytes());
+    // PT_LOAD with its offset, vaddr, filesz and memsz
+    data[64..68].copy_from_slice(&1u32.to_le_bytes());
+    for (field, value) in [8, 16, 32, 40].into_iter().zip(segment) {
+        data[64 + field..][..8].copy_from_slice(&value.to_le_bytes());
+    }
+    data
+}
+
+#[test]
+fn malformed_elf() {
+    let invalid = |data: &[u8]| matches!(Elf::parse(data), Err(EmulatorError::InvalidElf(_)));
+    assert!(Elf::parse(&elf64(64, 1, [0, 0x8000_0000, 8, 8])).is_ok());
+    // more bytes in the file than in memory
+    assert!(invalid(&elf64(64, 1, [0, 0x8000_0000, 8, 4])));
+    // the file offset and the address overflow
+    assert!(invalid(&elf64(64, 1, [u64::MAX, 0x8000_0000, 2, 2])));
+    assert!(invalid(&elf64(64, 1, [0, u64::MAX - 1, 0, 16])));
+    assert!(invalid(&elf64(u64::MAX - 8, 2, [0; 4])));
+    assert!(matches!(
+        Machine::builder()
+            .elf(elf64(64, 1, [0, 0x8000_0000, 8, 4]))
+            .build(),
+        Err(EmulatorError::InvalidElf(_))
+    ));
+
+    // a 32 bit object whose symbol table ends in its last entry
+    let mut data = vec![0; 152];
+    data[..6].copy_from_slice(b"\x7fELF\x01\x01");
+    data[16..18].copy_from_slice(&2u16.to_le_bytes());
+    data[18..20].copy_from_slice(&243u16.to_le_bytes());
+    data[32..36].copy_from_slice(&60u32.to_le_bytes());
+    data[46..48].copy_from_slice(&40u16.to_le_bytes());
+    data[48..50].copy_from_slice(&2u16.to_le_bytes());
+    data[52..57].copy_from_slice(b"\0foo\0");
+    // the symbol table, linked to the string table
+    for (field, value) in [(4, 2u32), (16, 140), (20, 16), (24, 1)] {
+        data[60 + field..][..4].copy_from_slice(&value.to_le_bytes());
+    }
+    for (field, value) in [(4, 3u32), (16, 52), (20, 5)] {
+        data[100 + field..][..4].copy_from_slice(&value.to_le_bytes());
+    }
+    data[140..144].copy_from_slice(&1u32.to_le_bytes());
+    let elf = Elf::parse(&data).unwrap();
+    assert!(matches!(elf.symbols(), Err(EmulatorError::InvalidElf(_))));
+}
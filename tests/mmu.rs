@@ -0,0 +1,453 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, Mode, MSTATUS, SATP},
+    exception::Exception,
+    hpm::{Hpm, MHPMCOUNTER3, MHPMEVENT3},
+    mmu::{Access, AdPolicy, AsidCounts, Satp, Scheme, TlbCounts},
+};
+
+const V: u64 = 1 << 0;
+const R: u64 = 1 << 1;
+const W: u64 = 1 << 2;
+const X: u64 = 1 << 3;
+const U: u64 = 1 << 4;
+const G: u64 = 1 << 5;
+const A: u64 = 1 << 6;
+const D: u64 = 1 << 7;
+const RWAD: u64 = V | R | W | A | D;
+
+const MPRV: u64 = 1 << 17;
+const SUM: u64 = 1 << 18;
+const MXR: u64 = 1 << 19;
+
+/// Page tables are allocated from here.
+const TABLES: u64 = DRAM_BASE + 0x10_0000;
+
+/// A cpu in supervisor mode translating with `scheme`, through the empty
+/// root table at [`TABLES`].
+fn translating(scheme: Scheme, code: Vec<u8>) -> Cpu {
+    let mut cpu = Cpu::new(code);
+    let mode = match scheme {
+        Scheme::Bare => 0,
+        Scheme::Sv39 => 8,
+        Scheme::Sv48 => 9,
+        Scheme::Sv57 => 10,
+    };
+    cpu.csrs[SATP] = mode << 60 | TABLES >> 12;
+    cpu.mode = Mode::Supervisor;
+    cpu
+}
+
+/// Page tables built from [`TABLES`] up, the root first.
+struct Tables {
+    next: u64,
+}
+
+impl Tables {
+    fn new() -> Self {
+        Tables {
+            next: TABLES + 0x1000,
+        }
+    }
+
+    /// Maps the page of `va` to `pa` with `flags`, a superpage of `level` if
+    /// nonzero, allocating the tables on the way.
+    fn map(&mut self, cpu: &mut Cpu, va: u64, pa: u64, flags: u64, level: u32) {
+        let mut table = TABLES;
+        for l in (level + 1..cpu.scheme().levels()).rev() {
+            let entry = table + ((va >> (12 + 9 * l)) & 0x1ff) * 8;
+            let mut pte = cpu.bus.load(entry, 64).unwrap();
+            if pte == 0 {
+                pte = (self.next >> 12) << 10 | V;
+                self.next += 0x1000;
+                cpu.bus.store(entry, 64, pte).unwrap();
+            }
+            table = (pte >> 10) << 12;
+        }
+        let entry = table + ((va >> (12 + 9 * level)) & 0x1ff) * 8;
+        cpu.bus.store(entry, 64, (pa >> 12) << 10 | flags).unwrap();
+    }
+}
+
+#[rstest]
+fn translates(#[values(Scheme::Sv39, Scheme::Sv48, Scheme::Sv57)] scheme: Scheme) {
+    let mut cpu = translating(scheme, vec![]);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x4000_1000, DRAM_BASE + 0x2000, RWAD, 0);
+    tables.map(&mut cpu, 0x4020_0000, DRAM_BASE + 0x20_0000, RWAD, 1);
+
+    assert_eq!(
+        cpu.translate(0x4000_1234, Access::Load),
+        Ok(DRAM_BASE + 0x2234)
+    );
+    assert_eq!(
+        cpu.translate(0x4000_1ff8, Access::Store),
+        Ok(DRAM_BASE + 0x2ff8)
+    );
+    // a megapage
+    assert_eq!(
+        cpu.translate(0x4021_2345, Access::Load),
+        Ok(DRAM_BASE + 0x21_2345)
+    );
+    assert_eq!(
+        cpu.translate(0x4000_2000, Access::Load),
+        Err(Exception::LoadPageFault(0x4000_2000))
+    );
+}
+
+#[rstest]
+#[case::sv39(Scheme::Sv39)]
+#[case::sv48(Scheme::Sv48)]
+fn non_canonical(#[case] scheme: Scheme) {
+    let mut cpu = translating(scheme, vec![]);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x4000_1000, DRAM_BASE + 0x2000, RWAD, 0);
+    // past the virtual address bits, instead of wrapping around to the page above
+    let addr = 1 << (9 * scheme.levels() + 12) | 0x4000_1000;
+    assert_eq!(
+        cpu.translate(addr, Access::Load),
+        Err(Exception::LoadPageFault(addr))
+    );
+}
+
+#[test]
+fn misaligned_superpage() {
+    let mut cpu = translating(Scheme::Sv39, vec![]);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x4020_0000, DRAM_BASE + 0x1000, RWAD, 1);
+    assert_eq!(
+        cpu.translate(0x4020_0000, Access::Load),
+        Err(Exception::LoadPageFault(0x4020_0000))
+    );
+}
+
+#[rstest]
+#[case::read(V | R | A, Mode::Supervisor, Access::Load, 0, true)]
+#[case::read_only(V | R | A | D, Mode::Supervisor, Access::Store, 0, false)]
+#[case::execute_only(V | X | A, Mode::Supervisor, Access::Load, 0, false)]
+#[case::mxr(V | X | A, Mode::Supervisor, Access::Load, MXR, true)]
+#[case::fetch(V | X | A, Mode::Supervisor, Access::Fetch, 0, true)]
+#[case::no_execute(RWAD, Mode::Supervisor, Access::Fetch, 0, false)]
+#[case::write_only(V | W | A | D, Mode::Supervisor, Access::Load, 0, false)]
+#[case::user(RWAD | U, Mode::User, Access::Store, 0, true)]
+#[case::not_user(RWAD, Mode::User, Access::Load, 0, false)]
+#[case::user_from_supervisor(RWAD | U, Mode::Supervisor, Access::Load, 0, false)]
+#[case::sum(RWAD | U, Mode::Supervisor, Access::Load, SUM, true)]
+#[case::sum_fetch(V | X | A | U, Mode::Supervisor, Access::Fetch, SUM, false)]
+#[case::not_accessed(V | R | W | D, Mode::Supervisor, Access::Load, 0, false)]
+#[case::not_dirty(V | R | W | A, Mode::Supervisor, Access::Store, 0, false)]
+#[case::invalid(R | W | A | D, Mode::Supervisor, Access::Load, 0, false)]
+#[case::reserved(RWAD | 1 << 60, Mode::Supervisor, Access::Load, 0, false)]
+fn permissions(
+    #[case] flags: u64,
+    #[case] mode: Mode,
+    #[case] access: Access,
+    #[case] mstatus: u64,
+    #[case] allowed: bool,
+) {
+    let mut cpu = translating(Scheme::Sv39, vec![]);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, flags, 0);
+    cpu.mode = mode;
+    cpu.csrs[MSTATUS] = mstatus;
+
+    let expected = match (allowed, access) {
+        (true, _) => Ok(DRAM_BASE + 0x2000),
+        (false, Access::Fetch) => Err(Exception::InstructionPageFault(0x1000)),
+        (false, Access::Load) => Err(Exception::LoadPageFault(0x1000)),
+        (false, Access::Store) => Err(Exception::StoreAmoPageFault(0x1000)),
+    };
+    assert_eq!(cpu.translate(0x1000, access), expected);
+}
+
+#[test]
+fn machine_mode() {
+    let mut cpu = translating(Scheme::Sv39, vec![]);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, RWAD, 0);
+    cpu.mode = Mode::Machine;
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(0x1000));
+
+    // loads and stores as the supervisor with MPRV, not fetches
+    cpu.csrs[MSTATUS] = MPRV | 1 << 11;
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + 0x2000));
+    assert_eq!(cpu.translate(0x1000, Access::Fetch), Ok(0x1000));
+}
+
+#[test]
+fn executes_translated() {
+    // ld t2, 0(t0); sd t2, 8(t0)
+    let code = [0x0002b383u32, 0x0072b423]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = translating(Scheme::Sv39, code);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE, V | R | X | A, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x2000, V | R | A, 0);
+    cpu.bus.store(DRAM_BASE + 0x2000, 64, 42).unwrap();
+    cpu.pc = 0x1000;
+    cpu.regs[5] = 0x2000;
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 42);
+    assert_eq!(cpu.step(), Err(Exception::StoreAmoPageFault(0x2008)));
+    assert_eq!(cpu.pc, 0x1004);
+}
+
+#[test]
+fn cbo_translated() {
+    // cbo.zero (t0); cbo.clean (t0)
+    let code = [0x0042a00fu32, 0x0012a00f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = translating(Scheme::Sv39, code);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE, V | R | X | A, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x3000, RWAD, 0);
+    tables.map(&mut cpu, 0x4000, DRAM_BASE + 0x5000, V | R | A, 0);
+    for offset in (0..0x80).step_by(8) {
+        cpu.bus
+            .store(DRAM_BASE + 0x3000 + offset, 64, u64::MAX)
+            .unwrap();
+    }
+    cpu.pc = 0x1000;
+    cpu.regs[5] = 0x2048;
+
+    // the physical block of the virtual address is zeroed
+    cpu.step().unwrap();
+    assert_eq!(cpu.bus.load(DRAM_BASE + 0x3000, 64).unwrap(), u64::MAX);
+    for offset in (0x40..0x80).step_by(8) {
+        assert_eq!(cpu.bus.load(DRAM_BASE + 0x3000 + offset, 64).unwrap(), 0);
+    }
+    // and the management operations need write permission too
+    cpu.regs[5] = 0x4000;
+    assert_eq!(cpu.step(), Err(Exception::StoreAmoPageFault(0x4000)));
+}
+
+#[rstest]
+#[case::sv48(9 << 60, 9 << 60)]
+#[case::bare(0, 0)]
+// reserved and Sv64, which isn't implemented, leave it as it was
+#[case::reserved(5 << 60, 8 << 60)]
+#[case::sv64(11 << 60, 8 << 60)]
+fn satp_mode(#[case] value: u64, #[case] expected: u64) {
+    // csrw satp, t0
+    let mut cpu = Cpu::new(0x18029073u32.to_le_bytes().to_vec());
+    cpu.csrs[SATP] = 8 << 60;
+    cpu.regs[5] = value;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[SATP], expected);
+}
+
+#[test]
+fn tlb() {
+    // sfence.vma
+    let mut cpu = translating(Scheme::Sv39, 0x12000073u32.to_le_bytes().to_vec());
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, RWAD, 0);
+    assert_eq!(cpu.translate(0x1008, Access::Load), Ok(DRAM_BASE + 0x2008));
+    assert_eq!(cpu.translate(0x1010, Access::Store), Ok(DRAM_BASE + 0x2010));
+    assert_eq!((cpu.tlb.hits(), cpu.tlb.misses()), (1, 1));
+
+    // the permissions are checked on a hit too
+    cpu.mode = Mode::User;
+    assert_eq!(
+        cpu.translate(0x1000, Access::Load),
+        Err(Exception::LoadPageFault(0x1000))
+    );
+
+    // the stale translation is used until a fence
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x3000, RWAD, 0);
+    cpu.mode = Mode::Supervisor;
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + 0x2000));
+    cpu.mode = Mode::Machine;
+    cpu.step().unwrap();
+    cpu.mode = Mode::Supervisor;
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + 0x3000));
+}
+
+#[test]
+fn tlb_counts() {
+    // ld t2, 0(t0); sfence.vma zero, t1; sfence.vma
+    let code = [0x0002b383u32, 0x12600073, 0x12000073]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = translating(Scheme::Sv39, code);
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE, V | R | X | A, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x2000, V | R | A, 0);
+    cpu.pc = 0x1000;
+    cpu.regs[5] = 0x2000;
+    cpu.regs[6] = 1;
+    // iTLB misses, dTLB misses and fences
+    for (i, event) in [9, 10, 11].into_iter().enumerate() {
+        cpu.csrs[MHPMEVENT3 + i] = event;
+    }
+    cpu.hpm = Hpm::from_csrs(&cpu.csrs);
+
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(cpu.tlb.itlb, TlbCounts { hits: 2, misses: 1 });
+    assert_eq!(cpu.tlb.dtlb, TlbCounts { hits: 0, misses: 1 });
+    assert_eq!(cpu.tlb.fences, 2);
+    assert_eq!(cpu.tlb.asid_fences.get(&1), Some(&1));
+    assert_eq!(cpu.csrs[MHPMCOUNTER3..MHPMCOUNTER3 + 3], [1, 1, 2]);
+    assert_eq!(
+        cpu.tlb.to_string(),
+        "4 translations, 2 hits, 2 misses (50.00% hit rate), itlb 2 hits, 1 misses, \
+         dtlb 0 hits, 1 misses, 2 sfence.vma (1 for asid 1), \
+         asid 0 itlb 2 hits, 1 misses, dtlb 0 hits, 1 misses"
+    );
+}
+
+#[test]
+fn asid() {
+    // csrw satp, t0
+    let mut cpu = translating(Scheme::Sv39, 0x18029073u32.to_le_bytes().to_vec());
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, RWAD, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x4000, RWAD | G, 0);
+    cpu.csrs[SATP] |= 1 << 44;
+    let first = cpu.csrs[SATP];
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + 0x2000));
+    assert_eq!(cpu.translate(0x2000, Access::Load), Ok(DRAM_BASE + 0x4000));
+
+    // another address space, with an empty root table
+    let root = TABLES + 0x10_0000;
+    cpu.mode = Mode::Machine;
+    cpu.regs[5] = 8 << 60 | 2 << 44 | root >> 12;
+    cpu.step().unwrap();
+    assert_eq!(
+        cpu.satp(),
+        Satp {
+            scheme: Scheme::Sv39,
+            asid: 2,
+            ppn: root >> 12
+        }
+    );
+    cpu.mode = Mode::Supervisor;
+    assert_eq!(
+        cpu.translate(0x1000, Access::Load),
+        Err(Exception::LoadPageFault(0x1000))
+    );
+    // global pages are in all of them
+    assert_eq!(cpu.translate(0x2000, Access::Load), Ok(DRAM_BASE + 0x4000));
+
+    // and the first one's translations are still there
+    cpu.csrs[SATP] = first;
+    let hits = cpu.tlb.hits();
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + 0x2000));
+    assert_eq!(cpu.tlb.hits(), hits + 1);
+
+    // counted apart for each address space
+    let dtlb = |hits, misses| AsidCounts {
+        itlb: TlbCounts::default(),
+        dtlb: TlbCounts { hits, misses },
+    };
+    assert_eq!(cpu.tlb.asids.get(&1), Some(&dtlb(1, 2)));
+    assert_eq!(cpu.tlb.asids.get(&2), Some(&dtlb(1, 1)));
+}
+
+#[rstest]
+#[case::all(None, None, false, false)]
+#[case::asid(None, Some(1), false, true)]
+#[case::other_asid(None, Some(2), true, true)]
+#[case::page(Some(0x1000), None, false, true)]
+#[case::global_page(Some(0x2000), None, true, false)]
+#[case::global_page_of_asid(Some(0x2000), Some(1), true, true)]
+#[case::page_of_asid(Some(0x1008), Some(1), false, true)]
+fn sfence_vma(
+    #[case] addr: Option<u64>,
+    #[case] asid: Option<u64>,
+    #[case] page_kept: bool,
+    #[case] global_kept: bool,
+) {
+    // sfence.vma t0, t1 with the registers which aren't given as zero
+    let rs1 = if addr.is_some() { 5 << 15 } else { 0 };
+    let rs2 = if asid.is_some() { 6 << 20 } else { 0 };
+    let inst = 0x12000073u32 | rs1 | rs2;
+    let mut cpu = translating(Scheme::Sv39, inst.to_le_bytes().to_vec());
+    cpu.csrs[SATP] |= 1 << 44;
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, RWAD, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x4000, RWAD | G, 0);
+    cpu.translate(0x1000, Access::Load).unwrap();
+    cpu.translate(0x2000, Access::Load).unwrap();
+
+    // remapped, the kept entries still translate the old way
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x3000, RWAD, 0);
+    tables.map(&mut cpu, 0x2000, DRAM_BASE + 0x5000, RWAD | G, 0);
+    cpu.mode = Mode::Machine;
+    cpu.regs[5] = addr.unwrap_or(0);
+    cpu.regs[6] = asid.unwrap_or(0);
+    cpu.step().unwrap();
+    cpu.mode = Mode::Supervisor;
+
+    let page = if page_kept { 0x2000 } else { 0x3000 };
+    let global = if global_kept { 0x4000 } else { 0x5000 };
+    assert_eq!(cpu.translate(0x1000, Access::Load), Ok(DRAM_BASE + page));
+    assert_eq!(cpu.translate(0x2000, Access::Load), Ok(DRAM_BASE + global));
+}
+
+#[test]
+fn sfence_vma_superpage() {
+    // sfence.vma t0, zero
+    let mut cpu = translating(Scheme::Sv39, 0x12028073u32.to_le_bytes().to_vec());
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x4020_0000, DRAM_BASE + 0x20_0000, RWAD, 1);
+    cpu.translate(0x4030_0000, Access::Load).unwrap();
+
+    // an address anywhere in the megapage flushes all of it
+    tables.map(&mut cpu, 0x4020_0000, DRAM_BASE + 0x40_0000, RWAD, 1);
+    cpu.mode = Mode::Machine;
+    cpu.regs[5] = 0x4020_0000;
+    cpu.step().unwrap();
+    cpu.mode = Mode::Supervisor;
+    assert_eq!(
+        cpu.translate(0x4030_0000, Access::Load),
+        Ok(DRAM_BASE + 0x50_0000)
+    );
+}
+
+#[rstest]
+#[case::load(Access::Load, V | R | W, V | R | W | A)]
+#[case::store(Access::Store, V | R | W, RWAD)]
+#[case::store_accessed(Access::Store, V | R | W | A, RWAD)]
+#[case::set(Access::Load, RWAD, RWAD)]
+fn ad_update(#[case] access: Access, #[case] flags: u64, #[case] expected: u64) {
+    let mut cpu = translating(Scheme::Sv39, vec![]);
+    cpu.ad_policy = AdPolicy::Update;
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x4000_1000, DRAM_BASE + 0x2000, flags, 0);
+    assert_eq!(cpu.translate(0x4000_1000, access), Ok(DRAM_BASE + 0x2000));
+
+    // the leaf is the second entry of the last table
+    let pte = cpu.bus.load(TABLES + 0x2000 + 8, 64).unwrap();
+    assert_eq!(pte, (DRAM_BASE + 0x2000) >> 2 | expected);
+}
+
+#[test]
+fn ad_update_after_load() {
+    let mut cpu = translating(Scheme::Sv39, vec![]);
+    cpu.ad_policy = AdPolicy::Update;
+    let mut tables = Tables::new();
+    tables.map(&mut cpu, 0x1000, DRAM_BASE + 0x2000, V | R | W, 0);
+    cpu.translate(0x1000, Access::Load).unwrap();
+    // the cached translation isn't dirty, the store walks again to set it
+    cpu.translate(0x1000, Access::Store).unwrap();
+    assert_eq!(cpu.tlb.misses(), 2);
+    let pte = cpu.bus.load(TABLES + 0x2000 + 8, 64).unwrap();
+    assert_eq!(pte & (A | D), A | D);
+}
+
+#[test]
+fn ad_policy() {
+    assert_eq!("fault".parse(), Ok(AdPolicy::Fault));
+    assert_eq!("update".parse(), Ok(AdPolicy::Update));
+    assert!("set".parse::<AdPolicy>().is_err());
+}
@@ -0,0 +1,164 @@
+use std::{
+    net::UdpSocket,
+    thread,
+    time::{Duration, Instant},
+};
+
+use rysk::{
+    cluster::Cluster,
+    machine::{ExitReason, Machine},
+    net::{
+        default_mac,
+        user::{GATEWAY, GATEWAY_MAC, GUEST},
+        Hub, Port, NIC_BASE,
+    },
+};
+
+#[test]
+fn hub_repeats_to_other_ports() {
+    let hub = Hub::default();
+    let (a, b, c) = (hub.port(), hub.port(), hub.port());
+    a.send(b"frame");
+    assert_eq!(a.recv(), None);
+    assert_eq!(b.recv().as_deref(), Some(&b"frame"[..]));
+    assert_eq!(c.recv().as_deref(), Some(&b"frame"[..]));
+    assert_eq!(c.recv(), None);
+}
+
+#[test]
+fn udp_link() {
+    let (left, right) = (Hub::default(), Hub::default());
+    let x = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let y = UdpSocket::bind("127.0.0.1:0").unwrap();
+    x.connect(y.local_addr().unwrap()).unwrap();
+    y.connect(x.local_addr().unwrap()).unwrap();
+    left.link(x).unwrap();
+    right.link(y).unwrap();
+
+    let (a, b) = (left.port(), right.port());
+    a.send(b"over udp");
+    let start = Instant::now();
+    let frame = loop {
+        if let Some(frame) = b.recv() {
+            break frame;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "no frame arrived");
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(frame, b"over udp");
+    // Frames from the link aren't sent back over it.
+    assert_eq!(a.recv(), None);
+}
+
+#[test]
+fn ping_pong() {
+    let ping = std::fs::read("tests/bare/ping.elf").expect("did you run 'make test' ?");
+    let pong = std::fs::read("tests/bare/pong.elf").unwrap();
+
+    let mut cluster = Cluster::new();
+    cluster.add(Machine::builder().elf(ping)).unwrap();
+    cluster.add(Machine::builder().elf(pong)).unwrap();
+    assert_eq!(
+        cluster.run(),
+        [ExitReason::Shutdown(43), ExitReason::Shutdown(0)]
+    );
+
+    let mac = cluster.machines[1].cpu.bus.load(NIC_BASE, 32).unwrap();
+    assert_eq!(mac, 0x12005452);
+}
+
+/// The next frame `port` receives, waiting for it.
+fn wait(port: &Port) -> Vec<u8> {
+    let start = Instant::now();
+    loop {
+        if let Some(frame) = port.recv() {
+            return frame;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5), "no frame arrived");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn ethernet(ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = [0xff; 6].to_vec();
+    frame.extend_from_slice(&default_mac(0));
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[test]
+fn user_gateway() {
+    let hub = Hub::default();
+    hub.link_user();
+    let guest = hub.port();
+
+    // who has 10.0.2.2
+    let mut arp = vec![0, 1, 8, 0, 6, 4, 0, 1];
+    arp.extend_from_slice(&default_mac(0));
+    arp.extend_from_slice(&GUEST.octets());
+    arp.extend_from_slice(&[0; 6]);
+    arp.extend_from_slice(&GATEWAY.octets());
+    guest.send(&ethernet(0x0806, &arp));
+    let reply = wait(&guest);
+    assert_eq!(reply[..6], default_mac(0));
+    assert_eq!(reply[12..14], [8, 6]);
+    assert_eq!(reply[21], 2);
+    assert_eq!(reply[22..28], GATEWAY_MAC);
+    assert_eq!(reply[28..32], GATEWAY.octets());
+
+    // dhcp discover
+    let mut dhcp = vec![0; 240];
+    dhcp[..3].copy_from_slice(&[1, 1, 6]);
+    dhcp[4..8].copy_from_slice(&[1, 2, 3, 4]);
+    dhcp[28..34].copy_from_slice(&default_mac(0));
+    dhcp[236..240].copy_from_slice(&[99, 130, 83, 99]);
+    dhcp.extend_from_slice(&[53, 1, 1, 255]);
+    let mut udp = [0, 68, 0, 67].to_vec();
+    udp.extend_from_slice(&(8 + dhcp.len() as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(&dhcp);
+    let mut ip = vec![0x45, 0];
+    ip.extend_from_slice(&(20 + udp.len() as u16).to_be_bytes());
+    ip.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255]);
+    ip.extend_from_slice(&udp);
+    guest.send(&ethernet(0x0800, &ip));
+    let offer = wait(&guest);
+    let bootp = &offer[14 + 20 + 8..];
+    assert_eq!(bootp[0], 2);
+    assert_eq!(bootp[4..8], [1, 2, 3, 4]);
+    assert_eq!(bootp[16..20], GUEST.octets());
+    // an offer
+    assert_eq!(bootp[240..243], [53, 1, 2]);
+}
+
+#[test]
+fn user_udp() {
+    let host = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let hub = Hub::default();
+    hub.link_user();
+    let guest = hub.port();
+
+    // from 10.0.2.15:1234 to the host through the gateway address
+    let mut udp = [4, 210].to_vec();
+    udp.extend_from_slice(&host.local_addr().unwrap().port().to_be_bytes());
+    udp.extend_from_slice(&[0, 13, 0, 0]);
+    udp.extend_from_slice(b"hello");
+    let mut ip = vec![0x45, 0, 0, 33, 0, 0, 0, 0, 64, 17, 0, 0];
+    ip.extend_from_slice(&GUEST.octets());
+    ip.extend_from_slice(&GATEWAY.octets());
+    ip.extend_from_slice(&udp);
+    guest.send(&ethernet(0x0800, &ip));
+
+    let mut buf = [0; 16];
+    host.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let (n, from) = host.recv_from(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    host.send_to(b"back", from).unwrap();
+    let reply = wait(&guest);
+    // from the gateway to 1234
+    assert_eq!(reply[26..30], GATEWAY.octets());
+    assert_eq!(reply[30..34], GUEST.octets());
+    assert_eq!(reply[36..38], [4, 210]);
+    assert_eq!(&reply[42..], b"back");
+}
@@ -0,0 +1,50 @@
+use rysk::{
+    exception::Exception,
+    instruction::Instruction,
+    machine::{ExitReason, Machine},
+    observer::{AccessKind, ExecutionObserver},
+};
+
+#[derive(Default)]
+struct Counter {
+    instructions: usize,
+    traps: Vec<Exception>,
+    csr_writes: Vec<(usize, u64)>,
+}
+
+impl ExecutionObserver for Counter {
+    fn on_instruction(&mut self, _pc: u64, _inst: &Instruction) {
+        self.instructions += 1;
+    }
+
+    fn on_trap(&mut self, _pc: u64, exception: &Exception) {
+        self.traps.push(*exception);
+    }
+
+    fn on_csr_access(&mut self, csr: usize, value: u64, kind: AccessKind) {
+        if kind == AccessKind::Write {
+            self.csr_writes.push((csr, value));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Other;
+
+impl ExecutionObserver for Other {}
+
+#[test]
+fn observers_coexist() {
+    let code = std::fs::read("tests/csr.bin").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.observers.add(Other);
+    machine.cpu.observers.add(Counter::default());
+
+    assert!(matches!(machine.run(), ExitReason::Exception(_)));
+
+    assert!(machine.cpu.observers.get::<Other>().is_some());
+    let counter = machine.cpu.observers.get::<Counter>().unwrap();
+    assert_eq!(counter.instructions, 11);
+    assert_eq!(counter.traps, [Exception::IllegalInstruction(0)]);
+    assert_eq!(counter.csr_writes[0], (0x300, 1));
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use rysk::{
+    machine::Machine,
+    pflash::{Pflash, PFLASH_BASE, SECTOR_SIZE},
+};
+
+/// An image of two sectors, the first holding `0x00..` and the second erased.
+fn image(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rysk-pflash-{name}-{}", std::process::id()));
+    let mut data: Vec<u8> = (0..SECTOR_SIZE).map(|i| i as u8).collect();
+    data.resize(2 * SECTOR_SIZE as usize, 0xff);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+#[test]
+fn query_and_identify() {
+    let path = image("query");
+    let mut machine = Machine::builder().pflash(&path).build().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(PFLASH_BASE + 4, 32), Ok(0x07060504));
+
+    bus.store(PFLASH_BASE, 32, 0x98).unwrap();
+    let query: Vec<u8> = (0x10..0x13)
+        .map(|i| bus.load(PFLASH_BASE + 4 * i, 32).unwrap() as u8)
+        .collect();
+    assert_eq!(query, b"QRY");
+    // 512 KiB of two sectors of 256 KiB
+    assert_eq!(bus.load(PFLASH_BASE + 4 * 0x27, 32), Ok(19));
+    assert_eq!(bus.load(PFLASH_BASE + 4 * 0x2d, 32), Ok(1));
+    assert_eq!(bus.load(PFLASH_BASE + 4 * 0x30, 32), Ok(4));
+
+    bus.store(PFLASH_BASE, 32, 0x90).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE, 32), Ok(0x89));
+    bus.store(PFLASH_BASE, 32, 0xff).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE, 8), Ok(0));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn program_and_erase() {
+    let path = image("program");
+    let mut machine = Machine::builder().pflash(&path).build().unwrap();
+    let bus = &mut machine.cpu.bus;
+    let second = PFLASH_BASE + SECTOR_SIZE;
+
+    bus.store(second, 32, 0x40).unwrap();
+    bus.store(second + 8, 32, 0x1234_5678).unwrap();
+    assert_eq!(bus.load(second, 32), Ok(0x80), "ready");
+    // programming only clears bits
+    bus.store(second, 32, 0x40).unwrap();
+    bus.store(second + 8, 32, 0xffff_0000).unwrap();
+
+    // two words through the write buffer
+    bus.store(second + 0x20, 32, 0xe8).unwrap();
+    bus.store(second + 0x20, 32, 1).unwrap();
+    bus.store(second + 0x20, 32, 0xaaaa_aaaa).unwrap();
+    bus.store(second + 0x24, 32, 0x5555_5555).unwrap();
+    bus.store(second + 0x20, 32, 0xd0).unwrap();
+    bus.store(second, 32, 0xff).unwrap();
+    assert_eq!(bus.load(second + 8, 32), Ok(0x1234_0000));
+    assert_eq!(bus.load(second + 0x20, 64), Ok(0x5555_5555_aaaa_aaaa));
+
+    // erase the first sector
+    bus.store(PFLASH_BASE + 0x100, 32, 0x20).unwrap();
+    bus.store(PFLASH_BASE + 0x100, 32, 0xd0).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE, 32), Ok(0x80));
+    bus.store(PFLASH_BASE, 32, 0xff).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE + 0x1234, 32), Ok(0xffff_ffff));
+
+    // an erase which isn't confirmed
+    bus.store(PFLASH_BASE, 32, 0x20).unwrap();
+    bus.store(PFLASH_BASE, 32, 0xff).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE, 32), Ok(0xb0));
+    bus.store(PFLASH_BASE, 32, 0x50).unwrap();
+    assert_eq!(bus.load(PFLASH_BASE, 32), Ok(0xffff_ffff));
+
+    let image = std::fs::read(&path).unwrap();
+    assert!(image[..SECTOR_SIZE as usize].iter().all(|x| *x == 0xff));
+    let second = SECTOR_SIZE as usize;
+    assert_eq!(image[second + 8..second + 12], 0x1234_0000u32.to_le_bytes());
+    assert_eq!(image[second + 0x20..second + 0x24], [0xaa; 4]);
+
+    // the next machine sees what was written
+    let mut machine = Machine::builder().pflash(&path).build().unwrap();
+    assert_eq!(
+        machine.cpu.bus.load(PFLASH_BASE + SECTOR_SIZE + 0x24, 32),
+        Ok(0x5555_5555)
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn image_size() {
+    let path = std::env::temp_dir().join(format!("rysk-pflash-size-{}", std::process::id()));
+    std::fs::write(&path, [0; 4096]).unwrap();
+    assert!(Pflash::open(&path).is_err());
+    assert!(Machine::builder().pflash(&path).build().is_err());
+    std::fs::remove_file(path).unwrap();
+}
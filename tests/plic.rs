@@ -0,0 +1,98 @@
+use rysk::{
+    cpu::MIP,
+    machine::Machine,
+    plic::PLIC_BASE,
+    uart::{UART_BASE, UART_IRQ},
+};
+
+const MEIP: u64 = 1 << 11;
+const SEIP: u64 = 1 << 9;
+
+const PRIORITY: u64 = PLIC_BASE + 4 * UART_IRQ as u64;
+const PENDING: u64 = PLIC_BASE + 0x1000;
+
+/// Enable bits of the uart for `context`.
+fn enable(context: u64) -> u64 {
+    PLIC_BASE + 0x2000 + 0x80 * context
+}
+
+fn threshold(context: u64) -> u64 {
+    PLIC_BASE + 0x20_0000 + 0x1000 * context
+}
+
+fn claim(context: u64) -> u64 {
+    threshold(context) + 4
+}
+
+fn machine(harts: u64) -> Machine {
+    // j .
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .harts(harts)
+        .build()
+        .unwrap();
+    // the uart raises its line while its holding register is empty
+    machine.cpu.bus.store(UART_BASE + 1, 8, 2).unwrap();
+    machine
+}
+
+#[test]
+fn claim_and_complete() {
+    let mut machine = machine(1);
+    machine.step().unwrap();
+    // disabled, so only pending
+    assert_eq!(machine.cpu.bus.load(PENDING, 32), Ok(1 << UART_IRQ));
+    assert_eq!(machine.cpu.csrs[MIP] & MEIP, 0);
+
+    machine.cpu.bus.store(PRIORITY, 32, 1).unwrap();
+    machine.cpu.bus.store(enable(0), 32, 1 << UART_IRQ).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & (MEIP | SEIP), MEIP);
+
+    assert_eq!(machine.cpu.bus.load(claim(0), 32), Ok(UART_IRQ.into()));
+    assert_eq!(machine.cpu.bus.load(PENDING, 32), Ok(0));
+    // nothing else to claim
+    assert_eq!(machine.cpu.bus.load(claim(0), 32), Ok(0));
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & MEIP, 0);
+
+    // the line is still high
+    machine
+        .cpu
+        .bus
+        .store(claim(0), 32, UART_IRQ.into())
+        .unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & MEIP, MEIP);
+
+    // and drops once the guest reads iir
+    machine.cpu.bus.load(UART_BASE + 2, 8).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & MEIP, 0);
+}
+
+#[test]
+fn threshold_masks() {
+    let mut machine = machine(1);
+    machine.cpu.bus.store(PRIORITY, 32, 2).unwrap();
+    machine.cpu.bus.store(enable(1), 32, 1 << UART_IRQ).unwrap();
+    machine.cpu.bus.store(threshold(1), 32, 2).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & SEIP, 0);
+    assert_eq!(machine.cpu.bus.load(claim(1), 32), Ok(0));
+
+    machine.cpu.bus.store(threshold(1), 32, 1).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & (MEIP | SEIP), SEIP);
+}
+
+#[test]
+fn other_harts() {
+    let mut machine = machine(2);
+    machine.cpu.bus.store(PRIORITY, 32, 1).unwrap();
+    // machine mode of hart 1
+    machine.cpu.bus.store(enable(2), 32, 1 << UART_IRQ).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.csrs[MIP] & MEIP, 0);
+    assert_eq!(machine.harts[0].csrs[MIP] & MEIP, MEIP);
+}
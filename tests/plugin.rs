@@ -0,0 +1,53 @@
+#![cfg(unix)]
+
+use std::{
+    env::consts::{DLL_EXTENSION, DLL_PREFIX},
+    path::PathBuf,
+};
+
+use rysk::{error::EmulatorError, machine::Machine};
+
+/// The example plugin, built next to the test binaries by `cargo test`.
+fn counter_plugin() -> PathBuf {
+    let deps = std::env::current_exe().unwrap();
+    let path = deps
+        .parent()
+        .unwrap()
+        .with_file_name("examples")
+        .join(format!("{DLL_PREFIX}counter_plugin.{DLL_EXTENSION}"));
+    assert!(
+        path.exists(),
+        "did you run 'cargo build --example counter_plugin' ?"
+    );
+    path
+}
+
+fn program(insts: &[u32]) -> Vec<u8> {
+    insts.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[test]
+fn load_device() {
+    // lui t0, 0x10001; addi t1, zero, 5; sw t1, 0(t0); sw t1, 0(t0); lw t2, 0(t0)
+    let code = program(&[0x100012b7, 0x00500313, 0x0062a023, 0x0062a023, 0x0002a383]);
+    let mut machine = Machine::builder()
+        .image(code)
+        .plugin(counter_plugin(), "0x10001000")
+        .build()
+        .unwrap();
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.regs[7], 7);
+}
+
+#[test]
+fn rejected_plugins() {
+    let result = Machine::builder()
+        .plugin(counter_plugin(), "not a number")
+        .build();
+    assert!(matches!(result, Err(EmulatorError::Plugin(_))));
+
+    let result = Machine::builder().plugin("/nonexistent.so", "").build();
+    assert!(matches!(result, Err(EmulatorError::Plugin(_))));
+}
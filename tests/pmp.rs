@@ -0,0 +1,196 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, Mode, MSTATUS},
+    exception::Exception,
+    mmu::Access,
+    pmp::{PMPADDR0, PMPCFG0},
+};
+
+const R: u64 = 1 << 0;
+const W: u64 = 1 << 1;
+const X: u64 = 1 << 2;
+const TOR: u64 = 1 << 3;
+const NA4: u64 = 2 << 3;
+const NAPOT: u64 = 3 << 3;
+const L: u64 = 1 << 7;
+
+/// `pmpaddr` of the naturally aligned power of two region of `size` bytes at
+/// `base`.
+const fn napot(base: u64, size: u64) -> u64 {
+    (base >> 2) | (size / 8 - 1)
+}
+
+/// A cpu at `mode` with the entries `(cfg, pmpaddr)`.
+fn protected(mode: Mode, entries: &[(u64, u64)]) -> Cpu {
+    let mut cpu = Cpu::new(vec![]);
+    for (i, (cfg, addr)) in entries.iter().enumerate() {
+        cpu.csrs[PMPCFG0] |= cfg << (8 * i);
+        cpu.csrs[PMPADDR0 + i] = *addr;
+    }
+    cpu.mode = mode;
+    cpu
+}
+
+/// The first 64 KiB of dram, allowing everything.
+const RAM: (u64, u64) = (NAPOT | R | W | X, napot(DRAM_BASE, 0x1_0000));
+
+#[rstest]
+#[case::unconfigured(&[], Mode::User, Access::Store, DRAM_BASE, 8, true)]
+#[case::napot(&[RAM], Mode::User, Access::Store, DRAM_BASE + 0xfff8, 8, true)]
+#[case::outside(&[RAM], Mode::User, Access::Load, DRAM_BASE + 0x1_0000, 8, false)]
+#[case::straddling(&[RAM], Mode::User, Access::Load, DRAM_BASE + 0xfffc, 8, false)]
+#[case::machine(&[RAM], Mode::Machine, Access::Load, 0, 8, true)]
+#[case::tor(
+    &[(0, DRAM_BASE >> 2), (TOR | R, (DRAM_BASE + 0x1000) >> 2)],
+    Mode::Supervisor,
+    Access::Load,
+    DRAM_BASE + 0xff8,
+    8,
+    true
+)]
+#[case::tor_read_only(
+    &[(0, DRAM_BASE >> 2), (TOR | R, (DRAM_BASE + 0x1000) >> 2)],
+    Mode::Supervisor,
+    Access::Store,
+    DRAM_BASE,
+    8,
+    false
+)]
+#[case::tor_below(
+    &[(0, DRAM_BASE >> 2), (TOR | R, (DRAM_BASE + 0x1000) >> 2)],
+    Mode::Supervisor,
+    Access::Load,
+    DRAM_BASE - 8,
+    8,
+    false
+)]
+#[case::na4(&[(NA4 | X, DRAM_BASE >> 2)], Mode::User, Access::Fetch, DRAM_BASE, 4, true)]
+#[case::na4_straddling(&[(NA4 | X, DRAM_BASE >> 2)], Mode::User, Access::Fetch, DRAM_BASE, 8, false)]
+#[case::no_execute(&[(NAPOT | R | W, napot(DRAM_BASE, 1 << 12))], Mode::User, Access::Fetch, DRAM_BASE, 4, false)]
+#[case::unlocked(&[(NAPOT | R, RAM.1)], Mode::Machine, Access::Store, DRAM_BASE, 8, true)]
+#[case::locked(&[(L | NAPOT | R, RAM.1)], Mode::Machine, Access::Store, DRAM_BASE, 8, false)]
+#[case::locked_read(&[(L | NAPOT | R, RAM.1)], Mode::Machine, Access::Load, DRAM_BASE, 8, true)]
+// the first matching entry decides
+#[case::priority(&[(NA4, DRAM_BASE >> 2), RAM], Mode::User, Access::Load, DRAM_BASE, 4, false)]
+#[case::priority_next(&[(NA4, DRAM_BASE >> 2), RAM], Mode::User, Access::Load, DRAM_BASE + 4, 4, true)]
+fn check(
+    #[case] entries: &[(u64, u64)],
+    #[case] mode: Mode,
+    #[case] access: Access,
+    #[case] addr: u64,
+    #[case] bytes: u64,
+    #[case] allowed: bool,
+) {
+    let cpu = protected(mode, entries);
+    let expected = match (allowed, access) {
+        (true, _) => Ok(()),
+        (false, Access::Fetch) => Err(Exception::InstructionAccessFault(addr)),
+        (false, Access::Load) => Err(Exception::LoadAccessFault(addr)),
+        (false, Access::Store) => Err(Exception::StoreAmoAccessFault(addr)),
+    };
+    assert_eq!(cpu.pmp_check(addr, addr, bytes, access), expected);
+}
+
+#[test]
+fn mprv() {
+    // machine mode loads as the user
+    let mut cpu = protected(Mode::Machine, &[(NAPOT | X, RAM.1)]);
+    cpu.csrs[MSTATUS] = 1 << 17;
+    assert_eq!(
+        cpu.pmp_check(DRAM_BASE, DRAM_BASE, 8, Access::Load),
+        Err(Exception::LoadAccessFault(DRAM_BASE))
+    );
+    assert_eq!(
+        cpu.pmp_check(DRAM_BASE, DRAM_BASE, 4, Access::Fetch),
+        Ok(())
+    );
+}
+
+#[test]
+fn faults() {
+    // ld t2, 0(t0); sd t2, 0(t0) from user mode, with the code readable but
+    // not writable
+    let code = [0x0002b383u32, 0x0072b023]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = Cpu::new(code);
+    cpu.csrs[PMPCFG0] = NAPOT | R | X;
+    cpu.csrs[PMPADDR0] = RAM.1;
+    cpu.mode = Mode::User;
+    cpu.regs[5] = DRAM_BASE;
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0x0072_b023_0002_b383);
+    assert_eq!(cpu.step(), Err(Exception::StoreAmoAccessFault(DRAM_BASE)));
+
+    cpu.csrs[PMPCFG0] = NAPOT | R | W;
+    assert_eq!(
+        cpu.step(),
+        Err(Exception::InstructionAccessFault(DRAM_BASE + 4))
+    );
+}
+
+#[rstest]
+fn cbo(#[values(0, 1, 2, 4)] op: u32) {
+    // cbo.* (t0) from user mode, with dram readable but not writable
+    let inst = op << 20 | 5 << 15 | 0x2 << 12 | 0x0f;
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.csrs[PMPCFG0] = NAPOT | R | X;
+    cpu.csrs[PMPADDR0] = RAM.1;
+    cpu.mode = Mode::User;
+    cpu.regs[5] = DRAM_BASE + 0x1010;
+    cpu.bus.store(DRAM_BASE + 0x1000, 64, u64::MAX).unwrap();
+    assert_eq!(
+        cpu.step(),
+        Err(Exception::StoreAmoAccessFault(DRAM_BASE + 0x1010))
+    );
+    assert_eq!(cpu.bus.load(DRAM_BASE + 0x1000, 64).unwrap(), u64::MAX);
+}
+
+#[test]
+fn straddling_fetch() {
+    // addi ra, zero, 1 across the end of the executable region
+    let mut cpu = Cpu::new(vec![]);
+    cpu.bus.store(DRAM_BASE + 0xfffe, 16, 0x0093).unwrap();
+    cpu.bus.store(DRAM_BASE + 0x1_0000, 16, 0x0010).unwrap();
+    cpu.csrs[PMPCFG0] = RAM.0;
+    cpu.csrs[PMPADDR0] = RAM.1;
+    cpu.mode = Mode::User;
+    cpu.pc = DRAM_BASE + 0xfffe;
+    assert_eq!(
+        cpu.step(),
+        Err(Exception::InstructionAccessFault(DRAM_BASE + 0x1_0000))
+    );
+
+    cpu.csrs[PMPADDR0] = napot(DRAM_BASE, 0x2_0000);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[1], 1);
+}
+
+#[rstest]
+// csrw pmpcfg0, t0
+#[case::cfg(0x3a029073, PMPCFG0, 0, 0x9f_1f, 0x9f_1f)]
+#[case::write_without_read(0x3a029073, PMPCFG0, 0, NAPOT | W, NAPOT)]
+#[case::locked_cfg(0x3a029073, PMPCFG0, L, 0x1f_1f, 0x1f_80)]
+// csrw pmpcfg1, t0, which RV64 doesn't have
+#[case::odd_cfg(0x3a129073, PMPCFG0 + 1, 0, 0x1f, 0)]
+// csrw pmpaddr0, t0
+#[case::addr(0x3b029073, PMPADDR0, 0, u64::MAX, (1 << 54) - 1)]
+#[case::locked_addr(0x3b029073, PMPADDR0, L, 0x1234, 0)]
+#[case::locked_tor(0x3b029073, PMPADDR0, (L | TOR) << 8, 0x1234, 0)]
+#[case::unlocked_tor(0x3b029073, PMPADDR0, TOR << 8, 0x1234, 0x1234)]
+fn warl(
+    #[case] inst: u32,
+    #[case] csr: usize,
+    #[case] cfg: u64,
+    #[case] value: u64,
+    #[case] expected: u64,
+) {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.csrs[PMPCFG0] = cfg;
+    cpu.regs[5] = value;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[csr], expected);
+}
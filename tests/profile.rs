@@ -0,0 +1,35 @@
+use rysk::{
+    elf::Elf,
+    machine::{ExitReason, Machine},
+    profile::{Cost, Profiler},
+};
+
+#[test]
+fn attributes_by_function() {
+    let data = include_bytes!("bare/calls.elf").to_vec();
+    let profiler = Profiler::from_elf(&Elf::parse(&data).unwrap()).unwrap();
+    let mut machine = Machine::builder().elf(data).build().unwrap();
+    machine.cpu.observers.add(profiler);
+    assert_eq!(machine.run(), ExitReason::Shutdown(0));
+
+    let entries = machine.cpu.observers.get::<Profiler>().unwrap().entries();
+    let costs: Vec<_> = entries
+        .iter()
+        .map(|x| {
+            (
+                x.name.as_str(),
+                x.self_cost.instructions,
+                x.cumulative.instructions,
+            )
+        })
+        .collect();
+    // Without timing models every instruction is a cycle.
+    assert_eq!(costs, [("work", 28, 32), ("_start", 8, 40), ("leaf", 4, 4)]);
+    assert_eq!(
+        entries[2].self_cost,
+        Cost {
+            instructions: 4,
+            cycles: 4
+        }
+    );
+}
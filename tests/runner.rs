@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use rysk::{
+    machine::Machine,
+    runner::{self, Outcome},
+};
+
+fn program(words: &[u32]) -> Machine {
+    let code = words.iter().flat_map(|x| x.to_le_bytes()).collect();
+    Machine::builder().image(code).build().unwrap()
+}
+
+#[test]
+fn outcomes() {
+    // ret, with a0 and ra 0
+    assert_eq!(
+        runner::run(&mut program(&[0x00008067]), 10),
+        Outcome::Passed
+    );
+    // li a0, 1; ret
+    assert_eq!(
+        runner::run(&mut program(&[0x00100513, 0x00008067]), 10),
+        Outcome::Failed(String::from("exit code 1"))
+    );
+    // j 0
+    assert_eq!(
+        runner::run(&mut program(&[0x0000006f]), 10),
+        Outcome::Failed(String::from("didn't stop within 10 instructions"))
+    );
+
+    let code = std::fs::read("tests/bare/finisher.elf").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+    assert_eq!(
+        runner::run(&mut machine, 100),
+        Outcome::Failed(String::from("exit code 5"))
+    );
+}
+
+#[test]
+fn cargo_messages() {
+    let messages = concat!(
+        r#"{"reason":"compiler-artifact","target":{"name":"fw"},"profile":{"test":false},"executable":null}"#,
+        "\n",
+        r#"{"reason":"compiler-artifact","target":{"name":"fw"},"profile":{"test":true},"executable":"/t/deps/fw-1a2b"}"#,
+        "\n",
+        r#"{"reason":"compiler-artifact","profile":{"test":true},"executable":"C:\\t\\it \"x\""}"#,
+        "\n",
+        r#"{"reason":"build-finished","success":true}"#,
+    );
+    assert_eq!(
+        runner::test_executables(messages),
+        [
+            PathBuf::from("/t/deps/fw-1a2b"),
+            PathBuf::from("C:\\t\\it \"x\"")
+        ]
+    );
+}
@@ -0,0 +1,245 @@
+use rstest::rstest;
+use rysk::{
+    bus::{Bus, DRAM_BASE},
+    cpu::{Cpu, INSTRET, MSTATUS, RDCYCLE},
+    dram::Dram,
+    error::EmulatorError,
+    exception::Exception,
+    hpm::MHPMCOUNTER3,
+    isa::{Isa, Xlen},
+    machine::{EbreakPolicy, ExitReason, Machine},
+};
+
+fn rv32(code: Vec<u8>) -> Cpu {
+    let isa = "rv32imac_zicsr_zicntr".parse::<Isa>().unwrap();
+    Cpu::with_bus(Bus::new(Dram::new(code)), isa)
+}
+
+/// Executes `inst` with `a` in t0 and `b` in t1, returning t2.
+fn execute(inst: u32, a: u64, b: u64) -> u64 {
+    let mut cpu = rv32(inst.to_le_bytes().to_vec());
+    cpu.regs[5] = a;
+    cpu.regs[6] = b;
+    cpu.step().unwrap();
+    cpu.regs[7]
+}
+
+const MINUS_ONE: u64 = u64::MAX;
+
+#[rstest]
+// add t2, t0, t1, the registers hold 32-bit values sign extended
+#[case::add_wraps(0x006283b3, 0x7fff_ffff, 1, 0xffff_ffff_8000_0000)]
+#[case::add_carry(0x006283b3, MINUS_ONE, 1, 0)]
+// srl t2, t0, t1, srli t2, t0, 4
+#[case::srl(0x0062d3b3, MINUS_ONE, 4, 0x0fff_ffff)]
+#[case::srli(0x0042d393, 0xffff_ffff_8000_0000, 0, 0x0800_0000)]
+// sll t2, t0, t1 with the shift amount taken mod 32
+#[case::sll(0x006293b3, 1, 33, 2)]
+#[case::sll_wraps(0x006293b3, 1, 31, 0xffff_ffff_8000_0000)]
+// sra t2, t0, t1
+#[case::sra(0x4062d3b3, 0xffff_ffff_8000_0000, 31, MINUS_ONE)]
+// sltu t2, t0, t1
+#[case::sltu(0x0062b3b3, 1, 0xffff_ffff_8000_0000, 1)]
+// mulh, mulhu, mulhsu t2, t0, t1
+#[case::mulh(0x026293b3, MINUS_ONE, MINUS_ONE, 0)]
+#[case::mulhu(0x0262b3b3, MINUS_ONE, MINUS_ONE, 0xffff_ffff_ffff_fffe)]
+#[case::mulhsu(0x0262a3b3, MINUS_ONE, MINUS_ONE, MINUS_ONE)]
+// div, divu, remu t2, t0, t1
+#[case::div_overflow(0x0262c3b3, 0xffff_ffff_8000_0000, MINUS_ONE, 0xffff_ffff_8000_0000)]
+#[case::divu(0x0262d3b3, MINUS_ONE, 2, 0x7fff_ffff)]
+#[case::remu(0x0262f3b3, MINUS_ONE, 10, 5)]
+fn arithmetic(#[case] inst: u32, #[case] a: u64, #[case] b: u64, #[case] expected: u64) {
+    assert_eq!(execute(inst, a, b), expected);
+}
+
+#[rstest]
+// ld t2, 0(t0)
+#[case::ld(0x0002b383)]
+// lwu t2, 0(t0)
+#[case::lwu(0x0002e383)]
+// sd t1, 0(t0)
+#[case::sd(0x0062b023)]
+// addiw t2, t0, 1
+#[case::addiw(0x0012839b)]
+// addw t2, t0, t1
+#[case::addw(0x006283bb)]
+// slli t2, t0, 32
+#[case::slli_shamt5(0x02029393)]
+// amoadd.d t2, t1, (t0)
+#[case::amoadd_d(0x0062b3af)]
+fn illegal(#[case] inst: u32) {
+    let mut cpu = rv32(inst.to_le_bytes().to_vec());
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[test]
+fn illegal_compressed() {
+    // c.ld s0, 0(s1), which is c.flw on RV32
+    let mut cpu = rv32(vec![0x80, 0x60]);
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x6080)));
+}
+
+#[test]
+fn c_jal() {
+    // c.jal 8, the encoding of c.addiw on RV64
+    let mut cpu = rv32(vec![0x21, 0x20, 0, 0, 0, 0, 0, 0]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 8);
+    assert_eq!(cpu.regs[1], (DRAM_BASE + 2) as i32 as u64);
+}
+
+#[test]
+fn addresses() {
+    // lui t0, 0x80000; jalr ra, 8(t0); sw t1, 4(t0); lw t2, 4(t0); amoadd.w t2, t1, (t0)
+    let code: Vec<u8> = [
+        0x800002b7u32,
+        0x008280e7,
+        0x0062a223,
+        0x0042a383,
+        0x0062a3af,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut cpu = rv32(code);
+    cpu.regs[6] = 0x8765_4321;
+
+    // t0 is sign extended, the pc and the addresses are 32 bits
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[5], 0xffff_ffff_8000_0000);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 8);
+    assert_eq!(cpu.regs[1], 0xffff_ffff_8000_0008);
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.bus.load(DRAM_BASE + 4, 32), Ok(0x8765_4321));
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0xffff_ffff_8765_4321);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0xffff_ffff_8000_02b7);
+}
+
+#[test]
+fn stack_pointer() {
+    let cpu = rv32(vec![]);
+    assert_eq!(
+        cpu.regs[2] as u32 as u64,
+        cpu.bus.dram.base + cpu.bus.dram.size()
+    );
+    assert_eq!(cpu.regs[2], cpu.regs[2] as i32 as u64);
+}
+
+#[test]
+fn counters() {
+    // csrr t2, cycle; csrr t2, cycleh; csrr t2, instreth; csrw mcycle, t0; csrw mcycleh, t0
+    let code: Vec<u8> = [
+        0xc00023f3u32,
+        0xc80023f3,
+        0xc82023f3,
+        0xb0029073,
+        0xb8029073,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut cpu = rv32(code);
+    cpu.csrs[RDCYCLE] = 0x5_ffff_fff0;
+    cpu.csrs[INSTRET] = 0x3_0000_0000;
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 0xffff_ffff_ffff_fff1);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 5);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 3);
+
+    // writes replace a half each
+    cpu.regs[5] = 7;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[RDCYCLE], 0x5_0000_0007);
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[RDCYCLE], 0x7_0000_0008);
+}
+
+#[test]
+fn hpm_counters() {
+    // csrr t2, hpmcounter3h; csrw mhpmcounter3, t0; csrw mhpmcounter3h, t0
+    let code: Vec<u8> = [0xc83023f3u32, 0xb0329073, 0xb8329073]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = rv32(code);
+    cpu.csrs[MHPMCOUNTER3] = 0x5_0000_0001;
+
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 5);
+
+    cpu.regs[5] = 7;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MHPMCOUNTER3], 0x5_0000_0007);
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MHPMCOUNTER3], 0x7_0000_0007);
+}
+
+/// An ELF32 executable with a single segment holding `code` at `DRAM_BASE`.
+fn elf32(code: &[u32]) -> Vec<u8> {
+    let code: Vec<u8> = code.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let half = |x: u16| x.to_le_bytes().to_vec();
+    let word = |x: u32| x.to_le_bytes().to_vec();
+    let base = DRAM_BASE as u32;
+    [
+        b"\x7fELF\x01\x01\x01".to_vec(),
+        vec![0; 9],
+        // executable, RISC-V, version 1, entry, phoff, shoff, flags
+        half(2),
+        half(243),
+        word(1),
+        word(base),
+        word(52),
+        word(0),
+        word(0),
+        // ehsize, phentsize, phnum, shentsize, shnum, shstrndx
+        half(52),
+        half(32),
+        half(1),
+        half(40),
+        half(0),
+        half(0),
+        // PT_LOAD, offset, vaddr, paddr, filesz, memsz, flags, align
+        word(1),
+        word(84),
+        word(base),
+        word(base),
+        word(code.len() as u32),
+        word(code.len() as u32),
+        word(5),
+        word(4),
+        code,
+    ]
+    .concat()
+}
+
+#[test]
+fn elf() {
+    // li a0, 42; ebreak
+    let elf = elf32(&[0x02a00513, 0x00100073]);
+    let mut machine = Machine::builder()
+        .isa("rv32im_zicsr")
+        .elf(elf.clone())
+        .ebreak(EbreakPolicy::Exit)
+        .build()
+        .unwrap();
+    assert_eq!(machine.cpu.isa.xlen, Xlen::Rv32);
+    assert_eq!(machine.run(), ExitReason::Shutdown(42));
+
+    assert!(matches!(
+        Machine::builder().elf(elf).build(),
+        Err(EmulatorError::InvalidElf(_))
+    ));
+}
+
+#[test]
+fn no_status_xlens() {
+    // mstatus has no UXL or SXL
+    assert_eq!(rv32(vec![]).csrs[MSTATUS] >> 32, 0);
+}
@@ -0,0 +1,79 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use rysk::{
+    elf::Elf,
+    machine::{ExitReason, Firmware, Machine},
+    time::InstretClock,
+};
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn builtin_firmware() {
+    let data = std::fs::read("tests/bare/sbi.elf").unwrap();
+    let results = Elf::parse(&data).unwrap().symbol("results").unwrap();
+    let mut machine = Machine::builder()
+        .elf(data)
+        .firmware(Firmware::Builtin)
+        .build()
+        .unwrap();
+    machine.cpu.clock = Box::new(InstretClock);
+    let output = Output::default();
+    let sbi = machine.sbi.take().unwrap();
+    machine.sbi = Some(sbi.with_output(output.clone()));
+
+    // The shutdown reported a system failure.
+    assert_eq!(machine.run(), ExitReason::Shutdown(1));
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"hi!\n");
+    let results: Vec<_> = (0..4)
+        .map(|i| machine.cpu.bus.load(results + i * 8, 64).unwrap())
+        .collect();
+    // Bytes written, SRST and an unknown extension probed, STIP and SSIP.
+    assert_eq!(results, [3, 1, 0, 0x22]);
+}
+
+#[test]
+fn parse_firmware() {
+    assert_eq!("builtin".parse(), Ok(Firmware::Builtin));
+    assert_eq!("none".parse(), Ok(Firmware::None));
+    assert!("opensbi".parse::<Firmware>().is_err());
+}
+
+#[test]
+fn reboot() {
+    // li a7, SRST; li a6, 0; li a0, 1; li a1, 0; ecall; j .
+    let code: Vec<u8> = [
+        0x535258b7u32,
+        0x35488893,
+        0x00000813,
+        0x00100513,
+        0x00000593,
+        0x00000073,
+        0x0000006f,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .firmware(Firmware::Builtin)
+        .build()
+        .unwrap();
+    // A cold reboot.
+    assert_eq!(machine.run(), ExitReason::Reboot);
+    assert_eq!(machine.exit_code(), None);
+}
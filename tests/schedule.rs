@@ -0,0 +1,90 @@
+use rysk::{
+    error::EmulatorError,
+    machine::{ExitReason, Machine, StopCondition},
+    schedule::{self, Turn},
+};
+
+fn turns(turns: &[(u64, u64)]) -> Vec<Turn> {
+    turns
+        .iter()
+        .map(|&(hart, instructions)| Turn { hart, instructions })
+        .collect()
+}
+
+fn spin(instructions: u64) -> StopCondition {
+    StopCondition {
+        max_instructions: Some(instructions),
+        ..StopCondition::default()
+    }
+}
+
+#[test]
+fn records_quantum_turns() {
+    // addi a0, a0, 1; j .-4
+    let code = [0x00150513u32, 0xffdff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder()
+        .image(code)
+        .harts(2)
+        .quantum(3)
+        .record_schedule(true)
+        .build()
+        .unwrap();
+    assert_eq!(machine.run_until(&spin(10)), ExitReason::MaxInstructions);
+    assert_eq!(
+        machine.schedule(),
+        Some(turns(&[(0, 3), (1, 3), (0, 3), (1, 1)]))
+    );
+    // Hart 1 is running, it started with its id in a0.
+    assert_eq!(machine.cpu.hart_id(), 1);
+    assert_eq!(machine.cpu.regs[10], 1 + 2);
+    assert_eq!(machine.harts[0].regs[10], 3);
+
+    // A single hart keeps one long turn.
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .record_schedule(true)
+        .build()
+        .unwrap();
+    machine.run_until(&spin(2500));
+    assert_eq!(machine.schedule(), Some(turns(&[(0, 2500)])));
+    assert!(Machine::builder().quantum(0).build().is_err());
+}
+
+#[test]
+fn replays_turns() {
+    let code: Vec<u8> = [0x00150513u32, 0xffdff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let replayed = turns(&[(0, 5), (1, 2), (0, 1), (1, 4)]);
+    let mut machine = Machine::builder()
+        .image(code)
+        .harts(2)
+        .replay_schedule(replayed.clone())
+        .record_schedule(true)
+        .build()
+        .unwrap();
+    machine.run_until(&spin(12));
+    assert_eq!(machine.schedule(), Some(replayed));
+    // Round-robin again, hart 1 ran 6 instructions and hart 0 runs.
+    assert_eq!(machine.cpu.hart_id(), 0);
+    assert_eq!(machine.harts[0].regs[10], 1 + 3);
+    machine.run_until(&spin(1000));
+    assert_eq!(machine.cpu.hart_id(), 1);
+}
+
+#[test]
+fn text_format() {
+    let recorded = turns(&[(0, 1000), (3, 12), (1, 7)]);
+    let mut text = Vec::new();
+    schedule::write(&recorded, &mut text).unwrap();
+    assert_eq!(text, b"0 1000\n3 12\n1 7\n");
+    assert_eq!(schedule::read(&text[..]).unwrap(), recorded);
+    assert!(matches!(
+        schedule::read(&b"0 10\n1\n"[..]),
+        Err(EmulatorError::InvalidSchedule(_))
+    ));
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "script")]
+
+use rysk::{bus::Bus, dram::Dram, machine::Machine, script::Script};
+
+fn program(insts: &[u32]) -> Vec<u8> {
+    insts.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[test]
+fn patch_function() {
+    // jal ra, 12; addi a1, a0, 1; ebreak; addi a0, zero, 7; ret
+    let code = program(&[0x00c000ef, 0x00150593, 0x00100073, 0x00700513, 0x00008067]);
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let script = r#"
+        on_breakpoint(0x8000000c, |cpu| {
+            cpu[10] = 41;
+            cpu.pc = cpu[1];
+        });
+    "#;
+    machine.script = Some(Script::new(script, &mut machine.cpu.bus).unwrap());
+
+    while machine.step().is_ok() {}
+    assert_eq!(machine.cpu.regs[10], 41);
+    assert_eq!(machine.cpu.regs[11], 42);
+}
+
+#[test]
+fn mmio_and_traps() {
+    // lui t0, 0x10000; lw t1, 4(t0); sw t1, 8(t0); ebreak; lw t2, 8(t0)
+    let code = program(&[0x100002b7, 0x0042a303, 0x0062a423, 0x00100073, 0x0082a383]);
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let script = r#"
+        on_mmio(0x10000000, 0x10, |offset, size| offset * 10 + size, |offset, size, value| {});
+        on_trap(|cpu, cause, tval| {
+            if cause != 3 { return false; }
+            cpu.store(0x80000100, 64, cpu[6]);
+            cpu.pc += 4;
+            true
+        });
+    "#;
+    machine.script = Some(Script::new(script, &mut machine.cpu.bus).unwrap());
+
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.regs[6], 4 * 10 + 32);
+    assert_eq!(machine.cpu.regs[7], 80 + 32);
+    assert_eq!(machine.cpu.bus.load(0x8000_0100, 64), Ok(72));
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn invalid_script() {
+    let mut bus = Bus::new(Dram::new(Vec::new()));
+    assert!(Script::new("on_breakpoint(", &mut bus).is_err());
+}
@@ -0,0 +1,48 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use rysk::{
+    exception::Exception,
+    machine::{ExitReason, Machine},
+    semihosting::Semihosting,
+};
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn console_and_exit() {
+    let code = std::fs::read("tests/bare/semihosting.elf").expect("did you run 'make test' ?");
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+
+    let output = Output::default();
+    machine.semihosting = Some(Semihosting::default().with_output(output.clone()));
+
+    assert_eq!(machine.run(), ExitReason::Shutdown(3));
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"hi\n!");
+}
+
+#[test]
+fn ebreak_without_semihosting() {
+    let code = std::fs::read("tests/bare/semihosting.elf").unwrap();
+    let mut machine = Machine::builder().elf(code).build().unwrap();
+    // The first call stops at its ebreak.
+    assert_eq!(
+        machine.run(),
+        ExitReason::Exception(Exception::Breakpoint(0x8000_0044))
+    );
+    assert_eq!(machine.exit_code(), None);
+    assert_eq!(machine.cpu.pc, 0x8000_0044);
+}
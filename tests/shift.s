@@ -0,0 +1,8 @@
+main:
+  addi t0, zero, 1
+  slli t1, t0, 44
+  srli t2, t1, 40
+  addi t3, zero, -16
+  srai t4, t3, 2
+  srli t5, t3, 60
+  auipc t6, 1
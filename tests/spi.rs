@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+
+use rysk::{
+    bus::Bus,
+    machine::Machine,
+    spi::{sd::BLOCK_SIZE, SPI_BASE},
+};
+
+const CSMODE: u64 = SPI_BASE + 0x18;
+const TXDATA: u64 = SPI_BASE + 0x48;
+const RXDATA: u64 = SPI_BASE + 0x4c;
+
+/// A machine with an SD card holding 1 MiB, block `n` filled with `n`.
+fn machine(name: &str) -> (Machine, PathBuf) {
+    let path = std::env::temp_dir().join(format!("rysk-sd-{name}-{}", std::process::id()));
+    let image: Vec<u8> = (0..2048).flat_map(|n| [n as u8; BLOCK_SIZE]).collect();
+    std::fs::write(&path, image).unwrap();
+    let mut machine = Machine::builder().sd_card(&path).build().unwrap();
+    // hold the chip select
+    machine.cpu.bus.store(CSMODE, 32, 2).unwrap();
+    (machine, path)
+}
+
+fn transfer(bus: &mut Bus, byte: u8) -> u8 {
+    bus.store(TXDATA, 32, byte.into()).unwrap();
+    let rx = bus.load(RXDATA, 32).unwrap();
+    assert_eq!(rx >> 31, 0, "the receive FIFO is empty");
+    rx as u8
+}
+
+/// Sends a command and returns its R1.
+fn command(bus: &mut Bus, index: u8, arg: u32) -> u8 {
+    let arg = arg.to_be_bytes();
+    for byte in [0x40 | index, arg[0], arg[1], arg[2], arg[3], 0x01] {
+        transfer(bus, byte);
+    }
+    (0..8)
+        .map(|_| transfer(bus, 0xff))
+        .find(|&x| x != 0xff)
+        .expect("no response")
+}
+
+fn read(bus: &mut Bus, len: usize) -> Vec<u8> {
+    (0..len).map(|_| transfer(bus, 0xff)).collect()
+}
+
+/// Waits for the start token of a data block and returns the block.
+fn data_block(bus: &mut Bus, len: usize) -> Vec<u8> {
+    assert!(
+        (0..16).any(|_| transfer(bus, 0xff) == 0xfe),
+        "no data token"
+    );
+    let data = read(bus, len);
+    // the CRC
+    read(bus, 2);
+    data
+}
+
+fn init(bus: &mut Bus) {
+    assert_eq!(command(bus, 0, 0), 0x01);
+    assert_eq!(command(bus, 8, 0x1aa), 0x01);
+    assert_eq!(read(bus, 4), [0, 0, 0x01, 0xaa]);
+    assert_eq!(command(bus, 41, 0), 0x05, "needs CMD55 first");
+    assert_eq!(command(bus, 55, 0), 0x01);
+    assert_eq!(command(bus, 41, 1 << 30), 0x00);
+    assert_eq!(command(bus, 58, 0), 0x00);
+    // powered up and high capacity
+    assert_eq!(read(bus, 4)[0], 0xc0);
+}
+
+#[test]
+fn sd_card_reads() {
+    let (mut machine, path) = machine("read");
+    let bus = &mut machine.cpu.bus;
+    init(bus);
+
+    assert_eq!(command(bus, 9, 0), 0x00);
+    let csd = data_block(bus, 16);
+    assert_eq!(csd[0] >> 6, 1, "version 2.0");
+    // 2 units of 512 KiB
+    assert_eq!(csd[9], 1);
+
+    assert_eq!(command(bus, 17, 3), 0x00);
+    assert_eq!(data_block(bus, BLOCK_SIZE), [3; BLOCK_SIZE]);
+    assert_eq!(command(bus, 17, 2048), 0x20, "address error");
+
+    assert_eq!(command(bus, 18, 7), 0x00);
+    assert_eq!(data_block(bus, BLOCK_SIZE), [7; BLOCK_SIZE]);
+    assert_eq!(data_block(bus, BLOCK_SIZE), [8; BLOCK_SIZE]);
+    assert_eq!(command(bus, 12, 0), 0x00);
+    assert_eq!(command(bus, 13, 0), 0x00);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn sd_card_writes() {
+    let (mut machine, path) = machine("write");
+    let bus = &mut machine.cpu.bus;
+    init(bus);
+
+    assert_eq!(command(bus, 24, 5), 0x00);
+    transfer(bus, 0xfe);
+    for _ in 0..BLOCK_SIZE + 2 {
+        transfer(bus, 0xaa);
+    }
+    // data accepted, then busy
+    assert_eq!(transfer(bus, 0xff) & 0x1f, 0x05);
+    while transfer(bus, 0xff) != 0xff {}
+
+    assert_eq!(command(bus, 25, 10), 0x00);
+    for byte in [0xb0, 0xb1] {
+        transfer(bus, 0xfc);
+        for _ in 0..BLOCK_SIZE + 2 {
+            transfer(bus, byte);
+        }
+        assert_eq!(transfer(bus, 0xff) & 0x1f, 0x05);
+        while transfer(bus, 0xff) != 0xff {}
+    }
+    transfer(bus, 0xfd);
+    while transfer(bus, 0xff) != 0xff {}
+
+    let image = std::fs::read(&path).unwrap();
+    let block = |n: usize| &image[n * BLOCK_SIZE..(n + 1) * BLOCK_SIZE];
+    assert_eq!(block(4), [4; BLOCK_SIZE]);
+    assert_eq!(block(5), [0xaa; BLOCK_SIZE]);
+    assert_eq!(block(10), [0xb0; BLOCK_SIZE]);
+    assert_eq!(block(11), [0xb1; BLOCK_SIZE]);
+    assert_eq!(block(12), [12; BLOCK_SIZE]);
+
+    assert_eq!(command(bus, 17, 11), 0x00);
+    assert_eq!(data_block(bus, BLOCK_SIZE), [0xb1; BLOCK_SIZE]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn chip_select() {
+    let (mut machine, path) = machine("cs");
+    let bus = &mut machine.cpu.bus;
+    // nothing on chip select 1
+    bus.store(SPI_BASE + 0x10, 32, 1).unwrap();
+    assert_eq!(command_bytes(bus), [0xff; 8]);
+    // no chip select active
+    bus.store(SPI_BASE + 0x10, 32, 0).unwrap();
+    bus.store(CSMODE, 32, 3).unwrap();
+    assert_eq!(command_bytes(bus), [0xff; 8]);
+    bus.store(CSMODE, 32, 2).unwrap();
+    assert_eq!(command(bus, 0, 0), 0x01);
+    assert_eq!(bus.load(RXDATA, 32).unwrap() >> 31, 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+/// What the card sends back to a `CMD0`, which only the selected card takes.
+fn command_bytes(bus: &mut Bus) -> Vec<u8> {
+    for byte in [0x40, 0, 0, 0, 0, 0x95] {
+        transfer(bus, byte);
+    }
+    read(bus, 8)
+}
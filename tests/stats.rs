@@ -0,0 +1,132 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use rysk::{
+    machine::Machine,
+    stats::{Counters, Period, Stats, Traffic},
+};
+
+/// Collects what the observer writes.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn parses_periods() {
+    assert_eq!("10s".parse(), Ok(Period::Time(Duration::from_secs(10))));
+    assert_eq!("50M".parse(), Ok(Period::Instructions(50_000_000)));
+    assert_eq!("1000".parse(), Ok(Period::Instructions(1000)));
+    for period in ["0s", "10h", "M", "99999999999G"] {
+        assert!(period.parse::<Period>().is_err(), "{period}");
+    }
+}
+
+#[test]
+fn reports_every_period() {
+    //   li t0, 0x100000
+    // 1:
+    //   sw zero, 0(t0)    # not a test finisher command, ignored
+    //   addi t1, t1, 1
+    //   j 1b
+    let code = [0x001002b7u32, 0x0002a023, 0x00130313, 0xff9ff06f]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let output = Output::default();
+    machine
+        .cpu
+        .observers
+        .add(Stats::periodic(Period::Instructions(30), output.clone()));
+    for _ in 0..100 {
+        machine.cpu.step().unwrap();
+    }
+
+    let stats = machine.cpu.observers.get::<Stats>().unwrap();
+    assert_eq!(
+        stats.counters,
+        Counters {
+            instructions: 100,
+            mmio_writes: 33,
+            ..Counters::default()
+        }
+    );
+    let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<_> = output.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[2].contains(": 90 instructions ("), "{}", lines[2]);
+    assert!(lines[2].ends_with(", 30 MMIO writes"), "{}", lines[2]);
+}
+
+#[test]
+fn counts_traffic_by_region() {
+    //   li t0, 0x100000
+    //   sw zero, 0(t0)
+    //   sd zero, -8(sp)
+    //   lh t1, -7(sp)
+    //   lbu t1, -8(sp)
+    let code = [
+        0x001002b7u32,
+        0x0002a023,
+        0xfe013c23,
+        0xff911303,
+        0xff814303,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let stats = Stats::new().regions(&machine.cpu.bus);
+    machine.cpu.observers.add(stats);
+    for _ in 0..5 {
+        machine.cpu.step().unwrap();
+    }
+
+    let stats = machine.cpu.observers.get::<Stats>().unwrap();
+    let finisher = stats.regions.iter().find(|x| x.base == 0x100000).unwrap();
+    assert_eq!(finisher.traffic.stores, [0, 0, 1, 0]);
+    let dram = stats.regions.iter().find(|x| x.name == "dram").unwrap();
+    assert_eq!(
+        dram.traffic,
+        Traffic {
+            loads: [1, 1, 0, 0],
+            stores: [0, 0, 0, 1],
+            misaligned: 1,
+        }
+    );
+    assert!(stats.to_string().contains("mmio 0x100000-0x101000"));
+}
+
+#[test]
+fn counts_csr_accesses() {
+    //   csrr a0, mscratch
+    //   csrr a0, mscratch
+    //   csrw mscratch, a0
+    //   csrr a1, cycle
+    let code = [0x34002573u32, 0x34002573, 0x34051073, 0xc00025f3]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.observers.add(Stats::new());
+    for _ in 0..4 {
+        machine.cpu.step().unwrap();
+    }
+
+    let stats = machine.cpu.observers.get::<Stats>().unwrap();
+    // csrw doesn't read, as rd is zero.
+    assert_eq!(stats.hot_csrs(10), [(0x340, [2, 1]), (0xc00, [1, 0])]);
+    assert!(stats.to_string().contains("mscratch (0x340)"));
+}
@@ -0,0 +1,34 @@
+use std::time::{Duration, Instant};
+
+use rysk::{
+    machine::{ExitReason, Machine, StopCondition},
+    throttle::parse_frequency,
+};
+
+#[test]
+fn paces_instructions() {
+    // j .
+    let build = || Machine::builder().image(vec![0x6f, 0, 0, 0]);
+    let stop = StopCondition {
+        max_instructions: Some(20_000),
+        ..StopCondition::default()
+    };
+    let mut machine = build().frequency(100_000).build().unwrap();
+    assert_eq!(machine.throttle.as_ref().unwrap().frequency(), 100_000);
+    let start = Instant::now();
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    assert!(start.elapsed() >= Duration::from_millis(190));
+
+    assert!(build().build().unwrap().throttle.is_none());
+}
+
+#[test]
+fn parse_frequencies() {
+    assert_eq!(parse_frequency("50mhz"), Some(50_000_000));
+    assert_eq!(parse_frequency("1GHz"), Some(1_000_000_000));
+    assert_eq!(parse_frequency("32768hz"), Some(32768));
+    assert_eq!(parse_frequency("100k"), Some(100_000));
+    assert_eq!(parse_frequency("12"), Some(12));
+    assert_eq!(parse_frequency("0hz"), None);
+    assert_eq!(parse_frequency("fast"), None);
+}
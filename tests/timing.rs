@@ -0,0 +1,80 @@
+use rysk::{
+    instruction::Instruction,
+    machine::Machine,
+    timing::{Pipeline, PipelineConfig},
+};
+
+fn program(words: &[u32], config: PipelineConfig) -> Machine {
+    let code = words.iter().flat_map(|x| x.to_le_bytes()).collect();
+    Machine::builder()
+        .image(code)
+        .pipeline(config)
+        .build()
+        .unwrap()
+}
+
+//   ld t2, -8(sp)
+//   addi t3, t2, 1
+//   mul t4, t3, t3
+//   j 1f
+//   nop
+// 1:
+//   csrr a0, mcycle
+//   csrr a1, minstret
+const STALLS: [u32; 7] = [
+    0xff813383, 0x00138e13, 0x03ce0eb3, 0x0080006f, 0x00000013, 0xb0002573, 0xb02025f3,
+];
+
+#[test]
+fn charges_stalls() {
+    let mut machine = program(&STALLS, PipelineConfig::default());
+    for _ in 0..6 {
+        machine.cpu.step().unwrap();
+    }
+    // One cycle per instruction before the csrr, plus a load-use stall, the
+    // multiplier and the jump.
+    assert_eq!(machine.cpu.regs[10], 5 + 1 + 2 + 2);
+    // the csrr itself hasn't retired yet
+    assert_eq!(machine.cpu.regs[11], 5);
+
+    let pipeline = machine.cpu.pipeline.as_ref().unwrap();
+    assert_eq!(
+        (
+            pipeline.load_use_stalls,
+            pipeline.branch_flushes,
+            pipeline.execute_stalls
+        ),
+        (1, 2, 2)
+    );
+}
+
+#[test]
+fn configures_latencies() {
+    let config: PipelineConfig = "branch=3,mul=0".parse().unwrap();
+    assert_eq!(
+        config,
+        PipelineConfig {
+            branch: 3,
+            mul: 0,
+            ..PipelineConfig::default()
+        }
+    );
+    assert!("branch".parse::<PipelineConfig>().is_err());
+    assert!("fetch=1".parse::<PipelineConfig>().is_err());
+
+    let mut machine = program(&STALLS, config);
+    for _ in 0..6 {
+        machine.cpu.step().unwrap();
+    }
+    assert_eq!(machine.cpu.regs[10], 5 + 1 + 3);
+}
+
+#[test]
+fn no_stall_without_dependency() {
+    let mut pipeline = Pipeline::default();
+    let load = Instruction::decode(0xff813383);
+    // addi t3, t1, 1
+    let independent = Instruction::decode(0x00130e13);
+    assert_eq!(pipeline.retire(&load, 0, 4), 0);
+    assert_eq!(pipeline.retire(&independent, 4, 8), 0);
+}
@@ -0,0 +1,98 @@
+use std::{net::TcpListener, thread};
+
+use rysk::{
+    finisher::FINISHER_BASE,
+    machine::{ExitReason, Machine},
+    trace::{TraceEvent, TraceReader, TraceStream, TRACE_VERSION},
+};
+
+#[test]
+fn stream_over_a_socket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let analyzer = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = TraceReader::new(stream).unwrap();
+        assert_eq!(reader.version, TRACE_VERSION);
+        let mut events = Vec::new();
+        while let Some(event) = reader.next_event().unwrap() {
+            events.push(event);
+        }
+        events
+    });
+
+    // lui t0, 0x100; lui t1, 0x5; addi t1, t1, 0x555; sw t1, 0(t0)
+    let code = [0x001002b7u32, 0x00005337, 0x55530313, 0x0062a023]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let base = machine.cpu.pc;
+    machine
+        .cpu
+        .observers
+        .add(TraceStream::connect(addr).unwrap());
+    assert_eq!(machine.run(), ExitReason::Shutdown(0));
+    // Flushes the stream and closes the connection.
+    drop(machine);
+
+    let events = analyzer.join().unwrap();
+    assert_eq!(
+        events,
+        [
+            TraceEvent::Instruction {
+                pc: base,
+                raw: 0x001002b7
+            },
+            TraceEvent::Instruction {
+                pc: base + 4,
+                raw: 0x00005337
+            },
+            TraceEvent::Instruction {
+                pc: base + 8,
+                raw: 0x55530313
+            },
+            TraceEvent::Mmio {
+                addr: FINISHER_BASE,
+                bits: 32,
+                value: 0x5555,
+                write: true
+            },
+            TraceEvent::Instruction {
+                pc: base + 12,
+                raw: 0x0062a023
+            },
+        ]
+    );
+}
+
+#[test]
+fn encoding() {
+    let mut buf = Vec::new();
+    TraceEvent::Exception {
+        pc: 0x8000_0000,
+        cause: 2,
+        tval: 0,
+    }
+    .encode(&mut buf);
+    assert_eq!(buf, [0x84, 0x01, 0x1a, 0x80, 0x00, 0x00, 0x00, 0x02, 0x00]);
+
+    let mut stream = Vec::new();
+    stream.extend_from_slice(b"\xa2\x66format\x6arysk-trace\x67version\x01");
+    // An unknown kind of event is skipped.
+    stream.extend_from_slice(&[0x82, 0x18, 0x63, 0x00]);
+    stream.extend_from_slice(&buf);
+    let mut reader = TraceReader::new(stream.as_slice()).unwrap();
+    assert_eq!(
+        reader.next_event().unwrap(),
+        Some(TraceEvent::Exception {
+            pc: 0x8000_0000,
+            cause: 2,
+            tval: 0
+        })
+    );
+    assert_eq!(reader.next_event().unwrap(), None);
+
+    assert!(TraceReader::new(&b"\xa0"[..]).is_err());
+    assert!(TraceReader::new(&b"\x80"[..]).is_err());
+}
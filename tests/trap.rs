@@ -0,0 +1,528 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{
+        Cpu, Mode, MCAUSE, MCOUNTEREN, MEDELEG, MENVCFG, MENVCFG_STCE, MEPC, MIDELEG, MIE, MIP,
+        MSTATUS, MTVAL, MTVEC, SCAUSE, SCOUNTEREN, SEPC, STIMECMP, STVAL, STVEC,
+    },
+    disasm::disassemble,
+    exception::{Exception, Interrupt},
+    isa::Isa,
+    machine::{EbreakPolicy, ExitReason, Machine},
+    time::InstretClock,
+};
+
+/// `mul t2, t0, t1`, illegal without M.
+const MUL: u32 = 0x026283b3;
+
+fn code(insts: &[u32]) -> Vec<u8> {
+    insts.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+#[test]
+fn exception_traps_to_mtvec() {
+    // auipc t0, 0; addi t0, t0, 20; csrw mtvec, t0; csrsi mstatus, 8; mul t2, t0, t1
+    // handler: csrr a0, mcause; csrr a1, mepc; csrr a2, mtval; ebreak
+    let image = code(&[
+        0x00000297, 0x01428293, 0x30529073, 0x30046073, MUL, 0x34202573, 0x341025f3, 0x34302673,
+        0x00100073,
+    ]);
+    let mut machine = Machine::builder()
+        .isa("rv64i_zicsr")
+        .image(image)
+        .ebreak(EbreakPolicy::Exit)
+        .build()
+        .unwrap();
+
+    assert_eq!(machine.run(), ExitReason::Shutdown(2));
+    assert_eq!(machine.cpu.regs[11], DRAM_BASE + 16);
+    assert_eq!(machine.cpu.regs[12], MUL as u64);
+    // SXL = UXL = 64, FS = initial, MPP = M, MPIE = 1, MIE = 0
+    assert_eq!(machine.cpu.csrs[MSTATUS], 0xa_0000_3880);
+}
+
+#[test]
+fn step_trap() {
+    let mut machine = Machine::builder()
+        .isa("rv64i_zicsr")
+        .image(code(&[MUL]))
+        .build()
+        .unwrap();
+    machine.cpu.csrs[MTVEC] = DRAM_BASE + 0x100;
+
+    assert_eq!(
+        machine.step_trap(),
+        Ok(Some(Exception::IllegalInstruction(MUL as u64)))
+    );
+    assert_eq!(machine.cpu.pc, DRAM_BASE + 0x100);
+}
+
+#[test]
+fn unhandled() {
+    // no handler, the exception is returned with the pc left at the instruction
+    let mut cpu = Cpu::new(code(&[MUL]));
+    cpu.isa = "rv64i".parse::<Isa>().unwrap();
+    assert_eq!(cpu.run(), Exception::IllegalInstruction(MUL as u64));
+    assert_eq!(cpu.pc, DRAM_BASE);
+    assert_eq!(cpu.csrs[MCAUSE], 0);
+
+    // a handler which can't be fetched stops the run instead of trapping again
+    cpu.csrs[MTVEC] = 0x1000;
+    assert_eq!(cpu.run(), Exception::InstructionAccessFault(0x1000));
+    assert_eq!(cpu.csrs[MEPC], DRAM_BASE);
+    assert_eq!(cpu.csrs[MCAUSE], 2);
+}
+
+#[test]
+fn fault_in_handler() {
+    // the handler's own first instruction faulting traps again like any other
+    let mut cpu = Cpu::new(code(&[MUL]));
+    cpu.isa = "rv64i_zicsr".parse::<Isa>().unwrap();
+    cpu.csrs[MTVEC] = DRAM_BASE;
+    let exception = cpu.step().unwrap_err();
+    assert_eq!(cpu.trap(exception), Ok(()));
+    assert_eq!(cpu.pc, DRAM_BASE);
+    assert_eq!(cpu.csrs[MEPC], DRAM_BASE);
+    assert_eq!(cpu.csrs[MCAUSE], 2);
+}
+
+#[rstest]
+// jal ra, 6
+#[case::jal(0x006000ef, DRAM_BASE + 6)]
+// jalr ra, 6(zero)
+#[case::jalr(0x006000e7, 6)]
+// beq zero, zero, 6
+#[case::branch(0x00000363, DRAM_BASE + 6)]
+fn misaligned_target(#[case] inst: u32, #[case] target: u64) {
+    let mut cpu = Cpu::new(code(&[inst]));
+    cpu.isa = "rv64ima".parse::<Isa>().unwrap();
+    assert_eq!(
+        cpu.step(),
+        Err(Exception::InstructionAddressMisaligned(target))
+    );
+    assert_eq!(cpu.pc, DRAM_BASE);
+    assert_eq!(cpu.regs[1], 0);
+
+    // C allows the 2 byte boundary
+    let mut cpu = Cpu::new(code(&[inst]));
+    cpu.isa = "rv64imac".parse::<Isa>().unwrap();
+    assert_eq!(cpu.step(), Ok(()));
+    assert_eq!(cpu.pc, target);
+}
+
+/// A cpu executing `inst` in `mode`, with `mstatus` set to `mstatus`.
+fn privileged(inst: u32, mode: Mode, mstatus: u64) -> Cpu {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.mode = mode;
+    cpu.csrs[MSTATUS] = mstatus;
+    cpu
+}
+
+const TVM: u64 = 1 << 20;
+const TW: u64 = 1 << 21;
+const TSR: u64 = 1 << 22;
+const MRET: u32 = 0x30200073;
+const SRET: u32 = 0x10200073;
+
+#[rstest]
+// csrr t2, mstatus
+#[case::machine_csr(0x300023f3, Mode::Supervisor, 0)]
+#[case::supervisor_csr(0x100023f3, Mode::User, 0)]
+// csrw cycle, t0, a read only csr
+#[case::read_only(0xc0029073, Mode::Machine, 0)]
+// csrw mhartid, t0
+#[case::read_only_machine(0xf1429073, Mode::Machine, 0)]
+// csrr t2, hstatus; csrr t2, dcsr
+#[case::hypervisor_csr(0x600023f3, Mode::Machine, 0)]
+#[case::debug_csr(0x7b0023f3, Mode::Machine, 0)]
+// csrr t2, satp
+#[case::tvm(0x180023f3, Mode::Supervisor, TVM)]
+#[case::sfence_vma(0x12000073, Mode::User, 0)]
+#[case::sfence_vma_tvm(0x12000073, Mode::Supervisor, TVM)]
+#[case::wfi(0x10500073, Mode::User, 0)]
+#[case::wfi_tw(0x10500073, Mode::Supervisor, TW)]
+#[case::mret(MRET, Mode::Supervisor, 0)]
+#[case::sret(SRET, Mode::User, 0)]
+#[case::sret_tsr(SRET, Mode::Supervisor, TSR)]
+fn illegal(#[case] inst: u32, #[case] mode: Mode, #[case] mstatus: u64) {
+    let mut cpu = privileged(inst, mode, mstatus);
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[rstest]
+// csrr t2, sstatus; csrr t2, satp
+#[case::supervisor_csr(0x100023f3, Mode::Supervisor, 0)]
+#[case::satp(0x180023f3, Mode::Supervisor, 0)]
+// csrr t2, mhartid, reading a read only csr
+#[case::read_only(0xf14023f3, Mode::Machine, 0)]
+#[case::sfence_vma(0x12000073, Mode::Supervisor, 0)]
+#[case::wfi(0x10500073, Mode::Supervisor, 0)]
+#[case::wfi_tw(0x10500073, Mode::Machine, TW)]
+fn allowed(#[case] inst: u32, #[case] mode: Mode, #[case] mstatus: u64) {
+    let mut cpu = privileged(inst, mode, mstatus);
+    assert_eq!(cpu.step(), Ok(()));
+}
+
+#[rstest]
+// rdcycle t2
+#[case::machine(0xc00023f3, Mode::Machine, 0, 0, true)]
+#[case::supervisor(0xc00023f3, Mode::Supervisor, 0b001, 0, true)]
+#[case::supervisor_disabled(0xc00023f3, Mode::Supervisor, 0b110, 0b001, false)]
+#[case::user(0xc00023f3, Mode::User, 0b001, 0b001, true)]
+#[case::user_machine_disabled(0xc00023f3, Mode::User, 0, 0b001, false)]
+#[case::user_supervisor_disabled(0xc00023f3, Mode::User, 0b001, 0, false)]
+// rdtime t2, rdinstret t2
+#[case::time(0xc01023f3, Mode::User, 0b010, 0b010, true)]
+#[case::instret(0xc02023f3, Mode::User, 0b011, 0b011, false)]
+// csrr t2, hpmcounter31
+#[case::hpmcounter(0xc1f023f3, Mode::Supervisor, 1 << 31, 0, true)]
+fn counteren(
+    #[case] inst: u32,
+    #[case] mode: Mode,
+    #[case] mcounteren: u64,
+    #[case] scounteren: u64,
+    #[case] allowed: bool,
+) {
+    let mut cpu = privileged(inst, mode, 0);
+    cpu.csrs[MCOUNTEREN] = mcounteren;
+    cpu.csrs[SCOUNTEREN] = scounteren;
+    let expected = match allowed {
+        true => Ok(()),
+        false => Err(Exception::IllegalInstruction(inst as u64)),
+    };
+    assert_eq!(cpu.step(), expected);
+}
+
+#[rstest]
+// csrr t2, stimecmp
+#[case::machine(Mode::Machine, 0, 0, true)]
+#[case::supervisor(Mode::Supervisor, MENVCFG_STCE, 0b010, true)]
+#[case::sstc_disabled(Mode::Supervisor, 0, 0b010, false)]
+#[case::time_disabled(Mode::Supervisor, MENVCFG_STCE, 0b101, false)]
+#[case::user(Mode::User, MENVCFG_STCE, 0b010, false)]
+fn stimecmp_access(
+    #[case] mode: Mode,
+    #[case] menvcfg: u64,
+    #[case] mcounteren: u64,
+    #[case] allowed: bool,
+) {
+    let mut cpu = privileged(0x14d023f3, mode, 0);
+    cpu.csrs[MENVCFG] = menvcfg;
+    cpu.csrs[MCOUNTEREN] = mcounteren;
+    let expected = match allowed {
+        true => Ok(()),
+        false => Err(Exception::IllegalInstruction(0x14d023f3)),
+    };
+    assert_eq!(cpu.step(), expected);
+}
+
+#[rstest]
+#[case(Mode::User, Exception::EnvironmentCallFromUMode)]
+#[case(Mode::Supervisor, Exception::EnvironmentCallFromSMode)]
+#[case(Mode::Machine, Exception::EnvironmentCallFromMMode)]
+fn ecall(#[case] mode: Mode, #[case] expected: Exception) {
+    let mut cpu = privileged(0x00000073, mode, 0);
+    assert_eq!(cpu.step(), Err(expected));
+}
+
+#[test]
+fn sstatus() {
+    // csrs sstatus, t0 only reaches the writable supervisor fields of
+    // mstatus, not UBE, XS or UXL, and SD follows the dirty FS and VS
+    let mut cpu = privileged(0x1002a073, Mode::Supervisor, 0);
+    cpu.regs[5] = u64::MAX;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MSTATUS], 1 << 63 | 0x000c_6722);
+}
+
+#[rstest]
+#[case::from_user(Mode::User, 0)]
+#[case::from_supervisor(Mode::Supervisor, 1 << 8)]
+fn delegated(#[case] mode: Mode, #[case] spp: u64) {
+    // SIE set
+    let mut cpu = privileged(MUL, mode, 1 << 1);
+    cpu.isa = "rv64i_zicsr".parse::<Isa>().unwrap();
+    cpu.csrs[MEDELEG] = 1 << 2;
+    cpu.csrs[MTVEC] = DRAM_BASE + 0x100;
+    cpu.csrs[STVEC] = DRAM_BASE + 0x200;
+
+    let exception = cpu.step().unwrap_err();
+    cpu.trap(exception).unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 0x200);
+    assert_eq!(cpu.mode, Mode::Supervisor);
+    assert_eq!(cpu.csrs[SEPC], DRAM_BASE);
+    assert_eq!(cpu.csrs[SCAUSE], 2);
+    assert_eq!(cpu.csrs[STVAL], MUL as u64);
+    assert_eq!(cpu.csrs[MCAUSE], 0);
+    // SPIE = 1, SIE = 0
+    assert_eq!(cpu.csrs[MSTATUS], 1 << 5 | spp);
+
+    // machine mode traps aren't delegated
+    cpu.pc = DRAM_BASE;
+    cpu.mode = Mode::Machine;
+    cpu.trap(exception).unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 0x100);
+    assert_eq!(cpu.csrs[MCAUSE], 2);
+}
+
+#[rstest]
+// csrw medeleg, t0
+#[case::medeleg(0x30229073, MEDELEG, 0xb3ff)]
+// csrw mideleg, t0
+#[case::mideleg(0x30329073, MIDELEG, 0x222)]
+fn delegable(#[case] inst: u32, #[case] csr: usize, #[case] expected: u64) {
+    let mut cpu = privileged(inst, Mode::Machine, 0);
+    cpu.regs[5] = u64::MAX;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[csr], expected);
+}
+
+#[test]
+fn trap_from_user() {
+    let mut cpu = privileged(MUL, Mode::User, 0);
+    cpu.isa = "rv64i_zicsr".parse::<Isa>().unwrap();
+    cpu.csrs[MTVEC] = DRAM_BASE + 0x100;
+    let exception = cpu.step().unwrap_err();
+    cpu.trap(exception).unwrap();
+    assert_eq!(cpu.mode, Mode::Machine);
+    // MPP = U
+    assert_eq!(cpu.csrs[MSTATUS], 0);
+}
+
+#[test]
+fn disassembly() {
+    // sfence.vma t0, t1
+    assert_eq!(
+        disassemble(0x12628073, DRAM_BASE).as_deref(),
+        Some("sfence.vma t0, t1")
+    );
+}
+
+#[rstest]
+// MPP = S, MPIE = 1
+#[case::mret(MRET, Mode::Machine, 1 << 11 | 1 << 7, MEPC, Mode::Supervisor, 1 << 7 | 1 << 3)]
+// MPP = M, MPIE = 0, MIE = 1
+#[case::mret_to_machine(MRET, Mode::Machine, 3 << 11 | 1 << 3, MEPC, Mode::Machine, 1 << 7)]
+// SPP = U, SPIE = 1
+#[case::sret(SRET, Mode::Supervisor, 1 << 5, SEPC, Mode::User, 1 << 5 | 1 << 1)]
+// SPP = S, SPIE = 0, SIE = 1, from machine mode
+#[case::sret_to_supervisor(SRET, Mode::Machine, 1 << 8 | 1 << 1, SEPC, Mode::Supervisor, 1 << 5)]
+fn xret(
+    #[case] inst: u32,
+    #[case] mode: Mode,
+    #[case] mstatus: u64,
+    #[case] epc: usize,
+    #[case] expected_mode: Mode,
+    #[case] expected_mstatus: u64,
+) {
+    let mut cpu = privileged(inst, mode, mstatus);
+    // the low bit isn't part of the address
+    cpu.csrs[epc] = DRAM_BASE + 0x101;
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 0x100);
+    assert_eq!(cpu.mode, expected_mode);
+    assert_eq!(cpu.csrs[MSTATUS], expected_mstatus);
+}
+
+#[test]
+fn return_to_user() {
+    // auipc t0, 0; addi t1, t0, 24; csrw mtvec, t1; addi t1, t0, 32; csrw mepc, t1; mret
+    // handler: csrr a0, mcause; ebreak
+    // user: ecall
+    let image = code(&[
+        0x00000297, 0x01828313, 0x30531073, 0x02028313, 0x34131073, MRET, 0x34202573, 0x00100073,
+        0x00000073,
+    ]);
+    let mut machine = Machine::builder()
+        .image(image)
+        .ebreak(EbreakPolicy::Exit)
+        .build()
+        .unwrap();
+
+    assert_eq!(machine.run(), ExitReason::Shutdown(8));
+    assert_eq!(machine.cpu.csrs[MEPC], DRAM_BASE + 32);
+    assert_eq!(machine.cpu.mode, Mode::Machine);
+}
+
+/// A cpu running nops in `mode` with `mstatus`, handlers at `DRAM_BASE +
+/// 0x100` and `0x200` and every interrupt enabled in `mie`.
+fn interruptible(mode: Mode, mstatus: u64) -> Cpu {
+    let mut cpu = privileged(0x13, mode, mstatus);
+    cpu.bus.dram.dram = code(&[0x13; 0x100]);
+    cpu.csrs[MTVEC] = DRAM_BASE + 0x100;
+    cpu.csrs[STVEC] = DRAM_BASE + 0x200;
+    cpu.csrs[MIE] = u64::MAX;
+    cpu
+}
+
+const MSTATUS_MIE: u64 = 1 << 3;
+const MSTATUS_SIE: u64 = 1 << 1;
+const INTERRUPT: u64 = 1 << 63;
+
+#[rstest]
+#[case::machine(Mode::Machine, MSTATUS_MIE, Interrupt::MachineTimer, 0, MTVEC)]
+#[case::from_supervisor(Mode::Supervisor, 0, Interrupt::MachineTimer, 0, MTVEC)]
+#[case::from_user(Mode::User, 0, Interrupt::MachineExternal, 0, MTVEC)]
+#[case::delegated(
+    Mode::Supervisor,
+    MSTATUS_SIE,
+    Interrupt::SupervisorTimer,
+    1 << 5,
+    STVEC
+)]
+#[case::delegated_from_user(Mode::User, 0, Interrupt::SupervisorSoftware, 1 << 1, STVEC)]
+fn taken(
+    #[case] mode: Mode,
+    #[case] mstatus: u64,
+    #[case] interrupt: Interrupt,
+    #[case] mideleg: u64,
+    #[case] tvec: usize,
+) {
+    let mut cpu = interruptible(mode, mstatus);
+    cpu.csrs[MIDELEG] = mideleg;
+    cpu.pc = DRAM_BASE + 8;
+    cpu.set_pending(interrupt, true);
+
+    // taken before the instruction, which is the first of the handler
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, cpu.csrs[tvec] + 4);
+    let (epc, cause) = match tvec {
+        MTVEC => (MEPC, MCAUSE),
+        _ => (SEPC, SCAUSE),
+    };
+    assert_eq!(cpu.csrs[epc], DRAM_BASE + 8);
+    assert_eq!(cpu.csrs[cause], INTERRUPT | interrupt.code());
+
+    // the handler runs with them disabled
+    assert_eq!(cpu.pending_interrupt(), None);
+}
+
+#[rstest]
+#[case::disabled(Mode::Machine, 0, Interrupt::MachineTimer, 0)]
+#[case::delegated_in_machine(Mode::Machine, MSTATUS_MIE | MSTATUS_SIE, Interrupt::SupervisorTimer, 1 << 5)]
+#[case::delegated_disabled(Mode::Supervisor, 0, Interrupt::SupervisorTimer, 1 << 5)]
+fn masked(
+    #[case] mode: Mode,
+    #[case] mstatus: u64,
+    #[case] interrupt: Interrupt,
+    #[case] mideleg: u64,
+) {
+    let mut cpu = interruptible(mode, mstatus);
+    cpu.csrs[MIDELEG] = mideleg;
+    cpu.set_pending(interrupt, true);
+    assert_eq!(cpu.pending_interrupt(), None);
+    cpu.step().unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 4);
+}
+
+#[test]
+fn stimecmp() {
+    const STIP: u64 = 1 << 5;
+    let mut cpu = interruptible(Mode::Supervisor, MSTATUS_SIE);
+    cpu.clock = Box::new(InstretClock);
+    cpu.csrs[MIDELEG] = STIP;
+    cpu.csrs[STIMECMP] = 2;
+    // csrs menvcfg, t0 enables Sstc
+    cpu.bus.dram.dram[..4].copy_from_slice(&0x30a2a073u32.to_le_bytes());
+    cpu.regs[5] = MENVCFG_STCE;
+    cpu.mode = Mode::Machine;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MIP] & STIP, 0);
+    cpu.mode = Mode::Supervisor;
+
+    // pending once time reaches stimecmp, taken before the next instruction
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MIP] & STIP, STIP);
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[SCAUSE], INTERRUPT | 5);
+
+    // csrw mip, zero can't clear it while Sstc is on, csrw stimecmp, t0 does
+    cpu.mode = Mode::Machine;
+    cpu.bus.dram.dram[0x200..0x208].copy_from_slice(&code(&[0x34401073, 0x14d29073]));
+    cpu.pc = DRAM_BASE + 0x200;
+    cpu.regs[5] = u64::MAX;
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MIP] & STIP, STIP);
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[MIP] & STIP, 0);
+}
+
+#[test]
+fn priority() {
+    let mut cpu = interruptible(Mode::User, 0);
+    cpu.csrs[MIDELEG] = 1 << 9;
+    for interrupt in [
+        Interrupt::SupervisorExternal,
+        Interrupt::MachineTimer,
+        Interrupt::MachineSoftware,
+    ] {
+        cpu.set_pending(interrupt, true);
+    }
+    // machine mode interrupts first, then external, software and timer
+    assert_eq!(cpu.pending_interrupt(), Some(Interrupt::MachineSoftware));
+    cpu.csrs[MIE] = !(1 << 3);
+    assert_eq!(cpu.pending_interrupt(), Some(Interrupt::MachineTimer));
+    cpu.csrs[MIE] = 1 << 9;
+    assert_eq!(cpu.take_interrupt(), Some(Interrupt::SupervisorExternal));
+    assert_eq!(cpu.mode, Mode::Supervisor);
+}
+
+#[test]
+fn no_handler() {
+    let mut cpu = interruptible(Mode::Machine, MSTATUS_MIE);
+    cpu.csrs[MTVEC] = 0;
+    cpu.set_pending(Interrupt::MachineTimer, true);
+    assert_eq!(cpu.take_interrupt(), None);
+}
+
+#[test]
+fn rv32_cause() {
+    let mut cpu = interruptible(Mode::Machine, MSTATUS_MIE);
+    cpu.isa = "rv32i_zicsr".parse::<Isa>().unwrap();
+    cpu.set_pending(Interrupt::MachineExternal, true);
+    cpu.take_interrupt().unwrap();
+    assert_eq!(cpu.csrs[MCAUSE], 1 << 31 | 11);
+}
+
+#[rstest]
+#[case::machine_timer(Interrupt::MachineTimer, MTVEC, 0, 0x100 + 4 * 7)]
+#[case::machine_external(Interrupt::MachineExternal, MTVEC, 0, 0x100 + 4 * 11)]
+#[case::supervisor_software(Interrupt::SupervisorSoftware, STVEC, 1 << 1, 0x200 + 4)]
+fn vectored(
+    #[case] interrupt: Interrupt,
+    #[case] tvec: usize,
+    #[case] mideleg: u64,
+    #[case] expected: u64,
+) {
+    let mut cpu = interruptible(Mode::User, 0);
+    cpu.csrs[MIDELEG] = mideleg;
+    cpu.csrs[tvec] |= 1;
+    cpu.set_pending(interrupt, true);
+    cpu.take_interrupt().unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + expected);
+}
+
+#[test]
+fn vectored_exception() {
+    // exceptions go to the base whatever the mode
+    let mut cpu = interruptible(Mode::Machine, 0);
+    cpu.csrs[MTVEC] |= 1;
+    cpu.trap(Exception::IllegalInstruction(0)).unwrap();
+    assert_eq!(cpu.pc, DRAM_BASE + 0x100);
+}
+
+#[rstest]
+// the unused encodings of op-32, op-imm-32 and branch, then custom-3
+#[case::op_32(0x0462833b)]
+#[case::op_imm_32(0x0002a31b)]
+#[case::branch(0x00002063)]
+#[case::opcode(0x0000007b)]
+#[case::op(0xfe628333)]
+fn unknown(#[case] inst: u32) {
+    let mut cpu = Cpu::new(code(&[inst]));
+    cpu.csrs[MTVEC] = DRAM_BASE + 0x100;
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+    cpu.trap(Exception::IllegalInstruction(inst as u64))
+        .unwrap();
+    assert_eq!(cpu.csrs[MTVAL], inst as u64);
+}
@@ -0,0 +1,150 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, Mode, MTVEC},
+    exception::Exception,
+    machine::Machine,
+    trigger::{Trigger, TCONTROL, TDATA1, TINFO, TSELECT},
+};
+
+const HIT0: u64 = 1 << 22;
+const ACTION_DEBUG: u64 = 1 << 12;
+const NAPOT: u64 = 1 << 7;
+const GE: u64 = 2 << 7;
+const LT: u64 = 3 << 7;
+const M: u64 = 1 << 6;
+const S: u64 = 1 << 4;
+const U: u64 = 1 << 3;
+const EXECUTE: u64 = 1 << 2;
+const STORE: u64 = 1 << 1;
+const LOAD: u64 = 1 << 0;
+const MTE: u64 = 1 << 3;
+const MCONTROL6: u64 = 6 << 60;
+
+/// `ld t2, 0(t0)` loading from `DATA`.
+const LD: u32 = 0x0002b383;
+const DATA: u64 = DRAM_BASE + 0x100;
+
+/// A cpu about to execute `inst` in `mode`, with t0 pointing at `DATA` and
+/// the first trigger set up.
+fn triggered(inst: u32, mode: Mode, control: u64, address: u64) -> Cpu {
+    let mut cpu = Cpu::new(inst.to_le_bytes().to_vec());
+    cpu.mode = mode;
+    cpu.regs[5] = DATA;
+    cpu.triggers[0] = Trigger {
+        control: Some(control),
+        address,
+    };
+    cpu
+}
+
+#[rstest]
+#[case::execute(EXECUTE | U, DRAM_BASE, Some(DRAM_BASE))]
+#[case::load(LOAD | U, DATA, Some(DATA))]
+// any byte of the access matches
+#[case::load_byte(LOAD | U, DATA + 7, Some(DATA))]
+#[case::load_after(LOAD | U, DATA + 8, None)]
+#[case::store(STORE | U, DATA, None)]
+#[case::other_mode(LOAD | S, DATA, None)]
+// the 16 bytes from DATA - 8
+#[case::napot(NAPOT | LOAD | U, DATA - 8 + 0b0111, Some(DATA))]
+#[case::napot_outside(NAPOT | LOAD | U, DATA - 8 + 0b0011, None)]
+#[case::napot_all(NAPOT | LOAD | U, u64::MAX, Some(DATA))]
+#[case::ge(GE | LOAD | U, DATA + 4, Some(DATA))]
+#[case::ge_above(GE | LOAD | U, DATA + 8, None)]
+#[case::lt(LT | LOAD | U, DATA + 1, Some(DATA))]
+#[case::lt_below(LT | LOAD | U, DATA, None)]
+fn fires(#[case] control: u64, #[case] address: u64, #[case] breakpoint: Option<u64>) {
+    let mut cpu = triggered(LD, Mode::User, control, address);
+    let expected = match breakpoint {
+        Some(addr) => Err(Exception::Breakpoint(addr)),
+        None => Ok(()),
+    };
+    assert_eq!(cpu.step(), expected);
+    let hit = cpu.triggers[0].control.unwrap() & HIT0 != 0;
+    assert_eq!(hit, breakpoint.is_some());
+    if breakpoint.is_some() {
+        // before the access
+        assert_eq!(cpu.pc, DRAM_BASE);
+        assert_eq!(cpu.regs[7], 0);
+    }
+}
+
+#[test]
+fn machine_mode() {
+    let mut cpu = triggered(LD, Mode::Machine, LOAD | M, DATA);
+    assert_eq!(cpu.step(), Ok(()));
+
+    cpu.pc = DRAM_BASE;
+    cpu.csrs[TCONTROL] = MTE;
+    assert_eq!(cpu.step(), Err(Exception::Breakpoint(DATA)));
+
+    // the trap disables them until mret
+    cpu.csrs[MTVEC] = DRAM_BASE + 0x200;
+    cpu.trap(Exception::Breakpoint(DATA)).unwrap();
+    assert_eq!(cpu.csrs[TCONTROL], MTE << 4);
+    cpu.bus.dram.dram[0x200..0x204].copy_from_slice(&0x30200073u32.to_le_bytes());
+    cpu.step().unwrap();
+    assert_eq!(cpu.csrs[TCONTROL], MTE << 4 | MTE);
+}
+
+#[rstest]
+// csrw tdata1, t0
+#[case::mcontrol6(0x7a129073, MCONTROL6 | M | LOAD, MCONTROL6 | M | LOAD)]
+#[case::unsupported_fields(0x7a129073, MCONTROL6 | 1 << 21 | 0xf << 7 | 0xf << 12 | S, MCONTROL6 | S)]
+// mcontrol, the older type, isn't supported
+#[case::mcontrol(0x7a129073, 2 << 60 | M | LOAD, 15 << 60)]
+fn tdata1(#[case] inst: u32, #[case] value: u64, #[case] expected: u64) {
+    // csrr t2, tdata1
+    let code = [inst, 0x7a1023f3]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = Cpu::new(code);
+    cpu.regs[5] = value;
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], expected);
+}
+
+#[test]
+fn select() {
+    // csrw tselect, t0; csrr t2, tdata1; csrr t3, tinfo
+    let code = [0x7a029073u32, 0x7a1023f3, 0x7a402e73]
+        .iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = Cpu::new(code);
+    cpu.csrs[TSELECT] = 3;
+    cpu.regs[5] = 4;
+    cpu.step().unwrap();
+    // there are 4
+    assert_eq!(cpu.csrs[TSELECT], 3);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[7], 15 << 60);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[28], 1 << 24 | 1 << 15 | 1 << 6);
+    assert_eq!(cpu.csrs[TDATA1] | cpu.csrs[TINFO], 0);
+}
+
+#[test]
+fn debug_action() {
+    // the breakpoint stops the machine rather than trapping to mtvec
+    let code = LD.to_le_bytes().to_vec();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    machine.cpu.csrs[MTVEC] = DRAM_BASE + 0x200;
+    machine.cpu.regs[5] = DATA;
+    machine.cpu.triggers[0] = Trigger {
+        control: Some(ACTION_DEBUG | LOAD | M),
+        address: DATA,
+    };
+    assert_eq!(machine.step(), Err(Exception::Breakpoint(DATA)));
+    assert_eq!(machine.cpu.pc, DRAM_BASE);
+    assert!(!machine.cpu.trigger_halt);
+
+    // a breakpoint exception traps
+    machine.cpu.triggers[0].control = Some(LOAD | M);
+    machine.cpu.csrs[TCONTROL] = MTE;
+    assert_eq!(machine.step(), Ok(()));
+    assert_eq!(machine.cpu.pc, DRAM_BASE + 0x200);
+}
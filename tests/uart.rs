@@ -0,0 +1,155 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use rysk::{
+    bus::Device,
+    console::Console,
+    machine::Machine,
+    uart::{UART_BASE, UART_IRQ},
+};
+
+const IER: u64 = UART_BASE + 1;
+const IIR: u64 = UART_BASE + 2;
+const LCR: u64 = UART_BASE + 3;
+const MCR: u64 = UART_BASE + 4;
+const LSR: u64 = UART_BASE + 5;
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn machine() -> Machine {
+    // j .
+    Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn transmits() {
+    // lui t0, 0x10000; li t1, 'h'; sb t1, 0(t0); li t1, 'i'; sb t1, 0(t0)
+    let code = [
+        0x100002b7u32,
+        0x06800313,
+        0x00628023,
+        0x06900313,
+        0x00628023,
+    ]
+    .iter()
+    .flat_map(|x| x.to_le_bytes())
+    .collect();
+    let mut machine = Machine::builder().image(code).build().unwrap();
+    let output = Output::default();
+    machine.uart.set_output(output.clone());
+    for _ in 0..5 {
+        machine.step().unwrap();
+    }
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"hi");
+    // transmitting is instant
+    assert_eq!(machine.cpu.bus.load(LSR, 8), Ok(0x60));
+}
+
+#[test]
+fn receives() {
+    let mut machine = machine();
+    let bus = &mut machine.cpu.bus;
+    machine.uart.receive(b"ab");
+    assert_eq!(bus.load(LSR, 8), Ok(0x61));
+    assert_eq!(machine.uart.pending_irq(), None);
+
+    // received data available
+    bus.store(IER, 8, 1).unwrap();
+    assert_eq!(machine.uart.pending_irq(), Some(UART_IRQ));
+    assert_eq!(bus.load(IIR, 8), Ok(0x04));
+    assert_eq!(bus.load(UART_BASE, 8), Ok(b'a'.into()));
+    assert_eq!(bus.load(UART_BASE, 8), Ok(b'b'.into()));
+    assert_eq!(bus.load(LSR, 8), Ok(0x60));
+    assert_eq!(bus.load(IIR, 8), Ok(0x01));
+    assert_eq!(machine.uart.pending_irq(), None);
+
+    // the fifo holds 16
+    machine.uart.receive(&[b'x'; 20]);
+    let mut received = 0;
+    while bus.load(LSR, 8).unwrap() & 1 != 0 {
+        bus.load(UART_BASE, 8).unwrap();
+        received += 1;
+    }
+    assert_eq!(received, 16);
+}
+
+#[test]
+fn transmitter_empty() {
+    let mut machine = machine();
+    let output = Output::default();
+    machine.uart.set_output(output.clone());
+    let bus = &mut machine.cpu.bus;
+    bus.store(IER, 8, 2).unwrap();
+    assert_eq!(machine.uart.pending_irq(), Some(UART_IRQ));
+    // reading iir acknowledges it
+    assert_eq!(bus.load(IIR, 8), Ok(0x02));
+    assert_eq!(machine.uart.pending_irq(), None);
+    bus.store(UART_BASE, 8, b'!'.into()).unwrap();
+    assert_eq!(machine.uart.pending_irq(), Some(UART_IRQ));
+    assert_eq!(output.0.lock().unwrap().as_slice(), b"!");
+
+    // the fifos enabled
+    bus.store(IIR, 8, 1).unwrap();
+    assert_eq!(bus.load(IIR, 8), Ok(0xc2));
+}
+
+#[test]
+fn registers() {
+    let mut machine = machine();
+    let output = Output::default();
+    machine.uart.set_output(output.clone());
+    let bus = &mut machine.cpu.bus;
+
+    // the divisor latch
+    bus.store(LCR, 8, 0x83).unwrap();
+    bus.store(UART_BASE, 8, 0x12).unwrap();
+    bus.store(IER, 8, 0x34).unwrap();
+    assert_eq!(bus.load(UART_BASE, 8), Ok(0x12));
+    assert_eq!(bus.load(IER, 8), Ok(0x34));
+    bus.store(LCR, 8, 0x03).unwrap();
+    assert_eq!(bus.load(IER, 8), Ok(0));
+
+    // loopback
+    bus.store(MCR, 8, 0x1f).unwrap();
+    bus.store(UART_BASE, 8, b'l'.into()).unwrap();
+    assert_eq!(bus.load(UART_BASE + 6, 8), Ok(0xf0));
+    assert_eq!(bus.load(UART_BASE, 8), Ok(b'l'.into()));
+    assert!(output.0.lock().unwrap().is_empty());
+
+    bus.store(UART_BASE + 7, 8, 0x5a).unwrap();
+    assert_eq!(bus.load(UART_BASE + 7, 8), Ok(0x5a));
+}
+
+#[test]
+fn console_input() {
+    let mut machine = machine();
+    machine.console = Some(Console::from_reader(&b"jk"[..]));
+    // left for the firmware until the guest uses the uart
+    machine.step().unwrap();
+    assert_eq!(
+        machine.console.as_ref().unwrap().read_blocking(),
+        Some(b'j')
+    );
+
+    machine.cpu.bus.store(IER, 8, 1).unwrap();
+    while machine.cpu.bus.load(LSR, 8).unwrap() & 1 == 0 {
+        machine.step().unwrap();
+    }
+    assert_eq!(machine.cpu.bus.load(UART_BASE, 8), Ok(b'k'.into()));
+}
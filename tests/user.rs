@@ -0,0 +1,78 @@
+#![cfg(target_os = "linux")]
+
+use rysk::{
+    elf::{Elf, ElfType},
+    error::EmulatorError,
+    user::UserMode,
+};
+
+#[test]
+fn parse_elf() {
+    let data = std::fs::read("tests/user/hello.elf").expect("did you run 'make test' ?");
+    let elf = Elf::parse(&data).unwrap();
+    assert_eq!(elf.kind, ElfType::Executable);
+    assert!(elf.interpreter.is_none());
+    assert_eq!(elf.segments.len(), 3);
+
+    let (start, end) = elf.bounds();
+    assert!(start <= elf.entry && elf.entry < end);
+
+    assert!(matches!(
+        Elf::parse(b"\x7fELF"),
+        Err(EmulatorError::InvalidElf(_))
+    ));
+    assert!(matches!(
+        Elf::parse(&std::fs::read("tests/addi.bin").unwrap()),
+        Err(EmulatorError::InvalidElf(_))
+    ));
+}
+
+#[test]
+fn syscalls() {
+    let mut user = UserMode::builder("tests/user/hello.elf").build().unwrap();
+    assert_eq!(user.run().unwrap(), 22);
+}
+
+#[test]
+fn initial_stack() {
+    let user = UserMode::builder("tests/user/hello.elf")
+        .args(["a", "bc"])
+        .env("FOO", "bar")
+        .env("FOO", "baz")
+        .build()
+        .unwrap();
+    let mut cpu = user.cpu;
+    let mut word = |addr: u64| cpu.bus.load(addr, 64).unwrap();
+
+    let sp = cpu.regs[2];
+    assert_eq!(sp % 16, 0);
+    assert_eq!(word(sp), 3);
+    let argv = [word(sp + 8), word(sp + 16), word(sp + 24)];
+    assert_eq!(word(sp + 32), 0);
+    let envp = word(sp + 40);
+    assert_eq!(word(sp + 48), 0);
+
+    let mut auxv = Vec::new();
+    let mut addr = sp + 56;
+    while word(addr) != 0 {
+        auxv.push((word(addr), word(addr + 8)));
+        addr += 16;
+    }
+    let aux = |key| auxv.iter().find(|x| x.0 == key).map(|x| x.1);
+    assert_eq!(aux(6), Some(4096), "AT_PAGESZ");
+    assert!(aux(3).is_some_and(|x| x != 0), "AT_PHDR");
+    let random = aux(25).expect("AT_RANDOM");
+
+    let mut string = |addr: u64| {
+        let mut s = Vec::new();
+        while let Ok(c @ 1..) = cpu.bus.load(addr + s.len() as u64, 8) {
+            s.push(c as u8);
+        }
+        s
+    };
+    assert_eq!(string(argv[0]), b"tests/user/hello.elf");
+    assert_eq!(string(argv[1]), b"a");
+    assert_eq!(string(argv[2]), b"bc");
+    assert_eq!(string(envp), b"FOO=baz");
+    assert!(random > sp);
+}
@@ -0,0 +1,46 @@
+# Exercises the user mode syscalls: exits with argc + bytes written + a value
+# round tripped through brk and mmap memory, 1 + 14 + 7 = 22.
+.globl _start
+_start:
+  # write(1, msg, 14)
+  li a0, 1
+  la a1, msg
+  li a2, 14
+  li a7, 64
+  ecall
+  mv s0, a0
+
+  # grow the heap by a page with brk
+  li a0, 0
+  li a7, 214
+  ecall
+  mv s1, a0
+  li t0, 4096
+  add a0, s1, t0
+  li a7, 214
+  ecall
+  li t1, 7
+  sd t1, 0(s1)
+  ld t2, 0(s1)
+
+  # mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+  li a0, 0
+  li a1, 4096
+  li a2, 3
+  li a3, 0x22
+  li a4, -1
+  li a5, 0
+  li a7, 222
+  ecall
+  sd t2, 0(a0)
+  ld t3, 0(a0)
+
+  ld t4, 0(sp)
+  add a0, s0, t3
+  add a0, a0, t4
+  li a7, 93
+  ecall
+
+.data
+msg:
+  .ascii "hello, world!\n"
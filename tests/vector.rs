@@ -0,0 +1,258 @@
+use rstest::rstest;
+use rysk::{
+    bus::DRAM_BASE,
+    cpu::{Cpu, VL, VSTART, VTYPE},
+    error::EmulatorError,
+    exception::Exception,
+    isa::Isa,
+    machine::Machine,
+    vector::Vector,
+};
+
+/// Runs `code` after `vsetvli t0, a0, e32, m1, ta, ma` with a0 = `vl`.
+fn program(code: &[u32], vl: u64) -> Cpu {
+    let code: Vec<u8> = [0x0d0572d7]
+        .iter()
+        .chain(code)
+        .flat_map(|x| x.to_le_bytes())
+        .collect();
+    let mut cpu = Cpu::new(code);
+    cpu.regs[10] = vl;
+    cpu.step().unwrap();
+    cpu
+}
+
+/// Sets the elements of a register of 32-bit elements.
+fn set(cpu: &mut Cpu, reg: usize, elements: &[u64]) {
+    for (i, x) in elements.iter().enumerate() {
+        cpu.vector.set_element(reg, i as u64, 32, *x);
+    }
+}
+
+fn elements(cpu: &Cpu, reg: usize) -> Vec<u64> {
+    (0..4).map(|i| cpu.vector.element(reg, i, 32)).collect()
+}
+
+#[rstest]
+#[case::e32_m1(0x10, 10, 128, 4)]
+#[case::e8_m8(0x03, u64::MAX, 128, 128)]
+#[case::e16_mf2(0x0f, 10, 128, 4)]
+#[case::e32_m2(0x11, 100, 256, 16)]
+#[case::e64_m1(0x18, 1, 128, 1)]
+fn vsetvl(#[case] vtype: u64, #[case] avl: u64, #[case] vlen: u64, #[case] vl: u64) {
+    // vsetvl t0, a0, a1
+    let mut cpu = Cpu::new(0x80b572d7u32.to_le_bytes().to_vec());
+    cpu.vector = Vector::new(vlen).unwrap();
+    cpu.regs[10] = avl;
+    cpu.regs[11] = vtype;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[5], vl);
+    assert_eq!(cpu.csrs[VL], vl);
+    assert_eq!(cpu.csrs[VTYPE], vtype);
+}
+
+#[rstest]
+// e64 with mf2 leaves no room for the widest element
+#[case::e64_mf2(0x1f)]
+#[case::reserved_vlmul(0x14)]
+#[case::reserved_vsew(0x20)]
+fn vill(#[case] vtype: u64) {
+    // vsetvl t0, a0, a1; vadd.vv v3, v1, v2
+    let code = [0x80b572d7u32, 0x021101d7];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.regs[10] = 4;
+    cpu.regs[11] = vtype;
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[5], 0);
+    assert_eq!(cpu.csrs[VTYPE], 1 << 63);
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(0x021101d7)));
+}
+
+#[test]
+fn vsetivli_and_x0() {
+    // vsetivli t0, 3, e8, m1, ta, ma; vsetvli t0, zero, e8, m8, ta, ma
+    let code = [0xcc01f2d7u32, 0x0c3072d7];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[5], 3);
+    // rs1 x0 with a destination asks for vlmax
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[5], 128);
+}
+
+#[test]
+fn load_add_store() {
+    let code = [
+        0x0205e087u32, // vle32.v v1, (a1)
+        0x02066107,    // vle32.v v2, (a2)
+        0x021101d7,    // vadd.vv v3, v1, v2
+        0x0206e1a7,    // vse32.v v3, (a3)
+    ];
+    // only 4 elements fit in a register
+    let mut cpu = program(&code, 5);
+    assert_eq!(cpu.regs[5], 4);
+    let (a, b, c) = (DRAM_BASE + 0x100, DRAM_BASE + 0x200, DRAM_BASE + 0x300);
+    for i in 0..5 {
+        cpu.bus.store(a + i * 4, 32, i + 1).unwrap();
+        cpu.bus.store(b + i * 4, 32, 0xffff_fff0).unwrap();
+    }
+    cpu.bus.store(c + 16, 32, 0xdead).unwrap();
+    (cpu.regs[11], cpu.regs[12], cpu.regs[13]) = (a, b, c);
+    for _ in 0..code.len() {
+        cpu.step().unwrap();
+    }
+    for i in 0..4 {
+        assert_eq!(cpu.bus.load(c + i * 4, 32).unwrap(), 0xffff_fff1 + i);
+    }
+    assert_eq!(cpu.bus.load(c + 16, 32).unwrap(), 0xdead);
+}
+
+#[test]
+fn strided_load() {
+    // vsetvli t0, a0, e64, m1, ta, ma; vlse64.v v4, (a1), a2
+    let code = [0x0d8572d7u32, 0x0ac5f207];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    let base = DRAM_BASE + 0x100;
+    cpu.bus.store(base, 64, 0x1111).unwrap();
+    cpu.bus.store(base + 24, 64, 0x2222).unwrap();
+    (cpu.regs[10], cpu.regs[11], cpu.regs[12]) = (2, base, 24);
+    cpu.step().unwrap();
+    cpu.step().unwrap();
+    assert_eq!(cpu.vector.element(4, 0, 64), 0x1111);
+    assert_eq!(cpu.vector.element(4, 1, 64), 0x2222);
+}
+
+#[rstest]
+#[case::vadd_vi(0x021eb1d7, &[1, 2, 3, 4], &[0; 4], 0, &[-2i32 as u32 as u64, -1i32 as u32 as u64, 0, 1])]
+#[case::vadd_vx(0x021541d7, &[1, 2, 3, 4], &[0; 4], 10, &[11, 12, 13, 14])]
+#[case::vrsub_vx(0x0e1541d7, &[1, 2, 3, 4], &[0; 4], 10, &[9, 8, 7, 6])]
+#[case::vmul_vv(0x961121d7, &[1, 2, 3, 0x1_0000], &[5, 6, 7, 0x1_0000], 0, &[5, 12, 21, 0])]
+#[case::vmin_vv(0x161101d7, &[0xffff_ffff, 2, 3, 4], &[1, 1, 5, 5], 0, &[0xffff_ffff, 1, 3, 4])]
+#[case::vmaxu_vx(0x1a1541d7, &[0xffff_ffff, 2, 3, 4], &[0; 4], 3, &[0xffff_ffff, 3, 3, 4])]
+#[case::vsra_vi(0xa618b1d7, &[0x8000_0000, 0x20000, 0, 1], &[0; 4], 0, &[0xffff_c000, 1, 0, 0])]
+#[case::vsll_vv(0x961101d7, &[1, 1, 1, 3], &[0, 4, 31, 33], 0, &[1, 16, 0x8000_0000, 6])]
+fn arithmetic(
+    #[case] inst: u32,
+    #[case] v1: &[u64],
+    #[case] v2: &[u64],
+    #[case] a0: u64,
+    #[case] expected: &[u64],
+) {
+    let mut cpu = program(&[inst], 4);
+    set(&mut cpu, 1, v1);
+    set(&mut cpu, 2, v2);
+    cpu.regs[10] = a0;
+    cpu.step().unwrap();
+    assert_eq!(elements(&cpu, 3), expected);
+}
+
+#[test]
+fn multiply_accumulate() {
+    // vmacc.vv v3, v1, v2
+    let mut cpu = program(&[0xb620a1d7], 4);
+    set(&mut cpu, 1, &[1, 2, 3, 4]);
+    set(&mut cpu, 2, &[2, 2, 2, 2]);
+    set(&mut cpu, 3, &[10, 20, 30, 40]);
+    cpu.step().unwrap();
+    assert_eq!(elements(&cpu, 3), [12, 24, 36, 48]);
+}
+
+#[test]
+fn masks() {
+    let code = [
+        0x6e154057u32, // vmslt.vx v0, v1, a0
+        0x001101d7,    // vadd.vv v3, v1, v2, v0.t
+        0x5c1101d7,    // vmerge.vvm v3, v1, v2, v0
+    ];
+    let mut cpu = program(&code, 4);
+    set(&mut cpu, 1, &[-1i32 as u32 as u64, 5, 1, 7]);
+    set(&mut cpu, 2, &[100, 100, 100, 100]);
+    set(&mut cpu, 3, &[9, 9, 9, 9]);
+    cpu.regs[10] = 2;
+    cpu.step().unwrap();
+    assert_eq!(cpu.vector.regs[0] & 0xf, 0b0101);
+    cpu.step().unwrap();
+    assert_eq!(elements(&cpu, 3), [99, 9, 101, 9]);
+    // v2 where the mask is set, v1 elsewhere
+    cpu.step().unwrap();
+    assert_eq!(elements(&cpu, 3), [100, 5, 100, 7]);
+}
+
+#[test]
+fn compare_immediate() {
+    // vmsgtu.vi v0, v1, 5
+    let mut cpu = program(&[0x7a12b057], 4);
+    set(&mut cpu, 1, &[4, 5, 6, 0xffff_ffff]);
+    cpu.step().unwrap();
+    assert_eq!(cpu.vector.regs[0] & 0xf, 0b1100);
+}
+
+#[test]
+fn reduction_and_moves() {
+    let code = [
+        0x420561d7u32, // vmv.s.x v3, a0
+        0x021121d7,    // vredsum.vs v3, v1, v2
+        0x42302557,    // vmv.x.s a0, v3
+        0x5e0541d7,    // vmv.v.x v3, a0
+    ];
+    let mut cpu = program(&code, 4);
+    set(&mut cpu, 1, &[1, 2, 3, 4]);
+    set(&mut cpu, 2, &[0xffff_fff0, 0, 0, 0]);
+    cpu.regs[10] = 7;
+    cpu.step().unwrap();
+    assert_eq!(cpu.vector.element(3, 0, 32), 7);
+    // v2[0] plus the elements of v1
+    cpu.step().unwrap();
+    assert_eq!(cpu.vector.element(3, 0, 32), 0xffff_fffa);
+    cpu.step().unwrap();
+    assert_eq!(cpu.regs[10], -6i64 as u64);
+    cpu.step().unwrap();
+    assert_eq!(elements(&cpu, 3), [0xffff_fffa; 4]);
+}
+
+#[test]
+fn faulting_load_sets_vstart() {
+    // vle32.v v1, (a1)
+    let mut cpu = program(&[0x0205e087], 4);
+    let end = DRAM_BASE + cpu.bus.dram.size();
+    cpu.bus.store(end - 8, 64, 0x2222_2222_1111_1111).unwrap();
+    cpu.regs[11] = end - 8;
+    assert_eq!(cpu.step(), Err(Exception::LoadAccessFault(end)));
+    assert_eq!(cpu.csrs[VSTART], 2);
+    assert_eq!(elements(&cpu, 1)[..2], [0x1111_1111, 0x2222_2222]);
+}
+
+#[rstest]
+// vadd.vv v2, v9, v4 under m2
+#[case::misaligned_group(0x0d1572d7, 0x02920157, "rv64imv")]
+// vadd.vv v0, v1, v2, v0.t
+#[case::masked_over_mask(0x0d0572d7, 0x00110057, "rv64imv")]
+// vle32.v v0, (a2), v0.t
+#[case::masked_load_over_mask(0x0d0572d7, 0x00066007, "rv64imv")]
+// vadd.vv v3, v1, v2
+#[case::without_v(0x00000013, 0x021101d7, "rv64imfd")]
+fn illegal(#[case] vset: u32, #[case] inst: u32, #[case] isa: &str) {
+    let code = [vset, inst];
+    let mut cpu = Cpu::new(code.iter().flat_map(|x| x.to_le_bytes()).collect());
+    cpu.isa = isa.parse::<Isa>().unwrap();
+    cpu.regs[10] = 4;
+    cpu.step().unwrap();
+    assert_eq!(cpu.step(), Err(Exception::IllegalInstruction(inst as u64)));
+}
+
+#[test]
+fn vlen() {
+    // csrr a0, vlenb
+    let csrr = 0xc2202573u32.to_le_bytes().to_vec();
+    let mut machine = Machine::builder().vlen(256).image(csrr).build().unwrap();
+    machine.cpu.step().unwrap();
+    assert_eq!(machine.cpu.regs[10], 32);
+    assert_eq!(machine.cpu.vector.regs.len(), 32 * 32);
+
+    for vlen in [32, 96, 1 << 17] {
+        assert!(matches!(
+            Machine::builder().vlen(vlen).build(),
+            Err(EmulatorError::InvalidVlen(x)) if x == vlen
+        ));
+    }
+}
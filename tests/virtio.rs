@@ -0,0 +1,729 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use rysk::{
+    bus::{Bus, DRAM_BASE},
+    console::Console,
+    machine::Machine,
+    net::{Hub, Port},
+    plic::PLIC_BASE,
+    virtio::{
+        console::VirtioConsole,
+        input::{InputEvent, BTN_LEFT},
+        VIRTIO_BASE,
+    },
+    vnc,
+};
+
+const MAGIC: u64 = 0x000;
+const VERSION: u64 = 0x004;
+const DEVICE_ID: u64 = 0x008;
+const DEVICE_FEATURES: u64 = 0x010;
+const DEVICE_FEATURES_SEL: u64 = 0x014;
+const QUEUE_SEL: u64 = 0x030;
+const QUEUE_NUM: u64 = 0x038;
+const QUEUE_READY: u64 = 0x044;
+const QUEUE_NOTIFY: u64 = 0x050;
+const INTERRUPT_STATUS: u64 = 0x060;
+const INTERRUPT_ACK: u64 = 0x064;
+const STATUS: u64 = 0x070;
+const QUEUE_DESC: u64 = 0x080;
+const QUEUE_DRIVER: u64 = 0x090;
+const QUEUE_DEVICE: u64 = 0x0a0;
+const CONFIG: u64 = 0x100;
+
+const QUEUE_SIZE: u64 = 8;
+/// Where the queues and their buffers go, past the program.
+const MEMORY: u64 = DRAM_BASE + 0x10000;
+
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+/// What a driver does with a device, to the queues it set up.
+struct Driver {
+    base: u64,
+    queues: Vec<DriverQueue>,
+}
+
+struct DriverQueue {
+    desc: u64,
+    avail: u64,
+    used: u64,
+    next_desc: u16,
+    next_avail: u16,
+}
+
+impl Driver {
+    /// Sets up `queues` queues and has the device running.
+    fn new(bus: &mut Bus, queues: u64) -> Self {
+        Self::slot(bus, 0, queues)
+    }
+
+    /// The same for the device in `slot`.
+    fn slot(bus: &mut Bus, slot: u64, queues: u64) -> Self {
+        let base = VIRTIO_BASE + slot * 0x1000;
+        bus.store(base + STATUS, 32, 0b1011).unwrap();
+        let queues = (0..queues)
+            .map(|i| {
+                let memory = MEMORY + slot * 0x2000 + i * 0x1000;
+                let queue = DriverQueue {
+                    desc: memory,
+                    avail: memory + 0x200,
+                    used: memory + 0x400,
+                    next_desc: 0,
+                    next_avail: 0,
+                };
+                bus.store(base + QUEUE_SEL, 32, i).unwrap();
+                bus.store(base + QUEUE_NUM, 32, QUEUE_SIZE).unwrap();
+                for (register, addr) in [
+                    (QUEUE_DESC, queue.desc),
+                    (QUEUE_DRIVER, queue.avail),
+                    (QUEUE_DEVICE, queue.used),
+                ] {
+                    bus.store(base + register, 32, addr & 0xffff_ffff).unwrap();
+                    bus.store(base + register + 4, 32, addr >> 32).unwrap();
+                }
+                bus.store(base + QUEUE_READY, 32, 1).unwrap();
+                queue
+            })
+            .collect();
+        bus.store(base + STATUS, 32, 0b1111).unwrap();
+        Self { base, queues }
+    }
+
+    /// Makes a chain of `buffers` available on `queue`, with their address,
+    /// length and whether the device writes them.
+    fn offer(&mut self, bus: &mut Bus, index: usize, buffers: &[(u64, u32, bool)]) {
+        let queue = &mut self.queues[index];
+        let head = queue.next_desc;
+        for (i, &(addr, len, write)) in buffers.iter().enumerate() {
+            let index = queue.next_desc;
+            queue.next_desc = (queue.next_desc + 1) % QUEUE_SIZE as u16;
+            let desc = queue.desc + 16 * u64::from(index);
+            let mut flags = if write { DESC_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                flags |= DESC_NEXT;
+            }
+            bus.store(desc, 64, addr).unwrap();
+            bus.store(desc + 8, 32, len.into()).unwrap();
+            bus.store(desc + 12, 16, flags.into()).unwrap();
+            bus.store(desc + 14, 16, queue.next_desc.into()).unwrap();
+        }
+        let slot = u64::from(queue.next_avail) % QUEUE_SIZE;
+        bus.store(queue.avail + 4 + 2 * slot, 16, head.into())
+            .unwrap();
+        queue.next_avail += 1;
+        bus.store(queue.avail + 2, 16, queue.next_avail.into())
+            .unwrap();
+        bus.store(self.base + QUEUE_NOTIFY, 32, index as u64)
+            .unwrap();
+    }
+
+    /// The used ring index of `queue` and its last entry.
+    fn used(&self, bus: &mut Bus, queue: usize) -> (u64, u64, u64) {
+        let queue = &self.queues[queue];
+        let idx = bus.load(queue.used + 2, 16).unwrap();
+        let entry = queue.used + 4 + 8 * ((idx + QUEUE_SIZE - 1) % QUEUE_SIZE);
+        (
+            idx,
+            bus.load(entry, 32).unwrap(),
+            bus.load(entry + 4, 32).unwrap(),
+        )
+    }
+}
+
+fn write(bus: &mut Bus, addr: u64, data: &[u8]) {
+    for (i, x) in data.iter().enumerate() {
+        bus.store(addr + i as u64, 8, (*x).into()).unwrap();
+    }
+}
+
+fn read(bus: &mut Bus, addr: u64, len: usize) -> Vec<u8> {
+    (0..len as u64)
+        .map(|i| bus.load(addr + i, 8).unwrap() as u8)
+        .collect()
+}
+
+fn net() -> (Machine, Port) {
+    let hub = Hub::default();
+    let other = hub.port();
+    // j .
+    let machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .virtio_net(&hub, [2, 0, 0, 0, 0, 1])
+        .build()
+        .unwrap();
+    (machine, other)
+}
+
+#[test]
+fn transport() {
+    let (mut machine, _) = net();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(VIRTIO_BASE + MAGIC, 32), Ok(0x74726976));
+    assert_eq!(bus.load(VIRTIO_BASE + VERSION, 32), Ok(2));
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(1));
+    // the mac address, and version 1
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_FEATURES, 32), Ok(1 << 5));
+    bus.store(VIRTIO_BASE + DEVICE_FEATURES_SEL, 32, 1).unwrap();
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_FEATURES, 32), Ok(1));
+    assert_eq!(bus.load(VIRTIO_BASE + CONFIG + 5, 8), Ok(1));
+    assert_eq!(bus.load(VIRTIO_BASE + CONFIG, 32), Ok(2));
+}
+
+#[test]
+fn net_transmits() {
+    let (mut machine, other) = net();
+    let mut driver = Driver::new(&mut machine.cpu.bus, 2);
+    let frame = MEMORY + 0x8000;
+    let bus = &mut machine.cpu.bus;
+    write(bus, frame, &[0; 12]);
+    write(bus, frame + 12, b"a frame");
+    // the header and the frame in two buffers
+    driver.offer(bus, 1, &[(frame, 12, false), (frame + 12, 7, false)]);
+    machine.step().unwrap();
+    assert_eq!(other.recv().as_deref(), Some(&b"a frame"[..]));
+    assert_eq!(driver.used(&mut machine.cpu.bus, 1), (1, 0, 0));
+
+    // a used buffer interrupt, on source 1
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(VIRTIO_BASE + INTERRUPT_STATUS, 32), Ok(1));
+    assert_eq!(bus.load(PLIC_BASE + 0x1000, 32), Ok(1 << 1));
+    bus.store(VIRTIO_BASE + INTERRUPT_ACK, 32, 1).unwrap();
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.bus.load(PLIC_BASE + 0x1000, 32), Ok(0));
+}
+
+#[test]
+fn net_receives() {
+    let (mut machine, other) = net();
+    let mut driver = Driver::new(&mut machine.cpu.bus, 2);
+    other.send(b"incoming");
+    // waits for a buffer
+    machine.step().unwrap();
+    assert_eq!(driver.used(&mut machine.cpu.bus, 0).0, 0);
+
+    let buffer = MEMORY + 0x8000;
+    driver.offer(&mut machine.cpu.bus, 0, &[(buffer, 1526, true)]);
+    machine.step().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(driver.used(bus, 0), (1, 0, 20));
+    // one buffer
+    assert_eq!(read(bus, buffer + 10, 2), [1, 0]);
+    assert_eq!(read(bus, buffer + 12, 8), b"incoming");
+}
+
+#[test]
+fn reset() {
+    let (mut machine, other) = net();
+    let mut driver = Driver::new(&mut machine.cpu.bus, 2);
+    machine.cpu.bus.store(VIRTIO_BASE + STATUS, 32, 0).unwrap();
+    let frame = MEMORY + 0x8000;
+    driver.offer(&mut machine.cpu.bus, 1, &[(frame, 20, false)]);
+    machine.step().unwrap();
+    // the queues are gone
+    assert_eq!(other.recv(), None);
+    assert_eq!(machine.cpu.bus.load(VIRTIO_BASE + QUEUE_READY, 32), Ok(0));
+}
+
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn console(device: VirtioConsole) -> Machine {
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .build()
+        .unwrap();
+    machine.add_virtio(device).unwrap();
+    machine
+}
+
+/// Steps `machine` until `done`, as the input comes from another thread.
+fn step_until(machine: &mut Machine, mut done: impl FnMut(&mut Machine) -> bool) {
+    for _ in 0..1000 {
+        machine.step().unwrap();
+        if done(machine) {
+            return;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    panic!("timed out");
+}
+
+#[test]
+fn console_transmits() {
+    let output = Output::default();
+    let mut machine = console(VirtioConsole::new(output.clone()));
+    assert_eq!(machine.cpu.bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(3));
+    // the emergency write, before the queues are set up
+    let bus = &mut machine.cpu.bus;
+    bus.store(VIRTIO_BASE + CONFIG + 8, 32, b'!'.into())
+        .unwrap();
+
+    let mut driver = Driver::new(bus, 2);
+    let buffer = MEMORY + 0x8000;
+    write(bus, buffer, b"hello");
+    driver.offer(bus, 1, &[(buffer, 5, false)]);
+    machine.step().unwrap();
+    assert_eq!(*output.0.lock().unwrap(), b"!hello");
+    assert_eq!(driver.used(&mut machine.cpu.bus, 1), (1, 0, 0));
+}
+
+#[test]
+fn console_receives() {
+    let mut machine = console(VirtioConsole::new(io::sink()));
+    machine.console = Some(Console::from_reader(&b"keys"[..]));
+    let mut driver = Driver::new(&mut machine.cpu.bus, 2);
+    // smaller than the input, which waits for the next buffer
+    let buffer = MEMORY + 0x8000;
+    driver.offer(&mut machine.cpu.bus, 0, &[(buffer, 3, true)]);
+    step_until(&mut machine, |x| driver.used(&mut x.cpu.bus, 0).0 == 1);
+    assert_eq!(driver.used(&mut machine.cpu.bus, 0), (1, 0, 3));
+    assert_eq!(read(&mut machine.cpu.bus, buffer, 3), b"key");
+
+    driver.offer(&mut machine.cpu.bus, 0, &[(buffer + 8, 8, true)]);
+    machine.step().unwrap();
+    assert_eq!(driver.used(&mut machine.cpu.bus, 0), (2, 1, 1));
+    assert_eq!(read(&mut machine.cpu.bus, buffer + 8, 1), b"s");
+}
+
+#[test]
+fn console_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut machine = console(VirtioConsole::listen(listener));
+    let mut driver = Driver::new(&mut machine.cpu.bus, 2);
+    let mut client = TcpStream::connect(addr).unwrap();
+    client.write_all(b"in").unwrap();
+
+    let buffer = MEMORY + 0x8000;
+    driver.offer(&mut machine.cpu.bus, 0, &[(buffer, 8, true)]);
+    step_until(&mut machine, |x| driver.used(&mut x.cpu.bus, 0).0 == 1);
+    assert_eq!(driver.used(&mut machine.cpu.bus, 0), (1, 0, 2));
+    assert_eq!(read(&mut machine.cpu.bus, buffer, 2), b"in");
+
+    // the client is connected once it sent something
+    write(&mut machine.cpu.bus, buffer + 8, b"out");
+    driver.offer(&mut machine.cpu.bus, 1, &[(buffer + 8, 3, false)]);
+    machine.step().unwrap();
+    let mut reply = [0; 3];
+    client.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"out");
+}
+
+/// The bytes the guest gets from a virtio-rng seeded with `seed`.
+fn entropy(seed: u64) -> Vec<u8> {
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .virtio_rng(seed)
+        .build()
+        .unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(4));
+    let mut driver = Driver::new(bus, 1);
+    let buffer = MEMORY + 0x8000;
+    driver.offer(bus, 0, &[(buffer, 12, true), (buffer + 12, 4, true)]);
+    machine.step().unwrap();
+    assert_eq!(driver.used(&mut machine.cpu.bus, 0), (1, 0, 16));
+    read(&mut machine.cpu.bus, buffer, 16)
+}
+
+#[test]
+fn rng() {
+    assert_eq!(entropy(1), entropy(1));
+    assert_ne!(entropy(1), entropy(2));
+    assert_ne!(entropy(1), [0; 16]);
+}
+
+/// A request of `kind` for `sector`, as the header buffer holds it.
+fn blk_header(kind: u32, sector: u64) -> Vec<u8> {
+    let mut header = kind.to_le_bytes().to_vec();
+    header.extend_from_slice(&[0; 4]);
+    header.extend_from_slice(&sector.to_le_bytes());
+    header
+}
+
+#[test]
+fn blk() {
+    let path = std::env::temp_dir().join(format!("rysk-blk-{}", std::process::id()));
+    let mut image = vec![0; 4 * 512];
+    image[512..520].copy_from_slice(b"sector 1");
+    std::fs::write(&path, &image).unwrap();
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .drive(&path)
+        .build()
+        .unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(2));
+    // the capacity in sectors, and flushes
+    assert_eq!(bus.load(VIRTIO_BASE + CONFIG, 64), Ok(4));
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_FEATURES, 32), Ok(1 << 9));
+    let mut driver = Driver::new(bus, 1);
+    let (header, data, status) = (MEMORY + 0x8000, MEMORY + 0x9000, MEMORY + 0x8100);
+
+    write(bus, header, &blk_header(0, 1));
+    driver.offer(
+        bus,
+        0,
+        &[(header, 16, false), (data, 512, true), (status, 1, true)],
+    );
+    machine.step().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(driver.used(bus, 0), (1, 0, 513));
+    assert_eq!(read(bus, data, 8), b"sector 1");
+    assert_eq!(read(bus, status, 1), [0]);
+
+    write(bus, header, &blk_header(1, 3));
+    write(bus, data, b"written");
+    driver.offer(
+        bus,
+        0,
+        &[(header, 16, false), (data, 512, false), (status, 1, true)],
+    );
+    machine.step().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(read(bus, status, 1), [0]);
+    assert_eq!(&std::fs::read(&path).unwrap()[3 * 512..][..7], b"written");
+
+    // past the end of the disk
+    write(bus, header, &blk_header(0, 4));
+    driver.offer(
+        bus,
+        0,
+        &[(header, 16, false), (data, 512, true), (status, 1, true)],
+    );
+    machine.step().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(read(bus, status, 1), [1]);
+
+    write(bus, header, &blk_header(8, 0));
+    driver.offer(
+        bus,
+        0,
+        &[(header, 16, false), (data, 20, true), (status, 1, true)],
+    );
+    machine.step().unwrap();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(read(bus, data, 5), b"rysk\0");
+    assert_eq!(read(bus, status, 1), [0]);
+
+    // an unknown request
+    write(bus, header, &blk_header(99, 0));
+    driver.offer(bus, 0, &[(header, 16, false), (status, 1, true)]);
+    machine.step().unwrap();
+    assert_eq!(read(&mut machine.cpu.bus, status, 1), [2]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn blk_missing_image() {
+    let result = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .drive("/nonexistent/rysk.img")
+        .build();
+    assert!(result.is_err());
+}
+
+/// A guest mounting a share of its own directory, in the temporary one.
+#[cfg(unix)]
+struct Share {
+    machine: Machine,
+    driver: Driver,
+    dir: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl Share {
+    fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rysk-9p-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let mut machine = Machine::builder()
+            .image(vec![0x6f, 0, 0, 0])
+            .share("tag", &dir)
+            .build()
+            .unwrap();
+        let driver = Driver::new(&mut machine.cpu.bus, 1);
+        let mut share = Self {
+            machine,
+            driver,
+            dir,
+        };
+        let mut version = 0x2000u32.to_le_bytes().to_vec();
+        version.extend_from_slice(&string("9P2000.L"));
+        assert_eq!(share.call(100, &version)[..4], 0x2000u32.to_le_bytes());
+        // fid 0 is the root
+        let mut attach = vec![0; 4];
+        attach.extend_from_slice(&u32::MAX.to_le_bytes());
+        attach.extend_from_slice(&string("user"));
+        attach.extend_from_slice(&string(""));
+        attach.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(share.call(104, &attach)[0], 0x80);
+        share
+    }
+
+    /// The body of the reply to a `kind` request with `body`, which must
+    /// succeed.
+    fn call(&mut self, kind: u8, body: &[u8]) -> Vec<u8> {
+        let (reply_kind, reply) = self.try_call(kind, body);
+        assert_eq!(reply_kind, kind + 1, "error {reply:?}");
+        reply
+    }
+
+    fn try_call(&mut self, kind: u8, body: &[u8]) -> (u8, Vec<u8>) {
+        let mut request = (7 + body.len() as u32).to_le_bytes().to_vec();
+        request.push(kind);
+        request.extend_from_slice(&[1, 0]);
+        request.extend_from_slice(body);
+        let (buffer, reply) = (MEMORY + 0x8000, MEMORY + 0x9000);
+        let bus = &mut self.machine.cpu.bus;
+        write(bus, buffer, &request);
+        let buffers = [(buffer, request.len() as u32, false), (reply, 0x1000, true)];
+        self.driver.offer(bus, 0, &buffers);
+        self.machine.step().unwrap();
+        let (_, _, len) = self.driver.used(&mut self.machine.cpu.bus, 0);
+        let reply = read(&mut self.machine.cpu.bus, reply, len as usize);
+        assert_eq!(
+            u32::from_le_bytes(reply[..4].try_into().unwrap()),
+            len as u32
+        );
+        (reply[4], reply[7..].to_vec())
+    }
+
+    /// Walks from the root to `newfid` through `names`, returning the qids.
+    fn walk(&mut self, newfid: u32, names: &[&str]) -> Vec<u8> {
+        let mut walk = 0u32.to_le_bytes().to_vec();
+        walk.extend_from_slice(&newfid.to_le_bytes());
+        walk.extend_from_slice(&(names.len() as u16).to_le_bytes());
+        for name in names {
+            walk.extend_from_slice(&string(name));
+        }
+        self.call(110, &walk)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Share {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(unix)]
+fn string(s: &str) -> Vec<u8> {
+    let mut x = (s.len() as u16).to_le_bytes().to_vec();
+    x.extend_from_slice(s.as_bytes());
+    x
+}
+
+#[cfg(unix)]
+#[test]
+fn p9_reads() {
+    let mut share = Share::new("reads");
+    std::fs::write(share.dir.join("file"), b"shared").unwrap();
+    let bus = &mut share.machine.cpu.bus;
+    // the mount tag
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(9));
+    assert_eq!(bus.load(VIRTIO_BASE + CONFIG, 16), Ok(3));
+    assert_eq!(read(bus, VIRTIO_BASE + CONFIG + 2, 3), b"tag");
+
+    let qids = share.walk(1, &["file"]);
+    assert_eq!(qids[..3], [1, 0, 0]);
+    // Tlopen, read only
+    share.call(12, &[1, 0, 0, 0, 0, 0, 0, 0]);
+    let mut tread = 1u32.to_le_bytes().to_vec();
+    tread.extend_from_slice(&2u64.to_le_bytes());
+    tread.extend_from_slice(&100u32.to_le_bytes());
+    assert_eq!(share.call(116, &tread)[..], b"\x04\0\0\0ared"[..]);
+
+    // .. stops at the root
+    let root = share.walk(2, &[]);
+    assert_eq!(root, [0, 0]);
+    let qids = share.walk(2, &["..", ".."]);
+    let root = share.call(24, &[0, 0, 0, 0, 0xff, 0x07, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(qids[2 + 13..], root[8..21]);
+
+    // Treaddir lists the file
+    share.call(12, &[2, 0, 0, 0, 0, 0, 0, 0]);
+    let mut treaddir = 2u32.to_le_bytes().to_vec();
+    treaddir.extend_from_slice(&0u64.to_le_bytes());
+    treaddir.extend_from_slice(&0x800u32.to_le_bytes());
+    let entries = share.call(40, &treaddir);
+    let names = entries.windows(6).filter(|x| *x == b"\x04\0file").count();
+    assert_eq!(names, 1);
+
+    // a missing file
+    let mut walk = 0u32.to_le_bytes().to_vec();
+    walk.extend_from_slice(&3u32.to_le_bytes());
+    walk.extend_from_slice(&1u16.to_le_bytes());
+    walk.extend_from_slice(&string("missing"));
+    assert_eq!(share.try_call(110, &walk), (7, 2u32.to_le_bytes().to_vec()));
+}
+
+#[cfg(unix)]
+#[test]
+fn p9_writes() {
+    let mut share = Share::new("writes");
+    share.walk(1, &[]);
+    // Tlcreate, read and write
+    let mut create = 1u32.to_le_bytes().to_vec();
+    create.extend_from_slice(&string("new"));
+    create.extend_from_slice(&2u32.to_le_bytes());
+    create.extend_from_slice(&0o644u32.to_le_bytes());
+    create.extend_from_slice(&0u32.to_le_bytes());
+    share.call(14, &create);
+    let mut twrite = 1u32.to_le_bytes().to_vec();
+    twrite.extend_from_slice(&0u64.to_le_bytes());
+    twrite.extend_from_slice(&5u32.to_le_bytes());
+    twrite.extend_from_slice(b"hello");
+    assert_eq!(share.call(118, &twrite), 5u32.to_le_bytes());
+    assert_eq!(std::fs::read(share.dir.join("new")).unwrap(), b"hello");
+
+    // Tmkdir then Tunlinkat of the directory
+    let mut mkdir = 0u32.to_le_bytes().to_vec();
+    mkdir.extend_from_slice(&string("dir"));
+    mkdir.extend_from_slice(&0o755u32.to_le_bytes());
+    mkdir.extend_from_slice(&0u32.to_le_bytes());
+    assert_eq!(share.call(72, &mkdir)[0], 0x80);
+    assert!(share.dir.join("dir").is_dir());
+    let mut unlink = 0u32.to_le_bytes().to_vec();
+    unlink.extend_from_slice(&string("dir"));
+    unlink.extend_from_slice(&0x200u32.to_le_bytes());
+    share.call(76, &unlink);
+    assert!(!share.dir.join("dir").exists());
+
+    // names don't reach out of the directory
+    let mut create = 0u32.to_le_bytes().to_vec();
+    create.extend_from_slice(&string("../escape"));
+    create.extend_from_slice(&[0; 12]);
+    assert_eq!(
+        share.try_call(14, &create),
+        (7, 22u32.to_le_bytes().to_vec())
+    );
+}
+
+fn input() -> (Machine, Driver, Driver) {
+    let mut machine = Machine::builder()
+        .image(vec![0x6f, 0, 0, 0])
+        .framebuffer(64, 32)
+        .virtio_input(true)
+        .build()
+        .unwrap();
+    let bus = &mut machine.cpu.bus;
+    let keyboard = Driver::slot(bus, 0, 2);
+    let tablet = Driver::slot(bus, 1, 2);
+    (machine, keyboard, tablet)
+}
+
+/// The next `n` events of `driver`, as type, code and value.
+fn events(machine: &mut Machine, driver: &mut Driver, n: u64) -> Vec<(u16, u16, i32)> {
+    let buffer = MEMORY + 0x8000;
+    let bus = &mut machine.cpu.bus;
+    for i in 0..n {
+        driver.offer(bus, 0, &[(buffer + 8 * i, 8, true)]);
+    }
+    step_until(machine, |x| driver.used(&mut x.cpu.bus, 0).0 >= n);
+    let data = read(&mut machine.cpu.bus, buffer, 8 * n as usize);
+    data.chunks(8)
+        .map(|x| {
+            (
+                u16::from_le_bytes([x[0], x[1]]),
+                u16::from_le_bytes([x[2], x[3]]),
+                i32::from_le_bytes(x[4..].try_into().unwrap()),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn input_config() {
+    let (mut machine, _, _) = input();
+    let bus = &mut machine.cpu.bus;
+    assert_eq!(bus.load(VIRTIO_BASE + DEVICE_ID, 32), Ok(18));
+    // the name of the keyboard
+    bus.store(VIRTIO_BASE + CONFIG, 8, 1).unwrap();
+    assert_eq!(bus.load(VIRTIO_BASE + CONFIG + 2, 8), Ok(13));
+    assert_eq!(read(bus, VIRTIO_BASE + CONFIG + 8, 13), b"rysk keyboard");
+    // the tablet has absolute axes, x and y
+    let tablet = VIRTIO_BASE + 0x1000;
+    bus.store(tablet + CONFIG, 16, 0x0311).unwrap();
+    assert_eq!(bus.load(tablet + CONFIG + 2, 8), Ok(1));
+    assert_eq!(bus.load(tablet + CONFIG + 8, 8), Ok(0b11));
+    bus.store(tablet + CONFIG, 16, 0x0012).unwrap();
+    assert_eq!(bus.load(tablet + CONFIG + 12, 32), Ok(0x7fff));
+    // and no key codes below the buttons
+    bus.store(tablet + CONFIG, 16, 0x0111).unwrap();
+    assert_eq!(bus.load(tablet + CONFIG + 2, 8), Ok(35));
+    assert_eq!(bus.load(tablet + CONFIG + 8, 64), Ok(0));
+}
+
+#[test]
+fn input_events() {
+    let (mut machine, mut keyboard, mut tablet) = input();
+    let input = machine.input.clone().unwrap();
+    input.send(InputEvent::Key {
+        code: 30,
+        down: true,
+    });
+    assert_eq!(
+        events(&mut machine, &mut keyboard, 2),
+        [(1, 30, 1), (0, 0, 0)]
+    );
+
+    input.send(InputEvent::Pointer {
+        x: 100,
+        y: 200,
+        buttons: 1,
+    });
+    assert_eq!(
+        events(&mut machine, &mut tablet, 4),
+        [(3, 0, 100), (3, 1, 200), (1, BTN_LEFT, 1), (0, 0, 0)]
+    );
+}
+
+#[test]
+fn input_from_vnc() {
+    let (mut machine, mut keyboard, mut tablet) = input();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let framebuffer = machine.framebuffer.clone().unwrap();
+    vnc::serve(listener, framebuffer, machine.input.clone());
+
+    let mut client = TcpStream::connect(addr).unwrap();
+    let mut version = [0; 12];
+    client.read_exact(&mut version).unwrap();
+    // 3.3, where the server picks no security
+    client.write_all(b"RFB 003.003\n").unwrap();
+    let mut init = [0; 4 + 28];
+    client.write_all(&[1]).unwrap();
+    client.read_exact(&mut init).unwrap();
+    assert_eq!(init[..4], [0, 0, 0, 1]);
+
+    // an uppercase A is the key of a
+    client.write_all(&[4, 1, 0, 0, 0, 0, 0, b'A']).unwrap();
+    assert_eq!(
+        events(&mut machine, &mut keyboard, 2),
+        [(1, 30, 1), (0, 0, 0)]
+    );
+
+    // the middle of the screen, without a button
+    client.write_all(&[5, 0, 0, 31, 0, 0]).unwrap();
+    let events = events(&mut machine, &mut tablet, 3);
+    assert_eq!(events[..2], [(3, 0, 31 * 0x7fff / 63), (3, 1, 0)]);
+}
@@ -0,0 +1,84 @@
+use rysk::{
+    bus::Device,
+    machine::{ExitReason, Machine, StopCondition},
+    time::InstretClock,
+    watchdog::{Watchdog, WatchdogAction, WATCHDOG_BASE},
+};
+
+/// Enables the watchdog with a timeout of 1000 ticks, then feeds it forever
+/// or, if `starve`, spins without feeding it.
+fn program(starve: bool) -> Vec<u8> {
+    // lui t0, 0x10050; li t1, 1000; sw t1, 4(t0); li t1, 1; sw t1, 0(t0)
+    let mut code = vec![
+        0x100502b7u32,
+        0x3e800313,
+        0x0062a223,
+        0x00100313,
+        0x0062a023,
+    ];
+    match starve {
+        // j .
+        true => code.push(0x0000006f),
+        // li t2, WATCHDOG_KEY; 1: sw t2, 8(t0); j 1b
+        false => code.extend([0x0d09f3b7, 0x00d38393, 0x0072a423, 0xffdff06f]),
+    }
+    code.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn build(starve: bool, action: WatchdogAction) -> Machine {
+    let mut machine = Machine::builder()
+        .image(program(starve))
+        .watchdog(action)
+        .build()
+        .unwrap();
+    machine.cpu.clock = Box::new(InstretClock);
+    machine
+}
+
+#[test]
+fn bites_when_starved() {
+    let mut machine = build(true, WatchdogAction::Reset);
+    assert_eq!(machine.run(), ExitReason::Reboot);
+    assert!(machine.watchdog.as_ref().unwrap().expired());
+    assert_eq!(machine.exit_code(), None);
+
+    let mut machine = build(true, WatchdogAction::Halt);
+    assert_eq!(machine.run(), ExitReason::Halted);
+}
+
+#[test]
+fn fed_watchdog_keeps_running() {
+    let mut machine = build(false, WatchdogAction::Reset);
+    let stop = StopCondition {
+        max_instructions: Some(100_000),
+        ..StopCondition::default()
+    };
+    assert_eq!(machine.run_until(&stop), ExitReason::MaxInstructions);
+    let mut watchdog = machine.watchdog.clone().unwrap();
+    assert!(!watchdog.expired());
+    assert_eq!(watchdog.load(0x0, 32), Ok(1));
+    assert!(watchdog.load(0xc, 32).unwrap() <= 1000);
+}
+
+#[test]
+fn registers() {
+    let mut watchdog = Watchdog::new(WatchdogAction::Reset);
+    assert_eq!(
+        watchdog.address_range(),
+        Some(WATCHDOG_BASE..WATCHDOG_BASE + 0x1000)
+    );
+    watchdog.store(0x4, 32, 50).unwrap();
+    watchdog.poll(10);
+    watchdog.store(0x0, 32, 1).unwrap();
+    watchdog.poll(40);
+    assert_eq!(watchdog.load(0xc, 32), Ok(20));
+    // a wrong key doesn't feed it
+    watchdog.store(0x8, 32, 1).unwrap();
+    watchdog.poll(59);
+    assert!(!watchdog.expired());
+    watchdog.store(0x8, 32, 0x0d09_f00d).unwrap();
+    watchdog.poll(100);
+    assert!(!watchdog.expired());
+    watchdog.poll(109);
+    assert!(watchdog.expired());
+}